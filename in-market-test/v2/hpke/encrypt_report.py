@@ -7,6 +7,9 @@ from enum import Enum
 import pyhpke
 from cryptography.hazmat.primitives.asymmetric import x25519
 
+# Version of the encrypted report format, see ipa-core/src/report/test_vectors/README.md.
+REPORT_VERSION = 1
+
 
 class EventType(Enum):
     SOURCE = 0
@@ -73,6 +76,7 @@ class IPAReportInfo:
             + self.key_id.to_bytes(1, "little")
             + self.epoch.to_bytes(2, "little")
             + self.event_type.to_bytes()
+            + REPORT_VERSION.to_bytes(1, "little")
         )
         return data
 
@@ -225,7 +229,8 @@ class IPAReport:
         )
 
         return (
-            self.mk_encap_key_ciphertext
+            REPORT_VERSION.to_bytes(1, "little")
+            + self.mk_encap_key_ciphertext
             + encrypted.encrypted_to_bytes()
             + encrypted.ipa_report_info_to_bytes()
         )
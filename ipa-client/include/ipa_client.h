@@ -0,0 +1,85 @@
+/*
+ * C interface to the ipa-client library, which secret-shares and encrypts IPA reports for the
+ * three helpers. See ipa-client/src/ffi.rs for the implementation.
+ *
+ * Functions that encrypt reports return IPA_OK on success and write one ciphertext per helper,
+ * in helper order, to `out`, which must point to an array of three IpaBuffers. Each buffer must
+ * be released with ipa_buffer_free. Every ciphertext starts with IPA_REPORT_VERSION.
+ */
+#ifndef IPA_CLIENT_H
+#define IPA_CLIENT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define IPA_OK 0
+#define IPA_ERR_NULL_POINTER 1
+#define IPA_ERR_OUT_OF_RANGE 2
+#define IPA_ERR_INVALID_SITE_DOMAIN 3
+#define IPA_ERR_INVALID_EVENT_TYPE 4
+#define IPA_ERR_INVALID_PUBLIC_KEY 5
+#define IPA_ERR_ENCRYPTION 6
+
+#define IPA_PUBLIC_KEY_LEN 32
+#define IPA_REPORT_VERSION 1
+
+#define IPA_EVENT_TYPE_SOURCE 0
+#define IPA_EVENT_TYPE_TRIGGER 1
+
+/* The public keys of the three helpers. */
+typedef struct IpaHelperKeys IpaHelperKeys;
+
+/*
+ * An OPRF IPA report. trigger_value must fit in 3 bits and timestamp in 20 bits. site_domain is
+ * a NUL-terminated, non-empty ASCII string.
+ */
+typedef struct {
+    uint64_t match_key;
+    uint8_t event_type;
+    uint8_t breakdown_key;
+    uint8_t trigger_value;
+    uint32_t timestamp;
+    uint16_t epoch;
+    const char *site_domain;
+} IpaOprfReport;
+
+/* Bytes allocated by the library. */
+typedef struct {
+    uint8_t *data;
+    size_t len;
+} IpaBuffer;
+
+/*
+ * Parses the IPA_PUBLIC_KEY_LEN byte X25519 public keys of the helpers. Returns NULL if any of
+ * them is invalid. The result must be released with ipa_helper_keys_free.
+ */
+IpaHelperKeys *ipa_helper_keys_new(uint8_t key_id, const uint8_t *helper_1,
+                                   const uint8_t *helper_2, const uint8_t *helper_3);
+
+void ipa_helper_keys_free(IpaHelperKeys *keys);
+
+int32_t ipa_encrypt_oprf_report(const IpaHelperKeys *keys, const IpaOprfReport *report,
+                                IpaBuffer out[3]);
+
+int32_t ipa_encrypt_hybrid_impression(const IpaHelperKeys *keys, uint64_t match_key,
+                                      uint8_t breakdown_key, const char *site_domain,
+                                      IpaBuffer out[3]);
+
+/* value must fit in 3 bits. */
+int32_t ipa_encrypt_hybrid_conversion(const IpaHelperKeys *keys, uint64_t match_key,
+                                      uint8_t value, const char *site_domain, IpaBuffer out[3]);
+
+void ipa_buffer_free(IpaBuffer buffer);
+
+/* A static, NUL-terminated description of a status code. */
+const char *ipa_status_message(int32_t status);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* IPA_CLIENT_H */
@@ -0,0 +1,415 @@
+//! C ABI for report encryption, declared in `include/ipa_client.h`.
+//!
+//! Functions return [`IPA_OK`] on success and one of the other `IPA_*` status codes on failure;
+//! [`ipa_status_message`] describes them. Ciphertexts are returned in [`IpaBuffer`]s that the
+//! caller owns and must release with [`ipa_buffer_free`]. Randomness comes from the operating
+//! system.
+//!
+//! Changes to anything in this module must keep the header in sync and be backwards compatible:
+//! existing functions, struct layouts and status codes are never changed, only added.
+
+use std::{
+    ffi::{c_char, CStr},
+    ptr, slice,
+};
+
+use rand_core::OsRng;
+
+use crate::{Error, EventType, HelperPublicKeys, HybridReport, OprfReport, REPORT_VERSION};
+
+pub const IPA_OK: i32 = 0;
+pub const IPA_ERR_NULL_POINTER: i32 = 1;
+pub const IPA_ERR_OUT_OF_RANGE: i32 = 2;
+pub const IPA_ERR_INVALID_SITE_DOMAIN: i32 = 3;
+pub const IPA_ERR_INVALID_EVENT_TYPE: i32 = 4;
+pub const IPA_ERR_INVALID_PUBLIC_KEY: i32 = 5;
+pub const IPA_ERR_ENCRYPTION: i32 = 6;
+
+/// Length of the X25519 public keys that [`ipa_helper_keys_new`] accepts.
+pub const IPA_PUBLIC_KEY_LEN: usize = 32;
+
+/// Version of the report format, which is the first byte of every ciphertext.
+pub const IPA_REPORT_VERSION: u8 = REPORT_VERSION;
+
+fn status(error: &Error) -> i32 {
+    match error {
+        Error::OutOfRange { .. } => IPA_ERR_OUT_OF_RANGE,
+        Error::InvalidSiteDomain => IPA_ERR_INVALID_SITE_DOMAIN,
+        Error::InvalidEventType(_) => IPA_ERR_INVALID_EVENT_TYPE,
+        Error::InvalidPublicKey { .. } => IPA_ERR_INVALID_PUBLIC_KEY,
+        Error::Encryption => IPA_ERR_ENCRYPTION,
+    }
+}
+
+/// The public keys of the three helpers. Opaque to C callers.
+pub struct IpaHelperKeys(HelperPublicKeys);
+
+/// An OPRF IPA report. `site_domain` is a NUL-terminated ASCII string that is only borrowed for
+/// the duration of the call.
+#[repr(C)]
+pub struct IpaOprfReport {
+    pub match_key: u64,
+    pub event_type: u8,
+    pub breakdown_key: u8,
+    pub trigger_value: u8,
+    pub timestamp: u32,
+    pub epoch: u16,
+    pub site_domain: *const c_char,
+}
+
+/// Bytes allocated by this library.
+#[repr(C)]
+pub struct IpaBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl IpaBuffer {
+    pub const EMPTY: Self = Self {
+        data: ptr::null_mut(),
+        len: 0,
+    };
+}
+
+impl From<Vec<u8>> for IpaBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        let bytes = Box::into_raw(bytes.into_boxed_slice());
+        Self {
+            data: bytes.cast(),
+            len: bytes.len(),
+        }
+    }
+}
+
+/// Parses the public keys of the three helpers, each [`IPA_PUBLIC_KEY_LEN`] bytes long. Returns
+/// null if any of the pointers is null or any of the keys is invalid. The keys must be released
+/// with [`ipa_helper_keys_free`].
+///
+/// # Safety
+/// Non-null key pointers must point to [`IPA_PUBLIC_KEY_LEN`] readable bytes.
+#[must_use]
+#[no_mangle]
+pub unsafe extern "C" fn ipa_helper_keys_new(
+    key_id: u8,
+    helper_1: *const u8,
+    helper_2: *const u8,
+    helper_3: *const u8,
+) -> *mut IpaHelperKeys {
+    if helper_1.is_null() || helper_2.is_null() || helper_3.is_null() {
+        return ptr::null_mut();
+    }
+    let key = |p: *const u8| slice::from_raw_parts(p, IPA_PUBLIC_KEY_LEN);
+    match HelperPublicKeys::from_bytes(key_id, [key(helper_1), key(helper_2), key(helper_3)]) {
+        Ok(keys) => Box::into_raw(Box::new(IpaHelperKeys(keys))),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `keys` must be null or returned by [`ipa_helper_keys_new`], and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn ipa_helper_keys_free(keys: *mut IpaHelperKeys) {
+    if !keys.is_null() {
+        drop(Box::from_raw(keys));
+    }
+}
+
+/// Secret-shares and encrypts `report`, writing the ciphertext for each helper to `out`, in
+/// helper order. `out` is left untouched on failure.
+///
+/// # Safety
+/// `keys` must be returned by [`ipa_helper_keys_new`], `report` must point to a valid
+/// [`IpaOprfReport`] and `out` to an array of three writable [`IpaBuffer`]s.
+#[no_mangle]
+pub unsafe extern "C" fn ipa_encrypt_oprf_report(
+    keys: *const IpaHelperKeys,
+    report: *const IpaOprfReport,
+    out: *mut IpaBuffer,
+) -> i32 {
+    let Some(report) = report.as_ref() else {
+        return IPA_ERR_NULL_POINTER;
+    };
+    let Some(site_domain) = site_domain(report.site_domain) else {
+        return IPA_ERR_NULL_POINTER;
+    };
+
+    encrypt(keys, out, |keys| {
+        OprfReport {
+            match_key: report.match_key,
+            event_type: EventType::try_from(report.event_type)?,
+            breakdown_key: report.breakdown_key,
+            trigger_value: report.trigger_value,
+            timestamp: report.timestamp,
+            epoch: report.epoch,
+            site_domain: site_domain?,
+        }
+        .encrypt(keys, &mut OsRng)
+    })
+}
+
+/// Secret-shares and encrypts a Hybrid impression report, see [`ipa_encrypt_oprf_report`].
+///
+/// # Safety
+/// `keys` must be returned by [`ipa_helper_keys_new`], `site_domain` must be a NUL-terminated
+/// string and `out` must point to an array of three writable [`IpaBuffer`]s.
+#[no_mangle]
+pub unsafe extern "C" fn ipa_encrypt_hybrid_impression(
+    keys: *const IpaHelperKeys,
+    match_key: u64,
+    breakdown_key: u8,
+    site_domain: *const c_char,
+    out: *mut IpaBuffer,
+) -> i32 {
+    encrypt_hybrid(
+        keys,
+        HybridReport::Impression {
+            match_key,
+            breakdown_key,
+        },
+        site_domain,
+        out,
+    )
+}
+
+/// Secret-shares and encrypts a Hybrid conversion report, see [`ipa_encrypt_oprf_report`].
+///
+/// # Safety
+/// `keys` must be returned by [`ipa_helper_keys_new`], `site_domain` must be a NUL-terminated
+/// string and `out` must point to an array of three writable [`IpaBuffer`]s.
+#[no_mangle]
+pub unsafe extern "C" fn ipa_encrypt_hybrid_conversion(
+    keys: *const IpaHelperKeys,
+    match_key: u64,
+    value: u8,
+    site_domain: *const c_char,
+    out: *mut IpaBuffer,
+) -> i32 {
+    encrypt_hybrid(
+        keys,
+        HybridReport::Conversion { match_key, value },
+        site_domain,
+        out,
+    )
+}
+
+/// Releases a buffer returned by this library. Empty buffers are ignored.
+///
+/// # Safety
+/// `buffer` must be empty or returned by this library, and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn ipa_buffer_free(buffer: IpaBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+/// A static, NUL-terminated description of `status`.
+#[must_use]
+#[no_mangle]
+pub extern "C" fn ipa_status_message(status: i32) -> *const c_char {
+    let message: &'static CStr = match status {
+        IPA_OK => c"ok",
+        IPA_ERR_NULL_POINTER => c"a required pointer is null",
+        IPA_ERR_OUT_OF_RANGE => {
+            c"a report field does not fit in the number of bits expected for it"
+        }
+        IPA_ERR_INVALID_SITE_DOMAIN => c"site domain must be a non-empty ASCII string",
+        IPA_ERR_INVALID_EVENT_TYPE => c"unknown event type",
+        IPA_ERR_INVALID_PUBLIC_KEY => c"helper public key is not a valid X25519 key",
+        IPA_ERR_ENCRYPTION => c"failed to encrypt the report",
+        _ => c"unknown status",
+    };
+    message.as_ptr()
+}
+
+/// Reads a NUL-terminated site domain. Returns `None` if the pointer is null, and an error if
+/// the string is not valid.
+unsafe fn site_domain(site_domain: *const c_char) -> Option<Result<String, Error>> {
+    if site_domain.is_null() {
+        return None;
+    }
+    Some(
+        CStr::from_ptr(site_domain)
+            .to_str()
+            .map(str::to_string)
+            .map_err(|_| Error::InvalidSiteDomain),
+    )
+}
+
+unsafe fn encrypt_hybrid(
+    keys: *const IpaHelperKeys,
+    report: HybridReport,
+    site_domain_ptr: *const c_char,
+    out: *mut IpaBuffer,
+) -> i32 {
+    let Some(site_domain) = site_domain(site_domain_ptr) else {
+        return IPA_ERR_NULL_POINTER;
+    };
+    encrypt(keys, out, |keys| {
+        report.encrypt(&site_domain?, keys, &mut OsRng)
+    })
+}
+
+unsafe fn encrypt<F>(keys: *const IpaHelperKeys, out: *mut IpaBuffer, f: F) -> i32
+where
+    F: FnOnce(&HelperPublicKeys) -> Result<[Vec<u8>; 3], Error>,
+{
+    let Some(IpaHelperKeys(keys)) = keys.as_ref() else {
+        return IPA_ERR_NULL_POINTER;
+    };
+    if out.is_null() {
+        return IPA_ERR_NULL_POINTER;
+    }
+
+    match f(keys) {
+        Ok(ciphertexts) => {
+            let out = slice::from_raw_parts_mut(out, 3);
+            for (buffer, ciphertext) in out.iter_mut().zip(ciphertexts) {
+                *buffer = ciphertext.into();
+            }
+            IPA_OK
+        }
+        Err(e) => status(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CStr, ptr, slice};
+
+    use hpke::{kem::Kem, Serializable};
+    use rand::thread_rng;
+
+    use super::{
+        ipa_buffer_free, ipa_encrypt_hybrid_conversion, ipa_encrypt_hybrid_impression,
+        ipa_encrypt_oprf_report, ipa_helper_keys_free, ipa_helper_keys_new, ipa_status_message,
+        IpaBuffer, IpaHelperKeys, IpaOprfReport, IPA_ERR_INVALID_EVENT_TYPE,
+        IPA_ERR_INVALID_SITE_DOMAIN, IPA_ERR_NULL_POINTER, IPA_ERR_OUT_OF_RANGE, IPA_OK,
+        IPA_REPORT_VERSION,
+    };
+
+    fn public_key() -> Vec<u8> {
+        let (_, pk) = hpke::kem::X25519HkdfSha256::gen_keypair(&mut thread_rng());
+        pk.to_bytes().to_vec()
+    }
+
+    fn helper_keys() -> *mut IpaHelperKeys {
+        let [k1, k2, k3] = [public_key(), public_key(), public_key()];
+        let keys = unsafe { ipa_helper_keys_new(0, k1.as_ptr(), k2.as_ptr(), k3.as_ptr()) };
+        assert!(!keys.is_null());
+        keys
+    }
+
+    /// Runs `f` with an array of three buffers and returns the status along with the contents
+    /// of the buffers, which are released.
+    fn call<F: FnOnce(*mut IpaBuffer) -> i32>(f: F) -> (i32, [Vec<u8>; 3]) {
+        let mut out = [IpaBuffer::EMPTY, IpaBuffer::EMPTY, IpaBuffer::EMPTY];
+        let status = f(out.as_mut_ptr());
+        let contents = out.map(|buffer| unsafe {
+            let bytes = if buffer.data.is_null() {
+                Vec::new()
+            } else {
+                slice::from_raw_parts(buffer.data, buffer.len).to_vec()
+            };
+            ipa_buffer_free(buffer);
+            bytes
+        });
+
+        (status, contents)
+    }
+
+    #[test]
+    fn encrypts_oprf_reports() {
+        let keys = helper_keys();
+        let mut report = IpaOprfReport {
+            match_key: 1,
+            event_type: 0,
+            breakdown_key: 45,
+            trigger_value: 0,
+            timestamp: 456,
+            epoch: 0,
+            site_domain: c"www.meta.com".as_ptr(),
+        };
+        let encrypt = |report: &IpaOprfReport| {
+            call(|out| unsafe { ipa_encrypt_oprf_report(keys, report, out) })
+        };
+
+        let (status, ciphertexts) = encrypt(&report);
+        assert_eq!(status, IPA_OK);
+        for ciphertext in ciphertexts {
+            assert_eq!(ciphertext.len(), 139);
+            assert_eq!(ciphertext[0], IPA_REPORT_VERSION);
+            assert!(ciphertext.ends_with(b"www.meta.com"));
+        }
+
+        report.event_type = 2;
+        assert_eq!(encrypt(&report).0, IPA_ERR_INVALID_EVENT_TYPE);
+        report.event_type = 1;
+        report.trigger_value = 8;
+        assert_eq!(encrypt(&report).0, IPA_ERR_OUT_OF_RANGE);
+        report.site_domain = ptr::null();
+        assert_eq!(encrypt(&report).0, IPA_ERR_NULL_POINTER);
+        assert_eq!(
+            call(|out| unsafe { ipa_encrypt_oprf_report(keys, ptr::null(), out) }).0,
+            IPA_ERR_NULL_POINTER
+        );
+
+        unsafe { ipa_helper_keys_free(keys) };
+    }
+
+    #[test]
+    fn encrypts_hybrid_reports() {
+        let keys = helper_keys();
+
+        let (status, ciphertexts) = call(|out| unsafe {
+            ipa_encrypt_hybrid_impression(keys, 1, 45, c"www.meta.com".as_ptr(), out)
+        });
+        assert_eq!(status, IPA_OK);
+        assert!(ciphertexts.iter().all(|c| c.len() == 139));
+
+        let (status, ciphertexts) = call(|out| unsafe {
+            ipa_encrypt_hybrid_conversion(keys, 1, 5, c"www.abc.com".as_ptr(), out)
+        });
+        assert_eq!(status, IPA_OK);
+        assert!(ciphertexts.iter().all(|c| c.len() == 138));
+
+        assert_eq!(
+            call(|out| unsafe { ipa_encrypt_hybrid_conversion(keys, 1, 5, c"".as_ptr(), out) }).0,
+            IPA_ERR_INVALID_SITE_DOMAIN
+        );
+        assert_eq!(
+            unsafe {
+                ipa_encrypt_hybrid_conversion(keys, 1, 5, c"www.abc.com".as_ptr(), ptr::null_mut())
+            },
+            IPA_ERR_NULL_POINTER
+        );
+
+        unsafe { ipa_helper_keys_free(keys) };
+    }
+
+    #[test]
+    fn rejects_missing_keys() {
+        let key = public_key();
+        unsafe {
+            assert!(ipa_helper_keys_new(0, key.as_ptr(), key.as_ptr(), ptr::null()).is_null());
+            ipa_helper_keys_free(ptr::null_mut());
+        }
+        assert_eq!(
+            call(|out| unsafe {
+                ipa_encrypt_hybrid_impression(ptr::null(), 1, 45, c"www.meta.com".as_ptr(), out)
+            })
+            .0,
+            IPA_ERR_NULL_POINTER
+        );
+    }
+
+    #[test]
+    fn status_messages() {
+        let message = |status| unsafe { CStr::from_ptr(ipa_status_message(status)) };
+        assert_eq!(message(IPA_OK), c"ok");
+        assert_eq!(message(-1), c"unknown status");
+    }
+}
@@ -0,0 +1,110 @@
+//! Client-side secret sharing and encryption of IPA reports.
+//!
+//! Devices and browsers that generate events use this crate to split each report into
+//! replicated secret shares and encrypt them to the helpers' public keys, producing the same
+//! bytes as `OprfReport::encrypt` in `ipa-core`. The resulting ciphertexts are uploaded to the
+//! report collector, which submits them to the helpers.
+//!
+//! Both the OPRF IPA report format and the Hybrid format are supported. Hybrid impressions and
+//! conversions are encoded as OPRF source and trigger events respectively, with the fields that
+//! they do not use set to zero.
+//!
+//! Besides the Rust API, the crate exposes a C ABI from the [`ffi`] module (see
+//! `include/ipa_client.h`) and, with the `wasm` feature, JavaScript bindings for the
+//! `wasm32-unknown-unknown` target.
+//!
+//! ```
+//! # use ipa_client::{EventType, HelperPublicKeys, OprfReport};
+//! # fn run(keys: [&[u8]; 3]) -> Result<(), ipa_client::Error> {
+//! let keys = HelperPublicKeys::from_bytes(0, keys)?;
+//! let report = OprfReport {
+//!     match_key: 0x1234,
+//!     event_type: EventType::Source,
+//!     breakdown_key: 45,
+//!     trigger_value: 0,
+//!     timestamp: 456,
+//!     epoch: 0,
+//!     site_domain: "www.meta.com".to_string(),
+//! };
+//! let [h1, h2, h3] = report.encrypt(&keys, &mut rand_core::OsRng)?;
+//! # Ok(())
+//! # }
+//! ```
+#![deny(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+
+pub mod ffi;
+mod report;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use hpke::Deserializable;
+
+pub use crate::report::{
+    EventType, HybridReport, OprfReport, ReportShare, Share, BREAKDOWN_KEY_BITS, HELPER_ORIGIN,
+    MATCH_KEY_BITS, REPORT_VERSION, TIMESTAMP_BITS, TRIGGER_VALUE_BITS,
+};
+
+pub type PublicKey = <hpke::kem::X25519HkdfSha256 as hpke::kem::Kem>::PublicKey;
+
+/// Identifies the key that the helpers use to decrypt a report, when they rotate keys.
+pub type KeyIdentifier = u8;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("{field} {value} does not fit in {bits} bits")]
+    OutOfRange {
+        field: &'static str,
+        value: u64,
+        bits: u32,
+    },
+    #[error("site domain must be a non-empty ASCII string")]
+    InvalidSiteDomain,
+    #[error("unknown event type {0}")]
+    InvalidEventType(u8),
+    #[error("public key for helper {helper} is not a valid X25519 key")]
+    InvalidPublicKey { helper: usize },
+    #[error("failed to encrypt the report")]
+    Encryption,
+}
+
+/// The public keys of the three helpers, in helper order, under the same key identifier.
+#[derive(Clone)]
+pub struct HelperPublicKeys {
+    key_id: KeyIdentifier,
+    keys: [PublicKey; 3],
+}
+
+impl HelperPublicKeys {
+    #[must_use]
+    pub fn new(key_id: KeyIdentifier, keys: [PublicKey; 3]) -> Self {
+        Self { key_id, keys }
+    }
+
+    /// Parses the raw 32 byte X25519 public keys of the helpers, as published in their
+    /// configuration.
+    ///
+    /// ## Errors
+    /// If any of the keys is not a valid X25519 public key.
+    pub fn from_bytes(key_id: KeyIdentifier, keys: [&[u8]; 3]) -> Result<Self, Error> {
+        let [k1, k2, k3] = keys;
+        let parse = |helper: usize, bytes: &[u8]| {
+            PublicKey::from_bytes(bytes).map_err(|_| Error::InvalidPublicKey { helper })
+        };
+
+        Ok(Self::new(
+            key_id,
+            [parse(1, k1)?, parse(2, k2)?, parse(3, k3)?],
+        ))
+    }
+
+    #[must_use]
+    pub fn key_id(&self) -> KeyIdentifier {
+        self.key_id
+    }
+
+    #[must_use]
+    pub fn keys(&self) -> &[PublicKey; 3] {
+        &self.keys
+    }
+}
@@ -0,0 +1,791 @@
+use hpke::{
+    aead::AesGcm128, kdf::HkdfSha256, kem::X25519HkdfSha256, single_shot_seal_in_place_detached,
+    OpModeS, Serializable,
+};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::{Error, HelperPublicKeys, KeyIdentifier, PublicKey};
+
+/// Origin of the helper network, bound to every encryption. Must match `HELPER_ORIGIN` in
+/// `ipa_core::report`.
+pub const HELPER_ORIGIN: &str = "github.com/private-attribution";
+const DOMAIN: &str = "private-attribution";
+
+/// Version of the encrypted report format, which is the first byte of every report. Must match
+/// `ReportVersion::CURRENT` in `ipa_core::report`.
+pub const REPORT_VERSION: u8 = 1;
+
+pub const MATCH_KEY_BITS: u32 = 64;
+pub const BREAKDOWN_KEY_BITS: u32 = 8;
+pub const TRIGGER_VALUE_BITS: u32 = 3;
+pub const TIMESTAMP_BITS: u32 = 20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventType {
+    Source = 0,
+    Trigger = 1,
+}
+
+impl TryFrom<u8> for EventType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Source),
+            1 => Ok(Self::Trigger),
+            _ => Err(Error::InvalidEventType(value)),
+        }
+    }
+}
+
+/// A report in the OPRF IPA format, before it is secret-shared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OprfReport {
+    pub match_key: u64,
+    pub event_type: EventType,
+    /// Only the low [`BREAKDOWN_KEY_BITS`] bits are used.
+    pub breakdown_key: u8,
+    /// Must fit in [`TRIGGER_VALUE_BITS`] bits.
+    pub trigger_value: u8,
+    /// Must fit in [`TIMESTAMP_BITS`] bits.
+    pub timestamp: u32,
+    pub epoch: u16,
+    pub site_domain: String,
+}
+
+impl OprfReport {
+    /// ## Errors
+    /// If a field does not fit in the number of bits the helpers expect for it, or the site domain
+    /// is empty or not ASCII.
+    pub fn validate(&self) -> Result<(), Error> {
+        check_range(
+            "trigger value",
+            self.trigger_value.into(),
+            TRIGGER_VALUE_BITS,
+        )?;
+        check_range("timestamp", self.timestamp.into(), TIMESTAMP_BITS)?;
+        if self.site_domain.is_empty() || !self.site_domain.is_ascii() {
+            return Err(Error::InvalidSiteDomain);
+        }
+
+        Ok(())
+    }
+
+    /// Splits this report into replicated secret shares, one for each helper.
+    ///
+    /// ## Errors
+    /// If the report is not valid, see [`Self::validate`].
+    pub fn share<R: RngCore>(&self, rng: &mut R) -> Result<[ReportShare; 3], Error> {
+        self.validate()?;
+
+        let [mk1, mk2, mk3] = share(self.match_key, MATCH_KEY_BITS, rng);
+        let [bk1, bk2, bk3] = share(self.breakdown_key.into(), BREAKDOWN_KEY_BITS, rng);
+        let [tv1, tv2, tv3] = share(self.trigger_value.into(), TRIGGER_VALUE_BITS, rng);
+        let [ts1, ts2, ts3] = share(self.timestamp.into(), TIMESTAMP_BITS, rng);
+        let report_share = |match_key, breakdown_key, trigger_value, timestamp| ReportShare {
+            match_key,
+            breakdown_key,
+            trigger_value,
+            timestamp,
+            event_type: self.event_type,
+            epoch: self.epoch,
+            site_domain: self.site_domain.clone(),
+        };
+
+        Ok([
+            report_share(mk1, bk1, tv1, ts1),
+            report_share(mk2, bk2, tv2, ts2),
+            report_share(mk3, bk3, tv3, ts3),
+        ])
+    }
+
+    /// Secret-shares this report and encrypts the shares, returning the ciphertext for each of
+    /// the helpers in helper order.
+    ///
+    /// ## Errors
+    /// If the report is not valid, or encryption fails.
+    pub fn encrypt<R: CryptoRng + RngCore>(
+        &self,
+        keys: &HelperPublicKeys,
+        rng: &mut R,
+    ) -> Result<[Vec<u8>; 3], Error> {
+        let [s1, s2, s3] = self.share(rng)?;
+        let [k1, k2, k3] = keys.keys();
+
+        Ok([
+            s1.encrypt(keys.key_id(), k1, rng)?,
+            s2.encrypt(keys.key_id(), k2, rng)?,
+            s3.encrypt(keys.key_id(), k3, rng)?,
+        ])
+    }
+}
+
+/// A report in the Hybrid format, before it is secret-shared.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HybridReport {
+    Impression { match_key: u64, breakdown_key: u8 },
+    Conversion { match_key: u64, value: u8 },
+}
+
+impl HybridReport {
+    /// The OPRF report that carries this report on the wire. Hybrid reports do not use
+    /// timestamps or epochs, so they are set to zero.
+    #[must_use]
+    pub fn to_oprf_report(&self, site_domain: &str) -> OprfReport {
+        let (match_key, event_type, breakdown_key, trigger_value) = match *self {
+            Self::Impression {
+                match_key,
+                breakdown_key,
+            } => (match_key, EventType::Source, breakdown_key, 0),
+            Self::Conversion { match_key, value } => (match_key, EventType::Trigger, 0, value),
+        };
+
+        OprfReport {
+            match_key,
+            event_type,
+            breakdown_key,
+            trigger_value,
+            timestamp: 0,
+            epoch: 0,
+            site_domain: site_domain.to_string(),
+        }
+    }
+
+    /// Secret-shares this report and encrypts the shares, returning the ciphertext for each of
+    /// the helpers in helper order.
+    ///
+    /// ## Errors
+    /// If the value does not fit in [`TRIGGER_VALUE_BITS`] bits, the site domain is not valid,
+    /// or encryption fails.
+    pub fn encrypt<R: CryptoRng + RngCore>(
+        &self,
+        site_domain: &str,
+        keys: &HelperPublicKeys,
+        rng: &mut R,
+    ) -> Result<[Vec<u8>; 3], Error> {
+        self.to_oprf_report(site_domain).encrypt(keys, rng)
+    }
+}
+
+/// A replicated share of a value held by one helper: its own share on the left and the share
+/// of the next helper on the right.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Share {
+    pub left: u64,
+    pub right: u64,
+}
+
+/// The shares of a report that are sent to one helper.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportShare {
+    pub match_key: Share,
+    pub breakdown_key: Share,
+    pub trigger_value: Share,
+    pub timestamp: Share,
+    pub event_type: EventType,
+    pub epoch: u16,
+    pub site_domain: String,
+}
+
+impl ReportShare {
+    const ENCAPSULATED_KEY_LEN: usize = 32;
+    const TAG_LEN: usize = 16;
+
+    /// Encrypts this share to the public key of the helper that it is meant for.
+    ///
+    /// The layout matches `EncryptedOprfReport` in `ipa-core`: the [`REPORT_VERSION`], the
+    /// encapsulated key, ciphertext and tag of the match key, followed by the same for the
+    /// timestamp, breakdown key and trigger value, and then the event type, key id, epoch and
+    /// site domain in the clear.
+    ///
+    /// ## Errors
+    /// If encryption fails.
+    pub fn encrypt<R: CryptoRng + RngCore>(
+        &self,
+        key_id: KeyIdentifier,
+        public_key: &PublicKey,
+        rng: &mut R,
+    ) -> Result<Vec<u8>, Error> {
+        let info = self.info(key_id);
+
+        let mut plaintext_mk = Vec::with_capacity(2 * share_len(MATCH_KEY_BITS));
+        put_share(&mut plaintext_mk, self.match_key, MATCH_KEY_BITS);
+
+        let mut plaintext_btt = Vec::new();
+        put_share(&mut plaintext_btt, self.timestamp, TIMESTAMP_BITS);
+        put_share(&mut plaintext_btt, self.breakdown_key, BREAKDOWN_KEY_BITS);
+        put_share(&mut plaintext_btt, self.trigger_value, TRIGGER_VALUE_BITS);
+
+        let mut out = Vec::with_capacity(
+            2 * (Self::ENCAPSULATED_KEY_LEN + Self::TAG_LEN)
+                + plaintext_mk.len()
+                + plaintext_btt.len()
+                + 5
+                + self.site_domain.len(),
+        );
+        out.push(REPORT_VERSION);
+        for plaintext in [&mut plaintext_mk, &mut plaintext_btt] {
+            let (encap_key, tag) = single_shot_seal_in_place_detached::<
+                AesGcm128,
+                HkdfSha256,
+                X25519HkdfSha256,
+                _,
+            >(
+                &OpModeS::Base, public_key, &info, plaintext, &[], rng
+            )
+            .map_err(|_| Error::Encryption)?;
+            out.extend_from_slice(&encap_key.to_bytes());
+            out.extend_from_slice(plaintext);
+            out.extend_from_slice(&tag.to_bytes());
+        }
+        out.push(self.event_type as u8);
+        out.push(key_id);
+        out.extend_from_slice(&self.epoch.to_le_bytes());
+        out.extend_from_slice(self.site_domain.as_bytes());
+
+        Ok(out)
+    }
+
+    /// The HPKE info that binds the ciphertexts to the fields sent in the clear and to the
+    /// [`REPORT_VERSION`], see `ipa_core::hpke::Info`.
+    fn info(&self, key_id: KeyIdentifier) -> Vec<u8> {
+        let mut info =
+            Vec::with_capacity(DOMAIN.len() + HELPER_ORIGIN.len() + self.site_domain.len() + 3 + 5);
+        for part in [DOMAIN, HELPER_ORIGIN, &self.site_domain] {
+            info.extend_from_slice(part.as_bytes());
+            info.push(0);
+        }
+        info.push(key_id);
+        info.extend_from_slice(&self.epoch.to_be_bytes());
+        info.push(self.event_type as u8);
+        info.push(REPORT_VERSION);
+
+        info
+    }
+}
+
+fn check_range(field: &'static str, value: u64, bits: u32) -> Result<(), Error> {
+    if value >> bits == 0 {
+        Ok(())
+    } else {
+        Err(Error::OutOfRange { field, value, bits })
+    }
+}
+
+/// Number of bytes that a share of a `bits` wide boolean array is serialized to.
+fn share_len(bits: u32) -> usize {
+    bits.div_ceil(8) as usize
+}
+
+fn put_share(out: &mut Vec<u8>, share: Share, bits: u32) {
+    let len = share_len(bits);
+    out.extend_from_slice(&share.left.to_le_bytes()[..len]);
+    out.extend_from_slice(&share.right.to_le_bytes()[..len]);
+}
+
+/// Shares `value` as a `bits` wide boolean array. The helpers hold `(x1, x2)`, `(x2, x3)` and
+/// `(x3, x1)`, where `x1 ^ x2 ^ x3 == value`.
+fn share<R: RngCore>(value: u64, bits: u32, rng: &mut R) -> [Share; 3] {
+    let mask = u64::MAX >> (u64::BITS - bits);
+    let x1 = rng.next_u64() & mask;
+    let x2 = rng.next_u64() & mask;
+    let x3 = value ^ x1 ^ x2;
+
+    [
+        Share {
+            left: x1,
+            right: x2,
+        },
+        Share {
+            left: x2,
+            right: x3,
+        },
+        Share {
+            left: x3,
+            right: x1,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use hpke::{
+        aead::{AeadTag, AesGcm128},
+        kdf::HkdfSha256,
+        kem::X25519HkdfSha256,
+        single_shot_open_in_place_detached, Deserializable, OpModeR,
+    };
+    use ipa_core::{
+        ff::{
+            boolean_array::{BA20, BA3, BA64, BA8},
+            U128Conversions,
+        },
+        hpke::{KeyRegistry, PublicKeyOnly},
+        report::{EventType as IpaEventType, OprfReport as IpaOprfReport},
+        secret_sharing::{
+            replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+            SharedValue,
+        },
+    };
+    use rand::{rngs::StdRng, thread_rng, CryptoRng, Rng, RngCore, SeedableRng};
+    use serde::Deserialize;
+
+    use super::{
+        put_share, share, share_len, EventType, HybridReport, OprfReport, ReportShare, Share,
+        BREAKDOWN_KEY_BITS, MATCH_KEY_BITS, REPORT_VERSION, TIMESTAMP_BITS, TRIGGER_VALUE_BITS,
+    };
+    use crate::{Error, HelperPublicKeys, KeyIdentifier};
+
+    /// Shared with `ipa_core::report::test_vectors`, see the README next to them for the format.
+    const TEST_VECTORS: [&str; 2] = [
+        include_str!("../../ipa-core/src/report/test_vectors/v1.json"),
+        include_str!("../../ipa-core/src/report/test_vectors/ios.json"),
+    ];
+
+    #[derive(Deserialize)]
+    struct TestVectors {
+        version: u8,
+        helper_keys: [HelperKeys; 3],
+        vectors: Vec<TestVector>,
+    }
+
+    #[derive(Deserialize)]
+    struct HelperKeys {
+        #[serde(with = "hex")]
+        public_key: Vec<u8>,
+        #[serde(with = "hex")]
+        private_key: Vec<u8>,
+    }
+
+    #[derive(Deserialize)]
+    struct TestVector {
+        name: String,
+        key_id: u8,
+        event_type: String,
+        epoch: u16,
+        site_domain: String,
+        match_key: u64,
+        breakdown_key: u8,
+        trigger_value: u8,
+        timestamp: u32,
+        #[serde(default)]
+        shares: Option<[HelperShares; 3]>,
+        ciphertexts: [String; 3],
+    }
+
+    #[derive(Deserialize)]
+    struct HelperShares {
+        #[serde(with = "hex")]
+        match_key: Vec<u8>,
+        #[serde(with = "hex")]
+        btt: Vec<u8>,
+        #[serde(with = "hex")]
+        match_key_ikm: Vec<u8>,
+        #[serde(with = "hex")]
+        btt_ikm: Vec<u8>,
+    }
+
+    impl TestVector {
+        fn report(&self) -> OprfReport {
+            OprfReport {
+                match_key: self.match_key,
+                event_type: match self.event_type.as_str() {
+                    "source" => EventType::Source,
+                    "trigger" => EventType::Trigger,
+                    other => panic!("unknown event type {other} in test vector {}", self.name),
+                },
+                breakdown_key: self.breakdown_key,
+                trigger_value: self.trigger_value,
+                timestamp: self.timestamp,
+                epoch: self.epoch,
+                site_domain: self.site_domain.clone(),
+            }
+        }
+    }
+
+    fn test_vectors() -> impl Iterator<Item = TestVectors> {
+        TEST_VECTORS
+            .into_iter()
+            .map(|json| serde_json::from_str(json).unwrap())
+    }
+
+    fn public_keys(vectors: &TestVectors, key_id: u8) -> HelperPublicKeys {
+        let [k1, k2, k3] = vectors.helper_keys.each_ref().map(|k| &k.public_key[..]);
+        HelperPublicKeys::from_bytes(key_id, [k1, k2, k3]).unwrap()
+    }
+
+    /// Version number of legacy reports in the test vectors, which have no version byte and do
+    /// not bind the version into the HPKE info.
+    const LEGACY_VERSION: u8 = 0;
+
+    /// Decrypts `ciphertext` with the helper's private key and returns the plaintexts of the
+    /// match key and of the timestamp, breakdown key and trigger value.
+    fn open(
+        keys: &HelperKeys,
+        key_id: KeyIdentifier,
+        report: &ReportShare,
+        version: u8,
+        ciphertext: &[u8],
+    ) -> [Vec<u8>; 2] {
+        let private_key =
+            <X25519HkdfSha256 as hpke::kem::Kem>::PrivateKey::from_bytes(&keys.private_key)
+                .unwrap();
+        let mut info = report.info(key_id);
+        let mk_len = 2 * share_len(MATCH_KEY_BITS);
+        let btt_len = 2
+            * (share_len(TIMESTAMP_BITS)
+                + share_len(BREAKDOWN_KEY_BITS)
+                + share_len(TRIGGER_VALUE_BITS));
+
+        let mut offset = if version == LEGACY_VERSION {
+            assert_eq!(info.pop(), Some(REPORT_VERSION));
+            0
+        } else {
+            assert_eq!(ciphertext[0], REPORT_VERSION);
+            1
+        };
+        [mk_len, btt_len].map(|len| {
+            let encap_key = <X25519HkdfSha256 as hpke::kem::Kem>::EncappedKey::from_bytes(
+                &ciphertext[offset..offset + ReportShare::ENCAPSULATED_KEY_LEN],
+            )
+            .unwrap();
+            offset += ReportShare::ENCAPSULATED_KEY_LEN;
+            let mut plaintext = ciphertext[offset..offset + len].to_vec();
+            offset += len;
+            let tag = AeadTag::<AesGcm128>::from_bytes(
+                &ciphertext[offset..offset + ReportShare::TAG_LEN],
+            )
+            .unwrap();
+            offset += ReportShare::TAG_LEN;
+
+            single_shot_open_in_place_detached::<AesGcm128, HkdfSha256, X25519HkdfSha256>(
+                &OpModeR::Base,
+                &private_key,
+                &encap_key,
+                &info,
+                &mut plaintext,
+                &[],
+                &tag,
+            )
+            .unwrap();
+            plaintext
+        })
+    }
+
+    /// Reads the left shares out of the decrypted plaintexts of each helper and reconstructs
+    /// the report fields from them.
+    fn reconstruct(plaintexts: &[[Vec<u8>; 2]; 3]) -> [u64; 4] {
+        let read = |bytes: &[u8], bits: u32| {
+            let mut le = [0; 8];
+            le[..share_len(bits)].copy_from_slice(&bytes[..share_len(bits)]);
+            u64::from_le_bytes(le)
+        };
+        let fields = |[mk, btt]: &[Vec<u8>; 2]| {
+            let ts_len = 2 * share_len(TIMESTAMP_BITS);
+            let bk_len = 2 * share_len(BREAKDOWN_KEY_BITS);
+            [
+                read(mk, MATCH_KEY_BITS),
+                read(&btt[ts_len..], BREAKDOWN_KEY_BITS),
+                read(&btt[ts_len + bk_len..], TRIGGER_VALUE_BITS),
+                read(btt, TIMESTAMP_BITS),
+            ]
+        };
+        let [f1, f2, f3] = plaintexts.each_ref().map(fields);
+
+        [0, 1, 2, 3].map(|i| f1[i] ^ f2[i] ^ f3[i])
+    }
+
+    #[test]
+    fn decrypts_shared_test_vectors() {
+        for vectors in test_vectors() {
+            assert!([LEGACY_VERSION, REPORT_VERSION].contains(&vectors.version));
+            decrypts_test_vectors(&vectors);
+        }
+    }
+
+    fn decrypts_test_vectors(vectors: &TestVectors) {
+        for vector in &vectors.vectors {
+            let report = vector.report();
+            let [template, ..] = report.share(&mut thread_rng()).unwrap();
+            let keys = public_keys(vectors, vector.key_id);
+
+            let plaintexts = [0, 1, 2].map(|i| {
+                let ciphertext = hex::decode(&vector.ciphertexts[i]).unwrap();
+                let ours = template
+                    .encrypt(keys.key_id(), &keys.keys()[i], &mut thread_rng())
+                    .unwrap();
+                let header_len = usize::from(vectors.version != LEGACY_VERSION);
+                assert_eq!(
+                    ciphertext.len() + 1 - header_len,
+                    ours.len(),
+                    "{}",
+                    vector.name
+                );
+                assert_eq!(
+                    ciphertext[ciphertext.len() - report.site_domain.len() - 4..],
+                    ours[ours.len() - report.site_domain.len() - 4..],
+                    "{}",
+                    vector.name
+                );
+
+                open(
+                    &vectors.helper_keys[i],
+                    keys.key_id(),
+                    &template,
+                    vectors.version,
+                    &ciphertext,
+                )
+            });
+
+            assert_eq!(
+                reconstruct(&plaintexts),
+                [
+                    vector.match_key,
+                    vector.breakdown_key.into(),
+                    vector.trigger_value.into(),
+                    vector.timestamp.into()
+                ],
+                "{}",
+                vector.name
+            );
+        }
+    }
+
+    /// Replays the key material that the ephemeral keys of a generated vector are derived from.
+    struct EphemeralKeyMaterial(Vec<u8>);
+
+    impl RngCore for EphemeralKeyMaterial {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.copy_from_slice(&self.0[..dest.len()]);
+            self.0.drain(..dest.len());
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for EphemeralKeyMaterial {}
+
+    fn read_share(bytes: &mut &[u8], bits: u32) -> Share {
+        let [left, right] = [0; 2].map(|_| {
+            let (share, rest) = bytes.split_at(share_len(bits));
+            *bytes = rest;
+            let mut le = [0; 8];
+            le[..share.len()].copy_from_slice(share);
+            u64::from_le_bytes(le)
+        });
+
+        Share { left, right }
+    }
+
+    /// Reads the pairs of shares out of the plaintexts of a helper.
+    fn read_shares(vector: &TestVector, shares: &HelperShares) -> ReportShare {
+        let mut btt = shares.btt.as_slice();
+        let match_key = read_share(&mut shares.match_key.as_slice(), MATCH_KEY_BITS);
+        let timestamp = read_share(&mut btt, TIMESTAMP_BITS);
+        let breakdown_key = read_share(&mut btt, BREAKDOWN_KEY_BITS);
+        let trigger_value = read_share(&mut btt, TRIGGER_VALUE_BITS);
+        let report = vector.report();
+
+        ReportShare {
+            match_key,
+            breakdown_key,
+            trigger_value,
+            timestamp,
+            event_type: report.event_type,
+            epoch: report.epoch,
+            site_domain: report.site_domain,
+        }
+    }
+
+    #[test]
+    fn reproduces_generated_test_vectors() {
+        let vectors = test_vectors().next().unwrap();
+        let keys = public_keys(&vectors, 0);
+        for vector in &vectors.vectors {
+            let shares = vector.shares.as_ref().unwrap();
+            for ((shares, public_key), expected) in
+                shares.iter().zip(keys.keys()).zip(&vector.ciphertexts)
+            {
+                let share = read_shares(vector, shares);
+                let mut plaintext = Vec::new();
+                put_share(&mut plaintext, share.match_key, MATCH_KEY_BITS);
+                assert_eq!(plaintext, shares.match_key, "{}", vector.name);
+
+                let mut rng =
+                    EphemeralKeyMaterial([&shares.match_key_ikm[..], &shares.btt_ikm[..]].concat());
+                let ciphertext = share.encrypt(vector.key_id, public_key, &mut rng).unwrap();
+
+                assert!(rng.0.is_empty(), "{}", vector.name);
+                assert_eq!(&hex::encode(ciphertext), expected, "{}", vector.name);
+            }
+        }
+    }
+
+    fn to_ipa_share<V: SharedValue + U128Conversions>(share: Share) -> AdditiveShare<V> {
+        AdditiveShare::new(
+            V::truncate_from(u128::from(share.left)),
+            V::truncate_from(u128::from(share.right)),
+        )
+    }
+
+    fn to_ipa_report(share: &ReportShare) -> IpaOprfReport<BA8, BA3, BA20> {
+        IpaOprfReport {
+            match_key: to_ipa_share::<BA64>(share.match_key),
+            breakdown_key: to_ipa_share(share.breakdown_key),
+            trigger_value: to_ipa_share(share.trigger_value),
+            timestamp: to_ipa_share(share.timestamp),
+            event_type: match share.event_type {
+                EventType::Source => IpaEventType::Source,
+                EventType::Trigger => IpaEventType::Trigger,
+            },
+            epoch: share.epoch,
+            site_domain: share.site_domain.clone(),
+        }
+    }
+
+    #[test]
+    fn matches_ipa_core_encryption() {
+        let vectors = test_vectors().next().unwrap();
+        let keys = public_keys(&vectors, 0);
+        let mut rng = thread_rng();
+
+        for event_type in [EventType::Source, EventType::Trigger] {
+            let report = OprfReport {
+                match_key: rng.gen(),
+                event_type,
+                breakdown_key: rng.gen(),
+                trigger_value: rng.gen_range(0..8),
+                timestamp: rng.gen_range(0..1 << 20),
+                epoch: rng.gen(),
+                site_domain: "www.example.com".to_string(),
+            };
+
+            for (share, public_key) in report.share(&mut rng).unwrap().iter().zip(keys.keys()) {
+                let seed = rng.gen();
+                let ours = share
+                    .encrypt(0, public_key, &mut StdRng::seed_from_u64(seed))
+                    .unwrap();
+                let registry = KeyRegistry::from_keys([PublicKeyOnly(public_key.clone())]);
+                let theirs = to_ipa_report(share)
+                    .encrypt(0, &registry, &mut StdRng::seed_from_u64(seed))
+                    .unwrap();
+
+                assert_eq!(ours, theirs);
+            }
+        }
+    }
+
+    #[test]
+    fn shares_reconstruct() {
+        let mut rng = thread_rng();
+        for bits in [
+            MATCH_KEY_BITS,
+            BREAKDOWN_KEY_BITS,
+            TRIGGER_VALUE_BITS,
+            TIMESTAMP_BITS,
+        ] {
+            let value = rng.gen::<u64>() >> (u64::BITS - bits);
+            let [s1, s2, s3] = share(value, bits, &mut rng);
+            assert_eq!(s1.left ^ s2.left ^ s3.left, value);
+            assert_eq!([s1.right, s2.right, s3.right], [s2.left, s3.left, s1.left]);
+
+            let mut bytes = Vec::new();
+            put_share(&mut bytes, s1, bits);
+            assert_eq!(bytes.len(), 2 * share_len(bits));
+        }
+    }
+
+    #[test]
+    fn hybrid_reports() {
+        let impression = HybridReport::Impression {
+            match_key: 1,
+            breakdown_key: 45,
+        }
+        .to_oprf_report("www.meta.com");
+        assert_eq!(
+            (
+                impression.event_type,
+                impression.breakdown_key,
+                impression.trigger_value
+            ),
+            (EventType::Source, 45, 0)
+        );
+
+        let conversion = HybridReport::Conversion {
+            match_key: 1,
+            value: 5,
+        }
+        .to_oprf_report("www.abc.com");
+        assert_eq!(
+            (
+                conversion.event_type,
+                conversion.breakdown_key,
+                conversion.trigger_value
+            ),
+            (EventType::Trigger, 0, 5)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_reports() {
+        let report = OprfReport {
+            match_key: 1,
+            event_type: EventType::Trigger,
+            breakdown_key: 0,
+            trigger_value: 5,
+            timestamp: 123,
+            epoch: 0,
+            site_domain: "www.abc.com".to_string(),
+        };
+        assert_eq!(report.validate(), Ok(()));
+
+        assert!(matches!(
+            OprfReport {
+                trigger_value: 8,
+                ..report.clone()
+            }
+            .validate(),
+            Err(Error::OutOfRange { bits: 3, .. })
+        ));
+        assert!(matches!(
+            OprfReport {
+                timestamp: 1 << 20,
+                ..report.clone()
+            }
+            .validate(),
+            Err(Error::OutOfRange { bits: 20, .. })
+        ));
+        for site_domain in ["", "www.abç.com"] {
+            assert_eq!(
+                OprfReport {
+                    site_domain: site_domain.to_string(),
+                    ..report.clone()
+                }
+                .validate(),
+                Err(Error::InvalidSiteDomain)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_public_keys() {
+        let vectors = test_vectors().next().unwrap();
+        let key = &vectors.helper_keys[0].public_key;
+        assert!(matches!(
+            HelperPublicKeys::from_bytes(0, [key, key, &key[..31]]),
+            Err(Error::InvalidPublicKey { helper: 3 })
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+//! JavaScript bindings, built with `wasm-pack build ipa-client --features wasm`.
+//!
+//! ```js
+//! import { HelperKeys, encryptHybridConversion } from "ipa-client";
+//!
+//! const keys = new HelperKeys(0, helper1Key, helper2Key, helper3Key);
+//! const [h1, h2, h3] = encryptHybridConversion(keys, matchKey, 5, "www.abc.com");
+//! ```
+//!
+//! Match keys are passed as `BigInt`s and ciphertexts are returned as an array of three
+//! `Uint8Array`s, in helper order.
+
+use js_sys::{Array, Uint8Array};
+use rand_core::OsRng;
+use wasm_bindgen::prelude::*;
+
+use crate::{EventType, HelperPublicKeys, HybridReport, OprfReport};
+
+/// The public keys of the three helpers.
+#[wasm_bindgen]
+pub struct HelperKeys(HelperPublicKeys);
+
+#[wasm_bindgen]
+impl HelperKeys {
+    /// ## Errors
+    /// If any of the keys is not a valid X25519 public key.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        key_id: u8,
+        helper_1: &[u8],
+        helper_2: &[u8],
+        helper_3: &[u8],
+    ) -> Result<HelperKeys, JsError> {
+        Ok(Self(HelperPublicKeys::from_bytes(
+            key_id,
+            [helper_1, helper_2, helper_3],
+        )?))
+    }
+}
+
+/// ## Errors
+/// If the report is not valid, or encryption fails.
+#[wasm_bindgen(js_name = encryptOprfReport)]
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_oprf_report(
+    keys: &HelperKeys,
+    match_key: u64,
+    event_type: u8,
+    breakdown_key: u8,
+    trigger_value: u8,
+    timestamp: u32,
+    epoch: u16,
+    site_domain: String,
+) -> Result<Array, JsError> {
+    let report = OprfReport {
+        match_key,
+        event_type: EventType::try_from(event_type)?,
+        breakdown_key,
+        trigger_value,
+        timestamp,
+        epoch,
+        site_domain,
+    };
+
+    Ok(to_js(report.encrypt(&keys.0, &mut OsRng)?))
+}
+
+/// ## Errors
+/// If the site domain is not valid, or encryption fails.
+#[wasm_bindgen(js_name = encryptHybridImpression)]
+pub fn encrypt_hybrid_impression(
+    keys: &HelperKeys,
+    match_key: u64,
+    breakdown_key: u8,
+    site_domain: &str,
+) -> Result<Array, JsError> {
+    let report = HybridReport::Impression {
+        match_key,
+        breakdown_key,
+    };
+
+    Ok(to_js(report.encrypt(site_domain, &keys.0, &mut OsRng)?))
+}
+
+/// ## Errors
+/// If the value or the site domain is not valid, or encryption fails.
+#[wasm_bindgen(js_name = encryptHybridConversion)]
+pub fn encrypt_hybrid_conversion(
+    keys: &HelperKeys,
+    match_key: u64,
+    value: u8,
+    site_domain: &str,
+) -> Result<Array, JsError> {
+    let report = HybridReport::Conversion { match_key, value };
+
+    Ok(to_js(report.encrypt(site_domain, &keys.0, &mut OsRng)?))
+}
+
+fn to_js([c1, c2, c3]: [Vec<u8>; 3]) -> Array {
+    let [c1, c2, c3] = [c1, c2, c3].map(|c| Uint8Array::from(c.as_slice()));
+    Array::of3(&c1, &c2, &c3)
+}
@@ -1,14 +1,18 @@
 use std::{
     env,
-    num::{NonZeroU32, NonZeroUsize},
-    time::Instant,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    time::{Duration, Instant},
 };
 
 use clap::Parser;
 use ipa_core::{
     error::Error,
     ff::Fp32BitPrime,
-    helpers::{query::IpaQueryConfig, GatewayConfig},
+    helpers::{
+        in_memory_shaping::{LinkShape, NetworkShape},
+        query::IpaQueryConfig,
+        GatewayConfig,
+    },
     protocol::{step::ProtocolStep::IpaPrf, Gate},
     test_fixture::{
         ipa::{ipa_in_the_clear, test_oprf_ipa, CappingOrder, IpaSecurityModel},
@@ -73,6 +77,22 @@ struct Args {
     /// The amount of active items to concurrently track.
     #[arg(short = 'a', long)]
     active_work: Option<NonZeroUsize>,
+    /// The number of bytes to read from the network at once. Uses the gateway default if not set.
+    #[arg(long)]
+    read_size: Option<NonZeroUsize>,
+    /// Simulated one-way latency between helpers, in milliseconds.
+    #[arg(long, default_value = "0")]
+    latency_ms: u64,
+    /// Maximum random delay added to the simulated latency, in milliseconds.
+    #[arg(long, default_value = "0")]
+    jitter_ms: u64,
+    /// Simulated bandwidth between each pair of helpers, in megabits per second. Unlimited if
+    /// not set.
+    #[arg(long)]
+    bandwidth_mbps: Option<NonZeroU64>,
+    /// Simulated overhead for each chunk of data sent between helpers, in microseconds.
+    #[arg(long, default_value = "0")]
+    batch_delay_us: u64,
     /// Desired security model for IPA protocol
     #[arg(short = 'm', long, value_enum, default_value_t=IpaSecurityModel::Malicious)]
     mode: IpaSecurityModel,
@@ -89,6 +109,18 @@ impl Args {
             .next_power_of_two()
     }
 
+    fn network_shape(&self) -> Option<NetworkShape> {
+        let link = LinkShape {
+            latency: Duration::from_millis(self.latency_ms),
+            jitter: Duration::from_millis(self.jitter_ms),
+            bandwidth: self
+                .bandwidth_mbps
+                .map(|mbps| mbps.saturating_mul(NonZeroU64::new(125_000).unwrap())),
+            batch_delay: Duration::from_micros(self.batch_delay_us),
+        };
+        (link != LinkShape::default()).then(|| NetworkShape::helper_links(link))
+    }
+
     fn attribution_window(&self) -> Option<NonZeroU32> {
         NonZeroU32::new(self.attribution_window)
     }
@@ -113,9 +145,11 @@ async fn run(args: Args) -> Result<(), Error> {
     let config = TestWorldConfig {
         gateway_config: GatewayConfig {
             active: args.active().try_into().unwrap(),
+            read_size: args.read_size.unwrap_or(GatewayConfig::default().read_size),
             ..Default::default()
         },
         initial_gate: Some(Gate::default().narrow(&IpaPrf)),
+        network_shape: args.network_shape(),
         ..TestWorldConfig::default()
     };
     // Construct TestWorld early to initialize logging.
@@ -143,8 +177,9 @@ async fn run(args: Args) -> Result<(), Error> {
         &raw_data,
         args.per_user_cap,
         args.attribution_window(),
+        None,
         args.breakdown_keys,
-        &order,
+        order,
     );
 
     tracing::trace!("Preparation complete in {:?}", _prep_time.elapsed());
@@ -0,0 +1,334 @@
+//! End-to-end load test for IPA. For every query size and security model, it starts three
+//! `helper` processes on localhost, generates and encrypts the reports with `report_collector`
+//! and `crypto_util`, runs the query and records its latency, and the peak RSS and bytes sent by
+//! each helper. The results are written as JSON after every run, see [`Report`].
+//!
+//! Hybrid queries are not included, because helpers do not run them yet.
+
+use std::{
+    array,
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    net::TcpListener,
+    num::NonZeroU32,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use command_fds::CommandFdExt;
+use ipa_core::{
+    cli::ResultExport, helpers::query::IpaQueryConfig, test_fixture::ipa::IpaSecurityModel,
+};
+use rand::random;
+use serde::Serialize;
+
+const HELPER_BIN: &str = env!("CARGO_BIN_EXE_helper");
+const REPORT_COLLECTOR_BIN: &str = env!("CARGO_BIN_EXE_report_collector");
+const CRYPTO_UTIL_BIN: &str = env!("CARGO_BIN_EXE_crypto_util");
+
+/// Helpers write their metrics every second, so this is enough for the metrics files to include
+/// everything a query sent.
+const METRICS_DELAY: Duration = Duration::from_millis(2500);
+
+/// Runs IPA queries against three helpers on localhost and reports their resource usage.
+#[derive(Parser)]
+#[command(about, long_about = None)]
+struct Args {
+    /// Comma-separated numbers of reports to run queries with.
+    #[arg(long, value_delimiter = ',', default_value = "1000,10000")]
+    sizes: Vec<NonZeroU32>,
+    /// Comma-separated security models to run queries with.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "semi-honest,malicious"
+    )]
+    security_models: Vec<IpaSecurityModel>,
+    /// Number of times to run each query.
+    #[arg(long, default_value = "1")]
+    repetitions: NonZeroU32,
+    #[clap(flatten)]
+    ipa_query_config: IpaQueryConfig,
+    /// The random seed for generating reports. Every run uses a different seed if not set.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// File to write the report to.
+    #[arg(long, default_value = "load-test.json")]
+    output_file: PathBuf,
+    /// Directory for the configuration, inputs and logs of each run. They are deleted after the
+    /// load test if not set.
+    #[arg(long)]
+    work_dir: Option<PathBuf>,
+    /// Needed for benches.
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+/// The output of the load test.
+#[derive(Serialize)]
+struct Report {
+    version: &'static str,
+    config: IpaQueryConfig,
+    runs: Vec<Run>,
+}
+
+#[derive(Serialize)]
+struct Run {
+    security_model: IpaSecurityModel,
+    query_size: u32,
+    repetition: u32,
+    seed: u64,
+    /// Query latency reported by the report collector, in seconds.
+    latency: f64,
+    /// Time to run the report collector, including uploading the inputs, in seconds.
+    wall_time: f64,
+    helpers: [HelperStats; 3],
+}
+
+#[derive(Serialize)]
+struct HelperStats {
+    /// Peak resident set size of the helper process. Only available on Linux.
+    peak_rss_bytes: Option<u64>,
+    /// Bytes sent to the other helpers by the protocol.
+    bytes_sent: Option<u64>,
+    /// Bytes sent to the other helpers over the network, after compression.
+    bytes_sent_wire: Option<u64>,
+    records_sent: Option<u64>,
+}
+
+/// A helper process, that is killed when dropped.
+struct Helper {
+    child: Child,
+    metrics_file: PathBuf,
+}
+
+impl Helper {
+    fn spawn(id: usize, dir: &Path, socket: &TcpListener) -> Result<Self, Box<dyn Error>> {
+        let metrics_file = dir.join(format!("h{id}_metrics.json"));
+        let log = fs::File::create(dir.join(format!("h{id}.log")))?;
+        let mut command = Command::new(HELPER_BIN);
+        command
+            .args(["-i", &id.to_string()])
+            .args(["--network".into(), dir.join("network.toml")])
+            .args(["--tls-cert".into(), dir.join(format!("h{id}.pem"))])
+            .args(["--tls-key".into(), dir.join(format!("h{id}.key"))])
+            .args(["--mk-public-key".into(), dir.join(format!("h{id}_mk.pub"))])
+            .args(["--mk-private-key".into(), dir.join(format!("h{id}_mk.key"))])
+            .args(["--metrics-file".as_ref(), metrics_file.as_os_str()])
+            .args(["--server-socket-fd", &socket.as_raw_fd().to_string()])
+            .preserved_fds(vec![socket.as_raw_fd()])
+            .stdout(log.try_clone()?)
+            .stderr(log);
+
+        let child = command.spawn()?;
+        Ok(Self {
+            child,
+            metrics_file,
+        })
+    }
+
+    fn stats(&self) -> HelperStats {
+        let counters = fs::read(&self.metrics_file)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<BTreeMap<String, u64>>(&json).ok())
+            .unwrap_or_default();
+        HelperStats {
+            peak_rss_bytes: peak_rss(self.child.id()),
+            bytes_sent: counters.get("bytes.sent").copied(),
+            bytes_sent_wire: counters.get("bytes.sent.wire").copied(),
+            records_sent: counters.get("records.sent").copied(),
+        }
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Reads the peak resident set size of a process from `/proc`.
+fn peak_rss(pid: u32) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+fn run_command(command: &mut Command) -> Result<(), Box<dyn Error>> {
+    let output = command.stdin(Stdio::null()).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{command:?} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )
+        .into())
+    }
+}
+
+fn query_args(config: &IpaQueryConfig) -> Vec<String> {
+    let mut args = vec![
+        "--max-breakdown-key".to_string(),
+        config.max_breakdown_key.to_string(),
+        "--per-user-credit-cap".to_string(),
+        config.per_user_credit_cap.to_string(),
+        "--with-dp".to_string(),
+        config.with_dp.to_string(),
+        "--epsilon".to_string(),
+        config.epsilon.to_string(),
+    ];
+    if let Some(window) = config.attribution_window_seconds {
+        args.extend([
+            "--attribution-window-seconds".to_string(),
+            window.to_string(),
+        ]);
+    }
+    args
+}
+
+fn run(
+    dir: &Path,
+    security_model: IpaSecurityModel,
+    query_size: u32,
+    config: &IpaQueryConfig,
+    seed: u64,
+) -> Result<(f64, f64, [HelperStats; 3]), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let network = dir.join("network.toml");
+    let inputs = dir.join("inputs.txt");
+    let output = dir.join("output.json");
+
+    run_command(
+        Command::new(REPORT_COLLECTOR_BIN)
+            .arg("--quiet")
+            .args(["--output-file".as_ref(), inputs.as_os_str()])
+            .arg("gen-ipa-inputs")
+            .args(["--count", &query_size.to_string()])
+            .args(["--max-breakdown-key", &config.max_breakdown_key.to_string()])
+            .args(["--seed", &seed.to_string()]),
+    )?;
+
+    let sockets: [_; 3] = array::from_fn(|_| TcpListener::bind("127.0.0.1:0").unwrap());
+    let ports = sockets
+        .each_ref()
+        .map(|socket| socket.local_addr().unwrap().port());
+    run_command(
+        Command::new(HELPER_BIN)
+            .arg("--quiet")
+            .arg("test-setup")
+            .args(["--output-dir".as_ref(), dir.as_os_str()])
+            .arg("--ports")
+            .args(ports.map(|port| port.to_string())),
+    )?;
+
+    run_command(
+        Command::new(CRYPTO_UTIL_BIN)
+            .arg("encrypt")
+            .args(["--input-file".as_ref(), inputs.as_os_str()])
+            .args(["--output-dir".as_ref(), dir.as_os_str()])
+            .args(["--network".as_ref(), network.as_os_str()]),
+    )?;
+
+    let helpers = (1..=3)
+        .zip(&sockets)
+        .map(|(id, socket)| Helper::spawn(id, dir, socket))
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(sockets);
+
+    let protocol = match security_model {
+        IpaSecurityModel::SemiHonest => "semi-honest-oprf-ipa",
+        IpaSecurityModel::Malicious => "malicious-oprf-ipa",
+    };
+    let start = Instant::now();
+    run_command(
+        Command::new(REPORT_COLLECTOR_BIN)
+            .arg("--quiet")
+            .args(["--network".as_ref(), network.as_os_str()])
+            .args(["--output-file".as_ref(), output.as_os_str()])
+            .args(["--wait", "10"])
+            .arg(protocol)
+            .args(["--enc-input-file1".into(), dir.join("helper1.enc")])
+            .args(["--enc-input-file2".into(), dir.join("helper2.enc")])
+            .args(["--enc-input-file3".into(), dir.join("helper3.enc")])
+            .args(query_args(config)),
+    )?;
+    let wall_time = start.elapsed().as_secs_f64();
+
+    let result = serde_json::from_slice::<ResultExport>(&fs::read(&output)?)?;
+    thread::sleep(METRICS_DELAY);
+    let stats = helpers.iter().map(Helper::stats).collect::<Vec<_>>();
+
+    Ok((
+        result.latency.as_secs_f64(),
+        wall_time,
+        stats.try_into().map_err(|_| "expected three helpers")?,
+    ))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    if args.ipa_query_config.dry_run {
+        return Err("dry runs do not run queries, so they cannot be load tested".into());
+    }
+    let tmp_dir;
+    let work_dir = if let Some(dir) = &args.work_dir {
+        dir.as_path()
+    } else {
+        tmp_dir = tempfile::tempdir()?;
+        tmp_dir.path()
+    };
+
+    let mut report = Report {
+        version: env!("CARGO_PKG_VERSION"),
+        config: args.ipa_query_config,
+        runs: Vec::new(),
+    };
+    for &security_model in &args.security_models {
+        for query_size in args.sizes.iter().map(|size| size.get()) {
+            for repetition in 0..args.repetitions.get() {
+                let seed = args.seed.unwrap_or_else(random);
+                let dir = work_dir.join(format!(
+                    "{model}-{query_size}-{repetition}",
+                    model = match security_model {
+                        IpaSecurityModel::SemiHonest => "semi-honest",
+                        IpaSecurityModel::Malicious => "malicious",
+                    }
+                ));
+                eprintln!("running {security_model:?} IPA with {query_size} reports in {dir:?}");
+                let (latency, wall_time, helpers) =
+                    run(&dir, security_model, query_size, &report.config, seed)?;
+                eprintln!("completed in {latency:.2}s");
+
+                report.runs.push(Run {
+                    security_model,
+                    query_size,
+                    repetition,
+                    seed,
+                    latency,
+                    wall_time,
+                    helpers,
+                });
+                // keep the results of the completed runs if a later one fails
+                fs::write(&args.output_file, serde_json::to_vec_pretty(&report)?)?;
+            }
+        }
+    }
+
+    Ok(())
+}
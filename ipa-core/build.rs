@@ -22,6 +22,7 @@ track_steps!(
             shuffle::step,
             aggregation::step,
             oprf_padding::step,
+            reach_frequency::step,
             step,
         },
         dp::step,
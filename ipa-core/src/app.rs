@@ -1,6 +1,7 @@
 use std::sync::Weak;
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     helpers::{
@@ -11,7 +12,8 @@ use crate::{
     },
     hpke::{KeyRegistry, PrivateKeyOnly},
     protocol::QueryId,
-    query::{NewQueryError, QueryProcessor, QueryStatus},
+    query::{NewQueryError, QueryProcessor, QueryStatus, QuerySummary},
+    report::KeyIdentifier,
     sync::Arc,
     utils::NonZeroU32PowerOfTwo,
 };
@@ -36,6 +38,36 @@ impl AppConfig {
     }
 }
 
+/// Cargo features that change how a helper behaves, reported by the admin API.
+const FEATURES: &[(&str, bool)] = &[
+    ("web-app", cfg!(feature = "web-app")),
+    ("in-memory-infra", cfg!(feature = "in-memory-infra")),
+    ("real-world-infra", cfg!(feature = "real-world-infra")),
+    ("compact-gate", cfg!(feature = "compact-gate")),
+    ("stall-detection", cfg!(feature = "stall-detection")),
+    ("multi-threading", cfg!(feature = "multi-threading")),
+    ("dns-discovery", cfg!(feature = "dns-discovery")),
+    ("quic", cfg!(feature = "quic")),
+    ("ipa-prf", cfg!(feature = "ipa-prf")),
+    ("aggregate-circuit", cfg!(feature = "aggregate-circuit")),
+    ("reveal-aggregation", cfg!(feature = "reveal-aggregation")),
+    ("relaxed-dp", cfg!(feature = "relaxed-dp")),
+    ("weak-field", cfg!(feature = "weak-field")),
+    ("test-fixture", cfg!(feature = "test-fixture")),
+    ("disable-metrics", cfg!(feature = "disable-metrics")),
+];
+
+/// State of a helper, as reported by the admin API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HelperState {
+    pub version: String,
+    /// Cargo features the helper was built with.
+    pub features: Vec<String>,
+    /// Identifiers of the HPKE keys the helper can decrypt reports with.
+    pub hpke_key_ids: Vec<KeyIdentifier>,
+    pub queries: Vec<QuerySummary>,
+}
+
 pub struct Setup {
     query_processor: QueryProcessor,
     handler: HandlerRef,
@@ -83,6 +115,18 @@ impl Setup {
         mpc_transport: MpcTransportImpl,
         shard_transport: ShardTransportImpl,
     ) -> HelperApp {
+        // Client updates are held back while a query is running, and applied once its state is
+        // gone, however it ended.
+        #[cfg(feature = "real-world-infra")]
+        {
+            let transport = Arc::downgrade(&mpc_transport);
+            self.query_processor.on_query_removed(move |_| {
+                if let Some(transport) = transport.upgrade() {
+                    transport.query_finished();
+                }
+            });
+        }
+
         let app = Arc::new(Inner {
             query_processor: self.query_processor,
             mpc_transport,
@@ -137,6 +181,12 @@ impl HelperApp {
         Ok(self.inner.query_processor.query_status(query_id)?)
     }
 
+    /// Returns the state of this helper: build information, keys and the queries it knows about.
+    #[must_use]
+    pub fn state(&self) -> HelperState {
+        self.inner.state()
+    }
+
     /// Waits for a query to complete and returns the result.
     ///
     /// ## Errors
@@ -151,6 +201,21 @@ impl HelperApp {
     }
 }
 
+impl Inner {
+    fn state(&self) -> HelperState {
+        HelperState {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            features: FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| (*name).to_string())
+                .collect(),
+            hpke_key_ids: self.query_processor.key_ids(),
+            queries: self.query_processor.queries(),
+        }
+    }
+}
+
 #[async_trait]
 impl RequestHandler for Inner {
     type Identity = HelperIdentity;
@@ -208,6 +273,7 @@ impl RequestHandler for Inner {
                 let query_id = ext_query_id(&req)?;
                 HelperResponse::from(qp.kill(query_id)?)
             }
+            RouteId::HelperState => HelperResponse::from(self.state()),
         })
     }
 }
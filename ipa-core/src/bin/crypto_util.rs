@@ -2,7 +2,7 @@ use std::fmt::Debug;
 
 use clap::{Parser, Subcommand};
 use ipa_core::{
-    cli::crypto::{DecryptArgs, EncryptArgs},
+    cli::crypto::{DecryptArgs, EncryptArgs, TestVectorArgs},
     error::BoxError,
 };
 
@@ -18,6 +18,7 @@ struct Args {
 enum CryptoUtilCommand {
     Encrypt(EncryptArgs),
     Decrypt(DecryptArgs),
+    TestVectors(TestVectorArgs),
 }
 
 #[tokio::main]
@@ -26,6 +27,7 @@ async fn main() -> Result<(), BoxError> {
     match args.action {
         CryptoUtilCommand::Encrypt(encrypt_args) => encrypt_args.encrypt()?,
         CryptoUtilCommand::Decrypt(decrypt_args) => decrypt_args.decrypt_and_reconstruct().await?,
+        CryptoUtilCommand::TestVectors(test_vector_args) => test_vector_args.run()?,
     }
     Ok(())
 }
@@ -1,22 +1,25 @@
 use std::{
-    fs,
-    io::BufReader,
+    collections::BTreeMap,
+    fs, io,
     net::TcpListener,
     os::fd::{FromRawFd, RawFd},
     path::{Path, PathBuf},
     process,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use clap::{self, Parser, Subcommand};
 use hyper::http::uri::Scheme;
 use ipa_core::{
     cli::{
-        client_config_setup, keygen, test_setup, ConfGenArgs, KeygenArgs, TestSetupArgs, Verbosity,
+        admin, client_config_setup, keygen, test_setup, AdminArgs, ConfGenArgs, KeygenArgs,
+        LoggingHandle, TestSetupArgs, Verbosity,
     },
     config::{hpke_registry, HpkeServerConfig, NetworkConfig, ServerConfig, TlsConfig},
     error::BoxError,
     helpers::HelperIdentity,
-    net::{ClientIdentity, HttpShardTransport, HttpTransport, MpcHelperClient},
+    net::{ClientIdentity, HttpShardTransport, HttpTransport, MpcHelperClient, MpcHelperServer},
     AppConfig, AppSetup, NonZeroU32PowerOfTwo,
 };
 use tracing::{error, info};
@@ -65,6 +68,13 @@ struct ServerArgs {
     #[arg(short = 'k', long)]
     disable_https: bool,
 
+    /// Also accept step streams from other helpers over QUIC
+    ///
+    /// Listens on the UDP port with the same number as `--port`. Requires HTTPS.
+    #[cfg(feature = "quic")]
+    #[arg(long, conflicts_with = "disable_https")]
+    enable_quic: bool,
+
     /// File containing helper network configuration
     #[arg(long, required = true)]
     network: Option<PathBuf>,
@@ -93,32 +103,47 @@ struct ServerArgs {
     /// Override the amount of active work processed in parallel
     #[arg(long)]
     active_work: Option<NonZeroU32PowerOfTwo>,
+
+    /// How often to check the network configuration file for changes, in seconds
+    ///
+    /// When the file changes, or peer discovery finds different peers, the new configuration is
+    /// validated and replaces the current one without restarting the helper. Set to 0 to disable.
+    #[arg(long, default_value = "30")]
+    network_reload_interval: u64,
+
+    /// Write the totals of the metrics this helper collects to this file as JSON, every second
+    ///
+    /// This is intended for load tests. Metrics are not collected with `--quiet`.
+    #[arg(hide = true, long)]
+    metrics_file: Option<PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
 enum HelperCommand {
+    Admin(AdminArgs),
     Confgen(ConfGenArgs),
     Keygen(KeygenArgs),
     TestSetup(TestSetupArgs),
 }
 
-fn read_file(path: &Path) -> Result<BufReader<fs::File>, BoxError> {
-    Ok(fs::OpenOptions::new()
-        .read(true)
-        .open(path)
-        .map(BufReader::new)
-        .map_err(|e| format!("failed to open file {}: {e:?}", path.display()))?)
-}
-
-async fn server(args: ServerArgs) -> Result<(), BoxError> {
+async fn server(args: ServerArgs, logging: &LoggingHandle) -> Result<(), BoxError> {
+    if args.metrics_file.is_some() && logging.metrics().is_none() {
+        return Err("--metrics-file requires metrics, which are not collected with --quiet".into());
+    }
     let my_identity = HelperIdentity::try_from(args.identity.expect("enforced by clap")).unwrap();
 
     let (identity, server_tls) = match (args.tls_cert, args.tls_key) {
         (Some(cert_file), Some(key_file)) => {
-            let mut key = read_file(&key_file)?;
-            let mut certs = read_file(&cert_file)?;
+            // The certificate is reloaded by both the server and the clients when the files change.
+            let identity = ClientIdentity::from_files(&cert_file, &key_file).map_err(|e| {
+                format!(
+                    "failed to load TLS certificate {} and key {}: {e:?}",
+                    cert_file.display(),
+                    key_file.display()
+                )
+            })?;
             (
-                ClientIdentity::from_pkcs8(&mut certs, &mut key)?,
+                identity,
                 Some(TlsConfig::File {
                     certificate_file: cert_file,
                     private_key_file: key_file,
@@ -143,6 +168,10 @@ async fn server(args: ServerArgs) -> Result<(), BoxError> {
         disable_https: args.disable_https,
         tls: server_tls,
         hpke_config: mk_encryption,
+        #[cfg(feature = "quic")]
+        enable_quic: args.enable_quic,
+        #[cfg(not(feature = "quic"))]
+        enable_quic: false,
     };
 
     let scheme = if args.disable_https {
@@ -150,10 +179,10 @@ async fn server(args: ServerArgs) -> Result<(), BoxError> {
     } else {
         Scheme::HTTPS
     };
-    let network_config_path = args.network.as_deref().unwrap();
-    let network_config = NetworkConfig::from_toml_str(&fs::read_to_string(network_config_path)?)?
-        .override_scheme(&scheme);
+    let network_config_path = args.network.clone().unwrap();
+    let network_config = read_network_config(&network_config_path, &scheme)?;
     let clients = MpcHelperClient::from_conf(&network_config, &identity);
+    let network_config_snapshot = network_config.clone();
 
     let (transport, server) = HttpTransport::new(
         my_identity,
@@ -164,6 +193,7 @@ async fn server(args: ServerArgs) -> Result<(), BoxError> {
     );
 
     let _app = setup.connect(transport.clone(), HttpShardTransport);
+    let server = Arc::new(server);
 
     let listener = args.server_socket_fd
         .map(|fd| {
@@ -189,18 +219,154 @@ async fn server(args: ServerArgs) -> Result<(), BoxError> {
         )
         .await;
 
-    server_handle.await?;
+    if args.network_reload_interval > 0 {
+        tokio::spawn(watch_network_config(
+            network_config_path,
+            scheme,
+            identity,
+            Arc::clone(&server),
+            network_config_snapshot,
+            Duration::from_secs(args.network_reload_interval),
+        ));
+    }
+
+    if let Some(path) = args.metrics_file {
+        tokio::select! {
+            res = server_handle => res?,
+            () = write_metrics(&path, logging) => {}
+        }
+    } else {
+        server_handle.await?;
+    }
 
     Ok(())
 }
 
+/// Writes the totals of the metrics collected by this helper to `path` every second. The file is
+/// replaced atomically, so readers never see a partial write.
+async fn write_metrics(path: &Path, logging: &LoggingHandle) {
+    const INTERVAL: Duration = Duration::from_secs(1);
+    let tmp_path = path.with_extension("tmp");
+    loop {
+        tokio::time::sleep(INTERVAL).await;
+        let Some(metrics) = logging.metrics() else {
+            continue;
+        };
+        let totals = metrics
+            .counters
+            .iter()
+            .map(|(name, details)| (name.as_str(), details.total_value))
+            .collect::<BTreeMap<_, _>>();
+        let result = serde_json::to_vec_pretty(&totals)
+            .map_err(io::Error::from)
+            .and_then(|json| {
+                fs::write(&tmp_path, json)?;
+                fs::rename(&tmp_path, path)
+            });
+        if let Err(e) = result {
+            error!("failed to write metrics to {}: {e}", path.display());
+        }
+    }
+}
+
+fn read_network_config(path: &Path, scheme: &Scheme) -> Result<NetworkConfig, BoxError> {
+    let network = NetworkConfig::from_toml_str(&fs::read_to_string(path)?)?.override_scheme(scheme);
+    network.validate()?;
+    Ok(network)
+}
+
+/// Polls the network configuration file, and applies it to the running server when it changes.
+/// If the configuration uses dynamic peer discovery, discovery is also repeated on every poll,
+/// and the result is applied if the peers changed.
+///
+/// Invalid configurations are logged and ignored, so a bad edit does not take the helper down.
+async fn watch_network_config(
+    path: PathBuf,
+    scheme: Scheme,
+    identity: ClientIdentity,
+    server: Arc<MpcHelperServer>,
+    mut current: NetworkConfig,
+    interval: Duration,
+) {
+    async fn modified(path: &Path) -> io::Result<SystemTime> {
+        tokio::fs::metadata(path).await?.modified()
+    }
+
+    let mut last_modified = modified(&path).await.ok();
+    loop {
+        tokio::time::sleep(interval).await;
+        let file_changed = match modified(&path).await {
+            Ok(m) if last_modified == Some(m) => false,
+            Ok(m) => {
+                last_modified = Some(m);
+                true
+            }
+            Err(e) => {
+                error!(
+                    "failed to check network configuration {}: {e}",
+                    path.display()
+                );
+                continue;
+            }
+        };
+
+        // Where the update came from, for the logs.
+        let (source, update) = if file_changed {
+            // Parsing runs discovery, if it is configured, and blocks until it completes.
+            let (file, scheme) = (path.clone(), scheme.clone());
+            let update = tokio::task::spawn_blocking(move || read_network_config(&file, &scheme))
+                .await
+                .map_err(BoxError::from)
+                .and_then(|network| network.map(Some));
+            let source = match &update {
+                Ok(Some(NetworkConfig {
+                    discovery: Some(discovery),
+                    ..
+                })) => format!("{} with peers from {discovery}", path.display()),
+                _ => path.display().to_string(),
+            };
+            (source, update)
+        } else {
+            let Some(discovery) = current.discovery.clone() else {
+                continue;
+            };
+            let update = current
+                .rediscover()
+                .await
+                .map(|network| {
+                    network
+                        .map(|network| network.override_scheme(&scheme))
+                        .filter(|network| network.peers != current.peers)
+                })
+                .map_err(BoxError::from);
+            (discovery.to_string(), update)
+        };
+
+        let result = update.and_then(|network| {
+            let Some(network) = network else {
+                return Ok(());
+            };
+            server.update_network_config(network.clone(), &identity)?;
+            info!("applied network configuration from {source}");
+            current = network;
+            Ok(())
+        });
+        if let Err(e) = result {
+            error!(
+                "failed to apply network configuration from {source}, keeping the current one: {e}"
+            );
+        }
+    }
+}
+
 #[tokio::main]
 pub async fn main() {
     let args = Args::parse();
-    let _handle = args.logging.setup_logging();
+    let handle = args.logging.setup_logging();
 
     let res = match args.command {
-        None => server(args.server).await,
+        None => server(args.server, &handle).await,
+        Some(HelperCommand::Admin(args)) => admin(args).await,
         Some(HelperCommand::Keygen(args)) => keygen(&args),
         Some(HelperCommand::TestSetup(args)) => test_setup(args),
         Some(HelperCommand::Confgen(args)) => client_config_setup(args),
@@ -1,10 +1,26 @@
-use std::{error::Error, fs::File, io::Write, num::NonZeroU32, path::PathBuf};
+//! Computes the expected result of a query without any helpers involved, for every query type
+//! that report collectors can run. The test query types (addition, multiplication and shuffle)
+//! are not covered here, as `report_collector test-mpc` already validates them in the clear.
 
-use clap::Parser;
+use std::{error::Error, fs::File, io::Write, path::PathBuf};
+
+use clap::{Parser, Subcommand};
 use ipa_core::{
     cli::{playbook::InputSource, Verbosity},
-    test_fixture::hybrid::{hybrid_in_the_clear, TestHybridRecord},
+    ff::boolean_array::BA32,
+    helpers::query::{DpMechanism, HybridQueryParams, IpaQueryConfig, ReachFrequencyQueryConfig},
+    protocol::dp::dp_for_histogram_in_the_clear,
+    test_fixture::{
+        hybrid::{hybrid_in_the_clear, TestHybridRecord},
+        ipa::{ipa_in_the_clear, ipa_value_buckets_in_the_clear, CappingOrder, TestRawDataRecord},
+        reach_frequency::reach_frequency_in_the_clear,
+    },
 };
+use rand::rngs::StdRng;
+use rand_core::SeedableRng;
+
+/// Number of bins in the histograms that the helpers add noise to.
+const HISTOGRAM_BINS: usize = 256;
 
 #[derive(Debug, Parser)]
 pub struct CommandInput {
@@ -39,8 +55,52 @@ struct Args {
     #[arg(long, value_name = "OUTPUT_FILE")]
     output_file: PathBuf,
 
-    #[arg(long, default_value = "20")]
-    max_breakdown_key: NonZeroU32,
+    /// Seed for the DP noise added to the result. Only used by queries that run with DP.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    #[command(subcommand)]
+    action: QueryType,
+}
+
+#[derive(Debug, Subcommand)]
+enum QueryType {
+    /// Computes the result of an OPRF IPA query, semi-honest or malicious.
+    OprfIpa {
+        #[clap(flatten)]
+        config: IpaQueryConfig,
+
+        /// Order in which the conversions of a user are capped.
+        #[arg(long, value_enum, default_value = "cap-most-recent-first")]
+        capping_order: CappingOrder,
+    },
+    /// Computes the result of a hybrid query.
+    Hybrid(HybridQueryParams),
+    /// Computes the result of a reach and frequency query.
+    ReachFrequency(ReachFrequencyQueryConfig),
+}
+
+/// Adds the noise that the helpers add to the result of a query with these DP parameters.
+fn add_dp_noise(
+    histogram: &mut [u32],
+    with_dp: u32,
+    epsilon: f64,
+    sensitivity: u32,
+    seed: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    let dp_params = match with_dp {
+        0 => return Ok(()),
+        _ => DpMechanism::DiscreteLaplace { epsilon },
+    };
+    let mut rng = seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+    dp_for_histogram_in_the_clear::<HISTOGRAM_BINS, BA32, _>(
+        histogram,
+        dp_params,
+        sensitivity,
+        &mut rng,
+    )?;
+
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -49,11 +109,78 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let input = InputSource::from(&args.input);
 
-    let input_rows = input.iter::<TestHybridRecord>().collect::<Vec<_>>();
-    let expected = hybrid_in_the_clear(
-        &input_rows,
-        usize::try_from(args.max_breakdown_key.get()).unwrap(),
-    );
+    let expected = match args.action {
+        QueryType::OprfIpa {
+            config,
+            capping_order,
+        } => {
+            let input_rows = input.iter::<TestRawDataRecord>().collect::<Vec<_>>();
+            let mut expected = if let Some(buckets) = config.value_buckets {
+                ipa_value_buckets_in_the_clear(
+                    &input_rows,
+                    config.per_user_credit_cap,
+                    config.attribution_window_seconds,
+                    config.attribution_weights,
+                    config.max_breakdown_key,
+                    &buckets,
+                    capping_order,
+                )
+            } else {
+                ipa_in_the_clear(
+                    &input_rows,
+                    config.per_user_credit_cap,
+                    config.attribution_window_seconds,
+                    config.attribution_weights,
+                    config.max_breakdown_key,
+                    capping_order,
+                )
+            };
+            // A user adds at most `per_user_credit_cap` to the sums, and counts at most that many
+            // conversions into value buckets.
+            add_dp_noise(
+                &mut expected,
+                config.with_dp,
+                config.epsilon,
+                config.per_user_credit_cap,
+                args.seed,
+            )?;
+            expected
+        }
+        QueryType::Hybrid(config) => {
+            let input_rows = input.iter::<TestHybridRecord>().collect::<Vec<_>>();
+            let mut expected = hybrid_in_the_clear(
+                &input_rows,
+                usize::try_from(config.max_breakdown_key).unwrap(),
+                config.per_user_credit_cap,
+            );
+            add_dp_noise(
+                &mut expected,
+                config.with_dp,
+                config.epsilon,
+                config.per_user_credit_cap,
+                args.seed,
+            )?;
+            expected
+        }
+        QueryType::ReachFrequency(config) => {
+            let input_rows = input.iter::<TestRawDataRecord>().collect::<Vec<_>>();
+            let mut expected = reach_frequency_in_the_clear(
+                &input_rows,
+                config.max_breakdown_key,
+                config.max_frequency,
+                config.max_breakdowns_per_user,
+            );
+            // A user changes at most `max_breakdowns_per_user` reach bins and one frequency bin.
+            add_dp_noise(
+                &mut expected,
+                config.with_dp,
+                config.epsilon,
+                config.max_breakdowns_per_user + 1,
+                args.seed,
+            )?;
+            expected
+        }
+    };
 
     let mut file = File::options()
         .write(true)
@@ -3,29 +3,37 @@ use std::{
     error::Error,
     fmt::Debug,
     fs::{File, OpenOptions},
-    io,
-    io::{stdout, Write},
-    ops::Deref,
+    io::{self, stdout, BufRead, BufReader, Write},
+    num::NonZeroU64,
     path::{Path, PathBuf},
 };
 
 use clap::{Parser, Subcommand};
 use hyper::http::uri::Scheme;
+#[cfg(feature = "parquet-io")]
+use ipa_core::cli::columnar;
 use ipa_core::{
     cli::{
+        batch::{run_batch, BatchArgs},
         playbook::{
             make_clients, playbook_oprf_ipa, run_query_and_validate, validate, validate_dp,
-            InputSource,
+            BreakdownKey, InputSource, Timestamp, TriggerValue,
         },
-        CsvSerializer, IpaQueryResult, Verbosity,
+        BreakdownLabels, CsvSerializer, IpaQueryResult, ResultExport, Verbosity,
     },
     config::{KeyRegistries, NetworkConfig},
     ff::{boolean_array::BA32, FieldType},
     helpers::query::{DpMechanism, IpaQueryConfig, QueryConfig, QuerySize, QueryType},
-    net::MpcHelperClient,
-    report::{EncryptedOprfReportStreams, DEFAULT_KEY_ID},
+    report::{
+        validation::{InputValidation, Rejection},
+        EncryptedOprfReportStreams, DEFAULT_KEY_ID,
+    },
+    report_collector::ReportCollector,
     test_fixture::{
-        ipa::{ipa_in_the_clear, CappingOrder, IpaSecurityModel, TestRawDataRecord},
+        ipa::{
+            ipa_in_the_clear, ipa_value_buckets_in_the_clear, CappingOrder, IpaSecurityModel,
+            TestRawDataRecord,
+        },
         EventGenerator, EventGeneratorConfig, HybridEventGenerator, HybridGeneratorConfig,
     },
 };
@@ -54,10 +62,15 @@ struct Args {
     #[clap(flatten)]
     input: CommandInput,
 
-    /// The destination file for output.
+    /// The destination file for output. Query results are written as CSV to files with the .csv
+    /// extension, as Parquet to files with the .parquet extension and as JSON otherwise.
     #[arg(long, value_name = "OUTPUT_FILE")]
     output_file: Option<PathBuf>,
 
+    /// JSON file with the names and metadata of breakdown keys, to label query results with
+    #[arg(long, value_name = "LABELS_FILE")]
+    breakdown_labels: Option<PathBuf>,
+
     #[command(subcommand)]
     action: ReportCollectorCommand,
 }
@@ -66,7 +79,8 @@ struct Args {
 pub struct CommandInput {
     #[arg(
         long,
-        help = "Read the input from the provided file, instead of standard input"
+        help = "Read the input from the provided file, instead of standard input. Files with the \
+                .parquet extension are read as Parquet"
     )]
     input_file: Option<PathBuf>,
 }
@@ -86,8 +100,12 @@ enum ReportCollectorCommand {
     /// Generate inputs for IPA
     GenIpaInputs {
         /// Number of records to generate
-        #[clap(long, short = 'n')]
-        count: u32,
+        #[clap(long, short = 'n', required_unless_present = "target_size")]
+        count: Option<u32>,
+
+        /// Approximate size of the generated CSV file in bytes, instead of a number of records
+        #[clap(long, conflicts_with = "count")]
+        target_size: Option<NonZeroU64>,
 
         /// The seed for random generator.
         #[clap(long, short = 's')]
@@ -98,8 +116,12 @@ enum ReportCollectorCommand {
     },
     GenHybridInputs {
         /// Number of records to generate
-        #[clap(long, short = 'n')]
-        count: u32,
+        #[clap(long, short = 'n', required_unless_present = "target_size")]
+        count: Option<u32>,
+
+        /// Approximate size of the generated CSV file in bytes, instead of a number of records
+        #[clap(long, conflicts_with = "count")]
+        target_size: Option<NonZeroU64>,
 
         /// The seed for random generator.
         #[clap(long, short = 's')]
@@ -132,6 +154,20 @@ enum ReportCollectorCommand {
         #[clap(flatten)]
         ipa_query_config: IpaQueryConfig,
     },
+    /// Check encrypted inputs for IPA without submitting a query: the helper inputs must have the
+    /// same number of reports, and every report must be well-formed, use a key id of the
+    /// helper it is for and agree with the other helpers on the fields in the clear.
+    Validate {
+        #[clap(flatten)]
+        encrypted_inputs: EncryptedInputs,
+
+        /// The reports are in the legacy format, without a version byte
+        #[arg(long)]
+        legacy_reports: bool,
+    },
+    /// Execute the OPRF IPA queries listed in a manifest, several at a time. Writes a summary of
+    /// the queries that succeeded and failed to the output file.
+    Batch(BatchArgs),
 }
 
 #[derive(Debug, clap::Args)]
@@ -143,19 +179,112 @@ struct GenInputArgs {
     breakdowns: u32,
 }
 
+/// The encrypted reports for each helper, or why a report could not be read.
+type ValidatedShares = [Vec<Result<Vec<u8>, Rejection>>; 3];
+
 #[derive(Debug, Parser)]
 struct EncryptedInputs {
     /// The encrypted input for H1
-    #[arg(long, value_name = "H1_ENCRYPTED_INPUT_FILE")]
-    enc_input_file1: PathBuf,
+    #[arg(
+        long,
+        value_name = "H1_ENCRYPTED_INPUT_FILE",
+        required_unless_present = "enc_input_parquet"
+    )]
+    enc_input_file1: Option<PathBuf>,
 
     /// The encrypted input for H2
-    #[arg(long, value_name = "H2_ENCRYPTED_INPUT_FILE")]
-    enc_input_file2: PathBuf,
+    #[arg(
+        long,
+        value_name = "H2_ENCRYPTED_INPUT_FILE",
+        required_unless_present = "enc_input_parquet"
+    )]
+    enc_input_file2: Option<PathBuf>,
 
     /// The encrypted input for H3
-    #[arg(long, value_name = "H3_ENCRYPTED_INPUT_FILE")]
-    enc_input_file3: PathBuf,
+    #[arg(
+        long,
+        value_name = "H3_ENCRYPTED_INPUT_FILE",
+        required_unless_present = "enc_input_parquet"
+    )]
+    enc_input_file3: Option<PathBuf>,
+
+    /// Parquet file with the encrypted inputs for all helpers, in the binary columns `helper_1`,
+    /// `helper_2` and `helper_3`. Replaces the three newline-delimited hex files.
+    #[arg(
+        long,
+        value_name = "ENCRYPTED_INPUT_PARQUET_FILE",
+        conflicts_with_all = ["enc_input_file1", "enc_input_file2", "enc_input_file3"]
+    )]
+    enc_input_parquet: Option<PathBuf>,
+}
+
+impl EncryptedInputs {
+    fn streams(&self) -> Result<EncryptedOprfReportStreams, Box<dyn Error>> {
+        if let Some(path) = &self.enc_input_parquet {
+            #[cfg(feature = "parquet-io")]
+            return Ok(columnar::read_encrypted_reports(
+                path,
+                columnar::ENCRYPTED_REPORT_COLUMNS,
+            )?);
+            #[cfg(not(feature = "parquet-io"))]
+            return Err(parquet_disabled(path));
+        }
+
+        // clap makes sure that all the files are present if the Parquet file is not.
+        let files = [
+            &self.enc_input_file1,
+            &self.enc_input_file2,
+            &self.enc_input_file3,
+        ]
+        .map(|file| file.as_ref().unwrap());
+        Ok(EncryptedOprfReportStreams::from(files))
+    }
+
+    /// Reads the reports for each helper, without length-delimiting them like [`Self::streams`].
+    /// Lines of the hex files that cannot be decoded are kept as [`Rejection::NotHex`].
+    fn reports(&self) -> Result<ValidatedShares, Box<dyn Error>> {
+        if let Some(path) = &self.enc_input_parquet {
+            #[cfg(feature = "parquet-io")]
+            return Ok(columnar::read_encrypted_report_columns(
+                path,
+                columnar::ENCRYPTED_REPORT_COLUMNS,
+            )?
+            .map(|reports| reports.into_iter().map(Ok).collect()));
+            #[cfg(not(feature = "parquet-io"))]
+            return Err(parquet_disabled(path));
+        }
+
+        let mut reports = [Vec::new(), Vec::new(), Vec::new()];
+        for (helper_reports, file) in reports.iter_mut().zip([
+            &self.enc_input_file1,
+            &self.enc_input_file2,
+            &self.enc_input_file3,
+        ]) {
+            let path = file.as_ref().unwrap();
+            *helper_reports = BufReader::new(File::open(path)?)
+                .lines()
+                .map(|line| {
+                    line.map(|line| hex::decode(line.trim()).map_err(|_| Rejection::NotHex))
+                })
+                .collect::<Result<_, io::Error>>()
+                .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        }
+
+        Ok(reports)
+    }
+}
+
+fn is_parquet(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "parquet")
+}
+
+#[cfg(not(feature = "parquet-io"))]
+fn parquet_disabled(path: &Path) -> Box<dyn Error> {
+    format!(
+        "{} is a Parquet file, but this binary was built without the parquet-io feature",
+        path.display()
+    )
+    .into()
 }
 
 #[tokio::main]
@@ -170,24 +299,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
     };
 
     let (clients, network) = make_clients(args.network.as_deref(), scheme, args.wait).await;
+    let collector = ReportCollector::new(clients);
     match args.action {
         ReportCollectorCommand::GenIpaInputs {
             count,
+            target_size,
             seed,
             gen_args,
-        } => gen_inputs(count, seed, args.output_file, gen_args)?,
+        } => gen_inputs(count, target_size, seed, args.output_file, gen_args)?,
         ReportCollectorCommand::GenHybridInputs {
             count,
+            target_size,
             seed,
             gen_args,
-        } => gen_hybrid_inputs(count, seed, args.output_file, gen_args)?,
+        } => gen_hybrid_inputs(count, target_size, seed, args.output_file, gen_args)?,
         ReportCollectorCommand::SemiHonestOprfIpaTest(config) => {
             ipa_test(
                 &args,
                 &network,
                 IpaSecurityModel::SemiHonest,
                 config,
-                &clients,
+                &collector,
             )
             .await?
         }
@@ -197,7 +329,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 &network,
                 IpaSecurityModel::Malicious,
                 config,
-                &clients,
+                &collector,
             )
             .await?
         }
@@ -209,7 +341,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 &args,
                 IpaSecurityModel::Malicious,
                 ipa_query_config,
-                &clients,
+                &collector,
                 encrypted_inputs,
             )
             .await?
@@ -222,62 +354,170 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 &args,
                 IpaSecurityModel::SemiHonest,
                 ipa_query_config,
-                &clients,
+                &collector,
                 encrypted_inputs,
             )
             .await?
         }
+        ReportCollectorCommand::Validate {
+            ref encrypted_inputs,
+            legacy_reports,
+        } => validate_inputs(&args, &network, encrypted_inputs, legacy_reports)?,
+        ReportCollectorCommand::Batch(ref batch_args) => {
+            batch(&args, batch_args, &collector).await?
+        }
     };
 
     Ok(())
 }
 
+fn validate_inputs(
+    args: &Args,
+    network: &NetworkConfig,
+    encrypted_inputs: &EncryptedInputs,
+    legacy_reports: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut key_registries = KeyRegistries::default();
+    let key_registries = key_registries
+        .init_from(network)
+        .ok_or("the network configuration does not have the public keys of all helpers")?;
+
+    let validation = InputValidation::validate::<BreakdownKey, TriggerValue, Timestamp, _, _>(
+        encrypted_inputs.reports()?,
+        legacy_reports,
+        key_registries,
+    );
+    write_json(args.output_file.as_deref(), &validation)?;
+
+    if validation.is_valid() {
+        Ok(())
+    } else {
+        Err(format!(
+            "the inputs are not valid, record counts: {:?}",
+            validation.record_counts()
+        )
+        .into())
+    }
+}
+
+async fn batch(
+    args: &Args,
+    batch_args: &BatchArgs,
+    collector: &ReportCollector,
+) -> Result<(), Box<dyn Error>> {
+    let summary = run_batch(collector, batch_args).await?;
+    write_json(args.output_file.as_deref(), &summary)?;
+
+    if summary.is_success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} of {} queries failed, run the batch again with --resume to retry them",
+            summary.failed,
+            summary.queries.len()
+        )
+        .into())
+    }
+}
+
+fn write_json<T: serde::Serialize>(path: Option<&Path>, value: &T) -> Result<(), Box<dyn Error>> {
+    let json = serde_json::to_string_pretty(value)?;
+    if let Some(path) = path {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        writeln!(file, "{json}")?;
+    } else {
+        println!("{json}");
+    }
+
+    Ok(())
+}
+
 fn gen_hybrid_inputs(
-    count: u32,
+    count: Option<u32>,
+    target_size: Option<NonZeroU64>,
     seed: Option<u64>,
     output_file: Option<PathBuf>,
     args: HybridGeneratorConfig,
-) -> io::Result<()> {
+) -> Result<(), Box<dyn Error>> {
     let rng = seed
         .map(StdRng::seed_from_u64)
         .unwrap_or_else(StdRng::from_entropy);
-    let event_gen = HybridEventGenerator::with_config(rng, args).take(count as usize);
+    let event_gen =
+        HybridEventGenerator::with_config(rng, args).take(count.map_or(usize::MAX, |c| c as usize));
 
-    let mut writer: Box<dyn Write> = if let Some(path) = output_file {
-        Box::new(OpenOptions::new().write(true).create_new(true).open(path)?)
-    } else {
-        Box::new(stdout().lock())
-    };
-
-    for event in event_gen {
-        event.to_csv(&mut writer)?;
-        writer.write_all(b"\n")?;
+    if let Some(path) = output_file.as_deref().filter(|path| is_parquet(path)) {
+        if target_size.is_some() {
+            return Err(target_size_unsupported(path));
+        }
+        #[cfg(feature = "parquet-io")]
+        return Ok(columnar::write_records(path, event_gen)?);
+        #[cfg(not(feature = "parquet-io"))]
+        return Err(parquet_disabled(path));
     }
 
-    Ok(())
+    write_csv(event_gen, target_size, output_file)
 }
 
 fn gen_inputs(
-    count: u32,
+    count: Option<u32>,
+    target_size: Option<NonZeroU64>,
     seed: Option<u64>,
     output_file: Option<PathBuf>,
     args: EventGeneratorConfig,
-) -> io::Result<()> {
+) -> Result<(), Box<dyn Error>> {
     let rng = seed
         .map(StdRng::seed_from_u64)
         .unwrap_or_else(StdRng::from_entropy);
-    let event_gen = EventGenerator::with_config(rng, args)
-        .take(count as usize)
-        .collect::<Vec<_>>();
+    let event_gen =
+        EventGenerator::with_config(rng, args).take(count.map_or(usize::MAX, |c| c as usize));
+
+    if let Some(path) = output_file.as_deref().filter(|path| is_parquet(path)) {
+        if target_size.is_some() {
+            return Err(target_size_unsupported(path));
+        }
+        #[cfg(feature = "parquet-io")]
+        return Ok(columnar::write_records(path, event_gen)?);
+        #[cfg(not(feature = "parquet-io"))]
+        return Err(parquet_disabled(path));
+    }
+
+    write_csv(event_gen, target_size, output_file)
+}
+
+fn target_size_unsupported(path: &Path) -> Box<dyn Error> {
+    format!(
+        "{} is a Parquet file, but target size is only supported for CSV output",
+        path.display()
+    )
+    .into()
+}
+
+/// Writes generated events as CSV to `output_file`, or to the standard output. If `target_size`
+/// is set, stops after the first event that makes the output reach that many bytes.
+fn write_csv<T: CsvSerializer>(
+    events: impl Iterator<Item = T>,
+    target_size: Option<NonZeroU64>,
+    output_file: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
     let mut writer: Box<dyn Write> = if let Some(path) = output_file {
         Box::new(OpenOptions::new().write(true).create_new(true).open(path)?)
     } else {
         Box::new(stdout().lock())
     };
 
-    for event in event_gen {
-        event.to_csv(&mut writer)?;
-        writer.write_all(b"\n")?;
+    let target_size = target_size.map_or(u64::MAX, NonZeroU64::get);
+    let mut written = 0_u64;
+    let mut line = Vec::new();
+    for event in events {
+        line.clear();
+        event.to_csv(&mut line)?;
+        line.push(b'\n');
+        writer.write_all(&line)?;
+
+        written += u64::try_from(line.len()).unwrap();
+        if written >= target_size {
+            break;
+        }
     }
 
     Ok(())
@@ -290,9 +530,27 @@ fn get_query_type(security_model: IpaSecurityModel, ipa_query_config: IpaQueryCo
     }
 }
 
+/// Reads the breakdown labels of `args`, if any, and checks that they fit the query.
+fn breakdown_labels(
+    args: &Args,
+    ipa_query_config: &IpaQueryConfig,
+) -> Result<Option<BreakdownLabels>, Box<dyn Error>> {
+    let Some(path) = args.breakdown_labels.as_deref() else {
+        return Ok(None);
+    };
+    let labels = BreakdownLabels::from_file(path)?;
+    labels.check(ipa_query_config.max_breakdown_key)?;
+    if args.output_file.as_deref().is_some_and(is_parquet) {
+        return Err("breakdown labels are only supported for CSV and JSON output".into());
+    }
+
+    Ok(Some(labels))
+}
+
 fn write_ipa_output_file(
     path: &PathBuf,
     query_result: &IpaQueryResult,
+    labels: Option<&BreakdownLabels>,
 ) -> Result<(), Box<dyn Error>> {
     // it will be sad to lose the results if file already exists.
     let path = if Path::is_file(path) {
@@ -319,13 +577,16 @@ fn write_ipa_output_file(
     } else {
         Cow::Borrowed(path)
     };
-    let mut file = File::options()
-        .write(true)
-        .create_new(true)
-        .open(path.deref())
-        .map_err(|e| format!("Failed to create output file {}: {e}", path.display()))?;
+    if is_parquet(&path) {
+        #[cfg(feature = "parquet-io")]
+        return Ok(columnar::write_query_result(&path, query_result)?);
+        #[cfg(not(feature = "parquet-io"))]
+        return Err(parquet_disabled(&path));
+    }
 
-    write!(file, "{}", serde_json::to_string_pretty(query_result)?)?;
+    ResultExport::new(query_result, labels)?
+        .write_to(&path)
+        .map_err(|e| format!("Failed to write output file {}: {e}", path.display()))?;
     Ok(())
 }
 
@@ -333,18 +594,12 @@ async fn ipa(
     args: &Args,
     security_model: IpaSecurityModel,
     ipa_query_config: IpaQueryConfig,
-    helper_clients: &[MpcHelperClient; 3],
+    collector: &ReportCollector,
     encrypted_inputs: &EncryptedInputs,
 ) -> Result<(), Box<dyn Error>> {
     let query_type = get_query_type(security_model, ipa_query_config);
 
-    let files = [
-        &encrypted_inputs.enc_input_file1,
-        &encrypted_inputs.enc_input_file2,
-        &encrypted_inputs.enc_input_file3,
-    ];
-
-    let encrypted_oprf_report_streams = EncryptedOprfReportStreams::from(files);
+    let encrypted_oprf_report_streams = encrypted_inputs.streams()?;
 
     let query_config = QueryConfig {
         size: QuerySize::try_from(encrypted_oprf_report_streams.query_size).unwrap(),
@@ -352,11 +607,17 @@ async fn ipa(
         query_type,
     };
 
-    let query_id = helper_clients[0]
-        .create_query(query_config)
-        .await
-        .expect("Unable to create query!");
+    if ipa_query_config.dry_run {
+        tracing::info!("Starting dry run for OPRF");
+        let summaries = collector
+            .submit(query_config, encrypted_oprf_report_streams.streams)
+            .await?
+            .dry_run_results()
+            .await?;
+        return write_json(args.output_file.as_deref(), &summaries);
+    }
 
+    let labels = breakdown_labels(args, &ipa_query_config)?;
     tracing::info!("Starting query for OPRF");
     // the value for histogram values (BA32) must be kept in sync with the server-side
     // implementation, otherwise a runtime reconstruct error will be generated.
@@ -364,16 +625,17 @@ async fn ipa(
     let actual = run_query_and_validate::<BA32>(
         encrypted_oprf_report_streams.streams,
         encrypted_oprf_report_streams.query_size,
-        helper_clients,
-        query_id,
+        collector,
+        query_config,
         ipa_query_config,
     )
     .await;
 
     if let Some(ref path) = args.output_file {
-        write_ipa_output_file(path, &actual)?;
+        write_ipa_output_file(path, &actual, labels.as_ref())?;
     } else {
-        println!("{}", serde_json::to_string_pretty(&actual)?);
+        let export = ResultExport::new(&actual, labels.as_ref())?;
+        println!("{}", serde_json::to_string_pretty(&export)?);
     }
     Ok(())
 }
@@ -383,29 +645,51 @@ async fn ipa_test(
     network: &NetworkConfig,
     security_model: IpaSecurityModel,
     ipa_query_config: IpaQueryConfig,
-    helper_clients: &[MpcHelperClient; 3],
+    collector: &ReportCollector,
 ) -> Result<(), Box<dyn Error>> {
-    let input = InputSource::from(&args.input);
+    if ipa_query_config.dry_run {
+        return Err("dry runs are only supported for queries on encrypted inputs".into());
+    }
     let query_type = get_query_type(security_model, ipa_query_config);
-
-    let input_rows = input.iter::<TestRawDataRecord>().collect::<Vec<_>>();
+    let labels = breakdown_labels(args, &ipa_query_config)?;
+
+    let input_rows = match args
+        .input
+        .input_file
+        .as_deref()
+        .filter(|path| is_parquet(path))
+    {
+        #[cfg(feature = "parquet-io")]
+        Some(path) => columnar::read_records::<TestRawDataRecord>(path)?,
+        #[cfg(not(feature = "parquet-io"))]
+        Some(path) => return Err(parquet_disabled(path)),
+        None => InputSource::from(&args.input)
+            .iter::<TestRawDataRecord>()
+            .collect::<Vec<_>>(),
+    };
     let query_config = QueryConfig {
         size: QuerySize::try_from(input_rows.len()).unwrap(),
         field_type: FieldType::Fp32BitPrime,
         query_type,
     };
-    let query_id = helper_clients[0]
-        .create_query(query_config)
-        .await
-        .expect("Unable to create query!");
-
-    let expected = {
+    let expected = if let Some(buckets) = ipa_query_config.value_buckets {
+        ipa_value_buckets_in_the_clear(
+            &input_rows,
+            ipa_query_config.per_user_credit_cap,
+            ipa_query_config.attribution_window_seconds,
+            ipa_query_config.attribution_weights,
+            ipa_query_config.max_breakdown_key,
+            &buckets,
+            CappingOrder::CapMostRecentFirst,
+        )
+    } else {
         let mut r = ipa_in_the_clear(
             &input_rows,
             ipa_query_config.per_user_credit_cap,
             ipa_query_config.attribution_window_seconds,
+            ipa_query_config.attribution_weights,
             ipa_query_config.max_breakdown_key,
-            &CappingOrder::CapMostRecentFirst,
+            CappingOrder::CapMostRecentFirst,
         );
 
         // pad the output vector to the max breakdown key, to make sure it is aligned with the MPC results
@@ -426,15 +710,15 @@ async fn ipa_test(
     // see ipa-core/src/query/executor.rs
     let actual = playbook_oprf_ipa::<BA32, _>(
         input_rows,
-        helper_clients,
-        query_id,
+        collector,
+        query_config,
         ipa_query_config,
         Some((DEFAULT_KEY_ID, key_registries)),
     )
     .await;
 
     if let Some(ref path) = args.output_file {
-        write_ipa_output_file(path, &actual)?;
+        write_ipa_output_file(path, &actual, labels.as_ref())?;
     }
 
     tracing::info!("{m:?}", m = ipa_query_config);
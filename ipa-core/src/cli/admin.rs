@@ -0,0 +1,66 @@
+use std::{fs, path::PathBuf};
+
+use clap::Args;
+use hyper::http::uri::Scheme;
+
+use crate::{
+    config::NetworkConfig,
+    error::BoxError,
+    helpers::HelperIdentity,
+    net::{ClientIdentity, MpcHelperClient},
+};
+
+#[derive(Debug, Args)]
+#[clap(
+    name = "admin",
+    about = "Show the state of a running helper",
+    next_help_heading = "Admin Options"
+)]
+pub struct AdminArgs {
+    /// File containing helper network configuration
+    #[arg(long)]
+    network: PathBuf,
+
+    /// Helper to inspect (1, 2, or 3)
+    #[arg(long)]
+    helper: usize,
+
+    /// TLS certificate of one of the helpers, to authenticate to the admin API
+    #[arg(long, visible_alias("cert"), requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// TLS key matching `--tls-cert`
+    #[arg(long, visible_alias("key"), requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Use insecure HTTP. The request claims the identity of the inspected helper.
+    #[arg(short = 'k', long, conflicts_with = "tls_cert")]
+    disable_https: bool,
+}
+
+/// Prints the state of a helper, as reported by its admin API, as JSON.
+///
+/// # Errors
+/// If the network configuration or the certificate cannot be read, or the request fails.
+pub async fn admin(args: AdminArgs) -> Result<(), BoxError> {
+    let helper = HelperIdentity::try_from(args.helper)?;
+    let scheme = if args.disable_https {
+        Scheme::HTTP
+    } else {
+        Scheme::HTTPS
+    };
+    let network =
+        NetworkConfig::from_toml_str(&fs::read_to_string(&args.network)?)?.override_scheme(&scheme);
+    let identity = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => ClientIdentity::from_files(cert, key)?,
+        (None, None) if args.disable_https => ClientIdentity::Helper(helper),
+        (None, None) => return Err("the admin API requires --tls-cert and --tls-key".into()),
+        _ => unreachable!("rejected by clap"),
+    };
+
+    let client = MpcHelperClient::from_conf(&network, &identity)[helper].clone();
+    let state = client.helper_state().await?;
+    println!("{}", serde_json::to_string_pretty(&state)?);
+
+    Ok(())
+}
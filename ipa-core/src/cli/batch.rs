@@ -0,0 +1,777 @@
+//! Runs many IPA queries on encrypted inputs, e.g. the daily reports of all campaigns of an
+//! advertiser, from a manifest.
+//!
+//! The manifest is a TOML or JSON file that lists the queries, each with a unique name, its
+//! inputs, the file to write its results to and its [`IpaQueryConfig`]:
+//!
+//! ```toml
+//! [[queries]]
+//! name = "campaign-1"
+//! security_model = "malicious"  # defaults to "semi-honest"
+//! inputs.files = ["campaign-1/h1.txt", "campaign-1/h2.txt", "campaign-1/h3.txt"]
+//! output_file = "results/campaign-1.csv"  # defaults to "<name>.json"
+//! breakdown_labels = "campaign-1/labels.json"  # optional
+//! config = { per_user_credit_cap = 8, max_breakdown_key = 20, with_dp = 1, epsilon = 3.0 }
+//!
+//! [[queries]]
+//! name = "campaign-2"
+//! inputs.parquet = "campaign-2.parquet"
+//! config = { per_user_credit_cap = 8, max_breakdown_key = 20, with_dp = 1, epsilon = 3.0 }
+//! ```
+//!
+//! Results are written in the formats of [`ResultExport`], as CSV to `.csv` files and as JSON to
+//! other files, or as Parquet to `.parquet` files. Relative paths are relative to the directory
+//! of the manifest. Queries run one after another, or up to [`BatchArgs::concurrency`] at a
+//! time if the helpers accept that many, and failed queries are retried. Every query that
+//! completes is recorded in a checkpoint file, so that a batch that did not complete can be
+//! rerun with [`BatchArgs::resume`] and only run the queries that have not completed yet.
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "parquet-io")]
+use crate::cli::columnar;
+use crate::{
+    cli::{
+        ipa_output::LabelError, playbook::query_result, BreakdownLabels, IpaQueryResult,
+        ResultExport,
+    },
+    error::BoxError,
+    ff::{boolean_array::BA32, FieldType},
+    helpers::query::{IpaQueryConfig, QueryConfig, QuerySize, QueryType},
+    report::EncryptedOprfReportStreams,
+    report_collector::ReportCollector,
+    test_fixture::ipa::IpaSecurityModel,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("failed to parse {path}: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("invalid manifest: {0}")]
+    InvalidManifest(String),
+    #[error("invalid breakdown labels for query {query:?}: {source}")]
+    Labels {
+        query: String,
+        #[source]
+        source: LabelError,
+    },
+    #[error(
+        "checkpoint {0} exists from a previous run, pass --resume to continue that run or remove \
+         the file to start over"
+    )]
+    CheckpointExists(PathBuf),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct BatchArgs {
+    /// TOML or JSON file listing the queries to run
+    #[arg(long)]
+    pub manifest: PathBuf,
+
+    /// Maximum number of queries running at the same time. Helpers reject new queries while one
+    /// is running, so values above 1 only help with helpers that run several queries at once.
+    #[arg(long, default_value = "1")]
+    pub concurrency: NonZeroUsize,
+
+    /// Number of times to run a query before giving up on it
+    #[arg(long, default_value = "3")]
+    pub max_attempts: NonZeroUsize,
+
+    /// Seconds to wait before running a failed query again. The wait grows linearly with the
+    /// number of failed attempts.
+    #[arg(long, default_value = "10")]
+    pub retry_delay: u64,
+
+    /// File that records the queries that completed. Defaults to the manifest path with the
+    /// `.checkpoint.json` extension.
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Continue a previous run of the manifest, skipping the queries that it completed
+    #[arg(long)]
+    pub resume: bool,
+}
+
+impl BatchArgs {
+    fn checkpoint_path(&self) -> PathBuf {
+        self.checkpoint
+            .clone()
+            .unwrap_or_else(|| self.manifest.with_extension("checkpoint.json"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub queries: Vec<ManifestQuery>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestQuery {
+    /// Identifies the query in the checkpoint and the summary. Only ASCII letters, digits, `-`,
+    /// `_` and `.` are allowed, so that it can be used as a file name.
+    pub name: String,
+    #[serde(default = "default_security_model")]
+    pub security_model: IpaSecurityModel,
+    pub inputs: QueryInputs,
+    pub output_file: Option<PathBuf>,
+    /// JSON file with the labels of the breakdown keys, see [`BreakdownLabels`].
+    pub breakdown_labels: Option<PathBuf>,
+    pub config: IpaQueryConfig,
+}
+
+fn default_security_model() -> IpaSecurityModel {
+    IpaSecurityModel::SemiHonest
+}
+
+/// The encrypted reports of a query, in the same formats that the `report_collector` accepts for
+/// a single query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryInputs {
+    /// Newline-delimited hex files, one for each helper.
+    Files([PathBuf; 3]),
+    /// A Parquet file with a binary column for each helper.
+    Parquet(PathBuf),
+}
+
+impl Manifest {
+    /// Reads a manifest from a `.toml` or `.json` file. Relative paths in the manifest are
+    /// resolved against the directory of the file, and the output file of queries that do not
+    /// have one is set to `<name>.json` in that directory.
+    ///
+    /// ## Errors
+    /// If the file cannot be read or parsed, or the manifest is not valid.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        let parse_error = |message: String| Error::Parse {
+            path: path.to_owned(),
+            message,
+        };
+        let mut manifest: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents).map_err(|e| parse_error(e.to_string()))?,
+            Some("json") => {
+                serde_json::from_str(&contents).map_err(|e| parse_error(e.to_string()))?
+            }
+            _ => return Err(parse_error("expected a .toml or .json file".to_string())),
+        };
+
+        manifest.resolve_paths(path.parent().unwrap_or(Path::new("")));
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.queries.is_empty() {
+            return Err(Error::InvalidManifest("there are no queries".to_string()));
+        }
+
+        let mut names = HashSet::new();
+        let mut output_files = HashSet::new();
+        for query in &self.queries {
+            let invalid =
+                |reason: &str| Error::InvalidManifest(format!("query {:?} {reason}", query.name));
+            if query.name.is_empty()
+                || !query
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            {
+                return Err(invalid(
+                    "must have a name made of ASCII letters, digits, '-', '_' and '.'",
+                ));
+            }
+            if !names.insert(&query.name) {
+                return Err(invalid("appears more than once"));
+            }
+            if query.config.dry_run {
+                return Err(invalid(
+                    "is a dry run, use `report_collector validate` to check inputs",
+                ));
+            }
+            if !output_files.insert(query.output_file()) {
+                return Err(invalid("writes to the output file of another query"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        for query in &mut self.queries {
+            match &mut query.inputs {
+                QueryInputs::Files(files) => {
+                    for file in files {
+                        *file = base.join(&*file);
+                    }
+                }
+                QueryInputs::Parquet(file) => *file = base.join(&*file),
+            }
+            if let Some(labels) = &mut query.breakdown_labels {
+                *labels = base.join(&*labels);
+            }
+            let output_file = query
+                .output_file
+                .take()
+                .unwrap_or_else(|| PathBuf::from(format!("{}.json", query.name)));
+            query.output_file = Some(base.join(output_file));
+        }
+    }
+}
+
+impl ManifestQuery {
+    fn output_file(&self) -> &Path {
+        // set by `Manifest::resolve_paths`
+        self.output_file.as_deref().unwrap()
+    }
+
+    fn query_type(&self) -> QueryType {
+        match self.security_model {
+            IpaSecurityModel::SemiHonest => QueryType::SemiHonestOprfIpa(self.config),
+            IpaSecurityModel::Malicious => QueryType::MaliciousOprfIpa(self.config),
+        }
+    }
+
+    /// Reads the inputs of this query. Reading the hex files panics if they are malformed, which
+    /// is why this runs on a blocking task: the query fails, not the batch.
+    async fn read_inputs(&self) -> Result<EncryptedOprfReportStreams, BoxError> {
+        let inputs = self.inputs.clone();
+        tokio::task::spawn_blocking(move || -> Result<_, BoxError> {
+            match inputs {
+                QueryInputs::Files(files) => Ok(EncryptedOprfReportStreams::from(files.each_ref())),
+                #[cfg(feature = "parquet-io")]
+                QueryInputs::Parquet(path) => Ok(columnar::read_encrypted_reports(
+                    &path,
+                    columnar::ENCRYPTED_REPORT_COLUMNS,
+                )?),
+                #[cfg(not(feature = "parquet-io"))]
+                QueryInputs::Parquet(path) => Err(parquet_disabled(&path)),
+            }
+        })
+        .await
+        .map_err(|e| format!("failed to read the inputs: {e}"))?
+    }
+
+    fn is_parquet_output(&self) -> bool {
+        self.output_file()
+            .extension()
+            .is_some_and(|ext| ext == "parquet")
+    }
+
+    /// Reads the breakdown labels of this query, before running it, so that it does not fail
+    /// after running for a long time.
+    fn read_labels(&self) -> Result<Option<BreakdownLabels>, Error> {
+        let Some(path) = &self.breakdown_labels else {
+            return Ok(None);
+        };
+        let labels_error = |source| Error::Labels {
+            query: self.name.clone(),
+            source,
+        };
+        let labels = BreakdownLabels::from_file(path).map_err(labels_error)?;
+        labels
+            .check(self.config.max_breakdown_key)
+            .map_err(labels_error)?;
+        if self.is_parquet_output() {
+            return Err(Error::InvalidManifest(format!(
+                "query {:?} has breakdown labels, which are only supported for CSV and JSON output",
+                self.name
+            )));
+        }
+
+        Ok(Some(labels))
+    }
+
+    /// Writes the result of this query, replacing the output of a previous run.
+    fn write_output(
+        &self,
+        result: &IpaQueryResult,
+        labels: Option<&BreakdownLabels>,
+    ) -> Result<(), BoxError> {
+        let path = self.output_file();
+        let write_error = |e| format!("failed to write {}: {e}", path.display());
+        if self.is_parquet_output() {
+            #[cfg(feature = "parquet-io")]
+            {
+                if path.exists() {
+                    fs::remove_file(path).map_err(write_error)?;
+                }
+                return Ok(columnar::write_query_result(path, result)?);
+            }
+            #[cfg(not(feature = "parquet-io"))]
+            return Err(parquet_disabled(path));
+        }
+
+        ResultExport::new(result, labels)?
+            .write_to(path)
+            .map_err(|e| write_error(e).into())
+    }
+}
+
+#[cfg(not(feature = "parquet-io"))]
+fn parquet_disabled(path: &Path) -> BoxError {
+    format!(
+        "{} is a Parquet file, but this binary was built without the parquet-io feature",
+        path.display()
+    )
+    .into()
+}
+
+/// The queries of a manifest that completed, saved after every query so that a batch can be
+/// resumed.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub completed: BTreeMap<String, CompletedQuery>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CompletedQuery {
+    /// The query as it was run. If the manifest changes it, a resumed batch runs it again.
+    pub query: ManifestQuery,
+    #[serde(
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs"
+    )]
+    pub latency: Duration,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint at `path`, or returns an empty one if there is no such file.
+    ///
+    /// ## Errors
+    /// If the file exists, but cannot be read or parsed.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| Error::Parse {
+                path: path.to_owned(),
+                message: e.to_string(),
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(Error::Io {
+                path: path.to_owned(),
+                source,
+            }),
+        }
+    }
+
+    /// Writes the checkpoint to `path`. The file is replaced atomically, so that it is not lost if
+    /// the process is killed while writing it.
+    ///
+    /// ## Errors
+    /// If the file cannot be written.
+    ///
+    /// ## Panics
+    /// If the checkpoint cannot be serialized, which does not happen.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let io_error = |source| Error::Io {
+            path: path.to_owned(),
+            source,
+        };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self).unwrap()).map_err(io_error)?;
+        fs::rename(&tmp, path).map_err(io_error)
+    }
+
+    /// Whether `query` completed in the run that this checkpoint is for, with the same inputs,
+    /// outputs and configuration.
+    #[must_use]
+    pub fn is_completed(&self, query: &ManifestQuery) -> bool {
+        self.completed
+            .get(&query.name)
+            .is_some_and(|completed| &completed.query == query)
+    }
+}
+
+/// What happened to each query of a manifest, in manifest order.
+#[derive(Debug, Serialize)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Queries that completed in a previous run and were not run again.
+    pub resumed: usize,
+    pub queries: Vec<QueryOutcome>,
+}
+
+impl BatchSummary {
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryOutcome {
+    pub name: String,
+    #[serde(flatten)]
+    pub status: QueryStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum QueryStatus {
+    Succeeded {
+        attempts: usize,
+        output_file: PathBuf,
+        #[serde(serialize_with = "crate::serde::duration::to_secs")]
+        latency: Duration,
+    },
+    Failed {
+        attempts: usize,
+        error: String,
+    },
+    Resumed {
+        output_file: PathBuf,
+    },
+}
+
+/// Runs the queries in the manifest of `args`, saving a checkpoint after every query that
+/// completes. Queries that fail are reported in the summary; they do not stop the others.
+///
+/// ## Errors
+/// If the manifest is not valid, or the checkpoint cannot be read or written.
+///
+/// ## Panics
+/// If a query in the manifest has no status, which does not happen.
+pub async fn run_batch(
+    collector: &ReportCollector,
+    args: &BatchArgs,
+) -> Result<BatchSummary, Error> {
+    let manifest = Manifest::from_file(&args.manifest)?;
+    let checkpoint_path = args.checkpoint_path();
+    let mut checkpoint = if args.resume {
+        Checkpoint::load(&checkpoint_path)?
+    } else if checkpoint_path.exists() {
+        return Err(Error::CheckpointExists(checkpoint_path));
+    } else {
+        Checkpoint::default()
+    };
+
+    let (done, pending): (Vec<_>, Vec<_>) = manifest
+        .queries
+        .iter()
+        .partition(|query| checkpoint.is_completed(query));
+    let pending = pending
+        .into_iter()
+        .map(|query| Ok((query, query.read_labels()?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let total = pending.len();
+    tracing::info!(
+        "Running {total} queries, {} completed in a previous run",
+        done.len()
+    );
+
+    let mut statuses = done
+        .into_iter()
+        .map(|query| {
+            let status = QueryStatus::Resumed {
+                output_file: query.output_file().to_owned(),
+            };
+            (query.name.clone(), status)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut finished = 0;
+    let mut runs = stream::iter(&pending)
+        .map(|(query, labels)| run_with_retries(collector, query, labels.as_ref(), args))
+        .buffer_unordered(args.concurrency.get());
+    while let Some((query, status)) = runs.next().await {
+        if let QueryStatus::Succeeded { latency, .. } = status {
+            checkpoint.completed.insert(
+                query.name.clone(),
+                CompletedQuery {
+                    query: query.clone(),
+                    latency,
+                },
+            );
+            checkpoint.save(&checkpoint_path)?;
+        }
+        statuses.insert(query.name.clone(), status);
+        finished += 1;
+        tracing::info!("{finished} of {total} queries finished");
+    }
+
+    let mut summary = BatchSummary {
+        succeeded: 0,
+        failed: 0,
+        resumed: 0,
+        queries: Vec::with_capacity(manifest.queries.len()),
+    };
+    for query in &manifest.queries {
+        let status = statuses.remove(&query.name).unwrap();
+        match status {
+            QueryStatus::Succeeded { .. } => summary.succeeded += 1,
+            QueryStatus::Failed { .. } => summary.failed += 1,
+            QueryStatus::Resumed { .. } => summary.resumed += 1,
+        }
+        summary.queries.push(QueryOutcome {
+            name: query.name.clone(),
+            status,
+        });
+    }
+
+    Ok(summary)
+}
+
+async fn run_with_retries<'a>(
+    collector: &ReportCollector,
+    query: &'a ManifestQuery,
+    labels: Option<&BreakdownLabels>,
+    args: &BatchArgs,
+) -> (&'a ManifestQuery, QueryStatus) {
+    let max_attempts = args.max_attempts.get();
+    let mut attempt = 1;
+    let status = loop {
+        match run_query(collector, query).await {
+            Ok(result) => {
+                tracing::info!("Query {} completed in {:?}", query.name, result.latency);
+                break match query.write_output(&result, labels) {
+                    Ok(()) => QueryStatus::Succeeded {
+                        attempts: attempt,
+                        output_file: query.output_file().to_owned(),
+                        latency: result.latency,
+                    },
+                    // running the query again would not help
+                    Err(e) => QueryStatus::Failed {
+                        attempts: attempt,
+                        error: e.to_string(),
+                    },
+                };
+            }
+            Err(e) if attempt < max_attempts => {
+                let delay = Duration::from_secs(args.retry_delay) * u32::try_from(attempt).unwrap();
+                tracing::warn!(
+                    "Query {} failed on attempt {attempt} of {max_attempts}, retrying in \
+                     {delay:?}: {e}",
+                    query.name
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                tracing::error!("Query {} failed after {attempt} attempts: {e}", query.name);
+                break QueryStatus::Failed {
+                    attempts: attempt,
+                    error: e.to_string(),
+                };
+            }
+        }
+    };
+
+    (query, status)
+}
+
+async fn run_query(
+    collector: &ReportCollector,
+    query: &ManifestQuery,
+) -> Result<IpaQueryResult, BoxError> {
+    let inputs = query.read_inputs().await?;
+    let query_config = QueryConfig {
+        size: QuerySize::try_from(inputs.query_size)?,
+        field_type: FieldType::Fp32BitPrime,
+        query_type: query.query_type(),
+    };
+
+    let start = Instant::now();
+    let handle = collector.submit(query_config, inputs.streams).await?;
+    tracing::info!("Submitted query {} as {:?}", query.name, handle.query_id());
+    // the value for histogram values (BA32) must be kept in sync with the server-side
+    // implementation, otherwise a runtime reconstruct error will be generated.
+    // see ipa-core/src/query/executor.rs
+    let results = match handle.results::<BA32>().await {
+        Ok(results) => results,
+        Err(e) => {
+            // don't leave the query running on the helpers if it is going to be retried
+            if let Err(cancel_error) = handle.cancel().await {
+                tracing::warn!("Failed to cancel query {}: {cancel_error}", query.name);
+            }
+            return Err(e.into());
+        }
+    };
+
+    Ok(query_result(
+        results,
+        inputs.query_size,
+        start.elapsed(),
+        query.config,
+    ))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{fs, path::Path};
+
+    use tempfile::tempdir;
+
+    use super::{Checkpoint, CompletedQuery, Error, Manifest, QueryInputs};
+    use crate::test_fixture::ipa::IpaSecurityModel;
+
+    const MANIFEST: &str = r#"
+        [[queries]]
+        name = "campaign-1"
+        security_model = "malicious"
+        inputs.files = ["campaign-1/h1.txt", "campaign-1/h2.txt", "/data/h3.txt"]
+        output_file = "results/campaign-1.csv"
+        breakdown_labels = "labels.json"
+        config = { per_user_credit_cap = 8, max_breakdown_key = 20, with_dp = 1, epsilon = 3.0 }
+
+        [[queries]]
+        name = "campaign-2"
+        inputs.parquet = "campaign-2.parquet"
+        config = { per_user_credit_cap = 8, max_breakdown_key = 20, with_dp = 0, epsilon = 1.0 }
+    "#;
+
+    fn write_manifest(dir: &Path, name: &str, contents: &str) -> Result<Manifest, Error> {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        Manifest::from_file(&path)
+    }
+
+    #[test]
+    fn parses_toml() {
+        let dir = tempdir().unwrap();
+        let manifest = write_manifest(dir.path(), "batch.toml", MANIFEST).unwrap();
+
+        let [first, second] = manifest.queries.as_slice() else {
+            panic!("expected two queries, got {manifest:?}");
+        };
+        assert_eq!(first.security_model, IpaSecurityModel::Malicious);
+        assert_eq!(
+            first.inputs,
+            QueryInputs::Files([
+                dir.path().join("campaign-1/h1.txt"),
+                dir.path().join("campaign-1/h2.txt"),
+                "/data/h3.txt".into(),
+            ])
+        );
+        assert_eq!(
+            first.output_file(),
+            dir.path().join("results/campaign-1.csv")
+        );
+        assert_eq!(first.breakdown_labels, Some(dir.path().join("labels.json")));
+        assert_eq!(first.config.per_user_credit_cap, 8);
+        assert_eq!(first.config.attribution_window_seconds, None);
+
+        assert_eq!(second.security_model, IpaSecurityModel::SemiHonest);
+        assert_eq!(
+            second.inputs,
+            QueryInputs::Parquet(dir.path().join("campaign-2.parquet"))
+        );
+        assert_eq!(second.output_file(), dir.path().join("campaign-2.json"));
+    }
+
+    #[test]
+    fn parses_json() {
+        let dir = tempdir().unwrap();
+        let toml = write_manifest(dir.path(), "batch.toml", MANIFEST).unwrap();
+        let json: toml::Value = toml::from_str(MANIFEST).unwrap();
+        let json = write_manifest(
+            dir.path(),
+            "batch.json",
+            &serde_json::to_string(&json).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(toml, json);
+    }
+
+    #[test]
+    fn rejects_invalid_manifests() {
+        let dir = tempdir().unwrap();
+        let query = |name: &str, output_file: &str, config: &str| {
+            format!(
+                "[[queries]]\nname = \"{name}\"\ninputs.parquet = \"in.parquet\"\n\
+                 output_file = \"{output_file}\"\nconfig = {{ per_user_credit_cap = 8, \
+                 max_breakdown_key = 20, with_dp = 0, epsilon = 1.0{config} }}\n"
+            )
+        };
+
+        for manifest in [
+            "queries = []".to_string(),
+            query("../campaign", "out.json", ""),
+            query("campaign", "1.json", "") + &query("campaign", "2.json", ""),
+            query("campaign-1", "out.json", "") + &query("campaign-2", "out.json", ""),
+            query("campaign", "out.json", ", dry_run = true"),
+            query("campaign", "out.json", "") + "unknown = 1\n",
+        ] {
+            assert!(
+                matches!(
+                    write_manifest(dir.path(), "batch.toml", &manifest),
+                    Err(Error::InvalidManifest(_) | Error::Parse { .. })
+                ),
+                "{manifest}"
+            );
+        }
+
+        assert!(matches!(
+            write_manifest(dir.path(), "batch.yaml", MANIFEST),
+            Err(Error::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_labels() {
+        let dir = tempdir().unwrap();
+        let manifest = write_manifest(dir.path(), "batch.toml", MANIFEST).unwrap();
+        let labels = dir.path().join("labels.json");
+
+        fs::write(&labels, r#"{ "19": { "name": "campaign-1" } }"#).unwrap();
+        let labels = manifest.queries[0].read_labels().unwrap().unwrap();
+        assert_eq!(labels.0[&19].name, "campaign-1");
+        assert!(manifest.queries[1].read_labels().unwrap().is_none());
+
+        fs::write(
+            dir.path().join("labels.json"),
+            r#"{ "20": { "name": "campaign-1" } }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            manifest.queries[0].read_labels(),
+            Err(Error::Labels { .. })
+        ));
+    }
+
+    #[test]
+    fn checkpoint() {
+        let dir = tempdir().unwrap();
+        let manifest = write_manifest(dir.path(), "batch.toml", MANIFEST).unwrap();
+        let path = dir.path().join("batch.checkpoint.json");
+        assert_eq!(Checkpoint::load(&path).unwrap(), Checkpoint::default());
+
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.completed.insert(
+            manifest.queries[0].name.clone(),
+            CompletedQuery {
+                query: manifest.queries[0].clone(),
+                latency: std::time::Duration::from_secs(42),
+            },
+        );
+        checkpoint.save(&path).unwrap();
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert!(checkpoint.is_completed(&manifest.queries[0]));
+        assert!(!checkpoint.is_completed(&manifest.queries[1]));
+
+        // a query that changed since it completed runs again
+        let mut changed = manifest.queries[0].clone();
+        changed.config.epsilon = 1.0;
+        assert!(!checkpoint.is_completed(&changed));
+    }
+}
@@ -9,6 +9,7 @@ use crate::{
     cli::paths::PathExt,
     config::{ClientConfig, HpkeClientConfig, NetworkConfig, PeerConfig},
     error::BoxError,
+    net::compression,
 };
 
 #[derive(Debug, Args)]
@@ -138,6 +139,10 @@ pub fn gen_client_config<'a>(
             String::from("hpke"),
             Value::Table(encode_hpke(mk_public_key)),
         );
+        peer.insert(
+            String::from("step_encodings"),
+            Value::Array(vec![Value::String(compression::ZSTD.into())]),
+        );
         peers.push(peer.into());
     }
 
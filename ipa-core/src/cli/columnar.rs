@@ -0,0 +1,701 @@
+//! Reads and writes report collector datasets as Parquet files.
+//!
+//! Encrypted reports are read from a single file with a binary column for each helper. Row `i` of
+//! each column holds the share of report `i` encrypted for that helper.
+//!
+//! Plaintext test records ([`TestRawDataRecord`], [`TestHybridRecord`]) are stored with one
+//! column per field, see [`ColumnarRecord`]. Query results are stored with one row per breakdown
+//! key, or per breakdown key and value bucket, and the query parameters are kept in the file
+//! metadata.
+//!
+//! [`TestRawDataRecord`]: crate::test_fixture::ipa::TestRawDataRecord
+//! [`TestHybridRecord`]: crate::test_fixture::hybrid::TestHybridRecord
+
+use std::{fs::File, path::Path, sync::Arc, time::Duration};
+
+use arrow_array::{Array, ArrayRef, BinaryArray, RecordBatch, UInt32Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use bytes::BufMut;
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    basic::{Compression, ZstdLevel},
+    errors::ParquetError,
+    file::{metadata::KeyValue, properties::WriterProperties},
+};
+
+use crate::{
+    cli::IpaQueryResult,
+    helpers::{query::IpaQueryConfig, BodyStream},
+    protocol::ipa_prf::ValueBuckets,
+    report::EncryptedOprfReportStreams,
+};
+
+/// Number of rows in each record batch that is read or written.
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Names of the columns that hold encrypted reports for each of the helpers, unless other
+/// names are given to [`read_encrypted_reports`].
+pub const ENCRYPTED_REPORT_COLUMNS: [&str; 3] = ["helper_1", "helper_2", "helper_3"];
+
+const RESULT_INPUT_SIZE_KEY: &str = "ipa.input_size";
+const RESULT_CONFIG_KEY: &str = "ipa.config";
+const RESULT_LATENCY_KEY: &str = "ipa.latency_secs";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Parquet(#[from] ParquetError),
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+    #[error("column {name} is missing or is not of type {expected}")]
+    MissingColumn { name: String, expected: DataType },
+    #[error("column {name} contains nulls")]
+    NullValue { name: String },
+    #[error("invalid value in column {name}: {reason}")]
+    InvalidValue { name: String, reason: String },
+    #[error("encrypted report of {0} bytes is too large")]
+    ReportTooLarge(usize),
+    #[error("metadata key {0} is missing or invalid")]
+    InvalidMetadata(&'static str),
+}
+
+/// A record that can be stored in a Parquet file, one column per field.
+pub trait ColumnarRecord: Sized {
+    fn schema() -> SchemaRef;
+
+    /// Converts `records` to a record batch with the schema returned by [`Self::schema`].
+    ///
+    /// ## Errors
+    /// If the batch cannot be created.
+    fn to_batch(records: &[Self]) -> Result<RecordBatch, ArrowError>;
+
+    /// Reads the records from `batch`. Columns that are not part of the schema are ignored.
+    ///
+    /// ## Errors
+    /// If a column is missing, or holds values that do not fit the record.
+    fn from_batch(batch: &RecordBatch) -> Result<Vec<Self>, Error>;
+}
+
+/// Reads all the records from the Parquet file at `path`.
+///
+/// ## Errors
+/// If the file cannot be read or does not contain records of type `T`.
+pub fn read_records<T: ColumnarRecord>(path: &Path) -> Result<Vec<T>, Error> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?
+        .with_batch_size(BATCH_SIZE)
+        .build()?;
+
+    let mut records = Vec::new();
+    for batch in reader {
+        records.extend(T::from_batch(&batch?)?);
+    }
+
+    Ok(records)
+}
+
+/// Writes `records` to a new Parquet file at `path`. The records are written in batches, so
+/// `records` does not need to fit in memory.
+///
+/// ## Errors
+/// If the file already exists or cannot be written.
+pub fn write_records<T: ColumnarRecord>(
+    path: &Path,
+    records: impl IntoIterator<Item = T>,
+) -> Result<(), Error> {
+    let file = File::options().write(true).create_new(true).open(path)?;
+    let mut writer = ArrowWriter::try_new(file, T::schema(), Some(writer_properties(None)))?;
+
+    let mut records = records.into_iter();
+    loop {
+        let batch = records.by_ref().take(BATCH_SIZE).collect::<Vec<_>>();
+        if batch.is_empty() {
+            break;
+        }
+        writer.write(&T::to_batch(&batch)?)?;
+    }
+    writer.close()?;
+
+    Ok(())
+}
+
+/// Reads encrypted reports from the Parquet file at `path`, where `columns` name the binary
+/// columns that hold the reports for each helper. The reports are length-delimited in the same
+/// way as [`EncryptedOprfReportStreams`] read from hex files.
+///
+/// ## Errors
+/// If the file cannot be read, a column is missing, or a report is too large.
+pub fn read_encrypted_reports(
+    path: &Path,
+    columns: [&str; 3],
+) -> Result<EncryptedOprfReportStreams, Error> {
+    let reports = read_encrypted_report_columns(path, columns)?;
+    let query_size = reports[0].len();
+
+    let mut buffers: [Vec<u8>; 3] = std::array::from_fn(|_| Vec::new());
+    for (buffer, reports) in buffers.iter_mut().zip(reports) {
+        for report in reports {
+            let len =
+                u16::try_from(report.len()).map_err(|_| Error::ReportTooLarge(report.len()))?;
+            buffer.put_u16_le(len);
+            buffer.put_slice(&report);
+        }
+    }
+
+    Ok(EncryptedOprfReportStreams {
+        streams: buffers.map(BodyStream::from),
+        query_size,
+    })
+}
+
+/// Reads the encrypted reports for each helper from the Parquet file at `path`, where `columns`
+/// name the binary columns that hold them.
+///
+/// ## Errors
+/// If the file cannot be read, or a column is missing.
+pub fn read_encrypted_report_columns(
+    path: &Path,
+    columns: [&str; 3],
+) -> Result<[Vec<Vec<u8>>; 3], Error> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?
+        .with_batch_size(BATCH_SIZE)
+        .build()?;
+
+    let mut reports: [Vec<Vec<u8>>; 3] = std::array::from_fn(|_| Vec::new());
+    for batch in reader {
+        let batch = batch?;
+        for (helper_reports, name) in reports.iter_mut().zip(columns) {
+            let column = non_null(
+                column::<BinaryArray>(&batch, name, &DataType::Binary)?,
+                name,
+            )?;
+            helper_reports.extend((0..column.len()).map(|i| column.value(i).to_vec()));
+        }
+    }
+
+    Ok(reports)
+}
+
+/// Writes the result of an IPA query to a new Parquet file at `path`, with a `breakdown_key` and
+/// a `value` column. Queries with value buckets also get a `bucket` column, with the lower bound
+/// of the bucket. The input size, query configuration and latency are stored in the file
+/// metadata.
+///
+/// ## Errors
+/// If the file already exists or cannot be written.
+pub fn write_query_result(path: &Path, result: &IpaQueryResult) -> Result<(), Error> {
+    let buckets = result.config.value_buckets;
+    let schema = result_schema(buckets.is_some());
+    let (breakdown_keys, lower_bounds): (Vec<_>, Vec<_>) = (0..result.breakdowns.len())
+        .map(|i| match &buckets {
+            Some(buckets) => (i / buckets.len(), buckets.lower_bounds()[i % buckets.len()]),
+            None => (i, 0),
+        })
+        .map(|(bk, lower_bound)| (u32::try_from(bk).unwrap(), lower_bound))
+        .unzip();
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(UInt32Array::from(breakdown_keys))];
+    if buckets.is_some() {
+        columns.push(Arc::new(UInt32Array::from(lower_bounds)));
+    }
+    columns.push(Arc::new(UInt32Array::from(result.breakdowns.clone())));
+    let batch = RecordBatch::try_new(Arc::clone(&schema), columns)?;
+
+    let metadata = vec![
+        KeyValue::new(
+            RESULT_INPUT_SIZE_KEY.to_string(),
+            usize::from(result.input_size).to_string(),
+        ),
+        KeyValue::new(
+            RESULT_CONFIG_KEY.to_string(),
+            serde_json::to_string(&result.config).unwrap(),
+        ),
+        KeyValue::new(
+            RESULT_LATENCY_KEY.to_string(),
+            result.latency.as_secs_f64().to_string(),
+        ),
+    ];
+    let file = File::options().write(true).create_new(true).open(path)?;
+    let mut writer = ArrowWriter::try_new(file, schema, Some(writer_properties(Some(metadata))))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+/// Reads the result of an IPA query written by [`write_query_result`].
+///
+/// ## Errors
+/// If the file cannot be read, or was not written by [`write_query_result`].
+pub fn read_query_result(path: &Path) -> Result<IpaQueryResult, Error> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let metadata = builder
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .cloned()
+        .unwrap_or_default();
+    let get = |key: &'static str| {
+        metadata
+            .iter()
+            .find(|kv| kv.key == key)
+            .and_then(|kv| kv.value.as_deref())
+            .ok_or(Error::InvalidMetadata(key))
+    };
+    let input_size = get(RESULT_INPUT_SIZE_KEY)?
+        .parse::<usize>()
+        .ok()
+        .and_then(|sz| sz.try_into().ok())
+        .ok_or(Error::InvalidMetadata(RESULT_INPUT_SIZE_KEY))?;
+    let config: IpaQueryConfig = serde_json::from_str(get(RESULT_CONFIG_KEY)?)
+        .map_err(|_| Error::InvalidMetadata(RESULT_CONFIG_KEY))?;
+    let latency = get(RESULT_LATENCY_KEY)?
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or(Error::InvalidMetadata(RESULT_LATENCY_KEY))?;
+
+    let mut breakdowns = Vec::new();
+    for batch in builder.build()? {
+        let batch = batch?;
+        let keys = non_null(
+            column::<UInt32Array>(&batch, "breakdown_key", &DataType::UInt32)?,
+            "breakdown_key",
+        )?;
+        let values = non_null(
+            column::<UInt32Array>(&batch, "value", &DataType::UInt32)?,
+            "value",
+        )?;
+        let lower_bounds = config
+            .value_buckets
+            .map(|_| {
+                non_null(
+                    column::<UInt32Array>(&batch, "bucket", &DataType::UInt32)?,
+                    "bucket",
+                )
+            })
+            .transpose()?;
+        for (row, (bk, value)) in keys.values().iter().zip(values.values()).enumerate() {
+            let bk = usize::try_from(*bk).unwrap();
+            let index = match (&config.value_buckets, lower_bounds) {
+                (Some(buckets), Some(lower_bounds)) => {
+                    buckets.histogram_index(bk, bucket_of(buckets, lower_bounds.value(row))?)
+                }
+                _ => bk,
+            };
+            if breakdowns.len() <= index {
+                breakdowns.resize(index + 1, 0);
+            }
+            breakdowns[index] = *value;
+        }
+    }
+
+    Ok(IpaQueryResult {
+        input_size,
+        config,
+        latency,
+        breakdowns,
+    })
+}
+
+fn result_schema(bucketed: bool) -> SchemaRef {
+    let mut fields = vec![Field::new("breakdown_key", DataType::UInt32, false)];
+    if bucketed {
+        fields.push(Field::new("bucket", DataType::UInt32, false));
+    }
+    fields.push(Field::new("value", DataType::UInt32, false));
+    Arc::new(Schema::new(fields))
+}
+
+/// The index of the value bucket with lower bound `lower_bound`.
+fn bucket_of(buckets: &ValueBuckets, lower_bound: u32) -> Result<usize, Error> {
+    buckets
+        .lower_bounds()
+        .iter()
+        .position(|&b| b == lower_bound)
+        .ok_or_else(|| Error::InvalidValue {
+            name: "bucket".to_string(),
+            reason: format!("{lower_bound} is not the lower bound of a value bucket"),
+        })
+}
+
+fn writer_properties(metadata: Option<Vec<KeyValue>>) -> WriterProperties {
+    WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .set_key_value_metadata(metadata)
+        .build()
+}
+
+/// Returns the column `name` of `batch`, if it has the type `A`.
+fn column<'a, A: Array + 'static>(
+    batch: &'a RecordBatch,
+    name: &str,
+    expected: &DataType,
+) -> Result<&'a A, Error> {
+    batch
+        .column_by_name(name)
+        .and_then(|c| c.as_any().downcast_ref::<A>())
+        .ok_or_else(|| Error::MissingColumn {
+            name: name.to_string(),
+            expected: expected.clone(),
+        })
+}
+
+/// Fails if `array` contains nulls.
+fn non_null<'a, A: Array>(array: &'a A, name: &str) -> Result<&'a A, Error> {
+    if array.null_count() > 0 {
+        return Err(Error::NullValue {
+            name: name.to_string(),
+        });
+    }
+
+    Ok(array)
+}
+
+#[cfg(any(test, feature = "test-fixture"))]
+mod test_records {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        Array, ArrayRef, BooleanArray, RecordBatch, StringArray, UInt32Array, UInt64Array,
+    };
+    use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+
+    use super::{column, non_null, ColumnarRecord, Error};
+    use crate::test_fixture::{hybrid::TestHybridRecord, ipa::TestRawDataRecord};
+
+    impl ColumnarRecord for TestRawDataRecord {
+        fn schema() -> SchemaRef {
+            Arc::new(Schema::new(vec![
+                Field::new("timestamp", DataType::UInt64, false),
+                Field::new("user_id", DataType::UInt64, false),
+                Field::new("is_trigger_report", DataType::Boolean, false),
+                Field::new("breakdown_key", DataType::UInt32, false),
+                Field::new("trigger_value", DataType::UInt32, false),
+            ]))
+        }
+
+        fn to_batch(records: &[Self]) -> Result<RecordBatch, ArrowError> {
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(UInt64Array::from_iter_values(
+                    records.iter().map(|r| r.timestamp),
+                )),
+                Arc::new(UInt64Array::from_iter_values(
+                    records.iter().map(|r| r.user_id),
+                )),
+                Arc::new(BooleanArray::from(
+                    records
+                        .iter()
+                        .map(|r| r.is_trigger_report)
+                        .collect::<Vec<_>>(),
+                )),
+                Arc::new(UInt32Array::from_iter_values(
+                    records.iter().map(|r| r.breakdown_key),
+                )),
+                Arc::new(UInt32Array::from_iter_values(
+                    records.iter().map(|r| r.trigger_value),
+                )),
+            ];
+            RecordBatch::try_new(Self::schema(), columns)
+        }
+
+        fn from_batch(batch: &RecordBatch) -> Result<Vec<Self>, Error> {
+            let timestamp = u64_column(batch, "timestamp")?;
+            let user_id = u64_column(batch, "user_id")?;
+            let is_trigger_report = non_null(
+                column::<BooleanArray>(batch, "is_trigger_report", &DataType::Boolean)?,
+                "is_trigger_report",
+            )?;
+            let breakdown_key = u32_column(batch, "breakdown_key")?;
+            let trigger_value = u32_column(batch, "trigger_value")?;
+
+            Ok((0..batch.num_rows())
+                .map(|i| TestRawDataRecord {
+                    timestamp: timestamp.value(i),
+                    user_id: user_id.value(i),
+                    is_trigger_report: is_trigger_report.value(i),
+                    breakdown_key: breakdown_key.value(i),
+                    trigger_value: trigger_value.value(i),
+                })
+                .collect())
+        }
+    }
+
+    /// Hybrid records have an `event_type` column that is `"i"` for impressions and `"c"` for
+    /// conversions, like the CSV format. Impressions have a `breakdown_key` and conversions have
+    /// a `value`, the other column is null.
+    impl ColumnarRecord for TestHybridRecord {
+        fn schema() -> SchemaRef {
+            Arc::new(Schema::new(vec![
+                Field::new("event_type", DataType::Utf8, false),
+                Field::new("match_key", DataType::UInt64, false),
+                Field::new("breakdown_key", DataType::UInt32, true),
+                Field::new("value", DataType::UInt32, true),
+            ]))
+        }
+
+        fn to_batch(records: &[Self]) -> Result<RecordBatch, ArrowError> {
+            let event_type = records
+                .iter()
+                .map(|r| match r {
+                    TestHybridRecord::TestImpression { .. } => Some("i"),
+                    TestHybridRecord::TestConversion { .. } => Some("c"),
+                })
+                .collect::<StringArray>();
+            let match_key = UInt64Array::from_iter_values(records.iter().map(|r| match r {
+                TestHybridRecord::TestImpression { match_key, .. }
+                | TestHybridRecord::TestConversion { match_key, .. } => *match_key,
+            }));
+            let breakdown_key = records
+                .iter()
+                .map(|r| match r {
+                    TestHybridRecord::TestImpression { breakdown_key, .. } => Some(*breakdown_key),
+                    TestHybridRecord::TestConversion { .. } => None,
+                })
+                .collect::<UInt32Array>();
+            let value = records
+                .iter()
+                .map(|r| match r {
+                    TestHybridRecord::TestImpression { .. } => None,
+                    TestHybridRecord::TestConversion { value, .. } => Some(*value),
+                })
+                .collect::<UInt32Array>();
+
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(event_type),
+                Arc::new(match_key),
+                Arc::new(breakdown_key),
+                Arc::new(value),
+            ];
+            RecordBatch::try_new(Self::schema(), columns)
+        }
+
+        fn from_batch(batch: &RecordBatch) -> Result<Vec<Self>, Error> {
+            let event_type = non_null(
+                column::<StringArray>(batch, "event_type", &DataType::Utf8)?,
+                "event_type",
+            )?;
+            let match_key = u64_column(batch, "match_key")?;
+            let breakdown_key = column::<UInt32Array>(batch, "breakdown_key", &DataType::UInt32)?;
+            let value = column::<UInt32Array>(batch, "value", &DataType::UInt32)?;
+
+            (0..batch.num_rows())
+                .map(|i| {
+                    let match_key = match_key.value(i);
+                    match event_type.value(i) {
+                        "i" => Ok(TestHybridRecord::TestImpression {
+                            match_key,
+                            breakdown_key: present(breakdown_key, i, "breakdown_key")?,
+                        }),
+                        "c" => Ok(TestHybridRecord::TestConversion {
+                            match_key,
+                            value: present(value, i, "value")?,
+                        }),
+                        other => Err(Error::InvalidValue {
+                            name: "event_type".to_string(),
+                            reason: format!("expected 'i' or 'c', got '{other}'"),
+                        }),
+                    }
+                })
+                .collect()
+        }
+    }
+
+    fn u64_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a UInt64Array, Error> {
+        non_null(column::<UInt64Array>(batch, name, &DataType::UInt64)?, name)
+    }
+
+    fn u32_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a UInt32Array, Error> {
+        non_null(column::<UInt32Array>(batch, name, &DataType::UInt32)?, name)
+    }
+
+    fn present(array: &UInt32Array, i: usize, name: &str) -> Result<u32, Error> {
+        if array.is_null(i) {
+            Err(Error::NullValue {
+                name: name.to_string(),
+            })
+        } else {
+            Ok(array.value(i))
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{fs::File, sync::Arc, time::Duration};
+
+    use arrow_array::{Array, BinaryArray, RecordBatch, UInt32Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+    use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter};
+    use tempfile::tempdir;
+
+    use super::{
+        read_encrypted_reports, read_query_result, read_records, write_query_result, write_records,
+        Error, ENCRYPTED_REPORT_COLUMNS,
+    };
+    use crate::{
+        cli::IpaQueryResult,
+        helpers::query::{IpaQueryConfig, QuerySize},
+        test_fixture::{hybrid::TestHybridRecord, ipa::TestRawDataRecord},
+    };
+
+    #[test]
+    fn raw_records() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("records.parquet");
+        let records = (0..10_u32)
+            .map(|i| TestRawDataRecord {
+                timestamp: u64::from(i) * 10,
+                user_id: u64::from(i % 3),
+                is_trigger_report: i % 2 == 0,
+                breakdown_key: i % 4,
+                trigger_value: i,
+            })
+            .collect::<Vec<_>>();
+
+        write_records(&path, records.clone()).unwrap();
+        assert_eq!(records, read_records::<TestRawDataRecord>(&path).unwrap());
+    }
+
+    #[test]
+    fn hybrid_records() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("records.parquet");
+        let records = vec![
+            TestHybridRecord::TestImpression {
+                match_key: 1,
+                breakdown_key: 2,
+            },
+            TestHybridRecord::TestConversion {
+                match_key: 1,
+                value: 5,
+            },
+        ];
+
+        write_records(&path, records.clone()).unwrap();
+        assert_eq!(records, read_records::<TestHybridRecord>(&path).unwrap());
+    }
+
+    #[test]
+    fn wrong_schema() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("records.parquet");
+        write_records(
+            &path,
+            [TestHybridRecord::TestConversion {
+                match_key: 1,
+                value: 5,
+            }],
+        )
+        .unwrap();
+
+        assert!(matches!(
+            read_records::<TestRawDataRecord>(&path),
+            Err(Error::MissingColumn { name, .. }) if name == "timestamp"
+        ));
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("reports.parquet");
+        let reports: [Vec<&[u8]>; 3] = [
+            vec![b"h1-first", b"h1-second"],
+            vec![b"h2-first", b"h2-second"],
+            vec![b"h3-first", b"h3-second"],
+        ];
+        let schema = Arc::new(Schema::new(
+            ENCRYPTED_REPORT_COLUMNS
+                .map(|name| Field::new(name, DataType::Binary, false))
+                .to_vec(),
+        ));
+        let batch = RecordBatch::try_new(
+            Arc::clone(&schema),
+            reports
+                .iter()
+                .map(|r| Arc::new(BinaryArray::from(r.clone())) as _)
+                .collect(),
+        )
+        .unwrap();
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let streams = read_encrypted_reports(&path, ENCRYPTED_REPORT_COLUMNS).unwrap();
+        assert_eq!(2, streams.query_size);
+        for (stream, reports) in streams.streams.into_iter().zip(reports) {
+            let bytes = stream.try_collect::<Vec<Bytes>>().await.unwrap().concat();
+            let expected = reports
+                .iter()
+                .flat_map(|r| {
+                    let len = u16::try_from(r.len()).unwrap().to_le_bytes();
+                    len.into_iter().chain(r.iter().copied())
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(expected, bytes);
+        }
+    }
+
+    #[test]
+    fn query_result() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("result.parquet");
+        let result = IpaQueryResult {
+            input_size: QuerySize::try_from(100).unwrap(),
+            config: IpaQueryConfig::default(),
+            latency: Duration::from_millis(1500),
+            breakdowns: vec![3, 0, 7, 1],
+        };
+
+        write_query_result(&path, &result).unwrap();
+        let read = read_query_result(&path).unwrap();
+        assert_eq!(result.input_size, read.input_size);
+        assert_eq!(result.config, read.config);
+        assert_eq!(result.latency, read.latency);
+        assert_eq!(result.breakdowns, read.breakdowns);
+    }
+
+    #[test]
+    fn query_result_with_value_buckets() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("result.parquet");
+        let result = IpaQueryResult {
+            input_size: QuerySize::try_from(100).unwrap(),
+            config: IpaQueryConfig {
+                max_breakdown_key: 2,
+                value_buckets: Some("10".parse().unwrap()),
+                ..IpaQueryConfig::default()
+            },
+            latency: Duration::from_millis(1500),
+            breakdowns: vec![3, 0, 7, 1],
+        };
+
+        write_query_result(&path, &result).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let column = |name| {
+            batch
+                .column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<UInt32Array>()
+                .unwrap()
+                .values()
+                .to_vec()
+        };
+        assert_eq!(column("breakdown_key"), vec![0, 0, 1, 1]);
+        assert_eq!(column("bucket"), vec![1, 10, 1, 10]);
+
+        assert_eq!(
+            result.breakdowns,
+            read_query_result(&path).unwrap().breakdowns
+        );
+    }
+}
@@ -2,18 +2,26 @@ use std::{
     fs::{read_to_string, OpenOptions},
     io::Write,
     iter::zip,
+    ops::Add,
     path::{Path, PathBuf},
 };
 
 use clap::Parser;
-use rand::thread_rng;
+use generic_array::ArrayLength;
+use rand::{rngs::ThreadRng, thread_rng};
+use typenum::{Sum, U16};
 
 use crate::{
     cli::playbook::{BreakdownKey, InputSource, Timestamp, TriggerValue},
     config::{KeyRegistries, NetworkConfig},
     error::BoxError,
+    ff::{
+        boolean_array::BooleanArray, fixed_point::FixedPointTriggerValue, Serializable,
+        U128Conversions,
+    },
+    hpke::{KeyRegistry, PublicKeyOnly},
     report::{OprfReport, DEFAULT_KEY_ID},
-    secret_sharing::IntoShares,
+    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, IntoShares},
     test_fixture::ipa::TestRawDataRecord,
 };
 
@@ -32,6 +40,10 @@ pub struct EncryptArgs {
     /// Path to helper network configuration file
     #[arg(long)]
     network: PathBuf,
+    /// Encrypt 16-bit trigger values, for queries with a `trigger_value_scale`. Trigger values
+    /// in the input file are counted in units of that scale, e.g. cents.
+    #[arg(long)]
+    fixed_point: bool,
 }
 
 impl EncryptArgs {
@@ -41,9 +53,17 @@ impl EncryptArgs {
             input_file: input_file.to_path_buf(),
             output_dir: output_dir.to_path_buf(),
             network: network.to_path_buf(),
+            fixed_point: false,
         }
     }
 
+    /// Encrypts fixed-point trigger values, see [`FixedPointTriggerValue`].
+    #[must_use]
+    pub fn with_fixed_point(mut self) -> Self {
+        self.fixed_point = true;
+        self
+    }
+
     /// # Panics
     /// if input file or network file are not correctly formatted
     /// # Errors
@@ -68,8 +88,50 @@ impl EncryptArgs {
             panic!("could not load network file")
         };
 
-        let shares: [Vec<OprfReport<BreakdownKey, TriggerValue, Timestamp>>; 3] =
-            input.iter::<TestRawDataRecord>().share();
+        if self.fixed_point {
+            self.write_encrypted::<BreakdownKey, FixedPointTriggerValue, Timestamp>(
+                input,
+                key_registries,
+                &mut rng,
+            )
+        } else {
+            self.write_encrypted::<BreakdownKey, TriggerValue, Timestamp>(
+                input,
+                key_registries,
+                &mut rng,
+            )
+        }
+    }
+
+    fn write_encrypted<BK, TV, TS>(
+        &self,
+        input: InputSource,
+        key_registries: [&KeyRegistry<PublicKeyOnly>; 3],
+        rng: &mut ThreadRng,
+    ) -> Result<(), BoxError>
+    where
+        BK: BooleanArray + U128Conversions + IntoShares<Replicated<BK>>,
+        TV: BooleanArray + U128Conversions + IntoShares<Replicated<TV>>,
+        TS: BooleanArray + U128Conversions + IntoShares<Replicated<TS>>,
+        Replicated<BK>: Serializable,
+        Replicated<TV>: Serializable,
+        Replicated<TS>: Serializable,
+        <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+            Add<<Replicated<TS> as Serializable>::Size>,
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >: Add<U16>,
+        Sum<
+            Sum<
+                Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+                <Replicated<TS> as Serializable>::Size,
+            >,
+            U16,
+        >: ArrayLength,
+    {
+        let shares: [Vec<OprfReport<BK, TV, TS>>; 3] = input.iter::<TestRawDataRecord>().share();
 
         for (index, (shares, key_registry)) in zip(shares, key_registries).enumerate() {
             let output_filename = format!("helper{}.enc", index + 1);
@@ -80,9 +142,7 @@ impl EncryptArgs {
                 .unwrap_or_else(|e| panic!("unable write to {}. {}", &output_filename, e));
 
             for share in shares {
-                let output = share
-                    .encrypt(DEFAULT_KEY_ID, key_registry, &mut rng)
-                    .unwrap();
+                let output = share.encrypt(DEFAULT_KEY_ID, key_registry, rng).unwrap();
                 let hex_output = hex::encode(&output);
                 writeln!(writer, "{hex_output}")?;
             }
@@ -94,7 +154,7 @@ impl EncryptArgs {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use std::{io::Write, sync::Arc};
+    use std::{io::Write, num::NonZeroU32, sync::Arc};
 
     use hpke::Deserializable;
     use tempfile::{tempdir, NamedTempFile};
@@ -104,7 +164,11 @@ mod tests {
             crypto::{encrypt::EncryptArgs, sample_data},
             CsvSerializer,
         },
-        ff::{boolean_array::BA16, U128Conversions},
+        ff::{
+            boolean_array::BA16,
+            fixed_point::{AttributionWeight, FixedPointScale},
+            U128Conversions,
+        },
         helpers::query::{IpaQueryConfig, QuerySize},
         hpke::{IpaPrivateKey, KeyRegistry, PrivateKeyOnly},
         query::OprfIpaQuery,
@@ -112,40 +176,13 @@ mod tests {
         test_fixture::{ipa::TestRawDataRecord, join3v, Reconstruct, TestWorld},
     };
 
-    #[tokio::test]
-    async fn encrypt_and_execute_query() {
-        const EXPECTED: &[u128] = &[0, 2, 5];
-
-        let records = vec![
-            TestRawDataRecord {
-                timestamp: 0,
-                user_id: 12345,
-                is_trigger_report: false,
-                breakdown_key: 2,
-                trigger_value: 0,
-            },
-            TestRawDataRecord {
-                timestamp: 4,
-                user_id: 68362,
-                is_trigger_report: false,
-                breakdown_key: 1,
-                trigger_value: 0,
-            },
-            TestRawDataRecord {
-                timestamp: 10,
-                user_id: 12345,
-                is_trigger_report: true,
-                breakdown_key: 0,
-                trigger_value: 5,
-            },
-            TestRawDataRecord {
-                timestamp: 12,
-                user_id: 68362,
-                is_trigger_report: true,
-                breakdown_key: 0,
-                trigger_value: 2,
-            },
-        ];
+    /// Encrypts `records` with the `encrypt` command, runs an IPA query over the encrypted
+    /// reports and returns the reconstructed histogram.
+    async fn encrypt_and_execute(
+        records: Vec<TestRawDataRecord>,
+        fixed_point: bool,
+        query_config: IpaQueryConfig,
+    ) -> Vec<u128> {
         let query_size = QuerySize::try_from(records.len()).unwrap();
         let mut input_file = NamedTempFile::new().unwrap();
 
@@ -158,9 +195,11 @@ mod tests {
         let output_dir = tempdir().unwrap();
         let network_file = sample_data::test_keys().network_config();
 
-        EncryptArgs::new(input_file.path(), output_dir.path(), network_file.path())
-            .encrypt()
-            .unwrap();
+        let mut args = EncryptArgs::new(input_file.path(), output_dir.path(), network_file.path());
+        if fixed_point {
+            args = args.with_fixed_point();
+        }
+        args.encrypt().unwrap();
 
         let files = [
             &output_dir.path().join("helper1.enc"),
@@ -187,12 +226,6 @@ mod tests {
                     let mk_private_key = hex::decode(mk_private_key)
                         .map(|bytes| IpaPrivateKey::from_bytes(&bytes).unwrap())
                         .unwrap();
-                    let query_config = IpaQueryConfig {
-                        max_breakdown_key: 3,
-                        with_dp: 0,
-                        epsilon: 1.0,
-                        ..Default::default()
-                    };
 
                     OprfIpaQuery::<_, BA16, _>::new(
                         query_config,
@@ -203,13 +236,108 @@ mod tests {
         )
         .await;
 
-        assert_eq!(
-            results.reconstruct()[0..3]
-                .iter()
-                .map(U128Conversions::as_u128)
-                .collect::<Vec<u128>>(),
-            EXPECTED
-        );
+        results
+            .reconstruct()
+            .iter()
+            .map(U128Conversions::as_u128)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn encrypt_and_execute_query() {
+        const EXPECTED: &[u128] = &[0, 2, 5];
+
+        let records = vec![
+            TestRawDataRecord {
+                timestamp: 0,
+                user_id: 12345,
+                is_trigger_report: false,
+                breakdown_key: 2,
+                trigger_value: 0,
+            },
+            TestRawDataRecord {
+                timestamp: 4,
+                user_id: 68362,
+                is_trigger_report: false,
+                breakdown_key: 1,
+                trigger_value: 0,
+            },
+            TestRawDataRecord {
+                timestamp: 10,
+                user_id: 12345,
+                is_trigger_report: true,
+                breakdown_key: 0,
+                trigger_value: 5,
+            },
+            TestRawDataRecord {
+                timestamp: 12,
+                user_id: 68362,
+                is_trigger_report: true,
+                breakdown_key: 0,
+                trigger_value: 2,
+            },
+        ];
+        let query_config = IpaQueryConfig {
+            max_breakdown_key: 3,
+            with_dp: 0,
+            epsilon: 1.0,
+            ..Default::default()
+        };
+
+        #[allow(clippy::large_futures)]
+        let results = encrypt_and_execute(records, false, query_config).await;
+        assert_eq!(&results[0..3], EXPECTED);
+    }
+
+    #[tokio::test]
+    async fn encrypt_and_execute_fixed_point_query() {
+        // Trigger values are in cents. 0.4 is rounded to a weight of 102/256.
+        const CAP: u32 = 10_000;
+        let scale = FixedPointScale::new(NonZeroU32::new(100).unwrap());
+        let weight = AttributionWeight::new(0.4).unwrap();
+
+        let source = |user_id, breakdown_key| TestRawDataRecord {
+            timestamp: 0,
+            user_id,
+            is_trigger_report: false,
+            breakdown_key,
+            trigger_value: 0,
+        };
+        let trigger = |user_id, dollars| TestRawDataRecord {
+            timestamp: 10,
+            user_id,
+            is_trigger_report: true,
+            breakdown_key: 0,
+            trigger_value: scale.quantize(dollars).unwrap(),
+        };
+        let records = vec![
+            source(12345, 2),
+            source(68362, 1),
+            source(31337, 1),
+            trigger(12345, 12.37),
+            trigger(68362, 5.0),
+            // weighted to 119.53, which is over the cap of 100.00
+            trigger(31337, 300.0),
+        ];
+        let query_config = IpaQueryConfig {
+            per_user_credit_cap: CAP,
+            max_breakdown_key: 3,
+            with_dp: 0,
+            epsilon: 1.0,
+            trigger_value_scale: Some(scale),
+            attribution_weights: Some(weight.into()),
+            ..Default::default()
+        };
+
+        #[allow(clippy::large_futures)]
+        let results = encrypt_and_execute(records, true, query_config).await;
+
+        // 1237 * 102 / 256 = 492.86, 500 * 102 / 256 = 199.22
+        assert_eq!(weight.apply(1237), 493);
+        assert_eq!(weight.apply(500), 199);
+        assert_eq!(weight.apply(30_000).min(CAP), CAP);
+        assert_eq!(&results[0..3], &[0, 10_199, 493]);
+        assert!((scale.to_decimal(10_199) - 101.99).abs() < f64::EPSILON);
     }
 
     #[test]
@@ -244,7 +372,7 @@ this is not toml!
     }
 
     #[test]
-    #[should_panic = "invalid length 2, expected an array of length 3"]
+    #[should_panic = "invalid network configuration: expected 3 peers, found 2"]
     fn encrypt_incomplete_network_file() {
         let input_file = sample_data::write_csv(sample_data::test_ipa_data().take(10)).unwrap();
 
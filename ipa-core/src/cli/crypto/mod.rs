@@ -1,8 +1,10 @@
 mod decrypt;
 mod encrypt;
+mod test_vectors;
 
 pub use decrypt::DecryptArgs;
 pub use encrypt::EncryptArgs;
+pub use test_vectors::TestVectorArgs;
 
 #[cfg(test)]
 mod sample_data {
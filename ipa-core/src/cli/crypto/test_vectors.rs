@@ -0,0 +1,77 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use clap::Parser;
+
+use crate::{
+    error::BoxError,
+    report::test_vectors::{TestVectors, DEFAULT_SEED},
+};
+
+/// Generates encrypted report test vectors for other client implementations, or checks that
+/// a file of them decrypts as expected. See `ipa-core/src/report/test_vectors/README.md`.
+#[derive(Debug, Parser)]
+#[clap(name = "test_vectors", about = "Encrypted report test vectors")]
+#[command(about)]
+pub struct TestVectorArgs {
+    /// Where to write the generated vectors. If not set, they are written to stdout.
+    #[arg(long, value_name = "FILE", conflicts_with = "verify")]
+    output: Option<PathBuf>,
+    /// Seed that all the keys, shares and ciphertexts are derived from.
+    #[arg(long, default_value_t = DEFAULT_SEED)]
+    seed: u64,
+    /// Verify the vectors in this file instead of generating new ones.
+    #[arg(long, value_name = "FILE")]
+    verify: Option<PathBuf>,
+}
+
+impl TestVectorArgs {
+    /// # Errors
+    /// If the output cannot be written, or the vectors to verify cannot be read or do not
+    /// decrypt to the values in them.
+    pub fn run(&self) -> Result<(), BoxError> {
+        if let Some(path) = &self.verify {
+            TestVectors::from_json(&fs::read_to_string(path)?)?.verify()?;
+            println!("{}: OK", path.display());
+            return Ok(());
+        }
+
+        let json = TestVectors::generate(self.seed).to_json();
+        match &self.output {
+            Some(path) => fs::write(path, json)?,
+            None => std::io::stdout().write_all(json.as_bytes())?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::TestVectorArgs;
+    use crate::report::test_vectors::{DEFAULT_SEED, V1};
+
+    #[test]
+    fn generates_and_verifies() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("v1.json");
+
+        TestVectorArgs {
+            output: Some(path.clone()),
+            seed: DEFAULT_SEED,
+            verify: None,
+        }
+        .run()
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), V1);
+
+        TestVectorArgs {
+            output: None,
+            seed: DEFAULT_SEED,
+            verify: Some(path),
+        }
+        .run()
+        .unwrap();
+    }
+}
@@ -1,8 +1,77 @@
-use std::time::Duration;
+//! Results of IPA queries, as the report collector writes them.
+//!
+//! [`QueryResult`] is what the helpers computed, with the breakdown keys as indices into
+//! `breakdowns`, or with one entry per breakdown key and value bucket at
+//! [`ValueBuckets::histogram_index`] for queries with `value_buckets`. [`ResultExport`] is the same result in a documented format for other tools to
+//! consume, with the breakdown keys labeled from a [`BreakdownLabels`] file if there is one.
+//!
+//! ## Breakdown labels
+//!
+//! A JSON object from breakdown key to its label, with optional string metadata:
+//!
+//! ```json
+//! {
+//!   "0": { "name": "summer-sale" },
+//!   "1": { "name": "back-to-school", "metadata": { "campaign_id": "1234", "channel": "video" } }
+//! }
+//! ```
+//!
+//! Breakdown keys without a label are exported without one.
+//!
+//! ## JSON export
+//!
+//! | Field            | Type             | Description                                        |
+//! |------------------|------------------|----------------------------------------------------|
+//! | `schema_version` | number           | [`ResultExport::SCHEMA_VERSION`]                   |
+//! | `input_size`     | number           | Number of reports in the query                     |
+//! | `latency`        | number           | Seconds from submitting the query to its results   |
+//! | `config`         | object           | The [`IpaQueryConfig`] of the query                |
+//! | `dp`             | object or `null` | [`DpParameters`], `null` if no noise was added     |
+//! | `breakdowns`     | array            | One [`BreakdownValue`] per breakdown key, in order |
+//!
+//! With `value_buckets`, `breakdowns` has one entry per breakdown key and value bucket, ordered
+//! by breakdown key and then by bucket. Each breakdown has the fields:
+//!
+//! | Field            | Type             | Description                                         |
+//! |------------------|------------------|-----------------------------------------------------|
+//! | `breakdown_key`  | number           | The breakdown key                                   |
+//! | `bucket`         | number, optional | Lower bound of the value bucket, with value buckets |
+//! | `label`          | string, optional | Label of the breakdown key                          |
+//! | `metadata`       | object, optional | Metadata of the label of the breakdown key          |
+//! | `value`          | number           | Sum of the attributed trigger values, or number of  |
+//! |                  |                  | attributed conversions in the bucket                |
+//! | `scaled_value`   | number, optional | `value` converted back to the `trigger_value_scale` |
+//!
+//! With DP, noise can make values negative. For queries with a `trigger_value_scale`, `value` and
+//! `bucket` are counted in units of the scale, and `scaled_value` is the value converted back to
+//! the scale, e.g. dollars for a scale of 100 cents. Counts of conversions in value buckets have
+//! no `scaled_value`.
+//!
+//! ## CSV export
+//!
+//! A header row and then one row per breakdown, with the columns `breakdown_key`, `bucket` for
+//! queries with `value_buckets`, `label`, `value`, `scaled_value` for queries with a
+//! `trigger_value_scale` and no `value_buckets`, one column per metadata key that any label uses
+//! (sorted), then `input_size`, `latency`, `per_user_credit_cap`,
+//! `max_breakdown_key`, `attribution_window_seconds`, `dp_mechanism`, `epsilon` and `delta`. The
+//! query columns repeat on every row, so that the file can be loaded as a single table. Empty
+//! cells stand for missing values.
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::helpers::query::{IpaQueryConfig, QuerySize};
+use crate::{
+    cli::CsvSerializer,
+    helpers::query::{IpaQueryConfig, QuerySize},
+    protocol::{dp::NoiseParams, ipa_prf::ValueBuckets},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QueryResult {
@@ -15,3 +84,492 @@ pub struct QueryResult {
     pub latency: Duration,
     pub breakdowns: Vec<u32>,
 }
+
+#[derive(thiserror::Error, Debug)]
+pub enum LabelError {
+    #[error("failed to read breakdown labels from {path}: {message}")]
+    Read { path: String, message: String },
+    #[error(
+        "breakdown key {key} has a label, but the query has {max_breakdown_key} breakdown keys"
+    )]
+    UnknownBreakdownKey { key: u32, max_breakdown_key: u32 },
+}
+
+/// Names and metadata of breakdown keys, e.g. the campaigns that they stand for.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BreakdownLabels(pub BTreeMap<u32, BreakdownLabel>);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BreakdownLabel {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl BreakdownLabels {
+    /// Reads the labels from a JSON file.
+    ///
+    /// ## Errors
+    /// If the file cannot be read or is not valid.
+    pub fn from_file(path: &Path) -> Result<Self, LabelError> {
+        let read_error = |message: String| LabelError::Read {
+            path: path.display().to_string(),
+            message,
+        };
+        let contents = fs::read_to_string(path).map_err(|e| read_error(e.to_string()))?;
+        serde_json::from_str(&contents).map_err(|e| read_error(e.to_string()))
+    }
+
+    /// Checks that all the labeled breakdown keys are smaller than `max_breakdown_key`.
+    ///
+    /// ## Errors
+    /// If a label is for a breakdown key that queries with `max_breakdown_key` do not have.
+    pub fn check(&self, max_breakdown_key: u32) -> Result<(), LabelError> {
+        match self.0.keys().find(|&&key| key >= max_breakdown_key) {
+            Some(&key) => Err(LabelError::UnknownBreakdownKey {
+                key,
+                max_breakdown_key,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Privacy parameters of the noise that the helpers added to the results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DpParameters {
+    pub mechanism: DpNoise,
+    pub epsilon: f64,
+    pub delta: f64,
+    pub per_user_credit_cap: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DpNoise {
+    TruncatedDiscreteLaplace,
+}
+
+impl DpNoise {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::TruncatedDiscreteLaplace => "truncated_discrete_laplace",
+        }
+    }
+}
+
+impl DpParameters {
+    /// The noise that the helpers add for `config`, see `query::runner::oprf_ipa`.
+    #[must_use]
+    pub fn for_query(config: &IpaQueryConfig) -> Option<Self> {
+        (config.with_dp != 0).then(|| Self {
+            mechanism: DpNoise::TruncatedDiscreteLaplace,
+            epsilon: config.epsilon,
+            delta: NoiseParams::default().delta,
+            per_user_credit_cap: config.per_user_credit_cap,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BreakdownValue {
+    pub breakdown_key: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    pub value: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scaled_value: Option<f64>,
+}
+
+/// A [`QueryResult`] in the format described in the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResultExport {
+    pub schema_version: u32,
+    pub input_size: QuerySize,
+    #[serde(
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs"
+    )]
+    pub latency: Duration,
+    pub config: IpaQueryConfig,
+    pub dp: Option<DpParameters>,
+    pub breakdowns: Vec<BreakdownValue>,
+}
+
+impl ResultExport {
+    pub const SCHEMA_VERSION: u32 = 2;
+
+    /// Exports `result`, labeling its breakdown keys with `labels`.
+    ///
+    /// ## Errors
+    /// If `labels` has a label for a breakdown key that is not in the result.
+    pub fn new(result: &QueryResult, labels: Option<&BreakdownLabels>) -> Result<Self, LabelError> {
+        if let Some(labels) = labels {
+            labels.check(result.config.max_breakdown_key)?;
+        }
+
+        let dp = DpParameters::for_query(&result.config);
+        let buckets = result.config.value_buckets;
+        let breakdowns = result
+            .breakdowns
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| {
+                let (breakdown_key, bucket) = breakdown_and_bucket(i, buckets.as_ref());
+                let label = labels.and_then(|labels| labels.0.get(&breakdown_key));
+                let value = if dp.is_some() {
+                    noisy_value(value)
+                } else {
+                    i64::from(value)
+                };
+                BreakdownValue {
+                    breakdown_key,
+                    bucket,
+                    label: label.map(|label| label.name.clone()),
+                    metadata: label
+                        .map(|label| label.metadata.clone())
+                        .unwrap_or_default(),
+                    value,
+                    scaled_value: result
+                        .config
+                        .trigger_value_scale
+                        .filter(|_| buckets.is_none())
+                        .map(|scale| scale.to_decimal(value)),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            schema_version: Self::SCHEMA_VERSION,
+            input_size: result.input_size,
+            latency: result.latency,
+            config: result.config,
+            dp,
+            breakdowns,
+        })
+    }
+
+    /// Writes the export to `path`, as CSV if it has the `.csv` extension and as JSON otherwise.
+    ///
+    /// ## Errors
+    /// If the file cannot be written.
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        let mut file = fs::File::create(path)?;
+        if path.extension().is_some_and(|ext| ext == "csv") {
+            self.to_csv(&mut file)
+        } else {
+            writeln!(file, "{}", serde_json::to_string_pretty(self)?)
+        }
+    }
+}
+
+impl CsvSerializer for ResultExport {
+    fn to_csv<W: Write>(&self, buf: &mut W) -> io::Result<()> {
+        let metadata_keys = self
+            .breakdowns
+            .iter()
+            .flat_map(|breakdown| breakdown.metadata.keys())
+            .collect::<BTreeSet<_>>();
+
+        let bucketed = self.config.value_buckets.is_some();
+        let scaled = self.config.trigger_value_scale.is_some() && !bucketed;
+
+        write!(buf, "breakdown_key")?;
+        if bucketed {
+            write!(buf, ",bucket")?;
+        }
+        write!(buf, ",label,value")?;
+        if scaled {
+            write!(buf, ",scaled_value")?;
+        }
+        for key in &metadata_keys {
+            write!(buf, ",{}", csv_field(key))?;
+        }
+        writeln!(
+            buf,
+            ",input_size,latency,per_user_credit_cap,max_breakdown_key,\
+             attribution_window_seconds,dp_mechanism,epsilon,delta"
+        )?;
+
+        let config = &self.config;
+        let query_columns = format!(
+            "{},{},{},{},{},{},{},{}",
+            usize::from(self.input_size),
+            self.latency.as_secs_f64(),
+            config.per_user_credit_cap,
+            config.max_breakdown_key,
+            config
+                .attribution_window_seconds
+                .map(|seconds| seconds.to_string())
+                .unwrap_or_default(),
+            self.dp.as_ref().map_or("", |dp| dp.mechanism.as_str()),
+            self.dp
+                .as_ref()
+                .map(|dp| dp.epsilon.to_string())
+                .unwrap_or_default(),
+            self.dp
+                .as_ref()
+                .map(|dp| dp.delta.to_string())
+                .unwrap_or_default(),
+        );
+
+        for breakdown in &self.breakdowns {
+            write!(buf, "{}", breakdown.breakdown_key)?;
+            if bucketed {
+                let bucket = breakdown.bucket.map(|b| b.to_string());
+                write!(buf, ",{}", bucket.unwrap_or_default())?;
+            }
+            write!(
+                buf,
+                ",{},{}",
+                csv_field(breakdown.label.as_deref().unwrap_or_default()),
+                breakdown.value
+            )?;
+            if scaled {
+                let scaled_value = breakdown.scaled_value.map(|v| v.to_string());
+                write!(buf, ",{}", scaled_value.unwrap_or_default())?;
+            }
+            for key in &metadata_keys {
+                let value = breakdown.metadata.get(*key).map_or("", String::as_str);
+                write!(buf, ",{}", csv_field(value))?;
+            }
+            writeln!(buf, ",{query_columns}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The breakdown key and the lower bound of the value bucket of entry `i` of the result of a
+/// query with `buckets`, see [`ValueBuckets::histogram_index`].
+fn breakdown_and_bucket(i: usize, buckets: Option<&ValueBuckets>) -> (u32, Option<u32>) {
+    let (breakdown_key, bucket) = match buckets {
+        Some(buckets) => (
+            i / buckets.len(),
+            Some(buckets.lower_bounds()[i % buckets.len()]),
+        ),
+        None => (i, None),
+    };
+    (u32::try_from(breakdown_key).unwrap(), bucket)
+}
+
+/// The results are 32 bit values modulo 2^32, which noise can make wrap around zero.
+/// `validate_dp` in the playbook reads them the same way.
+#[allow(clippy::cast_possible_wrap)]
+fn noisy_value(value: u32) -> i64 {
+    i64::from(value as i32)
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break (RFC 4180).
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{collections::BTreeMap, num::NonZeroU32, time::Duration};
+
+    use super::{BreakdownLabel, BreakdownLabels, DpNoise, LabelError, QueryResult, ResultExport};
+    use crate::{
+        cli::CsvSerializer,
+        helpers::query::{IpaQueryConfig, QuerySize},
+    };
+
+    fn result(with_dp: u32) -> QueryResult {
+        QueryResult {
+            input_size: QuerySize::try_from(100_usize).unwrap(),
+            config: IpaQueryConfig {
+                with_dp,
+                epsilon: 3.0,
+                max_breakdown_key: 3,
+                attribution_window_seconds: NonZeroU32::new(86_400),
+                ..IpaQueryConfig::default()
+            },
+            latency: Duration::from_millis(1500),
+            breakdowns: vec![5, 0, u32::MAX],
+        }
+    }
+
+    fn labels() -> BreakdownLabels {
+        serde_json::from_str(
+            r#"{
+                "0": { "name": "summer-sale" },
+                "2": { "name": "back, to \"school\"", "metadata": { "campaign_id": "1234" } }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_labels() {
+        assert_eq!(
+            labels().0.get(&2),
+            Some(&BreakdownLabel {
+                name: "back, to \"school\"".to_string(),
+                metadata: BTreeMap::from([("campaign_id".to_string(), "1234".to_string())]),
+            })
+        );
+        assert!(serde_json::from_str::<BreakdownLabels>(r#"{ "zero": { "name": "a" } }"#).is_err());
+    }
+
+    #[test]
+    fn json() {
+        let export = ResultExport::new(&result(0), Some(&labels())).unwrap();
+        let json = serde_json::to_value(&export).unwrap();
+
+        assert_eq!(json["schema_version"], 2);
+        assert_eq!(json["input_size"], 100);
+        assert_eq!(json["latency"], 1.5);
+        assert_eq!(json["config"]["max_breakdown_key"], 3);
+        assert_eq!(json["dp"], serde_json::Value::Null);
+        assert_eq!(
+            json["breakdowns"],
+            serde_json::json!([
+                { "breakdown_key": 0, "label": "summer-sale", "value": 5 },
+                { "breakdown_key": 1, "value": 0 },
+                {
+                    "breakdown_key": 2,
+                    "label": "back, to \"school\"",
+                    "metadata": { "campaign_id": "1234" },
+                    "value": u32::MAX,
+                },
+            ])
+        );
+        assert_eq!(
+            serde_json::from_value::<ResultExport>(json).unwrap(),
+            export
+        );
+    }
+
+    #[test]
+    fn dp_values_are_signed() {
+        let export = ResultExport::new(&result(1), None).unwrap();
+
+        let dp = export.dp.unwrap();
+        assert_eq!(dp.mechanism, DpNoise::TruncatedDiscreteLaplace);
+        assert!((dp.epsilon - 3.0).abs() < f64::EPSILON);
+        assert_eq!(
+            export
+                .breakdowns
+                .iter()
+                .map(|breakdown| breakdown.value)
+                .collect::<Vec<_>>(),
+            vec![5, 0, -1]
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let export = ResultExport::new(&result(1), Some(&labels())).unwrap();
+        let mut buf = Vec::new();
+        export.to_csv(&mut buf).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "breakdown_key,label,value,campaign_id,input_size,latency,per_user_credit_cap,\
+             max_breakdown_key,attribution_window_seconds,dp_mechanism,epsilon,delta\n\
+             0,summer-sale,5,,100,1.5,8,3,86400,truncated_discrete_laplace,3,0.000001\n\
+             1,,0,,100,1.5,8,3,86400,truncated_discrete_laplace,3,0.000001\n\
+             2,\"back, to \"\"school\"\"\",-1,1234,100,1.5,8,3,86400,truncated_discrete_laplace,\
+             3,0.000001\n"
+        );
+    }
+
+    #[test]
+    fn fixed_point_values() {
+        let mut result = result(0);
+        result.config.trigger_value_scale = Some("100".parse().unwrap());
+        result.breakdowns = vec![1237, 0, 5];
+        let export = ResultExport::new(&result, None).unwrap();
+
+        let json = serde_json::to_value(&export).unwrap();
+        assert_eq!(json["config"]["trigger_value_scale"], 100);
+        assert_eq!(
+            json["breakdowns"][0],
+            serde_json::json!({ "breakdown_key": 0, "value": 1237, "scaled_value": 12.37 })
+        );
+
+        let mut buf = Vec::new();
+        export.to_csv(&mut buf).unwrap();
+        let csv = String::from_utf8(buf).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("breakdown_key,label,value,scaled_value,input_size"));
+        assert!(lines.next().unwrap().starts_with("0,,1237,12.37,100"));
+        assert!(lines.nth(1).unwrap().starts_with("2,,5,0.05,100"));
+    }
+
+    #[test]
+    fn value_buckets() {
+        let mut result = result(0);
+        result.config.max_breakdown_key = 2;
+        result.config.value_buckets = Some("10,50".parse().unwrap());
+        result.config.trigger_value_scale = Some("100".parse().unwrap());
+        result.breakdowns = vec![4, 2, 0, 1, 0, 3];
+        let labels = serde_json::from_str(
+            r#"{ "0": { "name": "summer-sale" }, "1": { "name": "back-to-school" } }"#,
+        )
+        .unwrap();
+        let export = ResultExport::new(&result, Some(&labels)).unwrap();
+
+        let json = serde_json::to_value(&export).unwrap();
+        assert_eq!(
+            json["breakdowns"],
+            serde_json::json!([
+                { "breakdown_key": 0, "bucket": 1, "label": "summer-sale", "value": 4 },
+                { "breakdown_key": 0, "bucket": 10, "label": "summer-sale", "value": 2 },
+                { "breakdown_key": 0, "bucket": 50, "label": "summer-sale", "value": 0 },
+                { "breakdown_key": 1, "bucket": 1, "label": "back-to-school", "value": 1 },
+                { "breakdown_key": 1, "bucket": 10, "label": "back-to-school", "value": 0 },
+                { "breakdown_key": 1, "bucket": 50, "label": "back-to-school", "value": 3 },
+            ])
+        );
+
+        let mut buf = Vec::new();
+        export.to_csv(&mut buf).unwrap();
+        let csv = String::from_utf8(buf).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("breakdown_key,bucket,label,value,input_size"));
+        assert!(lines.next().unwrap().starts_with("0,1,summer-sale,4,100"));
+        assert!(lines
+            .nth(4)
+            .unwrap()
+            .starts_with("1,50,back-to-school,3,100"));
+    }
+
+    #[test]
+    fn rejects_labels_for_unknown_breakdown_keys() {
+        let mut labels = labels();
+        labels.0.insert(
+            3,
+            BreakdownLabel {
+                name: "out of range".to_string(),
+                metadata: BTreeMap::new(),
+            },
+        );
+
+        assert!(matches!(
+            ResultExport::new(&result(0), Some(&labels)),
+            Err(LabelError::UnknownBreakdownKey {
+                key: 3,
+                max_breakdown_key: 3
+            })
+        ));
+    }
+}
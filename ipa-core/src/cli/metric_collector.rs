@@ -35,6 +35,14 @@ pub fn install_collector() -> CollectorHandle {
     CollectorHandle { snapshotter }
 }
 
+impl CollectorHandle {
+    /// Returns the metrics collected so far.
+    #[must_use]
+    pub fn metrics(&self) -> Metrics {
+        Metrics::from_snapshot(self.snapshotter.snapshot())
+    }
+}
+
 impl Drop for CollectorHandle {
     fn drop(&mut self) {
         if !thread::panicking() {
@@ -1,9 +1,15 @@
 #[cfg(feature = "web-app")]
+mod admin;
+#[cfg(all(feature = "test-fixture", feature = "web-app", feature = "cli"))]
+pub mod batch;
+#[cfg(feature = "web-app")]
 mod clientconf;
+#[cfg(feature = "parquet-io")]
+pub mod columnar;
 #[cfg(all(feature = "test-fixture", feature = "web-app", feature = "cli",))]
 pub mod crypto;
 mod csv;
-mod ipa_output;
+pub mod ipa_output;
 #[cfg(feature = "web-app")]
 mod keygen;
 mod metric_collector;
@@ -14,13 +20,15 @@ pub mod playbook;
 mod test_setup;
 mod verbosity;
 #[cfg(feature = "web-app")]
+pub use admin::{admin, AdminArgs};
+#[cfg(feature = "web-app")]
 pub use clientconf::{setup as client_config_setup, ConfGenArgs};
 pub use csv::Serializer as CsvSerializer;
-pub use ipa_output::QueryResult as IpaQueryResult;
+pub use ipa_output::{BreakdownLabels, QueryResult as IpaQueryResult, ResultExport};
 #[cfg(feature = "web-app")]
 pub use keygen::{keygen, KeygenArgs};
 pub use metric_collector::{install_collector, CollectorHandle};
 pub use paths::PathExt as CliPaths;
 #[cfg(feature = "web-app")]
 pub use test_setup::{test_setup, TestSetupArgs};
-pub use verbosity::Verbosity;
+pub use verbosity::{LoggingHandle, Verbosity};
@@ -1,34 +1,34 @@
 #![cfg(all(feature = "web-app", feature = "cli"))]
 use std::{
-    cmp::min,
     iter::zip,
+    ops::Add,
     time::{Duration, Instant},
 };
 
-use futures_util::future::try_join_all;
-use generic_array::GenericArray;
+use generic_array::{ArrayLength, GenericArray};
 use rand::rngs::StdRng;
 use rand_core::SeedableRng;
-use tokio::time::sleep;
-use typenum::Unsigned;
+use typenum::{Sum, Unsigned, U16};
 
 use crate::{
     cli::{
         playbook::{BreakdownKey, Timestamp, TriggerValue},
         IpaQueryResult,
     },
-    ff::{Serializable, U128Conversions},
+    ff::{
+        boolean_array::BooleanArray, fixed_point::FixedPointTriggerValue, Serializable,
+        U128Conversions,
+    },
     helpers::{
-        query::{IpaQueryConfig, QueryInput, QuerySize},
+        query::{IpaQueryConfig, QueryConfig, QuerySize},
         BodyStream,
     },
     hpke::PublicKeyRegistry,
-    net::MpcHelperClient,
-    protocol::{ipa_prf::OPRFIPAInputRow, QueryId},
-    query::QueryStatus,
+    protocol::ipa_prf::OPRFIPAInputRow,
     report::{KeyIdentifier, OprfReport},
+    report_collector::ReportCollector,
     secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares, SharedValue},
-    test_fixture::{ipa::TestRawDataRecord, Reconstruct},
+    test_fixture::ipa::TestRawDataRecord,
 };
 
 /// Executes the IPA v3 protocol.
@@ -37,28 +37,88 @@ use crate::{
 /// If report encryption fails
 pub async fn playbook_oprf_ipa<HV, KR>(
     records: Vec<TestRawDataRecord>,
-    clients: &[MpcHelperClient; 3],
-    query_id: QueryId,
-    query_config: IpaQueryConfig,
+    collector: &ReportCollector,
+    query_config: QueryConfig,
+    ipa_query_config: IpaQueryConfig,
     encryption: Option<(KeyIdentifier, [&KR; 3])>,
 ) -> IpaQueryResult
 where
     HV: SharedValue + U128Conversions,
     AdditiveShare<HV>: Serializable,
     KR: PublicKeyRegistry,
+{
+    let query_size = records.len();
+    let buffers = if ipa_query_config.trigger_value_scale.is_some() {
+        encode_inputs::<BreakdownKey, FixedPointTriggerValue, Timestamp, KR>(
+            &records,
+            &ipa_query_config,
+            encryption,
+        )
+    } else {
+        encode_inputs::<BreakdownKey, TriggerValue, Timestamp, KR>(
+            &records,
+            &ipa_query_config,
+            encryption,
+        )
+    };
+
+    let inputs = buffers.map(BodyStream::from);
+    tracing::info!("Starting query for OPRF");
+
+    run_query_and_validate::<HV>(
+        inputs,
+        query_size,
+        collector,
+        query_config,
+        ipa_query_config,
+    )
+    .await
+}
+
+/// Secret-shares `records` for each helper, with trigger values of type `TV`, either as
+/// encrypted reports or, with `plaintext_match_keys`, as input rows.
+fn encode_inputs<BK, TV, TS, KR>(
+    records: &[TestRawDataRecord],
+    ipa_query_config: &IpaQueryConfig,
+    encryption: Option<(KeyIdentifier, [&KR; 3])>,
+) -> [Vec<u8>; 3]
+where
+    BK: BooleanArray + U128Conversions + IntoShares<AdditiveShare<BK>>,
+    TV: BooleanArray + U128Conversions + IntoShares<AdditiveShare<TV>>,
+    TS: BooleanArray + U128Conversions + IntoShares<AdditiveShare<TS>>,
+    KR: PublicKeyRegistry,
+    OPRFIPAInputRow<BK, TV, TS>: Serializable,
+    AdditiveShare<BK>: Serializable,
+    AdditiveShare<TV>: Serializable,
+    AdditiveShare<TS>: Serializable,
+    <AdditiveShare<BK> as Serializable>::Size: Add<<AdditiveShare<TV> as Serializable>::Size>,
+    Sum<<AdditiveShare<BK> as Serializable>::Size, <AdditiveShare<TV> as Serializable>::Size>:
+        Add<<AdditiveShare<TS> as Serializable>::Size>,
+    Sum<
+        Sum<<AdditiveShare<BK> as Serializable>::Size, <AdditiveShare<TV> as Serializable>::Size>,
+        <AdditiveShare<TS> as Serializable>::Size,
+    >: Add<U16>,
+    Sum<
+        Sum<
+            Sum<
+                <AdditiveShare<BK> as Serializable>::Size,
+                <AdditiveShare<TV> as Serializable>::Size,
+            >,
+            <AdditiveShare<TS> as Serializable>::Size,
+        >,
+        U16,
+    >: ArrayLength,
 {
     let mut buffers: [_; 3] = std::array::from_fn(|_| Vec::new());
     let query_size = records.len();
 
-    if query_config.plaintext_match_keys {
-        let sz =
-            <OPRFIPAInputRow<BreakdownKey, TriggerValue, Timestamp> as Serializable>::Size::USIZE;
+    if ipa_query_config.plaintext_match_keys {
+        let sz = <OPRFIPAInputRow<BK, TV, TS> as Serializable>::Size::USIZE;
         for buffer in &mut buffers {
             buffer.resize(query_size * sz, 0u8);
         }
 
-        let shares: [Vec<OPRFIPAInputRow<BreakdownKey, TriggerValue, Timestamp>>; 3] =
-            records.iter().cloned().share();
+        let shares: [Vec<OPRFIPAInputRow<BK, TV, TS>>; 3] = records.iter().cloned().share();
 
         zip(&mut buffers, shares).for_each(|(buf, shares)| {
             for (share, chunk) in zip(shares, buf.chunks_mut(sz)) {
@@ -72,8 +132,7 @@ where
         }
 
         let mut rng = StdRng::from_entropy();
-        let shares: [Vec<OprfReport<BreakdownKey, TriggerValue, Timestamp>>; 3] =
-            records.iter().cloned().share();
+        let shares: [Vec<OprfReport<BK, TV, TS>>; 3] = records.iter().cloned().share();
         zip(&mut buffers, shares)
             .zip(key_registries)
             .for_each(|((buf, shares), key_registry)| {
@@ -89,98 +148,77 @@ where
         )
     }
 
-    let inputs = buffers.map(BodyStream::from);
-    tracing::info!("Starting query for OPRF");
-
-    run_query_and_validate::<HV>(inputs, query_size, clients, query_id, query_config).await
+    buffers
 }
 
 /// # Panics
-/// if results are invalid
-#[allow(clippy::disallowed_methods)] // allow try_join_all
+/// if the query fails or its results are invalid
 pub async fn run_query_and_validate<HV>(
     inputs: [BodyStream; 3],
     query_size: usize,
-    clients: &[MpcHelperClient; 3],
-    query_id: QueryId,
-    query_config: IpaQueryConfig,
+    collector: &ReportCollector,
+    query_config: QueryConfig,
+    ipa_query_config: IpaQueryConfig,
 ) -> IpaQueryResult
 where
     HV: SharedValue + U128Conversions,
     AdditiveShare<HV>: Serializable,
 {
     let mpc_time = Instant::now();
-    try_join_all(
-        inputs
-            .into_iter()
-            .zip(clients)
-            .map(|(input_stream, client)| {
-                client.query_input(QueryInput {
-                    query_id,
-                    input_stream,
-                })
-            }),
-    )
-    .await
-    .unwrap();
-
-    let mut delay = Duration::from_millis(125);
-    loop {
-        if try_join_all(clients.iter().map(|client| client.query_status(query_id)))
-            .await
-            .unwrap()
-            .into_iter()
-            .all(|status| status == QueryStatus::Completed)
-        {
-            break;
-        }
-
-        sleep(delay).await;
-        delay = min(Duration::from_secs(5), delay * 2);
-        // TODO: Add a timeout of some sort. Possibly, add some sort of progress indicator to
-        // the status API so we can check whether the query is making progress.
-    }
-
-    // wait until helpers have processed the query and get the results from them
-    let results: [_; 3] = try_join_all(clients.iter().map(|client| client.query_results(query_id)))
-        .await
-        .unwrap()
-        .try_into()
-        .unwrap();
-
-    let results: Vec<HV> = results
-        .map(|bytes| {
-            AdditiveShare::<HV>::from_byte_slice(&bytes)
-                .collect::<Result<Vec<_>, _>>()
-                .unwrap()
-        })
-        .reconstruct();
+    let query = collector.submit(query_config, inputs).await.unwrap();
+    // TODO: Add a timeout of some sort. Possibly, add some sort of progress indicator to
+    // the status API so we can check whether the query is making progress.
+    let results: Vec<HV> = query.results().await.unwrap();
 
     let lat = mpc_time.elapsed();
 
     tracing::info!("Running IPA for {query_size:?} records took {t:?}", t = lat);
-    let mut breakdowns = vec![0; usize::try_from(query_config.max_breakdown_key).unwrap()];
+    query_result(results, query_size, lat, ipa_query_config)
+}
+
+/// Sums the histogram that the helpers returned for a query into one value per breakdown key,
+/// dropping the buckets past `max_breakdown_key`. For queries with value buckets, there is one
+/// value per (breakdown key, value bucket) pair instead.
+///
+/// # Panics
+/// If a bucket past `max_breakdown_key` has a non-zero value in a query without DP noise.
+#[must_use]
+pub fn query_result<HV>(
+    results: Vec<HV>,
+    query_size: usize,
+    latency: Duration,
+    ipa_query_config: IpaQueryConfig,
+) -> IpaQueryResult
+where
+    HV: SharedValue + U128Conversions,
+{
+    let max_breakdown_key = usize::try_from(ipa_query_config.max_breakdown_key).unwrap();
+    let bins = ipa_query_config
+        .value_buckets
+        .map_or(max_breakdown_key, |buckets| {
+            buckets.histogram_index(max_breakdown_key, 0)
+        });
+    let mut breakdowns = vec![0; bins];
     for (breakdown_key, trigger_value) in results.into_iter().enumerate() {
         // TODO: make the data type used consistent with `ipa_in_the_clear`
         // I think using u32 is wrong, we should move to u128
-        if query_config.with_dp == 0 {
+        if ipa_query_config.with_dp == 0 {
             // otherwise if DP is added trigger_values will not be zero due to noise
             assert!(
-                breakdown_key < query_config.max_breakdown_key.try_into().unwrap()
-                    || trigger_value == HV::ZERO,
+                breakdown_key < bins || trigger_value == HV::ZERO,
                 "trigger values were attributed to buckets more than max breakdown key"
             );
         }
 
-        if breakdown_key < query_config.max_breakdown_key.try_into().unwrap() {
+        if breakdown_key < bins {
             breakdowns[breakdown_key] += u32::try_from(trigger_value.as_u128()).unwrap();
         }
     }
 
     IpaQueryResult {
         input_size: QuerySize::try_from(query_size).unwrap(),
-        config: query_config,
-        latency: lat,
+        config: ipa_query_config,
+        latency,
         breakdowns,
     }
 }
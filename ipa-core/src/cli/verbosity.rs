@@ -10,6 +10,7 @@ use tracing_subscriber::{
 use crate::{
     cli::{install_collector, metric_collector::CollectorHandle},
     error::set_global_panic_hook,
+    telemetry::stats::Metrics,
 };
 
 #[derive(Debug, Parser)]
@@ -24,10 +25,18 @@ pub struct Verbosity {
 }
 
 pub struct LoggingHandle {
-    #[allow(dead_code)] // we care about handle's drop semantic so it is ok to not read it
     metrics_handle: Option<CollectorHandle>,
 }
 
+impl LoggingHandle {
+    /// Returns the metrics collected so far, or `None` if metrics are not collected because
+    /// output is silenced.
+    #[must_use]
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.metrics_handle.as_ref().map(CollectorHandle::metrics)
+    }
+}
+
 impl Verbosity {
     #[must_use]
     pub fn setup_logging(&self) -> LoggingHandle {
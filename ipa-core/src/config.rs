@@ -1,14 +1,17 @@
 use std::{
     array,
     borrow::{Borrow, Cow},
-    fmt::{Debug, Formatter},
+    fmt::{self, Debug, Display, Formatter},
     iter::Zip,
     path::PathBuf,
     slice,
     time::Duration,
 };
 
-use hyper::{http::uri::Scheme, Uri};
+use hyper::{
+    http::uri::{PathAndQuery, Scheme},
+    Uri,
+};
 use hyper_util::client::legacy::Builder;
 use rustls_pemfile::Item;
 use rustls_pki_types::{CertificateDer, PrivateKeyDer};
@@ -22,6 +25,10 @@ use crate::{
         Deserializable as _, IpaPrivateKey, IpaPublicKey, KeyRegistry, PrivateKeyOnly,
         PublicKeyOnly, Serializable as _,
     },
+    net::{
+        discovery::{self, PeerDiscovery},
+        ChannelClass,
+    },
 };
 
 pub type OwnedCertificate = CertificateDer<'static>;
@@ -35,43 +42,266 @@ pub enum Error {
     InvalidUri(#[from] hyper::http::uri::InvalidUri),
     #[error(transparent)]
     IOError(#[from] std::io::Error),
+    #[error("invalid network configuration: {0}")]
+    InvalidNetworkConfig(String),
+    #[error(transparent)]
+    Discovery(#[from] discovery::Error),
 }
 
 /// Configuration information describing a helper network.
 ///
 /// The most important thing this contains is discovery information for each of the participating
 /// helpers.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug)]
 pub struct NetworkConfig {
     /// Information about each helper participating in the network. The order that helpers are
     /// listed here determines their assigned helper identities in the network. Note that while the
     /// helper identities are stable, roles are assigned per query.
+    ///
+    /// If `discovery` is configured, this is the result of the most recent discovery.
     pub peers: [PeerConfig; 3],
 
     /// HTTP client configuration.
-    #[serde(default)]
     pub client: ClientConfig,
+
+    /// Dynamic discovery of the peers, used instead of listing them in `network.toml`.
+    pub discovery: Option<DiscoveryConfig>,
+
+    /// Root certificates used to verify peer TLS certificates.
+    ///
+    /// If this is empty (the default), each peer must pin its end-entity certificate in
+    /// [`PeerConfig::certificate`]. If root certificates are specified, peer certificates are
+    /// verified against them instead, and peers are identified by the name in
+    /// [`PeerConfig::identity`]. In `network.toml`, this is a PEM bundle that may contain several
+    /// certificates.
+    pub root_certificates: Vec<OwnedCertificate>,
+}
+
+/// The contents of `network.toml`. See [`NetworkConfig`] for the meaning of the fields.
+#[derive(Deserialize)]
+struct NetworkConfigToml {
+    #[serde(default)]
+    peers: Vec<PeerConfig>,
+    #[serde(default)]
+    discovery: Option<DiscoveryConfig>,
+    #[serde(default)]
+    client: ClientConfig,
+    #[serde(default, deserialize_with = "certificates_from_pem")]
+    root_certificates: Vec<OwnedCertificate>,
+}
+
+/// Selects a dynamic peer discovery backend. See [`crate::net::discovery`].
+///
+/// In `network.toml`:
+/// ```toml
+/// [discovery]
+/// type = "directory"
+/// path = "/etc/ipa/peers"
+/// ```
+/// or
+/// ```toml
+/// [discovery]
+/// type = "dns"
+/// domain = "ipa.example.com"
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DiscoveryConfig {
+    /// Read peers from a directory, see [`discovery::directory::Directory`].
+    Directory { path: PathBuf },
+    /// Resolve peers from DNS records, see [`discovery::dns::Dns`]. Requires the `dns-discovery`
+    /// feature.
+    Dns { domain: String },
+}
+
+impl DiscoveryConfig {
+    /// Runs discovery with the configured backend.
+    ///
+    /// # Errors
+    /// If discovery fails.
+    pub async fn discover(&self) -> Result<[PeerConfig; 3], discovery::Error> {
+        match self {
+            Self::Directory { path } => discovery::directory::Directory::new(path).peers().await,
+            #[cfg(feature = "dns-discovery")]
+            Self::Dns { domain } => {
+                discovery::dns::Dns::new(domain, discovery::dns::SystemResolver::new()?)
+                    .peers()
+                    .await
+            }
+            #[cfg(not(feature = "dns-discovery"))]
+            Self::Dns { .. } => Err(discovery::Error::Unsupported(
+                "DNS discovery requires the dns-discovery feature",
+            )),
+        }
+    }
+
+    /// Like [`Self::discover`], but blocks the calling thread until discovery completes.
+    ///
+    /// Discovery runs on a thread of its own with a runtime created for it, because blocking on
+    /// the runtime of the caller panics. Async code should call [`Self::discover`] instead.
+    fn discover_blocking(&self) -> Result<[PeerConfig; 3], discovery::Error> {
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(discovery::Error::Runtime)?
+                    .block_on(self.discover())
+            })
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+        })
+    }
+}
+
+impl Display for DiscoveryConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Directory { path } => write!(f, "directory {}", path.display()),
+            Self::Dns { domain } => write!(f, "DNS records of {domain}"),
+        }
+    }
 }
 
 impl NetworkConfig {
     /// Reads config from string. Expects config to be toml format.
     /// To read file, use `fs::read_to_string`
     ///
+    /// If the config selects a discovery backend, this blocks until discovery completes. Async
+    /// code that repeats discovery should call [`Self::rediscover`].
+    ///
     /// # Errors
     /// if `input` is in an invalid format
     pub fn from_toml_str(input: &str) -> Result<Self, Error> {
         use config::{Config, File, FileFormat};
 
-        let conf: Self = Config::builder()
+        let conf: NetworkConfigToml = Config::builder()
             .add_source(File::from_str(input, FileFormat::Toml))
             .build()?
             .try_deserialize()?;
 
-        Ok(conf)
+        let peers = match &conf.discovery {
+            Some(discovery) if conf.peers.is_empty() => discovery.discover_blocking()?,
+            Some(_) => {
+                return Err(Error::InvalidNetworkConfig(
+                    "peers must not be listed when discovery is configured".into(),
+                ))
+            }
+            None => conf.peers.try_into().map_err(|peers: Vec<_>| {
+                Error::InvalidNetworkConfig(format!("expected 3 peers, found {}", peers.len()))
+            })?,
+        };
+
+        Ok(Self {
+            peers,
+            client: conf.client,
+            discovery: conf.discovery,
+            root_certificates: conf.root_certificates,
+        })
     }
 
     pub fn new(peers: [PeerConfig; 3], client: ClientConfig) -> Self {
-        Self { peers, client }
+        Self {
+            peers,
+            client,
+            discovery: None,
+            root_certificates: Vec::new(),
+        }
+    }
+
+    /// Runs peer discovery again, if it is configured.
+    ///
+    /// Returns `None` if the peers are listed in the configuration, otherwise a copy of this
+    /// configuration with the newly discovered peers.
+    ///
+    /// # Errors
+    /// If discovery fails.
+    pub async fn rediscover(&self) -> Result<Option<Self>, Error> {
+        let Some(discovery) = &self.discovery else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            peers: discovery.discover().await?,
+            ..self.clone()
+        }))
+    }
+
+    #[must_use]
+    pub fn with_root_certificates(mut self, root_certificates: Vec<OwnedCertificate>) -> Self {
+        self.root_certificates = root_certificates;
+        self
+    }
+
+    /// Returns `true` if peer certificates are verified against a root of trust rather than
+    /// pinned.
+    pub fn uses_root_certificates(&self) -> bool {
+        !self.root_certificates.is_empty()
+    }
+
+    /// Checks that the configuration describes a usable helper network.
+    ///
+    /// This is intended to catch mistakes before a configuration replaces one that is in use. It
+    /// checks that:
+    ///  * Every peer URL has a scheme and an authority.
+    ///  * If root certificates are configured, each peer has a distinct identity.
+    ///  * Either all peers or none of them have a match key encryption key.
+    ///  * QUIC step transport is only selected if this build supports it, and without resuming
+    ///    step streams, which QUIC does not support.
+    ///
+    /// # Errors
+    /// If any of the checks fail.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |msg: String| Err(Error::InvalidNetworkConfig(msg));
+
+        for (id, peer) in self.enumerate_peers() {
+            if peer.url.scheme().is_none() || peer.url.authority().is_none() {
+                return invalid(format!(
+                    "URL {} for helper {id:?} must have a scheme and an authority",
+                    peer.url
+                ));
+            }
+        }
+
+        if self.uses_root_certificates() {
+            let identities = self
+                .peers
+                .iter()
+                .map(PeerConfig::expected_identity)
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    Error::InvalidNetworkConfig("every peer must have an identity".into())
+                })?;
+            for (i, identity) in identities.iter().enumerate() {
+                if identities[..i].contains(identity) {
+                    return invalid(format!("peer identity {identity} is not unique"));
+                }
+            }
+        }
+
+        let with_hpke = self
+            .peers
+            .iter()
+            .filter(|peer| peer.hpke_config.is_some())
+            .count();
+        if with_hpke != 0 && with_hpke != self.peers.len() {
+            return invalid(
+                "match key encryption must be configured for all peers or for none".into(),
+            );
+        }
+
+        if self.client.step_transport == StepTransport::Quic {
+            if !cfg!(feature = "quic") {
+                return invalid("QUIC step transport requires the `quic` feature".into());
+            }
+            if self.client.step_stream.max_reconnects > 0 {
+                return invalid(
+                    "QUIC step streams cannot be resumed, set step_stream.max_reconnects to 0"
+                        .into(),
+                );
+            }
+        }
+
+        Ok(())
     }
 
     pub fn peers(&self) -> &[PeerConfig; 3] {
@@ -88,7 +318,7 @@ impl NetworkConfig {
     }
 
     /// # Panics
-    /// If `PathAndQuery::from_str("")` fails
+    /// If a peer URL with the new scheme is not valid.
     #[must_use]
     pub fn override_scheme(self, scheme: &Scheme) -> NetworkConfig {
         NetworkConfig {
@@ -98,7 +328,7 @@ impl NetworkConfig {
                 // `http::uri::Uri::from_parts()` requires that a URI have a path if it has a
                 // scheme. If the URI does not have a scheme, it is not required to have a path.
                 if parts.path_and_query.is_none() {
-                    parts.path_and_query = Some("".parse().unwrap());
+                    parts.path_and_query = Some(PathAndQuery::from_static("/"));
                 }
                 peer.url = Uri::try_from(parts).unwrap();
                 peer
@@ -108,7 +338,7 @@ impl NetworkConfig {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct PeerConfig {
     /// Peer URL
     #[serde(with = "crate::serde::uri")]
@@ -116,18 +346,34 @@ pub struct PeerConfig {
 
     /// Peer's TLS certificate
     ///
-    /// The peer's end-entity TLS certificate must be specified here, unless HTTPS is disabled.
-    /// In `network.toml`, the certificate must be in PEM format. It is converted to DER
-    /// when the config is loaded.
+    /// Unless HTTPS is disabled, the peer's end-entity TLS certificate must be specified here, or
+    /// [`NetworkConfig::root_certificates`] must be configured. In `network.toml`, the certificate
+    /// must be in PEM format. It is converted to DER when the config is loaded.
     ///
-    /// Verifying a peer's TLS certificate against the system truststore or a custom root of
-    /// trust is not currently supported.
+    /// When root certificates are configured, this field is ignored.
     #[serde(default, deserialize_with = "certificate_from_pem")]
     pub certificate: Option<OwnedCertificate>,
 
+    /// Name that identifies the peer when its certificate is verified against
+    /// [`NetworkConfig::root_certificates`].
+    ///
+    /// The peer's certificate must carry this name as a DNS or URI subject alternative name, for
+    /// example `helper1.example.com` or `spiffe://example.com/ipa/helper1`. If not specified, the
+    /// host of `url` is used. Each peer must have a distinct identity.
+    #[serde(default)]
+    pub identity: Option<String>,
+
     /// Match key encryption configuration.
     #[serde(default, rename = "hpke")]
     pub hpke_config: Option<HpkeClientConfig>,
+
+    /// Encodings of step streams that the peer accepts, for example `"zstd"`.
+    ///
+    /// Step streams sent to the peer are only compressed (see [`CompressionConfig`]) with an
+    /// encoding listed here. Helpers that predate compression cannot tell a compressed stream
+    /// from an uncompressed one, so this must be left empty for them.
+    #[serde(default)]
+    pub step_encodings: Vec<String>,
 }
 
 impl PeerConfig {
@@ -135,9 +381,17 @@ impl PeerConfig {
         Self {
             url,
             certificate,
+            identity: None,
             hpke_config: None,
+            step_encodings: Vec::new(),
         }
     }
+
+    /// The name that the peer's certificate must carry when it is verified against a root of
+    /// trust. See [`PeerConfig::identity`].
+    pub fn expected_identity(&self) -> Option<&str> {
+        self.identity.as_deref().or_else(|| self.url.host())
+    }
 }
 
 /// Match key encryption client configuration. To encrypt match keys towards a helper node, clients
@@ -156,6 +410,12 @@ impl Debug for HpkeClientConfig {
     }
 }
 
+impl PartialEq for HpkeClientConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.public_key.to_bytes() == other.public_key.to_bytes()
+    }
+}
+
 impl HpkeClientConfig {
     #[must_use]
     pub fn new(public_key: IpaPublicKey) -> Self {
@@ -180,6 +440,19 @@ where
     }
 }
 
+/// Reads a bundle of zero or more certificates in PEM format using Serde Serialization
+fn certificates_from_pem<'de, D>(deserializer: D) -> Result<Vec<OwnedCertificate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(s) = <Option<String> as Deserialize>::deserialize(deserializer)? else {
+        return Ok(Vec::new());
+    };
+    rustls_pemfile::certs(&mut s.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(serde::de::Error::custom)
+}
+
 fn pk_from_str<'de, D>(deserializer: D) -> Result<IpaPublicKey, D::Error>
 where
     D: Deserializer<'de>,
@@ -261,6 +534,10 @@ pub struct ServerConfig {
 
     /// Configuration needed for decrypting match keys
     pub hpke_config: Option<HpkeServerConfig>,
+
+    /// If true, also accept step streams from other helpers over QUIC, on the UDP port with the
+    /// same number as the server port. Requires HTTPS and the `quic` feature.
+    pub enable_quic: bool,
 }
 
 pub trait HyperClientConfigurator {
@@ -270,6 +547,31 @@ pub trait HyperClientConfigurator {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientConfig {
     pub http_config: HttpClientConfigurator,
+
+    /// How failed requests are retried. Only control-plane requests are ever retried, see
+    /// [`RetryConfig`] for details.
+    #[serde(default)]
+    pub retry: RetryConfig,
+
+    /// Per-route request timeouts.
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+
+    /// When to stop sending requests to a helper that keeps failing.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// How step streams recover from connection loss.
+    #[serde(default)]
+    pub step_stream: StepStreamConfig,
+
+    /// Protocol used to send step streams to the other helpers.
+    #[serde(default)]
+    pub step_transport: StepTransport,
+
+    /// Compression of step streams, by class of data carried.
+    #[serde(default)]
+    pub compression: CompressionConfig,
 }
 
 impl Default for ClientConfig {
@@ -288,6 +590,12 @@ impl ClientConfig {
     pub fn configure_http2(conf: Http2Configurator) -> Self {
         Self {
             http_config: HttpClientConfigurator::Http2(conf),
+            retry: RetryConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            step_stream: StepStreamConfig::default(),
+            step_transport: StepTransport::default(),
+            compression: CompressionConfig::default(),
         }
     }
 
@@ -295,6 +603,321 @@ impl ClientConfig {
     pub fn use_http1() -> Self {
         Self {
             http_config: HttpClientConfigurator::http1(),
+            retry: RetryConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            step_stream: StepStreamConfig::default(),
+            step_transport: StepTransport::default(),
+            compression: CompressionConfig::default(),
+        }
+    }
+}
+
+/// Retry policy for requests made by [`MpcHelperClient`].
+///
+/// Retries are idempotency-aware:
+///  * Idempotent requests (echo, query status and query results) are retried after any transient
+///    failure: a connection error, a timeout, or a 502, 503 or 504 response.
+///  * Requests that are not idempotent (create query and prepare query) are retried only if the
+///    connection to the helper could not be established, because then the request was never
+///    delivered. Repeating a request that may have been processed would fail with "query already
+///    running", or worse, create a second query.
+///  * Query input requests are never retried, because the input is streamed and cannot be
+///    replayed. Step streams are resumed instead, see [`StepStreamConfig`].
+///
+/// [`MpcHelperClient`]: crate::net::MpcHelperClient
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Maximum number of attempts for a request, including the first one. `1` disables retries.
+    pub max_attempts: u32,
+
+    /// Delay before the first retry. The delay doubles with each subsequent retry.
+    #[serde(
+        rename = "initial_backoff_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs"
+    )]
+    pub initial_backoff: Duration,
+
+    /// Upper bound for the delay between retries.
+    #[serde(
+        rename = "max_backoff_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs"
+    )]
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Retry policy that sends each request only once.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the delay before retrying a request that failed `attempt` times.
+    #[must_use]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Circuit breaker for requests made by [`MpcHelperClient`] to one helper.
+///
+/// The breaker counts consecutive requests that fail with a transient error (see
+/// [`RetryConfig`]), across all routes and step streams. Once `failure_threshold` is reached, the
+/// circuit opens: requests fail immediately with [`Error::CircuitOpen`] instead of waiting for
+/// timeouts and backoffs. After `open_duration`, the circuit is half-open and lets a single request
+/// through. If it succeeds the circuit closes, otherwise it opens again.
+///
+/// Clones of a client share the breaker, so it covers all requests to that helper.
+///
+/// [`MpcHelperClient`]: crate::net::MpcHelperClient
+/// [`Error::CircuitOpen`]: crate::net::Error::CircuitOpen
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failures that opens the circuit. `0` disables the breaker.
+    pub failure_threshold: u32,
+
+    /// How long the circuit stays open before a request is let through to probe the helper.
+    #[serde(
+        rename = "open_duration_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs"
+    )]
+    pub open_duration: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 10,
+            open_duration: Duration::from_secs(10),
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    /// Circuit breaker that never opens.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            failure_threshold: 10,
+            ..Self::default()
+        }
+    }
+}
+
+/// Protocol used to send step streams (the MPC data exchanged by helpers while a query runs).
+/// Other requests always use HTTP.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepTransport {
+    /// One HTTP request per step, multiplexed on the HTTP connection to the helper.
+    #[default]
+    Http,
+    /// One QUIC stream per step. Streams are independent, so a lost packet on one step does not
+    /// hold up the others, which matters on high-latency links. The other helpers must have
+    /// [`ServerConfig::enable_quic`] set. Requires HTTPS and the `quic` feature.
+    ///
+    /// This is experimental. Step streams sent over QUIC are not resumed after connection loss,
+    /// so [`StepStreamConfig::max_reconnects`] must be `0`; [`NetworkConfig::validate`] rejects
+    /// the configuration otherwise. Compression and metrics work as they do over HTTP.
+    Quic,
+}
+
+/// Compression of step streams sent over HTTP, by [`ChannelClass`]. Nothing is compressed by
+/// default.
+///
+/// Secret shares are uniformly random and do not compress, but revealed values and the values
+/// exchanged to validate malicious security often do. Compression costs CPU time on both helpers,
+/// so it is worth enabling when network egress is the bottleneck or the main cost. The
+/// `bytes.sent.wire` metric, compared with `bytes.sent`, shows how well each step compresses.
+///
+/// Compression is negotiated per stream: if the receiving helper does not support the requested
+/// compression, the stream is sent uncompressed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub shares: Compression,
+    pub revealed: Compression,
+    pub control: Compression,
+}
+
+impl CompressionConfig {
+    #[must_use]
+    pub fn for_class(&self, class: ChannelClass) -> Compression {
+        match class {
+            ChannelClass::Shares => self.shares,
+            ChannelClass::Revealed => self.revealed,
+            ChannelClass::Control => self.control,
+        }
+    }
+}
+
+/// Compression algorithm for a step stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    /// Zstandard, at the given compression level (1 to 22; higher is smaller and slower).
+    Zstd {
+        #[serde(default = "Compression::default_zstd_level")]
+        level: i32,
+    },
+}
+
+impl Compression {
+    fn default_zstd_level() -> i32 {
+        3
+    }
+}
+
+/// Configuration of resumable step streams.
+///
+/// Records sent to another helper are retained until the receiver acknowledges them. If the
+/// connection is lost, the client reconnects and replays the records that were not acknowledged,
+/// and the receiver resumes the stream from where it stopped. Reconnects are spaced out according
+/// to [`RetryConfig::backoff`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StepStreamConfig {
+    /// Maximum number of times a single step stream is resumed. `0` fails the stream on the first
+    /// connection loss.
+    pub max_reconnects: u32,
+
+    /// Maximum number of unacknowledged bytes retained for each step stream. The sender stops
+    /// sending when it is reached, until the receiver catches up. This should be larger than the
+    /// HTTP/2 flow control window, otherwise it limits the throughput of the stream.
+    pub retained_bytes: usize,
+}
+
+impl Default for StepStreamConfig {
+    fn default() -> Self {
+        Self {
+            max_reconnects: 5,
+            retained_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+/// Timeouts for requests made by [`MpcHelperClient`], by route. A timeout covers a single attempt,
+/// from sending the request until the response is read. `None` means no timeout.
+///
+/// Query input, query results and step requests carry or wait for an amount of data that depends
+/// on the query size, so they have no timeout by default. For step requests, the timeout covers
+/// the time until the response headers are received.
+///
+/// [`MpcHelperClient`]: crate::net::MpcHelperClient
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    #[serde(
+        rename = "echo_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub echo: Option<Duration>,
+
+    /// Creating a query includes preparing it on the other helpers, so this should allow for
+    /// the prepare query requests to be retried.
+    #[serde(
+        rename = "create_query_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub create_query: Option<Duration>,
+
+    #[serde(
+        rename = "prepare_query_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub prepare_query: Option<Duration>,
+
+    #[serde(
+        rename = "query_input_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub query_input: Option<Duration>,
+
+    #[serde(
+        rename = "query_status_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub query_status: Option<Duration>,
+
+    #[serde(
+        rename = "query_results_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub query_results: Option<Duration>,
+
+    #[serde(
+        rename = "kill_query_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub kill_query: Option<Duration>,
+
+    #[serde(
+        rename = "step_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub step: Option<Duration>,
+
+    #[serde(
+        rename = "helper_state_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub helper_state: Option<Duration>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            echo: Some(Duration::from_secs(10)),
+            create_query: Some(Duration::from_secs(60)),
+            prepare_query: Some(Duration::from_secs(10)),
+            query_input: None,
+            query_status: Some(Duration::from_secs(10)),
+            query_results: None,
+            kill_query: Some(Duration::from_secs(10)),
+            step: None,
+            helper_state: Some(Duration::from_secs(10)),
         }
     }
 }
@@ -454,9 +1077,16 @@ mod tests {
     use rand_core::SeedableRng;
 
     use crate::{
-        config::{ClientConfig, HpkeClientConfig, Http2Configurator, HttpClientConfigurator},
+        config::{
+            CircuitBreakerConfig, ClientConfig, Compression, CompressionConfig, DiscoveryConfig,
+            Error, HpkeClientConfig, Http2Configurator, HttpClientConfigurator, NetworkConfig,
+            RetryConfig, StepStreamConfig, StepTransport, TimeoutConfig,
+        },
         helpers::HelperIdentity,
-        net::test::TestConfigBuilder,
+        net::{
+            test::{TestConfigBuilder, TEST_CERTS, TEST_CERTS_DER},
+            ChannelClass,
+        },
     };
 
     const URI_1: &str = "http://localhost:3000";
@@ -483,6 +1113,161 @@ mod tests {
         assert_eq!(value3.url, uri3);
     }
 
+    #[test]
+    fn parse_root_certificates() {
+        let bundle = format!(
+            "{}{}",
+            std::str::from_utf8(TEST_CERTS[0]).unwrap(),
+            std::str::from_utf8(TEST_CERTS[1]).unwrap()
+        );
+        let conf = NetworkConfig::from_toml_str(&format!(
+            r#"
+root_certificates = """
+{bundle}"""
+
+[[peers]]
+url = "helper1.example.com:443"
+identity = "spiffe://example.com/ipa/helper1"
+
+[[peers]]
+url = "helper2.example.com:443"
+
+[[peers]]
+url = "helper3.example.com:443"
+"#
+        ))
+        .unwrap();
+
+        assert!(conf.uses_root_certificates());
+        assert_eq!(conf.root_certificates, TEST_CERTS_DER[0..2]);
+        assert_eq!(
+            conf.peers()
+                .each_ref()
+                .map(|peer| peer.expected_identity().unwrap()),
+            [
+                "spiffe://example.com/ipa/helper1",
+                "helper2.example.com",
+                "helper3.example.com"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_discovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml = format!(
+            r#"
+[discovery]
+type = "directory"
+path = "{}"
+"#,
+            dir.path().display()
+        );
+        let write_peers = |host: &str| {
+            for i in 1..=3 {
+                std::fs::write(
+                    dir.path().join(format!("h{i}.toml")),
+                    format!("url = \"https://{host}{i}.example.com\"\n"),
+                )
+                .unwrap();
+            }
+        };
+
+        write_peers("helper");
+        let conf = NetworkConfig::from_toml_str(&toml).unwrap();
+        assert_eq!(
+            conf.discovery,
+            Some(DiscoveryConfig::Directory {
+                path: dir.path().to_owned()
+            })
+        );
+        assert_eq!(conf.peers[0].url, "https://helper1.example.com");
+
+        write_peers("moved");
+        let rediscovered = conf.rediscover().await.unwrap().unwrap();
+        assert_eq!(rediscovered.peers[2].url, "https://moved3.example.com");
+        assert_ne!(rediscovered.peers, conf.peers);
+    }
+
+    #[test]
+    fn peers_and_discovery() {
+        let err = NetworkConfig::from_toml_str(
+            r#"
+[[peers]]
+url = "helper1.example.com"
+
+[discovery]
+type = "dns"
+domain = "example.com"
+"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidNetworkConfig(_)));
+    }
+
+    #[tokio::test]
+    async fn literal_peers_not_rediscovered() {
+        let conf = TestConfigBuilder::with_http_and_default_test_ports().build();
+        assert!(conf.network.rediscover().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn validate() {
+        let mut conf = TestConfigBuilder::default().build().network;
+        conf.validate().unwrap();
+
+        let mut with_roots = conf
+            .clone()
+            .with_root_certificates(vec![TEST_CERTS_DER[0].clone()]);
+        // All three test peers are `localhost`, so their identities are not distinct.
+        assert!(matches!(
+            with_roots.validate(),
+            Err(Error::InvalidNetworkConfig(_))
+        ));
+        for (i, peer) in with_roots.peers.iter_mut().enumerate() {
+            peer.identity = Some(format!("helper{}.example.com", i + 1));
+        }
+        with_roots.validate().unwrap();
+
+        let mut no_scheme = conf.clone();
+        no_scheme.peers[0].url = "localhost:3000".parse().unwrap();
+        assert!(matches!(
+            no_scheme.validate(),
+            Err(Error::InvalidNetworkConfig(_))
+        ));
+
+        let mut quic = conf.clone();
+        quic.client.step_transport = StepTransport::Quic;
+        quic.client.step_stream.max_reconnects = 0;
+        assert_eq!(quic.validate().is_ok(), cfg!(feature = "quic"));
+
+        conf.peers[2].hpke_config = None;
+        assert!(matches!(
+            conf.validate(),
+            Err(Error::InvalidNetworkConfig(_))
+        ));
+    }
+
+    /// QUIC step streams are not resumed, so configuring both fails instead of silently dropping
+    /// resumption.
+    #[cfg(feature = "quic")]
+    #[test]
+    fn quic_with_resumption_rejected() {
+        let mut conf = TestConfigBuilder::default().build().network;
+        conf.client.step_transport = StepTransport::Quic;
+        assert!(conf.client.step_stream.max_reconnects > 0);
+        let Err(Error::InvalidNetworkConfig(msg)) = conf.validate() else {
+            panic!("QUIC with resumption must be rejected");
+        };
+        assert!(msg.contains("cannot be resumed"), "{msg}");
+    }
+
+    #[test]
+    fn no_root_certificates() {
+        let conf = TestConfigBuilder::with_http_and_default_test_ports().build();
+        assert!(!conf.network.uses_root_certificates());
+    }
+
     #[test]
     fn debug_hpke_client_config() {
         let mut rng = StdRng::seed_from_u64(1);
@@ -531,4 +1316,87 @@ mod tests {
             }),
         );
     }
+
+    #[test]
+    fn client_retry_and_timeouts_serde() {
+        let config: ClientConfig =
+            serde_json::from_str(r#"{ "http_config": { "version": "http2" } }"#).unwrap();
+        assert_eq!(config.retry, RetryConfig::default());
+        assert_eq!(config.timeouts, TimeoutConfig::default());
+        assert_eq!(config.circuit_breaker, CircuitBreakerConfig::default());
+        assert_eq!(config.step_stream, StepStreamConfig::default());
+        assert_eq!(config.step_transport, StepTransport::Http);
+        assert_eq!(config.compression, CompressionConfig::default());
+
+        let config: ClientConfig = serde_json::from_str(
+            r#"{
+                "http_config": { "version": "http2" },
+                "retry": { "max_attempts": 2, "initial_backoff_secs": 0.5 },
+                "timeouts": { "step_secs": 120, "echo_secs": null },
+                "circuit_breaker": { "failure_threshold": 3 },
+                "step_stream": { "max_reconnects": 0 },
+                "step_transport": "quic",
+                "compression": { "revealed": { "algorithm": "zstd" } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.retry,
+            RetryConfig {
+                max_attempts: 2,
+                initial_backoff: Duration::from_millis(500),
+                ..RetryConfig::default()
+            }
+        );
+        assert_eq!(config.timeouts.step, Some(Duration::from_secs(120)));
+        assert_eq!(config.timeouts.echo, None);
+        assert_eq!(
+            config.circuit_breaker,
+            CircuitBreakerConfig {
+                failure_threshold: 3,
+                ..CircuitBreakerConfig::default()
+            }
+        );
+        assert_eq!(
+            config.timeouts.prepare_query,
+            TimeoutConfig::default().prepare_query
+        );
+        assert_eq!(
+            config.step_stream,
+            StepStreamConfig {
+                max_reconnects: 0,
+                ..StepStreamConfig::default()
+            }
+        );
+        assert_eq!(config.step_transport, StepTransport::Quic);
+        assert_eq!(
+            config.compression,
+            CompressionConfig {
+                revealed: Compression::Zstd { level: 3 },
+                ..CompressionConfig::default()
+            }
+        );
+        assert_eq!(
+            config.compression.for_class(ChannelClass::Revealed),
+            Compression::Zstd { level: 3 }
+        );
+        assert_eq!(
+            config.compression.for_class(ChannelClass::Shares),
+            Compression::None
+        );
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let retry = RetryConfig {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        assert_eq!(retry.backoff(1), Duration::from_millis(100));
+        assert_eq!(retry.backoff(2), Duration::from_millis(200));
+        assert_eq!(retry.backoff(4), Duration::from_millis(800));
+        assert_eq!(retry.backoff(5), Duration::from_secs(1));
+        assert_eq!(retry.backoff(u32::MAX), Duration::from_secs(1));
+    }
 }
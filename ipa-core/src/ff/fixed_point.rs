@@ -0,0 +1,360 @@
+//! Fixed-point trigger values and attribution weights.
+//!
+//! The MPC protocols only operate on unsigned integers, so fractional trigger values such as
+//! revenue are carried as a whole number of units at a [`FixedPointScale`]. With a scale of 100,
+//! $12.37 is the integer 1237. Report collectors quantize values with
+//! [`FixedPointScale::quantize`] before encrypting them, and convert aggregated output back with
+//! [`FixedPointScale::to_decimal`].
+//!
+//! An [`AttributionWeight`] is a fraction between 0 and 1 with
+//! [`AttributionWeight::FRACTIONAL_BITS`] binary fractional digits. Weighting a trigger value
+//! keeps its scale: the product is rounded to the nearest unit, with halves rounded up.
+//! [`AttributionWeights`] split an attributed trigger value across the most recent source events
+//! of a user, one weight per touch.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    num::NonZeroU32,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, ff::boolean_array::BA16};
+
+/// Trigger value type for queries that set a [`FixedPointScale`]. At a scale of 100, it holds
+/// values up to 655.35.
+pub type FixedPointTriggerValue = BA16;
+
+/// Number of units per whole trigger value, e.g. 100 to express revenue in cents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FixedPointScale(NonZeroU32);
+
+impl FixedPointScale {
+    #[must_use]
+    pub const fn new(units: NonZeroU32) -> Self {
+        Self(units)
+    }
+
+    /// The number of units per whole value.
+    #[must_use]
+    pub fn units(self) -> u32 {
+        self.0.get()
+    }
+
+    /// Quantizes `value` to a whole number of units, rounding to the nearest unit.
+    ///
+    /// ## Errors
+    /// If `value` is negative, is not finite, or does not fit in a `u32` at this scale.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn quantize(self, value: f64) -> Result<u32, Error> {
+        let units = (value * f64::from(self.units())).round();
+        if !(0.0..=f64::from(u32::MAX)).contains(&units) {
+            return Err(Error::InvalidQueryParameter(
+                format!("{value} cannot be represented at a scale of {self}").into(),
+            ));
+        }
+        Ok(units as u32)
+    }
+
+    /// Converts a number of units back to a decimal value. Units are signed, because DP noise
+    /// can make aggregated values negative.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn to_decimal(self, units: i64) -> f64 {
+        units as f64 / f64::from(self.units())
+    }
+}
+
+impl Display for FixedPointScale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for FixedPointScale {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim().parse().map(Self).map_err(|e| {
+            Error::InvalidQueryParameter(format!("invalid fixed-point scale {s:?}: {e}").into())
+        })
+    }
+}
+
+/// Fraction of an attributed trigger value that is credited to its source event.
+///
+/// Weights are stored with [`Self::FRACTIONAL_BITS`] fractional bits, so they are rounded to the
+/// nearest multiple of 1/256: 0.4 is stored as 102/256 = 0.3984375. The weight must be greater
+/// than zero and at most one.
+///
+/// In a query config, the weight is written as a decimal number, e.g. `0.4`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AttributionWeight(u16);
+
+impl AttributionWeight {
+    /// Number of binary fractional digits in a weight.
+    pub const FRACTIONAL_BITS: u32 = 8;
+
+    /// Number of bits needed to hold any weight, including a weight of one.
+    pub const BITS: u32 = Self::FRACTIONAL_BITS + 1;
+
+    /// A weight that credits the full trigger value.
+    pub const ONE: Self = Self(1 << Self::FRACTIONAL_BITS);
+
+    /// ## Errors
+    /// If `fraction` is not greater than zero and at most one, or rounds to zero.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(fraction: f64) -> Result<Self, Error> {
+        let units = (fraction * f64::from(Self::ONE.0)).round();
+        if !(1.0..=f64::from(Self::ONE.0)).contains(&units) {
+            return Err(Error::InvalidQueryParameter(
+                format!(
+                    "attribution weight {fraction} must be between {} and 1",
+                    1.0 / f64::from(Self::ONE.0)
+                )
+                .into(),
+            ));
+        }
+        Ok(Self(units as u16))
+    }
+
+    /// The weight as a number of 1/256 units.
+    #[must_use]
+    pub fn units(self) -> u16 {
+        self.0
+    }
+
+    /// Applies this weight to `value`, rounding the result to the nearest unit and rounding
+    /// halves up. This is the same rounding that the MPC protocol applies.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn apply(self, value: u32) -> u32 {
+        let half = 1 << (Self::FRACTIONAL_BITS - 1);
+        let weighted = (u64::from(value) * u64::from(self.0) + half) >> Self::FRACTIONAL_BITS;
+        // the weight is at most one, so the result is at most `value`
+        weighted as u32
+    }
+}
+
+impl Display for AttributionWeight {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", f64::from(self.0) / f64::from(Self::ONE.0))
+    }
+}
+
+impl FromStr for AttributionWeight {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fraction = s.trim().parse::<f64>().map_err(|e| {
+            Error::InvalidQueryParameter(format!("invalid attribution weight {s:?}: {e}").into())
+        })?;
+        Self::new(fraction)
+    }
+}
+
+impl TryFrom<String> for AttributionWeight {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AttributionWeight> for String {
+    fn from(value: AttributionWeight) -> Self {
+        value.to_string()
+    }
+}
+
+/// Weights that split an attributed trigger value across the most recent source events (touches)
+/// that precede it, most recent touch first. With `0.5,0.3,0.2`, the last touch is credited with
+/// half of the value, the one before it with 30% and the one before that with 20%. A single
+/// weight credits the last touch only.
+///
+/// Every share is rounded on its own with [`AttributionWeight::apply`], so the shares of a value
+/// can add up to slightly more than the weights do, by at most half a unit per touch. Touches that
+/// do not exist, because the user has fewer source events before the trigger, or that are outside
+/// the attribution window get no credit; their share is dropped rather than given to other
+/// touches.
+///
+/// Every touch of a trigger contributes one row to aggregation, so the aggregation padding
+/// sensitivity should allow for [`Self::len`] contributions per event.
+///
+/// There can be at most [`Self::MAX_TOUCHES`] weights, and they must add up to at most one. In a
+/// query config, weights are written as a comma-separated list, e.g. `0.5,0.3,0.2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AttributionWeights {
+    weights: [AttributionWeight; Self::MAX_TOUCHES],
+    len: usize,
+}
+
+impl AttributionWeights {
+    /// The largest number of touches that a trigger value can be split across.
+    pub const MAX_TOUCHES: usize = 4;
+
+    /// ## Errors
+    /// If there are no weights, more than [`Self::MAX_TOUCHES`] weights, or the weights add up
+    /// to more than one.
+    pub fn new(weights: &[AttributionWeight]) -> Result<Self, Error> {
+        if weights.is_empty() || weights.len() > Self::MAX_TOUCHES {
+            return Err(Error::InvalidQueryParameter(
+                format!(
+                    "expected between 1 and {} attribution weights, got {}",
+                    Self::MAX_TOUCHES,
+                    weights.len()
+                )
+                .into(),
+            ));
+        }
+        let total = weights.iter().map(|w| u32::from(w.units())).sum::<u32>();
+        if total > u32::from(AttributionWeight::ONE.units()) {
+            return Err(Error::InvalidQueryParameter(
+                format!(
+                    "attribution weights must add up to at most 1, got {}",
+                    f64::from(total) / f64::from(AttributionWeight::ONE.units())
+                )
+                .into(),
+            ));
+        }
+
+        let mut padded = [AttributionWeight::ONE; Self::MAX_TOUCHES];
+        padded[..weights.len()].copy_from_slice(weights);
+        Ok(Self {
+            weights: padded,
+            len: weights.len(),
+        })
+    }
+
+    /// The weights, most recent touch first.
+    #[must_use]
+    pub fn as_slice(&self) -> &[AttributionWeight] {
+        &self.weights[..self.len]
+    }
+
+    /// The number of touches that a trigger value is split across.
+    #[must_use]
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl From<AttributionWeight> for AttributionWeights {
+    fn from(weight: AttributionWeight) -> Self {
+        Self::new(&[weight]).unwrap()
+    }
+}
+
+impl Display for AttributionWeights {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, weight) in self.as_slice().iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{weight}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for AttributionWeights {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights = s
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<AttributionWeight>, _>>()?;
+        Self::new(&weights)
+    }
+}
+
+impl TryFrom<String> for AttributionWeights {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AttributionWeights> for String {
+    fn from(value: AttributionWeights) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::{AttributionWeight, AttributionWeights, FixedPointScale};
+
+    #[test]
+    fn quantize_to_cents() {
+        let cents = FixedPointScale::new(NonZeroU32::new(100).unwrap());
+        assert_eq!(cents.quantize(12.37).unwrap(), 1237);
+        assert_eq!(cents.quantize(0.125).unwrap(), 13);
+        assert!((cents.to_decimal(1237) - 12.37).abs() < f64::EPSILON);
+        assert!(cents.quantize(-1.0).is_err());
+        assert!(cents.quantize(f64::NAN).is_err());
+        assert_eq!("100".parse::<FixedPointScale>().unwrap(), cents);
+        assert!("0".parse::<FixedPointScale>().is_err());
+    }
+
+    #[test]
+    fn weight_rounds_half_up() {
+        let half = AttributionWeight::new(0.5).unwrap();
+        assert_eq!(half.apply(3), 2);
+        assert_eq!(half.apply(4), 2);
+        assert_eq!(AttributionWeight::ONE.apply(1237), 1237);
+
+        let weight = "0.4".parse::<AttributionWeight>().unwrap();
+        assert_eq!(weight.units(), 102);
+        // 1237 * 102 / 256 = 492.86
+        assert_eq!(weight.apply(1237), 493);
+        assert_eq!(
+            weight.to_string().parse::<AttributionWeight>().unwrap(),
+            weight
+        );
+    }
+
+    #[test]
+    fn weight_out_of_range() {
+        for weight in ["0", "0.001", "1.01", "-0.5", "half"] {
+            assert!(weight.parse::<AttributionWeight>().is_err(), "{weight}");
+        }
+    }
+
+    #[test]
+    fn weights_per_touch() {
+        let weights = "0.5,0.3,0.2".parse::<AttributionWeights>().unwrap();
+        assert_eq!(
+            weights.as_slice(),
+            &[
+                AttributionWeight::new(0.5).unwrap(),
+                AttributionWeight::new(0.3).unwrap(),
+                AttributionWeight::new(0.2).unwrap(),
+            ]
+        );
+        assert_eq!(
+            weights.to_string().parse::<AttributionWeights>().unwrap(),
+            weights
+        );
+        assert_eq!(
+            "0.4".parse::<AttributionWeights>().unwrap(),
+            AttributionWeights::from(AttributionWeight::new(0.4).unwrap())
+        );
+    }
+
+    #[test]
+    fn weights_out_of_range() {
+        for weights in ["", "0.5,", "0.6,0.5", "0.2,0.2,0.2,0.2,0.1", "0.5,0"] {
+            assert!(weights.parse::<AttributionWeights>().is_err(), "{weights}");
+        }
+    }
+}
@@ -7,6 +7,7 @@ pub mod boolean_array;
 pub mod curve_points;
 pub mod ec_prime_field;
 mod field;
+pub mod fixed_point;
 mod galois_field;
 mod prime_field;
 
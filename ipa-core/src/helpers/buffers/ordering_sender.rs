@@ -15,8 +15,8 @@ use crate::{
     helpers::{buffers::circular::CircularBuf, Message},
     sync::{
         atomic::{
-            AtomicUsize,
-            Ordering::{AcqRel, Acquire},
+            AtomicBool, AtomicUsize,
+            Ordering::{AcqRel, Acquire, Release},
         },
         Mutex, MutexGuard,
     },
@@ -224,6 +224,16 @@ impl Waiting {
         self.shard(i).wake(i);
     }
 
+    /// Wakes all the tasks that are waiting for their turn.
+    fn wake_all(&self) {
+        for shard in &self.shards {
+            let wakers = std::mem::take(&mut shard.lock().unwrap().wakers);
+            for waker in wakers {
+                waker.w.wake();
+            }
+        }
+    }
+
     /// Returns all records currently waiting to be sent in sorted order.
     #[cfg(feature = "stall-detection")]
     fn waiting(&self) -> std::collections::BTreeSet<usize> {
@@ -273,6 +283,10 @@ pub struct OrderingSender {
     next: AtomicUsize,
     state: Mutex<State>,
     waiting: Waiting,
+    /// Set when the data can no longer be delivered. See [`abort`].
+    ///
+    /// [`abort`]: OrderingSender::abort
+    aborted: AtomicBool,
 }
 
 impl OrderingSender {
@@ -293,6 +307,7 @@ impl OrderingSender {
                 read_threshold.get(),
             )),
             waiting: Waiting::default(),
+            aborted: AtomicBool::new(false),
         }
     }
 
@@ -334,6 +349,42 @@ impl OrderingSender {
         self.state.lock().unwrap().is_closed()
     }
 
+    /// Gives up on sending: either whatever was reading the data is gone, or no more data is
+    /// coming. All pending and future `send` and `close` operations complete immediately
+    /// without doing anything, so that the tasks waiting on them are not blocked forever. Use
+    /// [`is_aborted`] to tell this apart from a successful send. The data already written to
+    /// the buffer can still be taken, after which the stream of data ends.
+    ///
+    /// ## Panics
+    /// If the underlying mutex is poisoned or locked by the same thread.
+    ///
+    /// [`is_aborted`]: OrderingSender::is_aborted
+    pub fn abort(&self) {
+        self.aborted.store(true, Release);
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.is_closed() {
+                state.close();
+            }
+            State::wake(&mut state.write_ready);
+        }
+        self.waiting.wake_all();
+    }
+
+    /// Returns `true` if this sender was aborted.
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Acquire)
+    }
+
+    /// Returns the number of bytes waiting in the buffer and the capacity of the buffer.
+    ///
+    /// ## Panics
+    /// If the underlying mutex is poisoned or locked by the same thread.
+    pub fn occupancy(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.buf.len(), state.buf.capacity())
+    }
+
     /// Perform the next `send` or `close` operation.
     fn next_op<F>(&self, i: usize, cx: &Context<'_>, f: F) -> Poll<()>
     where
@@ -342,6 +393,9 @@ impl OrderingSender {
         // This load here is on the hot path.
         // Don't acquire the state mutex unless this test passes.
         loop {
+            if self.is_aborted() {
+                break Poll::Ready(());
+            }
             let curr = self.next.load(Acquire);
             match curr.cmp(&i) {
                 Ordering::Greater => {
@@ -352,7 +406,14 @@ impl OrderingSender {
                     // No one else should be incrementing this atomic, so
                     // there should be no contention on this lock except for
                     // any calls to `take()`, which is tolerable.
-                    let res = f(&mut self.state.lock().unwrap());
+                    let mut state = self.state.lock().unwrap();
+                    // `abort` wakes the writer under this lock, so checking again here
+                    // guarantees that a writer that goes to sleep below will be woken.
+                    if self.is_aborted() {
+                        break Poll::Ready(());
+                    }
+                    let res = f(&mut state);
+                    drop(state);
                     if res.is_ready() {
                         let curr = self.next.fetch_add(1, AcqRel);
                         debug_assert_eq!(i, curr, "we just checked this");
@@ -375,6 +436,10 @@ impl OrderingSender {
                     // be rejected because writer has moved the waiting shard position ahead and it won't match
                     // the value of `self.next` read by the waiting thread.
                     if self.waiting.add(curr, i, cx.waker()).is_ok() {
+                        // `abort` may have woken the waiting tasks before this one was added.
+                        if self.is_aborted() {
+                            break Poll::Ready(());
+                        }
                         break Poll::Pending;
                     }
                 }
@@ -626,6 +691,36 @@ mod test {
         });
     }
 
+    /// Aborting the sender unblocks the writer waiting for space in the buffer and the writers
+    /// waiting for their turn. The records written before that are still delivered.
+    #[test]
+    fn abort_unblocks_senders() {
+        run(|| async {
+            let sender = sender::<Fp31>();
+            for i in 0..6_u8 {
+                sender.send(usize::from(i), Fp31::truncate_from(i)).await;
+            }
+
+            // buffer is now full.
+            let mut full = pin!(sender.send(6, Fp31::truncate_from(6_u128)));
+            let mut waiting = pin!(sender.send(7, Fp31::truncate_from(7_u128)));
+            assert_eq!(None, poll_immediate(&mut full).await);
+            assert_eq!(None, poll_immediate(&mut waiting).await);
+
+            sender.abort();
+            assert!(sender.is_aborted());
+            assert_eq!(Some(()), poll_immediate(full).await);
+            assert_eq!(Some(()), poll_immediate(waiting).await);
+            sender.close(8).await;
+
+            let taken = sender.as_stream().collect::<Vec<_>>().await;
+            assert_eq!(
+                (0..6).collect::<Vec<u8>>(),
+                taken.into_iter().flatten().collect::<Vec<_>>()
+            );
+        });
+    }
+
     type BoxedSendFn = Box<
         dyn for<'a> FnOnce(
             &'a OrderingSender,
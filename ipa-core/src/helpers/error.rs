@@ -27,4 +27,6 @@ pub enum Error<I: TransportIdentity> {
         channel_id: ChannelId<I>,
         total_records: TotalRecords,
     },
+    #[error("Connection to {channel_id:?} was lost before all records were sent")]
+    ConnectionLost { channel_id: ChannelId<I> },
 }
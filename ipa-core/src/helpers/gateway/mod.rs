@@ -11,6 +11,7 @@ use std::{
 
 pub(super) use receive::{MpcReceivingEnd, ShardReceivingEnd};
 pub(super) use send::SendingEnd;
+use serde::{Deserialize, Serialize};
 #[cfg(feature = "stall-detection")]
 pub(super) use stall_detection::InstrumentedGateway;
 pub use transport::RoleResolvingTransport;
@@ -29,7 +30,7 @@ use crate::{
     },
     protocol::QueryId,
     sharding::ShardIndex,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, Weak},
     utils::NonZeroU32PowerOfTwo,
 };
 
@@ -64,12 +65,71 @@ pub struct Gateway {
 
 #[derive(Default)]
 pub struct State {
-    mpc_senders: GatewaySenders<Role>,
+    /// Shared with [`GatewayMonitor`], which observes the channels from outside the query.
+    mpc_senders: Arc<GatewaySenders<Role>>,
     mpc_receivers: GatewayReceivers<Role, UR>,
     shard_senders: GatewaySenders<ShardIndex>,
     shard_receivers: GatewayReceivers<ShardIndex, ShardReceiveStream>,
 }
 
+/// Observes a [`Gateway`] from outside the query that uses it, e.g. for the admin API. It does
+/// not keep the gateway alive: once the query is done, it reports no channels.
+#[derive(Clone)]
+pub struct GatewayMonitor {
+    active_work: NonZeroU32PowerOfTwo,
+    mpc_senders: Weak<GatewaySenders<Role>>,
+}
+
+/// State of a [`Gateway`], as reported by [`GatewayMonitor::stats`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GatewayStats {
+    pub active_work: usize,
+    /// Send buffers of the channels to the other helpers, ordered by peer and gate.
+    pub send_buffers: Vec<SendBufferStats>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendBufferStats {
+    pub peer: Role,
+    pub gate: String,
+    /// Bytes waiting in the buffer to be sent.
+    pub buffered: usize,
+    pub capacity: usize,
+    pub closed: bool,
+}
+
+impl GatewayMonitor {
+    #[must_use]
+    pub fn stats(&self) -> GatewayStats {
+        let mut send_buffers = self
+            .mpc_senders
+            .upgrade()
+            .map(|senders| {
+                senders
+                    .inner
+                    .iter()
+                    .map(|sender| {
+                        let (buffered, capacity) = sender.occupancy();
+                        SendBufferStats {
+                            peer: sender.channel_id().peer,
+                            gate: sender.channel_id().gate.as_ref().to_string(),
+                            buffered,
+                            capacity,
+                            closed: sender.is_closed(),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        send_buffers.sort_by(|a, b| (a.peer, &a.gate).cmp(&(b.peer, &b.gate)));
+
+        GatewayStats {
+            active_work: self.active_work.to_non_zero_usize().get(),
+            send_buffers,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct GatewayConfig {
     /// The number of items that can be active at the one time.
@@ -140,6 +200,15 @@ impl Gateway {
         &self.config
     }
 
+    /// Returns a handle to observe this gateway while the query runs.
+    #[must_use]
+    pub fn monitor(&self) -> GatewayMonitor {
+        GatewayMonitor {
+            active_work: self.config.active,
+            mpc_senders: Arc::downgrade(&self.inner.mpc_senders),
+        }
+    }
+
     /// Returns a sender suitable for sending data between MPC helpers. The data must be approved
     /// for sending by implementing [`MpcMessage`] trait.
     ///
@@ -35,7 +35,7 @@ pub struct SendingEnd<I: TransportIdentity, M> {
 }
 
 /// Sending channels, indexed by identity and gate.
-pub(super) struct GatewaySenders<I> {
+pub(super) struct GatewaySenders<I: TransportIdentity> {
     pub(super) inner: DashMap<ChannelId<I>, Arc<GatewaySender<I>>>,
 }
 
@@ -45,8 +45,10 @@ pub(super) struct GatewaySender<I> {
     total_records: TotalRecords,
 }
 
-struct GatewaySendStream<I> {
+struct GatewaySendStream<I: Debug> {
     inner: Arc<GatewaySender<I>>,
+    /// Set when all the records were taken from the sender.
+    done: bool,
 }
 
 /// Configuration for each [`GatewaySender`]. All values stored here
@@ -74,6 +76,20 @@ impl<I: TransportIdentity> Default for GatewaySenders<I> {
     }
 }
 
+impl<I: TransportIdentity> Drop for GatewaySenders<I> {
+    fn drop(&mut self) {
+        // The query is over, possibly because it failed. End the streams of the channels that
+        // were not closed once the records already sent are delivered, so that the peers
+        // waiting on more records fail instead of waiting forever.
+        // Closed channels are left alone, as the transport may still be sending their data.
+        for sender in &self.inner {
+            if !sender.ordering_tx.is_closed() {
+                sender.ordering_tx.abort();
+            }
+        }
+    }
+}
+
 impl<I: TransportIdentity> GatewaySender<I> {
     fn new(channel_id: ChannelId<I>, tx: OrderingSender, total_records: TotalRecords) -> Self {
         Self {
@@ -102,14 +118,18 @@ impl<I: TransportIdentity> GatewaySender<I> {
             }
         }
 
-        // TODO: make OrderingSender::send fallible
-        // TODO: test channel close
         let i = usize::from(record_id);
         self.ordering_tx.send(i, msg).await;
         if self.total_records.is_last(record_id) {
             self.ordering_tx.close(i + 1).await;
         }
 
+        if self.ordering_tx.is_aborted() {
+            return Err(Error::ConnectionLost {
+                channel_id: self.channel_id.clone(),
+            });
+        }
+
         Ok(())
     }
 
@@ -123,6 +143,15 @@ impl<I: TransportIdentity> GatewaySender<I> {
         self.total_records
     }
 
+    /// Returns the number of bytes waiting in the send buffer and the capacity of the buffer.
+    pub fn occupancy(&self) -> (usize, usize) {
+        self.ordering_tx.occupancy()
+    }
+
+    pub fn channel_id(&self) -> &ChannelId<I> {
+        &self.channel_id
+    }
+
     pub fn is_closed(&self) -> bool {
         self.ordering_tx.is_closed()
     }
@@ -209,17 +238,22 @@ impl<I: TransportIdentity> GatewaySenders<I> {
                 entry.insert(Arc::clone(&sender));
 
                 tokio::spawn({
+                    let channel_id = channel_id.clone();
                     let ChannelId { peer, gate } = channel_id.clone();
                     let transport = transport.clone();
                     let stream = GatewaySendStream {
                         inner: Arc::clone(&sender),
+                        done: false,
                     };
                     async move {
-                        // TODO(651): In the HTTP case we probably need more robust error handling here.
-                        transport
+                        // If the transport fails, it drops the stream, which makes the pending
+                        // and future sends on this channel fail.
+                        if let Err(e) = transport
                             .send(peer, (RouteId::Records, query_id, gate), stream)
                             .await
-                            .expect("{channel_id:?} receiving end should be accepted by transport");
+                        {
+                            tracing::error!("failed to send records on {channel_id:?}: {e:?}");
+                        }
                     }
                 });
 
@@ -242,7 +276,28 @@ impl<I: Debug> Stream for GatewaySendStream<I> {
 
     #[tracing::instrument(level = "trace", name = "send_stream", skip_all, fields(to = ?self.inner.channel_id.peer, gate = ?self.inner.channel_id.gate))]
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::get_mut(self).inner.ordering_tx.take_next(cx)
+        let this = Pin::get_mut(self);
+        let next = this.inner.ordering_tx.take_next(cx);
+        if let Poll::Ready(None) = next {
+            this.done = true;
+        }
+        next
+    }
+}
+
+impl<I: Debug> Drop for GatewaySendStream<I> {
+    fn drop(&mut self) {
+        // The transport gave up on the stream, so the records in the send buffer will never be
+        // delivered. Without this, the protocol would wait forever for space in the buffer.
+        // Once the channel is closed, nothing waits on the buffer anymore.
+        if !self.done && !self.inner.ordering_tx.is_closed() {
+            tracing::warn!(
+                "stream to {:?} for {:?} was dropped before all records were sent",
+                self.inner.channel_id.peer,
+                self.inner.channel_id.gate
+            );
+            self.inner.ordering_tx.abort();
+        }
     }
 }
 
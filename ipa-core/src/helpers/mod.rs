@@ -54,7 +54,7 @@ mod gateway_exports {
     pub type ShardReceivingEnd<M> = gateway::ShardReceivingEnd<M>;
 }
 
-pub use gateway::GatewayConfig;
+pub use gateway::{GatewayConfig, GatewayMonitor, GatewayStats, SendBufferStats};
 // TODO: this type should only be available within infra. Right now several infra modules
 // are exposed at the root level. That makes it impossible to have a proper hierarchy here.
 pub use gateway::{
@@ -62,11 +62,14 @@ pub use gateway::{
 };
 pub use gateway_exports::{Gateway, MpcReceivingEnd, SendingEnd, ShardReceivingEnd};
 pub use prss_protocol::negotiate as negotiate_prss;
+#[cfg(any(test, feature = "test-fixture"))]
+pub use transport::faults;
 #[cfg(feature = "web-app")]
 pub use transport::WrappedAxumBodyStream;
 #[cfg(feature = "in-memory-infra")]
 pub use transport::{
-    config as in_memory_config, InMemoryMpcNetwork, InMemoryShardNetwork, InMemoryTransport,
+    config as in_memory_config, shaping as in_memory_shaping, InMemoryMpcNetwork,
+    InMemoryShardNetwork, InMemoryTransport,
 };
 pub use transport::{
     make_owned_handler, query, routing, ApiError, BodyStream, BytesStream, HandlerBox, HandlerRef,
@@ -0,0 +1,644 @@
+//! Fault injection for step streams, for testing.
+//!
+//! A [`FaultInjector`] wraps the streams of records sent between helpers and injects the faults
+//! described by its [`FaultRule`]s: lost connections, delayed or duplicated chunks, streams that
+//! end early, and helpers that crash. It is used by the in-memory transport (see
+//! [`TestWorldConfig::fault_injector`]) and can be attached to the HTTP client in tests.
+//!
+//! Faults are deterministic for a given seed. Each stream draws from its own random number
+//! generator, seeded from the injector seed and the identity of the stream, so the faults
+//! injected into a stream do not depend on the order in which the streams are created.
+//!
+//! [`TestWorldConfig::fault_injector`]: crate::test_fixture::TestWorldConfig::fault_injector
+
+use std::{collections::HashSet, fmt::Debug, pin::Pin, time::Duration};
+
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::BoxError,
+    helpers::HelperIdentity,
+    protocol::Gate,
+    sync::{Arc, Mutex},
+};
+
+/// A fault that can be injected into a stream. Faults are injected before a chunk of data is
+/// delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The connection carrying the stream is lost. The receiver gets an error instead of the
+    /// chunk, and nothing after it.
+    Disconnect,
+    /// The chunk is delivered late.
+    Delay(Duration),
+    /// The chunk is delivered twice.
+    Duplicate,
+    /// The stream ends without an error, before the chunk.
+    CloseEarly,
+    /// The sending helper crashes. This stream, and every stream sent by the helper after it,
+    /// fails as if the connection was lost.
+    Crash,
+}
+
+/// Injects a fault into the chunks of matching streams, with the given probability per chunk.
+#[derive(Clone, Debug)]
+pub struct FaultRule {
+    fault: Fault,
+    probability: f64,
+    from: Option<HelperIdentity>,
+    step: Option<String>,
+    first_chunk: usize,
+    limit: Option<usize>,
+}
+
+impl FaultRule {
+    /// ## Panics
+    /// If `probability` is not between 0 and 1.
+    #[must_use]
+    pub fn new(fault: Fault, probability: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "probability must be between 0 and 1, got {probability}"
+        );
+        Self {
+            fault,
+            probability,
+            from: None,
+            step: None,
+            first_chunk: 0,
+            limit: None,
+        }
+    }
+
+    /// Only injects the fault into streams sent by `helper`.
+    #[must_use]
+    pub fn from_helper(mut self, helper: HelperIdentity) -> Self {
+        self.from = Some(helper);
+        self
+    }
+
+    /// Only injects the fault into streams for gates that contain `step`.
+    #[must_use]
+    pub fn on_step(mut self, step: &str) -> Self {
+        self.step = Some(step.to_string());
+        self
+    }
+
+    /// Only injects the fault before chunk `chunk` of a stream, or a later one, so that the
+    /// chunks before it are delivered.
+    #[must_use]
+    pub fn from_chunk(mut self, chunk: usize) -> Self {
+        self.first_chunk = chunk;
+        self
+    }
+
+    /// Injects the fault at most `count` times, across all streams. Which streams get the faults
+    /// then depends on the order in which their chunks are sent.
+    #[must_use]
+    pub fn at_most(mut self, count: usize) -> Self {
+        self.limit = Some(count);
+        self
+    }
+
+    fn applies(&self, from: HelperIdentity, gate: &Gate, chunk: usize) -> bool {
+        chunk >= self.first_chunk
+            && self.from.map_or(true, |helper| helper == from)
+            && self
+                .step
+                .as_ref()
+                .map_or(true, |step| gate.as_ref().contains(step.as_str()))
+    }
+}
+
+/// A fault that was injected, as recorded by [`FaultInjector::injected`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InjectedFault {
+    pub from: HelperIdentity,
+    pub to: String,
+    pub gate: Gate,
+    /// Index of the chunk of the stream the fault was injected before.
+    pub chunk: usize,
+    pub fault: Fault,
+}
+
+/// Injects faults into streams according to a set of rules. See the [module documentation].
+///
+/// [module documentation]: self
+#[derive(Debug)]
+pub struct FaultInjector {
+    seed: u64,
+    rules: Vec<FaultRule>,
+    crashed: Mutex<HashSet<HelperIdentity>>,
+    injected: Mutex<Vec<InjectedFault>>,
+    /// Number of times each rule fired.
+    fired: Mutex<Vec<usize>>,
+}
+
+/// State of a stream with faults.
+struct FaultyStream<S> {
+    injector: Arc<FaultInjector>,
+    from: HelperIdentity,
+    to: String,
+    gate: Gate,
+    /// The stream, until a fault ends it. Dropping it tells the sender that the data is not
+    /// going to be delivered.
+    inner: Option<Pin<Box<S>>>,
+    rng: StdRng,
+    /// Chunk to deliver again.
+    duplicate: Option<Bytes>,
+    chunk: usize,
+}
+
+impl FaultInjector {
+    #[must_use]
+    pub fn new(seed: u64, rules: Vec<FaultRule>) -> Arc<Self> {
+        Arc::new(Self {
+            seed,
+            fired: Mutex::new(vec![0; rules.len()]),
+            rules,
+            crashed: Mutex::new(HashSet::new()),
+            injected: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Returns the faults injected so far, in the order they were injected.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    #[must_use]
+    pub fn injected(&self) -> Vec<InjectedFault> {
+        self.injected.lock().unwrap().clone()
+    }
+
+    /// Returns `true` if `helper` crashed.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    #[must_use]
+    pub fn is_crashed(&self, helper: HelperIdentity) -> bool {
+        self.crashed.lock().unwrap().contains(&helper)
+    }
+
+    /// Seed of the random number generator of a stream. It must not change across platforms and
+    /// releases, so that a seed that reproduces a failure keeps reproducing it.
+    fn stream_seed(&self, from: HelperIdentity, to: &str, gate: &Gate, segment: u64) -> [u8; 32] {
+        let gate = gate.as_ref();
+        Sha256::new()
+            .chain_update(self.seed.to_le_bytes())
+            .chain_update([u8::from(from)])
+            .chain_update((to.len() as u64).to_le_bytes())
+            .chain_update(to)
+            .chain_update((gate.len() as u64).to_le_bytes())
+            .chain_update(gate)
+            .chain_update(segment.to_le_bytes())
+            .finalize()
+            .into()
+    }
+
+    /// Injects faults into `stream`, sent by helper `from` to `to` for `gate`. `segment`
+    /// distinguishes the streams that carry the same records, e.g. when a stream is resumed
+    /// after a lost connection, so that they get different faults.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn inject<S, E>(
+        self: &Arc<Self>,
+        from: HelperIdentity,
+        to: &str,
+        gate: &Gate,
+        segment: u64,
+        stream: S,
+    ) -> impl Stream<Item = Result<Bytes, BoxError>> + Send
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        let state = FaultyStream {
+            injector: Arc::clone(self),
+            from,
+            to: to.to_string(),
+            gate: gate.clone(),
+            inner: Some(Box::pin(stream)),
+            rng: StdRng::from_seed(self.stream_seed(from, to, gate, segment)),
+            duplicate: None,
+            chunk: 0,
+        };
+
+        stream::unfold(state, |mut state| async move {
+            if let Some(chunk) = state.duplicate.take() {
+                return Some((Ok(chunk), state));
+            }
+            state.inner.as_ref()?;
+            if state.injector.is_crashed(state.from) {
+                state.inner = None;
+                return Some((Err(format!("{:?} crashed", state.from).into()), state));
+            }
+            let chunk = match state.inner.as_mut()?.next().await? {
+                Ok(chunk) => chunk,
+                Err(e) => return Some((Err(e.into()), state)),
+            };
+
+            let Some(fault) = state.next_fault() else {
+                return Some((Ok(chunk), state));
+            };
+            match fault {
+                Fault::Disconnect => {
+                    state.inner = None;
+                    Some((Err("connection lost (injected fault)".into()), state))
+                }
+                Fault::Crash => {
+                    state.injector.crashed.lock().unwrap().insert(state.from);
+                    state.inner = None;
+                    let error = format!("{:?} crashed (injected fault)", state.from);
+                    Some((Err(error.into()), state))
+                }
+                Fault::CloseEarly => None,
+                Fault::Delay(delay) => {
+                    ::tokio::time::sleep(delay).await;
+                    Some((Ok(chunk), state))
+                }
+                Fault::Duplicate => {
+                    state.duplicate = Some(chunk.clone());
+                    Some((Ok(chunk), state))
+                }
+            }
+        })
+    }
+}
+
+impl<S> FaultyStream<S> {
+    /// Picks the fault to inject before the next chunk, if any, and records it. If several
+    /// rules fire, the first one wins.
+    fn next_fault(&mut self) -> Option<Fault> {
+        let chunk = self.chunk;
+        self.chunk += 1;
+        let mut fired = self.injector.fired.lock().unwrap();
+        let fault = self
+            .injector
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.applies(self.from, &self.gate, chunk))
+            // Every rule draws, so that the faults of one rule do not depend on the others.
+            .fold(None, |fault, (i, rule)| {
+                let fires = self.rng.gen_bool(rule.probability);
+                if fault.is_some() || !fires || rule.limit.is_some_and(|limit| fired[i] >= limit) {
+                    return fault;
+                }
+                fired[i] += 1;
+                Some(rule.fault)
+            })?;
+        drop(fired);
+
+        tracing::info!(
+            "injecting {fault:?} into chunk {chunk} from {:?} to {} for {:?}",
+            self.from,
+            self.to,
+            self.gate
+        );
+        self.injector.injected.lock().unwrap().push(InjectedFault {
+            from: self.from,
+            to: self.to.clone(),
+            gate: self.gate.clone(),
+            chunk,
+            fault,
+        });
+        Some(fault)
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures::{stream, StreamExt};
+
+    use super::{Fault, FaultInjector, FaultRule};
+    use crate::{error::BoxError, helpers::HelperIdentity, protocol::Gate, sync::Arc};
+
+    const CHUNKS: u8 = 20;
+
+    /// Sends `CHUNKS` chunks through the injector and returns what was received, with errors
+    /// turned into strings.
+    async fn send(
+        injector: &Arc<FaultInjector>,
+        from: HelperIdentity,
+        gate: &str,
+    ) -> Vec<Result<u8, String>> {
+        let data = stream::iter(0..CHUNKS).map(|i| Ok::<_, BoxError>(Bytes::from(vec![i])));
+        injector
+            .inject(from, "H2", &Gate::from(gate), 0, data)
+            .map(|chunk| chunk.map(|c| c[0]).map_err(|e| e.to_string()))
+            .collect()
+            .await
+    }
+
+    fn ok(chunks: impl IntoIterator<Item = u8>) -> Vec<Result<u8, String>> {
+        chunks.into_iter().map(Ok).collect()
+    }
+
+    #[tokio::test]
+    async fn no_faults() {
+        let injector = FaultInjector::new(0, vec![]);
+        assert_eq!(
+            send(&injector, HelperIdentity::ONE, "step").await,
+            ok(0..CHUNKS)
+        );
+        assert!(injector.injected().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deterministic() {
+        let rules = vec![
+            FaultRule::new(Fault::Duplicate, 0.2),
+            FaultRule::new(Fault::Disconnect, 0.05),
+        ];
+        let first = FaultInjector::new(42, rules.clone());
+        let second = FaultInjector::new(42, rules);
+        for gate in ["a", "b", "c"] {
+            assert_eq!(
+                send(&first, HelperIdentity::ONE, gate).await,
+                send(&second, HelperIdentity::ONE, gate).await
+            );
+        }
+        assert_eq!(first.injected(), second.injected());
+        assert!(!first.injected().is_empty());
+    }
+
+    /// Seeds that reproduce a failure must keep doing so on other platforms and toolchains.
+    #[test]
+    fn stream_seed_is_stable() {
+        let injector = FaultInjector::new(42, vec![]);
+        assert_eq!(
+            hex::encode(injector.stream_seed(HelperIdentity::ONE, "H2", &Gate::from("foo"), 1)),
+            "2861642764d6c8341eb585c5e272ba633235fcd642ff3ea1775127023c989b89"
+        );
+    }
+
+    #[tokio::test]
+    async fn duplicate() {
+        let injector = FaultInjector::new(0, vec![FaultRule::new(Fault::Duplicate, 1.0)]);
+        let expected = (0..CHUNKS).flat_map(|i| [Ok(i), Ok(i)]).collect::<Vec<_>>();
+        assert_eq!(send(&injector, HelperIdentity::ONE, "step").await, expected);
+    }
+
+    #[tokio::test]
+    async fn disconnect_and_close() {
+        let injector = FaultInjector::new(0, vec![FaultRule::new(Fault::Disconnect, 1.0)]);
+        let received = send(&injector, HelperIdentity::ONE, "step").await;
+        assert_eq!(received.len(), 1);
+        assert!(received[0].is_err());
+
+        let injector = FaultInjector::new(0, vec![FaultRule::new(Fault::CloseEarly, 1.0)]);
+        assert_eq!(send(&injector, HelperIdentity::ONE, "step").await, ok([]));
+
+        let injector = FaultInjector::new(
+            0,
+            vec![FaultRule::new(Fault::Disconnect, 1.0).from_chunk(5)],
+        );
+        let received = send(&injector, HelperIdentity::ONE, "step").await;
+        assert_eq!(received[..5], ok(0..5));
+        assert!(received[5].is_err());
+        assert_eq!(received.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn delay() {
+        let injector = FaultInjector::new(
+            0,
+            vec![FaultRule::new(Fault::Delay(Duration::from_millis(5)), 1.0)],
+        );
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            send(&injector, HelperIdentity::ONE, "step").await,
+            ok(0..CHUNKS)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(5 * u64::from(CHUNKS)));
+    }
+
+    #[tokio::test]
+    async fn crash_is_sticky() {
+        let injector =
+            FaultInjector::new(0, vec![FaultRule::new(Fault::Crash, 1.0).on_step("crash")]);
+        assert!(send(&injector, HelperIdentity::ONE, "crash").await[0].is_err());
+        assert!(injector.is_crashed(HelperIdentity::ONE));
+
+        // Streams sent by the crashed helper fail, streams sent by other helpers do not.
+        assert!(send(&injector, HelperIdentity::ONE, "step").await[0].is_err());
+        assert_eq!(
+            send(&injector, HelperIdentity::TWO, "step").await,
+            ok(0..CHUNKS)
+        );
+    }
+
+    #[tokio::test]
+    async fn filters() {
+        let injector = FaultInjector::new(
+            0,
+            vec![FaultRule::new(Fault::CloseEarly, 1.0)
+                .from_helper(HelperIdentity::TWO)
+                .on_step("reveal")],
+        );
+        assert_eq!(
+            send(&injector, HelperIdentity::ONE, "/protocol/reveal").await,
+            ok(0..CHUNKS)
+        );
+        assert_eq!(
+            send(&injector, HelperIdentity::TWO, "/protocol/multiply").await,
+            ok(0..CHUNKS)
+        );
+        assert_eq!(
+            send(&injector, HelperIdentity::TWO, "/protocol/reveal").await,
+            ok([])
+        );
+    }
+
+    #[tokio::test]
+    async fn at_most() {
+        let injector =
+            FaultInjector::new(0, vec![FaultRule::new(Fault::Duplicate, 1.0).at_most(2)]);
+        let received = send(&injector, HelperIdentity::ONE, "a").await;
+        assert_eq!(received.len(), usize::from(CHUNKS) + 2);
+        assert_eq!(
+            send(&injector, HelperIdentity::ONE, "b").await,
+            ok(0..CHUNKS)
+        );
+        assert_eq!(injector.injected().len(), 2);
+    }
+
+    /// Queries run with faults injected into the in-memory transport must either succeed with
+    /// the right result or fail with an error, and never hang.
+    mod queries {
+        use std::{iter::zip, time::Duration};
+
+        use futures::future::try_join_all;
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        use super::super::{Fault, FaultInjector, FaultRule};
+        use crate::{
+            error::Error,
+            ff::Fp31,
+            helpers::{self, HelperIdentity},
+            protocol::{basics::SecureMul, context::Context, RecordId},
+            secret_sharing::IntoShares,
+            seq_join::SeqJoin,
+            sync::Arc,
+            test_fixture::{Reconstruct, TestWorld, TestWorldConfig},
+        };
+
+        const COUNT: usize = 200;
+        /// Generous, so that the tests do not fail on slow machines. A query that does not
+        /// complete within this time is considered hung.
+        const TIMEOUT: Duration = Duration::from_secs(30);
+
+        /// Multiplies `COUNT` pairs of values with faults injected by `injector`, and checks
+        /// that the query either succeeds with the right result or fails with an error that
+        /// says which channel was affected.
+        async fn multiply(injector: &Arc<FaultInjector>, seed: u64) -> Result<(), Error> {
+            let world =
+                TestWorld::new_with(TestWorldConfig::default().with_fault_injector(injector));
+            let mut rng = StdRng::seed_from_u64(seed);
+            let a = (0..COUNT).map(|_| rng.gen::<Fp31>()).collect::<Vec<_>>();
+            let b = (0..COUNT).map(|_| rng.gen::<Fp31>()).collect::<Vec<_>>();
+            let expected = zip(&a, &b).map(|(&a, &b)| a * b).collect::<Vec<_>>();
+            let shares = (a.into_iter(), b.into_iter()).share_with(&mut rng);
+
+            // Stop at the first helper that fails, as a real query would be aborted. Dropping
+            // the world then ends the streams of the other helpers.
+            let run = try_join_all(zip(world.contexts(), shares).map(|(ctx, (a, b))| {
+                let ctx = ctx.narrow("multiply").set_total_records(COUNT);
+                async move {
+                    ctx.try_join(zip(a, b).enumerate().map(|(i, (a, b))| {
+                        let ctx = ctx.clone();
+                        async move { a.multiply(&b, ctx, RecordId::from(i)).await }
+                    }))
+                    .await
+                }
+            }));
+            let results = tokio::time::timeout(TIMEOUT, run)
+                .await
+                .unwrap_or_else(|_| panic!("query hung with faults {:?}", injector.injected()))
+                .map_err(|e| {
+                    assert!(
+                        matches!(
+                            e,
+                            Error::MpcInfraError(
+                                helpers::Error::EndOfStream { .. }
+                                    | helpers::Error::ConnectionLost { .. }
+                                    | helpers::Error::DeserializeFailed { .. }
+                            )
+                        ),
+                        "unexpected error: {e:?}"
+                    );
+                    e
+                })?;
+
+            let results = <[_; 3]>::try_from(results).unwrap();
+            assert_eq!(expected, results.reconstruct());
+            Ok(())
+        }
+
+        fn lost_data(injector: &FaultInjector) -> bool {
+            injector.injected().iter().any(|injected| {
+                matches!(
+                    injected.fault,
+                    Fault::Disconnect | Fault::CloseEarly | Fault::Crash
+                )
+            })
+        }
+
+        #[tokio::test]
+        async fn delays() {
+            let injector = FaultInjector::new(
+                1,
+                vec![FaultRule::new(Fault::Delay(Duration::from_millis(2)), 1.0)],
+            );
+            multiply(&injector, 1).await.unwrap();
+            assert!(!injector.injected().is_empty());
+        }
+
+        #[tokio::test]
+        async fn disconnect() {
+            let injector = FaultInjector::new(
+                2,
+                vec![FaultRule::new(Fault::Disconnect, 1.0).from_helper(HelperIdentity::TWO)],
+            );
+            multiply(&injector, 2).await.unwrap_err();
+        }
+
+        #[tokio::test]
+        async fn close_early() {
+            let injector = FaultInjector::new(
+                3,
+                vec![FaultRule::new(Fault::CloseEarly, 1.0).from_helper(HelperIdentity::ONE)],
+            );
+            multiply(&injector, 3).await.unwrap_err();
+        }
+
+        #[tokio::test]
+        async fn crash() {
+            let injector = FaultInjector::new(
+                4,
+                vec![FaultRule::new(Fault::Crash, 1.0).from_helper(HelperIdentity::THREE)],
+            );
+            multiply(&injector, 4).await.unwrap_err();
+            assert!(injector.is_crashed(HelperIdentity::THREE));
+        }
+
+        /// Duplicated chunks corrupt the stream. Semi-honest multiplication cannot detect that,
+        /// so the result is not checked, but the query must still complete. A receiver that got
+        /// all its records stops reading, so the sender can only finish if the chunks left
+        /// unread fit in its send buffer. One duplicated chunk always does.
+        #[tokio::test]
+        async fn duplicates_do_not_hang() {
+            let injector =
+                FaultInjector::new(5, vec![FaultRule::new(Fault::Duplicate, 0.5).at_most(1)]);
+            let world =
+                TestWorld::new_with(TestWorldConfig::default().with_fault_injector(&injector));
+            let mut rng = StdRng::seed_from_u64(5);
+            let a = (0..COUNT).map(|_| rng.gen::<Fp31>()).collect::<Vec<_>>();
+            let shares = (a.clone().into_iter(), a.into_iter()).share_with(&mut rng);
+            let run = try_join_all(zip(world.contexts(), shares).map(|(ctx, (a, b))| {
+                let ctx = ctx.narrow("multiply").set_total_records(COUNT);
+                async move {
+                    ctx.try_join(zip(a, b).enumerate().map(|(i, (a, b))| {
+                        let ctx = ctx.clone();
+                        async move { a.multiply(&b, ctx, RecordId::from(i)).await }
+                    }))
+                    .await
+                }
+            }));
+            let _ = tokio::time::timeout(TIMEOUT, run)
+                .await
+                .expect("query hung with duplicated chunks");
+            assert!(!injector.injected().is_empty());
+        }
+
+        /// Mixes faults with random seeds. The query fails if and only if data was lost.
+        #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+        async fn random_faults() {
+            for seed in 0..20 {
+                let injector = FaultInjector::new(
+                    seed,
+                    vec![
+                        FaultRule::new(Fault::Delay(Duration::from_millis(1)), 0.05),
+                        FaultRule::new(Fault::Disconnect, 0.01),
+                        FaultRule::new(Fault::CloseEarly, 0.01),
+                        FaultRule::new(Fault::Crash, 0.005),
+                    ],
+                );
+                let result = multiply(&injector, seed).await;
+                assert_eq!(
+                    result.is_err(),
+                    lost_data(&injector),
+                    "seed {seed}: {result:?}, faults {:?}",
+                    injector.injected()
+                );
+            }
+        }
+    }
+}
@@ -15,6 +15,7 @@ use crate::{
         QueryKillStatus, QueryKilled, QueryStatus, QueryStatusError,
     },
     sync::{Arc, Mutex, Weak},
+    HelperState,
 };
 
 /// Represents some response sent from MPC helper acting on a given request. It is rudimental now
@@ -135,6 +136,13 @@ impl From<QueryStatus> for HelperResponse {
     }
 }
 
+impl From<HelperState> for HelperResponse {
+    fn from(value: HelperState) -> Self {
+        let v = serde_json::to_vec(&value).unwrap();
+        Self { body: v }
+    }
+}
+
 impl From<QueryKilled> for HelperResponse {
     fn from(value: QueryKilled) -> Self {
         let v = serde_json::to_vec(&json!({"query_id": value.0, "status": "killed"})).unwrap();
@@ -54,7 +54,7 @@ impl<F: Fn(&InspectContext, &mut Vec<u8>) + Send + Sync + 'static> StreamInterce
 }
 
 /// The general context provided to stream inspectors.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct InspectContext {
     /// The shard index of this instance.
     /// This is `None` for non-sharded helpers.
@@ -1,4 +1,5 @@
 pub mod config;
+pub mod shaping;
 mod sharding;
 mod transport;
 
@@ -6,9 +7,12 @@ pub use sharding::InMemoryShardNetwork;
 pub use transport::Setup;
 use transport::TransportConfigBuilder;
 
+#[cfg(any(test, feature = "test-fixture"))]
+use crate::helpers::transport::faults::FaultInjector;
 use crate::{
     helpers::{
-        in_memory_config::DynStreamInterceptor, transport::in_memory::config::passthrough,
+        in_memory_config::DynStreamInterceptor,
+        transport::in_memory::{config::passthrough, shaping::NetworkShaper},
         HandlerRef, HelperIdentity,
     },
     sync::{Arc, Weak},
@@ -43,10 +47,50 @@ impl InMemoryMpcNetwork {
     pub fn with_stream_interceptor(
         handlers: [Option<HandlerRef>; 3],
         interceptor: &DynStreamInterceptor,
+    ) -> Self {
+        Self::with_options(handlers, interceptor, None)
+    }
+
+    /// Creates the network with a stream interceptor, and delays the data sent over it with
+    /// `shaper`, if set (see [`shaping`]).
+    #[must_use]
+    pub fn with_options(
+        handlers: [Option<HandlerRef>; 3],
+        interceptor: &DynStreamInterceptor,
+        shaper: Option<&Arc<NetworkShaper>>,
+    ) -> Self {
+        Self::with_transport_config(handlers, |config_builder| {
+            config_builder
+                .with_interceptor(interceptor)
+                .with_network_shaper(shaper);
+        })
+    }
+
+    /// Same as [`Self::with_options`], and also injects faults into the data sent over the
+    /// network with `faults`, if set (see [`FaultInjector`]).
+    #[cfg(any(test, feature = "test-fixture"))]
+    #[must_use]
+    pub fn with_faults(
+        handlers: [Option<HandlerRef>; 3],
+        interceptor: &DynStreamInterceptor,
+        shaper: Option<&Arc<NetworkShaper>>,
+        faults: Option<&Arc<FaultInjector>>,
+    ) -> Self {
+        Self::with_transport_config(handlers, |config_builder| {
+            config_builder
+                .with_interceptor(interceptor)
+                .with_network_shaper(shaper)
+                .with_fault_injector(faults);
+        })
+    }
+
+    fn with_transport_config<F: Fn(&mut TransportConfigBuilder)>(
+        handlers: [Option<HandlerRef>; 3],
+        configure: F,
     ) -> Self {
         let [mut first, mut second, mut third]: [_; 3] = HelperIdentity::make_three().map(|i| {
             let mut config_builder = TransportConfigBuilder::for_helper(i);
-            config_builder.with_interceptor(interceptor);
+            configure(&mut config_builder);
 
             Setup::with_config(i, config_builder.not_sharded())
         });
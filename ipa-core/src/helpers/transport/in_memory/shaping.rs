@@ -0,0 +1,413 @@
+//! Simulation of network conditions for the in-memory transport.
+//!
+//! In-memory transports deliver data instantly, so queries run much faster than they would
+//! between helpers in different regions. A [`NetworkShaper`] delays the data sent over in-memory
+//! links according to a [`NetworkShape`]: latency, jitter, bandwidth, and a delay per chunk of
+//! data. It also records how long each step had data in flight, which is reported by
+//! [`NetworkShaper::report`].
+//!
+//! Data is delayed on its way to the receiver, without holding up the sender, except that the
+//! sender cannot send faster than the bandwidth allows. As with a real network, the sender is
+//! blocked once its send buffer is full (see [`GatewayConfig`]), so the effect of the network on
+//! `active_work` and `read_size` can be measured locally.
+//!
+//! [`GatewayConfig`]: crate::helpers::GatewayConfig
+
+use std::{
+    borrow::Cow,
+    cmp::{max, Reverse},
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+    num::NonZeroU64,
+    time::Duration,
+};
+
+use ::tokio::{
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use rand::Rng;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use super::transport::{InMemoryStream, StreamItem};
+use crate::{
+    helpers::{in_memory_config::InspectContext, HelperIdentity},
+    sharding::ShardIndex,
+    sync::{Arc, Mutex},
+};
+
+/// Conditions on a simulated network link, in one direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkShape {
+    /// Time for data to travel from the sender to the receiver.
+    pub latency: Duration,
+    /// Maximum random delay added to the latency of each chunk of data. Chunks of the same
+    /// stream are still delivered in order.
+    pub jitter: Duration,
+    /// Maximum rate at which data can be sent over the link, in bytes per second. `None` means
+    /// unlimited. The bandwidth is shared by all streams on the link.
+    pub bandwidth: Option<NonZeroU64>,
+    /// Time for which each chunk of data occupies the link, in addition to its transmission
+    /// time. This models per-packet or per-frame overheads, which make small chunks expensive.
+    pub batch_delay: Duration,
+}
+
+impl LinkShape {
+    /// A link between helpers in different cloud regions: 40ms latency with 5ms jitter, and
+    /// 1 Gbps of bandwidth.
+    #[must_use]
+    pub fn cross_region() -> Self {
+        Self {
+            latency: Duration::from_millis(40),
+            jitter: Duration::from_millis(5),
+            bandwidth: NonZeroU64::new(125_000_000),
+            batch_delay: Duration::ZERO,
+        }
+    }
+
+    fn is_instant(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Time for which a chunk of `len` bytes occupies the link.
+    #[allow(clippy::cast_precision_loss)]
+    fn transmission_time(&self, len: usize) -> Duration {
+        let transmission = self.bandwidth.map_or(Duration::ZERO, |bandwidth| {
+            Duration::from_secs_f64(len as f64 / bandwidth.get() as f64)
+        });
+        transmission + self.batch_delay
+    }
+
+    fn propagation_time(&self) -> Duration {
+        if self.jitter.is_zero() {
+            self.latency
+        } else {
+            self.latency + rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+        }
+    }
+}
+
+/// Network conditions for all the links of an in-memory network.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkShape {
+    /// Shape of the links between helpers, unless set for a particular link with
+    /// [`Self::with_link`].
+    pub helper_links: LinkShape,
+    /// Shape of the links between shards of the same helper.
+    pub shard_links: LinkShape,
+    links: HashMap<(HelperIdentity, HelperIdentity), LinkShape>,
+}
+
+impl NetworkShape {
+    /// Uses the same shape for all the links between helpers.
+    #[must_use]
+    pub fn helper_links(shape: LinkShape) -> Self {
+        Self {
+            helper_links: shape,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the shape of the link from helper `from` to helper `to`. The link in the other
+    /// direction is not affected.
+    #[must_use]
+    pub fn with_link(mut self, from: HelperIdentity, to: HelperIdentity, shape: LinkShape) -> Self {
+        self.links.insert((from, to), shape);
+        self
+    }
+
+    fn link(&self, from: HelperIdentity, to: HelperIdentity) -> LinkShape {
+        self.links
+            .get(&(from, to))
+            .copied()
+            .unwrap_or(self.helper_links)
+    }
+}
+
+/// Identifies a link. All the shards of a helper share the links to the other helpers, so
+/// the MPC links are identified by helpers only.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum LinkId {
+    Helpers(HelperIdentity, HelperIdentity),
+    Shards(HelperIdentity, ShardIndex, Cow<'static, str>),
+}
+
+/// Applies a [`NetworkShape`] to the streams sent over in-memory transports. The same shaper
+/// should be used by all the transports of a network, so that streams on the same link share
+/// its bandwidth.
+#[derive(Debug)]
+pub struct NetworkShaper {
+    shape: NetworkShape,
+    /// Time until which each link is busy sending data.
+    links: Mutex<HashMap<LinkId, Instant>>,
+    steps: Mutex<BTreeMap<String, StepNetworkStats>>,
+}
+
+impl NetworkShaper {
+    #[must_use]
+    pub fn new(shape: NetworkShape) -> Arc<Self> {
+        Arc::new(Self {
+            shape,
+            links: Mutex::new(HashMap::new()),
+            steps: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Returns the amount of data sent and the time spent on the network by each step so far.
+    ///
+    /// ## Panics
+    /// If a mutex is poisoned.
+    #[must_use]
+    pub fn report(&self) -> NetworkReport {
+        NetworkReport {
+            steps: self.steps.lock().unwrap().clone(),
+        }
+    }
+
+    /// Delays the chunks of `stream`, sent on the link and for the gate described by `ctx`.
+    pub(super) fn shape<S>(self: &Arc<Self>, ctx: &InspectContext, stream: S) -> InMemoryStream
+    where
+        S: Stream<Item = StreamItem> + Send + 'static,
+    {
+        let (link_id, shape) = if let Some(shard_index) = ctx.shard_index {
+            (
+                LinkId::Shards(ctx.identity, shard_index, ctx.dest.clone()),
+                self.shape.shard_links,
+            )
+        } else {
+            let dest = HelperIdentity::try_from(ctx.dest.as_ref())
+                .unwrap_or_else(|e| panic!("Can't resolve helper identity for {}: {e}", ctx.dest));
+            (
+                LinkId::Helpers(ctx.identity, dest),
+                self.shape.link(ctx.identity, dest),
+            )
+        };
+        let step = ctx.gate.as_ref().to_string();
+        let this = Arc::clone(self);
+
+        if shape.is_instant() {
+            // Still account for the data sent, so that the report covers all steps.
+            return InMemoryStream::wrap(stream.inspect(move |chunk| {
+                this.record(&step, chunk_len(chunk), Duration::ZERO);
+            }));
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        ::tokio::spawn(async move {
+            let mut stream = std::pin::pin!(stream);
+            let mut last_delivery = Instant::now();
+            while let Some(chunk) = stream.next().await {
+                let ready = Instant::now();
+                let len = chunk_len(&chunk);
+                let sent = this.transmit(&link_id, ready, shape.transmission_time(len));
+                let delivery = max(sent + shape.propagation_time(), last_delivery);
+                // Time during which this stream had data in flight, not counting the time
+                // already accounted for by the previous chunk.
+                let in_flight = delivery.saturating_duration_since(max(ready, last_delivery));
+                this.record(&step, len, in_flight);
+                last_delivery = delivery;
+
+                if tx.send((delivery, chunk)).is_err() {
+                    break;
+                }
+                // The sender can't send the next chunk before this one has left.
+                sleep_until(sent).await;
+            }
+        });
+
+        InMemoryStream::wrap(UnboundedReceiverStream::new(rx).then(
+            |(delivery, chunk)| async move {
+                sleep_until(delivery).await;
+                chunk
+            },
+        ))
+    }
+
+    /// Reserves the link for `duration`, starting when it is free, but not before `ready`.
+    /// Returns the time when the link is free again.
+    fn transmit(&self, link: &LinkId, ready: Instant, duration: Duration) -> Instant {
+        let mut links = self.links.lock().unwrap();
+        let busy_until = links.entry(link.clone()).or_insert(ready);
+        *busy_until = max(*busy_until, ready) + duration;
+        *busy_until
+    }
+
+    fn record(&self, step: &str, len: usize, in_flight: Duration) {
+        let mut steps = self.steps.lock().unwrap();
+        let stats = if let Some(stats) = steps.get_mut(step) {
+            stats
+        } else {
+            steps.entry(step.to_string()).or_default()
+        };
+        stats.bytes += len as u64;
+        stats.chunks += 1;
+        stats.network_time += in_flight;
+    }
+}
+
+fn chunk_len(chunk: &StreamItem) -> usize {
+    chunk.as_ref().map_or(0, Bytes::len)
+}
+
+/// Data sent by a step, and the time it spent on the network.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StepNetworkStats {
+    pub bytes: u64,
+    pub chunks: u64,
+    /// Total time during which the streams of the step had data in flight: waiting for the link,
+    /// being transmitted, or propagating to the receiver. Streams to different helpers are
+    /// counted separately, so this can be longer than the step took.
+    pub network_time: Duration,
+}
+
+/// Network usage by step, produced by [`NetworkShaper::report`].
+#[derive(Clone, Debug, Default)]
+pub struct NetworkReport {
+    pub steps: BTreeMap<String, StepNetworkStats>,
+}
+
+impl NetworkReport {
+    /// Total time spent on the network by all steps.
+    #[must_use]
+    pub fn network_time(&self) -> Duration {
+        self.steps.values().map(|s| s.network_time).sum()
+    }
+}
+
+impl Display for NetworkReport {
+    /// Lists the steps by the time they spent on the network, longest first.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut steps = self.steps.iter().collect::<Vec<_>>();
+        steps.sort_by_key(|(_, s)| Reverse(s.network_time));
+        writeln!(f, "Step,Network time (ms),Bytes sent,Chunks sent")?;
+        for (step, stats) in steps {
+            writeln!(
+                f,
+                "{step},{},{},{}",
+                stats.network_time.as_millis(),
+                stats.bytes,
+                stats.chunks
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{num::NonZeroU64, time::Duration};
+
+    use futures::StreamExt;
+    use tokio::time::Instant;
+
+    use super::{LinkShape, NetworkShape, NetworkShaper};
+    use crate::{
+        helpers::{transport::routing::RouteId, HelperIdentity, InMemoryMpcNetwork, Transport},
+        protocol::{Gate, QueryId},
+        sync::Arc,
+    };
+
+    const STEP: &str = "shaped";
+
+    /// Sends `chunks` from helper 1 to helper 2 and returns the time it took to receive them.
+    async fn send(shaper: &Arc<NetworkShaper>, chunks: Vec<Vec<u8>>) -> Duration {
+        let network = InMemoryMpcNetwork::with_options(
+            InMemoryMpcNetwork::noop_handlers(),
+            &crate::helpers::in_memory_config::passthrough(),
+            Some(shaper),
+        );
+        let gate = Gate::from(STEP);
+        let expected = chunks.concat();
+        let start = Instant::now();
+        network
+            .transport(HelperIdentity::ONE)
+            .send(
+                HelperIdentity::TWO,
+                (RouteId::Records, QueryId, gate.clone()),
+                futures::stream::iter(chunks),
+            )
+            .await
+            .unwrap();
+        let received = network
+            .transport(HelperIdentity::TWO)
+            .receive(HelperIdentity::ONE, (QueryId, gate))
+            .into_bytes_stream()
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        assert_eq!(expected, received);
+        start.elapsed()
+    }
+
+    #[tokio::test]
+    async fn latency() {
+        let shaper = NetworkShaper::new(NetworkShape::helper_links(LinkShape {
+            latency: Duration::from_millis(50),
+            ..LinkShape::default()
+        }));
+        // Chunks are pipelined, so the latency is paid once.
+        let elapsed = send(&shaper, vec![vec![1; 10]; 10]).await;
+        assert!(elapsed >= Duration::from_millis(50), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(500), "{elapsed:?}");
+
+        let stats = shaper.report().steps[STEP];
+        assert_eq!(stats.bytes, 100);
+        assert_eq!(stats.chunks, 10);
+        assert!(stats.network_time >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn bandwidth() {
+        let shaper = NetworkShaper::new(NetworkShape::helper_links(LinkShape {
+            bandwidth: NonZeroU64::new(10_000),
+            ..LinkShape::default()
+        }));
+        // 1000 bytes at 10kB/s take 100ms.
+        let elapsed = send(&shaper, vec![vec![1; 100]; 10]).await;
+        assert!(elapsed >= Duration::from_millis(100), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn per_link_shape() {
+        let slow = LinkShape {
+            latency: Duration::from_millis(200),
+            ..LinkShape::default()
+        };
+        // The link from helper 2 to helper 1 is slow, the other way is not.
+        let shaper = NetworkShaper::new(NetworkShape::default().with_link(
+            HelperIdentity::TWO,
+            HelperIdentity::ONE,
+            slow,
+        ));
+        let elapsed = send(&shaper, vec![vec![1; 10]]).await;
+        assert!(elapsed < Duration::from_millis(200), "{elapsed:?}");
+        assert_eq!(shaper.report().steps[STEP].network_time, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn jitter_keeps_order() {
+        let shaper = NetworkShaper::new(NetworkShape::helper_links(LinkShape {
+            latency: Duration::from_millis(1),
+            jitter: Duration::from_millis(20),
+            ..LinkShape::default()
+        }));
+        // `send` checks that the chunks are received in order.
+        send(&shaper, (0..50).map(|i| vec![i; 3]).collect()).await;
+    }
+
+    #[test]
+    fn report() {
+        let shaper = NetworkShaper::new(NetworkShape::default());
+        shaper.record("a", 10, Duration::from_millis(5));
+        shaper.record("b", 20, Duration::from_millis(7));
+        shaper.record("a", 10, Duration::from_millis(5));
+        let report = shaper.report();
+        assert_eq!(report.network_time(), Duration::from_millis(17));
+        assert_eq!(
+            report.to_string(),
+            "Step,Network time (ms),Bytes sent,Chunks sent\na,10,20,2\nb,7,20,1\n"
+        );
+    }
+}
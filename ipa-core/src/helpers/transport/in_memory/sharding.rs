@@ -1,7 +1,12 @@
+#[cfg(any(test, feature = "test-fixture"))]
+use crate::helpers::transport::faults::FaultInjector;
 use crate::{
     helpers::{
         in_memory_config::{passthrough, DynStreamInterceptor},
-        transport::in_memory::transport::{InMemoryTransport, Setup, TransportConfigBuilder},
+        transport::in_memory::{
+            shaping::NetworkShaper,
+            transport::{InMemoryTransport, Setup, TransportConfigBuilder},
+        },
         HelperIdentity,
     },
     sharding::ShardIndex,
@@ -29,11 +34,47 @@ impl InMemoryShardNetwork {
     pub fn with_stream_interceptor<I: Into<ShardIndex>>(
         shard_count: I,
         interceptor: &DynStreamInterceptor,
+    ) -> Self {
+        Self::with_options(shard_count, interceptor, None)
+    }
+
+    pub fn with_options<I: Into<ShardIndex>>(
+        shard_count: I,
+        interceptor: &DynStreamInterceptor,
+        shaper: Option<&Arc<NetworkShaper>>,
+    ) -> Self {
+        Self::with_transport_config(shard_count, |config_builder| {
+            config_builder
+                .with_interceptor(interceptor)
+                .with_network_shaper(shaper);
+        })
+    }
+
+    /// Same as [`Self::with_options`], and also injects faults into the data sent between the
+    /// shards with `faults`, if set (see [`FaultInjector`]).
+    #[cfg(any(test, feature = "test-fixture"))]
+    pub fn with_faults<I: Into<ShardIndex>>(
+        shard_count: I,
+        interceptor: &DynStreamInterceptor,
+        shaper: Option<&Arc<NetworkShaper>>,
+        faults: Option<&Arc<FaultInjector>>,
+    ) -> Self {
+        Self::with_transport_config(shard_count, |config_builder| {
+            config_builder
+                .with_interceptor(interceptor)
+                .with_network_shaper(shaper)
+                .with_fault_injector(faults);
+        })
+    }
+
+    fn with_transport_config<I: Into<ShardIndex>, F: Fn(&mut TransportConfigBuilder)>(
+        shard_count: I,
+        configure: F,
     ) -> Self {
         let shard_count = shard_count.into();
         let shard_network: [_; 3] = HelperIdentity::make_three().map(|h| {
             let mut config_builder = TransportConfigBuilder::for_helper(h);
-            config_builder.with_interceptor(interceptor);
+            configure(&mut config_builder);
 
             let mut shard_connections = shard_count
                 .iter()
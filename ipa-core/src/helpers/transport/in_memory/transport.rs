@@ -18,13 +18,15 @@ use shuttle::future as tokio;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::Instrument;
 
+#[cfg(any(test, feature = "test-fixture"))]
+use crate::helpers::transport::faults::FaultInjector;
 use crate::{
     error::BoxError,
     helpers::{
         in_memory_config,
         in_memory_config::DynStreamInterceptor,
         transport::{
-            in_memory::config::InspectContext,
+            in_memory::{config::InspectContext, shaping::NetworkShaper},
             routing::{Addr, RouteId},
         },
         ApiError, BodyStream, HandlerRef, HelperIdentity, HelperResponse, NoResourceIdentifier,
@@ -43,7 +45,7 @@ type Packet<I> = (
 );
 type ConnectionTx<I> = Sender<Packet<I>>;
 type ConnectionRx<I> = Receiver<Packet<I>>;
-type StreamItem = Result<Bytes, BoxError>;
+pub(super) type StreamItem = Result<Bytes, BoxError>;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error<I> {
@@ -120,7 +122,8 @@ impl<I: TransportIdentity> InMemoryTransport<I> {
                             | RouteId::QueryInput
                             | RouteId::QueryStatus
                             | RouteId::CompleteQuery
-                            | RouteId::KillQuery => {
+                            | RouteId::KillQuery
+                            | RouteId::HelperState => {
                                 handler
                                     .as_ref()
                                     .expect("Handler is set")
@@ -193,23 +196,41 @@ impl<I: TransportIdentity> Transport for Weak<InMemoryTransport<I>> {
             gate,
         });
 
-        channel
-            .send((
-                addr,
-                InMemoryStream::wrap(data.map({
-                    move |mut chunk| {
-                        if let Some(ref context) = context {
-                            this.config.stream_interceptor.peek(context, &mut chunk);
-                        }
-                        Ok(Bytes::from(chunk))
-                    }
-                })),
-                ack_tx,
-            ))
-            .await
-            .map_err(|_e| {
-                io::Error::new::<String>(io::ErrorKind::ConnectionAborted, "channel closed".into())
-            })?;
+        #[cfg(any(test, feature = "test-fixture"))]
+        let faults = this.config.fault_injector.clone();
+        let shaper = this.config.network_shaper.clone();
+        let step_context = context.clone();
+        let mut data = InMemoryStream::wrap(data.map({
+            move |mut chunk| {
+                if let Some(ref context) = context {
+                    this.config.stream_interceptor.peek(context, &mut chunk);
+                }
+                Ok(Bytes::from(chunk))
+            }
+        }));
+        if let Some(context) = step_context {
+            #[cfg(any(test, feature = "test-fixture"))]
+            if let Some(faults) = faults {
+                let to = match context.shard_index {
+                    Some(shard_index) => format!("{shard_index:?}->{}", context.dest),
+                    None => context.dest.to_string(),
+                };
+                data = InMemoryStream::wrap(faults.inject(
+                    context.identity,
+                    &to,
+                    &context.gate,
+                    0,
+                    data,
+                ));
+            }
+            if let Some(shaper) = shaper {
+                data = shaper.shape(&context, data);
+            }
+        }
+
+        channel.send((addr, data, ack_tx)).await.map_err(|_e| {
+            io::Error::new::<String>(io::ErrorKind::ConnectionAborted, "channel closed".into())
+        })?;
 
         ack_rx
             .await
@@ -244,7 +265,7 @@ pub struct InMemoryStream {
 }
 
 impl InMemoryStream {
-    fn wrap<S: Stream<Item = StreamItem> + Send + 'static>(value: S) -> Self {
+    pub(super) fn wrap<S: Stream<Item = StreamItem> + Send + 'static>(value: S) -> Self {
         Self {
             inner: Box::pin(value),
         }
@@ -629,11 +650,17 @@ pub struct TransportConfig {
     pub shard_index: Option<ShardIndex>,
     pub identity: HelperIdentity,
     pub stream_interceptor: DynStreamInterceptor,
+    pub network_shaper: Option<Arc<NetworkShaper>>,
+    #[cfg(any(test, feature = "test-fixture"))]
+    pub fault_injector: Option<Arc<FaultInjector>>,
 }
 
 pub struct TransportConfigBuilder {
     identity: HelperIdentity,
     stream_interceptor: DynStreamInterceptor,
+    network_shaper: Option<Arc<NetworkShaper>>,
+    #[cfg(any(test, feature = "test-fixture"))]
+    fault_injector: Option<Arc<FaultInjector>>,
 }
 
 impl TransportConfigBuilder {
@@ -641,6 +668,9 @@ impl TransportConfigBuilder {
         Self {
             identity,
             stream_interceptor: in_memory_config::passthrough(),
+            network_shaper: None,
+            #[cfg(any(test, feature = "test-fixture"))]
+            fault_injector: None,
         }
     }
 
@@ -650,11 +680,27 @@ impl TransportConfigBuilder {
         self
     }
 
+    pub fn with_network_shaper(&mut self, shaper: Option<&Arc<NetworkShaper>>) -> &mut Self {
+        self.network_shaper = shaper.cloned();
+
+        self
+    }
+
+    #[cfg(any(test, feature = "test-fixture"))]
+    pub fn with_fault_injector(&mut self, faults: Option<&Arc<FaultInjector>>) -> &mut Self {
+        self.fault_injector = faults.cloned();
+
+        self
+    }
+
     pub fn bind_to_shard(&self, shard_index: ShardIndex) -> TransportConfig {
         TransportConfig {
             shard_index: Some(shard_index),
             identity: self.identity,
             stream_interceptor: Arc::clone(&self.stream_interceptor),
+            network_shaper: self.network_shaper.clone(),
+            #[cfg(any(test, feature = "test-fixture"))]
+            fault_injector: self.fault_injector.clone(),
         }
     }
 
@@ -663,6 +709,9 @@ impl TransportConfigBuilder {
             shard_index: None,
             identity: self.identity,
             stream_interceptor: Arc::clone(&self.stream_interceptor),
+            network_shaper: self.network_shaper.clone(),
+            #[cfg(any(test, feature = "test-fixture"))]
+            fault_injector: self.fault_injector.clone(),
         }
     }
 }
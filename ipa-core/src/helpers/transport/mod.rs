@@ -12,6 +12,8 @@ use crate::{
     protocol::{Gate, QueryId},
 };
 
+#[cfg(any(test, feature = "test-fixture"))]
+pub mod faults;
 mod handler;
 #[cfg(feature = "in-memory-infra")]
 mod in_memory;
@@ -24,7 +26,7 @@ pub use handler::{
     make_owned_handler, Error as ApiError, HandlerBox, HandlerRef, HelperResponse, RequestHandler,
 };
 #[cfg(feature = "in-memory-infra")]
-pub use in_memory::{config, InMemoryMpcNetwork, InMemoryShardNetwork, InMemoryTransport};
+pub use in_memory::{config, shaping, InMemoryMpcNetwork, InMemoryShardNetwork, InMemoryTransport};
 pub use receive::{LogErrors, ReceiveRecords};
 #[cfg(feature = "web-app")]
 pub use stream::WrappedAxumBodyStream;
@@ -1,4 +1,5 @@
 mod hybrid;
+mod reach_frequency;
 
 use std::{
     fmt::{Debug, Display, Formatter},
@@ -6,15 +7,19 @@ use std::{
 };
 
 pub use hybrid::HybridQueryParams;
+pub use reach_frequency::ReachFrequencyQueryConfig;
 use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::{
-    ff::FieldType,
+    ff::{
+        fixed_point::{AttributionWeights, FixedPointScale},
+        FieldType,
+    },
     helpers::{
         transport::{routing::RouteId, BodyStream, NoQueryId, NoStep},
         RoleAssignment, RouteParams,
     },
-    protocol::QueryId,
+    protocol::{ipa_prf::ValueBuckets, QueryId},
 };
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize)]
@@ -22,6 +27,13 @@ pub struct QuerySize(u32);
 
 impl QuerySize {
     pub const MAX: u32 = 1_000_000_000;
+
+    /// Returns the number of bits needed to give every element of a query of this size
+    /// a unique counter, i.e. `⌈log₂ N⌉`.
+    #[must_use]
+    pub fn counter_bits(self) -> u32 {
+        self.0.next_power_of_two().trailing_zeros()
+    }
 }
 
 impl<'de> Deserialize<'de> for QuerySize {
@@ -92,6 +104,15 @@ pub struct QueryConfig {
 pub enum QueryConfigError {
     #[error(transparent)]
     BadQuerySize(#[from] BadQuerySizeError),
+    #[error(
+        "max breakdown key {max_breakdown_key} is too large for {buckets} value buckets; \
+        at most {breakdowns} breakdowns are supported"
+    )]
+    TooManyBreakdowns {
+        max_breakdown_key: u32,
+        buckets: usize,
+        breakdowns: usize,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -155,11 +176,26 @@ impl QueryConfig {
     where
         S: TryInto<QuerySize, Error = BadQuerySizeError>,
     {
-        Ok(Self {
+        let config = Self {
             size: size.try_into()?,
             field_type,
             query_type,
-        })
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the parameters of the query that can be checked before any input is seen.
+    ///
+    /// ## Errors
+    /// If the query parameters are inconsistent.
+    pub fn validate(&self) -> Result<(), QueryConfigError> {
+        match &self.query_type {
+            QueryType::SemiHonestOprfIpa(config) | QueryType::MaliciousOprfIpa(config) => {
+                config.validate()
+            }
+            _ => Ok(()),
+        }
     }
 }
 
@@ -206,6 +242,8 @@ pub enum QueryType {
     SemiHonestOprfIpa(IpaQueryConfig),
     MaliciousOprfIpa(IpaQueryConfig),
     SemiHonestHybrid(HybridQueryParams),
+    SemiHonestReachFrequency(ReachFrequencyQueryConfig),
+    MaliciousReachFrequency(ReachFrequencyQueryConfig),
 }
 
 impl QueryType {
@@ -216,6 +254,8 @@ impl QueryType {
     pub const SEMI_HONEST_OPRF_IPA_STR: &'static str = "semi-honest-oprf-ipa";
     pub const MALICIOUS_OPRF_IPA_STR: &'static str = "malicious-oprf-ipa";
     pub const SEMI_HONEST_HYBRID_STR: &'static str = "semi-honest-hybrid";
+    pub const SEMI_HONEST_REACH_FREQUENCY_STR: &'static str = "semi-honest-reach-frequency";
+    pub const MALICIOUS_REACH_FREQUENCY_STR: &'static str = "malicious-reach-frequency";
 }
 
 /// TODO: should this `AsRef` impl (used for `Substep`) take into account config of IPA?
@@ -231,6 +271,8 @@ impl AsRef<str> for QueryType {
             QueryType::SemiHonestOprfIpa(_) => Self::SEMI_HONEST_OPRF_IPA_STR,
             QueryType::MaliciousOprfIpa(_) => Self::MALICIOUS_OPRF_IPA_STR,
             QueryType::SemiHonestHybrid(_) => Self::SEMI_HONEST_HYBRID_STR,
+            QueryType::SemiHonestReachFrequency(_) => Self::SEMI_HONEST_REACH_FREQUENCY_STR,
+            QueryType::MaliciousReachFrequency(_) => Self::MALICIOUS_REACH_FREQUENCY_STR,
         }
     }
 }
@@ -266,6 +308,44 @@ pub struct IpaQueryConfig {
     #[cfg_attr(feature = "clap", arg(long))]
     #[serde(default)]
     pub plaintext_match_keys: bool,
+
+    /// If true, helpers only decrypt and validate the input reports, without running the
+    /// protocol. The result of the query is a summary of the reports that each helper rejected,
+    /// by reason, instead of a histogram.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// If true, the input reports are in the [`ReportVersion::Legacy`] format, without a version
+    /// byte, as sent by encoders that predate it. All the reports of a query must be in the same
+    /// format, because the two cannot be told apart.
+    ///
+    /// [`ReportVersion::Legacy`]: crate::report::ReportVersion::Legacy
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub legacy_reports: bool,
+
+    /// If set, the output histogram counts attributed conversions into these value buckets,
+    /// given as comma-separated boundaries, instead of summing trigger values. Each breakdown
+    /// gets one histogram bin per bucket.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_buckets: Option<ValueBuckets>,
+
+    /// If set, trigger values are fixed-point numbers with this many units per whole value, e.g.
+    /// 100 for cents. The input reports carry 16-bit trigger values counted in units, and the
+    /// per-user credit cap, value bucket boundaries and output are in units too.
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger_value_scale: Option<FixedPointScale>,
+
+    /// If set, each attributed trigger value is split across the most recent source events that
+    /// precede it, given as comma-separated weights between 0 and 1, most recent touch first,
+    /// e.g. `0.5,0.3,0.2`. Each share is weighted before per-user capping and rounded to the
+    /// nearest unit, with halves rounded up. See [`AttributionWeights`].
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribution_weights: Option<AttributionWeights>,
 }
 
 impl Default for IpaQueryConfig {
@@ -277,11 +357,40 @@ impl Default for IpaQueryConfig {
             with_dp: 1,
             epsilon: 0.10,
             plaintext_match_keys: false,
+            dry_run: false,
+            legacy_reports: false,
+            value_buckets: None,
+            trigger_value_scale: None,
+            attribution_weights: None,
         }
     }
 }
 
 impl IpaQueryConfig {
+    /// Number of bins in the histogram that OPRF IPA queries output.
+    pub const HISTOGRAM_SIZE: usize = 256;
+
+    /// Checks that the output histogram has a bin for every breakdown and value bucket. The
+    /// breakdown keys of attributed conversions are revealed during aggregation, so this must
+    /// be rejected before the query runs rather than discovered from the data.
+    ///
+    /// ## Errors
+    /// If `max_breakdown_key` breakdowns with `value_buckets` do not fit in the histogram.
+    pub fn validate(&self) -> Result<(), QueryConfigError> {
+        if let Some(buckets) = self.value_buckets {
+            let breakdowns = buckets.breakdowns(Self::HISTOGRAM_SIZE);
+            if usize::try_from(self.max_breakdown_key).map_or(true, |bk| bk > breakdowns) {
+                return Err(QueryConfigError::TooManyBreakdowns {
+                    max_breakdown_key: self.max_breakdown_key,
+                    buckets: buckets.len(),
+                    breakdowns,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     /// ## Panics
     /// If attribution window is 0
     #[must_use]
@@ -303,6 +412,11 @@ impl IpaQueryConfig {
             epsilon,
             // dp_params,
             plaintext_match_keys: false,
+            dry_run: false,
+            legacy_reports: false,
+            value_buckets: None,
+            trigger_value_scale: None,
+            attribution_weights: None,
         }
     }
 
@@ -324,6 +438,11 @@ impl IpaQueryConfig {
             with_dp,
             epsilon,
             plaintext_match_keys: false,
+            dry_run: false,
+            legacy_reports: false,
+            value_buckets: None,
+            trigger_value_scale: None,
+            attribution_weights: None,
         }
     }
 }
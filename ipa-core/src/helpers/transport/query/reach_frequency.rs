@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+/// Configuration of a reach and frequency query.
+///
+/// The output histogram holds `max_breakdown_key` reach bins (the number of distinct users with
+/// at least one impression on each breakdown), followed by `max_frequency` frequency bins (the
+/// number of users with exactly 1, 2, ... impressions; the last bin counts users with
+/// `max_frequency` or more).
+///
+/// Each user adds to at most `max_breakdowns_per_user` reach bins and one frequency bin, so the
+/// noise added for differential privacy grows with `max_breakdowns_per_user`.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct ReachFrequencyQueryConfig {
+    #[cfg_attr(feature = "clap", arg(long, default_value = "5"))]
+    pub max_breakdown_key: u32,
+    /// Impressions beyond this many per user are not counted.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "8"))]
+    pub max_frequency: u32,
+    /// Each user counts toward the reach of at most this many breakdowns, the ones with the
+    /// smallest breakdown keys.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "4"))]
+    pub max_breakdowns_per_user: u32,
+    #[cfg_attr(feature = "clap", arg(short = 'd', long, default_value = "1"))]
+    pub with_dp: u32,
+    #[cfg_attr(feature = "clap", arg(short = 'e', long, default_value = "5.0"))]
+    pub epsilon: f64,
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub plaintext_match_keys: bool,
+}
+
+#[cfg(test)]
+impl Eq for ReachFrequencyQueryConfig {}
+
+/// The same as the command line defaults.
+impl Default for ReachFrequencyQueryConfig {
+    fn default() -> Self {
+        Self {
+            max_breakdown_key: 5,
+            max_frequency: 8,
+            max_breakdowns_per_user: 4,
+            with_dp: 1,
+            epsilon: 5.0,
+            plaintext_match_keys: false,
+        }
+    }
+}
+
+#[cfg(all(test, unit_test, feature = "clap"))]
+mod tests {
+    use clap::Parser;
+
+    use super::ReachFrequencyQueryConfig;
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        config: ReachFrequencyQueryConfig,
+    }
+
+    #[test]
+    fn command_line_defaults() {
+        let args = Args::try_parse_from(["test"]).unwrap();
+        assert_eq!(args.config, ReachFrequencyQueryConfig::default());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::report::{Epoch, EventType, KeyIdentifier, NonAsciiStringError};
+use crate::report::{Epoch, EventType, KeyIdentifier, NonAsciiStringError, ReportVersion};
 
 const DOMAIN: &str = "private-attribution";
 
@@ -17,6 +17,7 @@ pub struct Info<'a> {
     pub(super) event_type: EventType,
     pub(super) helper_origin: &'a str,
     pub(super) site_domain: &'a str,
+    pub(super) report_version: ReportVersion,
 }
 
 impl<'a> Info<'a> {
@@ -47,9 +48,21 @@ impl<'a> Info<'a> {
             event_type,
             helper_origin,
             site_domain,
+            report_version: ReportVersion::Legacy,
         })
     }
 
+    /// Binds the format version of the report to the encryption, so that a report cannot be
+    /// decrypted as a different version. Without this, the info has the [`ReportVersion::Legacy`]
+    /// layout, which does not include the version.
+    #[must_use]
+    pub fn with_report_version(self, report_version: ReportVersion) -> Self {
+        Self {
+            report_version,
+            ..self
+        }
+    }
+
     /// Converts this instance into an owned byte slice that can further be used to create HPKE
     /// sender or receiver context.
     pub(super) fn to_bytes(&self) -> Box<[u8]> {
@@ -59,7 +72,8 @@ impl<'a> Info<'a> {
             + 3 // account for 3 delimiters
             + std::mem::size_of_val(&self.key_id)
             + std::mem::size_of_val(&self.epoch)
-            + std::mem::size_of_val(&self.event_type);
+            + std::mem::size_of_val(&self.event_type)
+            + usize::from(self.report_version.byte().is_some());
         let mut r = Vec::with_capacity(info_len);
 
         r.extend_from_slice(DOMAIN.as_bytes());
@@ -73,6 +87,7 @@ impl<'a> Info<'a> {
         // Spec dictates epoch to be encoded in BE
         r.extend_from_slice(&self.epoch.to_be_bytes());
         r.push((&self.event_type).into());
+        r.extend(self.report_version.byte());
 
         debug_assert_eq!(r.len(), info_len, "HPKE Info length estimation is incorrect and leads to extra allocation or wasted memory");
 
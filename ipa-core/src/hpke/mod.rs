@@ -172,7 +172,7 @@ mod tests {
     use crate::{
         ff::{Gf40Bit, Serializable as IpaSerializable},
         hpke::{open_in_place, seal_in_place, CryptError, Info, IpaAead, KeyPair, KeyRegistry},
-        report::{Epoch, EventType, KeyIdentifier},
+        report::{Epoch, EventType, KeyIdentifier, ReportVersion},
         secret_sharing::replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
     };
 
@@ -310,6 +310,12 @@ mod tests {
             b"private-attribution\0foo\0bar\0\xff\x7f\xff\x01",
             info.to_bytes().as_ref()
         );
+
+        let info = info.with_report_version(ReportVersion::V1);
+        assert_eq!(
+            b"private-attribution\0foo\0bar\0\xff\x7f\xff\x01\x01",
+            info.to_bytes().as_ref()
+        );
     }
 
     #[test]
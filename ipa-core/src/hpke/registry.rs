@@ -99,6 +99,11 @@ impl<K> KeyRegistry<K> {
         }
     }
 
+    /// Returns the identifiers of the keys in this registry.
+    pub fn key_ids(&self) -> impl Iterator<Item = KeyIdentifier> + '_ {
+        (0..self.keys.len()).map_while(|i| KeyIdentifier::try_from(i).ok())
+    }
+
     fn key(&self, key_id: KeyIdentifier) -> Option<&K> {
         match key_id as usize {
             key_id if key_id < self.keys.len() => Some(&self.keys[key_id]),
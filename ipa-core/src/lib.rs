@@ -21,6 +21,8 @@ pub mod net;
 pub mod protocol;
 pub mod query;
 pub mod report;
+#[cfg(feature = "web-app")]
+pub mod report_collector;
 pub mod secret_sharing;
 pub mod telemetry;
 
@@ -32,7 +34,7 @@ mod seq_join;
 mod serde;
 pub mod sharding;
 mod utils;
-pub use app::{AppConfig, HelperApp, Setup as AppSetup};
+pub use app::{AppConfig, HelperApp, HelperState, Setup as AppSetup};
 pub use utils::NonZeroU32PowerOfTwo;
 
 extern crate core;
@@ -43,7 +45,7 @@ extern crate shuttle_crate as shuttle;
 pub(crate) mod sync {
     pub use shuttle::sync::{Arc, Mutex, MutexGuard, Weak};
     pub mod atomic {
-        pub use shuttle::sync::atomic::{AtomicUsize, Ordering};
+        pub use shuttle::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     }
 }
 
@@ -51,7 +53,7 @@ pub(crate) mod sync {
 pub(crate) mod sync {
     pub use std::sync::{Arc, Mutex, MutexGuard, Weak};
     pub mod atomic {
-        pub use std::sync::atomic::{AtomicUsize, Ordering};
+        pub use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     }
 }
 
@@ -0,0 +1,547 @@
+//! TLS certificate handling that goes beyond what rustls provides out of the box: identifying
+//! peers by a subject alternative name, reloading certificates when they change on disk, and
+//! warning about certificates that are about to expire.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::{Duration, Instant, SystemTime},
+};
+
+use metrics::{gauge, increment_counter};
+use once_cell::sync::Lazy;
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        verify_server_cert_signed_by_trust_anchor, ResolvesClientCert,
+    },
+    crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    server::{ClientHello, ParsedCertificate, ResolvesServerCert},
+    sign::CertifiedKey,
+    CertificateError, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use tracing::{error, info, warn};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use crate::{
+    net::{parse_certificate_and_private_key_bytes, CRYPTO_PROVIDER},
+    telemetry::metrics::{TLS_CERTIFICATE_EXPIRES_IN, TLS_CERTIFICATE_RELOADED},
+};
+
+/// Certificates that expire sooner than this produce a warning.
+pub const EXPIRY_WARNING_THRESHOLD: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often the warning for a certificate that is expiring or has expired is repeated. Certificates
+/// are checked on every handshake, so without this the warning would flood the logs.
+pub const EXPIRY_WARNING_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often [`ReloadableCertificate`] checks whether the certificate files have changed.
+pub const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+fn parse<'a>(cert: &'a CertificateDer<'_>) -> Option<X509Certificate<'a>> {
+    match X509Certificate::from_der(cert.as_ref()) {
+        Ok((_, cert)) => Some(cert),
+        Err(e) => {
+            error!("failed to parse X.509 certificate: {e}");
+            None
+        }
+    }
+}
+
+/// Returns the DNS and URI subject alternative names of `cert`.
+pub fn subject_alt_names(cert: &CertificateDer<'_>) -> Vec<String> {
+    let Some(cert) = parse(cert) else {
+        return Vec::new();
+    };
+    let Ok(Some(san)) = cert.subject_alternative_name() else {
+        return Vec::new();
+    };
+    san.value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) | GeneralName::URI(name) => Some((*name).to_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns `true` if `cert` carries `identity` as a DNS or URI subject alternative name.
+///
+/// DNS names are compared case-insensitively. Wildcard names are not supported, because each
+/// helper is expected to have a certificate of its own.
+pub fn has_identity(cert: &CertificateDer<'_>, identity: &str) -> bool {
+    subject_alt_names(cert)
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(identity))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExpiryState {
+    Valid,
+    ExpiringSoon,
+    Expired,
+}
+
+/// The expiry state each certificate was last seen in, and when it was last logged.
+static EXPIRY_WARNINGS: Lazy<Mutex<HashMap<String, (ExpiryState, Instant)>>> =
+    Lazy::new(Mutex::default);
+
+/// Records that the certificate `label` is in `state`, and returns whether that should be logged.
+/// It should if the state changed, or if the certificate is still not valid and was last logged
+/// more than [`EXPIRY_WARNING_INTERVAL`] ago.
+fn expiry_warning_due(label: &str, state: ExpiryState) -> bool {
+    let mut warnings = EXPIRY_WARNINGS.lock().unwrap();
+    let due = match warnings.get(label) {
+        Some(&(previous, logged_at)) => {
+            previous != state
+                || (state != ExpiryState::Valid && logged_at.elapsed() >= EXPIRY_WARNING_INTERVAL)
+        }
+        None => state != ExpiryState::Valid,
+    };
+    if due || !warnings.contains_key(label) {
+        warnings.insert(label.to_owned(), (state, Instant::now()));
+    }
+    due
+}
+
+/// Updates the expiry metric for `cert`, and logs a warning if it has expired or expires within
+/// [`EXPIRY_WARNING_THRESHOLD`]. `label` describes the certificate in logs and metrics.
+///
+/// The warning is logged when a certificate's state changes, and then at most once per
+/// [`EXPIRY_WARNING_INTERVAL`].
+///
+/// ## Panics
+/// If the lock is poisoned.
+pub fn check_expiry(label: &str, cert: &CertificateDer<'_>) {
+    let Some(cert) = parse(cert) else {
+        return;
+    };
+    let not_after = cert.validity().not_after.timestamp();
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX));
+    let remaining = not_after.saturating_sub(now);
+
+    #[allow(clippy::cast_precision_loss)]
+    let remaining_f64 = remaining as f64;
+    gauge!(TLS_CERTIFICATE_EXPIRES_IN, remaining_f64, "certificate" => label.to_owned());
+
+    let state = if remaining <= 0 {
+        ExpiryState::Expired
+    } else if remaining.unsigned_abs() < EXPIRY_WARNING_THRESHOLD.as_secs() {
+        ExpiryState::ExpiringSoon
+    } else {
+        ExpiryState::Valid
+    };
+    if !expiry_warning_due(label, state) {
+        return;
+    }
+    match state {
+        ExpiryState::Expired => error!("TLS certificate {label} has expired"),
+        ExpiryState::ExpiringSoon => warn!(
+            "TLS certificate {label} expires in {} days",
+            remaining.unsigned_abs() / (24 * 60 * 60)
+        ),
+        ExpiryState::Valid => info!("TLS certificate {label} is no longer close to expiry"),
+    }
+}
+
+/// Verifies peer server certificates against a set of root certificates, and checks that the
+/// certificate carries the expected peer identity as a subject alternative name.
+///
+/// Unlike the standard `WebPkiServerVerifier`, the identity does not have to be the host name
+/// used to connect to the peer. This allows identities like `spiffe://example.com/ipa/helper1`.
+#[derive(Debug)]
+pub struct PeerCertificateVerifier {
+    roots: Arc<RootCertStore>,
+    identity: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PeerCertificateVerifier {
+    pub fn new(roots: Arc<RootCertStore>, identity: String) -> Self {
+        Self {
+            roots,
+            identity,
+            provider: Arc::clone(&CRYPTO_PROVIDER),
+        }
+    }
+}
+
+impl ServerCertVerifier for PeerCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let cert = ParsedCertificate::try_from(end_entity)?;
+        verify_server_cert_signed_by_trust_anchor(
+            &cert,
+            &self.roots,
+            intermediates,
+            now,
+            self.provider.signature_verification_algorithms.all,
+        )?;
+        if !has_identity(end_entity, &self.identity) {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName,
+            ));
+        }
+        check_expiry(&self.identity, end_entity);
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// A certificate chain and private key that are read from files, and re-read when the files
+/// change.
+///
+/// This implements both [`ResolvesServerCert`] and [`ResolvesClientCert`], so the same instance
+/// can be used by the helper server and by the clients it uses to talk to other helpers. A
+/// background task checks the files for changes every [`RELOAD_CHECK_INTERVAL`], so TLS
+/// handshakes never wait on the file system. Connections that are already established keep using
+/// the certificate they were established with.
+///
+/// If reloading fails (e.g. because the files were only partially written), the previous
+/// certificate remains in use and the error is logged.
+#[derive(Debug)]
+pub struct ReloadableCertificate {
+    certificate_file: PathBuf,
+    private_key_file: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+    /// When the files were last modified, as of the last check.
+    modified: Mutex<Option<SystemTime>>,
+}
+
+fn last_modified(certificate_file: &Path, private_key_file: &Path) -> io::Result<SystemTime> {
+    Ok(fs::metadata(certificate_file)?
+        .modified()?
+        .max(fs::metadata(private_key_file)?.modified()?))
+}
+
+fn load(certificate_file: &Path, private_key_file: &Path) -> io::Result<Arc<CertifiedKey>> {
+    let (certs, key) = parse_certificate_and_private_key_bytes(
+        &mut io::BufReader::new(fs::File::open(certificate_file)?),
+        &mut io::BufReader::new(fs::File::open(private_key_file)?),
+    )?;
+    let key = CRYPTO_PROVIDER
+        .key_provider
+        .load_private_key(key)
+        .map_err(io::Error::other)?;
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
+
+impl ReloadableCertificate {
+    /// Loads the certificate chain and private key from PEM files, and starts the task that
+    /// reloads them when they change. The task stops when the certificate is dropped. Outside of
+    /// a Tokio runtime, no task is started, and the files are only re-read by [`Self::reload`].
+    ///
+    /// ## Errors
+    /// If the files cannot be read, or do not contain a valid certificate and private key.
+    pub fn from_files(
+        certificate_file: impl Into<PathBuf>,
+        private_key_file: impl Into<PathBuf>,
+    ) -> io::Result<Arc<Self>> {
+        Self::with_reload_interval(certificate_file, private_key_file, RELOAD_CHECK_INTERVAL)
+    }
+
+    fn with_reload_interval(
+        certificate_file: impl Into<PathBuf>,
+        private_key_file: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> io::Result<Arc<Self>> {
+        let certificate_file = certificate_file.into();
+        let private_key_file = private_key_file.into();
+        let modified = last_modified(&certificate_file, &private_key_file).ok();
+        let current = load(&certificate_file, &private_key_file)?;
+        check_expiry(&certificate_file.display().to_string(), &current.cert[0]);
+        let this = Arc::new(Self {
+            certificate_file,
+            private_key_file,
+            current: RwLock::new(current),
+            modified: Mutex::new(modified),
+        });
+
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(watch(Arc::downgrade(&this), interval));
+        } else {
+            warn!(
+                "no async runtime, {} will not be reloaded when it changes",
+                this.certificate_file.display()
+            );
+        }
+
+        Ok(this)
+    }
+
+    /// Returns the certificate chain and key currently in use.
+    ///
+    /// ## Panics
+    /// If the lock is poisoned.
+    pub fn certified_key(&self) -> Arc<CertifiedKey> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// Re-reads the certificate files if they have changed since they were last read, and checks
+    /// whether the certificate in use is about to expire.
+    ///
+    /// Returns `true` if a new certificate was loaded.
+    ///
+    /// ## Errors
+    /// If the files have changed, but cannot be loaded.
+    ///
+    /// ## Panics
+    /// If the lock is poisoned.
+    pub fn reload(&self) -> io::Result<bool> {
+        let mut previous = self.modified.lock().unwrap();
+        let modified = last_modified(&self.certificate_file, &self.private_key_file)?;
+        let reloaded = if *previous == Some(modified) {
+            false
+        } else {
+            let new = load(&self.certificate_file, &self.private_key_file)?;
+            *self.current.write().unwrap() = new;
+            *previous = Some(modified);
+            increment_counter!(TLS_CERTIFICATE_RELOADED);
+            info!(
+                "reloaded TLS certificate from {}",
+                self.certificate_file.display()
+            );
+            true
+        };
+        check_expiry(
+            &self.certificate_file.display().to_string(),
+            &self.certified_key().cert[0],
+        );
+        Ok(reloaded)
+    }
+}
+
+/// Reloads `certificate` every `interval`, until it is dropped. The files are read on the
+/// blocking thread pool, like the network configuration that the helper watches.
+async fn watch(certificate: Weak<ReloadableCertificate>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(certificate) = certificate.upgrade() else {
+            return;
+        };
+        let file = certificate.certificate_file.clone();
+        match tokio::task::spawn_blocking(move || certificate.reload()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => error!(
+                "failed to reload TLS certificate from {}, continuing to use the previous one: {e}",
+                file.display()
+            ),
+            Err(e) => error!("TLS certificate reload task failed: {e}"),
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertificate {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key())
+    }
+}
+
+impl ResolvesClientCert for ReloadableCertificate {
+    fn resolve(
+        &self,
+        _root_hint_subjects: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{fs, sync::Arc, time::Duration};
+
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, DistinguishedName, IsCa, SanType,
+        PKCS_ECDSA_P256_SHA256,
+    };
+    use rustls::{client::danger::ServerCertVerifier, RootCertStore};
+    use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+    use tempfile::tempdir;
+
+    use super::{
+        expiry_warning_due, has_identity, subject_alt_names, ExpiryState, PeerCertificateVerifier,
+        ReloadableCertificate,
+    };
+    use crate::net::test;
+
+    fn ca() -> Certificate {
+        let mut params = CertificateParams::default();
+        params.alg = &PKCS_ECDSA_P256_SHA256;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name = DistinguishedName::new();
+        Certificate::from_params(params).unwrap()
+    }
+
+    fn leaf(names: Vec<SanType>) -> Certificate {
+        let mut params = CertificateParams::default();
+        params.alg = &PKCS_ECDSA_P256_SHA256;
+        params.subject_alt_names = names;
+        Certificate::from_params(params).unwrap()
+    }
+
+    fn verify(ca: &Certificate, cert: &CertificateDer<'_>, identity: &str) -> bool {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from(ca.serialize_der().unwrap()))
+            .unwrap();
+        PeerCertificateVerifier::new(Arc::new(roots), identity.to_owned())
+            .verify_server_cert(
+                cert,
+                &[],
+                &ServerName::try_from("localhost").unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn identity_from_san() {
+        let cert = leaf(vec![
+            SanType::DnsName("helper1.example.com".into()),
+            SanType::URI("spiffe://example.com/ipa/helper1".into()),
+        ]);
+        let der = CertificateDer::from(cert.serialize_der().unwrap());
+        assert_eq!(
+            subject_alt_names(&der),
+            ["helper1.example.com", "spiffe://example.com/ipa/helper1"]
+        );
+        assert!(has_identity(&der, "HELPER1.example.com"));
+        assert!(has_identity(&der, "spiffe://example.com/ipa/helper1"));
+        assert!(!has_identity(&der, "spiffe://example.com/ipa/helper2"));
+    }
+
+    #[test]
+    fn verifies_chain_and_identity() {
+        let ca = ca();
+        let cert = leaf(vec![SanType::URI(
+            "spiffe://example.com/ipa/helper1".into(),
+        )]);
+        let der = CertificateDer::from(cert.serialize_der_with_signer(&ca).unwrap());
+
+        assert!(verify(&ca, &der, "spiffe://example.com/ipa/helper1"));
+        assert!(!verify(&ca, &der, "spiffe://example.com/ipa/helper2"));
+
+        // Signed by a different CA.
+        assert!(!verify(
+            &self::ca(),
+            &der,
+            "spiffe://example.com/ipa/helper1"
+        ));
+    }
+
+    #[test]
+    fn reload() {
+        let dir = tempdir().unwrap();
+        let cert_file = dir.path().join("cert.pem");
+        let key_file = dir.path().join("key.pem");
+        fs::write(&cert_file, test::TEST_CERTS[0]).unwrap();
+        fs::write(&key_file, test::TEST_KEYS[0]).unwrap();
+
+        let cert = ReloadableCertificate::from_files(&cert_file, &key_file).unwrap();
+        assert_eq!(cert.certified_key().cert[0], test::TEST_CERTS_DER[0]);
+        assert!(!cert.reload().unwrap());
+
+        // Make sure the modification time changes, even on file systems with coarse timestamps.
+        std::thread::sleep(Duration::from_millis(1100));
+        fs::write(&cert_file, test::TEST_CERTS[1]).unwrap();
+        fs::write(&key_file, test::TEST_KEYS[1]).unwrap();
+        assert!(cert.reload().unwrap());
+        assert_eq!(cert.certified_key().cert[0], test::TEST_CERTS_DER[1]);
+
+        // A broken certificate is not loaded, and the previous one stays in use.
+        std::thread::sleep(Duration::from_millis(1100));
+        fs::write(&cert_file, b"garbage").unwrap();
+        cert.reload().unwrap_err();
+        assert_eq!(cert.certified_key().cert[0], test::TEST_CERTS_DER[1]);
+    }
+
+    #[tokio::test]
+    async fn reloads_in_background() {
+        let dir = tempdir().unwrap();
+        let cert_file = dir.path().join("cert.pem");
+        let key_file = dir.path().join("key.pem");
+        fs::write(&cert_file, test::TEST_CERTS[0]).unwrap();
+        fs::write(&key_file, test::TEST_KEYS[0]).unwrap();
+
+        let cert = ReloadableCertificate::with_reload_interval(
+            &cert_file,
+            &key_file,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        fs::write(&cert_file, test::TEST_CERTS[1]).unwrap();
+        fs::write(&key_file, test::TEST_KEYS[1]).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while cert.certified_key().cert[0] != test::TEST_CERTS_DER[1] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn expiry_warning_only_on_state_change() {
+        let label = "expiry_warning_only_on_state_change";
+        assert!(!expiry_warning_due(label, ExpiryState::Valid));
+        assert!(expiry_warning_due(label, ExpiryState::ExpiringSoon));
+        assert!(!expiry_warning_due(label, ExpiryState::ExpiringSoon));
+        assert!(expiry_warning_due(label, ExpiryState::Expired));
+        assert!(!expiry_warning_due(label, ExpiryState::Expired));
+        assert!(expiry_warning_due(label, ExpiryState::Valid));
+        assert!(!expiry_warning_due(label, ExpiryState::Valid));
+    }
+}
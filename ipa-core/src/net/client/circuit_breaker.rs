@@ -0,0 +1,174 @@
+use tokio::time::Instant;
+
+use crate::{config::CircuitBreakerConfig, net::Error, sync::Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Requests are sent. Counts the transient failures since the last success.
+    Closed { failures: u32 },
+    /// Requests fail without being sent, until the deadline.
+    Open { until: Instant },
+    /// A single request was let through to probe the helper, at the given time. Other requests
+    /// fail until it completes, or until it has been outstanding for the open duration, in case
+    /// it was dropped before completing.
+    HalfOpen { since: Instant },
+}
+
+/// Tracks the failures of requests to one helper and decides whether new requests are sent. See
+/// [`CircuitBreakerConfig`] for the policy.
+#[derive(Debug)]
+pub(super) struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Returns `true` if a request may be sent now. The caller must report its outcome with
+    /// [`Self::record`].
+    pub fn allow(&self) -> bool {
+        if self.config.failure_threshold == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if now < until => false,
+            State::HalfOpen { since } if now < since + self.config.open_duration => false,
+            State::Open { .. } | State::HalfOpen { .. } => {
+                *state = State::HalfOpen { since: now };
+                true
+            }
+        }
+    }
+
+    /// Records the outcome of a request that [`Self::allow`] let through. Only transient failures
+    /// count: any response from the helper, even an error, shows that it is reachable.
+    pub fn record<T>(&self, result: &Result<T, Error>) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        let failed = matches!(result, Err(e) if e.is_transient());
+        let mut state = self.state.lock().unwrap();
+        *state = match (*state, failed) {
+            (_, false) => State::Closed { failures: 0 },
+            (State::Closed { failures }, true) if failures + 1 < self.config.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            // A request that was sent before the circuit opened does not extend the open period.
+            (State::Open { until }, true) => State::Open { until },
+            (State::Closed { .. } | State::HalfOpen { .. }, true) => State::Open {
+                until: Instant::now() + self.config.open_duration,
+            },
+        };
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::StatusCode;
+
+    use super::CircuitBreaker;
+    use crate::{config::CircuitBreakerConfig, net::Error};
+
+    const OPEN_DURATION: Duration = Duration::from_millis(100);
+
+    fn breaker(failure_threshold: u32) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold,
+            open_duration: OPEN_DURATION,
+        })
+    }
+
+    fn transient() -> Result<(), Error> {
+        Err(Error::Timeout {
+            dest: "helper".into(),
+            route: "echo",
+            timeout: Duration::from_secs(1),
+        })
+    }
+
+    fn fail(breaker: &CircuitBreaker, times: u32) {
+        for _ in 0..times {
+            assert!(breaker.allow());
+            breaker.record(&transient());
+        }
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(3);
+        fail(&breaker, 2);
+        assert!(breaker.allow());
+        breaker.record(&Ok(()));
+
+        // The success reset the count.
+        fail(&breaker, 3);
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn responses_are_not_failures() {
+        let breaker = breaker(1);
+        breaker.record::<()>(&Err(Error::FailedHttpRequest {
+            dest: "helper".into(),
+            status: StatusCode::NOT_FOUND,
+            reason: "not found".into(),
+        }));
+        assert!(breaker.allow());
+    }
+
+    #[tokio::test]
+    async fn half_open_lets_one_request_through() {
+        let breaker = breaker(2);
+        fail(&breaker, 2);
+        assert!(!breaker.allow());
+
+        tokio::time::sleep(OPEN_DURATION).await;
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+
+        // The probe failed, so the circuit opens again.
+        breaker.record(&transient());
+        assert!(!breaker.allow());
+
+        tokio::time::sleep(OPEN_DURATION).await;
+        assert!(breaker.allow());
+        breaker.record(&Ok(()));
+        assert!(breaker.allow());
+        assert!(breaker.allow());
+    }
+
+    #[tokio::test]
+    async fn dropped_probe() {
+        let breaker = breaker(1);
+        fail(&breaker, 1);
+        tokio::time::sleep(OPEN_DURATION).await;
+        assert!(breaker.allow());
+
+        // The probe never reports back. Another one is let through after the open duration.
+        assert!(!breaker.allow());
+        tokio::time::sleep(OPEN_DURATION).await;
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn disabled() {
+        let breaker = breaker(0);
+        for _ in 0..10 {
+            assert!(breaker.allow());
+            breaker.record(&transient());
+        }
+    }
+}
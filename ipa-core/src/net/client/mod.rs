@@ -1,10 +1,16 @@
+mod circuit_breaker;
+#[cfg(feature = "quic")]
+mod quic;
+
 use std::{
     collections::HashMap,
     future::Future,
     io::{self, BufRead},
+    path::PathBuf,
     pin::Pin,
     sync::Arc,
     task::{ready, Context, Poll},
+    time::Duration,
 };
 
 use axum::{
@@ -22,18 +28,29 @@ use hyper_util::{
 };
 use pin_project::pin_project;
 use rustls::RootCertStore;
-use tracing::error;
+use tokio::time::Sleep;
+use tracing::{error, warn};
 
+use self::circuit_breaker::CircuitBreaker;
+#[cfg(any(test, feature = "test-fixture"))]
+use crate::helpers::faults::FaultInjector;
 use crate::{
     config::{
-        ClientConfig, HyperClientConfigurator, NetworkConfig, OwnedCertificate, OwnedPrivateKey,
-        PeerConfig,
+        ClientConfig, Compression, CompressionConfig, HyperClientConfigurator, NetworkConfig,
+        OwnedCertificate, OwnedPrivateKey, PeerConfig, RetryConfig, StepStreamConfig,
+        StepTransport, TimeoutConfig,
     },
     helpers::{
         query::{PrepareQuery, QueryConfig, QueryInput},
         HelperIdentity,
     },
-    net::{http_serde, server::HTTP_CLIENT_ID_HEADER, Error, CRYPTO_PROVIDER},
+    net::{
+        certificate::{PeerCertificateVerifier, ReloadableCertificate},
+        compression, http_serde,
+        resume::RetainedRecords,
+        server::HTTP_CLIENT_ID_HEADER,
+        ChannelClass, Error, CRYPTO_PROVIDER,
+    },
     protocol::{Gate, QueryId},
 };
 
@@ -49,6 +66,12 @@ pub enum ClientIdentity {
     /// This is only supported for HTTPS clients.
     Certificate((Vec<OwnedCertificate>, OwnedPrivateKey)),
 
+    /// Authenticate with an X.509 certificate that is reloaded when the files it was read from
+    /// change.
+    ///
+    /// This is only supported for HTTPS clients.
+    Reloadable(Arc<ReloadableCertificate>),
+
     /// Do not authenticate nor claim a helper identity.
     #[default]
     None,
@@ -74,6 +97,22 @@ impl ClientIdentity {
         ))
     }
 
+    /// Authenticates clients with an X.509 certificate and private key read from PEM files. The
+    /// files are re-read when they change, so that the certificate can be rotated without
+    /// restarting.
+    ///
+    /// ## Errors
+    /// If the files cannot be read or are not in the required format.
+    pub fn from_files(
+        certificate_file: impl Into<PathBuf>,
+        private_key_file: impl Into<PathBuf>,
+    ) -> Result<Self, io::Error> {
+        Ok(Self::Reloadable(ReloadableCertificate::from_files(
+            certificate_file,
+            private_key_file,
+        )?))
+    }
+
     /// Rust-tls-types crate intentionally does not implement Clone on private key types in order
     /// to minimize the exposure of private key data in memory. Since `ClientBuilder` API requires
     /// to own a private key, and we need to create 3 with the same config, we provide Clone
@@ -82,6 +121,7 @@ impl ClientIdentity {
     pub fn clone_with_key(&self) -> ClientIdentity {
         match self {
             Self::Certificate((c, pk)) => Self::Certificate((c.clone(), pk.clone_key())),
+            Self::Reloadable(r) => Self::Reloadable(Arc::clone(r)),
             Self::Helper(h) => Self::Helper(*h),
             Self::None => Self::None,
         }
@@ -95,6 +135,15 @@ pub struct ResponseFuture<'a> {
     authority: &'a uri::Authority,
     #[pin]
     inner: hyper_util::client::legacy::ResponseFuture,
+    /// Fails the request with [`Error::Timeout`] if the response does not arrive in time.
+    deadline: Option<(Route, Duration, Pin<Box<Sleep>>)>,
+}
+
+impl ResponseFuture<'_> {
+    fn with_timeout(mut self, route: Route, timeout: Option<Duration>) -> Self {
+        self.deadline = timeout.map(|t| (route, t, Box::pin(tokio::time::sleep(t))));
+        self
+    }
 }
 
 /// Similar to [fut](ResponseFuture), wraps the response and keeps the URI authority for better
@@ -127,19 +176,101 @@ impl<'a> Future for ResponseFuture<'a> {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        match ready!(this.inner.poll(cx)) {
+        if let Poll::Ready(result) = this.inner.poll(cx) {
+            return Poll::Ready(Self::complete(this.authority, result));
+        }
+        if let Some((route, timeout, sleep)) = this.deadline {
+            ready!(sleep.as_mut().poll(cx));
+            return Poll::Ready(Err(Error::Timeout {
+                dest: this.authority.to_string(),
+                route: route.name(),
+                timeout: *timeout,
+            }));
+        }
+        Poll::Pending
+    }
+}
+
+impl<'a> ResponseFuture<'a> {
+    fn complete(
+        authority: &'a uri::Authority,
+        result: Result<Response<hyper::body::Incoming>, hyper_util::client::legacy::Error>,
+    ) -> Result<ResponseFromEndpoint<'a>, Error> {
+        match result {
             Ok(resp) => {
                 let (http_parts, http_body) = resp.into_parts();
                 let axum_resp = Response::from_parts(http_parts, Body::new(http_body));
-                Poll::Ready(Ok(ResponseFromEndpoint {
-                    authority: this.authority,
+                Ok(ResponseFromEndpoint {
+                    authority,
                     inner: axum_resp,
-                }))
+                })
             }
-            Err(e) => Poll::Ready(Err(Error::ConnectError {
-                dest: this.authority.to_string(),
+            Err(e) => Err(Error::ConnectError {
+                dest: authority.to_string(),
                 inner: e,
-            })),
+            }),
+        }
+    }
+}
+
+/// Routes that [`MpcHelperClient`] sends requests to. Used to look up the timeout and to decide
+/// whether a failed request can be retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Route {
+    Echo,
+    CreateQuery,
+    PrepareQuery,
+    QueryInput,
+    QueryStatus,
+    QueryResults,
+    KillQuery,
+    Step,
+    HelperState,
+}
+
+impl Route {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Echo => "echo",
+            Self::CreateQuery => "create query",
+            Self::PrepareQuery => "prepare query",
+            Self::QueryInput => "query input",
+            Self::QueryStatus => "query status",
+            Self::QueryResults => "query results",
+            Self::KillQuery => "kill query",
+            Self::Step => "step",
+            Self::HelperState => "helper state",
+        }
+    }
+
+    fn timeout(self, timeouts: &TimeoutConfig) -> Option<Duration> {
+        match self {
+            Self::Echo => timeouts.echo,
+            Self::CreateQuery => timeouts.create_query,
+            Self::PrepareQuery => timeouts.prepare_query,
+            Self::QueryInput => timeouts.query_input,
+            Self::QueryStatus => timeouts.query_status,
+            Self::QueryResults => timeouts.query_results,
+            Self::KillQuery => timeouts.kill_query,
+            Self::Step => timeouts.step,
+            Self::HelperState => timeouts.helper_state,
+        }
+    }
+
+    /// Whether sending the request more than once has the same effect as sending it once.
+    fn is_idempotent(self) -> bool {
+        matches!(
+            self,
+            Self::Echo | Self::QueryStatus | Self::QueryResults | Self::HelperState
+        )
+    }
+
+    /// Decides whether a request that failed with `error` may be sent again. See [`RetryConfig`]
+    /// for the policy.
+    fn should_retry(self, error: &Error) -> bool {
+        match self {
+            Self::QueryInput | Self::Step => false,
+            _ => error.is_connect_failure() || (self.is_idempotent() && error.is_transient()),
         }
     }
 }
@@ -155,6 +286,22 @@ pub struct MpcHelperClient {
     scheme: uri::Scheme,
     authority: uri::Authority,
     auth_header: Option<(HeaderName, HeaderValue)>,
+    retry: RetryConfig,
+    timeouts: TimeoutConfig,
+    /// Shared by the clones of this client, so that it sees all requests to the helper.
+    circuit_breaker: Arc<CircuitBreaker>,
+    step_stream: StepStreamConfig,
+    compression: CompressionConfig,
+    /// Encodings of step streams that the other helper accepts. See
+    /// [`PeerConfig::step_encodings`].
+    step_encodings: Vec<String>,
+    /// Set if step streams are sent over QUIC (see [`StepTransport::Quic`]).
+    #[cfg(feature = "quic")]
+    quic: Option<Arc<quic::QuicClient>>,
+    /// Injects faults into the step streams sent by this client, for testing. Set with
+    /// [`Self::with_fault_injector`].
+    #[cfg(any(test, feature = "test-fixture"))]
+    fault_injector: Option<(HelperIdentity, crate::sync::Arc<FaultInjector>)>,
 }
 
 impl MpcHelperClient {
@@ -169,9 +316,14 @@ impl MpcHelperClient {
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn from_conf(conf: &NetworkConfig, identity: &ClientIdentity) -> [MpcHelperClient; 3] {
-        conf.peers()
-            .each_ref()
-            .map(|peer_conf| Self::new(&conf.client, peer_conf.clone(), identity.clone_with_key()))
+        conf.peers().each_ref().map(|peer_conf| {
+            Self::new_with_root_certificates(
+                &conf.client,
+                peer_conf.clone(),
+                &conf.root_certificates,
+                identity.clone_with_key(),
+            )
+        })
     }
 
     /// Create a new client with the given configuration
@@ -187,11 +339,34 @@ impl MpcHelperClient {
         peer_config: PeerConfig,
         identity: ClientIdentity,
     ) -> Self {
-        let (connector, auth_header) = if peer_config.url.scheme() == Some(&Scheme::HTTP) {
+        Self::new_with_root_certificates(client_config, peer_config, &[], identity)
+    }
+
+    /// Create a new client with the given configuration.
+    ///
+    /// If `root_certificates` is not empty, the server certificate is verified against them and
+    /// must carry the peer identity (see [`PeerConfig::identity`]) as a subject alternative name.
+    /// Otherwise, the server certificate must match the one pinned in `peer_config`, or, if
+    /// there is none, be trusted by the system truststore.
+    ///
+    /// # Panics
+    /// If some aspect of the configuration is not valid, or if [`StepTransport::Quic`] is
+    /// selected for an HTTP peer or without the `quic` feature. [`NetworkConfig::validate`]
+    /// rejects the latter.
+    #[must_use]
+    #[allow(clippy::too_many_lines)]
+    pub fn new_with_root_certificates(
+        client_config: &ClientConfig,
+        peer_config: PeerConfig,
+        root_certificates: &[OwnedCertificate],
+        identity: ClientIdentity,
+    ) -> Self {
+        let is_http = peer_config.url.scheme() == Some(&Scheme::HTTP);
+        let (connector, auth_header, tls_config) = if is_http {
             // This connector works for both http and https. A regular HttpConnector would suffice,
             // but would make the type of `self.client` variable.
             let auth_header = match identity {
-                ClientIdentity::Certificate(_) => {
+                ClientIdentity::Certificate(_) | ClientIdentity::Reloadable(_) => {
                     error!("certificate identity ignored for HTTP client");
                     None
                 }
@@ -206,25 +381,48 @@ impl MpcHelperClient {
                     .enable_http2()
                     .wrap_connector(make_http_connector()),
                 auth_header,
+                None,
             )
         } else {
             let builder = rustls::ClientConfig::builder_with_provider(Arc::clone(&CRYPTO_PROVIDER))
                 .with_safe_default_protocol_versions()
                 .expect("Default crypto provider should be valid");
-            let client_config = if let Some(certificate) = peer_config.certificate {
-                let cert_store = {
-                    let mut store = RootCertStore::empty();
-                    store
+            let builder = if root_certificates.is_empty() {
+                if let Some(certificate) = peer_config.certificate {
+                    let mut cert_store = RootCertStore::empty();
+                    cert_store
                         .add(certificate)
                         .expect("Error adding Certificate, should be a valid Trust Anchor.");
-                    store
-                };
-
-                let builder = builder.with_root_certificates(cert_store);
+                    Some(builder.with_root_certificates(cert_store))
+                } else {
+                    None
+                }
+            } else {
+                let mut cert_store = RootCertStore::empty();
+                for certificate in root_certificates {
+                    cert_store
+                        .add(certificate.clone())
+                        .expect("Error adding root certificate, should be a valid Trust Anchor.");
+                }
+                let peer_identity = peer_config
+                    .expected_identity()
+                    .expect("peer identity or URL host must be specified")
+                    .to_owned();
+                Some(
+                    builder
+                        .dangerous()
+                        .with_custom_certificate_verifier(Arc::new(PeerCertificateVerifier::new(
+                            Arc::new(cert_store),
+                            peer_identity,
+                        ))),
+                )
+            };
+            let tls_config = if let Some(builder) = builder {
                 match identity {
                     ClientIdentity::Certificate((cert_chain, pk)) => builder
                         .with_client_auth_cert(cert_chain, pk)
                         .expect("Can setup client authentication with certificate"),
+                    ClientIdentity::Reloadable(cert) => builder.with_client_cert_resolver(cert),
                     ClientIdentity::Helper(_) => {
                         error!("header-passed identity ignored for HTTPS client");
                         builder.with_no_client_auth()
@@ -232,7 +430,12 @@ impl MpcHelperClient {
                     ClientIdentity::None => builder.with_no_client_auth(),
                 }
             } else {
-                builder.with_native_roots().unwrap().with_no_client_auth()
+                rustls::ClientConfig::builder_with_provider(Arc::clone(&CRYPTO_PROVIDER))
+                    .with_safe_default_protocol_versions()
+                    .expect("Default crypto provider should be valid")
+                    .with_native_roots()
+                    .expect("Error creating client with Rustls, native roots should be available.")
+                    .with_no_client_auth()
             };
             // `enforce_http` must be false to request HTTPS URLs. This is done automatically by
             // `HttpsConnector::new()`, but not by `HttpsConnector::from()`.
@@ -240,22 +443,41 @@ impl MpcHelperClient {
             http.enforce_http(false);
             (
                 HttpsConnectorBuilder::new()
-                    .with_tls_config(client_config)
+                    .with_tls_config(tls_config.clone())
                     .https_only()
                     .enable_http2()
                     .wrap_connector(http),
                 None,
+                Some(tls_config),
             )
         };
-        Self::new_internal(peer_config.url, connector, auth_header, client_config)
+        let mut client = Self::new_internal(peer_config.url, connector, auth_header, client_config);
+        client.step_encodings = peer_config.step_encodings;
+        match client_config.step_transport {
+            StepTransport::Http => {}
+            #[cfg(feature = "quic")]
+            StepTransport::Quic => {
+                let tls_config = tls_config.expect("QUIC step transport requires HTTPS");
+                client.quic = Some(Arc::new(quic::QuicClient::new(
+                    tls_config,
+                    client.authority.clone(),
+                )));
+            }
+            #[cfg(not(feature = "quic"))]
+            StepTransport::Quic => {
+                let _ = tls_config;
+                panic!("QUIC step transport requires the `quic` feature");
+            }
+        }
+        client
     }
 
     #[must_use]
-    fn new_internal<C: HyperClientConfigurator>(
+    fn new_internal(
         addr: Uri,
         connector: HttpsConnector<HttpConnector>,
         auth_header: Option<(HeaderName, HeaderValue)>,
-        conf: &C,
+        conf: &ClientConfig,
     ) -> Self {
         let mut builder = Client::builder(TokioExecutor::new());
         // the following timer is necessary for http2, in particular for any timeouts
@@ -275,9 +497,32 @@ impl MpcHelperClient {
             scheme,
             authority,
             auth_header,
+            retry: conf.retry.clone(),
+            timeouts: conf.timeouts.clone(),
+            circuit_breaker: Arc::new(CircuitBreaker::new(conf.circuit_breaker.clone())),
+            step_stream: conf.step_stream.clone(),
+            compression: conf.compression.clone(),
+            step_encodings: Vec::new(),
+            #[cfg(feature = "quic")]
+            quic: None,
+            #[cfg(any(test, feature = "test-fixture"))]
+            fault_injector: None,
         }
     }
 
+    /// Injects faults into the step streams that helper `from` sends with this client. Faults
+    /// are injected before compression. Each attempt to resume a stream gets its own faults.
+    #[cfg(any(test, feature = "test-fixture"))]
+    #[must_use]
+    pub fn with_fault_injector(
+        mut self,
+        from: HelperIdentity,
+        injector: &crate::sync::Arc<FaultInjector>,
+    ) -> Self {
+        self.fault_injector = Some((from, crate::sync::Arc::clone(injector)));
+        self
+    }
+
     pub fn request(&self, mut req: Request<Body>) -> ResponseFuture<'_> {
         if let Some((k, v)) = self.auth_header.clone() {
             req.headers_mut().insert(k, v);
@@ -285,6 +530,67 @@ impl MpcHelperClient {
         ResponseFuture {
             authority: &self.authority,
             inner: self.client.request(req),
+            deadline: None,
+        }
+    }
+
+    /// Makes a request to `route`, applying the configured timeout and retry policy. `attempt`
+    /// is called to make each attempt, so it must build a new request every time.
+    ///
+    /// ## Errors
+    /// The error from the last attempt, if none of the attempts succeeded.
+    async fn with_retries<T, F, Fut>(&self, route: Route, mut attempt: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let timeout = route.timeout(&self.timeouts);
+        let mut attempts = 1;
+        loop {
+            self.check_circuit(route)?;
+            let result = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, attempt())
+                    .await
+                    .unwrap_or_else(|_| Err(self.timeout_error(route, timeout))),
+                None => attempt().await,
+            };
+            self.circuit_breaker.record(&result);
+            match result {
+                Err(e) if attempts < self.retry.max_attempts && route.should_retry(&e) => {
+                    let delay = self.retry.backoff(attempts);
+                    warn!(
+                        "{} request to {} failed (attempt {attempts} of {}), retrying in {delay:?}: {e}",
+                        route.name(),
+                        self.authority,
+                        self.retry.max_attempts,
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn timeout_error(&self, route: Route, timeout: Duration) -> Error {
+        Error::Timeout {
+            dest: self.authority.to_string(),
+            route: route.name(),
+            timeout,
+        }
+    }
+
+    /// Fails with [`Error::CircuitOpen`] if the circuit breaker does not let a request to `route`
+    /// through. Otherwise, the outcome of the request must be recorded in the breaker. See
+    /// [`CircuitBreakerConfig`](crate::config::CircuitBreakerConfig).
+    fn check_circuit(&self, route: Route) -> Result<(), Error> {
+        if self.circuit_breaker.allow() {
+            Ok(())
+        } else {
+            Err(Error::CircuitOpen {
+                dest: self.authority.to_string(),
+                route: route.name(),
+            })
         }
     }
 
@@ -312,6 +618,10 @@ impl MpcHelperClient {
     /// # Errors
     /// If the request has illegal arguments, or fails to deliver to helper
     pub async fn echo(&self, s: &str) -> Result<String, Error> {
+        self.with_retries(Route::Echo, || self.echo_once(s)).await
+    }
+
+    async fn echo_once(&self, s: &str) -> Result<String, Error> {
         const FOO: &str = "foo";
         let req =
             http_serde::echo::Request::new(HashMap::from([(FOO.into(), s.into())]), HashMap::new());
@@ -340,6 +650,11 @@ impl MpcHelperClient {
     /// # Errors
     /// If the request has illegal arguments, or fails to deliver to helper
     pub async fn create_query(&self, data: QueryConfig) -> Result<QueryId, Error> {
+        self.with_retries(Route::CreateQuery, || self.create_query_once(data))
+            .await
+    }
+
+    async fn create_query_once(&self, data: QueryConfig) -> Result<QueryId, Error> {
         let req = http_serde::query::create::Request::new(data);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
         let resp = self.request(req).await?;
@@ -359,6 +674,13 @@ impl MpcHelperClient {
     /// # Errors
     /// If the request has illegal arguments, or fails to deliver to helper
     pub async fn prepare_query(&self, data: PrepareQuery) -> Result<(), Error> {
+        self.with_retries(Route::PrepareQuery, || {
+            self.prepare_query_once(data.clone())
+        })
+        .await
+    }
+
+    async fn prepare_query_once(&self, data: PrepareQuery) -> Result<(), Error> {
         let req = http_serde::query::prepare::Request::new(data);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
         let resp = self.request(req).await?;
@@ -371,15 +693,30 @@ impl MpcHelperClient {
     /// # Errors
     /// If the request has illegal arguments, or fails to deliver to helper
     pub async fn query_input(&self, data: QueryInput) -> Result<(), Error> {
+        // The input is streamed, so it cannot be sent again. Only the timeout applies.
         let req = http_serde::query::input::Request::new(data);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
-        let resp = self.request(req).await?;
-        Self::resp_ok(resp).await
+        let request = async {
+            let resp = self.request(req).await?;
+            Self::resp_ok(resp).await
+        };
+        self.check_circuit(Route::QueryInput)?;
+        let result = match self.timeouts.query_input {
+            Some(timeout) => tokio::time::timeout(timeout, request)
+                .await
+                .unwrap_or_else(|_| Err(self.timeout_error(Route::QueryInput, timeout))),
+            None => request.await,
+        };
+        self.circuit_breaker.record(&result);
+        result
     }
 
     /// Sends a batch of messages associated with a query's step to another helper. Messages are a
     /// contiguous block of records. Also includes [`crate::protocol::RecordId`] information and
     /// [`crate::helpers::network::ChannelId`].
+    ///
+    /// Step requests are never retried, because the records cannot be replayed. The step timeout,
+    /// if configured, applies to receiving the response.
     /// # Errors
     /// If the request has illegal arguments, or fails to deliver to helper
     /// # Panics
@@ -394,17 +731,140 @@ impl MpcHelperClient {
         let body = axum::body::Body::from_stream(data);
         let req = http_serde::query::step::Request::new(query_id, gate.clone(), body);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
-        Ok(self.request(req))
+        Ok(self
+            .request(req)
+            .with_timeout(Route::Step, self.timeouts.step))
+    }
+
+    /// Sends all the records for a query's step to another helper, resuming the stream if the
+    /// connection is lost. See [`StepStreamConfig`] for how often the stream is resumed.
+    ///
+    /// Unlike [`Self::step`], this completes only when the other helper has received all the
+    /// records, or has stopped reading them.
+    ///
+    /// The records are compressed according to the class of the step (see [`CompressionConfig`]),
+    /// if the other helper accepts it (see [`PeerConfig::step_encodings`]).
+    ///
+    /// If the client is configured with [`StepTransport::Quic`], the records are sent on a QUIC
+    /// stream instead, and the stream is not resumed.
+    ///
+    /// # Errors
+    /// If the records cannot be delivered, even after resuming the stream.
+    pub async fn send_records<S: Stream<Item = Vec<u8>> + Send + 'static>(
+        &self,
+        query_id: QueryId,
+        gate: &Gate,
+        data: S,
+    ) -> Result<(), Error> {
+        let mut compression = compression::negotiate(
+            self.compression.for_class(ChannelClass::of(gate)),
+            &self.step_encodings,
+        );
+
+        #[cfg(feature = "quic")]
+        if let Some(quic) = &self.quic {
+            self.check_circuit(Route::Step)?;
+            let result = quic.send_records(query_id, gate, data, compression).await;
+            self.circuit_breaker.record(&result);
+            return result;
+        }
+
+        let records = RetainedRecords::new(data, self.step_stream.retained_bytes);
+        let mut reconnects = 0;
+        loop {
+            self.check_circuit(Route::Step)?;
+            let result = self
+                .send_retained(query_id, gate, &records, compression)
+                .await;
+            self.circuit_breaker.record(&result);
+            match result {
+                Err(Error::FailedHttpRequest {
+                    status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    reason,
+                    ..
+                }) if compression != Compression::None => {
+                    warn!(
+                        "{} does not accept {compression:?} for step stream {gate:?}, sending it uncompressed: {reason}",
+                        self.authority,
+                    );
+                    compression = Compression::None;
+                }
+                Err(e) if reconnects < self.step_stream.max_reconnects && e.is_transient() => {
+                    reconnects += 1;
+                    let delay = self.retry.backoff(reconnects);
+                    warn!(
+                        "step stream {gate:?} to {} interrupted, resuming in {delay:?} (attempt {reconnects} of {}): {e}",
+                        self.authority, self.step_stream.max_reconnects,
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Makes a single step request that sends the records that were not acknowledged yet, and
+    /// reads the acknowledgements until the receiver is done.
+    async fn send_retained(
+        &self,
+        query_id: QueryId,
+        gate: &Gate,
+        records: &RetainedRecords,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        use http_serde::query::step::ACK_LEN;
+
+        let (offset, replay) = records.replay();
+        #[cfg(any(test, feature = "test-fixture"))]
+        let body = match &self.fault_injector {
+            Some((from, injector)) => {
+                let to = self.authority.to_string();
+                let replay = injector.inject(*from, &to, gate, replay.id(), replay);
+                Body::from_stream(compression::encode(replay, compression, gate))
+            }
+            None => Body::from_stream(compression::encode(replay, compression, gate)),
+        };
+        #[cfg(not(any(test, feature = "test-fixture")))]
+        let body = Body::from_stream(compression::encode(replay, compression, gate));
+        let req = http_serde::query::step::Request::new(query_id, gate.clone(), body)
+            .with_offset(offset)
+            .with_encoding(compression::encoding(compression));
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self
+            .request(req)
+            .with_timeout(Route::Step, self.timeouts.step)
+            .await?;
+        if !resp.status().is_success() {
+            return Err(Error::from_failed_resp(resp).await);
+        }
+
+        let mut acks = resp.into_body().into_data_stream();
+        let mut buf = bytes::BytesMut::new();
+        while let Some(chunk) = acks.next().await {
+            buf.extend_from_slice(&chunk?);
+            while buf.len() >= ACK_LEN {
+                let ack = buf.split_to(ACK_LEN);
+                records.acknowledge(u64::from_be_bytes(ack[..].try_into().unwrap()));
+            }
+        }
+        Ok(())
     }
 
     /// Retrieve the status of a query.
     ///
     /// ## Errors
     /// If the request has illegal arguments, or fails to deliver to helper
-    #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))]
     pub async fn query_status(
         &self,
         query_id: QueryId,
+    ) -> Result<crate::query::QueryStatus, Error> {
+        self.with_retries(Route::QueryStatus, || self.query_status_once(query_id))
+            .await
+    }
+
+    async fn query_status_once(
+        &self,
+        query_id: QueryId,
     ) -> Result<crate::query::QueryStatus, Error> {
         let req = http_serde::query::status::Request::new(query_id);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
@@ -420,13 +880,40 @@ impl MpcHelperClient {
         }
     }
 
-    /// Wait for completion of the query and pull the results of this query. This is a blocking
-    /// API so it is not supposed to be used outside of CLI context.
+    /// Retrieve the state of the helper from the admin API. The client must be authenticated as
+    /// one of the helpers.
+    ///
+    /// ## Errors
+    /// If the request fails, or the client is not authorized to call the admin API.
+    pub async fn helper_state(&self) -> Result<crate::HelperState, Error> {
+        self.with_retries(Route::HelperState, || self.helper_state_once())
+            .await
+    }
+
+    async fn helper_state_once(&self) -> Result<crate::HelperState, Error> {
+        let req = http_serde::admin::state::Request
+            .try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = Self::response_to_bytes(resp).await?;
+            Ok(serde_json::from_slice(&bytes)?)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
+    /// Wait for completion of the query and pull the results of this query. The request does not
+    /// complete until the query does, so [`TimeoutConfig::query_results`] should either be unset
+    /// or allow for the whole query to run.
     ///
     /// ## Errors
     /// If the request has illegal arguments, or fails to deliver to helper
-    #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))]
     pub async fn query_results(&self, query_id: QueryId) -> Result<bytes::Bytes, Error> {
+        self.with_retries(Route::QueryResults, || self.query_results_once(query_id))
+            .await
+    }
+
+    async fn query_results_once(&self, query_id: QueryId) -> Result<bytes::Bytes, Error> {
         let req = http_serde::query::results::Request::new(query_id);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
         let resp = self.request(req).await?;
@@ -437,6 +924,23 @@ impl MpcHelperClient {
             Err(Error::from_failed_resp(resp).await)
         }
     }
+
+    /// Kill a query running on this helper. Killing a query that this helper does not know about
+    /// fails with [`StatusCode::NOT_FOUND`].
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    pub async fn kill_query(&self, query_id: QueryId) -> Result<(), Error> {
+        self.with_retries(Route::KillQuery, || self.kill_query_once(query_id))
+            .await
+    }
+
+    async fn kill_query_once(&self, query_id: QueryId) -> Result<(), Error> {
+        let req = http_serde::query::kill::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        Self::resp_ok(resp).await
+    }
 }
 
 fn make_http_connector() -> HttpConnector {
@@ -454,6 +958,7 @@ pub(crate) mod tests {
         fmt::Debug,
         future::{ready, Future},
         iter::zip,
+        sync::atomic::{AtomicUsize, Ordering},
         task::Poll,
     };
 
@@ -462,6 +967,7 @@ pub(crate) mod tests {
 
     use super::*;
     use crate::{
+        config::CircuitBreakerConfig,
         ff::{FieldType, Fp31},
         helpers::{
             make_owned_handler, query::QueryType::TestMultiply, BytesStream, HelperResponse,
@@ -469,7 +975,7 @@ pub(crate) mod tests {
         },
         net::test::TestServer,
         protocol::step::TestExecutionStep,
-        query::ProtocolResult,
+        query::{ProtocolResult, QueryStatus},
         secret_sharing::replicated::semi_honest::AdditiveShare as Replicated,
         sync::Arc,
     };
@@ -485,7 +991,9 @@ pub(crate) mod tests {
                 .parse()
                 .unwrap(),
             certificate: None,
+            identity: None,
             hpke_config: None,
+            step_encodings: Vec::new(),
         };
         let client =
             MpcHelperClient::new(&ClientConfig::default(), peer_config, ClientIdentity::None);
@@ -667,6 +1175,77 @@ pub(crate) mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn send_records() {
+        let TestServer {
+            client, transport, ..
+        } = TestServer::builder().build().await;
+        let expected_step = Gate::default().narrow(&TestExecutionStep::Iter(0));
+        let payload = vec![7u8; MESSAGE_PAYLOAD_SIZE_BYTES];
+
+        let send = tokio::spawn({
+            let expected_step = expected_step.clone();
+            let data = futures::stream::iter([payload.clone(), payload.clone()]);
+            async move { client.send_records(QueryId, &expected_step, data).await }
+        });
+
+        let received = Arc::clone(&transport)
+            .receive(HelperIdentity::ONE, (QueryId, expected_step))
+            .into_bytes_stream()
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        assert_eq!(received, [payload.clone(), payload].concat());
+
+        // Sending completes once the receiver has consumed the stream.
+        send.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_records_compressed() {
+        let TestServer {
+            addr, transport, ..
+        } = TestServer::builder().disable_https().build().await;
+        let config = ClientConfig {
+            compression: CompressionConfig {
+                shares: Compression::Zstd { level: 1 },
+                ..CompressionConfig::default()
+            },
+            ..ClientConfig::default()
+        };
+        let peer_config = PeerConfig {
+            step_encodings: vec![compression::ZSTD.into()],
+            ..PeerConfig::new(
+                format!("http://localhost:{}", addr.port()).parse().unwrap(),
+                None,
+            )
+        };
+        let client = MpcHelperClient::new(
+            &config,
+            peer_config,
+            ClientIdentity::Helper(HelperIdentity::ONE),
+        );
+        let expected_step = Gate::default().narrow(&TestExecutionStep::Iter(0));
+        let payload = (0..4 * MESSAGE_PAYLOAD_SIZE_BYTES)
+            .map(|i| u8::try_from(i % 4).unwrap())
+            .collect::<Vec<_>>();
+
+        let send = tokio::spawn({
+            let expected_step = expected_step.clone();
+            let data = futures::stream::iter([payload.clone(), payload.clone()]);
+            async move { client.send_records(QueryId, &expected_step, data).await }
+        });
+
+        let received = Arc::clone(&transport)
+            .receive(HelperIdentity::ONE, (QueryId, expected_step))
+            .into_bytes_stream()
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        assert_eq!(received, [payload.clone(), payload].concat());
+        send.await.unwrap().unwrap();
+    }
+
     #[tokio::test]
     async fn results() {
         let expected_results = [
@@ -695,4 +1274,129 @@ pub(crate) mod tests {
                 .to_bytes()
         );
     }
+
+    fn client_with_config(addr: std::net::SocketAddr, config: &ClientConfig) -> MpcHelperClient {
+        let peer_config = PeerConfig::new(
+            format!("http://localhost:{}", addr.port()).parse().unwrap(),
+            None,
+        );
+        MpcHelperClient::new(config, peer_config, ClientIdentity::None)
+    }
+
+    /// Returns a handler that answers query status requests, and that stalls on the first one.
+    fn stall_first_status(
+        calls: &Arc<AtomicUsize>,
+    ) -> Arc<dyn RequestHandler<Identity = HelperIdentity>> {
+        let calls = Arc::clone(calls);
+        make_owned_handler(move |_, _| {
+            let calls = Arc::clone(&calls);
+            async move {
+                if calls.fetch_add(1, Ordering::Relaxed) == 0 {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+                Ok(HelperResponse::from(QueryStatus::Running))
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn idempotent_request_retried_after_timeout() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        // The server only keeps a weak reference to the handler.
+        let handler = stall_first_status(&calls);
+        let TestServer { addr, .. } = TestServer::builder()
+            .disable_https()
+            .with_request_handler(Arc::clone(&handler))
+            .build()
+            .await;
+
+        let mut config = ClientConfig::default();
+        config.timeouts.query_status = Some(Duration::from_millis(200));
+        config.retry.initial_backoff = Duration::from_millis(10);
+        let client = client_with_config(addr, &config);
+
+        assert_eq!(
+            client.query_status(QueryId).await.unwrap(),
+            QueryStatus::Running
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn timeout_without_retries() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        // The server only keeps a weak reference to the handler.
+        let handler = stall_first_status(&calls);
+        let TestServer { addr, .. } = TestServer::builder()
+            .disable_https()
+            .with_request_handler(Arc::clone(&handler))
+            .build()
+            .await;
+
+        let mut config = ClientConfig::default();
+        config.timeouts.query_status = Some(Duration::from_millis(200));
+        config.retry = RetryConfig::disabled();
+        let client = client_with_config(addr, &config);
+
+        assert!(matches!(
+            client.query_status(QueryId).await,
+            Err(Error::Timeout {
+                route: "query status",
+                ..
+            })
+        ));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn connect_failure_retried() {
+        // Reserve a port and release it, so that nothing is listening on it.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut config = ClientConfig::default();
+        config.retry = RetryConfig {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        let client = client_with_config(addr, &config);
+
+        // Create query is not idempotent, but it is safe to retry if the request was never
+        // delivered. The client waits for two backoff periods before giving up.
+        let start = std::time::Instant::now();
+        let res = client
+            .create_query(QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap())
+            .await;
+        assert!(matches!(res, Err(ref e) if e.is_connect_failure()));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn circuit_opens_after_repeated_failures() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut config = ClientConfig::default();
+        config.retry = RetryConfig::disabled();
+        config.circuit_breaker = CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_duration: Duration::from_secs(60),
+        };
+        let client = client_with_config(addr, &config);
+
+        for _ in 0..2 {
+            assert!(matches!(client.echo("a").await, Err(ref e) if e.is_connect_failure()));
+        }
+        // Clones talk to the same helper, so they share the open circuit.
+        assert!(matches!(
+            client.clone().query_status(QueryId).await,
+            Err(Error::CircuitOpen {
+                route: "query status",
+                ..
+            })
+        ));
+    }
 }
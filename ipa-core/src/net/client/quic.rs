@@ -0,0 +1,149 @@
+use std::{
+    convert::Infallible,
+    fmt::{Debug, Formatter},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::http::uri;
+use quinn::{crypto::rustls::QuicClientConfig, Connection, Endpoint, SendStream};
+
+use crate::{
+    config::Compression,
+    error::BoxError,
+    net::{compression, Error, QUIC_ALPN},
+    protocol::{Gate, QueryId},
+    sync::Arc,
+};
+
+/// Sends step streams to another helper over QUIC, one stream per step.
+///
+/// The connection is established when the first stream is sent, and re-established if it is
+/// closed. Requests other than steps are sent over HTTP by [`super::MpcHelperClient`].
+pub(super) struct QuicClient {
+    config: quinn::ClientConfig,
+    authority: uri::Authority,
+    /// Endpoint and connection, created on first use. Creating an endpoint requires a Tokio
+    /// runtime, which may not be running yet when the client is constructed.
+    connection: tokio::sync::Mutex<Option<(Endpoint, Connection)>>,
+}
+
+impl Debug for QuicClient {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "QuicClient({})", self.authority)
+    }
+}
+
+impl QuicClient {
+    /// ## Panics
+    /// If `tls_config` does not support TLS 1.3, which QUIC requires.
+    pub fn new(mut tls_config: rustls::ClientConfig, authority: uri::Authority) -> Self {
+        tls_config.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+        let crypto = QuicClientConfig::try_from(tls_config)
+            .expect("QUIC requires a TLS configuration that supports TLS 1.3");
+        Self {
+            config: quinn::ClientConfig::new(Arc::new(crypto)),
+            authority,
+            connection: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    fn error<E: Into<BoxError>>(&self, inner: E) -> Error {
+        Error::Quic {
+            dest: self.authority.to_string(),
+            inner: inner.into(),
+        }
+    }
+
+    /// Returns the connection to the other helper, connecting if there is no open connection.
+    async fn connection(&self) -> Result<Connection, Error> {
+        let mut guard = self.connection.lock().await;
+        if let Some((_, conn)) = guard.as_ref() {
+            if conn.close_reason().is_none() {
+                return Ok(conn.clone());
+            }
+        }
+
+        let port = self.authority.port_u16().unwrap_or(443);
+        // Helper servers listen on IPv4 only, so prefer IPv4 addresses. Unlike TCP, a QUIC
+        // connection to an address with no listener fails only after the handshake times out.
+        let addrs = tokio::net::lookup_host((self.authority.host(), port))
+            .await
+            .map_err(|e| self.error(e))?
+            .collect::<Vec<_>>();
+        let addr = addrs
+            .iter()
+            .find(|addr| addr.is_ipv4())
+            .or_else(|| addrs.first())
+            .copied()
+            .ok_or_else(|| self.error(format!("{} did not resolve", self.authority.host())))?;
+        let endpoint = if let Some((endpoint, _)) = guard.take() {
+            endpoint
+        } else {
+            let local = if addr.is_ipv4() {
+                SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+            } else {
+                SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+            };
+            Endpoint::client(local).map_err(|e| self.error(e))?
+        };
+        let conn = endpoint
+            .connect_with(self.config.clone(), addr, self.authority.host())
+            .map_err(|e| self.error(e))?
+            .await
+            .map_err(|e| self.error(e))?;
+        *guard = Some((endpoint, conn.clone()));
+        Ok(conn)
+    }
+
+    /// Sends all the records for a query's step on a new stream, compressed with `compression`,
+    /// and waits until the other helper has received them.
+    ///
+    /// The stream starts with three fields: the query id, the gate name and the encoding of the
+    /// records (empty if they are not compressed). Each field is its length (two bytes,
+    /// big-endian) followed by its UTF-8 bytes. The records follow.
+    ///
+    /// ## Errors
+    /// If the connection cannot be established or is lost while sending the records.
+    pub async fn send_records<S: Stream<Item = Vec<u8>> + Send + 'static>(
+        &self,
+        query_id: QueryId,
+        gate: &Gate,
+        data: S,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        let conn = self.connection().await?;
+        let mut stream = conn.open_uni().await.map_err(|e| self.error(e))?;
+
+        for field in [
+            query_id.as_ref(),
+            gate.as_ref(),
+            compression::encoding(compression).unwrap_or_default(),
+        ] {
+            self.write_field(&mut stream, field).await?;
+        }
+
+        let data = data.map(|chunk| Ok::<_, Infallible>(Bytes::from(chunk)));
+        let mut data = std::pin::pin!(compression::encode(data, compression, gate));
+        while let Some(chunk) = data.next().await {
+            let chunk = chunk.map_err(|e| self.error(e))?;
+            stream.write_all(&chunk).await.map_err(|e| self.error(e))?;
+        }
+        stream.finish().map_err(|e| self.error(e))?;
+        stream.stopped().await.map_err(|e| self.error(e))?;
+        Ok(())
+    }
+
+    async fn write_field(&self, stream: &mut SendStream, field: &str) -> Result<(), Error> {
+        let len = u16::try_from(field.len()).map_err(|e| self.error(e))?;
+        stream
+            .write_all(&len.to_be_bytes())
+            .await
+            .map_err(|e| self.error(e))?;
+        stream
+            .write_all(field.as_bytes())
+            .await
+            .map_err(|e| self.error(e))
+    }
+}
@@ -0,0 +1,284 @@
+//! Compression of step streams.
+//!
+//! A compressed step stream is a sequence of frames. Each frame is the length of the compressed
+//! data (four bytes, big-endian), followed by the data, compressed independently of the other
+//! frames. Every chunk of records is compressed into its own frames as soon as it is available,
+//! so compression does not hold back records that the other helper is waiting for. Frames hold
+//! at most [`MAX_FRAME_SIZE`] bytes of records, which bounds the memory needed to decompress
+//! them.
+//!
+//! The sender requests compression with the [`ENCODING_HEADER`]. Helpers that predate compression
+//! ignore that header, so the sender only compresses streams with an encoding that the receiver
+//! lists in its [`PeerConfig::step_encodings`]. If the receiver nevertheless rejects the request
+//! with `415 Unsupported Media Type`, the sender falls back to sending the stream uncompressed.
+//!
+//! [`PeerConfig::step_encodings`]: crate::config::PeerConfig::step_encodings
+//!
+//! [`ENCODING_HEADER`]: crate::net::http_serde::query::step::ENCODING_HEADER
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{stream, Stream, StreamExt};
+
+use crate::{
+    config::Compression,
+    error::BoxError,
+    helpers::BodyStream,
+    protocol::Gate,
+    telemetry::{labels::STEP, metrics::BYTES_SENT_WIRE},
+};
+
+/// Value of the encoding header for streams compressed with zstd.
+pub const ZSTD: &str = "zstd";
+
+/// Largest number of uncompressed bytes in a frame.
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+const FRAME_HEADER_LEN: usize = 4;
+
+/// Class of data carried by a step stream, used to pick the compression for the stream. See
+/// [`CompressionConfig`].
+///
+/// [`CompressionConfig`]: crate::config::CompressionConfig
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelClass {
+    /// Secret shares. This is most of the data exchanged by the helpers.
+    Shares,
+    /// Values revealed to the other helpers, sent by steps named `reveal*`.
+    Revealed,
+    /// Values exchanged to validate the protocol execution, when running with malicious
+    /// security.
+    Control,
+}
+
+impl ChannelClass {
+    const CONTROL_STEPS: [&'static str; 4] =
+        ["validate", "generate_proof", "challenge", "verify_proof"];
+
+    /// Classifies the stream for `gate` by the names of its steps.
+    #[must_use]
+    pub fn of(gate: &Gate) -> Self {
+        let gate = gate.as_ref();
+        if gate
+            .rsplit('/')
+            .next()
+            .is_some_and(|step| step.starts_with("reveal"))
+        {
+            Self::Revealed
+        } else if gate
+            .split('/')
+            .any(|step| Self::CONTROL_STEPS.contains(&step))
+        {
+            Self::Control
+        } else {
+            Self::Shares
+        }
+    }
+}
+
+/// Returns the value of the encoding header for `compression`, or `None` if the stream is not
+/// compressed.
+#[must_use]
+pub fn encoding(compression: Compression) -> Option<&'static str> {
+    match compression {
+        Compression::None => None,
+        Compression::Zstd { .. } => Some(ZSTD),
+    }
+}
+
+/// Returns `compression` if its encoding is one of the `accepted` encodings of the receiver,
+/// otherwise [`Compression::None`].
+#[must_use]
+pub fn negotiate(compression: Compression, accepted: &[String]) -> Compression {
+    match encoding(compression) {
+        Some(encoding) if !accepted.iter().any(|e| e == encoding) => Compression::None,
+        _ => compression,
+    }
+}
+
+/// Prepares the records of a step stream for sending: compresses them as configured, and counts
+/// the bytes sent in the [`BYTES_SENT_WIRE`] metric.
+pub fn encode<S, E>(
+    data: S,
+    compression: Compression,
+    gate: &Gate,
+) -> impl Stream<Item = Result<Bytes, BoxError>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<BoxError>,
+{
+    let step = gate.as_ref().to_string();
+    data.map(move |chunk| {
+        let chunk = chunk.map_err(Into::into)?;
+        let chunk = match compression {
+            Compression::None => chunk,
+            Compression::Zstd { level } => {
+                let mut frames = BytesMut::new();
+                for data in chunk.chunks(MAX_FRAME_SIZE) {
+                    let frame = zstd::bulk::compress(data, level)?;
+                    frames.put_u32(u32::try_from(frame.len())?);
+                    frames.put_slice(&frame);
+                }
+                frames.freeze()
+            }
+        };
+        metrics::counter!(BYTES_SENT_WIRE, chunk.len() as u64, STEP => step.clone());
+        Ok(chunk)
+    })
+}
+
+/// Decompresses a step stream received with the `encoding` header value.
+///
+/// ## Errors
+/// If the encoding is not supported. The stream fails if it is not compressed correctly.
+pub fn decode(body: BodyStream, encoding: Option<&str>) -> Result<BodyStream, BoxError> {
+    match encoding {
+        None => Ok(body),
+        Some(ZSTD) => Ok(BodyStream::from_bytes_stream(decompress_zstd(body))),
+        Some(other) => Err(format!("unsupported step stream encoding: {other}").into()),
+    }
+}
+
+fn decompress_zstd(body: BodyStream) -> impl Stream<Item = Result<Bytes, BoxError>> + Send {
+    let max_frame_len = zstd::zstd_safe::compress_bound(MAX_FRAME_SIZE);
+    stream::unfold(Some((body, BytesMut::new())), move |state| async move {
+        let (mut body, mut buf) = state?;
+        loop {
+            if buf.len() >= FRAME_HEADER_LEN {
+                let frame_len = usize::try_from(u32::from_be_bytes(
+                    buf[..FRAME_HEADER_LEN].try_into().unwrap(),
+                ))
+                .unwrap();
+                if frame_len > max_frame_len {
+                    return Some((
+                        Err(format!("compressed frame is too large: {frame_len} bytes").into()),
+                        None,
+                    ));
+                }
+                if buf.len() >= FRAME_HEADER_LEN + frame_len {
+                    buf.advance(FRAME_HEADER_LEN);
+                    let frame = buf.split_to(frame_len);
+                    return match zstd::bulk::decompress(&frame, MAX_FRAME_SIZE) {
+                        Ok(data) => Some((Ok(Bytes::from(data)), Some((body, buf)))),
+                        Err(e) => Some((Err(e.into()), None)),
+                    };
+                }
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(e), None)),
+                None if buf.is_empty() => return None,
+                None => return Some((Err("step stream ends in a partial frame".into()), None)),
+            }
+        }
+    })
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use futures::{stream, StreamExt, TryStreamExt};
+
+    use super::*;
+
+    fn encode_all(chunks: Vec<Vec<u8>>, compression: Compression) -> Vec<Bytes> {
+        let data = stream::iter(chunks).map(|c| Ok::<_, BoxError>(Bytes::from(c)));
+        futures::executor::block_on(
+            encode(data, compression, &Gate::from("test")).try_collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    fn decode_all(chunks: Vec<Bytes>, encoding: Option<&str>) -> Result<Vec<u8>, BoxError> {
+        let body = BodyStream::from_bytes_stream(stream::iter(chunks).map(Ok::<_, BoxError>));
+        futures::executor::block_on(async {
+            let mut out = Vec::new();
+            let mut stream = decode(body, encoding)?;
+            while let Some(chunk) = stream.next().await {
+                out.extend_from_slice(&chunk?);
+            }
+            Ok(out)
+        })
+    }
+
+    #[test]
+    fn classify() {
+        for (gate, class) in [
+            ("/protocol/attribute/reveal_r", ChannelClass::Revealed),
+            ("/protocol/aggregate/reveal_step", ChannelClass::Revealed),
+            (
+                "/protocol/validate/propagate_u_and_w",
+                ChannelClass::Control,
+            ),
+            (
+                "/protocol/dzkp_batch_step3/generate_proof",
+                ChannelClass::Control,
+            ),
+            ("/protocol/multiply", ChannelClass::Shares),
+            ("/protocol/reveal/multiply", ChannelClass::Shares),
+        ] {
+            assert_eq!(ChannelClass::of(&Gate::from(gate)), class, "{gate}");
+        }
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        let chunks = vec![
+            vec![0; 4096],
+            vec![],
+            (0..=255).collect(),
+            vec![7; 3 * MAX_FRAME_SIZE],
+        ];
+        let expected = chunks.concat();
+        let encoded = encode_all(chunks, Compression::Zstd { level: 3 });
+        assert!(encoded.iter().map(Bytes::len).sum::<usize>() < expected.len() / 100);
+
+        // Frames may be split across chunks of the body.
+        let rechunked = encoded
+            .concat()
+            .chunks(5)
+            .map(Bytes::copy_from_slice)
+            .collect();
+        assert_eq!(decode_all(rechunked, Some(ZSTD)).unwrap(), expected);
+    }
+
+    #[test]
+    fn uncompressed() {
+        let chunks = vec![vec![1, 2, 3], vec![4]];
+        let encoded = encode_all(chunks, Compression::None);
+        assert_eq!(
+            encoded,
+            vec![Bytes::from(vec![1, 2, 3]), Bytes::from(vec![4])]
+        );
+        assert_eq!(decode_all(encoded, None).unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn partial_frame() {
+        let mut encoded = encode_all(vec![vec![0; 100]], Compression::Zstd { level: 3 }).concat();
+        encoded.pop();
+        decode_all(vec![Bytes::from(encoded)], Some(ZSTD)).unwrap_err();
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let header = Bytes::copy_from_slice(&u32::MAX.to_be_bytes());
+        let err = decode_all(vec![header], Some(ZSTD)).unwrap_err();
+        assert!(err.to_string().contains("too large"), "{err}");
+    }
+
+    #[test]
+    fn negotiate_with_receiver() {
+        let zstd = Compression::Zstd { level: 3 };
+        assert_eq!(negotiate(zstd, &[ZSTD.into()]), zstd);
+        assert_eq!(negotiate(zstd, &[]), Compression::None);
+        assert_eq!(negotiate(zstd, &["gzip".into()]), Compression::None);
+        assert_eq!(
+            negotiate(Compression::None, &[ZSTD.into()]),
+            Compression::None
+        );
+    }
+
+    #[test]
+    fn unsupported_encoding() {
+        decode_all(vec![], Some("gzip")).unwrap_err();
+    }
+}
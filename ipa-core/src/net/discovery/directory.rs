@@ -0,0 +1,154 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use config::{Config, File, FileFormat};
+use futures::future::try_join3;
+use tokio::fs;
+
+use crate::{
+    config::PeerConfig,
+    net::discovery::{Error, PeerDiscovery},
+};
+
+/// Reads the peer configuration from a directory.
+///
+/// The directory contains `h1.toml`, `h2.toml` and `h3.toml`, each holding the configuration of
+/// one helper in the same format as a `[[peers]]` entry in `network.toml`. The certificate of a
+/// helper may also be supplied in a separate `h1.pem` (etc.) file, which takes precedence over
+/// the certificate in the TOML file. This makes it possible to rotate certificates by replacing
+/// files, without editing TOML.
+///
+/// The directory is read every time [`PeerDiscovery::peers`] is called, so changes are picked up
+/// the next time the helper checks for them.
+pub struct Directory {
+    path: PathBuf,
+}
+
+impl Directory {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    async fn read(path: &Path) -> Result<Option<String>, Error> {
+        match fs::read_to_string(path).await {
+            Ok(s) => Ok(Some(s)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(inner) => Err(Error::Io {
+                path: path.to_owned(),
+                inner,
+            }),
+        }
+    }
+
+    async fn peer(&self, helper: usize) -> Result<PeerConfig, Error> {
+        let toml_path = self.path.join(format!("h{helper}.toml"));
+        let invalid = |message: String| Error::InvalidPeer {
+            helper,
+            source_name: toml_path.display().to_string(),
+            message,
+        };
+
+        let toml = Self::read(&toml_path)
+            .await?
+            .ok_or_else(|| invalid("file not found".into()))?;
+        let mut peer: PeerConfig = Config::builder()
+            .add_source(File::from_str(&toml, FileFormat::Toml))
+            .build()
+            .and_then(Config::try_deserialize)
+            .map_err(|e| invalid(e.to_string()))?;
+
+        let pem_path = self.path.join(format!("h{helper}.pem"));
+        if let Some(pem) = Self::read(&pem_path).await? {
+            let cert = rustls_pemfile::certs(&mut pem.as_bytes())
+                .next()
+                .transpose()
+                .map_err(|e| invalid(format!("{}: {e}", pem_path.display())))?
+                .ok_or_else(|| invalid(format!("{} has no certificate", pem_path.display())))?;
+            peer.certificate = Some(cert);
+        }
+
+        Ok(peer)
+    }
+}
+
+#[async_trait]
+impl PeerDiscovery for Directory {
+    async fn peers(&self) -> Result<[PeerConfig; 3], Error> {
+        let (h1, h2, h3) = try_join3(self.peer(1), self.peer(2), self.peer(3)).await?;
+        Ok([h1, h2, h3])
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::net::{
+        discovery::{directory::Directory, Error, PeerDiscovery},
+        test::{TEST_CERTS, TEST_CERTS_DER, TEST_HPKE_PUBLIC_KEY},
+    };
+
+    fn write_peers(dir: &std::path::Path) {
+        for i in 1..=3 {
+            fs::write(
+                dir.join(format!("h{i}.toml")),
+                format!(
+                    r#"
+url = "https://helper{i}.example.com:443"
+certificate = """
+{}"""
+
+[hpke]
+public_key = "{}"
+"#,
+                    std::str::from_utf8(TEST_CERTS[i - 1]).unwrap(),
+                    TEST_HPKE_PUBLIC_KEY.trim(),
+                ),
+            )
+            .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_peers() {
+        let dir = tempdir().unwrap();
+        write_peers(dir.path());
+
+        let peers = Directory::new(dir.path()).peers().await.unwrap();
+        for (i, peer) in peers.iter().enumerate() {
+            assert_eq!(
+                peer.url,
+                format!("https://helper{}.example.com:443", i + 1).as_str()
+            );
+            assert_eq!(peer.certificate.as_ref(), Some(&TEST_CERTS_DER[i]));
+            assert!(peer.hpke_config.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn certificate_file_overrides() {
+        let dir = tempdir().unwrap();
+        write_peers(dir.path());
+        fs::write(dir.path().join("h2.pem"), TEST_CERTS[2]).unwrap();
+
+        let peers = Directory::new(dir.path()).peers().await.unwrap();
+        assert_eq!(peers[1].certificate.as_ref(), Some(&TEST_CERTS_DER[2]));
+    }
+
+    #[tokio::test]
+    async fn missing_peer() {
+        let dir = tempdir().unwrap();
+        write_peers(dir.path());
+        fs::remove_file(dir.path().join("h3.toml")).unwrap();
+
+        assert!(matches!(
+            Directory::new(dir.path()).peers().await,
+            Err(Error::InvalidPeer { helper: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,333 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures::future::try_join3;
+
+use crate::{
+    config::{HpkeClientConfig, OwnedCertificate, PeerConfig},
+    hpke::{Deserializable as _, IpaPublicKey},
+    net::discovery::{Error, PeerDiscovery},
+};
+
+/// DNS SRV record. Only the fields that are needed for discovery are included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+/// Performs the DNS lookups needed by [`Dns`].
+///
+/// This is a trait so that discovery can be tested without a DNS server.
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    /// Looks up the SRV records for `name`.
+    ///
+    /// # Errors
+    /// If the lookup fails. A name without SRV records should be reported as an error.
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, Error>;
+
+    /// Looks up the TXT records for `name`. The character strings that make up each record are
+    /// concatenated.
+    ///
+    /// # Errors
+    /// If the lookup fails. A name without TXT records should be reported as an empty list.
+    async fn lookup_txt(&self, name: &str) -> Result<Vec<String>, Error>;
+}
+
+/// Resolves the peer configuration from DNS.
+///
+/// For helper `N` (1, 2 or 3) in `domain`, the following records are used:
+///  * SRV `_hN._ipa._tcp.<domain>`, which provides the host and port of the helper. If there are
+///    several records, the one with the lowest priority and then the highest weight is used. The
+///    peer URL is `https://<target>:<port>`.
+///  * TXT `_hN._ipa._tcp.<domain>` (optional), where each record is a `key=value` pair:
+///    - `identity=<name>` sets [`PeerConfig::identity`].
+///    - `hpke=<hex>` sets the match key encryption public key.
+///    - `cert=<base64>` sets the pinned certificate (DER, base64 encoded). Certificates do not fit
+///      in a single TXT character string, but resolvers concatenate the strings of a record.
+///    - `encodings=<list>` sets [`PeerConfig::step_encodings`] from a comma-separated list.
+///
+/// Unknown keys are ignored, to allow adding keys in the future.
+pub struct Dns<R> {
+    domain: String,
+    resolver: R,
+}
+
+impl<R: Resolver> Dns<R> {
+    pub fn new<D: Into<String>>(domain: D, resolver: R) -> Self {
+        Self {
+            domain: domain.into(),
+            resolver,
+        }
+    }
+
+    async fn peer(&self, helper: usize) -> Result<PeerConfig, Error> {
+        let name = format!("_h{helper}._ipa._tcp.{}", self.domain);
+        let invalid = |message: String| Error::InvalidPeer {
+            helper,
+            source_name: name.clone(),
+            message,
+        };
+
+        let srv = self
+            .resolver
+            .lookup_srv(&name)
+            .await?
+            .into_iter()
+            .min_by_key(|srv| (srv.priority, u16::MAX - srv.weight))
+            .ok_or_else(|| invalid("no SRV records".into()))?;
+        let url = format!("https://{}:{}", srv.target.trim_end_matches('.'), srv.port)
+            .parse()
+            .map_err(|e| invalid(format!("invalid SRV target {}: {e}", srv.target)))?;
+
+        let mut peer = PeerConfig::new(url, None);
+        for record in self.resolver.lookup_txt(&name).await? {
+            let Some((key, value)) = record.split_once('=') else {
+                continue;
+            };
+            match key {
+                "identity" => peer.identity = Some(value.to_owned()),
+                "hpke" => {
+                    let public_key = hex::decode(value)
+                        .map_err(|e| e.to_string())
+                        .and_then(|bytes| {
+                            IpaPublicKey::from_bytes(&bytes).map_err(|e| e.to_string())
+                        })
+                        .map_err(|e| invalid(format!("invalid hpke public key: {e}")))?;
+                    peer.hpke_config = Some(HpkeClientConfig::new(public_key));
+                }
+                "cert" => {
+                    let der = BASE64
+                        .decode(value)
+                        .map_err(|e| invalid(format!("invalid certificate: {e}")))?;
+                    peer.certificate = Some(OwnedCertificate::from(der));
+                }
+                "encodings" => {
+                    peer.step_encodings = value.split(',').map(ToOwned::to_owned).collect();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(peer)
+    }
+}
+
+#[async_trait]
+impl<R: Resolver> PeerDiscovery for Dns<R> {
+    async fn peers(&self) -> Result<[PeerConfig; 3], Error> {
+        let (h1, h2, h3) = try_join3(self.peer(1), self.peer(2), self.peer(3)).await?;
+        Ok([h1, h2, h3])
+    }
+}
+
+/// Resolver that uses the system DNS configuration. Lookups are made with the asynchronous
+/// resolver of `hickory-resolver`, on the runtime of the caller.
+#[cfg(feature = "dns-discovery")]
+pub struct SystemResolver {
+    resolver: hickory_resolver::TokioAsyncResolver,
+}
+
+#[cfg(feature = "dns-discovery")]
+impl SystemResolver {
+    /// # Errors
+    /// If the system DNS configuration cannot be read.
+    pub fn new() -> Result<Self, Error> {
+        hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()
+            .map(|resolver| Self { resolver })
+            .map_err(|e| Error::Io {
+                path: "/etc/resolv.conf".into(),
+                inner: e.into(),
+            })
+    }
+}
+
+#[cfg(feature = "dns-discovery")]
+fn resolve_error(name: &str, e: &hickory_resolver::error::ResolveError) -> Error {
+    Error::Resolve {
+        name: name.to_owned(),
+        message: e.to_string(),
+    }
+}
+
+#[cfg(feature = "dns-discovery")]
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, Error> {
+        let lookup = self
+            .resolver
+            .srv_lookup(name)
+            .await
+            .map_err(|e| resolve_error(name, &e))?;
+        Ok(lookup
+            .iter()
+            .map(|srv| SrvRecord {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                port: srv.port(),
+                target: srv.target().to_utf8(),
+            })
+            .collect())
+    }
+
+    async fn lookup_txt(&self, name: &str) -> Result<Vec<String>, Error> {
+        use hickory_resolver::error::ResolveErrorKind;
+
+        match self.resolver.txt_lookup(name).await {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|s| String::from_utf8_lossy(s))
+                        .collect()
+                })
+                .collect()),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(Vec::new()),
+            Err(e) => Err(resolve_error(name, &e)),
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::collections::HashMap;
+
+    use async_trait::async_trait;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+    use crate::net::{
+        discovery::{
+            dns::{Dns, Resolver, SrvRecord},
+            Error, PeerDiscovery,
+        },
+        test::{TEST_CERTS_DER, TEST_HPKE_PUBLIC_KEY},
+    };
+
+    /// Resolver stub that answers from fixed records.
+    #[derive(Default)]
+    struct StubResolver {
+        srv: HashMap<String, Vec<SrvRecord>>,
+        txt: HashMap<String, Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Resolver for StubResolver {
+        async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, Error> {
+            self.srv.get(name).cloned().ok_or_else(|| Error::Resolve {
+                name: name.to_owned(),
+                message: "NXDOMAIN".into(),
+            })
+        }
+
+        async fn lookup_txt(&self, name: &str) -> Result<Vec<String>, Error> {
+            Ok(self.txt.get(name).cloned().unwrap_or_default())
+        }
+    }
+
+    fn srv(priority: u16, weight: u16, target: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port: 443,
+            target: target.to_owned(),
+        }
+    }
+
+    fn resolver() -> StubResolver {
+        let mut resolver = StubResolver::default();
+        for i in 1..=3 {
+            resolver.srv.insert(
+                format!("_h{i}._ipa._tcp.example.com"),
+                vec![srv(0, 0, &format!("helper{i}.example.com."))],
+            );
+        }
+        resolver
+    }
+
+    #[tokio::test]
+    async fn srv_only() {
+        let peers = Dns::new("example.com", resolver()).peers().await.unwrap();
+        for (i, peer) in peers.iter().enumerate() {
+            assert_eq!(
+                peer.url,
+                format!("https://helper{}.example.com:443", i + 1).as_str()
+            );
+            assert!(peer.certificate.is_none());
+            assert!(peer.hpke_config.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn preferred_srv_record() {
+        let mut resolver = resolver();
+        resolver.srv.insert(
+            "_h2._ipa._tcp.example.com".into(),
+            vec![
+                srv(10, 100, "backup.example.com."),
+                srv(0, 1, "light.example.com."),
+                srv(0, 50, "heavy.example.com."),
+            ],
+        );
+        let peers = Dns::new("example.com", resolver).peers().await.unwrap();
+        assert_eq!(peers[1].url, "https://heavy.example.com:443");
+    }
+
+    #[tokio::test]
+    async fn txt_records() {
+        let mut resolver = resolver();
+        resolver.txt.insert(
+            "_h1._ipa._tcp.example.com".into(),
+            vec![
+                "identity=spiffe://example.com/ipa/helper1".into(),
+                format!("hpke={}", TEST_HPKE_PUBLIC_KEY.trim()),
+                format!("cert={}", BASE64.encode(&TEST_CERTS_DER[0])),
+                "encodings=zstd".into(),
+                "future-key=ignored".into(),
+            ],
+        );
+        let peers = Dns::new("example.com", resolver).peers().await.unwrap();
+        assert_eq!(
+            peers[0].identity.as_deref(),
+            Some("spiffe://example.com/ipa/helper1")
+        );
+        assert!(peers[0].hpke_config.is_some());
+        assert_eq!(peers[0].certificate.as_ref(), Some(&TEST_CERTS_DER[0]));
+        assert_eq!(peers[0].step_encodings, ["zstd"]);
+        assert!(peers[1].step_encodings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_helper() {
+        let mut resolver = resolver();
+        resolver.srv.remove("_h3._ipa._tcp.example.com");
+        assert!(matches!(
+            Dns::new("example.com", resolver).peers().await,
+            Err(Error::Resolve { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_hpke_key() {
+        let mut resolver = resolver();
+        resolver.txt.insert(
+            "_h2._ipa._tcp.example.com".into(),
+            vec!["hpke=not-hex".into()],
+        );
+        assert!(matches!(
+            Dns::new("example.com", resolver).peers().await,
+            Err(Error::InvalidPeer { helper: 2, .. })
+        ));
+    }
+
+    /// The lookups themselves may fail when there is no network.
+    #[cfg(feature = "dns-discovery")]
+    #[tokio::test]
+    async fn system_resolver() {
+        let resolver = super::SystemResolver::new().unwrap();
+        let _ = resolver.lookup_srv("_h1._ipa._tcp.example.com").await;
+        let _ = resolver.lookup_txt("_h1._ipa._tcp.example.com").await;
+    }
+}
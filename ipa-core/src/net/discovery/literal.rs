@@ -1,19 +1,25 @@
-use crate::net::discovery::{peer, PeerDiscovery};
+use async_trait::async_trait;
+
+use crate::{
+    config::PeerConfig,
+    net::discovery::{Error, PeerDiscovery},
+};
 
 pub struct Literal {
-    peers: [peer::Config; 3],
+    peers: [PeerConfig; 3],
 }
 
 impl Literal {
-    pub fn new(h1: peer::Config, h2: peer::Config, h3: peer::Config) -> Self {
+    pub fn new(h1: PeerConfig, h2: PeerConfig, h3: PeerConfig) -> Self {
         Self {
             peers: [h1, h2, h3],
         }
     }
 }
 
+#[async_trait]
 impl PeerDiscovery for Literal {
-    fn peers(&self) -> &[peer::Config; 3] {
-        &self.peers
+    async fn peers(&self) -> Result<[PeerConfig; 3], Error> {
+        Ok(self.peers.clone())
     }
 }
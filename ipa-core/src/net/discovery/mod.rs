@@ -0,0 +1,52 @@
+//! Discovery of the helpers that make up the network.
+//!
+//! The helper network is normally described by listing the three peers in `network.toml`
+//! (see [`literal::Literal`]). When helpers move around, it is more convenient to resolve them
+//! dynamically:
+//!  * [`directory::Directory`] reads peer configurations from a directory that can be updated
+//!    independently of `network.toml`, e.g. by a configuration management agent.
+//!  * [`dns::Dns`] resolves peers from DNS SRV and TXT records.
+//!
+//! Dynamic backends are selected in `network.toml` with a `[discovery]` section instead of
+//! `[[peers]]` (see [`crate::config::DiscoveryConfig`]). Helpers re-run discovery periodically and
+//! apply the result if it changed.
+//!
+//! Discovery is asynchronous, so that helpers can repeat it without blocking their runtime.
+
+use std::{io, path::PathBuf};
+
+use async_trait::async_trait;
+
+use crate::config::PeerConfig;
+
+pub mod directory;
+pub mod dns;
+pub mod literal;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read {path}: {inner}")]
+    Io { path: PathBuf, inner: io::Error },
+    #[error("invalid configuration for helper {helper} in {source_name}: {message}")]
+    InvalidPeer {
+        helper: usize,
+        source_name: String,
+        message: String,
+    },
+    #[error("failed to resolve {name}: {message}")]
+    Resolve { name: String, message: String },
+    #[error("{0}")]
+    Unsupported(&'static str),
+    #[error("failed to start a runtime for discovery: {0}")]
+    Runtime(io::Error),
+}
+
+/// A source of helper network configuration.
+#[async_trait]
+pub trait PeerDiscovery {
+    /// Returns the configuration of the three helpers, ordered by helper identity.
+    ///
+    /// # Errors
+    /// If the configuration of any helper cannot be obtained.
+    async fn peers(&self) -> Result<[PeerConfig; 3], Error>;
+}
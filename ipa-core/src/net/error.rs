@@ -1,9 +1,15 @@
+use std::time::Duration;
+
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
 
-use crate::{error::BoxError, net::client::ResponseFromEndpoint, protocol::QueryId};
+use crate::{
+    error::BoxError,
+    net::client::ResponseFromEndpoint,
+    protocol::{Gate, QueryId},
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -57,6 +63,32 @@ pub enum Error {
         #[source]
         inner: hyper_util::client::legacy::Error,
     },
+    #[error("request to {dest} ({route}) timed out after {timeout:?}")]
+    Timeout {
+        dest: String,
+        route: &'static str,
+        timeout: Duration,
+    },
+    /// The request was not sent, because recent requests to the destination kept failing. See
+    /// [`CircuitBreakerConfig`](crate::config::CircuitBreakerConfig).
+    #[error("not sending {route} request to {dest}: the circuit is open after repeated failures")]
+    CircuitOpen { dest: String, route: &'static str },
+    /// A QUIC connection or stream used to send step records failed.
+    #[error("QUIC stream to {dest} failed: {inner}")]
+    Quic {
+        dest: String,
+        #[source]
+        inner: BoxError,
+    },
+    /// Sending records to another helper failed, and the stream could not be resumed. This
+    /// failure is fatal for the query.
+    #[error("failed to send records for query {query_id}, step {gate:?}: {inner}")]
+    StepFailed {
+        query_id: QueryId,
+        gate: Gate,
+        #[source]
+        inner: Box<Error>,
+    },
     #[error("{error}")]
     Application { code: StatusCode, error: BoxError },
 }
@@ -86,6 +118,36 @@ impl Error {
             })
     }
 
+    /// Returns `true` if the request failed because a connection to the destination could not be
+    /// established. Such requests were never delivered, so it is safe to retry them even if they
+    /// are not idempotent.
+    #[must_use]
+    pub fn is_connect_failure(&self) -> bool {
+        matches!(self, Self::ConnectError { inner, .. } if inner.is_connect())
+    }
+
+    /// Returns `true` if the failure is likely to be transient: the connection failed or was
+    /// interrupted (including while reading the response body), the request timed out, or a
+    /// gateway reported that the destination is unavailable. The request may or may not have
+    /// been processed by the destination.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::ConnectError { .. }
+            | Self::HyperPassthrough(_)
+            | Self::AxumPassthrough(_)
+            | Self::Quic { .. }
+            | Self::Timeout { .. } => true,
+            Self::FailedHttpRequest { status, .. } => matches!(
+                *status,
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            _ => false,
+        }
+    }
+
     #[must_use]
     pub fn application<E: Into<BoxError>>(code: StatusCode, error: E) -> Self {
         Self::Application {
@@ -151,9 +213,15 @@ impl IntoResponse for Error {
             Self::HyperPassthrough { .. }
             | Self::HyperHttpPassthrough(_)
             | Self::FailedHttpRequest { .. }
+            | Self::StepFailed { .. }
+            | Self::Quic { .. }
             | Self::InvalidUri(_)
             | Self::MissingExtension(_) => StatusCode::INTERNAL_SERVER_ERROR,
 
+            Self::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+
+            Self::CircuitOpen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+
             Self::Application { code, .. } => code,
         };
         (status_code, self.to_string()).into_response()
@@ -11,8 +11,8 @@
 //! [`crate::net::server::handlers`]. This module provides functions to accept
 //! requests for each of the server APIs.
 //!
-//! This module is organized into the submodules "echo" and "query" for their
-//! respective APIs. Each module might have a Request struct used by the client
+//! This module is organized into the submodules "echo", "admin" and "query" for
+//! their respective APIs. Each module might have a Request struct used by the client
 //! to provide request parameters using [`crate::transport`] types.
 
 type OutgoingRequest = Result<hyper::Request<axum::body::Body>, crate::net::Error>;
@@ -68,6 +68,68 @@ pub mod echo {
     pub const AXUM_PATH: &str = "/echo";
 }
 
+pub mod admin {
+    /// Operators call the admin API to inspect a running helper. It requires the same client
+    /// authentication as the helper-to-helper API.
+    pub const BASE_AXUM_PATH: &str = "/admin";
+
+    pub mod state {
+        use crate::{
+            helpers::{routing::RouteId, HelperResponse, NoQueryId, NoStep, RouteParams},
+            HelperState,
+        };
+
+        #[derive(Debug, Clone, Default)]
+        pub struct Request;
+
+        impl RouteParams<RouteId, NoQueryId, NoStep> for Request {
+            type Params = String;
+
+            fn resource_identifier(&self) -> RouteId {
+                RouteId::HelperState
+            }
+
+            fn query_id(&self) -> NoQueryId {
+                NoQueryId
+            }
+
+            fn gate(&self) -> NoStep {
+                NoStep
+            }
+
+            fn extra(&self) -> Self::Params {
+                String::new()
+            }
+        }
+
+        impl Request {
+            #[allow(clippy::unused_self)] // same signature as the other requests
+            pub fn try_into_http_request(
+                self,
+                scheme: axum::http::uri::Scheme,
+                authority: axum::http::uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let uri = axum::http::uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!("{}{AXUM_PATH}", super::BASE_AXUM_PATH))
+                    .build()?;
+                Ok(hyper::Request::get(uri).body(axum::body::Body::empty())?)
+            }
+        }
+
+        pub type ResponseBody = HelperState;
+
+        impl From<HelperResponse> for ResponseBody {
+            fn from(value: HelperResponse) -> Self {
+                serde_json::from_slice(value.into_body().as_slice()).unwrap()
+            }
+        }
+
+        pub const AXUM_PATH: &str = "/state";
+    }
+}
+
 pub mod query {
     use std::fmt::{Display, Formatter};
 
@@ -130,6 +192,14 @@ pub mod query {
                     let Query(q) = req.extract().await?;
                     Ok(QueryType::MaliciousOprfIpa(q))
                 }
+                QueryType::SEMI_HONEST_REACH_FREQUENCY_STR => {
+                    let Query(q) = req.extract().await?;
+                    Ok(QueryType::SemiHonestReachFrequency(q))
+                }
+                QueryType::MALICIOUS_REACH_FREQUENCY_STR => {
+                    let Query(q) = req.extract().await?;
+                    Ok(QueryType::MaliciousReachFrequency(q))
+                }
                 other => Err(Error::bad_query_value("query_type", other)),
             }?;
             Ok(QueryConfigQueryParams(QueryConfig {
@@ -168,6 +238,26 @@ pub mod query {
                         write!(f, "&plaintext_match_keys=true")?;
                     }
 
+                    if config.dry_run {
+                        write!(f, "&dry_run=true")?;
+                    }
+
+                    if config.legacy_reports {
+                        write!(f, "&legacy_reports=true")?;
+                    }
+
+                    if let Some(buckets) = config.value_buckets {
+                        write!(f, "&value_buckets={buckets}")?;
+                    }
+
+                    if let Some(scale) = config.trigger_value_scale {
+                        write!(f, "&trigger_value_scale={scale}")?;
+                    }
+
+                    if let Some(weights) = config.attribution_weights {
+                        write!(f, "&attribution_weights={weights}")?;
+                    }
+
                     if let Some(window) = config.attribution_window_seconds {
                         write!(f, "&attribution_window_seconds={}", window.get())?;
                     }
@@ -188,6 +278,25 @@ pub mod query {
                         write!(f, "&plaintext_match_keys=true")?;
                     }
 
+                    Ok(())
+                }
+                QueryType::SemiHonestReachFrequency(config)
+                | QueryType::MaliciousReachFrequency(config) => {
+                    write!(
+                        f,
+                        "&max_breakdown_key={}&max_frequency={}&max_breakdowns_per_user={}\
+                        &with_dp={}&epsilon={}",
+                        config.max_breakdown_key,
+                        config.max_frequency,
+                        config.max_breakdowns_per_user,
+                        config.with_dp,
+                        config.epsilon,
+                    )?;
+
+                    if config.plaintext_match_keys {
+                        write!(f, "&plaintext_match_keys=true")?;
+                    }
+
                     Ok(())
                 }
             }
@@ -352,19 +461,37 @@ pub mod query {
     }
 
     pub mod step {
-        use axum::{body::Body, http::uri};
+        use axum::{
+            body::Body,
+            http::{uri, HeaderMap, HeaderName, HeaderValue},
+        };
 
         use crate::{
             net::{http_serde::query::BASE_AXUM_PATH, Error},
             protocol::{Gate, QueryId},
         };
 
+        /// Name of the header that carries the offset of the first byte in the request body,
+        /// within the stream of records for the step. It is non-zero when a stream is resumed
+        /// after connection loss.
+        pub static OFFSET_HEADER: HeaderName = HeaderName::from_static("x-ipa-step-offset");
+
+        /// Name of the header that carries the compression of the request body, if it is
+        /// compressed. See [`crate::net::compression`].
+        pub static ENCODING_HEADER: HeaderName = HeaderName::from_static("x-ipa-step-encoding");
+
+        /// Size of an acknowledgement in the response body. The receiver responds with a stream
+        /// of big-endian `u64` offsets, each acknowledging all the bytes of the stream before it.
+        pub const ACK_LEN: usize = 8;
+
         // When this type is used on the client side, `B` is `hyper::Body`. When this type
         // is used on the server side, `B` can be any body type supported by axum.
         #[derive(Debug)]
         pub struct Request<B> {
             pub query_id: QueryId,
             pub gate: Gate,
+            pub offset: u64,
+            pub encoding: Option<&'static str>,
             pub body: B,
         }
 
@@ -373,9 +500,45 @@ pub mod query {
                 Self {
                     query_id,
                     gate,
+                    offset: 0,
+                    encoding: None,
                     body,
                 }
             }
+
+            #[must_use]
+            pub fn with_offset(mut self, offset: u64) -> Self {
+                self.offset = offset;
+                self
+            }
+
+            #[must_use]
+            pub fn with_encoding(mut self, encoding: Option<&'static str>) -> Self {
+                self.encoding = encoding;
+                self
+            }
+        }
+
+        /// Reads the stream offset of a step request. Requests without the offset header start
+        /// at the beginning of the stream.
+        ///
+        /// ## Errors
+        /// If the header is not a valid offset.
+        pub fn offset(headers: &HeaderMap) -> Result<u64, Error> {
+            headers
+                .get(&OFFSET_HEADER)
+                .map_or(Ok(0), |value| Ok(value.to_str()?.parse()?))
+        }
+
+        /// Reads the compression of a step request body.
+        ///
+        /// ## Errors
+        /// If the header is not valid.
+        pub fn encoding(headers: &HeaderMap) -> Result<Option<&str>, Error> {
+            Ok(headers
+                .get(&ENCODING_HEADER)
+                .map(HeaderValue::to_str)
+                .transpose()?)
         }
 
         /// Convert to hyper request. Used on client side.
@@ -395,7 +558,11 @@ pub mod query {
                         self.gate.as_ref()
                     ))
                     .build()?;
-                Ok(hyper::Request::post(uri).body(self.body)?)
+                let mut req = hyper::Request::post(uri).header(&OFFSET_HEADER, self.offset);
+                if let Some(encoding) = self.encoding {
+                    req = req.header(&ENCODING_HEADER, encoding);
+                }
+                Ok(req.body(self.body)?)
             }
         }
 
@@ -437,12 +604,10 @@ pub mod query {
         }
 
         impl Request {
-            #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))] // needed because client is blocking; remove when non-blocking
             pub fn new(query_id: QueryId) -> Self {
                 Self { query_id }
             }
 
-            #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))] // needed because client is blocking; remove when non-blocking
             pub fn try_into_http_request(
                 self,
                 scheme: axum::http::uri::Scheme,
@@ -507,12 +672,10 @@ pub mod query {
         }
 
         impl Request {
-            #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))] // needed because client is blocking; remove when non-blocking
             pub fn new(query_id: QueryId) -> Self {
                 Self { query_id }
             }
 
-            #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))] // needed because client is blocking; remove when non-blocking
             pub fn try_into_http_request(
                 self,
                 scheme: axum::http::uri::Scheme,
@@ -567,18 +730,10 @@ pub mod query {
         }
 
         impl Request {
-            /// Currently, it is only possible to kill
-            /// a query by issuing an HTTP request manually.
-            /// Maybe report collector can support this API,
-            /// but for now, only tests exercise this path
-            /// hence methods here are hidden behind feature
-            /// flags
-            #[cfg(all(test, unit_test))]
             pub fn new(query_id: QueryId) -> Self {
                 Self { query_id }
             }
 
-            #[cfg(all(test, unit_test))]
             pub fn try_into_http_request(
                 self,
                 scheme: axum::http::uri::Scheme,
@@ -9,15 +9,21 @@ use rustls_pki_types::CertificateDer;
 
 use crate::config::{OwnedCertificate, OwnedPrivateKey};
 
+mod certificate;
 mod client;
+pub(crate) mod compression;
+pub mod discovery;
 mod error;
 mod http_serde;
+mod resume;
 mod server;
 #[cfg(all(test, not(feature = "shuttle")))]
 pub mod test;
 mod transport;
 
+pub use certificate::ReloadableCertificate;
 pub use client::{ClientIdentity, MpcHelperClient};
+pub use compression::ChannelClass;
 pub use error::Error;
 pub use server::{MpcHelperServer, TracingSpanMaker};
 pub use transport::{HttpShardTransport, HttpTransport};
@@ -34,6 +40,10 @@ pub(crate) const MAX_HTTP2_WINDOW_SIZE: u32 = (1 << 31) - 1;
 
 pub(crate) const MAX_HTTP2_CONCURRENT_STREAMS: u32 = 5000;
 
+/// ALPN protocol identifier for step streams sent over QUIC.
+#[cfg(feature = "quic")]
+pub(crate) const QUIC_ALPN: &[u8] = b"ipa-records/2";
+
 /// Provides access to IPAs Crypto Provider (AWS Libcrypto).
 static CRYPTO_PROVIDER: Lazy<Arc<CryptoProvider>> =
     Lazy::new(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
@@ -0,0 +1,687 @@
+//! Step streams that survive connection loss.
+//!
+//! Records for a step are sent to another helper as the body of a single, long-running HTTP
+//! request. To allow that stream to be resumed over a new connection:
+//!  * Every step request carries the offset of its first byte within the stream of records for
+//!    the step (see [`OFFSET_HEADER`]).
+//!  * The receiver responds with a stream of acknowledgements: the number of bytes of the stream
+//!    that were handed over to the protocol. The response ends when the stream is complete.
+//!  * The sender retains the bytes that were not acknowledged yet, up to a limit. If the request
+//!    fails, it reconnects and replays the retained bytes.
+//!  * The receiver attaches the new request body to the existing stream, skipping the bytes it
+//!    already received, so the protocol does not notice the interruption.
+//!
+//! [`OFFSET_HEADER`]: crate::net::http_serde::query::step::OFFSET_HEADER
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use axum::body::Body;
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use hyper::StatusCode;
+use tokio::{sync::watch, time::Sleep};
+
+use crate::{
+    error::BoxError,
+    helpers::{BodyStream, HelperIdentity, StreamKey},
+    net::Error,
+    sync::{Arc, Mutex, Weak},
+};
+
+/// How long the receiver waits for the sender to resume an interrupted stream, before failing
+/// it. This needs to cover the reconnect backoff of the sender.
+pub const RESUME_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Records sent on a step stream that were not acknowledged by the receiver yet.
+///
+/// Records are pulled from the underlying stream as they are sent, and retained until they are
+/// acknowledged. When the retained bytes reach the limit, sending stops until the receiver
+/// acknowledges more of the stream.
+pub struct RetainedRecords {
+    inner: Arc<Mutex<RetainedInner>>,
+}
+
+struct RetainedInner {
+    data: Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>,
+    data_done: bool,
+    /// Retained chunks, in the order they were sent.
+    chunks: VecDeque<Bytes>,
+    /// Offset of the first retained byte.
+    start: u64,
+    /// Number of retained bytes.
+    len: usize,
+    limit: usize,
+    /// Highest offset acknowledged by the receiver.
+    acked: u64,
+    /// Offset of the next byte to send in the current replay.
+    cursor: u64,
+    /// Identifies the current replay. Only one replay can send at a time.
+    replay: u64,
+    /// Replay waiting for acknowledgements to free up space.
+    waker: Option<Waker>,
+}
+
+impl RetainedInner {
+    fn end(&self) -> u64 {
+        self.start + self.len as u64
+    }
+
+    /// Drops the chunks that were acknowledged. Chunks that were not sent in the current replay
+    /// yet are kept, because the replay must be contiguous from its start.
+    fn trim(&mut self) {
+        let limit = self.acked.min(self.cursor);
+        while let Some(chunk) = self.chunks.front() {
+            if self.start + chunk.len() as u64 > limit {
+                break;
+            }
+            self.start += chunk.len() as u64;
+            self.len -= chunk.len();
+            self.chunks.pop_front();
+        }
+        if self.len < self.limit {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl RetainedRecords {
+    pub fn new<S: Stream<Item = Vec<u8>> + Send + 'static>(data: S, limit: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(RetainedInner {
+                data: Box::pin(data),
+                data_done: false,
+                chunks: VecDeque::new(),
+                start: 0,
+                len: 0,
+                limit,
+                acked: 0,
+                cursor: 0,
+                replay: 0,
+                waker: None,
+            })),
+        }
+    }
+
+    /// Starts sending the records again, from the first byte that was not acknowledged. Any
+    /// previous replay stops.
+    ///
+    /// Returns the offset of the first byte of the replay and the replay stream.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn replay(&self) -> (u64, Replay) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.replay += 1;
+        inner.cursor = inner.start;
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+        (
+            inner.start,
+            Replay {
+                inner: Arc::clone(&self.inner),
+                id: inner.replay,
+            },
+        )
+    }
+
+    /// Records that the receiver has the stream up to `offset`.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn acknowledge(&self, offset: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.acked = inner.acked.max(offset);
+        inner.trim();
+    }
+}
+
+/// Body of a step request, see [`RetainedRecords::replay`].
+pub struct Replay {
+    inner: Arc<Mutex<RetainedInner>>,
+    id: u64,
+}
+
+impl Replay {
+    /// Sequence number of this replay. The first one is `1`.
+    #[cfg(any(test, feature = "test-fixture"))]
+    #[must_use]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Stream for Replay {
+    type Item = Result<Bytes, Infallible>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.replay != self.id {
+            return Poll::Ready(None);
+        }
+
+        // Send the retained chunks first.
+        if inner.cursor < inner.end() {
+            let mut pos = inner.start;
+            let cursor = inner.cursor;
+            let chunk = inner
+                .chunks
+                .iter()
+                .find_map(|chunk| {
+                    let chunk_end = pos + chunk.len() as u64;
+                    let found = (cursor < chunk_end)
+                        .then(|| chunk.slice(usize::try_from(cursor - pos).unwrap()..));
+                    pos = chunk_end;
+                    found
+                })
+                .expect("cursor is within the retained chunks");
+            inner.cursor += chunk.len() as u64;
+            inner.trim();
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        if inner.data_done {
+            return Poll::Ready(None);
+        }
+        if inner.len >= inner.limit {
+            inner.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        match inner.data.poll_next_unpin(cx) {
+            Poll::Ready(Some(v)) => {
+                let chunk = Bytes::from(v);
+                inner.len += chunk.len();
+                inner.cursor += chunk.len() as u64;
+                inner.chunks.push_back(chunk.clone());
+                inner.trim();
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(None) => {
+                inner.data_done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Progress of a receiving stream, sent to the step request that is currently attached to it.
+#[derive(Clone, Copy, Debug, Default)]
+struct Ack {
+    /// Identifies the request that is currently attached.
+    segment: u64,
+    offset: u64,
+    /// The stream is complete and will not be acknowledged any further.
+    end: bool,
+}
+
+/// Receiving end of a step stream, shared between the protocol consuming the stream and the
+/// requests that carry it.
+struct ReceiverState {
+    inner: Mutex<ReceiverInner>,
+    acks: watch::Sender<Ack>,
+}
+
+#[derive(Default)]
+struct ReceiverInner {
+    /// Body of the step request that is currently attached.
+    segment: Option<BodyStream>,
+    segment_id: u64,
+    /// Number of bytes handed over to the protocol.
+    received: u64,
+    /// Number of bytes to discard from the start of the segment, because they were received
+    /// before the stream was interrupted.
+    skip: u64,
+    /// Error that interrupted the last segment.
+    error: Option<BoxError>,
+    /// The stream is complete, or it was given up on.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl ReceiverState {
+    fn ack(&self, inner: &ReceiverInner) {
+        self.acks.send_replace(Ack {
+            segment: inner.segment_id,
+            offset: inner.received,
+            end: inner.closed,
+        });
+    }
+
+    /// Attaches the body of a step request starting at `offset`, and returns the response body
+    /// with the acknowledgements for it.
+    fn attach(&self, offset: u64, body: BodyStream) -> Result<Body, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return Err(Error::application(
+                StatusCode::CONFLICT,
+                "step stream is closed and cannot be resumed",
+            ));
+        }
+        if offset > inner.received {
+            return Err(Error::application(
+                StatusCode::CONFLICT,
+                format!(
+                    "step stream resumed at offset {offset}, but only {} bytes were received",
+                    inner.received
+                ),
+            ));
+        }
+        if inner.segment_id > 0 {
+            tracing::info!("step stream resumed at offset {offset}");
+        }
+
+        inner.segment_id += 1;
+        inner.skip = inner.received - offset;
+        inner.segment = Some(body);
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+
+        let segment = inner.segment_id;
+        let acks = self.acks.subscribe();
+        self.ack(&inner);
+
+        Ok(Body::from_stream(stream::unfold(
+            (acks, false),
+            move |(mut acks, end)| async move {
+                if end || acks.changed().await.is_err() {
+                    return None;
+                }
+                let ack = *acks.borrow_and_update();
+                if ack.segment != segment {
+                    return None;
+                }
+                Some((
+                    Ok::<_, Infallible>(Bytes::copy_from_slice(&ack.offset.to_be_bytes())),
+                    (acks, ack.end),
+                ))
+            },
+        )))
+    }
+}
+
+/// Stream of records handed over to the protocol. It reads from the request that is currently
+/// attached, and waits for the sender to resume the stream if that request fails.
+struct ResumableBody {
+    state: Arc<ReceiverState>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl Stream for ResumableBody {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let mut inner = this.state.inner.lock().unwrap();
+            if let Some(segment) = inner.segment.as_mut() {
+                match segment.poll_next_unpin(cx) {
+                    Poll::Ready(Some(Ok(mut bytes))) => {
+                        let skip = inner.skip.min(bytes.len() as u64);
+                        inner.skip -= skip;
+                        let bytes = bytes.split_off(usize::try_from(skip).unwrap());
+                        if bytes.is_empty() {
+                            continue;
+                        }
+                        inner.received += bytes.len() as u64;
+                        this.deadline = None;
+                        this.state.ack(&inner);
+                        return Poll::Ready(Some(Ok(bytes)));
+                    }
+                    Poll::Ready(None) => {
+                        inner.segment = None;
+                        inner.closed = true;
+                        this.state.ack(&inner);
+                        return Poll::Ready(None);
+                    }
+                    Poll::Ready(Some(Err(e))) => {
+                        tracing::warn!(
+                            "step stream interrupted after {} bytes, waiting for the sender to resume it: {e}",
+                            inner.received
+                        );
+                        inner.segment = None;
+                        inner.error = Some(e);
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            if inner.closed {
+                return Poll::Ready(None);
+            }
+            inner.waker = Some(cx.waker().clone());
+            drop(inner);
+
+            let deadline = this
+                .deadline
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(RESUME_TIMEOUT)));
+            if deadline.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            let mut inner = this.state.inner.lock().unwrap();
+            if inner.segment.is_some() {
+                this.deadline = None;
+                continue;
+            }
+            inner.closed = true;
+            this.state.ack(&inner);
+            let error = inner
+                .error
+                .take()
+                .map_or_else(|| "no error".to_string(), |e| e.to_string());
+            return Poll::Ready(Some(Err(format!(
+                "step stream was not resumed within {RESUME_TIMEOUT:?}: {error}"
+            )
+            .into())));
+        }
+    }
+}
+
+impl Drop for ResumableBody {
+    fn drop(&mut self) {
+        // The protocol does not need the rest of the stream. Tell the sender to stop.
+        let mut inner = self.state.inner.lock().unwrap();
+        if !inner.closed {
+            inner.closed = true;
+            inner.segment = None;
+            self.state.ack(&inner);
+        }
+    }
+}
+
+/// Receiving ends of the step streams of the running query, indexed the same way as the
+/// streams handed over to the protocol.
+#[derive(Default)]
+pub struct ResumableStreams {
+    inner: Mutex<HashMap<StreamKey<HelperIdentity>, Weak<ReceiverState>>>,
+}
+
+impl ResumableStreams {
+    /// Attaches the body of a step request to the stream identified by `key`, starting at
+    /// `offset`.
+    ///
+    /// Returns the response body with the acknowledgements and, if this is the first request for
+    /// the stream, the stream to hand over to the protocol.
+    ///
+    /// ## Errors
+    /// If the request does not continue a stream that can be resumed.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn attach(
+        &self,
+        key: StreamKey<HelperIdentity>,
+        offset: u64,
+        body: BodyStream,
+    ) -> Result<(Body, Option<BodyStream>), Error> {
+        let mut streams = self.inner.lock().unwrap();
+        if let Some(state) = streams.get(&key).and_then(Weak::upgrade) {
+            return Ok((state.attach(offset, body)?, None));
+        }
+        if offset != 0 {
+            return Err(Error::application(
+                StatusCode::CONFLICT,
+                format!("cannot resume unknown step stream {key:?} at offset {offset}"),
+            ));
+        }
+
+        let state = Arc::new(ReceiverState {
+            inner: Mutex::new(ReceiverInner::default()),
+            acks: watch::Sender::new(Ack::default()),
+        });
+        let acks = state.attach(0, body)?;
+        streams.insert(key, Arc::downgrade(&state));
+        let stream = BodyStream::from_bytes_stream(ResumableBody {
+            state,
+            deadline: None,
+        });
+        Ok((acks, Some(stream)))
+    }
+
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::task::Poll;
+
+    use bytes::Bytes;
+    use futures::{
+        stream::{self, poll_immediate},
+        FutureExt, StreamExt,
+    };
+    use http_body_util::BodyExt;
+    use tokio::sync::mpsc::channel;
+    use tokio_stream::wrappers::ReceiverStream;
+
+    use crate::{
+        error::BoxError,
+        helpers::{BodyStream, HelperIdentity},
+        net::resume::{ResumableStreams, RetainedRecords},
+        protocol::{Gate, QueryId},
+    };
+
+    fn chunks(n: u8, len: usize) -> Vec<Vec<u8>> {
+        (0..n).map(|i| vec![i; len]).collect()
+    }
+
+    /// Sends up to `max_chunks` chunks of a new replay, returning the replay offset and the bytes
+    /// sent.
+    fn send(records: &RetainedRecords, max_chunks: usize) -> (u64, Vec<u8>) {
+        let (offset, mut replay) = records.replay();
+        let mut sent = Vec::new();
+        for _ in 0..max_chunks {
+            match replay.next().now_or_never() {
+                Some(Some(Ok(chunk))) => sent.extend_from_slice(&chunk),
+                _ => break,
+            }
+        }
+        (offset, sent)
+    }
+
+    #[test]
+    fn replay_from_acknowledged() {
+        let records = RetainedRecords::new(stream::iter(chunks(4, 4)), 1024);
+        let (offset, sent) = send(&records, 3);
+        assert_eq!(offset, 0);
+        assert_eq!(sent, [[0; 4], [1; 4], [2; 4]].concat());
+
+        // The acknowledgement falls in the middle of the second chunk, which must be sent again.
+        records.acknowledge(6);
+        let (offset, sent) = send(&records, 10);
+        assert_eq!(offset, 4);
+        assert_eq!(sent, [[1; 4], [2; 4], [3; 4]].concat());
+    }
+
+    #[tokio::test]
+    async fn retained_bytes_limit() {
+        let records = RetainedRecords::new(stream::iter(chunks(4, 4)), 8);
+        let (_, mut replay) = records.replay();
+
+        assert!(matches!(
+            poll_immediate(&mut replay).next().await,
+            Some(Poll::Ready(_))
+        ));
+        assert!(matches!(
+            poll_immediate(&mut replay).next().await,
+            Some(Poll::Ready(_))
+        ));
+        assert_eq!(
+            poll_immediate(&mut replay).next().await,
+            Some(Poll::Pending)
+        );
+
+        records.acknowledge(4);
+        assert_eq!(
+            poll_immediate(&mut replay).next().await,
+            Some(Poll::Ready(Ok(Bytes::from(vec![2; 4]))))
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_replay_stops() {
+        let records = RetainedRecords::new(stream::iter(chunks(2, 4)), 1024);
+        let (_, mut old) = records.replay();
+        let (_, mut new) = records.replay();
+        assert_eq!(old.next().await, None);
+        assert_eq!(new.next().await, Some(Ok(Bytes::from(vec![0; 4]))));
+    }
+
+    fn segment() -> (
+        tokio::sync::mpsc::Sender<Result<Bytes, BoxError>>,
+        BodyStream,
+    ) {
+        let (tx, rx) = channel(4);
+        (tx, BodyStream::from_bytes_stream(ReceiverStream::new(rx)))
+    }
+
+    async fn next_ack(acks: &mut axum::body::Body) -> Option<u64> {
+        let frame = acks.frame().await?.unwrap().into_data().unwrap();
+        Some(u64::from_be_bytes(frame[..].try_into().unwrap()))
+    }
+
+    fn key() -> (QueryId, HelperIdentity, Gate) {
+        (QueryId, HelperIdentity::TWO, Gate::default())
+    }
+
+    #[tokio::test]
+    async fn resume_after_interruption() {
+        let streams = ResumableStreams::default();
+        let (tx, body) = segment();
+        let (mut acks, stream) = streams.attach(key(), 0, body).unwrap();
+        let mut stream = stream.unwrap();
+
+        tx.send(Ok(Bytes::from_static(&[1, 2, 3]))).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), &[1, 2, 3][..]);
+        assert_eq!(next_ack(&mut acks).await, Some(3));
+        tx.send(Err("connection reset".into())).await.unwrap();
+        assert!(matches!(
+            poll_immediate(&mut stream).next().await,
+            Some(Poll::Pending)
+        ));
+
+        // The sender did not get the acknowledgement for the last byte, so it replays it.
+        let (tx, body) = segment();
+        let (mut new_acks, new_stream) = streams.attach(key(), 2, body).unwrap();
+        assert!(new_stream.is_none());
+        tx.send(Ok(Bytes::from_static(&[3, 4, 5]))).await.unwrap();
+        drop(tx);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), &[4, 5][..]);
+        assert!(stream.next().await.is_none());
+
+        // The acknowledgements for the first request end when it is replaced, and the ones for
+        // the second request end when the stream is complete.
+        assert_eq!(next_ack(&mut acks).await, None);
+        assert_eq!(next_ack(&mut new_acks).await, Some(5));
+        assert_eq!(next_ack(&mut new_acks).await, None);
+    }
+
+    #[tokio::test]
+    async fn gap_rejected() {
+        let streams = ResumableStreams::default();
+        let (_tx, body) = segment();
+        let (_, _stream) = streams.attach(key(), 0, body).unwrap();
+
+        let (_tx, body) = segment();
+        assert!(streams.attach(key(), 1, body).is_err());
+    }
+
+    #[tokio::test]
+    async fn unknown_stream_rejected() {
+        let streams = ResumableStreams::default();
+        let (_tx, body) = segment();
+        assert!(streams.attach(key(), 10, body).is_err());
+    }
+}
+
+/// Step streams sent between helpers over HTTP. These need the `real-world-infra` feature,
+/// because the in-memory infrastructure reads the whole body of a request before handling it.
+#[cfg(all(test, web_test, descriptive_gate))]
+mod e2e_tests {
+    use futures::{stream, StreamExt};
+    use ipa_step::StepNarrow;
+
+    use crate::{
+        config::{ClientConfig, PeerConfig},
+        helpers::{
+            faults::{Fault, FaultInjector, FaultRule},
+            HelperIdentity, Transport,
+        },
+        net::{test::TestServer, ClientIdentity, MpcHelperClient},
+        protocol::{Gate, QueryId},
+        sync::Arc,
+    };
+
+    fn chunks(n: u8, len: usize) -> Vec<Vec<u8>> {
+        (0..n).map(|i| vec![i; len]).collect()
+    }
+
+    /// Cuts the connection of a step stream sent over HTTP part way through. The sender retains
+    /// fewer bytes than were sent before the cut, so it can only complete the stream by resuming
+    /// it from the offset that the receiver acknowledged.
+    #[tokio::test]
+    async fn resume_over_http() {
+        const CHUNKS: u8 = 32;
+        const CHUNK_LEN: usize = 1024;
+        const CUT_AT: usize = 8;
+
+        let server = TestServer::builder().disable_https().build().await;
+        let mut config = ClientConfig::default();
+        config.step_stream.retained_bytes = 4 * CHUNK_LEN;
+        let faults = FaultInjector::new(
+            0,
+            vec![FaultRule::new(Fault::Disconnect, 1.0)
+                .from_chunk(CUT_AT)
+                .at_most(1)],
+        );
+        let client = MpcHelperClient::new(
+            &config,
+            PeerConfig::new(
+                format!("http://localhost:{}", server.addr.port())
+                    .parse()
+                    .unwrap(),
+                None,
+            ),
+            ClientIdentity::Helper(HelperIdentity::TWO),
+        )
+        .with_fault_injector(HelperIdentity::TWO, &faults);
+
+        let gate = Gate::default().narrow("resume");
+        let send = tokio::spawn({
+            let gate = gate.clone();
+            async move {
+                client
+                    .send_records(QueryId, &gate, stream::iter(chunks(CHUNKS, CHUNK_LEN)))
+                    .await
+            }
+        });
+        let received = Arc::clone(&server.transport)
+            .receive(HelperIdentity::TWO, (QueryId, gate))
+            .into_bytes_stream()
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        send.await.unwrap().unwrap();
+
+        assert_eq!(received, chunks(CHUNKS, CHUNK_LEN).concat());
+        let injected = faults.injected();
+        assert_eq!(injected.len(), 1);
+        assert_eq!(injected[0].chunk, CUT_AT);
+    }
+}
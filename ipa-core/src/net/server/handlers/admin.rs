@@ -0,0 +1,95 @@
+use axum::{routing::get, Extension, Json, Router};
+use hyper::StatusCode;
+use tower::layer::layer_fn;
+
+use crate::{
+    helpers::{BodyStream, Transport},
+    net::{
+        http_serde::admin::state::{self, Request},
+        server::{handlers::query::HelperAuthentication, Error},
+        HttpTransport,
+    },
+    sync::Arc,
+};
+
+async fn handler(
+    transport: Extension<Arc<HttpTransport>>,
+) -> Result<Json<state::ResponseBody>, Error> {
+    let transport = Transport::clone_ref(&*transport);
+    match transport.dispatch(Request, BodyStream::empty()).await {
+        Ok(resp) => Ok(Json(state::ResponseBody::from(resp))),
+        Err(e) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
+
+/// Construct router for the admin API, which reports the state of this helper to operators.
+///
+/// The admin API exposes the configuration of the queries this helper runs, so it requires the
+/// caller to authenticate like another helper does. See [`HelperAuthentication`].
+pub fn router(transport: Arc<HttpTransport>) -> Router {
+    Router::new()
+        .route(state::AXUM_PATH, get(handler))
+        .layer(Extension(transport))
+        .layer(layer_fn(HelperAuthentication::new))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::http::uri::{Authority, Scheme};
+    use hyper::StatusCode;
+
+    use crate::{
+        helpers::{
+            make_owned_handler,
+            routing::{Addr, RouteId},
+            BodyStream, HelperIdentity, HelperResponse,
+        },
+        net::{
+            http_serde,
+            server::{
+                handlers::query::test_helpers::{assert_fails_with, assert_success_with},
+                ClientIdentity,
+            },
+        },
+        HelperState,
+    };
+
+    fn state() -> HelperState {
+        HelperState {
+            version: "1.2.3".into(),
+            features: vec!["web-app".into()],
+            hpke_key_ids: vec![0, 1],
+            queries: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn state_test() {
+        let handler = make_owned_handler(
+            move |addr: Addr<HelperIdentity>, _data: BodyStream| async move {
+                let RouteId::HelperState = addr.route else {
+                    panic!("unexpected call");
+                };
+                Ok(HelperResponse::from(state()))
+            },
+        );
+
+        let mut req = http_serde::admin::state::Request
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        req.extensions_mut()
+            .insert(ClientIdentity(HelperIdentity::ONE));
+        let body = assert_success_with(req, handler).await;
+        let resp: HelperState = serde_json::from_slice(&body).unwrap();
+        assert_eq!(resp.version, "1.2.3");
+        assert_eq!(resp.hpke_key_ids, [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn requires_authentication() {
+        let req = http_serde::admin::state::Request
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        assert_fails_with(req, StatusCode::UNAUTHORIZED).await;
+    }
+}
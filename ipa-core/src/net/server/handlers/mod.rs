@@ -1,3 +1,4 @@
+mod admin;
 mod echo;
 mod query;
 
@@ -9,10 +10,15 @@ use crate::{
 };
 
 pub fn router(transport: Arc<HttpTransport>) -> Router {
-    echo::router().nest(
-        http_serde::query::BASE_AXUM_PATH,
-        Router::new()
-            .merge(query::query_router(Arc::clone(&transport)))
-            .merge(query::h2h_router(transport)),
-    )
+    echo::router()
+        .nest(
+            http_serde::admin::BASE_AXUM_PATH,
+            admin::router(Arc::clone(&transport)),
+        )
+        .nest(
+            http_serde::query::BASE_AXUM_PATH,
+            Router::new()
+                .merge(query::query_router(Arc::clone(&transport)))
+                .merge(query::h2h_router(transport)),
+        )
 }
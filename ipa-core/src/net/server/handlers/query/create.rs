@@ -23,6 +23,9 @@ async fn handler(
         Err(err @ ApiError::NewQuery(NewQueryError::State { .. })) => {
             Err(Error::application(StatusCode::CONFLICT, err))
         }
+        Err(err @ ApiError::NewQuery(NewQueryError::Config(_))) => {
+            Err(Error::application(StatusCode::BAD_REQUEST, err))
+        }
         Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
     }
 }
@@ -44,10 +47,15 @@ mod tests {
     };
 
     use crate::{
-        ff::FieldType,
+        ff::{
+            fixed_point::{AttributionWeights, FixedPointScale},
+            FieldType,
+        },
         helpers::{
             make_owned_handler,
-            query::{IpaQueryConfig, PrepareQuery, QueryConfig, QueryType},
+            query::{
+                IpaQueryConfig, PrepareQuery, QueryConfig, QueryType, ReachFrequencyQueryConfig,
+            },
             routing::RouteId,
             HelperResponse, Role, RoleAssignment,
         },
@@ -55,7 +63,7 @@ mod tests {
             http_serde,
             server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
         },
-        protocol::QueryId,
+        protocol::{ipa_prf::ValueBuckets, QueryId},
     };
 
     async fn create_test(expected_query_config: QueryConfig) {
@@ -97,6 +105,11 @@ mod tests {
                     with_dp: 0,
                     epsilon: 5.0,
                     plaintext_match_keys: true,
+                    dry_run: false,
+                    legacy_reports: false,
+                    value_buckets: None,
+                    trigger_value_scale: None,
+                    attribution_weights: None,
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -117,6 +130,11 @@ mod tests {
                     with_dp: 1,
                     epsilon: 5.0,
                     plaintext_match_keys: true,
+                    dry_run: false,
+                    legacy_reports: false,
+                    value_buckets: None,
+                    trigger_value_scale: None,
+                    attribution_weights: None,
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -137,6 +155,11 @@ mod tests {
                     with_dp: 1,
                     epsilon: 5.0,
                     plaintext_match_keys: true,
+                    dry_run: false,
+                    legacy_reports: false,
+                    value_buckets: None,
+                    trigger_value_scale: None,
+                    attribution_weights: None,
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -158,6 +181,81 @@ mod tests {
                 with_dp: 0,
                 epsilon: 5.0,
                 plaintext_match_keys: true,
+                dry_run: false,
+                legacy_reports: false,
+                value_buckets: None,
+                trigger_value_scale: None,
+                attribution_weights: None,
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_dry_run() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                dry_run: true,
+                ..Default::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_legacy_reports() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                legacy_reports: true,
+                ..Default::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_value_buckets() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                value_buckets: Some(ValueBuckets::new(&[10, 50]).unwrap()),
+                ..Default::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_ipa_fixed_point() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::MaliciousOprfIpa(IpaQueryConfig {
+                trigger_value_scale: Some(FixedPointScale::new(NonZeroU32::new(100).unwrap())),
+                attribution_weights: Some("0.4,0.3".parse::<AttributionWeights>().unwrap()),
+                ..Default::default()
+            }),
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_reach_frequency() {
+        create_test(QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::SemiHonestReachFrequency(ReachFrequencyQueryConfig {
+                max_breakdown_key: 20,
+                max_frequency: 4,
+                max_breakdowns_per_user: 2,
+                with_dp: 1,
+                epsilon: 5.0,
+                plaintext_match_keys: true,
             }),
         })
         .await;
@@ -68,7 +68,7 @@ pub struct HelperAuthentication<S> {
 }
 
 impl<S> HelperAuthentication<S> {
-    fn new(inner: S) -> Self {
+    pub(super) fn new(inner: S) -> Self {
         Self { inner }
     }
 }
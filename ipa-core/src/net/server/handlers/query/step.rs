@@ -1,9 +1,10 @@
-use axum::{extract::Path, routing::post, Extension, Router};
+use axum::{body::Body, extract::Path, http::HeaderMap, routing::post, Extension, Router};
+use hyper::StatusCode;
 
 use crate::{
     helpers::{BodyStream, Transport},
     net::{
-        http_serde,
+        compression, http_serde,
         server::{ClientIdentity, Error},
         HttpTransport,
     },
@@ -17,11 +18,15 @@ async fn handler(
     transport: Extension<Arc<HttpTransport>>,
     from: Extension<ClientIdentity>,
     Path((query_id, gate)): Path<(QueryId, Gate)>,
+    headers: HeaderMap,
     body: BodyStream,
-) -> Result<(), Error> {
+) -> Result<Body, Error> {
+    let offset = http_serde::query::step::offset(&headers)?;
+    let encoding = http_serde::query::step::encoding(&headers)?;
+    let body = compression::decode(body, encoding)
+        .map_err(|e| Error::application(StatusCode::UNSUPPORTED_MEDIA_TYPE, e))?;
     let transport = Transport::clone_ref(&*transport);
-    transport.receive_stream(query_id, gate, **from, body);
-    Ok(())
+    transport.receive_stream(query_id, gate, **from, offset, body)
 }
 
 pub fn router(transport: Arc<HttpTransport>) -> Router {
@@ -79,6 +84,8 @@ mod tests {
         client_id: Option<ClientIdentity>,
         query_id: String,
         gate: Gate,
+        offset: u64,
+        encoding: Option<&'static str>,
         payload: Vec<u8>,
     }
 
@@ -90,8 +97,12 @@ mod tests {
                 val.query_id,
                 val.gate.as_ref()
             );
-            hyper::Request::post(uri)
-                .maybe_extension(val.client_id)
+            let mut req = hyper::Request::post(uri)
+                .header(&http_serde::query::step::OFFSET_HEADER, val.offset);
+            if let Some(encoding) = val.encoding {
+                req = req.header(&http_serde::query::step::ENCODING_HEADER, encoding);
+            }
+            req.maybe_extension(val.client_id)
                 .body(Body::from(val.payload))
                 .unwrap()
         }
@@ -103,6 +114,8 @@ mod tests {
                 client_id: Some(ClientIdentity(HelperIdentity::ONE)),
                 query_id: QueryId.as_ref().to_string(),
                 gate: Gate::default().narrow("test"),
+                offset: 0,
+                encoding: None,
                 payload: vec![1; DATA_LEN * MESSAGE_PAYLOAD_SIZE_BYTES],
             }
         }
@@ -117,6 +130,24 @@ mod tests {
         assert_fails_with(req.into(), StatusCode::BAD_REQUEST).await;
     }
 
+    #[tokio::test]
+    async fn resume_unknown_stream_fails() {
+        let req = OverrideReq {
+            offset: 42,
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::CONFLICT).await;
+    }
+
+    #[tokio::test]
+    async fn unsupported_encoding_fails() {
+        let req = OverrideReq {
+            encoding: Some("gzip"),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::UNSUPPORTED_MEDIA_TYPE).await;
+    }
+
     #[tokio::test]
     async fn auth_required() {
         let req = OverrideReq {
@@ -1,8 +1,10 @@
 mod config;
 mod handlers;
+#[cfg(feature = "quic")]
+mod quic;
 
 use std::{
-    borrow::Cow,
+    collections::HashSet,
     io,
     net::{Ipv4Addr, SocketAddr, TcpListener},
     ops::Deref,
@@ -10,7 +12,6 @@ use std::{
 };
 
 use ::tokio::{
-    fs,
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
 };
@@ -42,14 +43,17 @@ use tower_http::trace::TraceLayer;
 use tracing::{error, Span};
 
 use crate::{
-    config::{NetworkConfig, OwnedCertificate, OwnedPrivateKey, ServerConfig, TlsConfig},
+    config::{NetworkConfig, ServerConfig, TlsConfig},
     error::BoxError,
     helpers::HelperIdentity,
     net::{
-        parse_certificate_and_private_key_bytes, server::config::HttpServerConfig, Error,
-        HttpTransport, CRYPTO_PROVIDER,
+        certificate::{check_expiry, has_identity, ReloadableCertificate},
+        client::{ClientIdentity as HelperClientIdentity, MpcHelperClient},
+        parse_certificate_and_private_key_bytes,
+        server::config::HttpServerConfig,
+        Error, HttpTransport, CRYPTO_PROVIDER,
     },
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::JoinHandle,
     telemetry::metrics::{web::RequestProtocolVersion, REQUESTS_RECEIVED},
 };
@@ -80,9 +84,16 @@ impl TracingSpanMaker for () {
 pub struct MpcHelperServer {
     transport: Arc<HttpTransport>,
     config: ServerConfig,
-    network_config: NetworkConfig,
+    network_config: SharedNetworkConfig,
+    rustls_config: Mutex<Option<RustlsConfig>>,
+    /// Accepts step streams over QUIC, if enabled in the server configuration.
+    #[cfg(feature = "quic")]
+    quic_endpoint: Mutex<Option<quinn::Endpoint>>,
 }
 
+/// Network configuration that can be replaced while the server is running.
+type SharedNetworkConfig = Arc<Mutex<Arc<NetworkConfig>>>;
+
 impl MpcHelperServer {
     pub fn new(
         transport: Arc<HttpTransport>,
@@ -92,8 +103,51 @@ impl MpcHelperServer {
         MpcHelperServer {
             transport,
             config,
-            network_config,
+            network_config: Arc::new(Mutex::new(Arc::new(network_config))),
+            rustls_config: Mutex::new(None),
+            #[cfg(feature = "quic")]
+            quic_endpoint: Mutex::new(None),
+        }
+    }
+
+    fn network_config(&self) -> Arc<NetworkConfig> {
+        Arc::clone(&self.network_config.lock().unwrap())
+    }
+
+    /// Replaces the helper network configuration without restarting the server.
+    ///
+    /// The new configuration is validated first, and if it is not valid, the current
+    /// configuration is kept. Otherwise:
+    ///  * New connections from other helpers are authenticated according to the new
+    ///    configuration. Established connections are not affected.
+    ///  * The transport gets new clients, built with `identity`, to talk to the other helpers.
+    ///    If a query is running, they are used starting with the next query.
+    ///
+    /// # Errors
+    /// If the new configuration is not valid.
+    ///
+    /// # Panics
+    /// If a mutex is poisoned.
+    pub fn update_network_config(
+        &self,
+        network_config: NetworkConfig,
+        identity: &HelperClientIdentity,
+    ) -> Result<(), BoxError> {
+        network_config.validate()?;
+        if !self.config.disable_https {
+            let tls_config = rustls_server_config(&self.config, &network_config)?;
+            #[cfg(feature = "quic")]
+            if let Some(endpoint) = self.quic_endpoint.lock().unwrap().as_ref() {
+                endpoint.set_server_config(Some(quic::server_config(tls_config.clone())?));
+            }
+            if let Some(rustls_config) = self.rustls_config.lock().unwrap().as_ref() {
+                rustls_config.reload_from_config(Arc::new(tls_config));
+            }
         }
+        self.transport
+            .update_clients(MpcHelperClient::from_conf(&network_config, identity));
+        *self.network_config.lock().unwrap() = Arc::new(network_config);
+        Ok(())
     }
 
     fn router(&self) -> Router {
@@ -115,10 +169,14 @@ impl MpcHelperServer {
     ///
     /// Returns the `SocketAddr` of the server socket and the `JoinHandle` of the server task.
     ///
+    /// If [`ServerConfig::enable_quic`] is set, also accepts step streams over QUIC on the UDP
+    /// port with the same number as the server port.
+    ///
     /// # Panics
     /// If the server TLS configuration is not valid, or if the match key encryption key
     /// configuration is invalid. (No match key encryption is okay for now, but if there is a key
-    /// configured, it must be valid.)
+    /// configured, it must be valid.) Also if QUIC is enabled, but the server uses HTTP, the
+    /// `quic` feature is not enabled, or the UDP port cannot be bound.
     pub async fn start_on<T: TracingSpanMaker>(
         &self,
         listener: Option<TcpListener>,
@@ -157,12 +215,10 @@ impl MpcHelperServer {
                 spawn_server(axum_server::bind(addr), handle.clone(), svc).await
             }
             (false, Some(listener)) => {
-                let rustls_config = rustls_config(&self.config, &self.network_config)
-                    .await
-                    .expect("invalid TLS configuration");
+                let rustls_config = self.start_rustls().expect("invalid TLS configuration");
                 spawn_server(
                     axum_server::from_tcp_rustls(listener, rustls_config).map(|a| {
-                        ClientCertRecognizingAcceptor::new(a, self.network_config.clone())
+                        ClientCertRecognizingAcceptor::new(a, Arc::clone(&self.network_config))
                     }),
                     handle.clone(),
                     svc.into_make_service(),
@@ -171,12 +227,10 @@ impl MpcHelperServer {
             }
             (false, None) => {
                 let addr = SocketAddr::new(BIND_ADDRESS.into(), self.config.port.unwrap_or(0));
-                let rustls_config = rustls_config(&self.config, &self.network_config)
-                    .await
-                    .expect("invalid TLS configuration");
+                let rustls_config = self.start_rustls().expect("invalid TLS configuration");
                 spawn_server(
                     axum_server::bind_rustls(addr, rustls_config).map(|a| {
-                        ClientCertRecognizingAcceptor::new(a, self.network_config.clone())
+                        ClientCertRecognizingAcceptor::new(a, Arc::clone(&self.network_config))
                     }),
                     handle.clone(),
                     svc.into_make_service(),
@@ -189,6 +243,12 @@ impl MpcHelperServer {
             .listening()
             .await
             .expect("Failed to bind server to a port");
+        #[cfg(feature = "quic")]
+        if self.config.enable_quic {
+            self.start_quic(bound_addr);
+        }
+        #[cfg(not(feature = "quic"))]
+        assert!(!self.config.enable_quic, "QUIC requires the `quic` feature");
         #[cfg(not(test))] // reduce spam in test output
         tracing::info!(
             "server listening on {}://{}",
@@ -202,6 +262,30 @@ impl MpcHelperServer {
         (bound_addr, task_handle)
     }
 
+    fn start_rustls(&self) -> Result<RustlsConfig, BoxError> {
+        let config = RustlsConfig::from_config(Arc::new(rustls_server_config(
+            &self.config,
+            &self.network_config(),
+        )?));
+        *self.rustls_config.lock().unwrap() = Some(config.clone());
+        Ok(config)
+    }
+
+    #[cfg(feature = "quic")]
+    fn start_quic(&self, addr: SocketAddr) {
+        assert!(!self.config.disable_https, "QUIC requires HTTPS");
+        let tls_config = rustls_server_config(&self.config, &self.network_config())
+            .expect("invalid TLS configuration");
+        let endpoint = quic::start(
+            addr,
+            quic::server_config(tls_config).expect("invalid QUIC TLS configuration"),
+            Arc::clone(&self.transport),
+            Arc::clone(&self.network_config),
+        )
+        .expect("Failed to bind QUIC endpoint");
+        *self.quic_endpoint.lock().unwrap() = Some(endpoint);
+    }
+
     pub fn start<T: TracingSpanMaker>(
         &self,
         tracing: T,
@@ -238,55 +322,48 @@ where
     })
 }
 
-async fn certificate_and_key(
-    config: &ServerConfig,
-) -> Result<(Vec<OwnedCertificate>, OwnedPrivateKey), BoxError> {
-    let (cert, key) = match &config.tls {
-        None => return Err("missing TLS configuration".into()),
-        Some(TlsConfig::Inline {
-            certificate,
-            private_key,
-        }) => (
-            Cow::Borrowed(certificate.as_bytes()),
-            Cow::Borrowed(private_key.as_bytes()),
-        ),
-        Some(TlsConfig::File {
-            certificate_file,
-            private_key_file,
-        }) => {
-            let cert = fs::read(certificate_file).await?;
-            let key = fs::read(private_key_file).await?;
-            (Cow::Owned(cert), Cow::Owned(key))
-        }
-    };
-    parse_certificate_and_private_key_bytes(&mut cert.as_ref(), &mut key.as_ref())
-        .map_err(BoxError::from)
-}
-
-/// Create a `RustlsConfig` for the `ServerConfig`.
+/// Create a native rustls configuration for the `ServerConfig`.
+///
+/// The server uses `RustlsConfig`, which is an axum type. Since we have particular needs related
+/// to client certificates, we build a native rustls config, and then convert it into the axum
+/// config type. The axum config can be updated with a new native config when the network
+/// configuration changes.
 ///
-/// `RustlsConfig` is an axum type. The native rustls configuration is `rustls::ServerConfig`, which
-/// we import as `RustlsServerConfig`. Since we have particular needs related to client
-/// certificates, we build a native rustls config, and then convert it into the axum config type.
+/// If the server certificate is configured with [`TlsConfig::File`], it is reloaded when the files
+/// change.
 ///
 /// # Errors
 /// If there is a problem with the TLS configuration.
-async fn rustls_config(
+fn rustls_server_config(
     config: &ServerConfig,
     network: &NetworkConfig,
-) -> Result<RustlsConfig, BoxError> {
-    let (cert, key) = certificate_and_key(config).await?;
-
+) -> Result<rustls::ServerConfig, BoxError> {
     let mut trusted_certs = RootCertStore::empty();
-    for cert in network
-        .peers()
-        .iter()
-        .filter_map(|peer| peer.certificate.clone())
-    {
-        // Note that this uses `webpki::TrustAnchor::try_from_cert_der`, which *does not* validate
-        // the certificate. That is not required for security, but might be desirable to flag
-        // configuration errors.
-        trusted_certs.add(cert)?;
+    if network.uses_root_certificates() {
+        let mut identities = HashSet::new();
+        for peer in network.peers() {
+            let identity = peer
+                .expected_identity()
+                .ok_or("peer identity or URL host must be specified")?;
+            if !identities.insert(identity) {
+                return Err(format!("peer identity {identity} is not unique").into());
+            }
+        }
+        for cert in &network.root_certificates {
+            trusted_certs.add(cert.clone())?;
+        }
+    } else {
+        for cert in network
+            .peers()
+            .iter()
+            .filter_map(|peer| peer.certificate.clone())
+        {
+            check_expiry("pinned peer certificate", &cert);
+            // Note that this uses `webpki::TrustAnchor::try_from_cert_der`, which *does not* validate
+            // the certificate. That is not required for security, but might be desirable to flag
+            // configuration errors.
+            trusted_certs.add(cert)?;
+        }
     }
     let client_verifier = WebPkiClientVerifier::builder_with_provider(
         trusted_certs.into(),
@@ -295,15 +372,35 @@ async fn rustls_config(
     .allow_unauthenticated()
     .build()
     .expect("Error building client verifier, should specify valid Trust Anchors");
-    let mut config = rustls::ServerConfig::builder_with_provider(Arc::clone(&CRYPTO_PROVIDER))
+    let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&CRYPTO_PROVIDER))
         .with_safe_default_protocol_versions()
         .expect("Default crypto provider should be valid")
-        .with_client_cert_verifier(client_verifier)
-        .with_single_cert(cert, key)?;
+        .with_client_cert_verifier(client_verifier);
+    let mut config = match &config.tls {
+        None => return Err("missing TLS configuration".into()),
+        Some(TlsConfig::Inline {
+            certificate,
+            private_key,
+        }) => {
+            let (cert, key) = parse_certificate_and_private_key_bytes(
+                &mut certificate.as_bytes(),
+                &mut private_key.as_bytes(),
+            )?;
+            check_expiry("server certificate", &cert[0]);
+            builder.with_single_cert(cert, key)?
+        }
+        Some(TlsConfig::File {
+            certificate_file,
+            private_key_file,
+        }) => builder.with_cert_resolver(ReloadableCertificate::from_files(
+            certificate_file,
+            private_key_file,
+        )?),
+    };
 
     config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 
-    Ok(RustlsConfig::from_config(Arc::new(config)))
+    Ok(config)
 }
 
 /// Axum `Extension` indicating the authenticated remote helper identity, if any.
@@ -327,14 +424,14 @@ impl Deref for ClientIdentity {
 #[derive(Clone)]
 struct ClientCertRecognizingAcceptor {
     inner: RustlsAcceptor,
-    network_config: Arc<NetworkConfig>,
+    network_config: SharedNetworkConfig,
 }
 
 impl ClientCertRecognizingAcceptor {
-    fn new(inner: RustlsAcceptor, network_config: NetworkConfig) -> Self {
+    fn new(inner: RustlsAcceptor, network_config: SharedNetworkConfig) -> Self {
         Self {
             inner,
-            network_config: Arc::new(network_config),
+            network_config,
         }
     }
 
@@ -344,11 +441,18 @@ impl ClientCertRecognizingAcceptor {
         cert_option: Option<&CertificateDer>,
     ) -> Option<ClientIdentity> {
         let cert = cert_option?;
-        // We currently require an exact match with the peer cert (i.e. we don't support verifying
-        // the certificate against a truststore and identifying the peer by the certificate
-        // subject). This could be changed if the need arises.
+        // If root certificates are configured, the certificate has already been verified against
+        // them, and the peer is identified by a subject alternative name. Otherwise, we require an
+        // exact match with the pinned peer cert.
         for (id, peer) in network_config.enumerate_peers() {
-            if peer.certificate.as_ref() == Some(cert) {
+            let matches = if network_config.uses_root_certificates() {
+                peer.expected_identity()
+                    .is_some_and(|identity| has_identity(cert, identity))
+            } else {
+                peer.certificate.as_ref() == Some(cert)
+            };
+            if matches {
+                check_expiry(&format!("{id:?} client certificate"), cert);
                 return Some(ClientIdentity(id));
             }
         }
@@ -372,7 +476,9 @@ where
 
     fn accept(&self, stream: I, service: S) -> Self::Future {
         let acceptor = self.inner.clone();
-        let network_config = Arc::clone(&self.network_config);
+        // Connections are identified using the network configuration at the time they are
+        // accepted.
+        let network_config = Arc::clone(&self.network_config.lock().unwrap());
 
         Box::pin(async move {
             let (stream, service) = acceptor.accept(stream, service).await.map_err(|err| {
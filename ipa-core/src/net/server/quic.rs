@@ -0,0 +1,142 @@
+use std::{io, net::SocketAddr};
+
+use futures::stream;
+use quinn::{crypto::rustls::QuicServerConfig, Connection, Endpoint, Incoming, RecvStream, VarInt};
+use rustls_pki_types::CertificateDer;
+use tracing::{debug, warn};
+
+use super::{ClientCertRecognizingAcceptor, ClientIdentity, SharedNetworkConfig};
+use crate::{
+    error::BoxError,
+    helpers::{BodyStream, HelperIdentity},
+    net::{compression, HttpTransport, QUIC_ALPN},
+    protocol::{Gate, QueryId},
+    sync::Arc,
+};
+
+/// Largest chunk read from a QUIC stream at once.
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// QUIC error code sent when closing a connection from a client that is not a known helper.
+const UNKNOWN_HELPER: VarInt = VarInt::from_u32(1);
+
+/// Converts the server TLS configuration into a QUIC configuration.
+///
+/// # Errors
+/// If the TLS configuration does not support TLS 1.3, which QUIC requires.
+pub(super) fn server_config(
+    mut tls_config: rustls::ServerConfig,
+) -> Result<quinn::ServerConfig, BoxError> {
+    tls_config.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(
+        QuicServerConfig::try_from(tls_config)?,
+    )))
+}
+
+/// Accepts step streams from other helpers on `addr`, and hands them to `transport`.
+///
+/// Each stream carries the records for one step, in the format written by the QUIC client: the
+/// query id, the gate name and the encoding of the records, each prefixed with its length (two
+/// bytes, big-endian), followed by the records.
+///
+/// # Errors
+/// If the UDP socket cannot be bound.
+pub(super) fn start(
+    addr: SocketAddr,
+    config: quinn::ServerConfig,
+    transport: Arc<HttpTransport>,
+    network_config: SharedNetworkConfig,
+) -> io::Result<Endpoint> {
+    let endpoint = Endpoint::server(config, addr)?;
+    tokio::spawn({
+        let endpoint = endpoint.clone();
+        async move {
+            while let Some(incoming) = endpoint.accept().await {
+                tokio::spawn(accept_connection(
+                    incoming,
+                    Arc::clone(&transport),
+                    Arc::clone(&network_config),
+                ));
+            }
+        }
+    });
+    Ok(endpoint)
+}
+
+async fn accept_connection(
+    incoming: Incoming,
+    transport: Arc<HttpTransport>,
+    network_config: SharedNetworkConfig,
+) {
+    let conn = match incoming.await {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!("failed to accept QUIC connection: {e}");
+            return;
+        }
+    };
+    let Some(ClientIdentity(from)) = identify(&conn, &network_config) else {
+        warn!(
+            "closing QUIC connection from {}: not a known helper",
+            conn.remote_address()
+        );
+        conn.close(UNKNOWN_HELPER, b"unknown helper");
+        return;
+    };
+    loop {
+        match conn.accept_uni().await {
+            Ok(stream) => {
+                let transport = Arc::clone(&transport);
+                tokio::spawn(async move {
+                    if let Err(e) = receive_stream(stream, from, transport).await {
+                        warn!("failed to receive QUIC stream from {from:?}: {e}");
+                    }
+                });
+            }
+            Err(e) => {
+                debug!("QUIC connection from {from:?} closed: {e}");
+                return;
+            }
+        }
+    }
+}
+
+fn identify(conn: &Connection, network_config: &SharedNetworkConfig) -> Option<ClientIdentity> {
+    let certs = conn
+        .peer_identity()?
+        .downcast::<Vec<CertificateDer<'static>>>()
+        .ok()?;
+    ClientCertRecognizingAcceptor::identify_client(&network_config.lock().unwrap(), certs.first())
+}
+
+async fn read_field(stream: &mut RecvStream) -> Result<String, BoxError> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).await?;
+    let mut field = vec![0; u16::from_be_bytes(len).into()];
+    stream.read_exact(&mut field).await?;
+    Ok(String::from_utf8(field)?)
+}
+
+async fn receive_stream(
+    mut stream: RecvStream,
+    from: HelperIdentity,
+    transport: Arc<HttpTransport>,
+) -> Result<(), BoxError> {
+    let query_id = QueryId::try_from(read_field(&mut stream).await?.as_str())?;
+    let gate = Gate::from(read_field(&mut stream).await?.as_str());
+    let encoding = read_field(&mut stream).await?;
+
+    let body = BodyStream::from_bytes_stream(stream::unfold(Some(stream), |stream| async move {
+        let mut stream = stream?;
+        match stream.read_chunk(MAX_CHUNK_SIZE, true).await {
+            Ok(Some(chunk)) => Some((Ok(chunk.bytes), Some(stream))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e.into()), None)),
+        }
+    }));
+    let body = compression::decode(body, Some(encoding.as_str()).filter(|e| !e.is_empty()))?;
+    // Streams received over QUIC are not resumed (see `StepTransport::Quic`), so
+    // acknowledgements are not needed.
+    transport.receive_stream(query_id, gate, from, 0, body)?;
+    Ok(())
+}
@@ -20,11 +20,11 @@ use tokio::task::JoinHandle;
 use crate::{
     config::{
         ClientConfig, HpkeClientConfig, HpkeServerConfig, NetworkConfig, PeerConfig, ServerConfig,
-        TlsConfig,
+        StepTransport, TlsConfig,
     },
     helpers::{HandlerBox, HelperIdentity, RequestHandler},
     hpke::{Deserializable as _, IpaPublicKey},
-    net::{ClientIdentity, HttpTransport, MpcHelperClient, MpcHelperServer},
+    net::{compression, ClientIdentity, HttpTransport, MpcHelperClient, MpcHelperServer},
     sync::Arc,
     test_fixture::metrics::MetricsHandle,
 };
@@ -69,6 +69,7 @@ fn server_config_insecure_http(port: u16, matchkey_encryption: bool) -> ServerCo
         disable_https: true,
         tls: None,
         hpke_config: get_dummy_matchkey_encryption_info(matchkey_encryption),
+        enable_quic: false,
     }
 }
 
@@ -87,6 +88,7 @@ pub fn server_config_https(
             private_key: String::from_utf8(private_key.to_owned()).unwrap(),
         }),
         hpke_config: get_dummy_matchkey_encryption_info(matchkey_encryption),
+        enable_quic: false,
     }
 }
 
@@ -95,6 +97,7 @@ pub struct TestConfigBuilder {
     ports: Option<[u16; 3]>,
     disable_https: bool,
     use_http1: bool,
+    use_quic: bool,
     disable_matchkey_encryption: bool,
 }
 
@@ -105,6 +108,7 @@ impl TestConfigBuilder {
             ports: Some(DEFAULT_TEST_PORTS),
             disable_https: true,
             use_http1: false,
+            use_quic: false,
             disable_matchkey_encryption: false,
         }
     }
@@ -115,6 +119,7 @@ impl TestConfigBuilder {
             ports: None,
             disable_https: false,
             use_http1: false,
+            use_quic: false,
             disable_matchkey_encryption: false,
         }
     }
@@ -131,6 +136,14 @@ impl TestConfigBuilder {
         self
     }
 
+    /// Send step streams over QUIC. Requires HTTPS.
+    #[cfg(feature = "quic")]
+    #[must_use]
+    pub fn with_use_quic_option(mut self, value: bool) -> Self {
+        self.use_quic = value;
+        self
+    }
+
     #[allow(dead_code)]
     #[must_use]
     // TODO(richaj) Add tests for checking the handling of this. At present the code to decrypt does not exist.
@@ -163,6 +176,7 @@ impl TestConfigBuilder {
                     .parse()
                     .unwrap(),
                 certificate: cert,
+                identity: None,
                 hpke_config: if self.disable_matchkey_encryption {
                     None
                 } else {
@@ -173,23 +187,29 @@ impl TestConfigBuilder {
                         .unwrap(),
                     ))
                 },
+                step_encodings: vec![compression::ZSTD.into()],
             })
             .collect::<Vec<_>>()
             .try_into()
             .unwrap();
-        let network = NetworkConfig {
-            peers,
-            client: self
-                .use_http1
-                .then(ClientConfig::use_http1)
-                .unwrap_or_default(),
-        };
-        let servers = if self.disable_https {
+        let mut client_config = self
+            .use_http1
+            .then(ClientConfig::use_http1)
+            .unwrap_or_default();
+        if self.use_quic {
+            client_config.step_transport = StepTransport::Quic;
+            client_config.step_stream.max_reconnects = 0;
+        }
+        let network = NetworkConfig::new(peers, client_config);
+        let mut servers = if self.disable_https {
             ports.map(|ports| server_config_insecure_http(ports, !self.disable_matchkey_encryption))
         } else {
             HelperIdentity::make_three()
                 .map(|id| server_config_https(id, ports[id], !self.disable_matchkey_encryption))
         };
+        for server in &mut servers {
+            server.enable_quic = self.use_quic;
+        }
         TestConfig {
             network,
             servers,
@@ -397,7 +417,7 @@ u9BeVKo7j/DyX4j3XcqrOYnIwOOhRANCAASEORA/IDvqRGiJpddoyocRa+9HEG2B
 
 // Yes, these strings have trailing newlines. Things that consume them
 // should strip whitespace.
-const TEST_HPKE_PUBLIC_KEY: &str = "\
+pub const TEST_HPKE_PUBLIC_KEY: &str = "\
 0ef21c2f73e6fac215ea8ec24d39d4b77836d09b1cf9aeb2257ddd181d7e663d
 ";
 
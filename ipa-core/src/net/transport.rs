@@ -6,7 +6,7 @@ use std::{
 };
 
 use async_trait::async_trait;
-use futures::{Stream, TryFutureExt};
+use futures::Stream;
 use pin_project::{pin_project, pinned_drop};
 
 use crate::{
@@ -18,23 +18,37 @@ use crate::{
         NoResourceIdentifier, NoStep, QueryIdBinding, ReceiveRecords, RequestHandler, RouteParams,
         StepBinding, StreamCollection, Transport,
     },
-    net::{client::MpcHelperClient, error::Error, MpcHelperServer},
+    net::{client::MpcHelperClient, error::Error, resume::ResumableStreams, MpcHelperServer},
     protocol::{Gate, QueryId},
     sharding::ShardIndex,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 /// HTTP transport for IPA helper service.
 /// TODO: rename to MPC
 pub struct HttpTransport {
     identity: HelperIdentity,
-    clients: [MpcHelperClient; 3],
+    clients: Mutex<Clients>,
     // TODO(615): supporting multiple queries likely require a hashmap here. It will be ok if we
     // only allow one query at a time.
     record_streams: StreamCollection<HelperIdentity, BodyStream>,
+    /// Receiving ends of the streams in `record_streams`, so that they can be resumed after
+    /// connection loss.
+    resumable_streams: ResumableStreams,
     handler: Option<HandlerRef>,
 }
 
+/// Clients used to talk to the other helpers.
+///
+/// Clients can be replaced when the network configuration changes. To avoid a query talking to
+/// different sets of peers over its lifetime, replacement clients are held back while a query is
+/// running, and take effect when it completes.
+struct Clients {
+    current: [MpcHelperClient; 3],
+    pending: Option<[MpcHelperClient; 3]>,
+    query_in_progress: bool,
+}
+
 /// A stub for HTTP transport implementation, suitable for serviing inter-shard traffic
 #[derive(Clone, Default)]
 pub struct HttpShardTransport;
@@ -80,12 +94,58 @@ impl HttpTransport {
     ) -> Arc<Self> {
         Arc::new(Self {
             identity,
-            clients,
+            clients: Mutex::new(Clients {
+                current: clients,
+                pending: None,
+                query_in_progress: false,
+            }),
             handler,
             record_streams: StreamCollection::default(),
+            resumable_streams: ResumableStreams::default(),
         })
     }
 
+    /// Replaces the clients used to talk to the other helpers, e.g. after the network configuration
+    /// has changed.
+    ///
+    /// If a query is running, the new clients take effect once it completes. Requests that are
+    /// already in flight are not affected either way.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn update_clients(&self, clients: [MpcHelperClient; 3]) {
+        let mut state = self.clients.lock().unwrap();
+        if state.query_in_progress {
+            tracing::info!("new helper clients will be used after the current query completes");
+            state.pending = Some(clients);
+        } else {
+            state.current = clients;
+        }
+    }
+
+    fn client(&self, dest: HelperIdentity) -> MpcHelperClient {
+        self.clients.lock().unwrap().current[dest].clone()
+    }
+
+    /// Marks a query as in progress, and returns whether one already was.
+    fn query_started(&self) -> bool {
+        std::mem::replace(&mut self.clients.lock().unwrap().query_in_progress, true)
+    }
+
+    /// Marks the current query as finished, and switches to the clients that were passed to
+    /// [`Self::update_clients`] while it was running, if any.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn query_finished(&self) {
+        let mut state = self.clients.lock().unwrap();
+        state.query_in_progress = false;
+        if let Some(clients) = state.pending.take() {
+            tracing::info!("switching to new helper clients");
+            state.current = clients;
+        }
+    }
+
     /// Dispatches the given request to the [`RequestHandler`] connected to this transport.
     ///
     /// ## Errors
@@ -102,7 +162,8 @@ impl HttpTransport {
         Option<QueryId>: From<Q>,
     {
         /// Cleans up the `records_stream` collection after drop to ensure this transport
-        /// can process the next query even in case of a panic.
+        /// can process the next query even in case of a panic. This also applies any client
+        /// updates that were deferred while the query was running.
         ///
         /// This implementation is a poor man's safety net and only works because we run
         /// one query at a time and don't use query identifiers.
@@ -125,6 +186,8 @@ impl HttpTransport {
         impl<F: Future> PinnedDrop for ClearOnDrop<F> {
             fn drop(self: Pin<&mut Self>) {
                 self.transport.record_streams.clear();
+                self.transport.resumable_streams.clear();
+                self.transport.query_finished();
             }
         }
 
@@ -135,29 +198,52 @@ impl HttpTransport {
             .expect("A Handler should be set by now")
             .handle(Addr::from_route(None, req), body);
 
-        if let RouteId::CompleteQuery | RouteId::KillQuery = route_id {
-            ClearOnDrop {
-                transport: Arc::clone(&self),
-                inner: r,
+        match route_id {
+            RouteId::CompleteQuery | RouteId::KillQuery => {
+                ClearOnDrop {
+                    transport: Arc::clone(&self),
+                    inner: r,
+                }
+                .await
             }
-            .await
-        } else {
-            r.await
+            RouteId::ReceiveQuery | RouteId::PrepareQuery => {
+                // Set before the handler runs, so that clients updated while the query is being
+                // set up are held back too. The query processor clears it when the query state is
+                // removed.
+                let already_running = self.query_started();
+                let resp = r.await;
+                if resp.is_err() && !already_running {
+                    self.query_finished();
+                }
+                resp
+            }
+            _ => r.await,
         }
     }
 
-    /// Connect an inbound stream of MPC record data.
+    /// Connect an inbound stream of MPC record data. `offset` is the position of the start of
+    /// `stream` within the records for the step. It is non-zero if the stream is being resumed
+    /// after connection loss.
     ///
-    /// This is called by peer helpers via the HTTP server.
+    /// This is called by peer helpers via the HTTP server. Returns the response body, which
+    /// acknowledges the records as they are consumed.
+    ///
+    /// ## Errors
+    /// If the stream is resumed at an offset that does not match what was received before.
     pub fn receive_stream(
         self: Arc<Self>,
         query_id: QueryId,
         gate: Gate,
         from: HelperIdentity,
+        offset: u64,
         stream: BodyStream,
-    ) {
-        self.record_streams
-            .add_stream((query_id, from, gate), stream);
+    ) -> Result<axum::body::Body, Error> {
+        let key = (query_id, from, gate);
+        let (acks, stream) = self.resumable_streams.attach(key.clone(), offset, stream)?;
+        if let Some(stream) = stream {
+            self.record_streams.add_stream(key, stream);
+        }
+        Ok(acks)
     }
 }
 
@@ -194,24 +280,27 @@ impl Transport for Arc<HttpTransport> {
                     .expect("query_id required when sending records");
                 let step =
                     <Option<Gate>>::from(route.gate()).expect("step required when sending records");
-                let resp_future = self.clients[dest].step(query_id, &step, data)?;
                 // we don't need to spawn a task here. Gateway's sender interface already does that
                 // so this can just poll this future.
-                resp_future
-                    .map_err(Into::into)
-                    .and_then(MpcHelperClient::resp_ok)
-                    .await?;
-                Ok(())
+                self.client(dest)
+                    .send_records(query_id, &step, data)
+                    .await
+                    .map_err(|inner| Error::StepFailed {
+                        query_id,
+                        gate: step,
+                        inner: Box::new(inner),
+                    })
             }
             RouteId::PrepareQuery => {
                 let req = serde_json::from_str(route.extra().borrow()).unwrap();
-                self.clients[dest].prepare_query(req).await
+                self.client(dest).prepare_query(req).await
             }
             evt @ (RouteId::QueryInput
             | RouteId::ReceiveQuery
             | RouteId::QueryStatus
             | RouteId::CompleteQuery
-            | RouteId::KillQuery) => {
+            | RouteId::KillQuery
+            | RouteId::HelperState) => {
                 unimplemented!(
                     "attempting to send client-specific request {evt:?} to another helper"
                 )
@@ -269,7 +358,7 @@ impl Transport for HttpShardTransport {
 
 #[cfg(all(test, web_test, descriptive_gate))]
 mod tests {
-    use std::{iter::zip, net::TcpListener, task::Poll};
+    use std::{iter::zip, net::TcpListener, task::Poll, time::Duration};
 
     use bytes::Bytes;
     use futures::stream::{poll_immediate, StreamExt};
@@ -285,6 +374,7 @@ mod tests {
         config::{NetworkConfig, ServerConfig},
         ff::{FieldType, Fp31, Serializable},
         helpers::{
+            faults::{Fault, FaultInjector, FaultRule},
             make_owned_handler,
             query::{QueryInput, QueryType::TestMultiply},
         },
@@ -292,6 +382,8 @@ mod tests {
             client::ClientIdentity,
             test::{get_test_identity, TestConfig, TestConfigBuilder, TestServer},
         },
+        query::QueryStatus,
+        report_collector::ReportCollector,
         secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
         test_fixture::Reconstruct,
         AppConfig, AppSetup, HelperApp,
@@ -325,6 +417,66 @@ mod tests {
         assert!(transport.record_streams.is_empty());
     }
 
+    #[tokio::test]
+    async fn update_clients_between_queries() {
+        let noop_handler = make_owned_handler(|_, _| async move {
+            {
+                Ok(HelperResponse::ok())
+            }
+        });
+        let TestServer { transport, .. } = TestServer::builder()
+            .with_request_handler(Arc::clone(&noop_handler))
+            .build()
+            .await;
+        let new_clients = || {
+            MpcHelperClient::from_conf(
+                &TestConfigBuilder::with_open_ports().build().network,
+                &ClientIdentity::None,
+            )
+        };
+
+        // No query is running, so the clients are replaced immediately.
+        transport.update_clients(new_clients());
+        assert!(transport.clients.lock().unwrap().pending.is_none());
+
+        Transport::clone_ref(&transport)
+            .dispatch(
+                QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
+                BodyStream::empty(),
+            )
+            .await
+            .unwrap();
+        transport.update_clients(new_clients());
+        assert!(transport.clients.lock().unwrap().pending.is_some());
+
+        Transport::clone_ref(&transport)
+            .dispatch((RouteId::CompleteQuery, QueryId), BodyStream::empty())
+            .await
+            .unwrap();
+        let clients = transport.clients.lock().unwrap();
+        assert!(clients.pending.is_none());
+        assert!(!clients.query_in_progress);
+    }
+
+    #[tokio::test]
+    async fn failed_query_does_not_hold_back_clients() {
+        let failing_handler =
+            make_owned_handler(|_, _| async move { Err(ApiError::BadRequest("rejected".into())) });
+        let TestServer { transport, .. } = TestServer::builder()
+            .with_request_handler(Arc::clone(&failing_handler))
+            .build()
+            .await;
+
+        assert!(Transport::clone_ref(&transport)
+            .dispatch(
+                QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
+                BodyStream::empty(),
+            )
+            .await
+            .is_err());
+        assert!(!transport.clients.lock().unwrap().query_in_progress);
+    }
+
     #[tokio::test]
     async fn receive_stream() {
         let (tx, rx) = channel::<Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>(1);
@@ -336,7 +488,9 @@ mod tests {
         let body = BodyStream::from_bytes_stream(ReceiverStream::new(rx));
 
         // Register the stream with the transport (normally called by step data HTTP API handler)
-        Arc::clone(&transport).receive_stream(QueryId, STEP.clone(), HelperIdentity::TWO, body);
+        Arc::clone(&transport)
+            .receive_stream(QueryId, STEP.clone(), HelperIdentity::TWO, 0, body)
+            .unwrap();
 
         // Request step data reception (normally called by protocol)
         let mut stream = Arc::clone(&transport)
@@ -373,6 +527,18 @@ mod tests {
         server_config: [ServerConfig; 3],
         network_config: &NetworkConfig,
         disable_https: bool,
+    ) -> [HelperApp; 3] {
+        make_helpers_with_faults(sockets, server_config, network_config, disable_https, None).await
+    }
+
+    /// Like [`make_helpers`], but faults from `faults`, if set, are injected into the step
+    /// streams that the helpers send to each other.
+    async fn make_helpers_with_faults(
+        sockets: [TcpListener; 3],
+        server_config: [ServerConfig; 3],
+        network_config: &NetworkConfig,
+        disable_https: bool,
+        faults: Option<&Arc<FaultInjector>>,
     ) -> [HelperApp; 3] {
         join_all(
             zip(HelperIdentity::make_three(), zip(sockets, server_config)).map(
@@ -383,7 +549,10 @@ mod tests {
                         get_test_identity(id)
                     };
                     let (setup, handler) = AppSetup::new(AppConfig::default());
-                    let clients = MpcHelperClient::from_conf(network_config, &identity);
+                    let mut clients = MpcHelperClient::from_conf(network_config, &identity);
+                    if let Some(faults) = faults {
+                        clients = clients.map(|client| client.with_fault_injector(id, faults));
+                    }
                     let (transport, server) = HttpTransport::new(
                         id,
                         server_config,
@@ -487,4 +656,237 @@ mod tests {
         let conf = TestConfigBuilder::with_open_ports().build();
         test_three_helpers(conf).await;
     }
+
+    #[cfg(feature = "quic")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn three_helpers_quic() {
+        let conf = TestConfigBuilder::with_open_ports()
+            .with_use_quic_option(true)
+            .build();
+        test_three_helpers(conf).await;
+    }
+
+    #[cfg(feature = "quic")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn three_helpers_quic_compressed() {
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_use_quic_option(true)
+            .build();
+        conf.network.client.compression.shares = crate::config::Compression::Zstd { level: 1 };
+        test_three_helpers(conf).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn report_collector() {
+        const SZ: usize = <AdditiveShare<Fp31> as Serializable>::Size::USIZE;
+
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_disable_https_option(true)
+            .build();
+        let collector = ReportCollector::from_network(&conf.network);
+        let _helpers = make_helpers(
+            conf.sockets.take().unwrap(),
+            conf.servers,
+            &conf.network,
+            conf.disable_https,
+        )
+        .await;
+
+        let inputs = (
+            Fp31::try_from(4u128).unwrap(),
+            Fp31::try_from(5u128).unwrap(),
+        )
+            .share()
+            .map(|(a, b)| {
+                let mut vec = vec![0u8; 2 * SZ];
+                a.serialize(GenericArray::from_mut_slice(&mut vec[..SZ]));
+                b.serialize(GenericArray::from_mut_slice(&mut vec[SZ..]));
+                BodyStream::from(vec)
+            });
+        let query = collector
+            .submit(
+                QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
+                inputs,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![Fp31::try_from(20u128).unwrap()],
+            query.results::<Fp31>().await.unwrap()
+        );
+        // Helpers forget the query once they returned its results.
+        assert!(query.status().await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn report_collector_cancel() {
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_disable_https_option(true)
+            .build();
+        let collector = ReportCollector::from_network(&conf.network);
+        let _helpers = make_helpers(
+            conf.sockets.take().unwrap(),
+            conf.servers,
+            &conf.network,
+            conf.disable_https,
+        )
+        .await;
+
+        let query_id = collector.clients()[0]
+            .create_query(QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap())
+            .await
+            .unwrap();
+        let query = collector.query(query_id);
+        assert_eq!(
+            [QueryStatus::AwaitingInputs; 3],
+            query.status().await.unwrap()
+        );
+
+        query.cancel().await.unwrap();
+        assert!(query.status().await.is_err());
+        // Cancelling a query that no longer exists is not an error.
+        query.cancel().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn report_collector_kills_query_on_rejected_input() {
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_disable_https_option(true)
+            .build();
+        let clients = MpcHelperClient::from_conf(&conf.network, &ClientIdentity::None);
+        let _helpers = make_helpers(
+            conf.sockets.take().unwrap(),
+            conf.servers,
+            &conf.network,
+            conf.disable_https,
+        )
+        .await;
+
+        // The report collector cannot reach the third helper, so the upload of its input fails.
+        let mut network = conf.network.clone();
+        let closed_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        network.peers[2].url = format!("http://localhost:{closed_port}").parse().unwrap();
+        let collector = ReportCollector::from_network(&network);
+
+        let Err(e) = collector
+            .submit(
+                QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
+                [
+                    BodyStream::empty(),
+                    BodyStream::empty(),
+                    BodyStream::empty(),
+                ],
+            )
+            .await
+        else {
+            panic!("query input was accepted by an unreachable helper");
+        };
+        assert_eq!(e.helper(), Some(HelperIdentity::THREE));
+
+        // The query was killed on the helpers that could be reached.
+        for client in &clients[..2] {
+            assert!(client.query_status(QueryId).await.is_err());
+        }
+        assert_eq!(
+            clients[2].query_status(QueryId).await.unwrap(),
+            QueryStatus::AwaitingInputs
+        );
+    }
+
+    /// Step streams that lose their connection are resumed, so the query succeeds.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn resumes_after_injected_disconnects() {
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_disable_https_option(true)
+            .build();
+        let faults = FaultInjector::new(1, vec![FaultRule::new(Fault::Disconnect, 1.0).at_most(2)]);
+        let clients = MpcHelperClient::from_conf(&conf.network, &ClientIdentity::None);
+        let _helpers = make_helpers_with_faults(
+            conf.sockets.take().unwrap(),
+            conf.servers,
+            &conf.network,
+            conf.disable_https,
+            Some(&faults),
+        )
+        .await;
+
+        test_multiply(&clients).await;
+        assert_eq!(faults.injected().len(), 2);
+    }
+
+    /// A step stream that ends early fails the query on the helpers that receive it. The helper
+    /// that sent it keeps waiting for records from the helpers that failed, which never open
+    /// their streams, so the report collector has to kill the query there.
+    ///
+    /// This runs on a single thread: on a multi-threaded runtime, the query runs inside
+    /// `block_in_place` and killing it cannot interrupt it.
+    #[tokio::test]
+    async fn fails_cleanly_on_injected_close() {
+        const SZ: usize = <AdditiveShare<Fp31> as Serializable>::Size::USIZE;
+        const TIMEOUT: Duration = Duration::from_secs(30);
+
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_disable_https_option(true)
+            .build();
+        let faults = FaultInjector::new(
+            1,
+            vec![FaultRule::new(Fault::CloseEarly, 1.0).from_helper(HelperIdentity::ONE)],
+        );
+        let clients = MpcHelperClient::from_conf(&conf.network, &ClientIdentity::None);
+        let _helpers = make_helpers_with_faults(
+            conf.sockets.take().unwrap(),
+            conf.servers,
+            &conf.network,
+            conf.disable_https,
+            Some(&faults),
+        )
+        .await;
+
+        let query_id = clients[0]
+            .create_query(QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap())
+            .await
+            .unwrap();
+        let inputs = (
+            Fp31::try_from(4u128).unwrap(),
+            Fp31::try_from(5u128).unwrap(),
+        )
+            .share()
+            .map(|(a, b)| {
+                let mut vec = vec![0u8; 2 * SZ];
+                a.serialize(GenericArray::from_mut_slice(&mut vec[..SZ]));
+                b.serialize(GenericArray::from_mut_slice(&mut vec[SZ..]));
+                BodyStream::from(vec)
+            });
+        try_join_all(zip(&clients, inputs).map(|(client, input_stream)| {
+            client.query_input(QueryInput {
+                query_id,
+                input_stream,
+            })
+        }))
+        .await
+        .unwrap();
+
+        let results = tokio::time::timeout(
+            TIMEOUT,
+            join_all(
+                clients[1..]
+                    .iter()
+                    .map(|client| client.query_results(query_id)),
+            ),
+        )
+        .await
+        .expect("query hung after a step stream was closed early");
+        assert!(!faults.injected().is_empty());
+        assert!(results.iter().all(Result::is_err), "{results:?}");
+        assert_eq!(
+            clients[0].query_status(query_id).await.unwrap(),
+            QueryStatus::Running
+        );
+        clients[0].kill_query(query_id).await.unwrap();
+    }
 }
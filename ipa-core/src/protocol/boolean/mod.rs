@@ -1,7 +1,7 @@
 use ipa_step::Step;
 
 use crate::protocol::boolean::step::{
-    EightBitStep, SixteenBitStep, ThirtyTwoBitStep, TwoHundredFiftySixBitOpStep,
+    EightBitStep, SixteenBitStep, SixtyFourBitStep, ThirtyTwoBitStep, TwoHundredFiftySixBitOpStep,
 };
 
 pub mod and;
@@ -33,6 +33,10 @@ impl NBitStep for ThirtyTwoBitStep {
     const BITS: u32 = 32;
 }
 
+impl NBitStep for SixtyFourBitStep {
+    const BITS: u32 = 64;
+}
+
 impl NBitStep for TwoHundredFiftySixBitOpStep {
     const BITS: u32 = 256;
 }
@@ -220,7 +220,7 @@ where
 // dp_for_aggregation is currently where the DP parameters epsilon, delta
 // are introduced and then from those the parameters of the noise distribution to generate are
 // calculated for use in aggregating histograms.  The DP parameters query_epsilon and
-// per_user_credit_cap come as inputs to the query with per_user_sensitivity_cap = 2^{SS_BITS}
+// per_user_credit_cap come as inputs to the query; the per-user sensitivity is the credit cap
 /// # Errors
 /// will propogate errors from `apply_dp_noise`
 /// Will return an error epsilon is not in the range (0,`MAX_EPSILON`); we allow very large
@@ -229,10 +229,11 @@ where
 /// may panic from asserts down in  `gen_binomial_noise`
 ///
 #[allow(clippy::too_many_lines)]
-pub async fn dp_for_histogram<C, const B: usize, OV, const SS_BITS: usize>(
+pub async fn dp_for_histogram<C, const B: usize, OV>(
     ctx: C,
     histogram_bin_values: BitDecomposed<Replicated<Boolean, B>>,
     dp_params: DpMechanism,
+    per_user_credit_cap: u32,
 ) -> Result<Vec<Replicated<OV>>, Error>
 where
     C: UpgradableContext,
@@ -256,8 +257,6 @@ where
                 return Err(EpsilonOutOfBounds);
             }
 
-            let per_user_credit_cap = 2_u32.pow(u32::try_from(SS_BITS).unwrap());
-
             let dimensions = f64::from(u32::try_from(B).unwrap());
 
             let noise_params = NoiseParams {
@@ -313,7 +312,7 @@ where
         DpMechanism::DiscreteLaplace { epsilon } => {
             let noise_params = NoiseParams {
                 epsilon,
-                per_user_credit_cap: 2_u32.pow(u32::try_from(SS_BITS).unwrap()),
+                per_user_credit_cap,
                 ..Default::default()
             };
 
@@ -407,6 +406,11 @@ impl ShiftedTruncatedDiscreteLaplace {
         self.truncated_discrete_laplace.sample(rng)
     }
 
+    /// A sample shifted to be centered at zero, with negative values wrapped around the modulus.
+    fn sample_symmetric<R: RngCore + CryptoRng>(&self, rng: &mut R) -> u32 {
+        self.sample(rng).wrapping_sub(self.shift) % self.modulus
+    }
+
     pub fn sample_shares<R, OV>(
         &self,
         rng: &mut R,
@@ -416,8 +420,7 @@ impl ShiftedTruncatedDiscreteLaplace {
         R: RngCore + CryptoRng,
         OV: BooleanArray + U128Conversions,
     {
-        let sample = self.sample(rng);
-        let symmetric_sample = sample.wrapping_sub(self.shift) % self.modulus;
+        let symmetric_sample = self.sample_symmetric(rng);
         match direction_to_excluded_helper {
             Direction::Left => {
                 AdditiveShare::new(OV::ZERO, OV::truncate_from(u128::from(symmetric_sample)))
@@ -429,6 +432,70 @@ impl ShiftedTruncatedDiscreteLaplace {
     }
 }
 
+/// Adds the noise that [`dp_for_histogram`] adds to a histogram with `B` bins of `OV` values, to
+/// the plaintext values in `histogram`. This is for reference implementations that compute query
+/// results without the helpers, so that their results are distributed like those of MPC,
+/// including noise that wraps around zero.
+///
+/// # Errors
+/// If the DP parameters are not valid, like [`dp_for_histogram`].
+/// # Panics
+/// if `OV::BITS > 32`
+pub fn dp_for_histogram_in_the_clear<const B: usize, OV, R>(
+    histogram: &mut [u32],
+    dp_params: DpMechanism,
+    per_user_credit_cap: u32,
+    rng: &mut R,
+) -> Result<(), Error>
+where
+    OV: BooleanArray,
+    R: RngCore + CryptoRng,
+{
+    assert!(OV::BITS <= 32);
+    let mask = u32::MAX >> (32 - OV::BITS);
+    let add = |value: u32, noise: u32| value.wrapping_add(noise) & mask;
+
+    match dp_params {
+        DpMechanism::NoDp => {}
+        DpMechanism::Binomial { epsilon } => {
+            if epsilon <= 0.0 || epsilon > MAX_EPSILON {
+                return Err(EpsilonOutOfBounds);
+            }
+            let noise_params = NoiseParams {
+                epsilon,
+                per_user_credit_cap,
+                ell_1_sensitivity: f64::from(per_user_credit_cap),
+                ell_2_sensitivity: f64::from(per_user_credit_cap),
+                ell_infty_sensitivity: f64::from(per_user_credit_cap),
+                dimensions: f64::from(u32::try_from(B).unwrap()),
+                ..Default::default()
+            };
+            let num_bernoulli = find_smallest_num_bernoulli(&noise_params);
+            for value in histogram {
+                // `gen_binomial_noise` sums `num_bernoulli` random bits
+                let noise = (0..num_bernoulli).map(|_| rng.next_u32() & 1).sum::<u32>();
+                *value = add(*value, noise);
+            }
+        }
+        DpMechanism::DiscreteLaplace { epsilon } => {
+            let noise_params = NoiseParams {
+                epsilon,
+                per_user_credit_cap,
+                ..Default::default()
+            };
+            let laplace = ShiftedTruncatedDiscreteLaplace::new(&noise_params, OV::BITS)?;
+            // one pass for each pair of helpers, see `apply_laplace_noise_pass`
+            for _ in 0..3 {
+                for value in histogram.iter_mut() {
+                    *value = add(*value, laplace.sample_symmetric(rng));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// # Errors
 /// will propagate errors from constructing a `truncated_discrete_laplace` distribution.
 /// # Panics
@@ -611,9 +678,9 @@ mod test {
         helpers::{query::DpMechanism, Direction},
         protocol::{
             dp::{
-                apply_dp_noise, delta_constraint, dp_for_histogram, epsilon_constraint, error,
-                find_smallest_num_bernoulli, gen_binomial_noise, NoiseParams,
-                ShiftedTruncatedDiscreteLaplace,
+                apply_dp_noise, delta_constraint, dp_for_histogram, dp_for_histogram_in_the_clear,
+                epsilon_constraint, error, find_smallest_num_bernoulli, gen_binomial_noise,
+                NoiseParams, ShiftedTruncatedDiscreteLaplace,
             },
             ipa_prf::oprf_padding::insecure::OPRFPaddingDp,
         },
@@ -712,16 +779,17 @@ mod test {
     }
 
     /// Test for discrete truncated laplace
-    // pub async fn dp_for_histogram<C, const B: usize, OV, const SS_BITS: usize>(
+    // pub async fn dp_for_histogram<C, const B: usize, OV>(
     //     ctx: C,
     //     histogram_bin_values: BitDecomposed<Replicated<Boolean, B>>,
     //     dp_params: DpMechanism,
+    //     per_user_credit_cap: u32,
     // ) -> Result<Vec<Replicated<OV>>, Error>
     #[tokio::test]
     pub async fn test_laplace_noise() {
         type OV = BA8;
         const NUM_BREAKDOWNS: u32 = 16;
-        const SS_BITS: usize = 3;
+        const PER_USER_CREDIT_CAP: u32 = 8;
         let epsilon = 2.0;
         let dp_params = DpMechanism::DiscreteLaplace { epsilon };
         let world = TestWorld::default();
@@ -731,8 +799,11 @@ mod test {
             vectorize_input(OV::BITS as usize, &input_values); // bit_width passed here needs to match OV::BITS
         let result = world
             .semi_honest(input, |ctx, input| async move {
-                dp_for_histogram::<_, { NUM_BREAKDOWNS as usize }, OV, SS_BITS>(
-                    ctx, input, dp_params,
+                dp_for_histogram::<_, { NUM_BREAKDOWNS as usize }, OV>(
+                    ctx,
+                    input,
+                    dp_params,
+                    PER_USER_CREDIT_CAP,
                 )
                 .await
                 .unwrap()
@@ -743,8 +814,7 @@ mod test {
             .iter()
             .map(|&v| u32::try_from(v.as_u128()).unwrap())
             .collect::<Vec<_>>();
-        let per_user_credit_cap = 2_u32.pow(u32::try_from(SS_BITS).unwrap());
-        let truncated_discrete_laplace = OPRFPaddingDp::new(epsilon, 1e-6, per_user_credit_cap);
+        let truncated_discrete_laplace = OPRFPaddingDp::new(epsilon, 1e-6, PER_USER_CREDIT_CAP);
         let (_, std) = truncated_discrete_laplace.unwrap().mean_and_std();
         let three_std = 3.0 * std;
         assert_eq!(NUM_BREAKDOWNS as usize, result_u32.len());
@@ -1018,4 +1088,67 @@ mod test {
         // println!("num_bernoulli {num_bernoulli}");
         println!("bytes_sent {bytes_sent}");
     }
+
+    #[test]
+    #[allow(clippy::cast_possible_wrap)]
+    fn dp_in_the_clear() {
+        const PER_USER_CREDIT_CAP: u32 = 8;
+        let mut rng = thread_rng();
+        let input = vec![0, 1, 100, 40];
+
+        let mut result = input.clone();
+        dp_for_histogram_in_the_clear::<256, BA32, _>(
+            &mut result,
+            DpMechanism::NoDp,
+            PER_USER_CREDIT_CAP,
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(result, input);
+
+        let epsilon = 2.0;
+        let mut result = input.clone();
+        dp_for_histogram_in_the_clear::<256, BA32, _>(
+            &mut result,
+            DpMechanism::DiscreteLaplace { epsilon },
+            PER_USER_CREDIT_CAP,
+            &mut rng,
+        )
+        .unwrap();
+        let (_, std) = OPRFPaddingDp::new(epsilon, 1e-6, PER_USER_CREDIT_CAP)
+            .unwrap()
+            .mean_and_std();
+        for (&noised, &value) in result.iter().zip(&input) {
+            // noise can be negative, and wraps around 2^32 like in MPC
+            let noise = f64::from(noised.wrapping_sub(value) as i32);
+            assert!(noise.abs() < 5.0 * 3.0 * std, "noise {noise} is too large");
+        }
+
+        let noise_params = NoiseParams {
+            epsilon,
+            per_user_credit_cap: PER_USER_CREDIT_CAP,
+            ell_1_sensitivity: f64::from(PER_USER_CREDIT_CAP),
+            ell_2_sensitivity: f64::from(PER_USER_CREDIT_CAP),
+            ell_infty_sensitivity: f64::from(PER_USER_CREDIT_CAP),
+            dimensions: 256.0,
+            ..Default::default()
+        };
+        let num_bernoulli = f64::from(find_smallest_num_bernoulli(&noise_params));
+        let mut result = input.clone();
+        dp_for_histogram_in_the_clear::<256, BA32, _>(
+            &mut result,
+            DpMechanism::Binomial { epsilon },
+            PER_USER_CREDIT_CAP,
+            &mut rng,
+        )
+        .unwrap();
+        let (mean, std) = (num_bernoulli * 0.5, (num_bernoulli * 0.25).sqrt());
+        for (&noised, &value) in result.iter().zip(&input) {
+            let noise = f64::from(noised - value);
+            assert!(
+                (noise - mean).abs() < 5.0 * std,
+                "noise {noise} is too far from {mean}"
+            );
+        }
+    }
 }
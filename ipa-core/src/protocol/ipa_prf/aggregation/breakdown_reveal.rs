@@ -6,20 +6,22 @@ use std::{
 use futures::{stream, Stream};
 use futures_util::{StreamExt, TryStreamExt};
 
-use super::{aggregate_values, AggResult};
+use super::{aggregate_values, AggResult, AggregationOutputMode, ValueBuckets};
 use crate::{
     error::{Error, UnwrapInfallible},
     ff::{
         boolean::Boolean,
         boolean_array::{BooleanArray, BA64},
-        U128Conversions,
+        ArrayAccess, U128Conversions,
     },
     helpers::TotalRecords,
     protocol::{
-        basics::semi_honest_reveal,
+        basics::{semi_honest_reveal, ShareKnownValue},
+        boolean::{step::ThirtyTwoBitStep, NBitStep},
         context::Context,
         ipa_prf::{
-            aggregation::step::AggregationStep,
+            aggregation::step::{AggregationStep, ValueBucketStep},
+            boolean_ops::comparison_and_subtraction_sequential::compare_gt,
             oprf_padding::{apply_dp_padding, PaddingParameters},
             prf_sharding::{AttributionOutputs, SecretSharedAttributionOutputs},
             shuffle::shuffle_attribution_outputs,
@@ -48,15 +50,21 @@ use crate::{
 /// 2. Reveal breakdown keys. This is the key difference to the previous
 ///    aggregation (see [`reveal_breakdowns`]).
 /// 3. Add all values for each breakdown.
+///
+/// With [`AggregationOutputMode::ValueBuckets`], step 3 instead counts, for each breakdown, the
+/// contributions whose value falls in each bucket. Bucket membership is computed with secure
+/// comparisons and is never revealed.
 #[tracing::instrument(name = "breakdown_reveal_aggregation", skip_all, fields(total = attributed_values.len()))]
 pub async fn breakdown_reveal_aggregation<C, BK, TV, HV, const B: usize>(
     ctx: C,
     attributed_values: Vec<SecretSharedAttributionOutputs<BK, TV>>,
     padding_params: &PaddingParameters,
+    output_mode: &AggregationOutputMode,
 ) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
 where
     C: Context,
     Boolean: FieldSimd<B>,
+    Replicated<Boolean>: BooleanProtocols<C>,
     Replicated<Boolean, B>: BooleanProtocols<C, B>,
     BK: BreakdownKey<B>,
     TV: BooleanArray + U128Conversions,
@@ -74,7 +82,12 @@ where
         .await?;
 
     let attributions = shuffle_attributions(&ctx, attributed_values_padded).await?;
-    let grouped_tvs = reveal_breakdowns(&ctx, attributions).await?;
+    let grouped_tvs = match output_mode {
+        AggregationOutputMode::Sum => reveal_breakdowns(&ctx, attributions).await?,
+        AggregationOutputMode::ValueBuckets(buckets) => {
+            reveal_breakdowns_into_value_buckets(&ctx, attributions, buckets).await?
+        }
+    };
     let num_rows = grouped_tvs.max_len;
     let ctx = ctx.narrow(&AggregationStep::SumContributions);
     aggregate_values::<_, HV, B>(ctx, grouped_tvs.into_stream(), num_rows).await
@@ -151,6 +164,148 @@ where
     Ok(grouped_tvs)
 }
 
+/// Like [`reveal_breakdowns`], but instead of grouping each trigger value under its breakdown, it
+/// groups one indicator per value bucket under the histogram bin of that (breakdown, bucket)
+/// pair. Each indicator is a secret-shared 0 or 1 in the least significant bit of a `TV`.
+#[tracing::instrument(name = "reveal_breakdowns_into_value_buckets", skip_all, fields(
+    total = attributions.len(),
+))]
+async fn reveal_breakdowns_into_value_buckets<C, BK, TV, const B: usize>(
+    parent_ctx: &C,
+    attributions: Vec<SecretSharedAttributionOutputs<BK, TV>>,
+    buckets: &ValueBuckets,
+) -> Result<GroupedTriggerValues<TV, B>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    Replicated<Boolean, B>: BooleanProtocols<C, B>,
+    Boolean: FieldSimd<B>,
+    BK: BreakdownKey<B>,
+    TV: BooleanArray + U128Conversions,
+{
+    let total_records = TotalRecords::specified(attributions.len())?;
+    let reveal_ctx = parent_ctx
+        .narrow(&AggregationStep::RevealStep)
+        .set_total_records(total_records);
+    let bucket_ctx = parent_ctx
+        .narrow(&AggregationStep::BucketValues)
+        .set_total_records(total_records);
+
+    let reveal_work = stream::iter(attributions).enumerate().map(|(i, ao)| {
+        let record_id = RecordId::from(i);
+        let reveal_ctx = reveal_ctx.clone();
+        let bucket_ctx = bucket_ctx.clone();
+        async move {
+            let (revealed_bk, indicators) = futures::future::try_join(
+                semi_honest_reveal(
+                    reveal_ctx,
+                    record_id,
+                    None,
+                    &ao.attributed_breakdown_key_bits,
+                ),
+                value_bucket_indicators(
+                    bucket_ctx,
+                    record_id,
+                    &ao.capped_attributed_trigger_value,
+                    buckets,
+                ),
+            )
+            .await?;
+            // Full reveal is used, meaning it is not possible to return None here
+            let revealed_bk = BK::from_array(&revealed_bk.unwrap());
+            let Ok(bk) = usize::try_from(revealed_bk.as_u128()) else {
+                return Err(Error::Internal);
+            };
+            Ok::<_, Error>((bk, indicators))
+        }
+    });
+    let mut grouped_tvs = GroupedTriggerValues::<TV, B>::new();
+    let mut stream = pin!(seq_join(reveal_ctx.active_work(), reveal_work));
+    while let Some((bk, indicators)) = stream.try_next().await? {
+        // The breakdown key is public at this point. The query config is checked to have a
+        // histogram bin for every breakdown up to `max_breakdown_key`, so only contributions to
+        // breakdowns outside that range, such as padding, do not fit. Like any breakdown above
+        // `max_breakdown_key`, they are not part of the output.
+        if bk >= buckets.breakdowns(B) {
+            continue;
+        }
+        for (bucket, indicator) in indicators.into_iter().enumerate() {
+            grouped_tvs.push(buckets.histogram_index(bk, bucket), indicator);
+        }
+    }
+
+    Ok(grouped_tvs)
+}
+
+/// Computes, for each value bucket, a secret-shared bit indicating whether `value` falls in that
+/// bucket. Each bit is returned in the least significant position of a `TV`.
+///
+/// `value >= lower_bound` is monotone in the lower bound, so the indicator for a bucket is the XOR
+/// of the comparison with its own lower bound and the comparison with the next bucket's lower
+/// bound. Only the comparisons need multiplications.
+async fn value_bucket_indicators<C, TV>(
+    ctx: C,
+    record_id: RecordId,
+    value: &Replicated<TV>,
+    buckets: &ValueBuckets,
+) -> Result<Vec<Replicated<TV>>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    TV: BooleanArray + U128Conversions,
+{
+    assert!(
+        TV::BITS <= ThirtyTwoBitStep::BITS,
+        "ThirtyTwoBitStep is not large enough to accomodate this comparison"
+    );
+    let value_bits = value.to_bits();
+    let at_least_lower_bound = ctx
+        .parallel_join(
+            buckets
+                .lower_bounds()
+                .iter()
+                .enumerate()
+                .map(|(i, &lower_bound)| {
+                    let ctx = ctx.narrow(&ValueBucketStep::from(i));
+                    let value_bits = &value_bits;
+                    async move {
+                        // `value >= lower_bound` is `value > lower_bound - 1`. A lower bound
+                        // that does not fit in `TV` is never reached.
+                        if u64::from(lower_bound) > 1_u64 << TV::BITS {
+                            return Ok(Replicated::ZERO);
+                        }
+                        let threshold = u128::from(lower_bound - 1);
+                        let threshold_bits = BitDecomposed::decompose(TV::BITS, |j| {
+                            Replicated::share_known_value(
+                                &ctx,
+                                Boolean::truncate_from((threshold >> j) & 0x1),
+                            )
+                        });
+                        compare_gt::<_, ThirtyTwoBitStep, 1>(
+                            ctx,
+                            record_id,
+                            value_bits,
+                            &threshold_bits,
+                        )
+                        .await
+                    }
+                }),
+        )
+        .await?;
+
+    Ok((0..at_least_lower_bound.len())
+        .map(|i| {
+            let mut indicator = at_least_lower_bound[i].clone();
+            if let Some(next) = at_least_lower_bound.get(i + 1) {
+                indicator += next;
+            }
+            let mut value = Replicated::<TV>::ZERO;
+            value.set(0, indicator);
+            value
+        })
+        .collect())
+}
+
 /// Helper type that hold all the Trigger Values, grouped by their Breakdown
 /// Key. The main functionality is to turn into a stream that can be given to
 /// [`aggregate_values`].
@@ -204,14 +359,16 @@ pub mod tests {
             U128Conversions,
         },
         protocol::ipa_prf::{
-            aggregation::breakdown_reveal::breakdown_reveal_aggregation,
+            aggregation::{
+                breakdown_reveal::breakdown_reveal_aggregation, AggregationOutputMode, ValueBuckets,
+            },
             oprf_padding::PaddingParameters,
             prf_sharding::{AttributionOutputsTestInput, SecretSharedAttributionOutputs},
         },
         secret_sharing::{
             replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, TransposeFrom,
         },
-        test_executor::run_with,
+        test_executor::{run, run_with},
         test_fixture::{Reconstruct, Runner, TestWorld},
     };
 
@@ -262,6 +419,7 @@ pub mod tests {
                             ctx,
                             aos,
                             &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::Sum,
                         )
                         .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
                             Vec::transposed_from(&d).unwrap()
@@ -277,4 +435,105 @@ pub mod tests {
             assert_eq!(result, expectation);
         });
     }
+
+    #[test]
+    fn semi_honest_value_buckets() {
+        run_with::<_, _, 3>(|| async {
+            let world = TestWorld::default();
+            let mut rng = rand::thread_rng();
+            // Buckets [1, 3), [3, 6), [6, 8) for 32 / 3 = 10 breakdowns.
+            let buckets = ValueBuckets::new(&[3, 6]).unwrap();
+            let mut expectation = vec![0_u128; 32];
+            let mut inputs = Vec::new();
+            for bk in 0..10 {
+                for _ in 0..rng.gen_range(0..20) {
+                    let tv = rng.gen_range(0u128..8);
+                    let bucket = match tv {
+                        0 => None,
+                        1..=2 => Some(0),
+                        3..=5 => Some(1),
+                        _ => Some(2),
+                    };
+                    if let Some(bucket) = bucket {
+                        expectation[buckets.histogram_index(bk, bucket)] += 1;
+                    }
+                    inputs.push(input_row(bk, tv));
+                }
+            }
+            // Contributions to breakdowns that do not fit in the histogram are dropped.
+            inputs.push(input_row(31, 0));
+            inputs.shuffle(&mut rng);
+
+            let result: Vec<_> = world
+                .upgraded_semi_honest(inputs.into_iter(), |ctx, input_rows| {
+                    let output_mode = AggregationOutputMode::ValueBuckets(buckets);
+                    async move {
+                        let aos = input_rows
+                            .into_iter()
+                            .map(|ti| SecretSharedAttributionOutputs {
+                                attributed_breakdown_key_bits: ti.0,
+                                capped_attributed_trigger_value: ti.1,
+                            })
+                            .collect();
+                        let r: Vec<Replicated<BA8>> =
+                            breakdown_reveal_aggregation::<_, BA5, BA3, BA8, 32>(
+                                ctx,
+                                aos,
+                                &PaddingParameters::relaxed(),
+                                &output_mode,
+                            )
+                            .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
+                                Vec::transposed_from(&d).unwrap()
+                            })
+                            .await
+                            .unwrap();
+                        r
+                    }
+                })
+                .await
+                .reconstruct();
+            let result = result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>();
+            assert_eq!(result, expectation);
+        });
+    }
+
+    #[test]
+    fn value_buckets_drop_out_of_range() {
+        run(|| async {
+            let world = TestWorld::default();
+            // Two buckets for 32 / 2 = 16 breakdowns.
+            let buckets = ValueBuckets::new(&[4]).unwrap();
+            let inputs = vec![input_row(3, 5), input_row(16, 1), input_row(31, 7)];
+            let mut expectation = vec![0_u128; 32];
+            expectation[buckets.histogram_index(3, 1)] = 1;
+
+            let result: Vec<_> = world
+                .upgraded_semi_honest(inputs.into_iter(), |ctx, input_rows| async move {
+                    let aos = input_rows
+                        .into_iter()
+                        .map(|ti| SecretSharedAttributionOutputs {
+                            attributed_breakdown_key_bits: ti.0,
+                            capped_attributed_trigger_value: ti.1,
+                        })
+                        .collect();
+                    let r: Vec<Replicated<BA8>> =
+                        breakdown_reveal_aggregation::<_, BA5, BA3, BA8, 32>(
+                            ctx,
+                            aos,
+                            &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::ValueBuckets(buckets),
+                        )
+                        .map_ok(|d: BitDecomposed<Replicated<Boolean, 32>>| {
+                            Vec::transposed_from(&d).unwrap()
+                        })
+                        .await
+                        .unwrap();
+                    r
+                })
+                .await
+                .reconstruct();
+            let result = result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>();
+            assert_eq!(result, expectation);
+        });
+    }
 }
@@ -1,6 +1,13 @@
-use std::{any::type_name, iter, pin::Pin};
+use std::{
+    any::type_name,
+    fmt::{self, Display, Formatter},
+    iter,
+    pin::Pin,
+    str::FromStr,
+};
 
 use futures::{Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
 use tracing::Instrument;
 
 use crate::{
@@ -83,6 +90,185 @@ where
     }
 }
 
+/// What the aggregation stage outputs for each breakdown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AggregationOutputMode {
+    /// The sum of attributed trigger values for each breakdown.
+    #[default]
+    Sum,
+    /// The number of attributed conversions falling into each value bucket, for each breakdown.
+    ///
+    /// The histogram has one bin per (breakdown, value bucket) pair, at index
+    /// [`ValueBuckets::histogram_index`]. With `K` buckets and a histogram of size `B`, only
+    /// breakdowns below `B / K` can be reported. Contributions to higher breakdowns, such as
+    /// those of padding records, are dropped. Queries are checked when they are created to have
+    /// room for every breakdown up to `max_breakdown_key`.
+    ValueBuckets(ValueBuckets),
+}
+
+impl AggregationOutputMode {
+    /// The most any single user can add to the output histogram, for calibrating DP noise.
+    ///
+    /// For value buckets, this is the same as for sums. A user's capped contributions add up to at
+    /// most `per_user_credit_cap`, and only contributions of at least 1 are counted, so a user
+    /// increments the histogram at most `per_user_credit_cap` times.
+    #[must_use]
+    pub fn sensitivity(&self, per_user_credit_cap: u32) -> u32 {
+        match self {
+            Self::Sum | Self::ValueBuckets(_) => per_user_credit_cap,
+        }
+    }
+
+    /// Returns a suitable proof chunk size (in records) for use with
+    /// [`breakdown_reveal::breakdown_reveal_aggregation`].
+    ///
+    /// For sums, this is [`aggregate_values_proof_chunk`]. For value buckets, each record needs one
+    /// `input_item_bits`-bit comparison per bucket, and the resulting indicators are aggregated
+    /// as 1-bit values.
+    #[must_use]
+    pub fn proof_chunk(&self, input_width: usize, input_item_bits: usize) -> usize {
+        match self {
+            Self::Sum => aggregate_values_proof_chunk(input_width, input_item_bits),
+            Self::ValueBuckets(buckets) => {
+                TARGET_PROOF_SIZE / (input_width * 2 + buckets.len() * input_item_bits)
+            }
+        }
+    }
+}
+
+/// Value buckets for counting attributed conversions.
+///
+/// Buckets are given by their boundaries. Boundaries `[10, 50]` define three buckets:
+/// `[1, 10)`, `[10, 50)` and `[50, ∞)`. Zero-valued contributions (trigger events that were not
+/// attributed, or that were capped to zero) are not counted in any bucket.
+///
+/// In a query config, buckets are written as a comma-separated list of boundaries, e.g.
+/// `10,50`. An empty list defines a single bucket that counts all non-zero contributions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ValueBuckets {
+    /// The smallest value in each bucket. The first bucket always starts at 1. Only the first
+    /// `len` entries are used.
+    lower_bounds: [u32; Self::MAX_BUCKETS],
+    len: usize,
+}
+
+impl ValueBuckets {
+    /// Maximum number of buckets, including the first one.
+    pub const MAX_BUCKETS: usize = 8;
+
+    /// ## Errors
+    /// If the boundaries are not strictly increasing, if any boundary is less than 2, or if there
+    /// would be more than [`Self::MAX_BUCKETS`] buckets.
+    pub fn new(boundaries: &[u32]) -> Result<Self, Error> {
+        if boundaries.len() >= Self::MAX_BUCKETS {
+            return Err(Error::InvalidQueryParameter(
+                format!(
+                    "{} value bucket boundaries define more than {} buckets",
+                    boundaries.len(),
+                    Self::MAX_BUCKETS
+                )
+                .into(),
+            ));
+        }
+        let mut lower_bounds = [0; Self::MAX_BUCKETS];
+        for (slot, bound) in lower_bounds
+            .iter_mut()
+            .zip(iter::once(1).chain(boundaries.iter().copied()))
+        {
+            *slot = bound;
+        }
+        let len = boundaries.len() + 1;
+        if lower_bounds[..len].windows(2).any(|w| w[0] >= w[1]) {
+            return Err(Error::InvalidQueryParameter(
+                format!(
+                    "value bucket boundaries {boundaries:?} must be strictly increasing and \
+                    greater than 1"
+                )
+                .into(),
+            ));
+        }
+
+        Ok(Self { lower_bounds, len })
+    }
+
+    /// The number of buckets.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Always false; there is at least one bucket.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// The smallest value in each bucket.
+    #[must_use]
+    pub fn lower_bounds(&self) -> &[u32] {
+        &self.lower_bounds[..self.len]
+    }
+
+    /// The number of breakdowns that fit in a histogram of size `histogram_size`.
+    #[must_use]
+    pub fn breakdowns(&self, histogram_size: usize) -> usize {
+        histogram_size / self.len()
+    }
+
+    /// Position of the count for `bucket` of `breakdown` in the output histogram.
+    #[must_use]
+    pub fn histogram_index(&self, breakdown: usize, bucket: usize) -> usize {
+        breakdown * self.len() + bucket
+    }
+}
+
+impl Display for ValueBuckets {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, boundary) in self.lower_bounds()[1..].iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{boundary}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ValueBuckets {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let boundaries = s
+            .split(',')
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+            .map(|b| {
+                b.parse::<u32>().map_err(|e| {
+                    Error::InvalidQueryParameter(
+                        format!("invalid value bucket boundary {b:?}: {e}").into(),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(&boundaries)
+    }
+}
+
+impl TryFrom<String> for ValueBuckets {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ValueBuckets> for String {
+    fn from(value: ValueBuckets) -> Self {
+        value.to_string()
+    }
+}
+
 /// A vector of histogram contributions for each output bucket.
 ///
 /// Aggregation is vectorized over histogram buckets, so bit 0 for every histogram bucket is stored
@@ -232,7 +418,7 @@ pub mod tests {
     use proptest::prelude::*;
     use rand::{rngs::StdRng, SeedableRng};
 
-    use super::aggregate_values;
+    use super::{aggregate_values, ValueBuckets};
     use crate::{
         const_assert,
         error::Error,
@@ -251,6 +437,29 @@ pub mod tests {
         })
     }
 
+    #[test]
+    fn value_buckets() {
+        let buckets = ValueBuckets::new(&[10, 50]).unwrap();
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(buckets.lower_bounds(), &[1, 10, 50]);
+        assert_eq!(buckets.histogram_index(0, 2), 2);
+        assert_eq!(buckets.histogram_index(4, 1), 13);
+
+        assert_eq!(ValueBuckets::new(&[]).unwrap().len(), 1);
+        assert!(matches!(
+            ValueBuckets::new(&[50, 10]),
+            Err(Error::InvalidQueryParameter(_))
+        ));
+        assert!(matches!(
+            ValueBuckets::new(&[1, 10]),
+            Err(Error::InvalidQueryParameter(_))
+        ));
+        assert!(matches!(
+            ValueBuckets::new(&[2, 3, 4, 5, 6, 7, 8, 9]),
+            Err(Error::InvalidQueryParameter(_))
+        ));
+    }
+
     #[test]
     fn aggregate_even() {
         // Test aggregation with clean log2 structure
@@ -10,10 +10,17 @@ pub(crate) enum AggregationStep {
     #[step(child = crate::protocol::ipa_prf::shuffle::step::OPRFShuffleStep)]
     Shuffle,
     RevealStep,
+    #[step(child = ValueBucketStep)]
+    BucketValues,
     #[step(child = AggregateChunkStep)]
     SumContributions,
 }
 
+/// Comparison of the trigger value with the lower bound of one value bucket.
+#[derive(CompactStep)]
+#[step(count = 8, child = crate::protocol::boolean::step::ThirtyTwoBitStep, name = "bucket")]
+pub(crate) struct ValueBucketStep(usize);
+
 #[derive(CompactStep)]
 #[step(count = 32, child = AggregateValuesStep, name = "depth")]
 pub(crate) struct AggregateChunkStep(usize);
@@ -92,6 +92,36 @@ where
     subtraction_circuit::<_, S, 1>(ctx, record_id, x, y, &mut carry).await
 }
 
+/// unsigned integer subtraction that also outputs the carry
+/// subtracts y from x and returns `(x - y, x >= y)`. The difference is computed exactly as in
+/// `integer_sub`, and the carry bit is one exactly when the subtraction did not underflow.
+/// Only correct when length(x) >= log2(y).
+/// # Errors
+/// propagates errors from multiply
+pub async fn integer_sub_with_carry<C, S>(
+    ctx: C,
+    record_id: RecordId,
+    x: &BitDecomposed<AdditiveShare<Boolean>>,
+    y: &BitDecomposed<AdditiveShare<Boolean>>,
+) -> Result<
+    (
+        BitDecomposed<AdditiveShare<Boolean>>,
+        AdditiveShare<Boolean>,
+    ),
+    Error,
+>
+where
+    C: Context,
+    S: NBitStep,
+    AdditiveShare<Boolean>: BooleanProtocols<C>,
+    Gate: StepNarrow<S>,
+{
+    // we need to initialize carry to 1 for a subtraction
+    let mut carry = AdditiveShare::<Boolean>::share_known_value(&ctx, Boolean::ONE);
+    let difference = subtraction_circuit::<_, S, 1>(ctx, record_id, x, y, &mut carry).await?;
+    Ok((difference, carry))
+}
+
 /// saturated unsigned integer subtraction
 /// subtracts y from x, Output has same length as x (we dont seem to need support for different length).
 /// when y>x, it outputs 0. Only correct when length(x) >= log2(y).
@@ -232,7 +262,7 @@ mod test {
             boolean::step::DefaultBitStep,
             context::Context,
             ipa_prf::boolean_ops::comparison_and_subtraction_sequential::{
-                compare_geq, compare_gt, integer_sat_sub, integer_sub,
+                compare_geq, compare_gt, integer_sat_sub, integer_sub, integer_sub_with_carry,
             },
             RecordId,
         },
@@ -577,6 +607,41 @@ mod test {
         });
     }
 
+    #[test]
+    fn semi_honest_sub_with_carry() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let mut rng = thread_rng();
+
+            let records = (rng.gen::<BA64>(), rng.gen::<BA32>());
+            let x = records.0.as_u128();
+            let y = records.1.as_u128();
+            let z = 1_u128 << 64;
+
+            let expected = (((x + z) - y) % z, x >= y);
+
+            let (result, carry): (BA64, Boolean) = world
+                .upgraded_semi_honest(records, |ctx, x_y| async move {
+                    let (difference, carry) = integer_sub_with_carry::<_, DefaultBitStep>(
+                        ctx.set_total_records(1),
+                        protocol::RecordId(0),
+                        &x_y.0.to_bits(),
+                        &x_y.1.to_bits(),
+                    )
+                    .await
+                    .unwrap();
+                    (difference.collect_bits::<AdditiveShare<BA64>>(), carry)
+                })
+                .await
+                .reconstruct();
+            assert_eq!(
+                (x, y, result.as_u128(), bool::from(carry)),
+                (x, y, expected.0, expected.1)
+            );
+        });
+    }
+
     #[test]
     fn test_overflow_behavior() {
         run(|| async move {
@@ -4,12 +4,20 @@ use crate::{
     error::Error,
     ff::boolean::Boolean,
     protocol::{
-        basics::mul::SecureMul, boolean::NBitStep, context::Context,
-        ipa_prf::boolean_ops::addition_sequential::integer_add, BooleanProtocols, Gate, RecordId,
+        basics::mul::SecureMul,
+        boolean::{step::ThirtyTwoBitStep, NBitStep},
+        context::Context,
+        ipa_prf::boolean_ops::{
+            addition_sequential::integer_add, step::MultiplicationPartialProductStep,
+        },
+        BooleanProtocols, Gate, RecordId,
     },
     secret_sharing::{replicated::semi_honest::AdditiveShare, BitDecomposed, FieldSimd},
 };
 
+/// The largest product, in bits, that `integer_mul` supports.
+pub(crate) const MAX_PRODUCT_BITS: usize = 32;
+
 /// This function multiplies x by y in these steps:
 /// 1. Double the input precision for y and repeat the most significant bit in the extra bits (Sign extension)
 ///    X is assumed to be a positive number, so we will automatically pad with ZERO.
@@ -17,8 +25,16 @@ use crate::{
 /// 3. Add up the partial products using `integer_add`
 ///    x is assumed to be a positive number
 ///    y is assumed to be in two's complement and can be either signed or unsigned
-#[allow(dead_code)]
-pub async fn integer_mul<C, S, const N: usize>(
+///
+/// The product has `x.len() + y.len()` bits. `S` narrows the context for each bit of the
+/// sign-extended `y`, and its child must be [`MultiplicationPartialProductStep`]. Compact gates
+/// need a concrete child type, so each caller defines its own `S`.
+///
+/// # Errors
+/// Propagates errors from multiplications
+/// # Panics
+/// If the product has more than [`MAX_PRODUCT_BITS`] bits, or more bits than `S` has steps.
+pub(crate) async fn integer_mul<C, S, const N: usize>(
     ctx: C,
     record_id: RecordId,
     x: &BitDecomposed<AdditiveShare<Boolean, N>>,
@@ -31,18 +47,25 @@ where
     AdditiveShare<Boolean, N>: BooleanProtocols<C, N>,
     Gate: StepNarrow<S>,
 {
-    use super::step::MultiplicationStep as Step;
-
     let new_len = x.len() + y.len();
+    assert!(
+        new_len <= MAX_PRODUCT_BITS && new_len <= usize::try_from(S::BITS).unwrap(),
+        "integer_mul supports products of at most {} bits, got {new_len}",
+        std::cmp::min(MAX_PRODUCT_BITS, usize::try_from(S::BITS).unwrap()),
+    );
     let mut y = y.clone();
     y.resize(new_len, y[y.len() - 1].clone());
 
     let mut result = BitDecomposed::with_capacity(new_len);
     for (i, yb) in y.into_iter().enumerate() {
         let ctx_for_bit_of_y = ctx.narrow(&S::from(i));
-        let product_of_x_and_yb = ctx_for_bit_of_y
+        let ctx_for_product = ctx_for_bit_of_y.narrow::<MultiplicationPartialProductStep>(
+            &MultiplicationPartialProductStep::Multiply,
+        );
+        let product_of_x_and_yb = ctx_for_product
             .parallel_join(x.iter().take(new_len - i).enumerate().map(|(j, xb)| {
-                let ctx_for_x_times_y_combo = ctx_for_bit_of_y.narrow(&S::from(j));
+                let ctx_for_x_times_y_combo =
+                    ctx_for_product.narrow::<ThirtyTwoBitStep>(&ThirtyTwoBitStep::from(j));
                 let yb = yb.clone();
                 async move { yb.multiply(xb, ctx_for_x_times_y_combo, record_id).await }
             }))
@@ -54,8 +77,10 @@ where
         } else {
             // add up bits i.. with the product
             let add_y = BitDecomposed::new(result.clone().into_iter().skip(i));
-            let (add_result, carry) = integer_add::<_, S, N>(
-                ctx_for_bit_of_y.narrow::<Step>(&Step::Add),
+            let (add_result, carry) = integer_add::<_, ThirtyTwoBitStep, N>(
+                ctx_for_bit_of_y.narrow::<MultiplicationPartialProductStep>(
+                    &MultiplicationPartialProductStep::Add,
+                ),
                 record_id,
                 &t,
                 &add_y,
@@ -73,6 +98,25 @@ where
     Ok(result)
 }
 
+/// Returns the number of Boolean multiplications that `integer_mul` performs for inputs of
+/// `x_bits` and `y_bits` bits, for use in sizing proof batches.
+#[must_use]
+pub(crate) fn integer_mul_multiplications(x_bits: usize, y_bits: usize) -> usize {
+    let new_len = x_bits + y_bits;
+    (0..new_len)
+        .map(|i| {
+            let partial_product = std::cmp::min(x_bits, new_len - i);
+            // every partial product after the first is added to the result with one
+            // multiplication per bit
+            if i == 0 {
+                partial_product
+            } else {
+                2 * partial_product
+            }
+        })
+        .sum()
+}
+
 #[cfg(all(test, unit_test))]
 mod test {
     use std::iter;
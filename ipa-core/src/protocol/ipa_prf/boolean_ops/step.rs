@@ -30,8 +30,11 @@ pub(crate) enum Fp25519ConversionStep {
     RevealY,
 }
 
+/// Child of the per-bit step that callers of `integer_mul` pass in.
 #[derive(CompactStep)]
-pub(crate) enum MultiplicationStep {
-    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+pub(crate) enum MultiplicationPartialProductStep {
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    Multiply,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
     Add,
 }
@@ -4,7 +4,7 @@ use futures::{stream, StreamExt, TryStreamExt};
 use generic_array::{ArrayLength, GenericArray};
 use typenum::{Const, Unsigned, U18};
 
-use self::{quicksort::quicksort_ranges_by_key_insecure, shuffle::shuffle_inputs};
+use self::{quicksort::quicksort_ranges_by_key, shuffle::shuffle_inputs};
 use crate::{
     error::{Error, LengthError, UnwrapInfallible},
     ff::{
@@ -12,6 +12,7 @@ use crate::{
         boolean_array::{BooleanArray, BA5, BA64, BA8},
         curve_points::RP25519,
         ec_prime_field::Fp25519,
+        fixed_point::AttributionWeights,
         Serializable, U128Conversions,
     },
     helpers::{
@@ -45,9 +46,11 @@ use crate::{
 
 pub(crate) mod aggregation;
 pub mod boolean_ops;
+pub use aggregation::{AggregationOutputMode, ValueBuckets};
 pub mod oprf_padding;
 pub mod prf_eval;
 pub mod prf_sharding;
+pub mod reach_frequency;
 
 mod malicious_security;
 mod quicksort;
@@ -92,7 +95,7 @@ pub const SORT_CHUNK: usize = 256;
 use step::IpaPrfStep as Step;
 
 use crate::{
-    helpers::query::DpMechanism,
+    helpers::query::{DpMechanism, QuerySize},
     protocol::{
         context::Validator,
         dp::dp_for_histogram,
@@ -211,9 +214,13 @@ where
 /// 4. Computes an OPRF of these elliptic curve points and reveals this "pseudonym"
 /// 5. Groups together rows with the same OPRF, and then obliviously sorts each group by the
 ///    secret-shared timestamp
-/// 6. Attributes trigger events to source events
-/// 7. Caps each user's total contribution to the final result
-/// 8. Aggregates the contributions of all users
+/// 6. Attributes trigger events to source events. If `attribution_weights` are given, each
+///    trigger value is split across the most recent source events, one weighted share per touch;
+///    otherwise it goes to the most recent one
+/// 7. Caps each user's total contribution to the final result at `per_user_credit_cap`, which
+///    can be any value from 1 to `2^SS_BITS`
+/// 8. Aggregates the contributions of all users, either by summing them or by counting them into
+///    value buckets, as selected by `output_mode`
 /// 9. Adds random noise to the total for each breakdown key (to provide a differential
 ///    privacy guarantee)
 /// # Errors
@@ -224,8 +231,11 @@ pub async fn oprf_ipa<'ctx, C, BK, TV, HV, TS, const SS_BITS: usize, const B: us
     ctx: C,
     input_rows: Vec<OPRFIPAInputRow<BK, TV, TS>>,
     attribution_window_seconds: Option<NonZeroU32>,
+    attribution_weights: Option<AttributionWeights>,
+    per_user_credit_cap: u32,
     dp_params: DpMechanism,
     dp_padding_params: PaddingParameters,
+    output_mode: &AggregationOutputMode,
 ) -> Result<Vec<Replicated<HV>>, Error>
 where
     C: UpgradableContext + 'ctx + Shuffle,
@@ -283,12 +293,17 @@ where
         // No user has more than one record.
         return Ok(vec![Replicated::ZERO; B]);
     }
-    quicksort_ranges_by_key_insecure(
+    // Padding adds dummy rows, so the counters appended to sort keys are sized for the
+    // padded input rather than the query size requested by the report collector.
+    let sort_size = QuerySize::try_from(prfd_inputs.len())
+        .map_err(|e| Error::InvalidQueryParameter(e.into()))?;
+    quicksort_ranges_by_key(
         ctx.narrow(&Step::SortByTimestamp),
         &mut prfd_inputs,
         false,
         |x| &x.sort_key,
         ranges,
+        sort_size,
     )
     .await?;
 
@@ -296,13 +311,21 @@ where
         ctx.narrow(&Step::Attribution),
         prfd_inputs,
         attribution_window_seconds,
+        attribution_weights,
+        per_user_credit_cap,
         &row_count_histogram,
         &dp_padding_params,
+        output_mode,
     )
     .await?;
 
-    let noisy_output_histogram =
-        dp_for_histogram::<_, B, HV, SS_BITS>(ctx, output_histogram, dp_params).await?;
+    let noisy_output_histogram = dp_for_histogram::<_, B, HV>(
+        ctx,
+        output_histogram,
+        dp_params,
+        output_mode.sensitivity(per_user_credit_cap),
+    )
+    .await?;
     Ok(noisy_output_histogram)
 }
 
@@ -414,7 +437,7 @@ pub mod tests {
         helpers::query::DpMechanism,
         protocol::{
             dp::NoiseParams,
-            ipa_prf::{oprf_ipa, oprf_padding::PaddingParameters},
+            ipa_prf::{oprf_ipa, oprf_padding::PaddingParameters, AggregationOutputMode},
         },
         test_executor::run,
         test_fixture::{ipa::TestRawDataRecord, Reconstruct, Runner, TestWorld},
@@ -467,8 +490,11 @@ pub mod tests {
                         ctx,
                         input_rows,
                         None,
+                        None,
+                        32,
                         dp_params,
                         padding_params,
+                        &AggregationOutputMode::Sum,
                     )
                     .await
                     .unwrap()
@@ -507,8 +533,11 @@ pub mod tests {
                         ctx,
                         input_rows,
                         None,
+                        None,
+                        32,
                         dp_params,
                         padding_params,
+                        &AggregationOutputMode::Sum,
                     )
                     .await
                     .unwrap()
@@ -564,8 +593,11 @@ pub mod tests {
                         ctx,
                         input_rows,
                         None,
+                        None,
+                        1 << SS_BITS,
                         dp_params,
                         padding_params,
+                        &AggregationOutputMode::Sum,
                     )
                     .await
                     .unwrap()
@@ -625,8 +657,11 @@ pub mod tests {
                         ctx,
                         input_rows,
                         None,
+                        None,
+                        32,
                         dp_params,
                         padding_params,
+                        &AggregationOutputMode::Sum,
                     )
                     .await
                     .unwrap()
@@ -661,8 +696,11 @@ pub mod tests {
                         ctx,
                         input_rows,
                         None,
+                        None,
+                        32,
                         dp_params,
                         padding_params,
+                        &AggregationOutputMode::Sum,
                     )
                     .await
                     .unwrap()
@@ -715,8 +753,11 @@ pub mod tests {
                         ctx,
                         input_rows,
                         None,
+                        None,
+                        32,
                         dp_params,
                         padding_params,
+                        &AggregationOutputMode::Sum,
                     )
                     .await
                     .unwrap()
@@ -743,7 +784,7 @@ mod compact_gate_tests {
         },
         helpers::query::DpMechanism,
         protocol::{
-            ipa_prf::{oprf_ipa, oprf_padding::PaddingParameters},
+            ipa_prf::{oprf_ipa, oprf_padding::PaddingParameters, AggregationOutputMode},
             step::{ProtocolGate, ProtocolStep},
         },
         test_executor::run,
@@ -828,12 +869,15 @@ mod compact_gate_tests {
 
             let mut result: Vec<_> = world
                 .semi_honest(records.into_iter(), |ctx, input_rows| async move {
-                    oprf_ipa::<_, BA5, BA8, BA8, BA20, 5, 32>(
+                    oprf_ipa::<_, BA5, BA8, BA8, BA20, 10, 32>(
                         ctx,
                         input_rows,
                         None,
+                        None,
+                        1000,
                         dp_params,
                         padding_params,
+                        &AggregationOutputMode::Sum,
                     )
                     .await
                     .unwrap()
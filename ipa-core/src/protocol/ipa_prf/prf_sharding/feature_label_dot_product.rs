@@ -44,9 +44,8 @@ impl InputsRequiredFromPrevRow {
     /// - Last touch attribution
     ///     - Every source event which has a subsequent trigger event receives attribution
     /// - Per user capping
-    ///     - A cumulative count of "Source Events Receiving Attribution" is maintained
-    ///     - Bitwise addition is used, and a single bit indicates if the sum is "saturated"
-    ///     - The only available values for "cap" are powers of 2 (i.e. 1, 2, 4, 8, 16, 32, ...)
+    ///     - A single bit indicates if a source event has already received attribution ("saturated")
+    ///     - The cap is one attributed source event per user
     ///     - Prior to saturation, feature vectors of source events receiving attribution contribute to the dot-product.
     ///     - All subsequent rows contribute zero
     /// - Outputs
@@ -9,7 +9,7 @@ use std::{
 use futures::{
     future::{try_join, try_join3},
     stream::{self, unfold},
-    FutureExt, Stream, StreamExt, TryStreamExt,
+    Stream, StreamExt, TryStreamExt,
 };
 
 use super::aggregation::breakdown_reveal::breakdown_reveal_aggregation;
@@ -18,14 +18,14 @@ use crate::{
     ff::{
         boolean::Boolean,
         boolean_array::{BooleanArray, BA32, BA7},
+        fixed_point::{AttributionWeight, AttributionWeights},
         ArrayAccess, Field, U128Conversions,
     },
-    helpers::{repeat_n, stream::TryFlattenItersExt, TotalRecords},
+    helpers::{stream::TryFlattenItersExt, TotalRecords},
     protocol::{
         basics::{select, BooleanArrayMul, BooleanProtocols, SecureMul, ShareKnownValue},
         boolean::{
-            or::or,
-            step::{EightBitStep, ThirtyTwoBitStep},
+            step::{SixteenBitStep, ThirtyTwoBitStep},
             NBitStep,
         },
         context::{
@@ -33,16 +33,20 @@ use crate::{
             Context, DZKPContext, DZKPUpgraded, MaliciousProtocolSteps, UpgradableContext,
         },
         ipa_prf::{
-            aggregation::aggregate_values_proof_chunk,
+            aggregation::AggregationOutputMode,
             boolean_ops::{
                 addition_sequential::integer_add,
-                comparison_and_subtraction_sequential::{compare_gt, integer_sub},
+                comparison_and_subtraction_sequential::{
+                    compare_gt, integer_sub, integer_sub_with_carry,
+                },
                 expand_shared_array_in_place,
+                multiplication::{integer_mul, integer_mul_multiplications},
             },
             oprf_padding::PaddingParameters,
             prf_sharding::step::{
                 AttributionPerRowStep as PerRowStep, AttributionStep as Step,
-                AttributionWindowStep as WindowStep,
+                AttributionTouchStep as TouchStep, AttributionWeightBitStep,
+                AttributionWeightStep as WeightStep, AttributionWindowStep as WindowStep,
                 AttributionZeroOutTriggerStep as ZeroOutTriggerStep, UserNthRowStep,
             },
             BreakdownKey, AGG_CHUNK,
@@ -76,7 +80,8 @@ where
     /// The order of sorting is `timestamp`, `is_trigger_bit`, `counter`.
     /// We sort by `is_trigger_bit` to ensure source events come before trigger in case there
     /// is a tie in timestamp
-    /// Counter is added to ensure each sorting key is unique to avoid privacy leakage
+    /// Counter orders a user's events with equal timestamps. Uniqueness of the key across the
+    /// whole sort is enforced separately by `quicksort_ranges_by_key`, which appends its own counter
     /// NOTE: the sort key will be interpreted in Little endian format, so the order in
     /// which things are appended is important.
     /// We still need to add epoch which will be added later
@@ -104,33 +109,46 @@ impl<BK: SharedValue, TS: SharedValue, TV: SharedValue> GroupingKey
     }
 }
 
-struct InputsRequiredFromPrevRow<BK: SharedValue, TV: SharedValue, TS: SharedValue> {
+/// A source event that later trigger events of the same user can be attributed to.
+struct Touch<BK: SharedValue, TS: SharedValue> {
     ever_encountered_a_source_event: Replicated<Boolean>,
     attributed_breakdown_key_bits: Replicated<BK>,
-    saturating_sum: BitDecomposed<Replicated<Boolean>>,
-    is_saturated: Replicated<Boolean>,
-    difference_to_cap: Replicated<TV>,
     source_event_timestamp: Replicated<TS>,
 }
 
+struct InputsRequiredFromPrevRow<BK: SharedValue, TS: SharedValue> {
+    /// The most recent source events of the user, most recent first. There is one per attribution
+    /// weight, or a single one for last touch attribution.
+    touches: Vec<Touch<BK, TS>>,
+    remaining_cap: BitDecomposed<Replicated<Boolean>>,
+}
+
 /// Returns the number of Boolean multiplications per input record, for use in computing the number
 /// of records in each DZKP. These multiplications are in `compute_row_with_previous` and the
 /// functions it calls.
-fn multiplications_per_record<BK: SharedValue, TV: SharedValue, TS: SharedValue>(
+fn multiplications_per_record<
+    BK: SharedValue,
+    TV: SharedValue,
+    TS: SharedValue,
+    const SS_BITS: usize,
+>(
     attribution_window: Option<NonZeroU32>,
+    attribution_weights: Option<AttributionWeights>,
 ) -> usize {
+    let remaining_cap_bits = u32::try_from(SS_BITS).unwrap() + 1;
     let mut count =
         // breakdown_key_of_most_recent_source_event
         BK::BITS +
         // zero_out_trigger_value_unless_attributed
-        // cumulative trigger value sum
+        // compute_capped_trigger_value
+        2 * TV::BITS +
         // difference to cap
-        // compute_capped_trigger_value (2x)
-        5 * TV::BITS +
+        std::cmp::max(TV::BITS, remaining_cap_bits) +
+        // remaining cap
+        remaining_cap_bits +
         // ever_encountered_a_source_event
-        // overflow_bit_and_prev_row_not_saturated
         // did_trigger_get_attributed
-        3;
+        2;
 
     if attribution_window.is_some() {
         count +=
@@ -142,13 +160,26 @@ fn multiplications_per_record<BK: SharedValue, TV: SharedValue, TS: SharedValue>
             1;
     }
 
-    usize::try_from(count).unwrap()
+    let mut count = usize::try_from(count).unwrap();
+    if attribution_weights.is_some() {
+        let tv_bits = usize::try_from(TV::BITS).unwrap();
+        count +=
+            // apply_attribution_weight
+            integer_mul_multiplications(tv_bits, weight_bits()) + tv_bits + 1;
+    }
+
+    // all of the above is done once per touch
+    count * touch_count(attribution_weights)
 }
 
-impl<BK, TV, TS> InputsRequiredFromPrevRow<BK, TV, TS>
+/// The number of touches that each trigger value is split across.
+fn touch_count(attribution_weights: Option<AttributionWeights>) -> usize {
+    attribution_weights.map_or(1, |weights| weights.len())
+}
+
+impl<BK, TS> InputsRequiredFromPrevRow<BK, TS>
 where
     BK: BooleanArray + U128Conversions,
-    TV: BooleanArray + U128Conversions,
     TS: BooleanArray + U128Conversions,
 {
     ///
@@ -156,18 +187,22 @@ where
     /// Multiple rows of data about a single user are processed in-order from oldest to newest.
     ///
     /// Summary:
-    /// - Last touch attribution
+    /// - Attribution
     ///     - Every trigger event which is preceded by a source event is attributed
-    ///     - Trigger events are attributed to the `breakdown_key` of the most recent preceding source event
+    ///     - Without `attribution_weights`, trigger events are attributed to the `breakdown_key` of the most recent preceding
+    ///       source event (last touch attribution)
+    ///     - With `attribution_weights`, the trigger value is split across the most recent preceding source events, one per
+    ///       weight: touch `k` is credited with the trigger value multiplied by weight `k`
+    ///     - A touch that does not exist, or is outside of the attribution window, is credited with zero
     /// - Per user capping
-    ///     - A cumulative sum of "Attributed Trigger Value" is maintained
-    ///     - Bitwise addition is used, and a single bit indicates if the sum is "saturated"
-    ///     - The only available values for "cap" are powers of 2 (i.e. 1, 2, 4, 8, 16, 32, ...)
-    ///     - Prior to the cumulative sum reaching saturation, attributed trigger values are passed along
-    ///     - The row which puts the cumulative sum over the cap is "capped" to the delta between the cumulative sum of the last row and the cap
+    ///     - The "remaining cap" (the cap minus the value contributed so far) is maintained
+    ///     - The cap can be any value from 1 to `2^SS_BITS`, it does not need to be a power of 2
+    ///     - While the remaining cap covers it, an attributed trigger value is passed along in full
+    ///     - The row which exceeds the remaining cap is "capped" to the remaining cap
     ///     - All subsequent rows contribute zero
+    ///     - The touches of a row are capped in order, most recent first
     /// - Outputs
-    ///     - If a user has `N` input rows, they will generate `N-1` output rows. (The first row cannot possibly contribute any value to the output)
+    ///     - If a user has `N` input rows, they will generate `N-1` output rows per touch. (The first row cannot possibly contribute any value to the output)
     ///     - Each output row has two main values:
     ///         - `capped_attributed_trigger_value` - the value to contribute to the output (bitwise secret-shared),
     ///         - `attributed_breakdown_key` - the breakdown to which this contribution applies (bitwise secret-shared),
@@ -175,130 +210,188 @@ where
     ///         - `did_trigger_get_attributed` - a secret-shared bit indicating if this row corresponds to a trigger event
     ///           which was attributed. Might be able to reveal this (after a shuffle and the addition of dummies) to minimize
     ///           the amount of processing work that must be done in the Aggregation stage.
-    pub async fn compute_row_with_previous<C>(
+    pub async fn compute_row_with_previous<C, TV>(
         &mut self,
         ctx: C,
         record_id: RecordId,
         input_row: &PrfShardedIpaInputRow<BK, TV, TS>,
         attribution_window_seconds: Option<NonZeroU32>,
-    ) -> Result<AttributionOutputs<Replicated<BK>, Replicated<TV>>, Error>
+        attribution_weights: Option<AttributionWeights>,
+    ) -> Result<Vec<AttributionOutputs<Replicated<BK>, Replicated<TV>>>, Error>
     where
         C: Context,
+        TV: BooleanArray + U128Conversions,
         Replicated<Boolean>: BooleanProtocols<C>,
         Replicated<BK>: BooleanArrayMul<C>,
         Replicated<TS>: BooleanArrayMul<C>,
         Replicated<TV>: BooleanArrayMul<C>,
     {
-        let is_source_event = input_row.is_trigger_bit.clone().not();
+        // A source event becomes the most recent touch, and every touch moves one place back.
+        let most_recent = Touch {
+            ever_encountered_a_source_event: Replicated::share_known_value(&ctx, Boolean::ONE),
+            attributed_breakdown_key_bits: input_row.breakdown_key.clone(),
+            source_event_timestamp: input_row.timestamp.clone(),
+        };
+        let (touches, attributed_trigger_values): (Vec<_>, Vec<_>) = ctx
+            .parallel_join(self.touches.iter().enumerate().map(|(k, touch)| {
+                let ctx = ctx.narrow(&TouchStep::from(k));
+                let newer = if k == 0 {
+                    &most_recent
+                } else {
+                    &self.touches[k - 1]
+                };
+                let weight = attribution_weights.map(|weights| weights.as_slice()[k]);
+                async move {
+                    let touch = touch
+                        .update(
+                            ctx.clone(),
+                            record_id,
+                            &input_row.is_trigger_bit,
+                            attribution_window_seconds,
+                            newer,
+                        )
+                        .await?;
+                    let attributed_trigger_value = touch
+                        .attributed_trigger_value(
+                            ctx,
+                            record_id,
+                            input_row,
+                            attribution_window_seconds,
+                            weight,
+                        )
+                        .await?;
+                    Ok::<_, Error>((touch, attributed_trigger_value))
+                }
+            }))
+            .await?
+            .into_iter()
+            .unzip();
+
+        let mut outputs = Vec::with_capacity(touches.len());
+        for (k, (touch, attributed_trigger_value)) in
+            zip(&touches, attributed_trigger_values).enumerate()
+        {
+            let (capped_attributed_trigger_value, remaining_cap) = compute_capped_trigger_value(
+                ctx.narrow(&TouchStep::from(k)),
+                record_id,
+                &self.remaining_cap,
+                &attributed_trigger_value,
+            )
+            .await?;
+            self.remaining_cap = remaining_cap;
+            outputs.push(AttributionOutputs {
+                attributed_breakdown_key_bits: touch.attributed_breakdown_key_bits.clone(),
+                capped_attributed_trigger_value,
+            });
+        }
+        self.touches = touches;
+
+        Ok(outputs)
+    }
+}
 
+impl<BK, TS> Touch<BK, TS>
+where
+    BK: BooleanArray + U128Conversions,
+    TS: BooleanArray + U128Conversions,
+{
+    /// Returns this touch after a row: unchanged after a trigger event, and the `newer` touch after a
+    /// source event.
+    async fn update<C>(
+        &self,
+        ctx: C,
+        record_id: RecordId,
+        is_trigger_bit: &Replicated<Boolean>,
+        attribution_window_seconds: Option<NonZeroU32>,
+        newer: &Self,
+    ) -> Result<Self, Error>
+    where
+        C: Context,
+        Replicated<Boolean>: BooleanProtocols<C>,
+        Replicated<BK>: BooleanArrayMul<C>,
+        Replicated<TS>: BooleanArrayMul<C>,
+    {
         let (
             ever_encountered_a_source_event,
             attributed_breakdown_key_bits,
             source_event_timestamp,
         ) = try_join3(
-            or(
-                ctx.narrow(&PerRowStep::EverEncounteredSourceEvent),
-                record_id,
-                &is_source_event,
-                &self.ever_encountered_a_source_event,
-            ),
+            async {
+                // newer ^ is_trigger * (self ^ newer)
+                let keep = is_trigger_bit
+                    .multiply(
+                        &(&self.ever_encountered_a_source_event
+                            + &newer.ever_encountered_a_source_event),
+                        ctx.narrow(&PerRowStep::EverEncounteredSourceEvent),
+                        record_id,
+                    )
+                    .await?;
+                Ok(keep + &newer.ever_encountered_a_source_event)
+            },
             breakdown_key_of_most_recent_source_event(
                 ctx.narrow(&PerRowStep::AttributedBreakdownKey),
                 record_id,
-                &input_row.is_trigger_bit,
+                is_trigger_bit,
                 &self.attributed_breakdown_key_bits,
-                &input_row.breakdown_key,
+                &newer.attributed_breakdown_key_bits,
             ),
             timestamp_of_most_recent_source_event(
                 ctx.narrow(&PerRowStep::SourceEventTimestamp),
                 record_id,
                 attribution_window_seconds,
-                &input_row.is_trigger_bit,
+                is_trigger_bit,
                 &self.source_event_timestamp,
-                &input_row.timestamp,
+                &newer.source_event_timestamp,
             ),
         )
         .await?;
 
+        Ok(Self {
+            ever_encountered_a_source_event,
+            attributed_breakdown_key_bits,
+            source_event_timestamp,
+        })
+    }
+
+    /// Returns the share of the trigger value in `input_row` that is credited to this touch, before
+    /// capping.
+    async fn attributed_trigger_value<C, TV>(
+        &self,
+        ctx: C,
+        record_id: RecordId,
+        input_row: &PrfShardedIpaInputRow<BK, TV, TS>,
+        attribution_window_seconds: Option<NonZeroU32>,
+        attribution_weight: Option<AttributionWeight>,
+    ) -> Result<Replicated<TV>, Error>
+    where
+        C: Context,
+        TV: BooleanArray + U128Conversions,
+        Replicated<Boolean>: BooleanProtocols<C>,
+        Replicated<TV>: BooleanArrayMul<C>,
+    {
         let attributed_trigger_value = zero_out_trigger_value_unless_attributed(
             ctx.narrow(&PerRowStep::AttributedTriggerValue),
             record_id,
             &input_row.is_trigger_bit,
-            &ever_encountered_a_source_event,
+            &self.ever_encountered_a_source_event,
             &input_row.trigger_value,
             attribution_window_seconds,
             &input_row.timestamp,
-            &source_event_timestamp,
-        )
-        .await?;
-
-        assert!(
-            TV::BITS <= EightBitStep::BITS,
-            "EightBitStep not large enough to accomodate this sum"
-        );
-        let (updated_sum, overflow_bit) = integer_add::<_, EightBitStep, 1>(
-            ctx.narrow(&PerRowStep::ComputeSaturatingSum),
-            record_id,
-            &self.saturating_sum,
-            &attributed_trigger_value.to_bits(),
+            &self.source_event_timestamp,
         )
         .await?;
 
-        assert!(
-            TV::BITS <= EightBitStep::BITS,
-            "EightBitStep not large enough to accomodate this subtraction"
-        );
-        let (overflow_bit_and_prev_row_not_saturated, difference_to_cap) = try_join(
-            overflow_bit.multiply(
-                &self.is_saturated.clone().not(),
-                ctx.narrow(&PerRowStep::IsSaturatedAndPrevRowNotSaturated),
+        if let Some(weight) = attribution_weight {
+            apply_attribution_weight(
+                ctx.narrow(&PerRowStep::ApplyAttributionWeight),
                 record_id,
-            ),
-            // It is okay that we are calling `integer_sub` with length(y) > length(x) here.
-            // `difference_to_cap` only needs to be accurate in the case where the next row will
-            // overflow. When that is the case, `updated_sum` must be within `2^TV::BITS` of the
-            // cap, and a `TV::BITS` subtraction of the `TV::BITS` least significant bits of
-            // `updated_sum` from zero will correctly compute the difference to the cap.
-            integer_sub::<_, EightBitStep>(
-                ctx.narrow(&PerRowStep::ComputeDifferenceToCap),
-                record_id,
-                &BitDecomposed::new(repeat_n(
-                    Replicated::ZERO,
-                    usize::try_from(TV::BITS).unwrap(),
-                )),
-                &updated_sum,
+                weight,
+                &attributed_trigger_value,
             )
-            .map(|res| res.map(BitDecomposed::collect_bits)),
-        )
-        .await?;
-
-        // Tricky way of expressing an `OR` condition, but with no additional multiplications:
-        //   Logically: "Did this row just become saturated OR was the previous row already saturated"
-        //   This works because these conditions cannot both be true
-        let is_saturated = &self.is_saturated + &overflow_bit_and_prev_row_not_saturated;
-
-        let capped_attributed_trigger_value = compute_capped_trigger_value(
-            ctx,
-            record_id,
-            &is_saturated,
-            &overflow_bit_and_prev_row_not_saturated,
-            &self.difference_to_cap,
-            &attributed_trigger_value,
-        )
-        .await?;
-
-        self.ever_encountered_a_source_event = ever_encountered_a_source_event;
-        self.attributed_breakdown_key_bits = attributed_breakdown_key_bits.clone();
-        self.saturating_sum = updated_sum;
-        self.is_saturated = is_saturated;
-        self.difference_to_cap = difference_to_cap;
-        self.source_event_timestamp = source_event_timestamp;
-
-        let outputs_for_aggregation = AttributionOutputs {
-            attributed_breakdown_key_bits,
-            capped_attributed_trigger_value,
-        };
-        Ok(outputs_for_aggregation)
+            .await
+        } else {
+            Ok(attributed_trigger_value)
+        }
     }
 }
 
@@ -449,9 +542,13 @@ where
 /// This circuit expects to receive records from multiple users,
 /// but with all of the records from a given user adjacent to one another, and in time order.
 ///
-/// This circuit will compute attribution, per-user capping and aggregation.
+/// This circuit will compute attribution, per-user capping and aggregation. `output_mode` selects
+/// whether aggregation sums attributed values or counts them into value buckets.
+/// `per_user_credit_cap` is the maximum total value any one user can contribute; it can be any
+/// value from 1 to `2^SS_BITS`.
 ///
 /// # Errors
+/// If `per_user_credit_cap` is outside of `[1, 2^SS_BITS]`.
 /// Propagates errors from multiplications
 /// # Panics
 /// Propagates errors from multiplications
@@ -469,8 +566,11 @@ pub async fn attribute_cap_aggregate<
     sh_ctx: C,
     input_rows: Vec<PrfShardedIpaInputRow<BK, TV, TS>>,
     attribution_window_seconds: Option<NonZeroU32>,
+    attribution_weights: Option<AttributionWeights>,
+    per_user_credit_cap: u32,
     histogram: &[usize],
     padding_parameters: &PaddingParameters,
+    output_mode: &AggregationOutputMode,
 ) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
 where
     C: UpgradableContext + 'ctx,
@@ -498,13 +598,26 @@ where
     Vec<Replicated<HV>>:
         for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
 {
+    if per_user_credit_cap == 0 || u64::from(per_user_credit_cap) > 1 << SS_BITS {
+        return Err(Error::InvalidQueryParameter(
+            format!(
+                "per-user credit cap {per_user_credit_cap} must be between 1 and {max}",
+                max = 1_u64 << SS_BITS,
+            )
+            .into(),
+        ));
+    }
+
     // Get the validator and context to use for Boolean multiplication operations.
     // Record IDs count users. The maximum number of multiplications per record (user) is:
     // (max_events - 1) * multiplictions_per_record, because the attribution circuit is
     // only evaluated for the second and subsequent records.
     let chunk_size = TARGET_PROOF_SIZE
         / ((histogram.len() - 1)
-            * multiplications_per_record::<BK, TV, TS>(attribution_window_seconds));
+            * multiplications_per_record::<BK, TV, TS, SS_BITS>(
+                attribution_window_seconds,
+                attribution_weights,
+            ));
 
     // Tricky hacks to work around the limitations of our current infrastructure
     let mut dzkp_validator = sh_ctx.clone().dzkp_validator(
@@ -536,6 +649,8 @@ where
         ctx_for_row_number,
         collected,
         attribution_window_seconds,
+        attribution_weights,
+        per_user_credit_cap,
     );
 
     let validator = sh_ctx.dzkp_validator(
@@ -543,13 +658,16 @@ where
             protocol: &Step::Aggregate,
             validate: &Step::AggregateValidate,
         },
-        aggregate_values_proof_chunk(B, usize::try_from(TV::BITS).unwrap()).next_power_of_two(),
+        output_mode
+            .proof_chunk(B, usize::try_from(TV::BITS).unwrap())
+            .next_power_of_two(),
     );
     let user_contributions = flattened_user_results.try_collect::<Vec<_>>().await?;
     let result = breakdown_reveal_aggregation::<_, _, _, HV, B>(
         validator.context(),
         user_contributions,
         padding_parameters,
+        output_mode,
     )
     .await;
     validator.validate().await?;
@@ -562,6 +680,8 @@ fn attribute<'ctx, V, BK, TV, TS, const SS_BITS: usize, const B: usize>(
     contexts: Vec<V::Context>,
     input: Vec<Vec<PrfShardedIpaInputRow<BK, TV, TS>>>,
     attribution_window_seconds: Option<NonZeroU32>,
+    attribution_weights: Option<AttributionWeights>,
+    per_user_credit_cap: u32,
 ) -> impl Stream<Item = Result<SecretSharedAttributionOutputs<BK, TV>, Error>> + Send + 'ctx
 where
     V: DZKPValidator + 'ctx,
@@ -586,6 +706,8 @@ where
                     RecordId::from(record_id),
                     rows_for_user,
                     attribution_window_seconds,
+                    attribution_weights,
+                    per_user_credit_cap,
                 )
             });
 
@@ -602,6 +724,8 @@ async fn evaluate_per_user_attribution_circuit<C, BK, TV, TS, const SS_BITS: usi
     record_id: RecordId,
     rows_for_user: Vec<PrfShardedIpaInputRow<BK, TV, TS>>,
     attribution_window_seconds: Option<NonZeroU32>,
+    attribution_weights: Option<AttributionWeights>,
+    per_user_credit_cap: u32,
 ) -> Result<Vec<SecretSharedAttributionOutputs<BK, TV>>, Error>
 where
     C: DZKPContext,
@@ -618,16 +742,26 @@ where
         return Ok(Vec::new());
     }
     let first_row = &rows_for_user[0];
-    let mut prev_row_inputs =
-        initialize_new_device_attribution_variables::<BK, TV, TS, SS_BITS>(first_row);
+    let mut prev_row_inputs = initialize_new_device_attribution_variables::<BK, TV, TS, SS_BITS>(
+        first_row,
+        touch_count(attribution_weights),
+        per_user_credit_cap,
+    );
 
-    let mut output = Vec::with_capacity(rows_for_user.len() - 1);
+    let mut output =
+        Vec::with_capacity((rows_for_user.len() - 1) * touch_count(attribution_weights));
     for (row, ctx) in zip(rows_for_user.iter().skip(1), ctx_for_row_number.into_iter()) {
         let capped_attribution_outputs = prev_row_inputs
-            .compute_row_with_previous(ctx, record_id, row, attribution_window_seconds)
+            .compute_row_with_previous(
+                ctx,
+                record_id,
+                row,
+                attribution_window_seconds,
+                attribution_weights,
+            )
             .await?;
 
-        output.push(capped_attribution_outputs);
+        output.extend(capped_attribution_outputs);
     }
     Ok(output)
 }
@@ -638,21 +772,35 @@ where
 ///
 fn initialize_new_device_attribution_variables<BK, TV, TS, const SS_BITS: usize>(
     input_row: &PrfShardedIpaInputRow<BK, TV, TS>,
-) -> InputsRequiredFromPrevRow<BK, TV, TS>
+    touch_count: usize,
+    per_user_credit_cap: u32,
+) -> InputsRequiredFromPrevRow<BK, TS>
 where
     BK: SharedValue,
     TV: SharedValue,
     TS: SharedValue,
 {
-    InputsRequiredFromPrevRow {
+    let most_recent = Touch {
         ever_encountered_a_source_event: input_row.is_trigger_bit.clone().not(),
         attributed_breakdown_key_bits: input_row.breakdown_key.clone(),
-        saturating_sum: BitDecomposed::new(repeat_n(Replicated::ZERO, SS_BITS)),
-        is_saturated: Replicated::<Boolean>::ZERO,
-        // This is incorrect in the case that the CAP is less than the maximum value of "trigger value" for a single row
-        // Not a problem if you assume that's an invalid input
-        difference_to_cap: Replicated::<TV>::ZERO,
         source_event_timestamp: input_row.timestamp.clone(),
+    };
+    // Older touches have not been encountered yet.
+    let older = iter::repeat_with(|| Touch {
+        ever_encountered_a_source_event: Replicated::ZERO,
+        attributed_breakdown_key_bits: Replicated::ZERO,
+        source_event_timestamp: Replicated::ZERO,
+    });
+    InputsRequiredFromPrevRow {
+        touches: iter::once(most_recent)
+            .chain(older)
+            .take(touch_count)
+            .collect(),
+        // The cap is public, so every helper can hold the same value in both halves of its share.
+        remaining_cap: BitDecomposed::decompose(u32::try_from(SS_BITS).unwrap() + 1, |i| {
+            let bit = Boolean::truncate_from((u64::from(per_user_credit_cap) >> i) & 0x1);
+            Replicated::new(bit, bit)
+        }),
     }
 }
 
@@ -828,60 +976,158 @@ where
     }
 }
 
+impl NBitStep for AttributionWeightBitStep {
+    const BITS: u32 = 32;
+}
+
+/// Width of the weight operand of `integer_mul` in [`apply_attribution_weight`]: the weight, plus a
+/// zero sign bit, because `integer_mul` reads its second operand as two's complement.
+fn weight_bits() -> usize {
+    usize::try_from(AttributionWeight::BITS).unwrap() + 1
+}
+
+///
+/// Multiplies the attributed trigger value by `weight`, keeping the scale of the trigger value.
+///
+/// The weight has `AttributionWeight::FRACTIONAL_BITS` fractional bits, so the product does too.
+/// It is rounded to the nearest whole unit, with halves rounded up, by adding one half unit and
+/// dropping the fractional bits. A half unit is a one in the most significant fractional bit; the
+/// fractional bits below it cannot carry, so they are dropped before the addition. Because the
+/// weight is at most one, the rounded product is at most the trigger value and fits in `TV::BITS`
+/// bits. This matches [`AttributionWeight::apply`].
+///
+async fn apply_attribution_weight<C, TV>(
+    ctx: C,
+    record_id: RecordId,
+    weight: AttributionWeight,
+    attributed_trigger_value: &Replicated<TV>,
+) -> Result<Replicated<TV>, Error>
+where
+    C: Context,
+    TV: BooleanArray + U128Conversions,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    let tv_bits = usize::try_from(TV::BITS).unwrap();
+    let fractional_bits = usize::try_from(AttributionWeight::FRACTIONAL_BITS).unwrap();
+    assert!(
+        tv_bits < usize::try_from(ThirtyTwoBitStep::BITS).unwrap(),
+        "ThirtyTwoBitStep is not large enough to round the weighted trigger value"
+    );
+
+    let weight_bits = BitDecomposed::decompose(u32::try_from(weight_bits()).unwrap(), |i| {
+        Replicated::share_known_value(&ctx, Boolean::truncate_from((weight.units() >> i) & 0x1))
+    });
+    let product = integer_mul::<_, AttributionWeightBitStep, 1>(
+        ctx.narrow(&WeightStep::Multiply),
+        record_id,
+        &attributed_trigger_value.to_bits(),
+        &weight_bits,
+    )
+    .await?;
+
+    let (rounded, _) = integer_add::<_, ThirtyTwoBitStep, 1>(
+        ctx.narrow(&WeightStep::Round),
+        record_id,
+        &BitDecomposed::new(
+            product
+                .into_iter()
+                .skip(fractional_bits - 1)
+                .take(tv_bits + 1),
+        ),
+        &BitDecomposed::new(iter::once(Replicated::share_known_value(
+            &ctx,
+            Boolean::ONE,
+        ))),
+    )
+    .await?;
+
+    Ok(rounded.into_iter().skip(1).collect::<Replicated<TV>>())
+}
+
 ///
 /// To provide a differential privacy guarantee, we need to bound the maximum contribution from any given user to some cap.
 ///
-/// The following values are computed for each row:
-/// (1) The uncapped "Attributed trigger value" (which is either the original `trigger_value` bits or zero if it was unattributed)
-/// (2) The cumulative sum of "Attributed trigger value" thus far (which "saturates" at a given power of two as indicated by the `is_saturated` flag)
-/// (3) The "delta to cap", which is the difference between the "cap" and the cumulative sum (this value is meaningless once the cumulative sum is saturated)
+/// Instead of a cumulative sum, each user carries the "remaining cap": the cap minus the sum of the capped attributed trigger
+/// values it has contributed so far. It starts out equal to the cap, which can be any value representable in `SS_BITS + 1` bits.
 ///
 /// To perfectly cap each user's contributions at precisely the cap, the "attributed trigger value" will sometimes need to be lowered,
-/// such that the total cumulative sum adds up to exactly the cap.
+/// such that the total contribution adds up to exactly the cap.
 ///
-/// This oblivious algorithm computes the "capped attributed trigger value" in the following way:
-/// IF the cumulative is NOT YET saturated:
-///     - just return the attributed trigger value
-/// ELSE IF the cumulative sum JUST became saturated (that is, it was NOT saturated on the preceding line but IS on this line):
-///     - return the "delta to cap" from the preceding line
-/// ELSE
-///     - return zero
+/// This oblivious algorithm subtracts the attributed trigger value from the remaining cap, and uses the carry of that
+/// subtraction to decide what to do:
+/// IF the remaining cap is at least the attributed trigger value:
+///     - return the attributed trigger value, and the difference becomes the new remaining cap
+/// ELSE:
+///     - return the remaining cap, and the new remaining cap is zero
+///
+/// Once the remaining cap is zero, every subsequent row contributes zero.
 ///
 async fn compute_capped_trigger_value<C, TV>(
     ctx: C,
     record_id: RecordId,
-    is_saturated: &Replicated<Boolean>,
-    is_saturated_and_prev_row_not_saturated: &Replicated<Boolean>,
-    prev_row_diff_to_cap: &Replicated<TV>,
+    prev_row_remaining_cap: &BitDecomposed<Replicated<Boolean>>,
     attributed_trigger_value: &Replicated<TV>,
-) -> Result<Replicated<TV>, Error>
+) -> Result<(Replicated<TV>, BitDecomposed<Replicated<Boolean>>), Error>
 where
     C: Context,
     TV: BooleanArray + U128Conversions,
+    Replicated<Boolean>: BooleanProtocols<C>,
     Replicated<TV>: BooleanArrayMul<C>,
 {
-    let narrowed_ctx1 =
-        ctx.narrow(&PerRowStep::ComputedCappedAttributedTriggerValueNotSaturatedCase);
-    let narrowed_ctx2 =
-        ctx.narrow(&PerRowStep::ComputedCappedAttributedTriggerValueJustSaturatedCase);
+    let tv_bits = usize::try_from(TV::BITS).unwrap();
+    let remaining_cap_bits = prev_row_remaining_cap.len();
+    let width = std::cmp::max(tv_bits, remaining_cap_bits);
+    assert!(
+        width <= usize::try_from(SixteenBitStep::BITS).unwrap(),
+        "SixteenBitStep not large enough to accomodate this subtraction"
+    );
 
-    let attributed_trigger_value_or_zero = select(
-        narrowed_ctx1,
-        record_id,
-        is_saturated,
-        &Replicated::new(<TV as SharedValue>::ZERO, <TV as SharedValue>::ZERO),
-        attributed_trigger_value,
+    // Zero-extend the remaining cap so that the subtraction satisfies `length(x) >= log2(y)`.
+    let mut remaining_cap = prev_row_remaining_cap.clone();
+    remaining_cap.resize(width, Replicated::ZERO);
+
+    let (difference_to_cap, remaining_cap_covers_value) =
+        integer_sub_with_carry::<_, SixteenBitStep>(
+            ctx.narrow(&PerRowStep::ComputeDifferenceToCap),
+            record_id,
+            &remaining_cap,
+            &attributed_trigger_value.to_bits(),
+        )
+        .await?;
+
+    // When the value exceeds the remaining cap, the remaining cap is smaller than the value and
+    // therefore fits in `TV::BITS` bits.
+    let remaining_cap_as_tv = remaining_cap
+        .into_iter()
+        .take(tv_bits)
+        .collect::<Replicated<TV>>();
+
+    let ctx_remaining_cap = ctx.narrow(&PerRowStep::ComputeRemainingCap);
+    let (capped_attributed_trigger_value, updated_remaining_cap) = try_join(
+        select(
+            ctx.narrow(&PerRowStep::ComputeCappedAttributedTriggerValue),
+            record_id,
+            &remaining_cap_covers_value,
+            attributed_trigger_value,
+            &remaining_cap_as_tv,
+        ),
+        ctx_remaining_cap.parallel_join(
+            difference_to_cap
+                .iter()
+                .take(remaining_cap_bits)
+                .enumerate()
+                .map(|(i, bit)| {
+                    let ctx = ctx_remaining_cap.narrow(&SixteenBitStep::from(i));
+                    bit.multiply(&remaining_cap_covers_value, ctx, record_id)
+                }),
+        ),
     )
     .await?;
 
-    select(
-        narrowed_ctx2,
-        record_id,
-        is_saturated_and_prev_row_not_saturated,
-        prev_row_diff_to_cap,
-        &attributed_trigger_value_or_zero,
-    )
-    .await
+    Ok((
+        capped_attributed_trigger_value,
+        BitDecomposed::new(updated_remaining_cap),
+    ))
 }
 
 #[cfg(all(test, unit_test))]
@@ -893,11 +1139,13 @@ pub mod tests {
         ff::{
             boolean::Boolean,
             boolean_array::{BooleanArray, BA16, BA20, BA3, BA5, BA8},
+            fixed_point::{AttributionWeight, AttributionWeights},
             Field, U128Conversions,
         },
         helpers::repeat_n,
         protocol::ipa_prf::{
             oprf_padding::PaddingParameters, prf_sharding::attribute_cap_aggregate,
+            AggregationOutputMode, ValueBuckets,
         },
         rand::Rng,
         secret_sharing::{
@@ -1087,8 +1335,11 @@ pub mod tests {
                             ctx,
                             input_rows,
                             None,
+                            None,
+                            32,
                             &histogram,
                             &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::Sum,
                         )
                         .await
                         .unwrap(),
@@ -1107,6 +1358,314 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn malicious_aggregation_value_buckets() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input(123, false, 7, 0),
+                oprf_test_input(123, true, 0, 7),
+                oprf_test_input(123, false, 10, 0),
+                oprf_test_input(123, true, 0, 3),
+                /* Second User */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 5),
+                /* Third User */
+                oprf_test_input(345, false, 10, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, false, 8, 0),
+                oprf_test_input(345, false, 12, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+            ];
+
+            // Buckets [1, 4) and [4, ∞), so breakdown `bk` counts into bins `2 * bk` and
+            // `2 * bk + 1`. The last contribution of the third user is capped to 4.
+            let buckets = ValueBuckets::new(&[4]).unwrap();
+            let mut expected = [0_u128; 32];
+            expected[15] = 1;
+            expected[20] = 1;
+            expected[21] = 1;
+            expected[25] = 5;
+
+            let histogram = [3, 3, 2, 2, 1, 1, 1, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                            ctx,
+                            input_rows,
+                            None,
+                            None,
+                            32,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::ValueBuckets(buckets),
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn malicious_aggregation_attribution_weight() {
+        const PER_USER_CAP: u32 = 16;
+
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input(123, false, 7, 0),
+                oprf_test_input(123, true, 0, 7),
+                oprf_test_input(123, false, 10, 0),
+                oprf_test_input(123, true, 0, 3),
+                /* Second User */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 5),
+                /* Third User */
+                oprf_test_input(345, false, 10, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, false, 8, 0),
+                oprf_test_input(345, false, 12, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+            ];
+
+            // Weighted values round halves up: 7 -> 4, 3 -> 2 and 5 -> 3. The last contribution
+            // of the third user exceeds the cap and is dropped.
+            let weight = AttributionWeight::new(0.5).unwrap();
+            let mut expected = [0_u128; 32];
+            expected[7] = weight.apply(7).into();
+            expected[10] = u128::from(weight.apply(3) + weight.apply(7));
+            expected[12] = u128::from(weight.apply(5) + 3 * weight.apply(7));
+            assert_eq!(&expected[7..=12], &[4, 0, 0, 6, 0, 15]);
+
+            let histogram = [3, 3, 2, 2, 1, 1, 1, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                            ctx,
+                            input_rows,
+                            None,
+                            Some(weight.into()),
+                            PER_USER_CAP,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::Sum,
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn malicious_aggregation_attribution_weights_per_touch() {
+        const PER_USER_CAP: u32 = 16;
+
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User */
+                oprf_test_input(123, false, 7, 0),
+                oprf_test_input(123, true, 0, 7),
+                oprf_test_input(123, false, 10, 0),
+                oprf_test_input(123, true, 0, 6),
+                /* Second User (only the two most recent touches get credit) */
+                oprf_test_input(234, true, 0, 5),
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, false, 3, 0),
+                oprf_test_input(234, false, 4, 0),
+                oprf_test_input(234, true, 0, 7),
+                /* Third User (capped in the middle of a trigger) */
+                oprf_test_input(345, false, 1, 0),
+                oprf_test_input(345, false, 2, 0),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                oprf_test_input(345, true, 0, 7),
+                /* Fourth User (the older touch is outside of the attribution window) */
+                oprf_test_input_with_timestamp(456, false, 5, 0, 0),
+                oprf_test_input_with_timestamp(456, false, 6, 0, 20),
+                oprf_test_input_with_timestamp(456, true, 0, 4, 25),
+            ];
+
+            // The last touch gets half of each value and the one before it a quarter, rounded
+            // half up: 7 -> 4 and 2, 6 -> 3 and 2, 4 -> 2 and 1.
+            let weights = "0.5,0.25".parse::<AttributionWeights>().unwrap();
+            let mut expected = [0_u128; 32];
+            expected[7] = 4 + 2;
+            expected[10] = 3;
+            expected[4] = 4;
+            expected[3] = 2;
+            // 4 + 2 for each of the first two triggers, and 4 more reach the cap.
+            expected[2] = 4 + 4 + 4;
+            expected[1] = 2 + 2;
+            expected[6] = 2;
+
+            let histogram = [4, 4, 4, 3, 2, 1, 1, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                            ctx,
+                            input_rows,
+                            NonZeroU32::new(10),
+                            Some(weights),
+                            PER_USER_CAP,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::Sum,
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn semi_honest_aggregation_capping_not_power_of_two() {
+        const PER_USER_CAP: u32 = 10;
+
+        run(|| async move {
+            let world = TestWorld::default();
+
+            let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                /* First User (exactly reaches the cap) */
+                oprf_test_input(123, false, 17, 0),
+                oprf_test_input(123, true, 0, 7), // remaining cap = 3
+                oprf_test_input(123, false, 20, 0),
+                oprf_test_input(123, true, 0, 3), // remaining cap = 0
+                /* Second User (does not reach the cap) */
+                oprf_test_input(234, false, 12, 0),
+                oprf_test_input(234, true, 0, 5), // remaining cap = 5
+                /* Third User (partially capped, then fully capped) */
+                oprf_test_input(345, false, 20, 0),
+                oprf_test_input(345, true, 0, 7), // remaining cap = 3
+                oprf_test_input(345, false, 18, 0),
+                oprf_test_input(345, false, 12, 0),
+                oprf_test_input(345, true, 0, 7), // capped to 3
+                oprf_test_input(345, true, 0, 7), // capped to 0
+                oprf_test_input(345, true, 0, 7), // capped to 0
+            ];
+
+            let mut expected = [0_u128; 32];
+            expected[12] = 8;
+            expected[17] = 7;
+            expected[20] = 10;
+
+            let histogram = [3, 3, 2, 2, 1, 1, 1];
+
+            let result: [Vec<Replicated<BA16>>; 3] = world
+                .malicious(records.into_iter(), |ctx, input_rows| async move {
+                    Vec::transposed_from(
+                        &attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                            ctx,
+                            input_rows,
+                            None,
+                            None,
+                            PER_USER_CAP,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::Sum,
+                        )
+                        .await
+                        .unwrap(),
+                    )
+                })
+                .await
+                .map(Result::unwrap);
+            let result_reconstructed: Vec<BA16> = result.reconstruct();
+            assert_eq!(
+                result_reconstructed
+                    .iter()
+                    .map(U128Conversions::as_u128)
+                    .collect::<Vec<_>>(),
+                &expected
+            );
+        });
+    }
+
+    #[test]
+    fn attribution_rejects_cap_out_of_range() {
+        run(|| async move {
+            let world = TestWorld::default();
+
+            for per_user_cap in [0, 33] {
+                let records: Vec<PreShardedAndSortedOPRFTestInput<BA5, BA3, BA20>> = vec![
+                    oprf_test_input(123, false, 17, 0),
+                    oprf_test_input(123, true, 0, 7),
+                ];
+                let histogram = [1, 1];
+
+                let results = world
+                    .semi_honest(records.into_iter(), |ctx, input_rows| async move {
+                        attribute_cap_aggregate::<_, BA5, BA3, BA16, BA20, 5, 32>(
+                            ctx,
+                            input_rows,
+                            None,
+                            None,
+                            per_user_cap,
+                            &histogram,
+                            &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::Sum,
+                        )
+                        .await
+                    })
+                    .await;
+                assert!(results.iter().all(Result::is_err));
+            }
+        });
+    }
+
     #[test]
     fn semi_honest_aggregation_capping_attribution_with_attribution_window() {
         const ATTRIBUTION_WINDOW_SECONDS: u32 = 200;
@@ -1148,8 +1707,11 @@ pub mod tests {
                             ctx,
                             input_rows,
                             NonZeroU32::new(ATTRIBUTION_WINDOW_SECONDS),
+                            None,
+                            32,
                             &histogram,
                             &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::Sum,
                         )
                         .await
                         .unwrap(),
@@ -1186,8 +1748,11 @@ pub mod tests {
                         ctx,
                         input_rows,
                         None,
+                        None,
+                        32,
                         histogram_ref,
                         &PaddingParameters::relaxed(),
+                        &AggregationOutputMode::Sum,
                     )
                     .await
                     .unwrap()
@@ -1278,8 +1843,11 @@ pub mod tests {
                             ctx,
                             input_rows,
                             None,
+                            None,
+                            1 << SaturatingSumType::BITS,
                             &HISTOGRAM,
                             &PaddingParameters::relaxed(),
+                            &AggregationOutputMode::Sum,
                         )
                         .await
                         .unwrap(),
@@ -1,9 +1,15 @@
 use ipa_step_derive::CompactStep;
 
 #[derive(CompactStep)]
-#[step(count = 64, child = AttributionPerRowStep, name = "row")]
+#[step(count = 64, child = AttributionTouchStep, name = "row")]
 pub struct UserNthRowStep(usize);
 
+/// One step per touch that a trigger value is split across. The count is
+/// `AttributionWeights::MAX_TOUCHES`.
+#[derive(CompactStep)]
+#[step(count = 4, child = AttributionPerRowStep, name = "touch")]
+pub(crate) struct AttributionTouchStep(usize);
+
 #[derive(CompactStep)]
 pub(crate) enum AttributionStep {
     #[step(child = UserNthRowStep)]
@@ -22,14 +28,14 @@ pub(crate) enum AttributionPerRowStep {
     AttributedBreakdownKey,
     #[step(child = AttributionZeroOutTriggerStep)]
     AttributedTriggerValue,
+    #[step(child = AttributionWeightStep)]
+    ApplyAttributionWeight,
     SourceEventTimestamp,
-    #[step(child = crate::protocol::boolean::step::EightBitStep)]
-    ComputeSaturatingSum,
-    IsSaturatedAndPrevRowNotSaturated,
-    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
     ComputeDifferenceToCap,
-    ComputedCappedAttributedTriggerValueNotSaturatedCase,
-    ComputedCappedAttributedTriggerValueJustSaturatedCase,
+    ComputeCappedAttributedTriggerValue,
+    #[step(child = crate::protocol::boolean::step::SixteenBitStep)]
+    ComputeRemainingCap,
 }
 
 #[derive(CompactStep)]
@@ -40,6 +46,20 @@ pub(crate) enum AttributionZeroOutTriggerStep {
     AttributedEventCheckFlag,
 }
 
+#[derive(CompactStep)]
+pub(crate) enum AttributionWeightStep {
+    #[step(child = AttributionWeightBitStep)]
+    Multiply,
+    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    Round,
+}
+
+/// One step per bit of the weight in the `integer_mul` that applies an attribution weight. The
+/// count is the largest product that `integer_mul` supports.
+#[derive(CompactStep)]
+#[step(count = 32, child = crate::protocol::ipa_prf::boolean_ops::step::MultiplicationPartialProductStep, name = "bit")]
+pub(crate) struct AttributionWeightBitStep(usize);
+
 #[derive(CompactStep)]
 pub(crate) enum AttributionWindowStep {
     #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
@@ -8,12 +8,13 @@ use crate::{
     error::{Error, LengthError, UnwrapInfallible},
     ff::{boolean::Boolean, boolean_array::BooleanArray, Expand},
     helpers::{
+        query::QuerySize,
         stream::{div_round_up, process_stream_by_chunks, ChunkBuffer, TryFlattenItersExt},
         TotalRecords,
     },
     protocol::{
         basics::reveal,
-        boolean::{step::ThirtyTwoBitStep, NBitStep},
+        boolean::{step::SixtyFourBitStep, NBitStep},
         context::{
             dzkp_validator::DZKPValidator, Context, DZKPUpgraded, MaliciousProtocolSteps,
             UpgradableContext,
@@ -97,6 +98,71 @@ where
     }
 }
 
+/// Multiplier used to scatter element positions over the counter space. Any odd value is a
+/// bijection modulo a power of two. Without scattering, a run of equal keys would be ordered by
+/// position, which is the worst case for a first-element pivot.
+const COUNTER_MULTIPLIER: u32 = 0x9E37_79B9;
+
+/// Quicksort using MPC comparisons and a key extraction function `get_key`, with
+/// uniqueness-enforcing keys.
+///
+/// `get_key` takes as input an element in the slice and outputs the key by which we sort by
+/// follows partially the function signature of `sort_by_key`,
+/// see `https://doc.rust-lang.org/src/alloc/slice.rs.html#305-308`.
+///
+/// Set `desc` to `true` for descending ordering.
+///
+/// Every element is assigned a counter that all helpers agree on and that is unique across the
+/// `size` elements of the sort. The counter is appended inside MPC below the least significant
+/// bit of the key, so the composite key occupies `K::BITS + size.counter_bits()` bits. Because
+/// no two composite keys are equal, the number of passes no longer depends on how many of the
+/// keys are equal. Elements with equal keys are ordered by counter, which is derived from the
+/// element's position in `list` on entry.
+///
+/// # Errors
+/// If `list` contains more than `size` elements. Will propagate errors from transport and a few
+/// typecasts.
+///
+/// # Panics
+/// If any of the input ranges are empty, or if the composite key is wider than 64 bits.
+pub async fn quicksort_ranges_by_key<C, K, F, S>(
+    ctx: C,
+    list: &mut [S],
+    desc: bool,
+    get_key: F,
+    ranges_to_sort: Vec<Range<usize>>,
+    size: QuerySize,
+) -> Result<(), Error>
+where
+    C: UpgradableContext,
+    S: Send + Sync,
+    F: Fn(&S) -> &AdditiveShare<K> + Sync + Send + Copy,
+    K: BooleanArray,
+    AdditiveShare<Boolean, SORT_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, SORT_CHUNK>,
+    BitDecomposed<AdditiveShare<Boolean, SORT_CHUNK>>:
+        for<'a> TransposeFrom<&'a [AdditiveShare<K>; SORT_CHUNK], Error = Infallible>,
+{
+    if list.len() > usize::from(size) {
+        return Err(Error::InvalidQueryParameter(
+            format!(
+                "cannot sort {len} elements with unique counters sized for {size} elements",
+                len = list.len(),
+            )
+            .into(),
+        ));
+    }
+
+    quicksort_ranges(
+        ctx,
+        list,
+        desc,
+        get_key,
+        ranges_to_sort,
+        size.counter_bits(),
+    )
+    .await
+}
+
 /// Insecure quicksort using MPC comparisons and a key extraction function `get_key`.
 ///
 /// `get_key` takes as input an element in the slice and outputs the key by which we sort by
@@ -111,7 +177,8 @@ where
 /// it is only expected to run in time `O(n log n)`.
 ///
 /// The leakage can be fixed by appending a counter on each element that is unique to the element.
-/// This adds another `log_2(N)` bits, where `N` is the amount of elements
+/// This adds another `log_2(N)` bits, where `N` is the amount of elements. See
+/// [`quicksort_ranges_by_key`].
 ///
 /// This implementation of quicksort is in place and uses a stack instead of recursion.
 /// It terminates once the stack is empty.
@@ -120,12 +187,13 @@ where
 ///
 /// # Panics
 /// If any of the input ranges are empty
+#[cfg(all(test, unit_test))]
 pub async fn quicksort_ranges_by_key_insecure<C, K, F, S>(
     ctx: C,
     list: &mut [S],
     desc: bool,
     get_key: F,
-    mut ranges_to_sort: Vec<Range<usize>>,
+    ranges_to_sort: Vec<Range<usize>>,
 ) -> Result<(), Error>
 where
     C: UpgradableContext,
@@ -135,6 +203,42 @@ where
     AdditiveShare<Boolean, SORT_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, SORT_CHUNK>,
     BitDecomposed<AdditiveShare<Boolean, SORT_CHUNK>>:
         for<'a> TransposeFrom<&'a [AdditiveShare<K>; SORT_CHUNK], Error = Infallible>,
+{
+    quicksort_ranges(ctx, list, desc, get_key, ranges_to_sort, 0).await
+}
+
+/// Builds the secret sharing of the counters in `ids`, truncated to `bits` bits.
+///
+/// The counters are public, so every helper holds the same value as both of its shares. For
+/// a Boolean sharing this reconstructs to the counter itself.
+fn counter_bits(ids: &[u32], bits: u32) -> BitDecomposed<AdditiveShare<Boolean, SORT_CHUNK>> {
+    BitDecomposed::decompose(bits, |bit| {
+        let lane = |i: usize| Boolean::from(ids.get(i).is_some_and(|id| (id >> bit) & 1 == 1));
+        AdditiveShare::from_fns(lane, lane)
+    })
+}
+
+/// Quicksort implementation shared by [`quicksort_ranges_by_key`] and the insecure variant
+/// used in tests. `suffix_bits` is the width of the per-element
+/// counter appended to each key, or zero to compare keys as they are.
+///
+/// This implementation of quicksort is in place and uses a stack instead of recursion.
+/// It terminates once the stack is empty.
+#[allow(clippy::too_many_lines)]
+async fn quicksort_ranges<C, K, F, S>(
+    ctx: C,
+    list: &mut [S],
+    desc: bool,
+    get_key: F,
+    mut ranges_to_sort: Vec<Range<usize>>,
+    suffix_bits: u32,
+) -> Result<(), Error>
+where
+    C: UpgradableContext,
+    S: Send + Sync,
+    F: Fn(&S) -> &AdditiveShare<K> + Sync + Send + Copy,
+    K: BooleanArray,
+    AdditiveShare<Boolean, SORT_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, SORT_CHUNK>,
     BitDecomposed<AdditiveShare<Boolean, SORT_CHUNK>>:
         for<'a> TransposeFrom<&'a [AdditiveShare<K>; SORT_CHUNK], Error = Infallible>,
 {
@@ -147,6 +251,18 @@ where
         return Ok(());
     }
 
+    assert!(
+        K::BITS + suffix_bits <= SixtyFourBitStep::BITS,
+        "SixtyFourBitStep is not large enough to accommodate this sort"
+    );
+
+    // Counters travel with the elements they were assigned to, so that every comparison
+    // involving an element uses the same composite key.
+    let counter_mask = u32::try_from((1_u64 << suffix_bits) - 1).unwrap();
+    let mut counters = (0..u32::try_from(list.len()).unwrap())
+        .map(|i| i.wrapping_mul(COUNTER_MULTIPLIER) & counter_mask)
+        .collect::<Vec<_>>();
+
     let desc = <Boolean as Vectorizable<SORT_CHUNK>>::Array::expand(&Boolean::from(desc));
     let mut ranges_for_next_pass = Vec::with_capacity(ranges_to_sort.len() * 2);
     let mut quicksort_pass = 1;
@@ -193,10 +309,24 @@ where
                 })
                 .map(Ok);
 
-        assert!(
-            K::BITS <= ThirtyTwoBitStep::BITS,
-            "ThirtyTwoBitStep is not large enough to accommodate this sort"
-        );
+        // Counters of the pivot and of the compared element, in the same order as
+        // `compare_index_pairs`.
+        let (pivot_counters, element_counters): (Vec<_>, Vec<_>) = if suffix_bits > 0 {
+            ranges_to_sort
+                .iter()
+                .filter(|r| r.len() > 1)
+                .flat_map(|range| {
+                    let pivot = counters[range.start];
+                    counters[range.start + 1..range.end]
+                        .iter()
+                        .map(move |&counter| (pivot, counter))
+                })
+                .unzip()
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let (pivot_counters, element_counters) = (&pivot_counters, &element_counters);
+
         let compare_results = seq_join(
             ctx.active_work(),
             process_stream_by_chunks::<_, _, _, _, _, _, SORT_CHUNK>(
@@ -205,9 +335,25 @@ where
                 move |idx, (pivot, k)| {
                     let cmp_ctx = cmp_ctx.clone();
                     let record_id = RecordId::from(idx);
+                    let (pivot, k) = if suffix_bits > 0 {
+                        let lanes = idx * SORT_CHUNK..;
+                        let with_counter = |key: BitDecomposed<_>, counters: &[u32]| {
+                            BitDecomposed::new(
+                                counter_bits(&counters[lanes.clone()], suffix_bits)
+                                    .into_iter()
+                                    .chain(key),
+                            )
+                        };
+                        (
+                            with_counter(pivot, pivot_counters),
+                            with_counter(k, element_counters),
+                        )
+                    } else {
+                        (pivot, k)
+                    };
                     async move {
                         // Compare elements against pivot
-                        compare_gt::<_, ThirtyTwoBitStep, SORT_CHUNK>(
+                        compare_gt::<_, SixtyFourBitStep, SORT_CHUNK>(
                             cmp_ctx, record_id, &k, &pivot,
                         )
                         .await
@@ -247,12 +393,14 @@ where
                 let comparison = comp_it.next().unwrap();
                 if comparison {
                     list.swap(i, n);
+                    counters.swap(i, n);
                     i += 1;
                 }
             }
 
             // swap the pivot element with the last of the elements meant to be left of it
             list.swap(i - 1, pivot_index);
+            counters.swap(i - 1, pivot_index);
 
             // mark which ranges need to be sorted in the next pass
             if i > pivot_index + 2 {
@@ -280,15 +428,20 @@ pub mod tests {
     };
 
     use ipa_step_derive::CompactStep;
-    use rand::Rng;
+    use rand::{seq::index::sample, Rng};
 
     use crate::{
         ff::{boolean_array::BA32, U128Conversions},
-        protocol::{context::Context, ipa_prf::quicksort::quicksort_ranges_by_key_insecure},
+        helpers::query::QuerySize,
+        protocol::{
+            context::Context,
+            ipa_prf::quicksort::{quicksort_ranges_by_key, quicksort_ranges_by_key_insecure},
+        },
         rand::thread_rng,
-        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares, SharedValue},
+        telemetry::metrics::BYTES_SENT,
         test_executor::run,
-        test_fixture::{Reconstruct, Runner, TestWorld},
+        test_fixture::{Reconstruct, Runner, TestWorld, TestWorldConfig},
     };
 
     type TestSortKey = BA32;
@@ -574,6 +727,118 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn test_quicksort_semi_honest_with_duplicates() {
+        run(|| async move {
+            let world = TestWorld::default();
+            let mut rng = thread_rng();
+
+            for desc in [false, true] {
+                // few distinct values, so most keys have duplicates
+                let records: Vec<TestSortKey> = repeat_with(|| rng.gen_range(0..4u128))
+                    .map(TestSortKey::truncate_from)
+                    .take(40)
+                    .collect();
+
+                let mut expected: Vec<u128> =
+                    records.clone().into_iter().map(|x| x.as_u128()).collect();
+                expected.sort_unstable();
+                if desc {
+                    expected.reverse();
+                }
+
+                let result: Vec<_> = world
+                    .semi_honest(records.into_iter(), |ctx, mut r| async move {
+                        let size = QuerySize::try_from(r.len()).unwrap();
+                        #[allow(clippy::single_range_in_vec_init)]
+                        quicksort_ranges_by_key(ctx, &mut r, desc, |x| x, vec![0..40], size)
+                            .await
+                            .unwrap();
+                        r
+                    })
+                    .await
+                    .reconstruct();
+
+                assert_eq!(
+                    result
+                        .into_iter()
+                        .map(|x| x.as_u128())
+                        .collect::<Vec<u128>>(),
+                    expected
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_quicksort_rejects_undersized_counter() {
+        run(|| async move {
+            let records: Vec<TestSortKey> = repeat_with(|| thread_rng().gen()).take(20).collect();
+
+            TestWorld::default()
+                .semi_honest(records.into_iter(), |ctx, mut r| async move {
+                    let size = QuerySize::try_from(r.len() - 1).unwrap();
+                    #[allow(clippy::single_range_in_vec_init)]
+                    let res =
+                        quicksort_ranges_by_key(ctx, &mut r, false, |x| x, vec![0..20], size).await;
+                    assert!(res.is_err());
+                })
+                .await;
+        });
+    }
+
+    /// Sorting a list where every key is equal must cost about the same as sorting a list of
+    /// distinct keys, otherwise the number of passes reveals how many keys are equal. The cost of
+    /// quicksort depends on the order of its input, so the equal keys are compared against
+    /// several lists of random distinct keys rather than expected to match one of them exactly.
+    #[test]
+    fn test_quicksort_equal_running_time_for_equal_and_distinct() {
+        const COUNT: usize = 200;
+        const DISTINCT_RUNS: usize = 5;
+
+        async fn bytes_sent(records: Vec<TestSortKey>) -> u64 {
+            let world = TestWorld::new_with(TestWorldConfig::default().enable_metrics());
+            let result: Vec<_> = world
+                .semi_honest(records.into_iter(), |ctx, mut r| async move {
+                    let size = QuerySize::try_from(r.len()).unwrap();
+                    #[allow(clippy::single_range_in_vec_init)]
+                    quicksort_ranges_by_key(ctx, &mut r, false, |x| x, vec![0..COUNT], size)
+                        .await
+                        .unwrap();
+                    r
+                })
+                .await
+                .reconstruct();
+            assert!(result.windows(2).all(|w| w[0].as_u128() <= w[1].as_u128()));
+
+            world.metrics_snapshot().get_counter(BYTES_SENT)
+        }
+
+        run(|| async move {
+            let mut rng = thread_rng();
+            let equal: Vec<TestSortKey> = repeat(rng.gen()).take(COUNT).collect();
+            let equal_cost = bytes_sent(equal).await;
+
+            let mut distinct_costs = Vec::with_capacity(DISTINCT_RUNS);
+            for _ in 0..DISTINCT_RUNS {
+                let distinct: Vec<TestSortKey> = sample(&mut rng, 1 << TestSortKey::BITS, COUNT)
+                    .into_iter()
+                    .map(|key| TestSortKey::truncate_from(u128::try_from(key).unwrap()))
+                    .collect();
+                distinct_costs.push(bytes_sent(distinct).await);
+            }
+
+            // Without unique counters, equal keys cost `O(n^2)` comparisons instead of
+            // `O(n log n)`, which is an order of magnitude more for this many keys.
+            let min = *distinct_costs.iter().min().unwrap();
+            let max = *distinct_costs.iter().max().unwrap();
+            assert!(
+                2 * equal_cost >= min && equal_cost <= 2 * max,
+                "sorting equal keys sent {equal_cost} bytes, distinct keys sent {distinct_costs:?}"
+            );
+        });
+    }
+
     #[derive(Clone, Copy, Debug)]
     struct SillyStruct {
         timestamp: TestSortKey,
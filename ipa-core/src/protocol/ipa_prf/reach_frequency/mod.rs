@@ -0,0 +1,727 @@
+use std::{
+    cmp::{min, Reverse},
+    convert::Infallible,
+    ops::Not,
+};
+
+use futures::{future::try_join, stream, Stream, TryStreamExt};
+
+use self::step::{ReachFrequencyStep as Step, ReachNthRowStep, ReachPerRowStep as PerRowStep};
+use crate::{
+    error::{Error, LengthError},
+    ff::{
+        boolean::Boolean,
+        boolean_array::{BooleanArray, BA32},
+        curve_points::RP25519,
+        ec_prime_field::Fp25519,
+        ArrayAccess, Field, U128Conversions,
+    },
+    helpers::{
+        query::{DpMechanism, QuerySize},
+        stream::TryFlattenItersExt,
+        TotalRecords,
+    },
+    protocol::{
+        basics::{BooleanProtocols, Reveal, SecureMul},
+        boolean::{step::EightBitStep, NBitStep},
+        context::{
+            dzkp_validator::{DZKPValidator, TARGET_PROOF_SIZE},
+            Context, DZKPUpgraded, MacUpgraded, MaliciousProtocolSteps, UpgradableContext,
+        },
+        dp::dp_for_histogram,
+        ipa_prf::{
+            aggregation::{
+                aggregate_values_proof_chunk, breakdown_reveal::breakdown_reveal_aggregation,
+                AggregationOutputMode,
+            },
+            boolean_ops::{
+                comparison_and_subtraction_sequential::compare_gt, expand_shared_array_in_place,
+            },
+            compute_prf_for_inputs,
+            oprf_padding::{apply_dp_padding, PaddingParameters},
+            prf_eval::PrfSharing,
+            prf_sharding::{
+                histograms_ranges_sortkeys, AttributionOutputs, PrfShardedIpaInputRow,
+                SecretSharedAttributionOutputs,
+            },
+            quicksort::quicksort_ranges_by_key,
+            shuffle::{shuffle_inputs, Shuffle},
+            step::IpaPrfStep,
+            BreakdownKey, OPRFIPAInputRow, AGG_CHUNK, CONV_CHUNK, PRF_CHUNK, SORT_CHUNK,
+        },
+        prss::FromPrss,
+        RecordId,
+    },
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        BitDecomposed, FieldSimd, SharedValue, TransposeFrom, Vectorizable,
+    },
+};
+
+pub(crate) mod step;
+
+/// Largest supported `max_frequency`.
+pub const MAX_FREQUENCY: u32 = 32;
+
+/// Largest supported `max_breakdowns_per_user`.
+pub const MAX_BREAKDOWNS_PER_USER: u32 = 8;
+
+/// Reach and frequency protocol.
+///
+/// Counts the distinct users exposed to each breakdown, and how many impressions (source events)
+/// each user saw. The output histogram has `B` bins:
+/// * bins `0..max_breakdown_key` hold the reach of each breakdown, i.e. the number of users with
+///   at least one impression on it. Impressions on breakdowns `>= max_breakdown_key` are ignored.
+///   Each user counts toward the reach of at most `max_breakdowns_per_user` breakdowns, the
+///   smallest ones they were exposed to.
+/// * bins `max_breakdown_key..max_breakdown_key + max_frequency` hold the frequency histogram.
+///   Bin `max_breakdown_key + f - 1` counts users with exactly `f` impressions, except the last
+///   one, which counts users with `max_frequency` or more.
+/// * the remaining bins are zero before noise is added.
+///
+/// Trigger events are accepted in the input, but do not contribute to either histogram.
+///
+/// Users are pseudonymised and grouped exactly as in [`oprf_ipa`]. Each user's rows are then
+/// sorted by breakdown key rather than by timestamp, so that a user's first impression on a
+/// breakdown can be found by comparing adjacent rows. Reach and frequency are bounded
+/// separately: a user changes at most `max_breakdowns_per_user` reach bins and one frequency bin
+/// by one, so `max_breakdowns_per_user + 1` is the sensitivity used for differential privacy.
+///
+/// [`oprf_ipa`]: crate::protocol::ipa_prf::oprf_ipa
+///
+/// # Errors
+/// If `max_frequency` is not between 1 and [`MAX_FREQUENCY`], if `max_breakdowns_per_user` is not
+/// between 1 and [`MAX_BREAKDOWNS_PER_USER`], if `max_breakdown_key` is zero or if the reach and
+/// frequency bins do not fit in `B`.
+/// Propagates errors from running the protocol.
+/// # Panics
+/// If `BK` is wider than 8 bits, or if a user has more than 64 rows.
+pub async fn oprf_reach_frequency<'ctx, C, BK, TV, HV, TS, const B: usize>(
+    ctx: C,
+    input_rows: Vec<OPRFIPAInputRow<BK, TV, TS>>,
+    max_breakdown_key: u32,
+    max_frequency: u32,
+    max_breakdowns_per_user: u32,
+    dp_params: DpMechanism,
+    dp_padding_params: PaddingParameters,
+) -> Result<Vec<Replicated<HV>>, Error>
+where
+    C: UpgradableContext + 'ctx + Shuffle,
+    BK: BreakdownKey<B>,
+    TV: BooleanArray + U128Conversions,
+    HV: BooleanArray + U128Conversions,
+    TS: BooleanArray + U128Conversions,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    Replicated<Boolean, AGG_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, AGG_CHUNK>,
+    Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
+    Replicated<Boolean, SORT_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, SORT_CHUNK>,
+    Replicated<Fp25519, PRF_CHUNK>:
+        PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
+    Replicated<RP25519, PRF_CHUNK>:
+        Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
+    Vec<Replicated<HV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, B>>, Error = LengthError>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<HV>; B], Error = Infallible>,
+{
+    assert!(
+        BK::BITS <= EightBitStep::BITS,
+        "EightBitStep is not large enough to accommodate breakdown key comparisons"
+    );
+    if max_frequency == 0 || max_frequency > MAX_FREQUENCY {
+        return Err(Error::InvalidQueryParameter(
+            format!("max frequency {max_frequency} must be between 1 and {MAX_FREQUENCY}").into(),
+        ));
+    }
+    if max_breakdowns_per_user == 0 || max_breakdowns_per_user > MAX_BREAKDOWNS_PER_USER {
+        return Err(Error::InvalidQueryParameter(
+            format!(
+                "max breakdowns per user {max_breakdowns_per_user} must be between 1 and \
+                {MAX_BREAKDOWNS_PER_USER}"
+            )
+            .into(),
+        ));
+    }
+    if max_breakdown_key == 0
+        || u64::from(max_breakdown_key) + u64::from(max_frequency) > u64::try_from(B).unwrap()
+    {
+        return Err(Error::InvalidQueryParameter(
+            format!(
+                "{max_breakdown_key} reach bins and {max_frequency} frequency bins do not fit in \
+                a histogram of size {B}"
+            )
+            .into(),
+        ));
+    }
+    if input_rows.is_empty() {
+        return Ok(vec![Replicated::ZERO; B]);
+    }
+
+    // Dummy rows added by padding look like source events with breakdown key 0. Flipping the
+    // event type bit of the real rows beforehand turns it into an "is a real impression" flag,
+    // which is zero for every dummy row.
+    let input_rows = input_rows
+        .into_iter()
+        .map(|mut row| {
+            row.is_trigger = row.is_trigger.not();
+            row
+        })
+        .collect::<Vec<_>>();
+    let padded_input_rows = apply_dp_padding::<_, OPRFIPAInputRow<BK, TV, TS>, B>(
+        ctx.narrow(&IpaPrfStep::PaddingDp),
+        input_rows,
+        &dp_padding_params,
+    )
+    .await?;
+
+    let shuffled = shuffle_inputs(ctx.narrow(&IpaPrfStep::Shuffle), padded_input_rows).await?;
+    let mut prfd_inputs = compute_prf_for_inputs(ctx.clone(), &shuffled).await?;
+
+    prfd_inputs.sort_by_key(|row| row.prf_of_match_key);
+
+    let (row_count_histogram, ranges) = histograms_ranges_sortkeys(&mut prfd_inputs);
+    for row in &mut prfd_inputs {
+        row.sort_key = breakdown_sort_key(row);
+    }
+    let sort_size = QuerySize::try_from(prfd_inputs.len())
+        .map_err(|e| Error::InvalidQueryParameter(e.into()))?;
+    let rf_ctx = ctx.narrow(&IpaPrfStep::ReachFrequency);
+    quicksort_ranges_by_key(
+        rf_ctx.narrow(&Step::SortByBreakdownKey),
+        &mut prfd_inputs,
+        false,
+        |x| &x.sort_key,
+        ranges.clone(),
+        sort_size,
+    )
+    .await?;
+
+    // Sorting only permutes rows within each user's range.
+    let mut rows = prfd_inputs.into_iter();
+    let rows_by_user = ranges
+        .iter()
+        .map(|range| rows.by_ref().take(range.len()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let output_histogram = count_reach_frequency::<_, _, _, HV, _, B>(
+        rf_ctx,
+        rows_by_user,
+        &row_count_histogram,
+        max_breakdown_key,
+        usize::try_from(max_frequency).unwrap(),
+        usize::try_from(max_breakdowns_per_user).unwrap(),
+        &dp_padding_params,
+    )
+    .await?;
+
+    dp_for_histogram::<_, B, HV>(
+        ctx,
+        output_histogram,
+        dp_params,
+        max_breakdowns_per_user + 1,
+    )
+    .await
+}
+
+/// Sort key that puts a user's impressions first, in order of breakdown key, and trigger events
+/// last. `quicksort_ranges_by_key` appends a counter to make the keys unique.
+fn breakdown_sort_key<BK, TV, TS>(row: &PrfShardedIpaInputRow<BK, TV, TS>) -> Replicated<BA32>
+where
+    BK: BooleanArray,
+    TV: SharedValue,
+    TS: SharedValue,
+{
+    let mut sort_key = Replicated::<BA32>::ZERO;
+    expand_shared_array_in_place(&mut sort_key, &row.breakdown_key, 0);
+    // `is_trigger_bit` holds the "is a real impression" flag at this point.
+    sort_key.set(
+        usize::try_from(BK::BITS).unwrap(),
+        row.is_trigger_bit.clone().not(),
+    );
+    sort_key
+}
+
+/// Returns an upper bound on the number of Boolean multiplications for each row of a user, in
+/// `evaluate_per_user_reach_frequency_circuit`.
+fn multiplications_per_row<BK: SharedValue>(max_breakdowns_per_user: usize) -> usize {
+    // is_breakdown_in_range
+    // is_new_breakdown
+    2 * usize::try_from(BK::BITS).unwrap() +
+    // is_counted_breakdown
+    // is_new_impression
+    // is_reached
+    3 +
+    // breakdowns_reached
+    max_breakdowns_per_user
+}
+
+/// Evaluates the per-user circuit for every user and aggregates the resulting indicators into
+/// the reach and frequency histogram.
+#[tracing::instrument(name = "count_reach_frequency", skip_all, fields(users = rows_by_user.len()))]
+async fn count_reach_frequency<'ctx, C, BK, TV, HV, TS, const B: usize>(
+    sh_ctx: C,
+    mut rows_by_user: Vec<Vec<PrfShardedIpaInputRow<BK, TV, TS>>>,
+    histogram: &[usize],
+    max_breakdown_key: u32,
+    max_frequency: usize,
+    max_breakdowns_per_user: usize,
+    padding_parameters: &PaddingParameters,
+) -> Result<BitDecomposed<Replicated<Boolean, B>>, Error>
+where
+    C: UpgradableContext + 'ctx,
+    BK: BreakdownKey<B>,
+    TV: BooleanArray + U128Conversions,
+    HV: BooleanArray + U128Conversions,
+    TS: BooleanArray,
+    Boolean: FieldSimd<B>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean, B>: BooleanProtocols<DZKPUpgraded<C>, B>,
+    BitDecomposed<Replicated<Boolean, B>>:
+        for<'a> TransposeFrom<&'a [Replicated<TV>; B], Error = Infallible>,
+{
+    // Record IDs count users.
+    let chunk_size = TARGET_PROOF_SIZE
+        / (histogram.len() * multiplications_per_row::<BK>(max_breakdowns_per_user));
+
+    let mut dzkp_validator = sh_ctx.clone().dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &Step::CountUsers,
+            validate: &Step::CountUsersValidate,
+        },
+        min(sh_ctx.active_work().get(), chunk_size.next_power_of_two()),
+    );
+    dzkp_validator.set_total_records(TotalRecords::specified(histogram[0])?);
+    let ctx = dzkp_validator.context();
+    let ctx_for_row_number = histogram
+        .iter()
+        .enumerate()
+        .map(|(row_number, &num_users_having_that_row_number)| {
+            Ok(ctx
+                .narrow(&ReachNthRowStep::from(row_number))
+                .set_total_records(TotalRecords::specified(num_users_having_that_row_number)?))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    // The number of users having row `n` is the number of record IDs at that row depth, so users
+    // with more rows must come first.
+    rows_by_user.sort_by_key(|rows| Reverse(rows.len()));
+    let user_contributions = count_users(
+        dzkp_validator,
+        ctx_for_row_number,
+        rows_by_user,
+        max_breakdown_key,
+        max_frequency,
+        max_breakdowns_per_user,
+    )
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let validator = sh_ctx.dzkp_validator(
+        MaliciousProtocolSteps {
+            protocol: &Step::Aggregate,
+            validate: &Step::AggregateValidate,
+        },
+        aggregate_values_proof_chunk(B, usize::try_from(TV::BITS).unwrap()).next_power_of_two(),
+    );
+    let result = breakdown_reveal_aggregation::<_, _, _, HV, B>(
+        validator.context(),
+        user_contributions,
+        padding_parameters,
+        &AggregationOutputMode::Sum,
+    )
+    .await;
+    validator.validate().await?;
+    result
+}
+
+/// Evaluates the per-user circuit for every user, validating the multiplications in chunks.
+///
+/// `contexts` holds one context per row number.
+fn count_users<'ctx, V, BK, TV, TS>(
+    dzkp_validator: V,
+    contexts: Vec<V::Context>,
+    rows_by_user: Vec<Vec<PrfShardedIpaInputRow<BK, TV, TS>>>,
+    max_breakdown_key: u32,
+    max_frequency: usize,
+    max_breakdowns_per_user: usize,
+) -> impl Stream<Item = Result<SecretSharedAttributionOutputs<BK, TV>, Error>> + Send + 'ctx
+where
+    V: DZKPValidator + 'ctx,
+    Replicated<Boolean>: BooleanProtocols<V::Context>,
+    BK: BooleanArray + U128Conversions,
+    TV: BooleanArray,
+    TS: BooleanArray,
+{
+    let per_user_results =
+        rows_by_user
+            .into_iter()
+            .enumerate()
+            .map(move |(record_id, rows_for_user)| {
+                let contexts = contexts[..rows_for_user.len()].to_owned();
+                evaluate_per_user_reach_frequency_circuit(
+                    contexts,
+                    RecordId::from(record_id),
+                    rows_for_user,
+                    max_breakdown_key,
+                    max_frequency,
+                    max_breakdowns_per_user,
+                )
+            });
+
+    dzkp_validator
+        .validated_seq_join::<_, _, Vec<SecretSharedAttributionOutputs<BK, TV>>>(stream::iter(
+            per_user_results,
+        ))
+        .try_flatten_iters()
+}
+
+/// Decides whether a new breakdown seen at row `row_number` of a user is reached, and adds it to the
+/// count of breakdowns the user reached so far.
+///
+/// `breakdowns_reached` holds the count in unary: bit `k` is set if at least `k + 1` breakdowns were
+/// reached. Fewer than `row_number` breakdowns can have been reached before this row, so the count
+/// only needs to be checked from row `breakdowns_reached.len()` on, and only its first
+/// `row_number + 1` bits can change.
+async fn count_reached_breakdown<C>(
+    ctx: &C,
+    record_id: RecordId,
+    row_number: usize,
+    is_new_breakdown: Replicated<Boolean>,
+    breakdowns_reached: &mut [Replicated<Boolean>],
+) -> Result<Replicated<Boolean>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+{
+    let max_breakdowns_per_user = breakdowns_reached.len();
+    let one = Replicated::new(Boolean::ONE, Boolean::ONE);
+    let is_reached = async {
+        if row_number < max_breakdowns_per_user {
+            Ok(is_new_breakdown.clone())
+        } else {
+            is_new_breakdown
+                .multiply(
+                    &(breakdowns_reached[max_breakdowns_per_user - 1].clone() + &one),
+                    ctx.narrow(&PerRowStep::Reached),
+                    record_id,
+                )
+                .await
+        }
+    };
+    let count_ctx = ctx.narrow(&PerRowStep::BreakdownsReached);
+    let increments = (0..min(row_number + 1, max_breakdowns_per_user)).map(|k| {
+        // Bit `k` is set by this row if the count was exactly `k`.
+        let was_exactly_k = match k {
+            0 => breakdowns_reached[0].clone() + &one,
+            _ => breakdowns_reached[k - 1].clone() + &breakdowns_reached[k],
+        };
+        let ctx = count_ctx.narrow(&EightBitStep::from(k));
+        let is_new_breakdown = &is_new_breakdown;
+        async move {
+            is_new_breakdown
+                .multiply(&was_exactly_k, ctx, record_id)
+                .await
+        }
+    });
+    let (is_reached, increments) =
+        try_join(is_reached, count_ctx.parallel_join(increments)).await?;
+    for (bit, increment) in breakdowns_reached.iter_mut().zip(increments) {
+        *bit += &increment;
+    }
+
+    Ok(is_reached)
+}
+
+/// Computes the contributions of one user to the reach and frequency histogram.
+///
+/// The user's rows must be sorted with [`breakdown_sort_key`], so that all impressions come first,
+/// ordered by breakdown key. Each contribution is a histogram bin and a secret-shared 0 or 1 in
+/// the least significant bit of a `TV`:
+/// * row `i` is a new breakdown if it is an impression, its breakdown is below
+///   `max_breakdown_key`, and it is the first row or its breakdown is greater than the breakdown
+///   of row `i - 1`. It contributes to the reach of its breakdown if fewer than
+///   `max_breakdowns_per_user` earlier rows were new breakdowns. That count is kept in unary, as
+///   one bit per possible value that is set if at least that many breakdowns were reached.
+/// * frequency `f` is indicated by row `f - 1` being an impression and row `f` not being one.
+///   Both the bin and the rows involved are public, so no multiplications are needed.
+#[tracing::instrument(level = "debug", name = "per_user", skip_all, fields(rows = rows_for_user.len()))]
+async fn evaluate_per_user_reach_frequency_circuit<C, BK, TV, TS>(
+    ctx_for_row_number: Vec<C>,
+    record_id: RecordId,
+    rows_for_user: Vec<PrfShardedIpaInputRow<BK, TV, TS>>,
+    max_breakdown_key: u32,
+    max_frequency: usize,
+    max_breakdowns_per_user: usize,
+) -> Result<Vec<SecretSharedAttributionOutputs<BK, TV>>, Error>
+where
+    C: Context,
+    Replicated<Boolean>: BooleanProtocols<C>,
+    BK: BooleanArray + U128Conversions,
+    TV: BooleanArray,
+    TS: BooleanArray,
+{
+    let counted_rows = min(rows_for_user.len(), max_frequency);
+    // The bins are public, so every helper can hold the same value in both halves of its share.
+    let max_breakdown_key_bits = BitDecomposed::decompose(BK::BITS, |i| {
+        let bit = Boolean::truncate_from((max_breakdown_key >> i) & 0x1);
+        Replicated::new(bit, bit)
+    });
+    let public_breakdown = |value: u32| {
+        let value = BK::truncate_from(value);
+        Replicated::new(value, value)
+    };
+    let indicator = |bit: Replicated<Boolean>| {
+        let mut value = Replicated::<TV>::ZERO;
+        value.set(0, bit);
+        value
+    };
+    let mut output = Vec::with_capacity(rows_for_user.len() + counted_rows);
+    let mut prev_breakdown_key_bits = None;
+    // `breakdowns_reached[k]` is set if at least `k + 1` breakdowns were reached so far.
+    let mut breakdowns_reached = vec![Replicated::<Boolean>::ZERO; max_breakdowns_per_user];
+    for (i, (row, ctx)) in rows_for_user.iter().zip(ctx_for_row_number).enumerate() {
+        // `is_trigger_bit` holds the "is a real impression" flag.
+        let is_impression = &row.is_trigger_bit;
+        let breakdown_key_bits = row.breakdown_key.to_bits();
+        let is_breakdown_in_range = compare_gt::<_, EightBitStep, 1>(
+            ctx.narrow(&PerRowStep::BreakdownInRange),
+            record_id,
+            &max_breakdown_key_bits,
+            &breakdown_key_bits,
+        );
+        let is_counted_breakdown = match prev_breakdown_key_bits {
+            None => is_breakdown_in_range.await?,
+            Some(ref prev_breakdown_key_bits) => {
+                let (is_breakdown_in_range, is_new_breakdown) = try_join(
+                    is_breakdown_in_range,
+                    compare_gt::<_, EightBitStep, 1>(
+                        ctx.narrow(&PerRowStep::NewBreakdown),
+                        record_id,
+                        &breakdown_key_bits,
+                        prev_breakdown_key_bits,
+                    ),
+                )
+                .await?;
+                is_breakdown_in_range
+                    .multiply(
+                        &is_new_breakdown,
+                        ctx.narrow(&PerRowStep::CountedBreakdown),
+                        record_id,
+                    )
+                    .await?
+            }
+        };
+        let is_new_impression = is_counted_breakdown
+            .multiply(
+                is_impression,
+                ctx.narrow(&PerRowStep::NewImpression),
+                record_id,
+            )
+            .await?;
+
+        let is_reached = count_reached_breakdown(
+            &ctx,
+            record_id,
+            i,
+            is_new_impression,
+            &mut breakdowns_reached,
+        )
+        .await?;
+
+        output.push(AttributionOutputs {
+            attributed_breakdown_key_bits: row.breakdown_key.clone(),
+            capped_attributed_trigger_value: indicator(is_reached),
+        });
+        prev_breakdown_key_bits = Some(breakdown_key_bits);
+    }
+
+    for frequency in 1..=counted_rows {
+        // Impressions come first, so the user saw exactly `frequency` impressions if and only if
+        // the impression flag changes between these two rows.
+        let mut has_frequency = rows_for_user[frequency - 1].is_trigger_bit.clone();
+        if frequency < max_frequency {
+            if let Some(next_row) = rows_for_user.get(frequency) {
+                has_frequency += &next_row.is_trigger_bit;
+            }
+        }
+        output.push(AttributionOutputs {
+            attributed_breakdown_key_bits: public_breakdown(
+                max_breakdown_key + u32::try_from(frequency).unwrap() - 1,
+            ),
+            capped_attributed_trigger_value: indicator(has_frequency),
+        });
+    }
+
+    Ok(output)
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use crate::{
+        ff::{
+            boolean_array::{BA16, BA20, BA3, BA5},
+            U128Conversions,
+        },
+        helpers::query::DpMechanism,
+        protocol::ipa_prf::{
+            oprf_padding::PaddingParameters, reach_frequency::oprf_reach_frequency,
+        },
+        test_executor::run,
+        test_fixture::{ipa::TestRawDataRecord, Reconstruct, Runner, TestWorld},
+    };
+
+    fn impression(user_id: u64, breakdown_key: u32) -> TestRawDataRecord {
+        TestRawDataRecord {
+            timestamp: 0,
+            user_id,
+            is_trigger_report: false,
+            breakdown_key,
+            trigger_value: 0,
+        }
+    }
+
+    fn conversion(user_id: u64, trigger_value: u32) -> TestRawDataRecord {
+        TestRawDataRecord {
+            timestamp: 10,
+            user_id,
+            is_trigger_report: true,
+            breakdown_key: 0,
+            trigger_value,
+        }
+    }
+
+    const MAX_BREAKDOWN_KEY: u32 = 4;
+    const MAX_FREQUENCY: u32 = 3;
+    const MAX_BREAKDOWNS_PER_USER: u32 = 3;
+
+    /// Reach of breakdowns 0 to 3, followed by the number of users with 1, 2 and 3+ impressions.
+    const EXPECTED: &[u128] = &[2, 3, 4, 1, 1, 1, 3, 0];
+
+    fn test_records() -> Vec<TestRawDataRecord> {
+        vec![
+            // Reaches breakdowns 1 and 2 with three impressions.
+            impression(1, 1),
+            conversion(1, 5),
+            impression(1, 2),
+            impression(1, 1),
+            // Reaches breakdown 1 with one impression.
+            impression(2, 1),
+            // Has no impressions.
+            conversion(3, 2),
+            // Five impressions on three breakdowns. All three are reached, even though the
+            // frequency is clipped at three.
+            impression(4, 0),
+            impression(4, 3),
+            impression(4, 3),
+            impression(4, 2),
+            impression(4, 0),
+            // Breakdown 7 is out of range, but the impression still counts toward frequency.
+            impression(5, 7),
+            impression(5, 2),
+            // Four breakdowns, only the three smallest are reached.
+            impression(6, 3),
+            impression(6, 2),
+            impression(6, 1),
+            impression(6, 0),
+        ]
+    }
+
+    #[test]
+    fn semi_honest() {
+        run(|| async {
+            let world = TestWorld::default();
+
+            let mut result: Vec<_> = world
+                .semi_honest(test_records().into_iter(), |ctx, input_rows| async move {
+                    oprf_reach_frequency::<_, BA5, BA3, BA16, BA20, 32>(
+                        ctx,
+                        input_rows,
+                        MAX_BREAKDOWN_KEY,
+                        MAX_FREQUENCY,
+                        MAX_BREAKDOWNS_PER_USER,
+                        DpMechanism::NoDp,
+                        // Dummy users added by padding must not be counted.
+                        PaddingParameters::relaxed(),
+                    )
+                    .await
+                    .unwrap()
+                })
+                .await
+                .reconstruct();
+            result.truncate(EXPECTED.len());
+            assert_eq!(
+                result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>(),
+                EXPECTED,
+            );
+        });
+    }
+
+    #[test]
+    fn malicious() {
+        run(|| async {
+            let world = TestWorld::default();
+
+            let mut result: Vec<_> = world
+                .malicious(test_records().into_iter(), |ctx, input_rows| async move {
+                    oprf_reach_frequency::<_, BA5, BA3, BA16, BA20, 32>(
+                        ctx,
+                        input_rows,
+                        MAX_BREAKDOWN_KEY,
+                        MAX_FREQUENCY,
+                        MAX_BREAKDOWNS_PER_USER,
+                        DpMechanism::NoDp,
+                        PaddingParameters::no_padding(),
+                    )
+                    .await
+                    .unwrap()
+                })
+                .await
+                .reconstruct();
+            result.truncate(EXPECTED.len());
+            assert_eq!(
+                result.iter().map(|&v| v.as_u128()).collect::<Vec<_>>(),
+                EXPECTED,
+            );
+        });
+    }
+
+    #[test]
+    fn rejects_invalid_bins() {
+        run(|| async {
+            let world = TestWorld::default();
+
+            for (max_breakdown_key, max_frequency, max_breakdowns_per_user) in [
+                (4, 0, 3),
+                (4, 33, 3),
+                (0, 3, 3),
+                (30, 3, 3),
+                (4, 3, 0),
+                (4, 3, 9),
+            ] {
+                let results = world
+                    .semi_honest(test_records().into_iter(), |ctx, input_rows| async move {
+                        oprf_reach_frequency::<_, BA5, BA3, BA16, BA20, 32>(
+                            ctx,
+                            input_rows,
+                            max_breakdown_key,
+                            max_frequency,
+                            max_breakdowns_per_user,
+                            DpMechanism::NoDp,
+                            PaddingParameters::no_padding(),
+                        )
+                        .await
+                    })
+                    .await;
+                assert!(
+                    results.iter().all(Result::is_err),
+                    "{max_breakdown_key} breakdowns, max frequency {max_frequency} and \
+                    {max_breakdowns_per_user} breakdowns per user should be rejected"
+                );
+            }
+        });
+    }
+}
@@ -0,0 +1,33 @@
+use ipa_step_derive::CompactStep;
+
+#[derive(CompactStep)]
+pub(crate) enum ReachFrequencyStep {
+    #[step(child = crate::protocol::ipa_prf::step::QuicksortStep)]
+    SortByBreakdownKey,
+    #[step(child = ReachNthRowStep)]
+    CountUsers,
+    #[step(child = crate::protocol::context::step::DzkpBatchStep)]
+    CountUsersValidate,
+    #[step(child = crate::protocol::ipa_prf::aggregation::step::AggregationStep)]
+    Aggregate,
+    #[step(child = crate::protocol::context::step::DzkpSingleBatchStep)]
+    AggregateValidate,
+}
+
+/// Users may have as many rows as in attribution.
+#[derive(CompactStep)]
+#[step(count = 64, child = ReachPerRowStep, name = "row")]
+pub(crate) struct ReachNthRowStep(usize);
+
+#[derive(CompactStep)]
+pub(crate) enum ReachPerRowStep {
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    BreakdownInRange,
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    NewBreakdown,
+    CountedBreakdown,
+    NewImpression,
+    Reached,
+    #[step(child = crate::protocol::boolean::step::EightBitStep)]
+    BreakdownsReached,
+}
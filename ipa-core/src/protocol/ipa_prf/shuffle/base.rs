@@ -62,9 +62,9 @@ where
     let zs = generate_random_tables_with_peers(shares_len, &ctx_z);
 
     match ctx.role() {
-        Role::H1 => Box::pin(run_h1(&ctx, shares_len, shares, zs)).await,
-        Role::H2 => Box::pin(run_h2(&ctx, shares_len, shares, zs)).await,
-        Role::H3 => Box::pin(run_h3(&ctx, shares_len, zs)).await,
+        Role::H1 => Box::pin(run_h1::<_, _, S, _, _>(&ctx, shares_len, shares, zs)).await,
+        Role::H2 => Box::pin(run_h2::<_, _, S, _, _>(&ctx, shares_len, shares, zs)).await,
+        Role::H3 => Box::pin(run_h3::<_, S, _, _>(&ctx, shares_len, zs)).await,
     }
 }
 
@@ -468,7 +468,10 @@ pub mod tests {
         // Stable seed is used to get predictable shuffle results.
         let mut actual = TestWorld::new_with(TestWorldConfig::default().with_seed(123))
             .semi_honest(records.clone().into_iter(), |ctx, shares| async move {
-                shuffle_protocol(ctx, shares).await.unwrap().0
+                shuffle_protocol::<_, _, MatchKey>(ctx, shares)
+                    .await
+                    .unwrap()
+                    .0
             })
             .await
             .reconstruct();
@@ -499,7 +502,7 @@ pub mod tests {
 
             let [h1, h2, h3] = world
                 .semi_honest(records.clone().into_iter(), |ctx, records| async move {
-                    shuffle_protocol(ctx, records).await
+                    shuffle_protocol::<_, _, Gf40Bit>(ctx, records).await
                 })
                 .await;
 
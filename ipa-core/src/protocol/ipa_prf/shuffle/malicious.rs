@@ -591,7 +591,9 @@ mod tests {
                     let key_shares = vec![AdditiveShare::new(Gf32Bit::ONE, Gf32Bit::ONE); 1];
                     // run shuffle
                     let (shares, messages) =
-                        shuffle_protocol(ctx.narrow("shuffle"), rows).await.unwrap();
+                        shuffle_protocol::<_, _, BA64>(ctx.narrow("shuffle"), rows)
+                            .await
+                            .unwrap();
                     // verify it
                     verify_shuffle::<_, BA32, BA64>(
                         ctx.narrow("verify"),
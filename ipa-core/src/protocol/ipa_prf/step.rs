@@ -17,6 +17,8 @@ pub(crate) enum IpaPrfStep {
     SortByTimestamp,
     #[step(child = crate::protocol::ipa_prf::prf_sharding::step::AttributionStep)]
     Attribution,
+    #[step(child = crate::protocol::ipa_prf::reach_frequency::step::ReachFrequencyStep)]
+    ReachFrequency,
     #[step(child = crate::protocol::dp::step::DPStep, name = "dp")]
     DifferentialPrivacy,
     #[step(child = crate::protocol::context::step::DzkpSingleBatchStep)]
@@ -34,7 +36,7 @@ pub(crate) enum QuicksortStep {
 
 #[derive(CompactStep)]
 pub(crate) enum QuicksortPassStep {
-    #[step(child = crate::protocol::boolean::step::ThirtyTwoBitStep)]
+    #[step(child = crate::protocol::boolean::step::SixtyFourBitStep)]
     Compare,
     Reveal,
 }
@@ -35,8 +35,6 @@ pub enum DeadCodeStep {
     SaturatedSubtraction,
     #[step(child = crate::protocol::ipa_prf::prf_sharding::step::FeatureLabelDotProductStep)]
     FeatureLabelDotProduct,
-    #[step(child = crate::protocol::ipa_prf::boolean_ops::step::MultiplicationStep)]
-    Multiplication,
 }
 
 /// Provides a unique per-iteration context in tests.
@@ -44,7 +44,7 @@ use crate::{
         Gate,
     },
     query::{
-        runner::{HybridQuery, OprfIpaQuery, QueryResult},
+        runner::{oprf_ipa_dry_run, HybridQuery, OprfIpaQuery, QueryResult, ReachFrequencyQuery},
         state::RunningQuery,
     },
     sync::Arc,
@@ -117,6 +117,27 @@ pub fn execute<R: PrivateKeyRegistry>(
                 ))
             })
         }
+        (QueryType::SemiHonestOprfIpa(ipa_config) | QueryType::MaliciousOprfIpa(ipa_config), _)
+            if ipa_config.dry_run =>
+        {
+            do_query(
+                config,
+                gateway,
+                input,
+                move |_prss, _gateway, _config, input| {
+                    Box::pin(async move {
+                        oprf_ipa_dry_run(
+                            key_registry.as_ref(),
+                            ipa_config.trigger_value_scale,
+                            ipa_config.legacy_reports,
+                            input,
+                        )
+                        .await
+                        .map(|summary| Box::new(summary) as Box<dyn Result>)
+                    })
+                },
+            )
+        }
         // TODO(953): This is really using BA32, not Fp32bitPrime. The `FieldType` mechanism needs
         // to be reworked.
         (QueryType::SemiHonestOprfIpa(ipa_config), _) => do_query(
@@ -158,6 +179,32 @@ pub fn execute<R: PrivateKeyRegistry>(
                 )
             },
         ),
+        (QueryType::SemiHonestReachFrequency(query_config), _) => do_query(
+            config,
+            gateway,
+            input,
+            move |prss, gateway, config, input| {
+                let ctx = SemiHonestContext::new(prss, gateway);
+                Box::pin(
+                    ReachFrequencyQuery::<_, BA32, R>::new(query_config, key_registry)
+                        .execute(ctx, config.size, input)
+                        .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
+                )
+            },
+        ),
+        (QueryType::MaliciousReachFrequency(query_config), _) => do_query(
+            config,
+            gateway,
+            input,
+            move |prss, gateway, config, input| {
+                let ctx = MaliciousContext::new(prss, gateway);
+                Box::pin(
+                    ReachFrequencyQuery::<_, BA32, R>::new(query_config, key_registry)
+                        .execute(ctx, config.size, input)
+                        .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
+                )
+            },
+        ),
     }
 }
 
@@ -9,7 +9,7 @@ use serde::Serialize;
 use crate::{
     error::Error as ProtocolError,
     helpers::{
-        query::{PrepareQuery, QueryConfig, QueryInput},
+        query::{PrepareQuery, QueryConfig, QueryConfigError, QueryInput},
         Gateway, GatewayConfig, MpcTransportError, MpcTransportImpl, Role, RoleAssignment,
         ShardTransportImpl, Transport,
     },
@@ -17,9 +17,10 @@ use crate::{
     protocol::QueryId,
     query::{
         executor,
-        state::{QueryState, QueryStatus, RemoveQuery, RunningQueries, StateError},
+        state::{QueryState, QueryStatus, QuerySummary, RemoveQuery, RunningQueries, StateError},
         CompletionHandle, ProtocolResult,
     },
+    report::KeyIdentifier,
     sync::Arc,
     utils::NonZeroU32PowerOfTwo,
 };
@@ -63,6 +64,8 @@ pub enum NewQueryError {
     State(#[from] StateError),
     #[error(transparent)]
     MpcTransport(#[from] MpcTransportError),
+    #[error(transparent)]
+    Config(#[from] QueryConfigError),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -72,6 +75,8 @@ pub enum PrepareQueryError {
     #[error("Query is already running")]
     AlreadyRunning,
     #[error(transparent)]
+    Config(#[from] QueryConfigError),
+    #[error(transparent)]
     StateError {
         #[from]
         source: StateError,
@@ -128,6 +133,7 @@ impl Processor {
     }
 
     /// Upon receiving a new query request:
+    /// * validates the query configuration
     /// * processor generates new query id
     /// * assigns roles to helpers in the ring.
     ///     Helper that received new query request becomes `Role::H1` (aka coordinator).
@@ -141,13 +147,14 @@ impl Processor {
     /// * returns query configuration
     ///
     /// ## Errors
-    /// When other peers failed to acknowledge this query
+    /// When the query configuration is invalid or other peers failed to acknowledge this query
     #[allow(clippy::missing_panics_doc)]
     pub async fn new_query(
         &self,
         transport: MpcTransportImpl,
         req: QueryConfig,
     ) -> Result<PrepareQuery, NewQueryError> {
+        req.validate()?;
         let query_id = QueryId;
         let handle = self.queries.handle(query_id);
         handle.set_state(QueryState::Preparing(req))?;
@@ -181,12 +188,14 @@ impl Processor {
 
     /// On prepare, each follower:
     /// * ensures that it is not the leader on this query
+    /// * validates the query configuration
     /// * query is not registered yet
     /// * creates gateway and network
     /// * registers query
     ///
     /// ## Errors
-    /// if query is already running or this helper cannot be a follower in it
+    /// if query is already running, its configuration is invalid or this helper cannot be a
+    /// follower in it
     pub fn prepare(
         &self,
         transport: &MpcTransportImpl,
@@ -197,6 +206,7 @@ impl Processor {
         if my_role == Role::H1 {
             return Err(PrepareQueryError::WrongTarget);
         }
+        req.config.validate()?;
         let handle = self.queries.handle(req.query_id);
         if handle.status().is_some() {
             return Err(PrepareQueryError::AlreadyRunning);
@@ -246,6 +256,7 @@ impl Processor {
                         mpc_transport,
                         shard_transport,
                     );
+                    self.queries.handle(query_id).set_gateway(gateway.monitor());
                     queries.insert(
                         input.query_id,
                         QueryState::Running(executor::execute(
@@ -308,7 +319,10 @@ impl Processor {
             let mut queries = self.queries.inner.lock().unwrap();
 
             match queries.remove(&query_id) {
-                Some(QueryState::Completed(result)) => return result.map_err(Into::into),
+                Some(QueryState::Completed(result)) => {
+                    self.queries.forget(query_id);
+                    return result.map_err(Into::into);
+                }
                 Some(QueryState::Running(handle)) => {
                     queries.insert(query_id, QueryState::AwaitingCompletion);
                     CompletionHandle::new(RemoveQuery::new(query_id, &self.queries), handle)
@@ -330,6 +344,24 @@ impl Processor {
         Ok(handle.await?)
     }
 
+    /// Calls `callback` every time the state of a query is removed from this processor, whether
+    /// the query completed, failed or was killed.
+    pub fn on_query_removed<F: Fn(QueryId) + Send + Sync + 'static>(&self, callback: F) {
+        self.queries.on_remove(callback);
+    }
+
+    /// Returns the queries known to this helper, for the admin API.
+    #[must_use]
+    pub fn queries(&self) -> Vec<QuerySummary> {
+        self.queries.summaries()
+    }
+
+    /// Returns the identifiers of the HPKE keys this helper can decrypt reports with.
+    #[must_use]
+    pub fn key_ids(&self) -> Vec<KeyIdentifier> {
+        self.key_registry.key_ids().collect()
+    }
+
     /// Terminates a query with the given id. If query is running, then it
     /// is unregistered and its task is terminated.
     ///
@@ -343,6 +375,7 @@ impl Processor {
         let Some(state) = queries.remove(&query_id) else {
             return Err(QueryKillStatus::NoSuchQuery(query_id));
         };
+        self.queries.forget(query_id);
 
         if let QueryState::Running(handle) = state {
             handle.join_handle.abort();
@@ -373,11 +406,14 @@ mod tests {
         ff::FieldType,
         helpers::{
             make_owned_handler,
-            query::{PrepareQuery, QueryConfig, QueryType::TestMultiply},
+            query::{
+                IpaQueryConfig, PrepareQuery, QueryConfig, QueryConfigError, QueryType,
+                QueryType::TestMultiply,
+            },
             ApiError, HandlerBox, HelperIdentity, HelperResponse, InMemoryMpcNetwork,
             RequestHandler, RoleAssignment, Transport,
         },
-        protocol::QueryId,
+        protocol::{ipa_prf::ValueBuckets, QueryId},
         query::{
             processor::Processor, state::StateError, NewQueryError, PrepareQueryError, QueryStatus,
         },
@@ -477,6 +513,33 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn lists_queries() {
+        let handlers =
+            array::from_fn(|_| prepare_query_handler(|_| async { Ok(HelperResponse::ok()) }));
+        let network =
+            InMemoryMpcNetwork::new(handlers.each_ref().map(HandlerBox::owning_ref).map(Some));
+        let [t0, _, _] = network.transports();
+        let p0 = Processor::default();
+        let request = test_multiply_config();
+        assert!(p0.queries().is_empty());
+
+        p0.new_query(t0, request).await.unwrap();
+        let [query] = <[_; 1]>::try_from(p0.queries()).unwrap();
+        assert_eq!(query.query_id, QueryId);
+        assert_eq!(query.status, QueryStatus::AwaitingInputs);
+        assert_eq!(query.config, Some(request));
+        assert_eq!(
+            query.roles,
+            Some(RoleAssignment::new(HelperIdentity::make_three()))
+        );
+        assert!(query.created_at.is_some());
+        assert!(query.gateway.is_none());
+
+        p0.kill(QueryId).unwrap();
+        assert!(p0.queries().is_empty());
+    }
+
     #[tokio::test]
     async fn prepare_error() {
         let h2 = respond_ok();
@@ -498,6 +561,38 @@ mod tests {
         ));
     }
 
+    /// A config with more breakdowns than fit in the histogram with its value buckets. It cannot
+    /// be built with [`QueryConfig::new`], which validates it.
+    fn too_many_breakdowns_config() -> QueryConfig {
+        QueryConfig {
+            size: 1.try_into().unwrap(),
+            field_type: FieldType::Fp32BitPrime,
+            query_type: QueryType::SemiHonestOprfIpa(IpaQueryConfig {
+                max_breakdown_key: 129,
+                value_buckets: Some(ValueBuckets::new(&[10]).unwrap()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_config() {
+        let network = InMemoryMpcNetwork::default();
+        let [t0, _, _] = network.transports();
+        let p0 = Processor::default();
+
+        assert!(matches!(
+            p0.new_query(t0, too_many_breakdowns_config())
+                .await
+                .unwrap_err(),
+            NewQueryError::Config(QueryConfigError::TooManyBreakdowns {
+                breakdowns: 128,
+                ..
+            })
+        ));
+        assert!(p0.queries().is_empty());
+    }
+
     #[tokio::test]
     async fn can_recover_from_prepare_error() {
         let h2 = respond_ok();
@@ -565,6 +660,24 @@ mod tests {
             ));
         }
 
+        #[tokio::test]
+        async fn rejects_invalid_config() {
+            let network = InMemoryMpcNetwork::default();
+            let identities = HelperIdentity::make_three();
+            let req = PrepareQuery {
+                config: too_many_breakdowns_config(),
+                ..prepare_query(identities)
+            };
+            let transport = network.transport(identities[1]);
+            let processor = Processor::default();
+
+            assert!(matches!(
+                processor.prepare(&transport, req),
+                Err(PrepareQueryError::Config(_))
+            ));
+            assert!(processor.queries().is_empty());
+        }
+
         #[tokio::test]
         async fn rejects_if_query_exists() {
             let network = InMemoryMpcNetwork::default();
@@ -581,7 +694,10 @@ mod tests {
     }
 
     mod kill {
-        use std::sync::Arc;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
 
         use crate::{
             ff::FieldType,
@@ -644,6 +760,39 @@ mod tests {
             });
         }
 
+        #[test]
+        fn notifies_removal() {
+            run(|| async move {
+                let h2 = respond_ok();
+                let h3 = respond_ok();
+                let network = InMemoryMpcNetwork::new([
+                    None,
+                    Some(HandlerBox::owning_ref(&h2)),
+                    Some(HandlerBox::owning_ref(&h3)),
+                ]);
+                let processor = Processor::default();
+                let removed = Arc::new(AtomicUsize::new(0));
+                processor.on_query_removed({
+                    let removed = Arc::clone(&removed);
+                    move |query_id| {
+                        assert_eq!(QueryId, query_id);
+                        removed.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+                processor
+                    .new_query(
+                        network.transport(HelperIdentity::ONE),
+                        QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(0, removed.load(Ordering::Relaxed));
+
+                processor.kill(QueryId).unwrap();
+                assert_eq!(1, removed.load(Ordering::Relaxed));
+            });
+        }
+
         #[test]
         fn aborts_protocol_task() {
             run(|| async move {
@@ -811,6 +960,11 @@ mod tests {
                             with_dp: 0,
                             epsilon: 5.0,
                             plaintext_match_keys: true,
+                            dry_run: false,
+                            legacy_reports: false,
+                            value_buckets: None,
+                            trigger_value_scale: None,
+                            attribution_weights: None,
                         }),
                     },
                 )
@@ -2,16 +2,18 @@
 mod add_in_prime_field;
 mod hybrid;
 mod oprf_ipa;
+mod reach_frequency;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 mod test_multiply;
 
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 pub(super) use add_in_prime_field::execute as test_add_in_prime_field;
 pub use hybrid::Query as HybridQuery;
+pub(super) use oprf_ipa::dry_run as oprf_ipa_dry_run;
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
 pub(super) use test_multiply::execute_test_multiply;
 
-pub use self::oprf_ipa::OprfIpaQuery;
+pub use self::{oprf_ipa::OprfIpaQuery, reach_frequency::ReachFrequencyQuery};
 use crate::{error::Error, query::ProtocolResult};
 
 pub(super) type QueryResult = Result<Box<dyn ProtocolResult>, Error>;
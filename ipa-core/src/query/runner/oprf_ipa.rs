@@ -1,7 +1,10 @@
-use std::{convert::Infallible, marker::PhantomData};
+use std::{convert::Infallible, marker::PhantomData, ops::Add};
 
-use futures::{stream::iter, StreamExt, TryStreamExt};
+use bytes::Bytes;
+use futures::{future::ready, stream::iter, StreamExt, TryStreamExt};
 use futures_util::stream::repeat;
+use generic_array::ArrayLength;
+use typenum::{Sum, U16};
 
 use crate::{
     error::{Error, LengthError},
@@ -10,6 +13,7 @@ use crate::{
         boolean_array::{BooleanArray, BA20, BA3, BA8},
         curve_points::RP25519,
         ec_prime_field::Fp25519,
+        fixed_point::{FixedPointScale, FixedPointTriggerValue},
         Field, Serializable, U128Conversions,
     },
     helpers::{
@@ -22,13 +26,16 @@ use crate::{
         context::{DZKPUpgraded, MacUpgraded, UpgradableContext},
         ipa_prf::{
             oprf_ipa, oprf_padding::PaddingParameters, prf_eval::PrfSharing, shuffle::Shuffle,
-            OPRFIPAInputRow, AGG_CHUNK, CONV_CHUNK, PRF_CHUNK, SORT_CHUNK,
+            AggregationOutputMode, OPRFIPAInputRow, AGG_CHUNK, CONV_CHUNK, PRF_CHUNK, SORT_CHUNK,
         },
         prss::FromPrss,
         step::ProtocolStep::IpaPrf,
         BooleanProtocols,
     },
-    report::{EncryptedOprfReport, EventType},
+    report::{
+        validation::{decrypt_report, ValidationSummary},
+        EncryptedOprfReport, EventType,
+    },
     secret_sharing::{
         replicated::semi_honest::{AdditiveShare as Replicated, AdditiveShare},
         BitDecomposed, SharedValue, TransposeFrom, Vectorizable,
@@ -36,6 +43,11 @@ use crate::{
     sync::Arc,
 };
 
+/// Fixed-point trigger values are counted in units, so per-user caps are much larger than for
+/// integer trigger values. A single `SS_BITS` covers all of them, up to `2^FIXED_POINT_SS_BITS`
+/// units.
+const FIXED_POINT_SS_BITS: usize = 15;
+
 pub struct OprfIpaQuery<C, HV, R: PrivateKeyRegistry> {
     config: IpaQueryConfig,
     key_registry: Arc<R>,
@@ -71,6 +83,7 @@ where
     Replicated<BA8>: BooleanArrayMul<DZKPUpgraded<C>>,
     Replicated<BA20>: BooleanArrayMul<DZKPUpgraded<C>>,
     Replicated<BA3>: BooleanArrayMul<DZKPUpgraded<C>>,
+    Replicated<FixedPointTriggerValue>: BooleanArrayMul<DZKPUpgraded<C>>,
     Vec<Replicated<HV>>:
         for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, 256>>, Error = LengthError>,
     BitDecomposed<AdditiveShare<Boolean, 256>>:
@@ -90,51 +103,15 @@ where
         } = self;
         tracing::info!("New query: {config:?}");
         let ctx = ctx.narrow(&IpaPrf);
-        let sz = usize::from(query_size);
-
-        let input = if config.plaintext_match_keys {
-            let mut v = RecordsStream::<OPRFIPAInputRow<BA8, BA3, BA20>, _>::new(input_stream)
-                .try_concat()
-                .await?;
-            v.truncate(sz);
-            v
-        } else {
-            LengthDelimitedStream::<EncryptedOprfReport<BA8, BA3, BA20, _>, _>::new(input_stream)
-                .map_err(Into::<Error>::into)
-                .map_ok(|enc_reports| {
-                    iter(enc_reports.into_iter().map(|enc_report| {
-                        enc_report
-                            .decrypt(key_registry.as_ref())
-                            .map_err(Into::<Error>::into)
-                    }))
-                })
-                .try_flatten()
-                .take(sz)
-                .zip(repeat(ctx.clone()))
-                .map(|(res, ctx)| {
-                    res.map(|report| {
-                        let is_trigger = Replicated::<Boolean>::share_known_value(
-                            &ctx,
-                            match report.event_type {
-                                EventType::Source => Boolean::ZERO,
-                                EventType::Trigger => Boolean::ONE,
-                            },
-                        );
-
-                        OPRFIPAInputRow {
-                            timestamp: report.timestamp,
-                            match_key: report.match_key,
-                            is_trigger,
-                            breakdown_key: report.breakdown_key,
-                            trigger_value: report.trigger_value,
-                        }
-                    })
-                })
-                .try_collect::<Vec<_>>()
-                .await?
-        };
+
+        // `max_breakdown_key` is checked against the value buckets when the query is created.
+        let output_mode = config.value_buckets.map_or(
+            AggregationOutputMode::Sum,
+            AggregationOutputMode::ValueBuckets,
+        );
 
         let aws = config.attribution_window_seconds;
+        let weights = config.attribution_weights;
         let dp_params: DpMechanism = match config.with_dp {
             0 => DpMechanism::NoDp,
             _ => DpMechanism::DiscreteLaplace {
@@ -146,20 +123,241 @@ where
         let padding_params = PaddingParameters::relaxed();
         #[cfg(not(feature = "relaxed-dp"))]
         let padding_params = PaddingParameters::default();
-        match config.per_user_credit_cap {
-            8 => oprf_ipa::<_, BA8, BA3, HV, BA20, 3, 256>(ctx, input, aws, dp_params, padding_params).await,
-            16 => oprf_ipa::<_, BA8, BA3, HV, BA20, 4, 256>(ctx, input, aws, dp_params, padding_params).await,
-            32 => oprf_ipa::<_, BA8, BA3, HV, BA20, 5, 256>(ctx, input, aws, dp_params, padding_params).await,
-            64 => oprf_ipa::<_, BA8, BA3, HV, BA20, 6, 256>(ctx, input, aws, dp_params, padding_params).await,
-            128 => oprf_ipa::<_, BA8, BA3, HV, BA20, 7, 256>(ctx, input, aws, dp_params, padding_params).await,
-            _ => panic!(
-                "Invalid value specified for per-user cap: {:?}. Must be one of 8, 16, 32, 64, or 128.",
-                config.per_user_credit_cap
-            ),
+        // The cap does not need to be a power of two; `SS_BITS` only needs to be large enough to
+        // hold it.
+        let cap = config.per_user_credit_cap;
+
+        if config.trigger_value_scale.is_some() {
+            if cap == 0 || cap > 1 << FIXED_POINT_SS_BITS {
+                return Err(Error::InvalidQueryParameter(
+                    format!(
+                        "Invalid value specified for per-user cap: {cap}. Must be between 1 and \
+                        {} for fixed-point trigger values.",
+                        1 << FIXED_POINT_SS_BITS,
+                    )
+                    .into(),
+                ));
+            }
+            let input = read_input_rows::<_, _, BA8, FixedPointTriggerValue, BA20>(
+                &ctx,
+                config.plaintext_match_keys,
+                config.legacy_reports,
+                key_registry.as_ref(),
+                query_size,
+                input_stream,
+            )
+            .await?;
+            return oprf_ipa::<_, BA8, FixedPointTriggerValue, HV, BA20, FIXED_POINT_SS_BITS, 256>(
+                ctx,
+                input,
+                aws,
+                weights,
+                cap,
+                dp_params,
+                padding_params,
+                &output_mode,
+            )
+            .await;
+        }
+
+        let input = read_input_rows::<_, _, BA8, BA3, BA20>(
+            &ctx,
+            config.plaintext_match_keys,
+            config.legacy_reports,
+            key_registry.as_ref(),
+            query_size,
+            input_stream,
+        )
+        .await?;
+        match cap {
+            1..=8 => {
+                oprf_ipa::<_, BA8, BA3, HV, BA20, 3, 256>(
+                    ctx,
+                    input,
+                    aws,
+                    weights,
+                    cap,
+                    dp_params,
+                    padding_params,
+                    &output_mode,
+                )
+                .await
+            }
+            9..=16 => {
+                oprf_ipa::<_, BA8, BA3, HV, BA20, 4, 256>(
+                    ctx,
+                    input,
+                    aws,
+                    weights,
+                    cap,
+                    dp_params,
+                    padding_params,
+                    &output_mode,
+                )
+                .await
+            }
+            17..=32 => {
+                oprf_ipa::<_, BA8, BA3, HV, BA20, 5, 256>(
+                    ctx,
+                    input,
+                    aws,
+                    weights,
+                    cap,
+                    dp_params,
+                    padding_params,
+                    &output_mode,
+                )
+                .await
+            }
+            33..=64 => {
+                oprf_ipa::<_, BA8, BA3, HV, BA20, 6, 256>(
+                    ctx,
+                    input,
+                    aws,
+                    weights,
+                    cap,
+                    dp_params,
+                    padding_params,
+                    &output_mode,
+                )
+                .await
+            }
+            65..=128 => {
+                oprf_ipa::<_, BA8, BA3, HV, BA20, 7, 256>(
+                    ctx,
+                    input,
+                    aws,
+                    weights,
+                    cap,
+                    dp_params,
+                    padding_params,
+                    &output_mode,
+                )
+                .await
+            }
+            _ => Err(Error::InvalidQueryParameter(
+                format!(
+                    "Invalid value specified for per-user cap: {cap}. Must be between 1 and 128."
+                )
+                .into(),
+            )),
         }
     }
 }
 
+/// Reads up to `query_size` input rows for an OPRF-based query, with trigger values of type `TV`.
+///
+/// If `plaintext_match_keys` is false, the input is a stream of encrypted reports that are
+/// decrypted with `key_registry`, in the legacy format if `legacy_reports` is set. Otherwise, it
+/// is a stream of secret-shared input rows.
+pub(super) async fn read_input_rows<C, R, BK, TV, TS>(
+    ctx: &C,
+    plaintext_match_keys: bool,
+    legacy_reports: bool,
+    key_registry: &R,
+    query_size: QuerySize,
+    input_stream: BodyStream,
+) -> Result<Vec<OPRFIPAInputRow<BK, TV, TS>>, Error>
+where
+    C: UpgradableContext,
+    R: PrivateKeyRegistry,
+    BK: BooleanArray,
+    TV: BooleanArray,
+    TS: BooleanArray,
+    Replicated<Boolean>: Serializable + ShareKnownValue<C, Boolean>,
+    OPRFIPAInputRow<BK, TV, TS>: Serializable,
+    Replicated<BK>: Serializable,
+    Replicated<TV>: Serializable,
+    Replicated<TS>: Serializable,
+    <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+    Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+        Add<<Replicated<TS> as Serializable>::Size>,
+    Sum<
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+        <Replicated<TS> as Serializable>::Size,
+    >: Add<U16>,
+    Sum<
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >,
+        U16,
+    >: ArrayLength,
+{
+    let sz = usize::from(query_size);
+    let rows = if plaintext_match_keys {
+        let mut v = RecordsStream::<OPRFIPAInputRow<BK, TV, TS>, _>::new(input_stream)
+            .try_concat()
+            .await?;
+        v.truncate(sz);
+        v
+    } else {
+        LengthDelimitedStream::<Bytes, _>::new(input_stream)
+            .map_err(Into::<Error>::into)
+            .map_ok(|enc_reports| {
+                iter(enc_reports.into_iter().map(|bytes| {
+                    EncryptedOprfReport::<BK, TV, TS, _>::from_input_bytes(bytes, legacy_reports)
+                        .and_then(|enc_report| enc_report.decrypt(key_registry))
+                        .map_err(Into::<Error>::into)
+                }))
+            })
+            .try_flatten()
+            .take(sz)
+            .zip(repeat(ctx.clone()))
+            .map(|(res, ctx)| {
+                res.map(|report| {
+                    let is_trigger = Replicated::<Boolean>::share_known_value(
+                        &ctx,
+                        match report.event_type {
+                            EventType::Source => Boolean::ZERO,
+                            EventType::Trigger => Boolean::ONE,
+                        },
+                    );
+
+                    OPRFIPAInputRow {
+                        timestamp: report.timestamp,
+                        match_key: report.match_key,
+                        is_trigger,
+                        breakdown_key: report.breakdown_key,
+                        trigger_value: report.trigger_value,
+                    }
+                })
+            })
+            .try_collect::<Vec<_>>()
+            .await?
+    };
+
+    Ok(rows)
+}
+
+/// Decrypts the input reports of an OPRF-based query without running the protocol, and counts the
+/// reports that this helper would reject. Reports carry fixed-point trigger values if
+/// `trigger_value_scale` is set, and are in the legacy format if `legacy_reports` is set.
+pub async fn dry_run<R: PrivateKeyRegistry>(
+    key_registry: &R,
+    trigger_value_scale: Option<FixedPointScale>,
+    legacy_reports: bool,
+    input_stream: BodyStream,
+) -> Result<ValidationSummary, Error> {
+    LengthDelimitedStream::<Bytes, _>::new(input_stream)
+        .map_err(Into::<Error>::into)
+        .try_fold(ValidationSummary::default(), |mut summary, reports| {
+            for report in reports {
+                summary.record(if trigger_value_scale.is_some() {
+                    decrypt_report::<BA8, FixedPointTriggerValue, BA20, _>(
+                        &report,
+                        legacy_reports,
+                        key_registry,
+                    )
+                } else {
+                    decrypt_report::<BA8, BA3, BA20, _>(&report, legacy_reports, key_registry)
+                });
+            }
+            ready(Ok(summary))
+        })
+        .await
+}
+
 #[cfg(all(test, unit_test))]
 mod tests {
     use std::{iter::zip, sync::Arc};
@@ -177,14 +375,25 @@ mod tests {
             BodyStream,
         },
         hpke::{KeyPair, KeyRegistry},
-        query::runner::OprfIpaQuery,
-        report::{OprfReport, DEFAULT_KEY_ID},
+        query::runner::{oprf_ipa::dry_run, OprfIpaQuery},
+        report::{validation::Rejection, OprfReport, ReportVersion, DEFAULT_KEY_ID},
         secret_sharing::IntoShares,
         test_fixture::{ipa::TestRawDataRecord, join3v, Reconstruct, TestWorld},
     };
 
     #[tokio::test]
     async fn encrypted_reports() {
+        Box::pin(run_encrypted_reports(ReportVersion::CURRENT)).await;
+    }
+
+    /// Reports from encoders that predate the version byte are accepted by queries that are
+    /// created for them.
+    #[tokio::test]
+    async fn legacy_encrypted_reports() {
+        Box::pin(run_encrypted_reports(ReportVersion::Legacy)).await;
+    }
+
+    async fn run_encrypted_reports(version: ReportVersion) {
         const EXPECTED: &[u128] = &[0, 8, 5];
 
         let records: Vec<TestRawDataRecord> = vec![
@@ -243,9 +452,18 @@ mod tests {
         let shares: [Vec<OprfReport<BA8, BA3, BA20>>; 3] = records.into_iter().share();
         for (buf, shares) in zip(&mut buffers, shares) {
             for share in shares {
+                let mut report = Vec::new();
                 share
-                    .delimited_encrypt_to(key_id, key_registry.as_ref(), &mut rng, buf)
+                    .encrypt_as_to(
+                        version,
+                        key_id,
+                        key_registry.as_ref(),
+                        &mut rng,
+                        &mut report,
+                    )
                     .unwrap();
+                buf.extend(u16::try_from(report.len()).unwrap().to_le_bytes());
+                buf.extend(report);
             }
         }
 
@@ -255,11 +473,11 @@ mod tests {
         let results = join3v(buffers.into_iter().zip(contexts).map(|(buffer, ctx)| {
             let query_config = IpaQueryConfig {
                 per_user_credit_cap: 8,
-                attribution_window_seconds: None,
                 max_breakdown_key: 3,
                 with_dp: 0,
                 epsilon: 5.0,
-                plaintext_match_keys: false,
+                legacy_reports: version == ReportVersion::Legacy,
+                ..Default::default()
             };
             let input = BodyStream::from(buffer);
 
@@ -279,4 +497,49 @@ mod tests {
             EXPECTED
         );
     }
+
+    #[tokio::test]
+    async fn dry_run_counts_rejected_reports() {
+        let records = (0..4).map(|i| TestRawDataRecord {
+            timestamp: i,
+            user_id: 12345,
+            is_trigger_report: i % 2 == 1,
+            breakdown_key: 1,
+            trigger_value: 3,
+        });
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let shares: [Vec<OprfReport<BA8, BA3, BA20>>; 3] = records.share_with(&mut rng);
+        let [mut reports, ..] = shares.map(|shares| {
+            shares
+                .iter()
+                .map(|share| {
+                    share
+                        .encrypt(DEFAULT_KEY_ID, &key_registry, &mut rng)
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+        });
+        reports[1][0] = 2;
+        reports[2][40] ^= 1;
+
+        let mut buffer = Vec::new();
+        for report in reports {
+            buffer.extend(u16::try_from(report.len()).unwrap().to_le_bytes());
+            buffer.extend(report);
+        }
+        let summary = dry_run(&key_registry, None, false, BodyStream::from(buffer))
+            .await
+            .unwrap();
+
+        assert_eq!((summary.records, summary.valid), (4, 2));
+        assert_eq!(
+            summary.rejected.into_iter().collect::<Vec<_>>(),
+            [
+                (Rejection::UnsupportedVersion, 1),
+                (Rejection::DecryptionFailed, 1)
+            ]
+        );
+    }
 }
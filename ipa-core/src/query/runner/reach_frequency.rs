@@ -0,0 +1,258 @@
+use std::{convert::Infallible, marker::PhantomData};
+
+use crate::{
+    error::{Error, LengthError},
+    ff::{
+        boolean::Boolean,
+        boolean_array::{BooleanArray, BA20, BA3, BA8},
+        curve_points::RP25519,
+        ec_prime_field::Fp25519,
+        Serializable, U128Conversions,
+    },
+    helpers::{
+        query::{DpMechanism, QuerySize, ReachFrequencyQueryConfig},
+        BodyStream,
+    },
+    hpke::PrivateKeyRegistry,
+    protocol::{
+        basics::{Reveal, ShareKnownValue},
+        context::{DZKPUpgraded, MacUpgraded, UpgradableContext},
+        ipa_prf::{
+            oprf_padding::PaddingParameters, prf_eval::PrfSharing,
+            reach_frequency::oprf_reach_frequency, shuffle::Shuffle, AGG_CHUNK, CONV_CHUNK,
+            PRF_CHUNK, SORT_CHUNK,
+        },
+        prss::FromPrss,
+        step::ProtocolStep::IpaPrf,
+        BooleanProtocols,
+    },
+    query::runner::oprf_ipa::read_input_rows,
+    secret_sharing::{
+        replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, TransposeFrom,
+        Vectorizable,
+    },
+    sync::Arc,
+};
+
+pub struct ReachFrequencyQuery<C, HV, R: PrivateKeyRegistry> {
+    config: ReachFrequencyQueryConfig,
+    key_registry: Arc<R>,
+    phantom_data: PhantomData<(C, HV)>,
+}
+
+impl<C, HV, R: PrivateKeyRegistry> ReachFrequencyQuery<C, HV, R> {
+    pub fn new(config: ReachFrequencyQueryConfig, key_registry: Arc<R>) -> Self {
+        Self {
+            config,
+            key_registry,
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<C, HV, R> ReachFrequencyQuery<C, HV, R>
+where
+    C: UpgradableContext + Shuffle,
+    HV: BooleanArray + U128Conversions,
+    R: PrivateKeyRegistry,
+    Replicated<Boolean>: Serializable + ShareKnownValue<C, Boolean>,
+    Replicated<Boolean>: BooleanProtocols<DZKPUpgraded<C>>,
+    Replicated<Boolean, 256>: BooleanProtocols<DZKPUpgraded<C>, 256>,
+    Replicated<Boolean, AGG_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, AGG_CHUNK>,
+    Replicated<Boolean, CONV_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, CONV_CHUNK>,
+    Replicated<Boolean, SORT_CHUNK>: BooleanProtocols<DZKPUpgraded<C>, SORT_CHUNK>,
+    Replicated<Fp25519, PRF_CHUNK>:
+        PrfSharing<MacUpgraded<C, Fp25519>, PRF_CHUNK, Field = Fp25519> + FromPrss,
+    Replicated<RP25519, PRF_CHUNK>:
+        Reveal<MacUpgraded<C, Fp25519>, Output = <RP25519 as Vectorizable<PRF_CHUNK>>::Array>,
+    Vec<Replicated<HV>>:
+        for<'a> TransposeFrom<&'a BitDecomposed<Replicated<Boolean, 256>>, Error = LengthError>,
+    BitDecomposed<Replicated<Boolean, 256>>:
+        for<'a> TransposeFrom<&'a [Replicated<HV>; 256], Error = Infallible>,
+{
+    #[tracing::instrument("reach_frequency_query", skip_all, fields(sz=%query_size))]
+    pub async fn execute(
+        self,
+        ctx: C,
+        query_size: QuerySize,
+        input_stream: BodyStream,
+    ) -> Result<Vec<Replicated<HV>>, Error> {
+        let Self {
+            config,
+            key_registry,
+            phantom_data: _,
+        } = self;
+        tracing::info!("New query: {config:?}");
+        let ctx = ctx.narrow(&IpaPrf);
+
+        let input = read_input_rows::<_, _, BA8, BA3, BA20>(
+            &ctx,
+            config.plaintext_match_keys,
+            false,
+            key_registry.as_ref(),
+            query_size,
+            input_stream,
+        )
+        .await?;
+
+        let dp_params: DpMechanism = match config.with_dp {
+            0 => DpMechanism::NoDp,
+            _ => DpMechanism::DiscreteLaplace {
+                epsilon: config.epsilon,
+            },
+        };
+
+        #[cfg(feature = "relaxed-dp")]
+        let padding_params = PaddingParameters::relaxed();
+        #[cfg(not(feature = "relaxed-dp"))]
+        let padding_params = PaddingParameters::default();
+
+        oprf_reach_frequency::<_, BA8, BA3, HV, BA20, 256>(
+            ctx,
+            input,
+            config.max_breakdown_key,
+            config.max_frequency,
+            config.max_breakdowns_per_user,
+            dp_params,
+            padding_params,
+        )
+        .await
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{iter::zip, sync::Arc};
+
+    use rand::rngs::StdRng;
+    use rand_core::SeedableRng;
+
+    use crate::{
+        ff::{
+            boolean_array::{BA16, BA20, BA3, BA8},
+            U128Conversions,
+        },
+        helpers::{
+            query::{QuerySize, ReachFrequencyQueryConfig},
+            BodyStream,
+        },
+        hpke::{KeyPair, KeyRegistry},
+        query::runner::ReachFrequencyQuery,
+        report::{OprfReport, DEFAULT_KEY_ID},
+        secret_sharing::IntoShares,
+        test_fixture::{ipa::TestRawDataRecord, join3v, Reconstruct, TestWorld},
+    };
+
+    /// Reach of breakdowns 0 to 2, followed by the number of users with 1 and 2+ impressions.
+    const EXPECTED: &[u128] = &[0, 2, 1, 1, 1];
+
+    const QUERY_CONFIG: ReachFrequencyQueryConfig = ReachFrequencyQueryConfig {
+        max_breakdown_key: 3,
+        max_frequency: 2,
+        max_breakdowns_per_user: 2,
+        with_dp: 0,
+        epsilon: 5.0,
+        plaintext_match_keys: false,
+    };
+
+    /// Encrypts the shares of the test records for each helper.
+    fn encrypted_inputs() -> (QuerySize, Arc<KeyRegistry<KeyPair>>, [Vec<u8>; 3]) {
+        let records: Vec<TestRawDataRecord> = vec![
+            TestRawDataRecord {
+                timestamp: 0,
+                user_id: 12345,
+                is_trigger_report: false,
+                breakdown_key: 2,
+                trigger_value: 0,
+            },
+            TestRawDataRecord {
+                timestamp: 4,
+                user_id: 68362,
+                is_trigger_report: false,
+                breakdown_key: 1,
+                trigger_value: 0,
+            },
+            TestRawDataRecord {
+                timestamp: 10,
+                user_id: 12345,
+                is_trigger_report: true,
+                breakdown_key: 0,
+                trigger_value: 5,
+            },
+            TestRawDataRecord {
+                timestamp: 20,
+                user_id: 12345,
+                is_trigger_report: false,
+                breakdown_key: 1,
+                trigger_value: 0,
+            },
+        ];
+
+        let query_size = QuerySize::try_from(records.len()).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let key_id = DEFAULT_KEY_ID;
+        let key_registry = Arc::new(KeyRegistry::<KeyPair>::random(1, &mut rng));
+
+        let mut buffers: [_; 3] = std::array::from_fn(|_| Vec::new());
+
+        let shares: [Vec<OprfReport<BA8, BA3, BA20>>; 3] = records.into_iter().share();
+        for (buf, shares) in zip(&mut buffers, shares) {
+            for share in shares {
+                share
+                    .delimited_encrypt_to(key_id, key_registry.as_ref(), &mut rng, buf)
+                    .unwrap();
+            }
+        }
+
+        (query_size, key_registry, buffers)
+    }
+
+    fn assert_expected(results: &[BA16]) {
+        assert_eq!(
+            results[0..5]
+                .iter()
+                .map(U128Conversions::as_u128)
+                .collect::<Vec<u128>>(),
+            EXPECTED
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_reports() {
+        let (query_size, key_registry, buffers) = encrypted_inputs();
+
+        let world = TestWorld::default();
+        let contexts = world.contexts();
+        #[allow(clippy::large_futures)]
+        let results = join3v(buffers.into_iter().zip(contexts).map(|(buffer, ctx)| {
+            ReachFrequencyQuery::<_, BA16, KeyRegistry<KeyPair>>::new(
+                QUERY_CONFIG,
+                Arc::clone(&key_registry),
+            )
+            .execute(ctx, query_size, BodyStream::from(buffer))
+        }))
+        .await;
+
+        assert_expected(&results.reconstruct());
+    }
+
+    #[tokio::test]
+    async fn malicious_encrypted_reports() {
+        let (query_size, key_registry, buffers) = encrypted_inputs();
+
+        let world = TestWorld::default();
+        let contexts = world.malicious_contexts();
+        #[allow(clippy::large_futures)]
+        let results = join3v(buffers.into_iter().zip(contexts).map(|(buffer, ctx)| {
+            ReachFrequencyQuery::<_, BA16, KeyRegistry<KeyPair>>::new(
+                QUERY_CONFIG,
+                Arc::clone(&key_registry),
+            )
+            .execute(ctx, query_size, BodyStream::from(buffer))
+        }))
+        .await;
+
+        assert_expected(&results.reconstruct());
+    }
+}
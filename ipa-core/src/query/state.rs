@@ -3,6 +3,7 @@ use std::{
     fmt::{Debug, Formatter},
     future::Future,
     task::Poll,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use ::tokio::sync::oneshot::{error::TryRecvError, Receiver};
@@ -10,7 +11,7 @@ use futures::{ready, FutureExt};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    helpers::{query::QueryConfig, RoleAssignment},
+    helpers::{query::QueryConfig, GatewayMonitor, GatewayStats, RoleAssignment},
     protocol::QueryId,
     query::runner::QueryResult,
     sync::Mutex,
@@ -130,16 +131,46 @@ pub enum StateError {
 /// Keeps track of queries running on this helper.
 pub struct RunningQueries {
     pub inner: Mutex<HashMap<QueryId, QueryState>>,
+    /// Details of the queries in `inner`, reported by [`Self::summaries`]. When both are locked,
+    /// `inner` must be locked first.
+    info: Mutex<HashMap<QueryId, QueryInfo>>,
+    /// Called with the ID of every query that is removed from `inner`.
+    on_remove: Mutex<Option<RemoveCallback>>,
 }
 
+type RemoveCallback = Box<dyn Fn(QueryId) + Send + Sync>;
+
 impl Default for RunningQueries {
     fn default() -> Self {
         Self {
             inner: Mutex::new(HashMap::default()),
+            info: Mutex::new(HashMap::default()),
+            on_remove: Mutex::new(None),
         }
     }
 }
 
+/// What is known about a query besides its state.
+struct QueryInfo {
+    created: SystemTime,
+    config: QueryConfig,
+    roles: Option<RoleAssignment>,
+    gateway: Option<GatewayMonitor>,
+}
+
+/// A query known to this helper, as reported by the admin API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuerySummary {
+    pub query_id: QueryId,
+    pub status: QueryStatus,
+    /// When the query was created on this helper, in seconds since the Unix epoch.
+    pub created_at: Option<u64>,
+    pub config: Option<QueryConfig>,
+    pub roles: Option<RoleAssignment>,
+    /// Set while the query is running.
+    pub gateway: Option<GatewayStats>,
+}
+
 impl Debug for RunningQueries {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "RunningQueries[{}]", self.inner.lock().unwrap().len())
@@ -153,20 +184,51 @@ pub struct QueryHandle<'a> {
 
 impl QueryHandle<'_> {
     pub fn set_state(&self, new_state: QueryState) -> Result<(), StateError> {
+        let details = match &new_state {
+            QueryState::Preparing(config) => Some((*config, None)),
+            QueryState::AwaitingInputs(_, config, roles) => Some((*config, Some(roles.clone()))),
+            _ => None,
+        };
+
         let mut inner = self.queries.inner.lock().unwrap();
         let entry = inner.entry(self.query_id);
-        match entry {
+        let created = match entry {
             Entry::Occupied(mut entry) => {
                 entry.insert(QueryState::transition(entry.get(), new_state)?);
+                false
             }
             Entry::Vacant(entry) => {
                 entry.insert(QueryState::transition(&QueryState::Empty, new_state)?);
+                true
+            }
+        };
+
+        if let Some((config, roles)) = details {
+            let mut info = self.queries.info.lock().unwrap();
+            if created {
+                info.remove(&self.query_id);
+            }
+            let info = info.entry(self.query_id).or_insert_with(|| QueryInfo {
+                created: SystemTime::now(),
+                config,
+                roles: None,
+                gateway: None,
+            });
+            if roles.is_some() {
+                info.roles = roles;
             }
         }
 
         Ok(())
     }
 
+    /// Makes the state of the gateway used by the query available to [`RunningQueries::summaries`].
+    pub fn set_gateway(&self, gateway: GatewayMonitor) {
+        if let Some(info) = self.queries.info.lock().unwrap().get_mut(&self.query_id) {
+            info.gateway = Some(gateway);
+        }
+    }
+
     pub fn status(&self) -> Option<QueryStatus> {
         let inner = self.queries.inner.lock().unwrap();
         inner.get(&self.query_id).map(QueryStatus::from)
@@ -184,6 +246,56 @@ impl RunningQueries {
             queries: self,
         }
     }
+
+    /// Forgets the details of a query that was removed from `inner`, and notifies the callback
+    /// set with [`Self::on_remove`].
+    pub fn forget(&self, query_id: QueryId) {
+        self.info.lock().unwrap().remove(&query_id);
+        if let Some(on_remove) = self.on_remove.lock().unwrap().as_ref() {
+            on_remove(query_id);
+        }
+    }
+
+    /// Sets a callback that is called every time a query is removed, whether it completed, failed
+    /// or was killed. The callback must not access this collection.
+    pub fn on_remove<F: Fn(QueryId) + Send + Sync + 'static>(&self, callback: F) {
+        *self.on_remove.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    /// Returns all the queries known to this helper.
+    ///
+    /// ## Panics
+    /// If one of the mutexes is poisoned.
+    pub fn summaries(&self) -> Vec<QuerySummary> {
+        let mut inner = self.inner.lock().unwrap();
+        let info = self.info.lock().unwrap();
+        inner
+            .iter_mut()
+            .map(|(query_id, state)| {
+                if let QueryState::Running(running) = state {
+                    if let Some(result) = running.try_complete() {
+                        *state = QueryState::Completed(result);
+                    }
+                }
+                let info = info.get(query_id);
+                QuerySummary {
+                    query_id: *query_id,
+                    status: QueryStatus::from(&*state),
+                    created_at: info.map(|info| {
+                        info.created
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs())
+                    }),
+                    config: info.map(|info| info.config),
+                    roles: info.and_then(|info| info.roles.clone()),
+                    gateway: info
+                        .filter(|_| matches!(state, QueryState::Running(_)))
+                        .and_then(|info| info.gateway.as_ref())
+                        .map(GatewayMonitor::stats),
+                }
+            })
+            .collect()
+    }
 }
 
 /// RAII guard to clean up query state when dropped.
@@ -211,14 +323,14 @@ impl<'a> RemoveQuery<'a> {
 impl Drop for RemoveQuery<'_> {
     fn drop(&mut self) {
         if let Some(inner) = &self.inner {
-            if inner
+            let removed = inner
                 .queries
                 .inner
                 .lock()
                 .unwrap()
-                .remove_entry(&inner.query_id)
-                .is_none()
-            {
+                .remove_entry(&inner.query_id);
+            inner.queries.forget(inner.query_id);
+            if removed.is_none() {
                 tracing::warn!(
                     "{q} query is not registered, but attempted to terminate",
                     q = inner.query_id
@@ -114,7 +114,7 @@ mod test {
     use crate::{
         ff::boolean_array::{BA20, BA3, BA8},
         hpke::{KeyPair, KeyRegistry},
-        report::{EventType, OprfReport},
+        report::{EventType, InvalidReportError, OprfReport, ReportVersion},
         secret_sharing::replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
     };
 
@@ -187,4 +187,22 @@ mod test {
 
         assert_eq!(hybrid_report, hybrid_report2);
     }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let mut rng = thread_rng();
+
+        let oprf_report = build_oprf_report(EventType::Source, &mut rng);
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+
+        let mut enc_report_bytes = oprf_report.encrypt(0, &key_registry, &mut rng).unwrap();
+        enc_report_bytes[0] = u8::from(ReportVersion::CURRENT) + 1;
+        let err = HybridReport::<BA8, BA3>::from_bytes::<_, _, BA20>(
+            enc_report_bytes.as_slice(),
+            &key_registry,
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, InvalidReportError::UnsupportedVersion(_)));
+    }
 }
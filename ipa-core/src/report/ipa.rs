@@ -9,7 +9,8 @@
 //!
 //! From the Report Collectors's POV, there are two potential paths:
 //! 1. In production, encrypted events are recieved from clients and accumulated out of band
-//!    as 3 files of newline delimited hex encoded enrypted events.
+//!    as 3 files of newline delimited hex encoded enrypted events, or as a single Parquet file
+//!    with a binary column per helper (see `cli::columnar`, behind the `parquet-io` feature).
 //! 2. For testing, simluated plaintext events are provided as a CSV.
 //!
 //! Path 1 is proccssed as follows:
@@ -137,6 +138,72 @@ impl From<&EventType> for u8 {
     }
 }
 
+/// Version of the encrypted report format, carried in the first byte of every report.
+///
+/// Any change to the layout of [`EncryptedOprfReport`], or to the meaning of its fields, must
+/// introduce a new version. Decoders dispatch on the version and reject the ones they do not
+/// know, so encoders that are already deployed keep working until they are updated. The version
+/// is also bound into the HPKE info, so a report cannot be decrypted as another version. The test
+/// vectors for each version are checked in under `report/test_vectors`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum ReportVersion {
+    /// Reports from encoders that predate the version byte, such as the iOS client. They have
+    /// the [`Self::V1`] layout without the version byte, and the version is not part of the HPKE
+    /// info. The first byte of such a report is random, so they cannot be told apart from
+    /// versioned reports; helpers only accept them in queries created with
+    /// [`IpaQueryConfig::legacy_reports`] set.
+    ///
+    /// [`IpaQueryConfig::legacy_reports`]: crate::helpers::query::IpaQueryConfig::legacy_reports
+    Legacy = 0,
+    /// Encapsulated keys and ciphertexts for the match key and for the timestamp, breakdown key
+    /// and trigger value, followed by the event type, key id, epoch and site domain.
+    V1 = 1,
+}
+
+impl ReportVersion {
+    /// The version that encoders produce.
+    pub const CURRENT: Self = Self::V1;
+
+    /// The version byte that starts reports of this version, if they have one.
+    #[must_use]
+    pub fn byte(self) -> Option<u8> {
+        match self {
+            Self::Legacy => None,
+            Self::V1 => Some(self as u8),
+        }
+    }
+
+    fn header_len(self) -> usize {
+        usize::from(self.byte().is_some())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("report version {0} is not supported")]
+pub struct UnsupportedReportVersion(u8);
+
+/// Reads the version byte of a report. [`ReportVersion::Legacy`] has no version byte, so it is
+/// never returned.
+impl TryFrom<u8> for ReportVersion {
+    type Error = UnsupportedReportVersion;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::V1),
+            _ => Err(UnsupportedReportVersion(value)),
+        }
+    }
+}
+
+/// The number of the version, `0` for [`ReportVersion::Legacy`]. This is not necessarily the
+/// version byte, see [`ReportVersion::byte`].
+impl From<ReportVersion> for u8 {
+    fn from(value: ReportVersion) -> Self {
+        value as u8
+    }
+}
+
 #[derive(Debug)]
 pub struct NonAsciiStringError {
     input: String,
@@ -175,6 +242,8 @@ impl From<&'_ str> for NonAsciiStringError {
 pub enum InvalidReportError {
     #[error("{0}")]
     BadEventType(#[from] ParseEventTypeError),
+    #[error("{0}")]
+    UnsupportedVersion(#[from] UnsupportedReportVersion),
     #[error("bad site_domain: {0}")]
     NonAsciiString(#[from] NonAsciiStringError),
     #[error("timestamp {0} out of range")]
@@ -224,8 +293,11 @@ impl From<[&PathBuf; 3]> for EncryptedOprfReportStreams {
         }
         // Panic if input sizes are not the same
         // Panic instead of returning an Error as this is non-recoverable
-        assert_eq!(query_sizes[0], query_sizes[1]);
-        assert_eq!(query_sizes[1], query_sizes[2]);
+        assert!(
+            query_sizes[0] == query_sizes[1] && query_sizes[1] == query_sizes[2],
+            "helper inputs have different numbers of reports: {query_sizes:?}, \
+            `report_collector validate` can tell which reports are missing or invalid"
+        );
 
         Self {
             streams: buffers.map(BodyStream::from),
@@ -238,6 +310,7 @@ impl From<[&PathBuf; 3]> for EncryptedOprfReportStreams {
 
 /// A binary report as submitted by a report collector, containing encrypted `OprfReport`
 /// An `EncryptedOprfReport` consists of:
+///     `version`: [`ReportVersion`] of the layout below
 ///     `ct_mk`: Enc(`match_key`)
 ///     `ct_btt`: Enc(`breakdown_key`, `trigger_value`, `timestamp`)
 ///     associated data of `ct_mk`: `key_id`, `epoch`, `event_type`, `site_domain`,
@@ -249,12 +322,14 @@ where
     TV: SharedValue,
     TS: SharedValue,
 {
+    version: ReportVersion,
     data: B,
     phantom_data: PhantomData<(BK, TV, TS)>,
 }
 
 // follows the outline of the implementation of `EncryptedReport`
-// Report structure:
+// Report structure (version 1), following the version byte. Legacy reports have the same
+// structure, without the version byte:
 //  * 0..a: `encap_key_1`
 //  * a..b: `mk_ciphertext`
 //  * b..c: `encap_key_2`
@@ -315,37 +390,46 @@ where
     const TV_OFFSET: usize = Self::BK_OFFSET + <Replicated<BK> as Serializable>::Size::USIZE;
     const TV_END: usize = Self::TV_OFFSET + <Replicated<TV> as Serializable>::Size::USIZE;
 
+    pub fn version(&self) -> ReportVersion {
+        self.version
+    }
+
+    /// The report without the version byte.
+    fn body(&self) -> &[u8] {
+        &self.data[self.version.header_len()..]
+    }
+
     pub fn encap_key_mk(&self) -> &[u8] {
-        &self.data[Self::ENCAP_KEY_MK_OFFSET..Self::CIPHERTEXT_MK_OFFSET]
+        &self.body()[Self::ENCAP_KEY_MK_OFFSET..Self::CIPHERTEXT_MK_OFFSET]
     }
 
     pub fn mk_ciphertext(&self) -> &[u8] {
-        &self.data[Self::CIPHERTEXT_MK_OFFSET..Self::ENCAP_KEY_BTT_OFFSET]
+        &self.body()[Self::CIPHERTEXT_MK_OFFSET..Self::ENCAP_KEY_BTT_OFFSET]
     }
 
     pub fn encap_key_btt(&self) -> &[u8] {
-        &self.data[Self::ENCAP_KEY_BTT_OFFSET..Self::CIPHERTEXT_BTT_OFFSET]
+        &self.body()[Self::ENCAP_KEY_BTT_OFFSET..Self::CIPHERTEXT_BTT_OFFSET]
     }
 
     pub fn btt_ciphertext(&self) -> &[u8] {
-        &self.data[Self::CIPHERTEXT_BTT_OFFSET..Self::EVENT_TYPE_OFFSET]
+        &self.body()[Self::CIPHERTEXT_BTT_OFFSET..Self::EVENT_TYPE_OFFSET]
     }
 
     /// ## Panics
     /// Only if a `Report` constructor failed to validate the contents properly, which would be a bug.
     pub fn event_type(&self) -> EventType {
-        EventType::try_from(self.data[Self::EVENT_TYPE_OFFSET]).unwrap() // validated on construction
+        EventType::try_from(self.body()[Self::EVENT_TYPE_OFFSET]).unwrap() // validated on construction
     }
 
     pub fn key_id(&self) -> KeyIdentifier {
-        self.data[Self::KEY_IDENTIFIER_OFFSET]
+        self.body()[Self::KEY_IDENTIFIER_OFFSET]
     }
 
     /// ## Panics
     /// Never.
     pub fn epoch(&self) -> Epoch {
         u16::from_le_bytes(
-            self.data[Self::EPOCH_OFFSET..Self::SITE_DOMAIN_OFFSET]
+            self.body()[Self::EPOCH_OFFSET..Self::SITE_DOMAIN_OFFSET]
                 .try_into()
                 .unwrap(), // infallible slice-to-array conversion
         )
@@ -354,24 +438,58 @@ where
     /// ## Panics
     /// Only if a `Report` constructor failed to validate the contents properly, which would be a bug.
     pub fn site_domain(&self) -> &str {
-        std::str::from_utf8(&self.data[Self::SITE_DOMAIN_OFFSET..]).unwrap() // validated on construction
+        std::str::from_utf8(&self.body()[Self::SITE_DOMAIN_OFFSET..]).unwrap() // validated on construction
     }
 
+    /// Parses a report that starts with a version byte.
+    ///
     /// ## Errors
-    /// If the report contents are invalid.
+    /// If the version is not supported, or the report contents are invalid.
     pub fn from_bytes(bytes: B) -> Result<Self, InvalidReportError> {
-        if bytes.len() <= Self::SITE_DOMAIN_OFFSET {
+        let Some(&version) = bytes.first() else {
+            return Err(InvalidReportError::Length(0, Self::SITE_DOMAIN_OFFSET + 1));
+        };
+        Self::parse(ReportVersion::try_from(version)?, bytes)
+    }
+
+    /// Parses a report from an encoder that predates the version byte (see
+    /// [`ReportVersion::Legacy`]).
+    ///
+    /// ## Errors
+    /// If the report contents are invalid.
+    pub fn from_legacy_bytes(bytes: B) -> Result<Self, InvalidReportError> {
+        Self::parse(ReportVersion::Legacy, bytes)
+    }
+
+    /// Parses a report from a query input, with [`Self::from_legacy_bytes`] if `legacy` is set
+    /// and with [`Self::from_bytes`] otherwise.
+    ///
+    /// ## Errors
+    /// If the version is not supported, or the report contents are invalid.
+    pub fn from_input_bytes(bytes: B, legacy: bool) -> Result<Self, InvalidReportError> {
+        if legacy {
+            Self::from_legacy_bytes(bytes)
+        } else {
+            Self::from_bytes(bytes)
+        }
+    }
+
+    fn parse(version: ReportVersion, bytes: B) -> Result<Self, InvalidReportError> {
+        let header_len = version.header_len();
+        if bytes.len() <= header_len + Self::SITE_DOMAIN_OFFSET {
             return Err(InvalidReportError::Length(
                 bytes.len(),
-                Self::SITE_DOMAIN_OFFSET,
+                header_len + Self::SITE_DOMAIN_OFFSET,
             ));
         }
-        EventType::try_from(bytes[Self::EVENT_TYPE_OFFSET])?;
-        let site_domain = &bytes[Self::SITE_DOMAIN_OFFSET..];
+        let body = &bytes[header_len..];
+        EventType::try_from(body[Self::EVENT_TYPE_OFFSET])?;
+        let site_domain = &body[Self::SITE_DOMAIN_OFFSET..];
         if !site_domain.is_ascii() {
             return Err(NonAsciiStringError::from(site_domain).into());
         }
         Ok(Self {
+            version,
             data: bytes,
             phantom_data: PhantomData,
         })
@@ -403,7 +521,8 @@ where
             HELPER_ORIGIN,
             self.site_domain(),
         )
-        .unwrap(); // validated on construction
+        .unwrap() // validated on construction
+        .with_report_version(self.version);
 
         let mut ct_mk: GenericArray<u8, CTMKLength> =
             *GenericArray::from_slice(self.mk_ciphertext());
@@ -513,7 +632,8 @@ where
     /// # Panics
     /// If report length does not fit in `u16`.
     pub fn encrypted_len(&self) -> u16 {
-        let len = EncryptedOprfReport::<BK, TV, TS, &[u8]>::SITE_DOMAIN_OFFSET
+        let len = ReportVersion::CURRENT.header_len()
+            + EncryptedOprfReport::<BK, TV, TS, &[u8]>::SITE_DOMAIN_OFFSET
             + self.site_domain.as_bytes().len();
         len.try_into().unwrap()
     }
@@ -553,6 +673,22 @@ where
         key_registry: &impl PublicKeyRegistry,
         rng: &mut R,
         out: &mut B,
+    ) -> Result<(), InvalidReportError> {
+        self.encrypt_as_to(ReportVersion::CURRENT, key_id, key_registry, rng, out)
+    }
+
+    /// Encrypts the report in the format of `version` rather than the current one, which only
+    /// tests need to do.
+    ///
+    /// # Errors
+    /// If there is a problem encrypting the report.
+    pub(crate) fn encrypt_as_to<R: CryptoRng + RngCore, B: BufMut>(
+        &self,
+        version: ReportVersion,
+        key_id: KeyIdentifier,
+        key_registry: &impl PublicKeyRegistry,
+        rng: &mut R,
+        out: &mut B,
     ) -> Result<(), InvalidReportError> {
         let info = Info::new(
             key_id,
@@ -560,7 +696,8 @@ where
             self.event_type,
             HELPER_ORIGIN,
             self.site_domain.as_ref(),
-        )?;
+        )?
+        .with_report_version(version);
 
         let mut plaintext_mk = GenericArray::default();
         self.match_key.serialize(&mut plaintext_mk);
@@ -583,6 +720,9 @@ where
         let (encap_key_btt, ciphertext_btt, tag_btt) =
             seal_in_place(key_registry, plaintext_btt.as_mut(), &info, rng)?;
 
+        if let Some(version) = version.byte() {
+            out.put_u8(version);
+        }
         out.put_slice(&encap_key_mk.to_bytes());
         out.put_slice(ciphertext_mk);
         out.put_slice(&tag_mk.to_bytes());
@@ -605,11 +745,13 @@ mod test {
     use super::*;
     use crate::{
         ff::boolean_array::{BA20, BA3, BA8},
-        hpke::{Deserializable, IpaPrivateKey, IpaPublicKey, KeyPair, KeyRegistry},
+        hpke::{KeyPair, KeyRegistry},
         report,
-        report::EventType::{Source, Trigger},
+        report::{
+            test_vectors::{self, TestVectors},
+            EventType::{Source, Trigger},
+        },
         secret_sharing::replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
-        test_fixture::Reconstruct,
     };
 
     #[test]
@@ -679,7 +821,7 @@ mod test {
     #[test]
     fn invalid_event_type() {
         let bytes = hex::decode(
-            "2879655662559e44389efb0cb27675b0571f878623411364c525f8201f94\
+            "012879655662559e44389efb0cb27675b0571f878623411364c525f8201f94\
             c449df144ed7087b5d628615028b55483a0f675494c4ab0f8ba92625921cf71406\
             2055ab3d676cada0505745e9f8c25a269da20c81019a4db50212090073067b9400\
             28672642880bdc9a4b8eafc9f0a8a0a350f66447aaab563c8a5603007d06626232\
@@ -696,7 +838,7 @@ mod test {
     #[test]
     fn invalid_site_domain() {
         let bytes = hex::decode(
-            "2879655662559e44389efb0cb27675b0571f878623411364c525f8201f94\
+            "012879655662559e44389efb0cb27675b0571f878623411364c525f8201f94\
             c449df144ed7087b5d628615028b55483a0f675494c4ab0f8ba92625921cf71406\
             2055ab3d676cada0505745e9f8c25a269da20c81019a4db50212090073067b9400\
             28672642880bdc9a4b8eafc9f0a8a0a350f66447aaab563c8a5601007d06626232\
@@ -710,213 +852,118 @@ mod test {
         assert!(matches!(err, InvalidReportError::NonAsciiString(_)));
     }
 
-    struct RawReport {
-        event_type: EventType,
-        epoch: u16,
-        site_domain: String,
-        matchkey: u128,
-        trigger_value: u128,
-        breakdown_key: u128,
-        timestamp: u128,
-    }
-
-    fn decrypt_report(
-        pk: &[u8],
-        sk: &[u8],
-        encrypted_report_bytes: &[u8],
-        expected: &RawReport,
-    ) -> OprfReport<BA8, BA3, BA20> {
-        let key_registry1 = KeyRegistry::<KeyPair>::from_keys([KeyPair::from((
-            IpaPrivateKey::from_bytes(sk).unwrap(),
-            IpaPublicKey::from_bytes(pk).unwrap(),
-        ))]);
-
-        let enc_report = EncryptedOprfReport::from_bytes(encrypted_report_bytes).unwrap();
-        let dec_report: OprfReport<BA8, BA3, BA20> = enc_report.decrypt(&key_registry1).unwrap();
-
-        assert_eq!(dec_report.event_type, expected.event_type);
-        assert_eq!(dec_report.epoch, expected.epoch);
-        assert_eq!(dec_report.site_domain, expected.site_domain);
-
-        dec_report
-    }
-
-    fn validate_blobs(
-        enc_report_bytes1: &[u8],
-        enc_report_bytes2: &[u8],
-        enc_report_bytes3: &[u8],
-        expected: &RawReport,
-    ) {
-        let pk = [
-            hex::decode("92a6fb666c37c008defd74abf3204ebea685742eab8347b08e2f7c759893947a")
-                .unwrap(),
-            hex::decode("cfdbaaff16b30aa8a4ab07eaad2cdd80458208a1317aefbb807e46dce596617e")
-                .unwrap(),
-            hex::decode("b900be35da06106a83ed73c33f733e03e4ea5888b7ea4c912ab270b0b0f8381e")
-                .unwrap(),
-        ];
-        let sk = [
-            hex::decode("53d58e022981f2edbf55fec1b45dbabd08a3442cb7b7c598839de5d7a5888bff")
-                .unwrap(),
-            hex::decode("3a0a993a3cfc7e8d381addac586f37de50c2a14b1a6356d71e94ca2afaeb2569")
-                .unwrap(),
-            hex::decode("1fb5c5274bf85fbe6c7935684ef05499f6cfb89ac21640c28330135cc0e8a0f7")
-                .unwrap(),
-        ];
-
-        let dec_report1: OprfReport<BA8, BA3, BA20> = decrypt_report(
-            pk[0].as_slice(),
-            sk[0].as_slice(),
-            enc_report_bytes1,
-            expected,
-        );
-
-        let dec_report2: OprfReport<BA8, BA3, BA20> = decrypt_report(
-            pk[1].as_slice(),
-            sk[1].as_slice(),
-            enc_report_bytes2,
-            expected,
-        );
-
-        let dec_report3: OprfReport<BA8, BA3, BA20> = decrypt_report(
-            pk[2].as_slice(),
-            sk[2].as_slice(),
-            enc_report_bytes3,
-            expected,
-        );
-
-        assert_eq!(
-            [
-                dec_report1.match_key,
-                dec_report2.match_key,
-                dec_report3.match_key
-            ]
-            .reconstruct(),
-            expected.matchkey
-        );
-        assert_eq!(
-            [
-                dec_report1.breakdown_key,
-                dec_report2.breakdown_key,
-                dec_report3.breakdown_key
-            ]
-            .reconstruct(),
-            expected.breakdown_key
-        );
-        assert_eq!(
-            [
-                dec_report1.trigger_value,
-                dec_report2.trigger_value,
-                dec_report3.trigger_value
-            ]
-            .reconstruct(),
-            expected.trigger_value
-        );
-        assert_eq!(
-            [
-                dec_report1.timestamp,
-                dec_report2.timestamp,
-                dec_report3.timestamp
-            ]
-            .reconstruct(),
-            expected.timestamp
-        );
-    }
-
     #[test]
-    fn check_compatibility_impressionmk_with_ios_encryption() {
-        let enc_report_bytes1 = hex::decode(
-            "12854879d86ef277cd70806a7f6bad269877adc95ee107380381caf15b841a7e995e41\
-        4c63a9d82f834796cdd6c40529189fca82720714d24200d8a916a1e090b123f27eaf24\
-        f047f3930a77e5bcd33eeb823b73b0e9546c59d3d6e69383c74ae72b79645698fe1422\
-        f83886bd3cbca9fbb63f7019e2139191dd000000007777772e6d6574612e636f6d",
-        )
-        .unwrap();
-        let enc_report_bytes2 = hex::decode(
-            "1d85741b3edf3f49e8ed5824b8ea0ed156301fb6d450fc30ad76785fc3b281775\
-            937d0275efc237d3e3ac92e22cf60ebd8dc09a41abaa20c0a7ee9e5e1c736708c0\
-            1dd65f592e5683f8ca0e23f8bfcd3a7736335cc5bec95beceb6474abb816b01f9a\
-            df7cc12c344c1538bb84c98b089b24733790032e70c7406000000007777772e6d6\
-            574612e636f6d",
-        )
-        .unwrap();
-        let enc_report_bytes3 = hex::decode(
-            "545f9df229a16c70497dd1f93ac75bef8ad33e836bb20f2ff37297bd814a09138\
-            9d85db9007e7b95231a3e5a0055ae59dc56d431849c0aaf5e01e66c8e6b7888bf2\
-            99f66907861798097aba96aae193d59b7fcafd5655e745f4b4ae51631c6342e36e\
-            e3b6f1682385b46295b7ce0128af02f6828cba562bf0c12000000007777772e6d6\
-            574612e636f6d",
+    fn unsupported_version() {
+        let mut bytes = hex::decode(
+            test_vectors::TestVectors::from_json(test_vectors::V1)
+                .unwrap()
+                .vectors[0]
+                .ciphertexts[0]
+                .as_str(),
         )
         .unwrap();
+        bytes[0] = 2;
 
-        assert_eq!(enc_report_bytes1.len(), 138);
-        assert_eq!(enc_report_bytes2.len(), 138);
-        assert_eq!(enc_report_bytes3.len(), 138);
+        let err = EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(bytes.as_slice())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            InvalidReportError::UnsupportedVersion(UnsupportedReportVersion(2))
+        ));
+
+        let err = EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(&[][..])
+            .err()
+            .unwrap();
+        assert!(matches!(err, InvalidReportError::Length(0, _)));
+    }
 
-        let expected = RawReport {
-            event_type: EventType::Source,
+    #[test]
+    fn version_is_bound_to_encryption() {
+        let mut rng = thread_rng();
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let report = OprfReport::<BA8, BA3, BA20> {
+            match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            event_type: Source,
+            breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            trigger_value: AdditiveShare::new(rng.gen(), rng.gen()),
+            timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
             epoch: 0,
-            site_domain: String::from("www.meta.com"),
-            matchkey: 1,
-            trigger_value: 0,
-            breakdown_key: 45,
-            timestamp: 456,
+            site_domain: "www.example.com".to_owned(),
         };
+        let bytes = report
+            .encrypt(DEFAULT_KEY_ID, &key_registry, &mut rng)
+            .unwrap();
+        assert_eq!(bytes[0], u8::from(ReportVersion::V1));
+
+        let enc_report = EncryptedOprfReport::from_bytes(bytes.as_slice()).unwrap();
+        assert_eq!(enc_report.version(), ReportVersion::V1);
+        assert_eq!(enc_report.decrypt(&key_registry).unwrap(), report);
+
+        // Without the version byte, the report parses as a legacy report, but does not decrypt.
+        let legacy =
+            EncryptedOprfReport::<BA8, BA3, BA20, _>::from_legacy_bytes(&bytes[1..]).unwrap();
+        assert_eq!(legacy.version(), ReportVersion::Legacy);
+        assert_eq!(legacy.site_domain(), report.site_domain);
+        assert!(matches!(
+            legacy.decrypt(&key_registry),
+            Err(InvalidReportError::Crypt(_))
+        ));
+    }
 
-        validate_blobs(
-            &enc_report_bytes1,
-            &enc_report_bytes2,
-            &enc_report_bytes3,
-            &expected,
-        );
+    fn ios_vectors(name: &str) -> TestVectors {
+        let mut vectors = TestVectors::from_json(test_vectors::IOS).unwrap();
+        vectors.vectors.retain(|vector| vector.name == name);
+        assert_eq!(vectors.vectors.len(), 1, "no test vector named {name}");
+
+        vectors
     }
 
     #[test]
-    fn check_compatibility_conversion_with_ios_encryption() {
-        let enc_report_bytes1 = hex::decode(
-            "741cd5012df1cf8f337258066a55c408d1052297af27a35bdef571773215ad7cb\
-            d367eab689145a24ad9666a12731a221ff5548cc7591a5ce50da4dcde203cc6141\
-            75759ef230641adac977187143471b512f1c8fd95eafeb53602d90a69a6411f3af\
-            9cb44e02417f6f27b7162f08bff009e82b1c2c2aaaf156f010000007777772e616\
-            2632e636f6d",
-        )
-        .unwrap();
-
-        let enc_report_bytes2 = hex::decode(
-            "effd53a97a3df4020d717409a9905210510932d894aa70430d324f2048e0b768e7f696\
-        60861ff5e73c64d71547c2245f0120957b51925bb9dfbda319ec04b79139467438e647\
-        f2b384995af9c66eab0a7943c9ee7a4238c08f5aa52ca460936a89b7ea07a171ff6e3c\
-        247ae1d30a43be78b46db7f638050a8fcf010000007777772e6162632e636f6d",
-        )
-        .unwrap();
+    fn check_compatibility_impressionmk_with_ios_encryption() {
+        let vectors = ios_vectors("ios_impression");
+        let expected = &vectors.vectors[0];
 
-        let enc_report_bytes3 = hex::decode(
-            "e708bd1d032ea399964e2f1e2dfe3145203cfc079f519f00e8e789db412f297c9d02e0\
-        0cc38c3dd3d3cff2771d3811c70b1f37b334402216ca664f224e34900c641edb48469b\
-        cf1f09f34fd2a7775d886e5a770e6c6d2089595c87300c87962c3481aec4b4bc1f3f4f\
-        3944c3143e590e1e2c87d2cbd91eabe6be010000007777772e6162632e636f6d",
-        )
-        .unwrap();
+        assert_eq!(
+            expected.ciphertexts.each_ref().map(String::len),
+            [2 * 138; 3]
+        );
+        assert_eq!(expected.event_type, Source);
+        assert_eq!(expected.site_domain, "www.meta.com");
+        assert_eq!(
+            (
+                expected.match_key,
+                expected.breakdown_key,
+                expected.trigger_value,
+                expected.timestamp
+            ),
+            (1, 45, 0, 456)
+        );
 
-        assert_eq!(enc_report_bytes1.len(), 137);
-        assert_eq!(enc_report_bytes2.len(), 137);
-        assert_eq!(enc_report_bytes3.len(), 137);
+        vectors.verify().unwrap();
+    }
 
-        let expected = RawReport {
-            event_type: EventType::Trigger,
-            epoch: 0,
-            site_domain: String::from("www.abc.com"),
-            matchkey: 1,
-            trigger_value: 5,
-            breakdown_key: 0,
-            timestamp: 123,
-        };
+    #[test]
+    fn check_compatibility_conversion_with_ios_encryption() {
+        let vectors = ios_vectors("ios_conversion");
+        let expected = &vectors.vectors[0];
 
-        validate_blobs(
-            &enc_report_bytes1,
-            &enc_report_bytes2,
-            &enc_report_bytes3,
-            &expected,
+        assert_eq!(
+            expected.ciphertexts.each_ref().map(String::len),
+            [2 * 137; 3]
         );
+        assert_eq!(expected.event_type, Trigger);
+        assert_eq!(expected.site_domain, "www.abc.com");
+        assert_eq!(
+            (
+                expected.match_key,
+                expected.breakdown_key,
+                expected.trigger_value,
+                expected.timestamp
+            ),
+            (1, 0, 5, 123)
+        );
+
+        vectors.verify().unwrap();
     }
 }
@@ -1,3 +1,6 @@
 pub mod ipa;
 pub use self::ipa::*;
 pub mod hybrid;
+#[cfg(any(test, feature = "test-fixture"))]
+pub mod test_vectors;
+pub mod validation;
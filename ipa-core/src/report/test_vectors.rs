@@ -0,0 +1,547 @@
+//! Test vectors for the encrypted report format.
+//!
+//! The vectors are shared with every other encoder of [`EncryptedOprfReport`] (`ipa-client`, the
+//! SDKs of partners), so that all of them agree with the helpers byte for byte. They are checked
+//! in as JSON under `report/test_vectors`, one file per [`ReportVersion`], along with a
+//! description of the format for implementers:
+//!
+//! * `v1.json` is generated by `crypto_util test-vectors` from a fixed seed. All the randomness
+//!   that goes into a report, that is the helper keys, the shares and the HPKE ephemeral keys, is
+//!   derived from the seed with SHA-256, so that other implementations can reproduce the
+//!   ciphertexts exactly.
+//! * `ios.json` holds reports encrypted by the iOS client, exactly as it produced them. These are
+//!   [`ReportVersion::Legacy`] reports, without a version byte.
+//!
+//! [`EncryptedOprfReport`]: crate::report::EncryptedOprfReport
+
+use std::{array, collections::VecDeque};
+
+use generic_array::GenericArray;
+use hpke::{kem::X25519HkdfSha256, Deserializable, Kem, Serializable as _};
+use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::BoxError,
+    ff::{
+        boolean_array::{BA20, BA3, BA64, BA8},
+        Serializable, U128Conversions,
+    },
+    hpke::{IpaPrivateKey, IpaPublicKey, KeyPair, KeyRegistry},
+    report::{
+        EncryptedOprfReport, Epoch, EventType, KeyIdentifier, OprfReport, ReportVersion,
+        DEFAULT_KEY_ID,
+    },
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare as Replicated, ReplicatedSecretSharing},
+        SharedValue,
+    },
+};
+
+/// Vectors for [`ReportVersion::V1`], generated from [`DEFAULT_SEED`].
+pub const V1: &str = include_str!("test_vectors/v1.json");
+
+/// Reports encrypted by the iOS client, in the [`ReportVersion::Legacy`] format.
+pub const IOS: &str = include_str!("test_vectors/ios.json");
+
+/// Seed that the checked-in vectors are generated from.
+pub const DEFAULT_SEED: u64 = 1;
+
+const DESCRIPTION: &str = "Encrypted reports for the helpers, see README.md in this directory \
+for the format. Each report is secret-shared between the three helpers and encrypted for each \
+of them. `shares` holds, for each helper, the plaintexts of the match key and btt (timestamp, \
+breakdown key and trigger value) ciphertexts, and the input keying material that the HPKE \
+ephemeral key of each ciphertext is derived from.";
+
+/// The reports that [`TestVectors::generate`] produces vectors for.
+const REPORTS: [Report; 6] = [
+    Report {
+        name: "source",
+        event_type: EventType::Source,
+        epoch: 0,
+        site_domain: "www.meta.com",
+        match_key: 1,
+        breakdown_key: 45,
+        trigger_value: 0,
+        timestamp: 456,
+    },
+    Report {
+        name: "trigger",
+        event_type: EventType::Trigger,
+        epoch: 0,
+        site_domain: "www.abc.com",
+        match_key: 1,
+        breakdown_key: 0,
+        trigger_value: 5,
+        timestamp: 123,
+    },
+    Report {
+        name: "zero_values",
+        event_type: EventType::Source,
+        epoch: 0,
+        site_domain: "a",
+        match_key: 0,
+        breakdown_key: 0,
+        trigger_value: 0,
+        timestamp: 0,
+    },
+    Report {
+        name: "max_values",
+        event_type: EventType::Trigger,
+        epoch: u16::MAX,
+        site_domain: "www.example.com",
+        match_key: u64::MAX,
+        breakdown_key: u8::MAX,
+        trigger_value: 7,
+        timestamp: (1 << 20) - 1,
+    },
+    Report {
+        name: "epoch_byte_order",
+        event_type: EventType::Source,
+        epoch: 0x0102,
+        site_domain: "www.example.org",
+        match_key: 0x0123_4567_89ab_cdef,
+        breakdown_key: 0x81,
+        trigger_value: 0,
+        timestamp: 0x5_4321,
+    },
+    Report {
+        name: "long_site_domain",
+        event_type: EventType::Trigger,
+        epoch: 17,
+        site_domain: "checkout.subdomain.of.a.rather.long.registrable-domain.example.co.uk",
+        match_key: 0xfedc_ba98_7654_3210,
+        breakdown_key: 0,
+        trigger_value: 6,
+        timestamp: 0x7fff,
+    },
+];
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    /// The [`ReportVersion`] of the ciphertexts, `0` for [`ReportVersion::Legacy`].
+    pub version: u8,
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    pub helper_keys: [HelperKeys; 3],
+    pub vectors: Vec<TestVector>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HelperKeys {
+    #[serde(with = "hex")]
+    pub public_key: Vec<u8>,
+    #[serde(with = "hex")]
+    pub private_key: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    pub name: String,
+    pub key_id: KeyIdentifier,
+    #[serde(with = "event_type")]
+    pub event_type: EventType,
+    pub epoch: Epoch,
+    pub site_domain: String,
+    pub match_key: u64,
+    pub breakdown_key: u8,
+    pub trigger_value: u8,
+    pub timestamp: u32,
+    /// Only known for generated vectors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shares: Option<[HelperShares; 3]>,
+    pub ciphertexts: [String; 3],
+}
+
+/// What a single helper receives in a report, before it is encrypted.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HelperShares {
+    #[serde(with = "hex")]
+    pub match_key: Vec<u8>,
+    #[serde(with = "hex")]
+    pub btt: Vec<u8>,
+    #[serde(with = "hex")]
+    pub match_key_ikm: Vec<u8>,
+    #[serde(with = "hex")]
+    pub btt_ikm: Vec<u8>,
+}
+
+struct Report {
+    name: &'static str,
+    event_type: EventType,
+    epoch: Epoch,
+    site_domain: &'static str,
+    match_key: u64,
+    breakdown_key: u8,
+    trigger_value: u8,
+    timestamp: u32,
+}
+
+impl TestVectors {
+    /// Generates vectors for the current [`ReportVersion`]. The output only depends on `seed`.
+    ///
+    /// ## Panics
+    /// If a report cannot be encrypted.
+    #[must_use]
+    pub fn generate(seed: u64) -> Self {
+        let key_pairs: [_; 3] = array::from_fn(|i| {
+            X25519HkdfSha256::derive_keypair(&derive(seed, &format!("helper_key/{}", i + 1)))
+        });
+
+        Self {
+            version: ReportVersion::CURRENT.into(),
+            description: DESCRIPTION.to_string(),
+            seed: Some(seed),
+            helper_keys: key_pairs.each_ref().map(|(sk, pk)| HelperKeys {
+                public_key: pk.to_bytes().to_vec(),
+                private_key: sk.to_bytes().to_vec(),
+            }),
+            vectors: REPORTS
+                .iter()
+                .map(|report| report.encrypt(seed, &key_pairs))
+                .collect(),
+        }
+    }
+
+    /// ## Errors
+    /// If the JSON is not valid.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// ## Panics
+    /// Never.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let mut json = serde_json::to_string_pretty(self).unwrap();
+        json.push('\n');
+        json
+    }
+
+    /// Checks that every ciphertext decrypts with the helper keys to the shares in the vector,
+    /// and that the shares reconstruct to the report in the vector.
+    ///
+    /// ## Errors
+    /// Describing the first vector that does not check out.
+    pub fn verify(&self) -> Result<(), BoxError> {
+        let version = match self.version {
+            0 => ReportVersion::Legacy,
+            version => ReportVersion::try_from(version)?,
+        };
+        let key_registries = self
+            .helper_keys
+            .iter()
+            .map(HelperKeys::key_registry)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for vector in &self.vectors {
+            vector
+                .verify(version, &key_registries)
+                .map_err(|e| format!("test vector {}: {e}", vector.name))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl HelperKeys {
+    fn key_registry(&self) -> Result<KeyRegistry<KeyPair>, BoxError> {
+        let key_pair = KeyPair::from((
+            IpaPrivateKey::from_bytes(&self.private_key)?,
+            IpaPublicKey::from_bytes(&self.public_key)?,
+        ));
+        Ok(KeyRegistry::from_keys([key_pair]))
+    }
+}
+
+impl TestVector {
+    fn verify(
+        &self,
+        version: ReportVersion,
+        key_registries: &[KeyRegistry<KeyPair>],
+    ) -> Result<(), BoxError> {
+        let mut reports = Vec::with_capacity(3);
+        for (i, (ciphertext, key_registry)) in
+            self.ciphertexts.iter().zip(key_registries).enumerate()
+        {
+            let ciphertext = hex::decode(ciphertext)?;
+            let encrypted = match version {
+                ReportVersion::Legacy => {
+                    EncryptedOprfReport::<BA8, BA3, BA20, _>::from_legacy_bytes(
+                        ciphertext.as_slice(),
+                    )?
+                }
+                ReportVersion::V1 => {
+                    EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(ciphertext.as_slice())?
+                }
+            };
+            if encrypted.version() != version
+                || encrypted.key_id() != self.key_id
+                || encrypted.event_type() != self.event_type
+                || encrypted.epoch() != self.epoch
+                || encrypted.site_domain() != self.site_domain
+            {
+                return Err(format!("helper {}: fields in the clear do not match", i + 1).into());
+            }
+
+            let report = encrypted.decrypt(key_registry)?;
+            if let Some(shares) = &self.shares {
+                let (match_key, btt) = plaintexts(&report);
+                if match_key != shares[i].match_key || btt != shares[i].btt {
+                    return Err(format!("helper {}: decrypted shares do not match", i + 1).into());
+                }
+            }
+            reports.push(report);
+        }
+
+        let reconstructed = [
+            reconstruct("match key", reports.iter().map(|r| &r.match_key))?,
+            reconstruct("breakdown key", reports.iter().map(|r| &r.breakdown_key))?,
+            reconstruct("trigger value", reports.iter().map(|r| &r.trigger_value))?,
+            reconstruct("timestamp", reports.iter().map(|r| &r.timestamp))?,
+        ];
+        let expected = [
+            u128::from(self.match_key),
+            u128::from(self.breakdown_key),
+            u128::from(self.trigger_value),
+            u128::from(self.timestamp),
+        ];
+        if reconstructed != expected {
+            return Err(format!("reconstructed {reconstructed:?}, expected {expected:?}").into());
+        }
+
+        Ok(())
+    }
+}
+
+impl Report {
+    fn encrypt(&self, seed: u64, key_pairs: &[(IpaPrivateKey, IpaPublicKey); 3]) -> TestVector {
+        let share = |field: &str, value: u64, bits: u32| {
+            let random = |n: u8| {
+                let digest = derive(seed, &format!("{}/{field}/{n}", self.name));
+                u64::from_le_bytes(digest[..8].try_into().unwrap()) & (u64::MAX >> (64 - bits))
+            };
+            let (x1, x2) = (random(1), random(2));
+            let x3 = value ^ x1 ^ x2;
+            [(x1, x2), (x2, x3), (x3, x1)]
+        };
+        let match_key = share("match_key", self.match_key, 64);
+        let breakdown_key = share("breakdown_key", self.breakdown_key.into(), 8);
+        let trigger_value = share("trigger_value", self.trigger_value.into(), 3);
+        let timestamp = share("timestamp", self.timestamp.into(), 20);
+
+        let mut shares = Vec::with_capacity(3);
+        let mut ciphertexts = Vec::with_capacity(3);
+        for (i, (sk, pk)) in key_pairs.iter().enumerate() {
+            let report = OprfReport::<BA8, BA3, BA20> {
+                match_key: replicated::<BA64>(match_key[i]),
+                event_type: self.event_type,
+                breakdown_key: replicated(breakdown_key[i]),
+                trigger_value: replicated(trigger_value[i]),
+                timestamp: replicated(timestamp[i]),
+                epoch: self.epoch,
+                site_domain: self.site_domain.to_string(),
+            };
+            let (match_key, btt) = plaintexts(&report);
+            let helper = i + 1;
+            let match_key_ikm = derive(seed, &format!("{}/helper/{helper}/match_key", self.name));
+            let btt_ikm = derive(seed, &format!("{}/helper/{helper}/btt", self.name));
+
+            let key_registry = KeyRegistry::from_keys([KeyPair::from((sk.clone(), pk.clone()))]);
+            let mut rng = EphemeralKeyMaterial::new([match_key_ikm, btt_ikm]);
+            let ciphertext = report
+                .encrypt(DEFAULT_KEY_ID, &key_registry, &mut rng)
+                .unwrap();
+            assert!(
+                rng.0.is_empty(),
+                "encryption did not derive the ephemeral keys from the key material"
+            );
+
+            shares.push(HelperShares {
+                match_key,
+                btt,
+                match_key_ikm: match_key_ikm.to_vec(),
+                btt_ikm: btt_ikm.to_vec(),
+            });
+            ciphertexts.push(hex::encode(ciphertext));
+        }
+
+        TestVector {
+            name: self.name.to_string(),
+            key_id: DEFAULT_KEY_ID,
+            event_type: self.event_type,
+            epoch: self.epoch,
+            site_domain: self.site_domain.to_string(),
+            match_key: self.match_key,
+            breakdown_key: self.breakdown_key,
+            trigger_value: self.trigger_value,
+            timestamp: self.timestamp,
+            shares: Some(shares.try_into().unwrap()),
+            ciphertexts: ciphertexts.try_into().unwrap(),
+        }
+    }
+}
+
+/// Derives 32 bytes of key material for `label`: `SHA-256(seed || label)`, with the seed
+/// encoded as 8 little-endian bytes.
+fn derive(seed: u64, label: &str) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(seed.to_le_bytes())
+        .chain_update(label.as_bytes())
+        .finalize()
+        .into()
+}
+
+fn replicated<V: SharedValue + U128Conversions>((left, right): (u64, u64)) -> Replicated<V> {
+    Replicated::new(V::truncate_from(left), V::truncate_from(right))
+}
+
+fn serialize<T: Serializable>(value: &T) -> Vec<u8> {
+    let mut buf = GenericArray::default();
+    value.serialize(&mut buf);
+    buf.to_vec()
+}
+
+/// The plaintexts of the match key and btt ciphertexts in `report`.
+fn plaintexts(report: &OprfReport<BA8, BA3, BA20>) -> (Vec<u8>, Vec<u8>) {
+    let mut btt = serialize(&report.timestamp);
+    btt.extend(serialize(&report.breakdown_key));
+    btt.extend(serialize(&report.trigger_value));
+
+    (serialize(&report.match_key), btt)
+}
+
+fn reconstruct<'a, V, I>(field: &str, shares: I) -> Result<u128, BoxError>
+where
+    V: SharedValue + U128Conversions,
+    I: Iterator<Item = &'a Replicated<V>>,
+{
+    let shares = shares.collect::<Vec<_>>();
+    let [s1, s2, s3] = shares.as_slice() else {
+        return Err(format!("expected 3 shares of the {field}").into());
+    };
+    if s1.right() != s2.left() || s2.right() != s3.left() || s3.right() != s1.left() {
+        return Err(format!("{field} shares are inconsistent").into());
+    }
+
+    Ok((s1.left() + s2.left() + s3.left()).as_u128())
+}
+
+/// Randomness for encrypting a report, which HPKE only uses for the ephemeral key of each
+/// ciphertext. Handing out fixed key material makes the ciphertexts deterministic.
+struct EphemeralKeyMaterial(VecDeque<u8>);
+
+impl EphemeralKeyMaterial {
+    fn new<const N: usize>(ikm: [[u8; 32]; N]) -> Self {
+        Self(ikm.into_iter().flatten().collect())
+    }
+}
+
+impl RngCore for EphemeralKeyMaterial {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        assert!(
+            dest.len() <= self.0.len(),
+            "encryption used more randomness than the ephemeral key material"
+        );
+        let n = dest.len();
+        for (byte, ikm) in dest.iter_mut().zip(self.0.drain(..n)) {
+            *byte = ikm;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for EphemeralKeyMaterial {}
+
+mod event_type {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::report::EventType;
+
+    #[allow(clippy::trivially_copy_pass_by_ref)] // required by serde
+    pub fn serialize<S: Serializer>(event_type: &EventType, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(match event_type {
+            EventType::Source => "source",
+            EventType::Trigger => "trigger",
+        })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<EventType, D::Error> {
+        match String::deserialize(d)?.as_str() {
+            "source" => Ok(EventType::Source),
+            "trigger" => Ok(EventType::Trigger),
+            other => Err(D::Error::custom(format!("unknown event type {other}"))),
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{TestVectors, DEFAULT_SEED, IOS, V1};
+    use crate::report::ReportVersion;
+
+    #[test]
+    fn v1_vectors_are_up_to_date() {
+        let generated = TestVectors::generate(DEFAULT_SEED);
+        assert_eq!(generated.version, u8::from(ReportVersion::V1));
+        assert_eq!(
+            generated.to_json(),
+            V1,
+            "v1.json is out of date, regenerate it with `crypto_util test-vectors`"
+        );
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        assert_eq!(TestVectors::generate(7), TestVectors::generate(7));
+        assert_ne!(
+            TestVectors::generate(7).helper_keys,
+            TestVectors::generate(8).helper_keys
+        );
+    }
+
+    #[test]
+    fn verifies_checked_in_vectors() {
+        for json in [V1, IOS] {
+            TestVectors::from_json(json).unwrap().verify().unwrap();
+        }
+    }
+
+    #[test]
+    fn verifies_generated_vectors() {
+        TestVectors::generate(7).verify().unwrap();
+    }
+
+    #[test]
+    fn rejects_tampered_vectors() {
+        let mut vectors = TestVectors::from_json(V1).unwrap();
+        vectors.vectors[1].trigger_value ^= 1;
+        assert!(vectors.verify().is_err());
+
+        let mut vectors = TestVectors::from_json(V1).unwrap();
+        vectors.vectors[0].shares.as_mut().unwrap()[2].btt[0] ^= 1;
+        assert!(vectors.verify().is_err());
+
+        let mut vectors = TestVectors::from_json(V1).unwrap();
+        vectors.vectors[0].epoch += 1;
+        assert!(vectors.verify().is_err());
+
+        let mut vectors = TestVectors::from_json(V1).unwrap();
+        vectors.vectors[0].ciphertexts[0].replace_range(0..2, "02");
+        assert!(vectors.verify().is_err());
+    }
+}
@@ -0,0 +1,450 @@
+//! Checks that encrypted reports are well-formed, without running a query on them.
+//!
+//! Report collectors check the three helper inputs against the public keys of the helpers with
+//! [`InputValidation`] (`report_collector validate`). Helpers check their own input with
+//! [`decrypt_report`] when a query is created with `dry_run` set, and return a
+//! [`ValidationSummary`] instead of running the protocol.
+//!
+//! Neither of them can see the secret-shared values, so for example breakdown keys that are out of
+//! range are only found by running the protocol.
+
+use std::{array, collections::BTreeMap, ops::Add};
+
+use generic_array::ArrayLength;
+use serde::{Deserialize, Serialize};
+use typenum::{Sum, U16};
+
+use crate::{
+    ff::Serializable,
+    hpke::{CryptError, PrivateKeyRegistry, PublicKeyRegistry},
+    query::ProtocolResult,
+    report::{
+        EncryptedOprfReport, Epoch, EventType, InvalidReportError, KeyIdentifier, ReportVersion,
+    },
+    secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, SharedValue},
+};
+
+/// Why a report was rejected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rejection {
+    /// The line is not hex-encoded.
+    NotHex,
+    /// The report is too short for the breakdown key, trigger value and timestamp widths.
+    Malformed,
+    UnsupportedVersion,
+    InvalidEventType,
+    InvalidSiteDomain,
+    /// The key id is not one of the keys of the helper.
+    UnknownKeyId,
+    DecryptionFailed,
+    /// The report decrypts, but the shares in it are not valid.
+    InvalidShares,
+    /// The fields in the clear (version, key id, event type, epoch and site domain) differ from
+    /// those of the same report in the input of another helper.
+    InconsistentWithOtherHelpers,
+}
+
+impl From<&InvalidReportError> for Rejection {
+    fn from(err: &InvalidReportError) -> Self {
+        match err {
+            InvalidReportError::Length(..) => Self::Malformed,
+            InvalidReportError::UnsupportedVersion(_) => Self::UnsupportedVersion,
+            InvalidReportError::BadEventType(_) => Self::InvalidEventType,
+            InvalidReportError::NonAsciiString(_) => Self::InvalidSiteDomain,
+            InvalidReportError::Crypt(CryptError::NoSuchKey(_)) => Self::UnknownKeyId,
+            InvalidReportError::Crypt(CryptError::Other) => Self::DecryptionFailed,
+            InvalidReportError::Timestamp(_) | InvalidReportError::DeserializationError(..) => {
+                Self::InvalidShares
+            }
+        }
+    }
+}
+
+impl From<InvalidReportError> for Rejection {
+    fn from(err: InvalidReportError) -> Self {
+        Self::from(&err)
+    }
+}
+
+/// Record counts for the input of one helper.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationSummary {
+    pub records: u64,
+    pub valid: u64,
+    /// Number of rejected records, by reason.
+    pub rejected: BTreeMap<Rejection, u64>,
+}
+
+impl ValidationSummary {
+    pub fn record(&mut self, result: Result<(), Rejection>) {
+        self.records += 1;
+        match result {
+            Ok(()) => self.valid += 1,
+            Err(rejection) => *self.rejected.entry(rejection).or_default() += 1,
+        }
+    }
+
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.valid == self.records
+    }
+}
+
+/// Helpers return the summary of a dry run as JSON.
+impl ProtocolResult for ValidationSummary {
+    fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
+
+/// Parses `bytes` as a report, in the legacy format if `legacy` is set, and checks that its key id
+/// is one of `key_registry`.
+///
+/// ## Errors
+/// If the report is malformed or its key id is unknown.
+pub fn check_report<'a, BK, TV, TS, P>(
+    bytes: &'a [u8],
+    legacy: bool,
+    key_registry: &P,
+) -> Result<EncryptedOprfReport<BK, TV, TS, &'a [u8]>, Rejection>
+where
+    BK: SharedValue,
+    TV: SharedValue,
+    TS: SharedValue,
+    P: PublicKeyRegistry,
+    Replicated<BK>: Serializable,
+    Replicated<TV>: Serializable,
+    Replicated<TS>: Serializable,
+    <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+    Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+        Add<<Replicated<TS> as Serializable>::Size>,
+    Sum<
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+        <Replicated<TS> as Serializable>::Size,
+    >: Add<U16>,
+    Sum<
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >,
+        U16,
+    >: ArrayLength,
+{
+    let report = EncryptedOprfReport::from_input_bytes(bytes, legacy)?;
+    if key_registry.public_key(report.key_id()).is_none() {
+        return Err(Rejection::UnknownKeyId);
+    }
+
+    Ok(report)
+}
+
+/// Parses and decrypts the report in `bytes`, which is what a helper does before running a query.
+/// The report is in the legacy format if `legacy` is set.
+///
+/// ## Errors
+/// If the report is malformed or cannot be decrypted with `key_registry`.
+pub fn decrypt_report<BK, TV, TS, P>(
+    bytes: &[u8],
+    legacy: bool,
+    key_registry: &P,
+) -> Result<(), Rejection>
+where
+    BK: SharedValue,
+    TV: SharedValue,
+    TS: SharedValue,
+    P: PrivateKeyRegistry,
+    Replicated<BK>: Serializable,
+    Replicated<TV>: Serializable,
+    Replicated<TS>: Serializable,
+    <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+    Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+        Add<<Replicated<TS> as Serializable>::Size>,
+    Sum<
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+        <Replicated<TS> as Serializable>::Size,
+    >: Add<U16>,
+    Sum<
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >,
+        U16,
+    >: ArrayLength,
+{
+    EncryptedOprfReport::<BK, TV, TS, _>::from_input_bytes(bytes, legacy)?.decrypt(key_registry)?;
+
+    Ok(())
+}
+
+/// Validation of the inputs of all three helpers, as done by a report collector before
+/// submitting a query.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputValidation {
+    pub helpers: [ValidationSummary; 3],
+}
+
+impl InputValidation {
+    /// Checks the reports in `inputs`, one input for each helper, with the public keys of the
+    /// helpers. Reports at the same position in each input are expected to be shares of the same
+    /// report, with the same fields in the clear. The reports are in the legacy format if
+    /// `legacy` is set.
+    #[must_use]
+    pub fn validate<BK, TV, TS, I, P>(inputs: [I; 3], legacy: bool, key_registries: [&P; 3]) -> Self
+    where
+        BK: SharedValue,
+        TV: SharedValue,
+        TS: SharedValue,
+        I: IntoIterator<Item = Result<Vec<u8>, Rejection>>,
+        P: PublicKeyRegistry,
+        Replicated<BK>: Serializable,
+        Replicated<TV>: Serializable,
+        Replicated<TS>: Serializable,
+        <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+            Add<<Replicated<TS> as Serializable>::Size>,
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >: Add<U16>,
+        Sum<
+            Sum<
+                Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+                <Replicated<TS> as Serializable>::Size,
+            >,
+            U16,
+        >: ArrayLength,
+    {
+        let mut validation = Self::default();
+        let mut inputs = inputs.map(IntoIterator::into_iter);
+        loop {
+            let records = inputs.each_mut().map(Iterator::next);
+            if records.iter().all(Option::is_none) {
+                break;
+            }
+
+            let results: [_; 3] = array::from_fn(|i| {
+                records[i].as_ref().map(|bytes| {
+                    bytes
+                        .as_ref()
+                        .map_err(|rejection| *rejection)
+                        .and_then(|bytes| {
+                            check_report::<BK, TV, TS, _>(bytes, legacy, key_registries[i])
+                        })
+                })
+            });
+            let consistent = match results.each_ref() {
+                [Some(Ok(r1)), Some(Ok(r2)), Some(Ok(r3))] => {
+                    clear_fields(r1) == clear_fields(r2) && clear_fields(r2) == clear_fields(r3)
+                }
+                _ => true,
+            };
+
+            for (summary, result) in validation.helpers.iter_mut().zip(results) {
+                if let Some(result) = result {
+                    summary.record(match result {
+                        Ok(_) if !consistent => Err(Rejection::InconsistentWithOtherHelpers),
+                        result => result.map(|_| ()),
+                    });
+                }
+            }
+        }
+
+        validation
+    }
+
+    #[must_use]
+    pub fn record_counts(&self) -> [u64; 3] {
+        self.helpers.each_ref().map(|summary| summary.records)
+    }
+
+    /// Whether the helpers have the same number of records, and all of them are valid.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        let [c1, c2, c3] = self.record_counts();
+        c1 == c2 && c2 == c3 && self.helpers.iter().all(ValidationSummary::is_valid)
+    }
+}
+
+fn clear_fields<'r, BK, TV, TS>(
+    report: &'r EncryptedOprfReport<BK, TV, TS, &[u8]>,
+) -> (ReportVersion, KeyIdentifier, EventType, Epoch, &'r str)
+where
+    BK: SharedValue,
+    TV: SharedValue,
+    TS: SharedValue,
+    Replicated<BK>: Serializable,
+    Replicated<TV>: Serializable,
+    Replicated<TS>: Serializable,
+    <Replicated<BK> as Serializable>::Size: Add<<Replicated<TV> as Serializable>::Size>,
+    Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>:
+        Add<<Replicated<TS> as Serializable>::Size>,
+    Sum<
+        Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+        <Replicated<TS> as Serializable>::Size,
+    >: Add<U16>,
+    Sum<
+        Sum<
+            Sum<<Replicated<BK> as Serializable>::Size, <Replicated<TV> as Serializable>::Size>,
+            <Replicated<TS> as Serializable>::Size,
+        >,
+        U16,
+    >: ArrayLength,
+{
+    (
+        report.version(),
+        report.key_id(),
+        report.event_type(),
+        report.epoch(),
+        report.site_domain(),
+    )
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::thread_rng;
+
+    use super::{decrypt_report, InputValidation, Rejection, ValidationSummary};
+    use crate::{
+        ff::boolean_array::{BA20, BA3, BA8},
+        hpke::{KeyPair, KeyRegistry},
+        report::{OprfReport, DEFAULT_KEY_ID},
+        secret_sharing::IntoShares,
+        test_fixture::ipa::TestRawDataRecord,
+    };
+
+    /// Offset of the key id in reports with these breakdown key, trigger value and timestamp
+    /// widths.
+    const KEY_ID_OFFSET: usize = 124;
+
+    fn encrypted_inputs(
+        records: u64,
+        key_registry: &KeyRegistry<KeyPair>,
+    ) -> [Vec<Result<Vec<u8>, Rejection>>; 3] {
+        let mut rng = thread_rng();
+        let records = (0..records).map(|i| TestRawDataRecord {
+            timestamp: i,
+            user_id: 1,
+            is_trigger_report: i % 2 == 1,
+            breakdown_key: 1,
+            trigger_value: 2,
+        });
+        let shares: [Vec<OprfReport<BA8, BA3, BA20>>; 3] = records.share_with(&mut rng);
+
+        shares.map(|shares| {
+            shares
+                .iter()
+                .map(|share| {
+                    Ok(share
+                        .encrypt(DEFAULT_KEY_ID, key_registry, &mut rng)
+                        .unwrap())
+                })
+                .collect()
+        })
+    }
+
+    fn validate(
+        inputs: [Vec<Result<Vec<u8>, Rejection>>; 3],
+        key_registry: &KeyRegistry<KeyPair>,
+    ) -> InputValidation {
+        InputValidation::validate::<BA8, BA3, BA20, _, _>(inputs, false, [key_registry; 3])
+    }
+
+    #[test]
+    fn accepts_valid_inputs() {
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut thread_rng());
+        let validation = validate(encrypted_inputs(4, &key_registry), &key_registry);
+
+        assert!(validation.is_valid());
+        assert_eq!(validation.record_counts(), [4; 3]);
+    }
+
+    #[test]
+    fn counts_rejections() {
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut thread_rng());
+        let mut inputs = encrypted_inputs(6, &key_registry);
+        inputs[0][0] = Err(Rejection::NotHex);
+        inputs[0][1].as_mut().unwrap().truncate(10);
+        inputs[1][2].as_mut().unwrap()[0] = 2;
+        inputs[2][3].as_mut().unwrap()[KEY_ID_OFFSET] = 1;
+        // a different site domain for helper 1
+        *inputs[0][4].as_mut().unwrap().last_mut().unwrap() = b'!';
+
+        let validation = validate(inputs, &key_registry);
+        assert!(!validation.is_valid());
+        let rejected = validation
+            .helpers
+            .each_ref()
+            .map(|summary| summary.rejected.clone().into_iter().collect::<Vec<_>>());
+        assert_eq!(
+            rejected,
+            [
+                vec![
+                    (Rejection::NotHex, 1),
+                    (Rejection::Malformed, 1),
+                    (Rejection::InconsistentWithOtherHelpers, 1)
+                ],
+                vec![
+                    (Rejection::UnsupportedVersion, 1),
+                    (Rejection::InconsistentWithOtherHelpers, 1)
+                ],
+                vec![
+                    (Rejection::UnknownKeyId, 1),
+                    (Rejection::InconsistentWithOtherHelpers, 1)
+                ],
+            ]
+        );
+        assert_eq!(validation.helpers[0].valid, 3);
+    }
+
+    #[test]
+    fn rejects_different_record_counts() {
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut thread_rng());
+        let mut inputs = encrypted_inputs(3, &key_registry);
+        inputs[1].pop();
+
+        let validation = validate(inputs, &key_registry);
+        assert!(validation.helpers.iter().all(ValidationSummary::is_valid));
+        assert_eq!(validation.record_counts(), [3, 2, 3]);
+        assert!(!validation.is_valid());
+    }
+
+    #[test]
+    fn decrypts_reports() {
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut thread_rng());
+        let other_keys = KeyRegistry::<KeyPair>::random(1, &mut thread_rng());
+        let [mut input, ..] = encrypted_inputs(1, &key_registry);
+        let mut report = input.pop().unwrap().unwrap();
+
+        assert_eq!(
+            decrypt_report::<BA8, BA3, BA20, _>(&report, false, &key_registry),
+            Ok(())
+        );
+        assert_eq!(
+            decrypt_report::<BA8, BA3, BA20, _>(&report, false, &other_keys),
+            Err(Rejection::DecryptionFailed)
+        );
+        report[10] ^= 1;
+        assert_eq!(
+            decrypt_report::<BA8, BA3, BA20, _>(&report, false, &key_registry),
+            Err(Rejection::DecryptionFailed)
+        );
+    }
+
+    #[test]
+    fn summary_round_trips_as_json() {
+        let mut summary = ValidationSummary::default();
+        summary.record(Ok(()));
+        summary.record(Err(Rejection::UnknownKeyId));
+
+        let json = serde_json::to_string(&summary).unwrap();
+        assert_eq!(
+            json,
+            r#"{"records":2,"valid":1,"rejected":{"unknown_key_id":1}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ValidationSummary>(&json).unwrap(),
+            summary
+        );
+    }
+}
@@ -0,0 +1,405 @@
+//! Client for report collectors, to run queries on the helper network.
+//!
+//! [`ReportCollector`] drives a query through the three helpers: it creates the query on the
+//! leader, uploads the input shares, waits for the helpers to finish and reconstructs the result
+//! from the shares they return.
+//!
+//! ```no_run
+//! # use ipa_core::{
+//! #     config::NetworkConfig, ff::boolean_array::BA32, helpers::{query::QueryConfig, BodyStream},
+//! #     report_collector::{Error, ReportCollector},
+//! # };
+//! # async fn run(network: &NetworkConfig, config: QueryConfig, inputs: [BodyStream; 3]) -> Result<(), Error> {
+//! let collector = ReportCollector::from_network(network);
+//! let query = collector.submit(config, inputs).await?;
+//! let histogram = query.results::<BA32>().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{cmp::min, future::Future, time::Duration};
+
+use futures::future::{join3, try_join3};
+use hyper::StatusCode;
+use tokio::time::sleep;
+use typenum::Unsigned;
+
+use crate::{
+    config::NetworkConfig,
+    error::BoxError,
+    ff::Serializable,
+    helpers::{
+        query::{QueryConfig, QueryInput},
+        BodyStream, HelperIdentity,
+    },
+    net::{self, ClientIdentity, MpcHelperClient},
+    protocol::QueryId,
+    query::QueryStatus,
+    report::validation::ValidationSummary,
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+        SharedValue,
+    },
+};
+
+/// The first status poll happens this long after the inputs are uploaded. Subsequent polls back
+/// off exponentially, up to [`MAX_POLL_INTERVAL`].
+const INITIAL_POLL_INTERVAL: Duration = Duration::from_millis(125);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("request to helper {helper:?} failed: {source}")]
+    Helper {
+        helper: HelperIdentity,
+        #[source]
+        source: net::Error,
+    },
+    #[error("helper {helper:?} returned malformed results: {source}")]
+    MalformedResults {
+        helper: HelperIdentity,
+        #[source]
+        source: BoxError,
+    },
+    #[error("helpers returned results of different lengths: {lengths:?}")]
+    ResultLengthMismatch { lengths: [usize; 3] },
+    #[error("result shares at index {index} are inconsistent")]
+    InconsistentShares { index: usize },
+}
+
+impl Error {
+    /// The helper that this error came from, if it came from a single helper.
+    #[must_use]
+    pub fn helper(&self) -> Option<HelperIdentity> {
+        match self {
+            Self::Helper { helper, .. } | Self::MalformedResults { helper, .. } => Some(*helper),
+            Self::ResultLengthMismatch { .. } | Self::InconsistentShares { .. } => None,
+        }
+    }
+}
+
+/// Runs queries on behalf of a report collector, using a client for each of the three helpers.
+pub struct ReportCollector {
+    clients: [MpcHelperClient; 3],
+}
+
+impl ReportCollector {
+    /// Creates a report collector that talks to the helpers through `clients`. The first client
+    /// must be for the helper that coordinates queries.
+    #[must_use]
+    pub fn new(clients: [MpcHelperClient; 3]) -> Self {
+        Self { clients }
+    }
+
+    /// Creates a report collector for the helpers in `network`. Report collectors do not
+    /// authenticate to the helpers.
+    #[must_use]
+    pub fn from_network(network: &NetworkConfig) -> Self {
+        Self::new(MpcHelperClient::from_conf(network, &ClientIdentity::None))
+    }
+
+    #[must_use]
+    pub fn clients(&self) -> &[MpcHelperClient; 3] {
+        &self.clients
+    }
+
+    /// Creates a query and uploads the input shares for it, one stream for each helper. The
+    /// query starts running once all the helpers have their inputs. If any of the helpers does
+    /// not accept its input, the query is killed, so that it does not hold the helpers until it
+    /// times out.
+    ///
+    /// ## Errors
+    /// If the query could not be created, or any of the helpers did not accept its input.
+    pub async fn submit(
+        &self,
+        config: QueryConfig,
+        inputs: [BodyStream; 3],
+    ) -> Result<QueryHandle<'_>, Error> {
+        let query_id = self.clients[0]
+            .create_query(config)
+            .await
+            .map_err(|source| Error::Helper {
+                helper: HelperIdentity::ONE,
+                source,
+            })?;
+
+        let query = self.query(query_id);
+        let uploaded = self
+            .on_each_helper(inputs, |helper, client, input_stream| async move {
+                client
+                    .query_input(QueryInput {
+                        query_id,
+                        input_stream,
+                    })
+                    .await
+                    .map_err(|source| Error::Helper { helper, source })
+            })
+            .await;
+        if let Err(e) = uploaded {
+            if let Err(cancel_error) = query.cancel().await {
+                tracing::warn!("Failed to kill query {query_id:?} after its input was rejected: {cancel_error}");
+            }
+            return Err(e);
+        }
+
+        Ok(query)
+    }
+
+    /// Returns a handle to a query that was submitted before, e.g. by another process.
+    #[must_use]
+    pub fn query(&self, query_id: QueryId) -> QueryHandle<'_> {
+        QueryHandle {
+            collector: self,
+            query_id,
+        }
+    }
+
+    /// Calls `f` for each of the helpers concurrently, with the client for that helper and the
+    /// value from `values` that is meant for it.
+    async fn on_each_helper<'a, T, O, F, Fut>(
+        &'a self,
+        values: [T; 3],
+        f: F,
+    ) -> Result<[O; 3], Error>
+    where
+        F: Fn(HelperIdentity, &'a MpcHelperClient, T) -> Fut,
+        Fut: Future<Output = Result<O, Error>>,
+    {
+        let [h1, h2, h3] = HelperIdentity::make_three();
+        let [c1, c2, c3] = self.clients.each_ref();
+        let [v1, v2, v3] = values;
+        let (o1, o2, o3) = try_join3(f(h1, c1, v1), f(h2, c2, v2), f(h3, c3, v3)).await?;
+
+        Ok([o1, o2, o3])
+    }
+}
+
+/// A query submitted by a [`ReportCollector`].
+pub struct QueryHandle<'a> {
+    collector: &'a ReportCollector,
+    query_id: QueryId,
+}
+
+impl QueryHandle<'_> {
+    #[must_use]
+    pub fn query_id(&self) -> QueryId {
+        self.query_id
+    }
+
+    /// Returns the status of the query on each of the helpers.
+    ///
+    /// ## Errors
+    /// If any of the helpers does not know about this query, or cannot be reached.
+    pub async fn status(&self) -> Result<[QueryStatus; 3], Error> {
+        self.collector
+            .on_each_helper([(); 3], |helper, client, ()| async move {
+                client
+                    .query_status(self.query_id)
+                    .await
+                    .map_err(|source| Error::Helper { helper, source })
+            })
+            .await
+    }
+
+    /// Kills the query on all the helpers. Helpers that already forgot about the query, because
+    /// it finished or was killed before, are skipped. A helper that cannot be reached does not
+    /// prevent the query from being killed on the others.
+    ///
+    /// ## Errors
+    /// If any of the helpers cannot be reached.
+    pub async fn cancel(&self) -> Result<(), Error> {
+        let [c1, c2, c3] = self.collector.clients.each_ref();
+        let results = join3(
+            c1.kill_query(self.query_id),
+            c2.kill_query(self.query_id),
+            c3.kill_query(self.query_id),
+        )
+        .await;
+        let [h1, h2, h3] = HelperIdentity::make_three();
+        for (helper, result) in [(h1, results.0), (h2, results.1), (h3, results.2)] {
+            match result {
+                Err(net::Error::FailedHttpRequest {
+                    status: StatusCode::NOT_FOUND,
+                    ..
+                })
+                | Ok(()) => {}
+                Err(source) => return Err(Error::Helper { helper, source }),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits until the query is completed on all the helpers.
+    ///
+    /// ## Errors
+    /// If the status of the query cannot be retrieved from any of the helpers.
+    pub async fn wait(&self) -> Result<(), Error> {
+        let mut delay = INITIAL_POLL_INTERVAL;
+        while !self
+            .status()
+            .await?
+            .iter()
+            .all(|status| *status == QueryStatus::Completed)
+        {
+            sleep(delay).await;
+            delay = min(MAX_POLL_INTERVAL, delay * 2);
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the query to complete and reconstructs its result from the shares returned by
+    /// the helpers. For IPA queries, the result is the histogram of trigger values, indexed by
+    /// breakdown key.
+    ///
+    /// `HV` must match the type of the result shares produced by the helpers. The helpers forget
+    /// the query once they returned its results, so they can only be retrieved once.
+    ///
+    /// ## Errors
+    /// If the query fails on any of the helpers, or the shares they return do not reconstruct to
+    /// a valid result.
+    pub async fn results<HV>(&self) -> Result<Vec<HV>, Error>
+    where
+        HV: SharedValue,
+        AdditiveShare<HV>: Serializable,
+    {
+        self.wait().await?;
+
+        let shares = self
+            .collector
+            .on_each_helper([(); 3], |helper, client, ()| async move {
+                let bytes = client
+                    .query_results(self.query_id)
+                    .await
+                    .map_err(|source| Error::Helper { helper, source })?;
+                deserialize_shares::<HV>(&bytes)
+                    .map_err(|source| Error::MalformedResults { helper, source })
+            })
+            .await?;
+
+        reconstruct(&shares)
+    }
+
+    /// Waits for a query that was created with `dry_run` set to complete, and returns the summary
+    /// of the input reports of each helper. Like [`Self::results`], this can only be called once.
+    ///
+    /// ## Errors
+    /// If the query fails on any of the helpers, or a helper does not return a summary.
+    pub async fn dry_run_results(&self) -> Result<[ValidationSummary; 3], Error> {
+        self.wait().await?;
+
+        self.collector
+            .on_each_helper([(); 3], |helper, client, ()| async move {
+                let bytes = client
+                    .query_results(self.query_id)
+                    .await
+                    .map_err(|source| Error::Helper { helper, source })?;
+                serde_json::from_slice(&bytes).map_err(|e| Error::MalformedResults {
+                    helper,
+                    source: e.into(),
+                })
+            })
+            .await
+    }
+}
+
+fn deserialize_shares<HV>(bytes: &[u8]) -> Result<Vec<AdditiveShare<HV>>, BoxError>
+where
+    HV: SharedValue,
+    AdditiveShare<HV>: Serializable,
+{
+    let size = <AdditiveShare<HV> as Serializable>::Size::USIZE;
+    if bytes.len() % size != 0 {
+        return Err(format!(
+            "{} bytes is not a multiple of the share size {size}",
+            bytes.len()
+        )
+        .into());
+    }
+
+    AdditiveShare::<HV>::from_byte_slice(bytes)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(Into::into)
+}
+
+/// Reconstructs the values shared by the helpers, checking that the replicated shares agree.
+fn reconstruct<HV: SharedValue>(shares: &[Vec<AdditiveShare<HV>>; 3]) -> Result<Vec<HV>, Error> {
+    let lengths = shares.each_ref().map(Vec::len);
+    if lengths[1] != lengths[0] || lengths[2] != lengths[0] {
+        return Err(Error::ResultLengthMismatch { lengths });
+    }
+
+    (0..lengths[0])
+        .map(|index| {
+            let [s0, s1, s2] = shares.each_ref().map(|s| &s[index]);
+            if s0.right() != s1.left() || s1.right() != s2.left() || s2.right() != s0.left() {
+                return Err(Error::InconsistentShares { index });
+            }
+            Ok(s0.left() + s1.left() + s2.left())
+        })
+        .collect()
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::thread_rng;
+    use typenum::Unsigned;
+
+    use super::{deserialize_shares, reconstruct, Error};
+    use crate::{
+        ff::{Fp31, Serializable, U128Conversions},
+        secret_sharing::{
+            replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+            IntoShares,
+        },
+    };
+
+    fn share(values: &[u128]) -> [Vec<AdditiveShare<Fp31>>; 3] {
+        values
+            .iter()
+            .map(|&v| Fp31::truncate_from(v))
+            .share_with(&mut thread_rng())
+    }
+
+    #[test]
+    fn reconstructs() {
+        let shares = share(&[1, 2, 3]);
+        assert_eq!(
+            reconstruct(&shares).unwrap(),
+            [1_u128, 2, 3].map(Fp31::truncate_from)
+        );
+    }
+
+    #[test]
+    fn rejects_inconsistent_shares() {
+        let mut shares = share(&[1, 2, 3]);
+        shares[1][2] = AdditiveShare::new(Fp31::truncate_from(7_u128), Fp31::truncate_from(8_u128));
+        assert!(matches!(
+            reconstruct(&shares),
+            Err(Error::InconsistentShares { index: 2 })
+        ));
+    }
+
+    #[test]
+    fn rejects_different_lengths() {
+        let mut shares = share(&[1, 2, 3]);
+        shares[2].pop();
+        assert!(matches!(
+            reconstruct(&shares),
+            Err(Error::ResultLengthMismatch { lengths: [3, 3, 2] })
+        ));
+    }
+
+    #[test]
+    fn rejects_partial_shares() {
+        let size = <AdditiveShare<Fp31> as Serializable>::Size::USIZE;
+        assert!(deserialize_shares::<Fp31>(&vec![0; 2 * size + 1]).is_err());
+        assert_eq!(
+            deserialize_shares::<Fp31>(&vec![0; 2 * size])
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
@@ -230,9 +230,9 @@ mod tests {
         }
 
         assert_eq!(L::ZERO, sum_owned(L::ZERO, L::ZERO));
-        assert_eq!(L::ZERO, sum_ref_ref(&L::ZERO, &L::ZERO));
+        assert_eq!(L::ZERO, sum_ref_ref::<L, F>(&L::ZERO, &L::ZERO));
         assert_eq!(L::ZERO, sum_owned_ref(L::ZERO, &L::ZERO));
-        assert_eq!(L::ZERO, sum_ref_owned(&L::ZERO, L::ZERO));
+        assert_eq!(L::ZERO, sum_ref_owned::<L, F>(&L::ZERO, L::ZERO));
     }
 
     #[test]
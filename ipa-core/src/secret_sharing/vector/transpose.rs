@@ -796,6 +796,15 @@ impl_transpose_shares_ba_to_bool!(BA16, 256, 16, test_transpose_shares_ba_to_boo
 impl_transpose_shares_ba_to_bool!(BA16, 32, 16, test_transpose_shares_ba_to_bool_32x16);
 impl_transpose_shares_ba_to_bool_small!(BA8, 16, 8, test_transpose_shares_ba_to_bool_16x8);
 
+// Usage: Aggregation input for fixed-point trigger values. M = AGG_CHUNK, N = TV bits. The array
+// transpose is shared with the Laplace noise mechanism above.
+impl_transpose_shim!(
+    &Vec<AdditiveShare<BA16>>, AdditiveShare<BA16>,
+    BitDecomposed<AdditiveShare<Boolean, 256>>, AdditiveShare<Boolean, 256>,
+    256, 16,
+    LengthError,
+);
+
 // Special transpose used for "aggregation intermediate". See [`aggregate_contributions`] for
 // additional details.
 //
@@ -9,15 +9,18 @@ pub mod labels {
 }
 
 pub mod metrics {
-    use metrics::{describe_counter, Unit};
+    use metrics::{describe_counter, describe_gauge, Unit};
 
     pub const REQUESTS_RECEIVED: &str = "requests.received";
     pub const RECORDS_SENT: &str = "records.sent";
     pub const BYTES_SENT: &str = "bytes.sent";
+    pub const BYTES_SENT_WIRE: &str = "bytes.sent.wire";
     pub const INDEXED_PRSS_GENERATED: &str = "i.prss.gen";
     pub const SEQUENTIAL_PRSS_GENERATED: &str = "s.prss.gen";
     pub use ::ipa_step::descriptive::labels::STEP_NARROWED;
     pub const DZKP_BATCH_INCREMENTS: &str = "batch.realloc.front";
+    pub const TLS_CERTIFICATE_EXPIRES_IN: &str = "tls.certificate.expires_in";
+    pub const TLS_CERTIFICATE_RELOADED: &str = "tls.certificate.reloaded";
 
     #[cfg(feature = "web-app")]
     pub mod web {
@@ -75,6 +78,18 @@ pub mod metrics {
                 Unit::Count,
                 "Total number of HTTP/2 requests received"
             );
+
+            describe_gauge!(
+                TLS_CERTIFICATE_EXPIRES_IN,
+                Unit::Seconds,
+                "Time remaining until a TLS certificate expires, labeled by certificate"
+            );
+
+            describe_counter!(
+                TLS_CERTIFICATE_RELOADED,
+                Unit::Count,
+                "Number of times the helper TLS certificate was reloaded from disk"
+            );
         }
 
         describe_counter!(
@@ -89,6 +104,12 @@ pub mod metrics {
             "Bytes sent from the infrastructure layer to the network"
         );
 
+        describe_counter!(
+            BYTES_SENT_WIRE,
+            Unit::Count,
+            "Bytes of step streams sent over HTTP to other helpers, after compression"
+        );
+
         describe_counter!(
             INDEXED_PRSS_GENERATED,
             Unit::Count,
@@ -84,6 +84,11 @@ impl Metrics {
 
         let snapshot = snapshot.into_vec();
         for (ckey, _, descr, val) in snapshot {
+            // Only counters are collected. Gauges, like the time left before a TLS certificate
+            // expires, are skipped.
+            if ckey.kind() != MetricKind::Counter {
+                continue;
+            }
             let (key_name, labels) = ckey.key().clone().into_parts();
             if !filter_fn(labels.as_slice()) {
                 continue;
@@ -94,10 +99,7 @@ impl Metrics {
                 this.metric_description.insert(key_name, descr);
             }
 
-            match ckey.kind() {
-                MetricKind::Counter => entry.add(&ckey, &val),
-                MetricKind::Gauge | MetricKind::Histogram => unimplemented!(),
-            }
+            entry.add(&ckey, &val);
         }
 
         this
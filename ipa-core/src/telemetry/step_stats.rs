@@ -10,7 +10,8 @@ use std::{
 use crate::telemetry::{
     labels,
     metrics::{
-        BYTES_SENT, INDEXED_PRSS_GENERATED, RECORDS_SENT, SEQUENTIAL_PRSS_GENERATED, STEP_NARROWED,
+        BYTES_SENT, BYTES_SENT_WIRE, INDEXED_PRSS_GENERATED, RECORDS_SENT,
+        SEQUENTIAL_PRSS_GENERATED, STEP_NARROWED,
     },
     stats::Metrics,
 };
@@ -40,15 +41,16 @@ impl CsvExporter for Metrics {
         // because it does not allow such breakdown atm.
         writeln!(
             w,
-            "Step,Records sent,Bytes sent,Indexed PRSS,Sequential PRSS,Step narrowed"
+            "Step,Records sent,Bytes sent,Wire bytes sent,Indexed PRSS,Sequential PRSS,Step narrowed"
         )?;
         for (step, stats) in steps_stats.all_steps() {
             writeln!(
                 w,
-                "{},{},{},{},{},{}",
+                "{},{},{},{},{},{},{}",
                 step,
                 stats.get(RECORDS_SENT),
                 stats.get(BYTES_SENT),
+                stats.get(BYTES_SENT_WIRE),
                 stats.get(INDEXED_PRSS_GENERATED),
                 stats.get(SEQUENTIAL_PRSS_GENERATED),
                 stats.get(STEP_NARROWED),
@@ -3,7 +3,13 @@ use std::{
     num::{NonZeroU32, NonZeroU64},
 };
 
-use crate::{rand::Rng, test_fixture::ipa::TestRawDataRecord};
+use crate::{
+    rand::Rng,
+    test_fixture::{
+        event_model::{CampaignModel, TimeModel, UserModel},
+        ipa::TestRawDataRecord,
+    },
+};
 
 #[derive(Copy, Clone, Hash, Ord, PartialOrd, Eq, PartialEq)]
 struct UserId(u64);
@@ -68,6 +74,12 @@ pub struct Config {
     pub report_filter: ReportFilter,
     #[cfg_attr(feature = "clap", arg(long, required_if_eq("report_filter", "TriggerOnly"), default_value = "0.02", value_parser = validate_probability))]
     pub conversion_probability: Option<f32>,
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub users: UserModel,
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub time: TimeModel,
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub campaign: CampaignModel,
 }
 
 fn validate_probability(value: &str) -> Result<f32, String> {
@@ -110,6 +122,9 @@ impl Config {
             max_events_per_user: NonZeroU32::try_from(max_events_per_user).unwrap(),
             report_filter: ReportFilter::All,
             conversion_probability: None,
+            users: UserModel::default(),
+            time: TimeModel::default(),
+            campaign: CampaignModel::default(),
         }
     }
 
@@ -125,6 +140,8 @@ struct UserStats {
     generated: u32,
     max: u32,
     used_timestamps: HashSet<Timestamp>,
+    /// Breakdown key and timestamp of the most recently generated impression.
+    last_impression: Option<(u32, Timestamp)>,
 }
 
 impl UserStats {
@@ -134,6 +151,7 @@ impl UserStats {
             generated: 0,
             max: max_events,
             used_timestamps: HashSet::new(),
+            last_impression: None,
         }
     }
 
@@ -151,6 +169,9 @@ impl UserStats {
 /// Number of events generated depends on the configured number of unique users per set
 /// and maximum number of events per user. See [`Config`] for more details
 ///
+/// If conversion rates are configured per breakdown key, conversions of a user follow their most
+/// recent impression, at the conversion rate of its breakdown key.
+///
 /// [`Config`]: Config
 pub struct EventGenerator<R: Rng> {
     config: Config,
@@ -177,6 +198,9 @@ impl<R: Rng> EventGenerator<R> {
             mt = config.max_timestamp,
             me = config.max_events_per_user,
         );
+        config.users.validate();
+        config.time.validate();
+        config.campaign.validate();
         Self {
             config,
             rng,
@@ -187,26 +211,34 @@ impl<R: Rng> EventGenerator<R> {
 
     fn gen_event(&mut self, idx: usize) -> TestRawDataRecord {
         let user_id = self.users[idx].user_id;
-
-        // Generate a new random timestamp between [0..`max_timestamp`) and distinct from
-        // already-used timestamps. `EventGenerator::with_config` checks that `max_timestamp`
-        // exceeds `max_events_per_user` by a margin large enough that this is likely to complete.
-        let current_ts = loop {
-            let ts = self.rng.gen_range(0..self.config.max_timestamp.get());
-            if self.users[idx].used_timestamps.insert(ts) {
-                break ts;
-            }
-        };
+        let last_impression = self.users[idx].last_impression;
 
         match self.config.report_filter {
-            ReportFilter::All => {
+            ReportFilter::All if self.config.campaign.breakdown_conversion_rates.is_empty() => {
+                let current_ts = self.gen_timestamp(idx, 0);
                 if self.rng.gen() {
-                    self.gen_trigger(user_id, current_ts)
+                    let breakdown_key = last_impression.map(|(bk, _)| bk);
+                    self.gen_trigger(user_id, current_ts, breakdown_key)
                 } else {
-                    self.gen_source(user_id, current_ts)
+                    self.gen_source(idx, current_ts)
                 }
             }
+            ReportFilter::All => match last_impression {
+                Some((breakdown_key, impression_ts))
+                    if impression_ts + 1 < self.config.max_timestamp.get()
+                        && self.rng.gen::<f64>()
+                            < self.config.campaign.conversion_rate(breakdown_key).unwrap() =>
+                {
+                    let current_ts = self.gen_timestamp(idx, impression_ts + 1);
+                    self.gen_trigger(user_id, current_ts, Some(breakdown_key))
+                }
+                _ => {
+                    let current_ts = self.gen_timestamp(idx, 0);
+                    self.gen_source(idx, current_ts)
+                }
+            },
             ReportFilter::TriggerOnly => {
+                let current_ts = self.gen_timestamp(idx, 0);
                 // safe to unwrap because clap validation is done before
                 let user_id =
                     if self.rng.gen::<f32>() <= self.config.conversion_probability.unwrap() {
@@ -214,14 +246,57 @@ impl<R: Rng> EventGenerator<R> {
                     } else {
                         UserId::EPHEMERAL
                     };
-                self.gen_trigger(user_id, current_ts)
+                self.gen_trigger(user_id, current_ts, None)
+            }
+            ReportFilter::SourceOnly => {
+                let current_ts = self.gen_timestamp(idx, 0);
+                self.gen_source(idx, current_ts)
             }
-            ReportFilter::SourceOnly => self.gen_source(user_id, current_ts),
         }
     }
 
-    fn gen_trigger(&mut self, user_id: UserId, timestamp: Timestamp) -> TestRawDataRecord {
-        let trigger_value = self.rng.gen_range(1..=self.config.max_trigger_value.get());
+    /// Generates a new random timestamp between [`start`..`max_timestamp`) and distinct from
+    /// already-used timestamps. `EventGenerator::with_config` checks that `max_timestamp`
+    /// exceeds `max_events_per_user` by a margin large enough that this is likely to complete
+    /// when `start` is 0. Narrower ranges may be exhausted, so the timestamp is picked from the
+    /// whole range if the narrow one does not yield an unused timestamp quickly.
+    fn gen_timestamp(&mut self, idx: usize, start: Timestamp) -> Timestamp {
+        const NARROW_RANGE_ATTEMPTS: usize = 16;
+        let max_timestamp = self.config.max_timestamp.get();
+        let mut attempts = 0;
+        loop {
+            let start = if attempts < NARROW_RANGE_ATTEMPTS {
+                start
+            } else {
+                0
+            };
+            attempts += 1;
+            let ts = self
+                .config
+                .time
+                .timestamp(&mut self.rng, start..max_timestamp);
+            if self.users[idx].used_timestamps.insert(ts) {
+                break ts;
+            }
+        }
+    }
+
+    /// Generates a trigger event. Its value follows the value distribution of `breakdown_key`,
+    /// the breakdown key of the impression it is expected to be attributed to, if there is one.
+    fn gen_trigger(
+        &mut self,
+        user_id: UserId,
+        timestamp: Timestamp,
+        breakdown_key: Option<u32>,
+    ) -> TestRawDataRecord {
+        let max_trigger_value = self.config.max_trigger_value.get();
+        let trigger_value = breakdown_key
+            .and_then(|bk| {
+                self.config
+                    .campaign
+                    .value(&mut self.rng, bk, max_trigger_value)
+            })
+            .unwrap_or_else(|| self.rng.gen_range(1..=max_trigger_value));
 
         TestRawDataRecord {
             user_id: user_id.into(),
@@ -232,11 +307,12 @@ impl<R: Rng> EventGenerator<R> {
         }
     }
 
-    fn gen_source(&mut self, user_id: UserId, timestamp: Timestamp) -> TestRawDataRecord {
+    fn gen_source(&mut self, idx: usize, timestamp: Timestamp) -> TestRawDataRecord {
         let breakdown_key = self.rng.gen_range(0..self.config.max_breakdown_key.get());
+        self.users[idx].last_impression = Some((breakdown_key, timestamp));
 
         TestRawDataRecord {
-            user_id: user_id.into(),
+            user_id: self.users[idx].user_id.into(),
             timestamp: timestamp.into(),
             is_trigger_report: false,
             breakdown_key,
@@ -259,12 +335,12 @@ impl<R: Rng> EventGenerator<R> {
             }
             self.used_ids.insert(user_id);
 
-            break Some(UserStats::new(
-                user_id,
-                self.rng.gen_range(
-                    self.config.min_events_per_user.get()..=self.config.max_events_per_user.get(),
-                ),
-            ));
+            let max_events = self.config.users.events_per_user(
+                &mut self.rng,
+                self.config.min_events_per_user.get(),
+                self.config.max_events_per_user.get(),
+            );
+            break Some(UserStats::new(user_id, max_events));
         }
     }
 }
@@ -299,7 +375,10 @@ impl<R: Rng> Iterator for EventGenerator<R> {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use rand::thread_rng;
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, thread_rng};
+    use rand_core::SeedableRng;
 
     use super::*;
 
@@ -329,6 +408,36 @@ mod tests {
         assert!(iter.next().is_none());
     }
 
+    #[test]
+    fn conversions_follow_impressions() {
+        let gen = EventGenerator::with_config(
+            StdRng::seed_from_u64(42),
+            Config {
+                user_count: NonZeroU64::new(1000).unwrap(),
+                max_breakdown_key: NonZeroU32::new(2).unwrap(),
+                campaign: CampaignModel {
+                    breakdown_conversion_rates: vec![1.0, 0.0],
+                    breakdown_value_means: vec![],
+                },
+                ..Config::default()
+            },
+        );
+
+        let events = gen.collect::<Vec<_>>();
+        let mut impressions = HashMap::<_, Vec<_>>::new();
+        for event in events.iter().filter(|e| !e.is_trigger_report) {
+            impressions.entry(event.user_id).or_default().push(event);
+        }
+        let mut triggers = events.iter().filter(|e| e.is_trigger_report).peekable();
+        assert!(triggers.peek().is_some());
+        for trigger in triggers {
+            // only impressions with breakdown key 0 convert
+            assert!(impressions[&trigger.user_id]
+                .iter()
+                .any(|i| i.breakdown_key == 0 && i.timestamp < trigger.timestamp));
+        }
+    }
+
     #[test]
     #[should_panic(expected = "must be at least twice max_events_per_user")]
     fn invalid_max_timestamp() {
@@ -448,6 +557,7 @@ mod tests {
                         ReportFilter::TriggerOnly => Some(0.02),
                         _ => None,
                     },
+                    ..Config::default()
                 }
             }
         }
@@ -0,0 +1,401 @@
+//! Statistical models used by the event generators to produce data that looks more like real
+//! advertising data than uniformly random events. Every model defaults to the uniform behavior
+//! of the generators, so the models only change the output when they are configured.
+
+use std::num::NonZeroU32;
+
+use rand::Rng;
+
+const SECONDS_IN_DAY: u32 = 86_400;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum ActivityDistribution {
+    /// Every user is equally active.
+    Uniform,
+    /// A few users are much more active than the rest, following a Zipf distribution.
+    Zipf,
+}
+
+/// How many events users have, and how many devices report them under the same match key.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct UserModel {
+    /// Distribution of user activity. For IPA inputs, this is the distribution of the number of
+    /// events per user. For hybrid inputs, it picks the returning users that new events belong to.
+    #[cfg_attr(feature = "clap", arg(value_enum, long, default_value_t = ActivityDistribution::Uniform))]
+    pub user_activity: ActivityDistribution,
+    /// Exponent of the Zipf distribution of user activity. Larger values make very active users
+    /// rarer.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "1.1", value_parser = validate_exponent))]
+    pub zipf_exponent: f64,
+    /// Probability that events of a user come from more than one device sharing the same match
+    /// key.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "0.0", value_parser = validate_probability))]
+    pub cross_device_probability: f64,
+    /// Maximum number of devices that share the match key of a cross-device user.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "3"))]
+    pub max_devices_per_user: NonZeroU32,
+}
+
+impl Default for UserModel {
+    fn default() -> Self {
+        Self {
+            user_activity: ActivityDistribution::Uniform,
+            zipf_exponent: 1.1,
+            cross_device_probability: 0.0,
+            max_devices_per_user: NonZeroU32::new(3).unwrap(),
+        }
+    }
+}
+
+impl UserModel {
+    /// ## Panics
+    /// If the model parameters are out of range.
+    pub fn validate(&self) {
+        assert!(
+            self.zipf_exponent > 0.0,
+            "zipf_exponent must be positive, got {}",
+            self.zipf_exponent
+        );
+        assert!(
+            (0.0..=1.0).contains(&self.cross_device_probability),
+            "cross_device_probability must be between 0.0 and 1.0, got {}",
+            self.cross_device_probability
+        );
+    }
+
+    /// Samples the number of events for a new user, between `min` and `max` inclusive. Every
+    /// device of a cross-device user contributes its own number of events, and the total is
+    /// capped at `max`.
+    pub fn events_per_user<R: Rng>(&self, rng: &mut R, min: u32, max: u32) -> u32 {
+        let devices = self.devices(rng);
+        (0..devices)
+            .map(|_| self.activity(rng, min, max))
+            .fold(0_u32, u32::saturating_add)
+            .min(max)
+    }
+
+    /// Samples whether the next event comes from another device of a user that has already been
+    /// seen, and if so, the index of that user in a list of `users` returning users, ordered from
+    /// the most to the least active.
+    ///
+    /// ## Panics
+    /// If `usize` is narrower than 32 bits.
+    pub fn returning_user<R: Rng>(&self, rng: &mut R, users: usize) -> Option<usize> {
+        if users == 0
+            || self.cross_device_probability <= 0.0
+            || rng.gen::<f64>() >= self.cross_device_probability
+        {
+            return None;
+        }
+        let users = u32::try_from(users).unwrap_or(u32::MAX);
+        Some(usize::try_from(self.activity(rng, 1, users) - 1).unwrap())
+    }
+
+    fn devices<R: Rng>(&self, rng: &mut R) -> u32 {
+        if self.cross_device_probability > 0.0
+            && self.max_devices_per_user.get() > 1
+            && rng.gen::<f64>() < self.cross_device_probability
+        {
+            rng.gen_range(2..=self.max_devices_per_user.get())
+        } else {
+            1
+        }
+    }
+
+    fn activity<R: Rng>(&self, rng: &mut R, min: u32, max: u32) -> u32 {
+        match self.user_activity {
+            ActivityDistribution::Uniform => rng.gen_range(min..=max),
+            ActivityDistribution::Zipf => min + zipf(rng, max - min + 1, self.zipf_exponent) - 1,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum TimestampDistribution {
+    /// Events are equally likely at any time.
+    Uniform,
+    /// Events follow a daily cycle, with most events around the peak hour.
+    Diurnal,
+}
+
+/// When events happen.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct TimeModel {
+    /// Distribution of event timestamps over the day.
+    #[cfg_attr(feature = "clap", arg(value_enum, long, default_value_t = TimestampDistribution::Uniform))]
+    pub timestamp_distribution: TimestampDistribution,
+    /// Hour of the day, between 0 and 23, when events are most likely.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "20", value_parser = clap::value_parser!(u8).range(0..24)))]
+    pub diurnal_peak_hour: u8,
+    /// Relative difference between the event rates at the peak hour and the mean rate, between
+    /// 0.0 (no daily cycle) and 1.0 (no events twelve hours from the peak).
+    #[cfg_attr(feature = "clap", arg(long, default_value = "0.8", value_parser = validate_probability))]
+    pub diurnal_amplitude: f64,
+}
+
+impl Default for TimeModel {
+    fn default() -> Self {
+        Self {
+            timestamp_distribution: TimestampDistribution::Uniform,
+            diurnal_peak_hour: 20,
+            diurnal_amplitude: 0.8,
+        }
+    }
+}
+
+impl TimeModel {
+    /// ## Panics
+    /// If the model parameters are out of range.
+    pub fn validate(&self) {
+        assert!(
+            self.diurnal_peak_hour < 24,
+            "diurnal_peak_hour must be between 0 and 23, got {}",
+            self.diurnal_peak_hour
+        );
+        assert!(
+            (0.0..=1.0).contains(&self.diurnal_amplitude),
+            "diurnal_amplitude must be between 0.0 and 1.0, got {}",
+            self.diurnal_amplitude
+        );
+    }
+
+    /// Samples a timestamp in `range`, which must not be empty. Timestamps are seconds, and
+    /// timestamp 0 is midnight.
+    pub fn timestamp<R: Rng>(&self, rng: &mut R, range: std::ops::Range<u32>) -> u32 {
+        match self.timestamp_distribution {
+            TimestampDistribution::Uniform => rng.gen_range(range),
+            TimestampDistribution::Diurnal => {
+                // Rejection sampling, that accepts a timestamp with a probability proportional to
+                // the event rate at that time of the day. Short ranges around the trough may not
+                // contain any likely timestamp, so give up after a while.
+                const MAX_ATTEMPTS: usize = 100;
+                let peak = u32::from(self.diurnal_peak_hour) * 3600;
+                for _ in 0..MAX_ATTEMPTS {
+                    let ts = rng.gen_range(range.clone());
+                    let phase =
+                        f64::from((ts % SECONDS_IN_DAY + SECONDS_IN_DAY - peak) % SECONDS_IN_DAY)
+                            / f64::from(SECONDS_IN_DAY);
+                    let rate =
+                        1.0 + self.diurnal_amplitude * (2.0 * std::f64::consts::PI * phase).cos();
+                    if rng.gen::<f64>() * (1.0 + self.diurnal_amplitude) < rate {
+                        return ts;
+                    }
+                }
+                rng.gen_range(range)
+            }
+        }
+    }
+}
+
+/// How often impressions of each breakdown convert, and how much conversions are worth.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct CampaignModel {
+    /// Comma-separated probabilities that an impression with breakdown key 0, 1, ... leads to a
+    /// conversion. Breakdown keys past the end of the list use the last rate. If empty, reports
+    /// are impressions or conversions regardless of breakdown keys.
+    #[cfg_attr(feature = "clap", arg(long, value_delimiter = ',', value_parser = validate_probability))]
+    pub breakdown_conversion_rates: Vec<f64>,
+    /// Comma-separated mean values of conversions attributed to breakdown key 0, 1, ... Values
+    /// follow a geometric distribution, capped at the maximum value. Breakdown keys past the end
+    /// of the list use the last mean. If empty, values are uniform.
+    #[cfg_attr(feature = "clap", arg(long, value_delimiter = ',', value_parser = validate_mean))]
+    pub breakdown_value_means: Vec<f64>,
+}
+
+impl CampaignModel {
+    /// ## Panics
+    /// If the model parameters are out of range.
+    pub fn validate(&self) {
+        assert!(
+            self.breakdown_conversion_rates
+                .iter()
+                .all(|rate| (0.0..=1.0).contains(rate)),
+            "conversion rates must be between 0.0 and 1.0, got {:?}",
+            self.breakdown_conversion_rates
+        );
+        assert!(
+            self.breakdown_value_means.iter().all(|&mean| mean >= 1.0),
+            "mean values must be at least 1.0, got {:?}",
+            self.breakdown_value_means
+        );
+    }
+
+    /// Returns the conversion rate of impressions with `breakdown_key`, if conversion rates are
+    /// configured.
+    #[must_use]
+    pub fn conversion_rate(&self, breakdown_key: u32) -> Option<f64> {
+        per_breakdown(&self.breakdown_conversion_rates, breakdown_key)
+    }
+
+    /// Samples the value of a conversion attributed to `breakdown_key`, between 1 and `max_value`
+    /// inclusive. Returns `None` if mean values are not configured, in which case generators pick
+    /// a uniform value.
+    pub fn value<R: Rng>(&self, rng: &mut R, breakdown_key: u32, max_value: u32) -> Option<u32> {
+        per_breakdown(&self.breakdown_value_means, breakdown_key)
+            .map(|mean| geometric(rng, mean).min(max_value))
+    }
+}
+
+fn per_breakdown(values: &[f64], breakdown_key: u32) -> Option<f64> {
+    usize::try_from(breakdown_key)
+        .ok()
+        .and_then(|bk| values.get(bk))
+        .or_else(|| values.last())
+        .copied()
+}
+
+/// Samples a rank between 1 and `n` inclusive from a bounded power law with the given exponent,
+/// which is the continuous approximation of a Zipf distribution. Inverting its CDF takes
+/// constant time for any `n`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn zipf<R: Rng>(rng: &mut R, n: u32, exponent: f64) -> u32 {
+    let u = rng.gen::<f64>();
+    let end = f64::from(n) + 1.0;
+    let x = if (exponent - 1.0).abs() < f64::EPSILON {
+        end.powf(u)
+    } else {
+        let a = 1.0 - exponent;
+        (1.0 + u * (end.powf(a) - 1.0)).powf(1.0 / a)
+    };
+    (x.floor() as u32).clamp(1, n)
+}
+
+/// Samples a value of at least 1 from a geometric distribution with the given mean.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn geometric<R: Rng>(rng: &mut R, mean: f64) -> u32 {
+    if mean <= 1.0 {
+        return 1;
+    }
+    // 1 - u is in (0, 1], so its logarithm is finite
+    let u = 1.0 - rng.gen::<f64>();
+    let failures = (u.ln() / (1.0 - 1.0 / mean).ln()).floor();
+    1_u32.saturating_add(failures.min(f64::from(u32::MAX)) as u32)
+}
+
+#[cfg(feature = "clap")]
+fn validate_probability(value: &str) -> Result<f64, String> {
+    let v = value
+        .parse::<f64>()
+        .map_err(|e| format!("{e} not a float number"))?;
+    if (0.0..=1.0).contains(&v) {
+        Ok(v)
+    } else {
+        Err(format!("probability must be between 0.0 and 1.0, got {v}"))
+    }
+}
+
+#[cfg(feature = "clap")]
+fn validate_exponent(value: &str) -> Result<f64, String> {
+    let v = value
+        .parse::<f64>()
+        .map_err(|e| format!("{e} not a float number"))?;
+    if v > 0.0 {
+        Ok(v)
+    } else {
+        Err(format!("exponent must be positive, got {v}"))
+    }
+}
+
+#[cfg(feature = "clap")]
+fn validate_mean(value: &str) -> Result<f64, String> {
+    let v = value
+        .parse::<f64>()
+        .map_err(|e| format!("{e} not a float number"))?;
+    if v >= 1.0 {
+        Ok(v)
+    } else {
+        Err(format!("mean value must be at least 1.0, got {v}"))
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::{rngs::StdRng, thread_rng};
+    use rand_core::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn zipf_activity() {
+        const SAMPLES: usize = 100_000;
+        let model = UserModel {
+            user_activity: ActivityDistribution::Zipf,
+            ..UserModel::default()
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut histogram = [0_usize; 10];
+        for _ in 0..SAMPLES {
+            let events = model.events_per_user(&mut rng, 1, 10);
+            assert!((1..=10).contains(&events));
+            histogram[usize::try_from(events - 1).unwrap()] += 1;
+        }
+
+        // users with few events are the most common, and the tail is long
+        assert!(histogram.windows(2).all(|w| w[0] >= w[1]), "{histogram:?}");
+        assert!(histogram[0] > SAMPLES / 4, "{histogram:?}");
+        assert!(histogram[9] > 0, "{histogram:?}");
+    }
+
+    #[test]
+    fn cross_device_users() {
+        let model = UserModel {
+            cross_device_probability: 1.0,
+            max_devices_per_user: NonZeroU32::new(2).unwrap(),
+            ..UserModel::default()
+        };
+        let mut rng = thread_rng();
+        for _ in 0..1000 {
+            // two devices with at least 2 events each, capped at 5
+            assert!((4..=5).contains(&model.events_per_user(&mut rng, 2, 5)));
+        }
+        assert!(model.returning_user(&mut rng, 0).is_none());
+        assert!(model.returning_user(&mut rng, 10).unwrap() < 10);
+        assert!(UserModel::default().returning_user(&mut rng, 10).is_none());
+    }
+
+    #[test]
+    fn diurnal_timestamps() {
+        const DAYS: u32 = 7;
+        let model = TimeModel {
+            timestamp_distribution: TimestampDistribution::Diurnal,
+            diurnal_peak_hour: 20,
+            diurnal_amplitude: 1.0,
+        };
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut by_hour = [0_u32; 24];
+        for _ in 0..100_000 {
+            let ts = model.timestamp(&mut rng, 0..DAYS * SECONDS_IN_DAY);
+            assert!(ts < DAYS * SECONDS_IN_DAY);
+            by_hour[usize::try_from(ts % SECONDS_IN_DAY / 3600).unwrap()] += 1;
+        }
+
+        assert!(by_hour[20] > 5 * by_hour[8], "{by_hour:?}");
+    }
+
+    #[test]
+    fn campaign() {
+        let model = CampaignModel {
+            breakdown_conversion_rates: vec![0.5, 0.1],
+            breakdown_value_means: vec![1.0, 4.0],
+        };
+        assert_eq!(model.conversion_rate(0), Some(0.5));
+        assert_eq!(model.conversion_rate(7), Some(0.1));
+        assert_eq!(CampaignModel::default().conversion_rate(0), None);
+
+        let mut rng = StdRng::seed_from_u64(42);
+        assert_eq!(model.value(&mut rng, 0, 10), Some(1));
+        assert_eq!(CampaignModel::default().value(&mut rng, 0, 10), None);
+        let values = (0..10_000)
+            .map(|_| model.value(&mut rng, 3, 100).unwrap())
+            .collect::<Vec<_>>();
+        let mean = f64::from(values.iter().sum::<u32>()) / 10_000.0;
+        assert!((mean - 4.0).abs() < 0.2, "mean {mean}");
+        assert!(values.iter().all(|&v| (1..=100).contains(&v)));
+        assert!((0..1000).all(|_| (1..=3).contains(&model.value(&mut rng, 1, 3).unwrap())));
+    }
+}
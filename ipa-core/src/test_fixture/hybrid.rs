@@ -20,10 +20,17 @@ impl HashmapEntry {
     }
 }
 
+/// Attributes the conversions of each user to the last impression of that user in `input_rows`,
+/// and caps the value attributed to each user at `per_user_cap`.
+///
 /// # Panics
 /// It won't, so long as you can convert a u32 to a usize
 #[must_use]
-pub fn hybrid_in_the_clear(input_rows: &[TestHybridRecord], max_breakdown: usize) -> Vec<u32> {
+pub fn hybrid_in_the_clear(
+    input_rows: &[TestHybridRecord],
+    max_breakdown: usize,
+    per_user_cap: u32,
+) -> Vec<u32> {
     let mut conversion_match_keys = HashSet::new();
     let mut impression_match_keys = HashSet::new();
 
@@ -67,7 +74,8 @@ pub fn hybrid_in_the_clear(input_rows: &[TestHybridRecord], max_breakdown: usize
 
     let mut output = vec![0; max_breakdown];
     for (_, entry) in attributed_conversions {
-        output[usize::try_from(entry.breakdown_key).unwrap()] += entry.total_value;
+        output[usize::try_from(entry.breakdown_key).unwrap()] +=
+            std::cmp::min(entry.total_value, per_user_cap);
     }
 
     output
@@ -144,7 +152,15 @@ mod tests {
             13, 33, // 25 + 8
             0,
         ];
-        let result = hybrid_in_the_clear(&test_data, 6);
+        let result = hybrid_in_the_clear(&test_data, 6, 64);
         assert_eq!(result, expected);
+
+        let capped = vec![
+            0, 0, 20, // 12 + 31, capped at 20
+            13, 28, // 20 (25 capped) + 8
+            0,
+        ];
+        let result = hybrid_in_the_clear(&test_data, 6, 20);
+        assert_eq!(result, capped);
     }
 }
@@ -2,7 +2,13 @@ use std::num::NonZeroU32;
 
 use rand::Rng;
 
-use super::hybrid::TestHybridRecord;
+use super::{
+    event_model::{CampaignModel, UserModel},
+    hybrid::TestHybridRecord,
+};
+
+/// Maximum number of match keys that cross-device users can return with.
+const MAX_RETURNING_USERS: usize = 100_000;
 
 #[derive(Debug, Copy, Clone)]
 #[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
@@ -25,6 +31,10 @@ pub struct Config {
     /// Indicates the distribution of impression to conversion reports.
     #[cfg_attr(feature = "clap", arg(value_enum, long, default_value_t = ConversionDistribution::Default))]
     pub conversion_distribution: ConversionDistribution,
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub users: UserModel,
+    #[cfg_attr(feature = "clap", clap(flatten))]
+    pub campaign: CampaignModel,
 }
 
 impl Default for Config {
@@ -50,14 +60,24 @@ impl Config {
             max_breakdown_key: NonZeroU32::try_from(max_breakdown_key).unwrap(),
             max_convs_per_imp: NonZeroU32::try_from(max_convs_per_imp).unwrap(),
             conversion_distribution,
+            users: UserModel::default(),
+            campaign: CampaignModel::default(),
         }
     }
 }
 
+/// Generates random impressions and conversions, grouped by match key.
+///
+/// With cross-device users, some groups reuse the match key of an earlier group, as if another
+/// device of the same user reported them. If conversion rates are configured per breakdown key,
+/// impressions convert at the rate of their breakdown key.
 pub struct EventGenerator<R: Rng> {
     config: Config,
     rng: R,
     in_flight: Vec<TestHybridRecord>,
+    /// Match keys that cross-device users can return with, and how many devices used each of
+    /// them so far.
+    returning_users: Vec<(u64, u32)>,
 }
 
 impl<R: Rng> EventGenerator<R> {
@@ -70,11 +90,14 @@ impl<R: Rng> EventGenerator<R> {
     /// If the configuration is not valid.
     #[allow(dead_code)]
     pub fn with_config(rng: R, config: Config) -> Self {
+        config.users.validate();
+        config.campaign.validate();
         let max_capacity = usize::try_from(config.max_convs_per_imp.get() + 1).unwrap();
         Self {
             config,
             rng,
             in_flight: Vec::with_capacity(max_capacity),
+            returning_users: Vec::new(),
         }
     }
 
@@ -102,18 +125,23 @@ impl<R: Rng> EventGenerator<R> {
         subsequent_conversion_prob: f32,
     ) {
         assert!(unmatched_conversions + unmatched_impressions <= 1.0);
-        let match_key = self.rng.gen::<u64>();
+        let match_key = self.gen_match_key();
         let rand = self.rng.gen_range(0.0..1.0);
         if rand < unmatched_conversions {
-            let conv = self.gen_conversion(match_key);
+            let conv = self.gen_conversion(match_key, None);
             self.in_flight.push(conv);
-        } else if rand < unmatched_conversions + unmatched_impressions {
-            let imp = self.gen_impression(match_key);
-            self.in_flight.push(imp);
-        } else {
-            let imp = self.gen_impression(match_key);
-            let conv = self.gen_conversion(match_key);
-            self.in_flight.push(imp);
+            return;
+        }
+
+        let breakdown_key = self.rng.gen_range(0..self.config.max_breakdown_key.get());
+        let imp = Self::gen_impression(match_key, breakdown_key);
+        self.in_flight.push(imp);
+        let converts = match self.config.campaign.conversion_rate(breakdown_key) {
+            Some(rate) if unmatched_impressions < 1.0 => self.rng.gen::<f64>() < rate,
+            _ => rand >= unmatched_conversions + unmatched_impressions,
+        };
+        if converts {
+            let conv = self.gen_conversion(match_key, Some(breakdown_key));
             self.in_flight.push(conv);
             let mut conv_count = 1;
             // long-tailed distribution of # of conversions per impression
@@ -121,26 +149,59 @@ impl<R: Rng> EventGenerator<R> {
             while conv_count < self.config.max_convs_per_imp.get()
                 && self.rng.gen_range(0.0..1.0) < subsequent_conversion_prob
             {
-                let conv = self.gen_conversion(match_key);
+                let conv = self.gen_conversion(match_key, Some(breakdown_key));
                 self.in_flight.push(conv);
                 conv_count += 1;
             }
         }
     }
 
-    fn gen_conversion(&mut self, match_key: u64) -> TestHybridRecord {
-        TestHybridRecord::TestConversion {
-            match_key,
-            value: self
-                .rng
-                .gen_range(1..self.config.max_conversion_value.get()),
+    /// Returns the match key of a returning cross-device user, or a new match key.
+    fn gen_match_key(&mut self) -> u64 {
+        let max_devices = self.config.users.max_devices_per_user.get();
+        if let Some(idx) = self
+            .config
+            .users
+            .returning_user(&mut self.rng, self.returning_users.len())
+        {
+            let (match_key, devices) = &mut self.returning_users[idx];
+            let match_key = *match_key;
+            *devices += 1;
+            if *devices >= max_devices {
+                // keep the order, as the most active users come first
+                self.returning_users.remove(idx);
+            }
+            return match_key;
+        }
+
+        let match_key = self.rng.gen::<u64>();
+        if self.config.users.cross_device_probability > 0.0
+            && max_devices > 1
+            && self.returning_users.len() < MAX_RETURNING_USERS
+        {
+            self.returning_users.push((match_key, 1));
         }
+        match_key
+    }
+
+    /// Generates a conversion. Its value follows the value distribution of `breakdown_key`, the
+    /// breakdown key of the impression it is attributed to, if there is one.
+    fn gen_conversion(&mut self, match_key: u64, breakdown_key: Option<u32>) -> TestHybridRecord {
+        let max_conversion_value = self.config.max_conversion_value.get();
+        let value = breakdown_key
+            .and_then(|bk| {
+                self.config
+                    .campaign
+                    .value(&mut self.rng, bk, max_conversion_value)
+            })
+            .unwrap_or_else(|| self.rng.gen_range(1..max_conversion_value));
+        TestHybridRecord::TestConversion { match_key, value }
     }
 
-    fn gen_impression(&mut self, match_key: u64) -> TestHybridRecord {
+    fn gen_impression(match_key: u64, breakdown_key: u32) -> TestHybridRecord {
         TestHybridRecord::TestImpression {
             match_key,
-            breakdown_key: self.rng.gen_range(0..self.config.max_breakdown_key.get()),
+            breakdown_key,
         }
     }
 }
@@ -299,6 +360,73 @@ mod tests {
         }
     }
 
+    #[test]
+    fn cross_device_users() {
+        const NUM_EVENTS: usize = 10_000;
+        let gen = EventGenerator::with_config(
+            thread_rng(),
+            Config {
+                users: UserModel {
+                    cross_device_probability: 0.5,
+                    ..UserModel::default()
+                },
+                ..Config::new(10, 10, 1, ConversionDistribution::OnlyImpressions)
+            },
+        );
+        let max_devices = gen.config.users.max_devices_per_user.get();
+        let mut devices_per_match_key = HashMap::new();
+        for event in gen.take(NUM_EVENTS) {
+            let TestHybridRecord::TestImpression { match_key, .. } = event else {
+                panic!("No conversions should be generated");
+            };
+            *devices_per_match_key.entry(match_key).or_insert(0) += 1;
+        }
+
+        assert!(devices_per_match_key.len() < NUM_EVENTS * 3 / 4);
+        assert!(devices_per_match_key
+            .values()
+            .all(|&devices| devices <= max_devices));
+    }
+
+    #[test]
+    fn per_breakdown_conversion_rates() {
+        let gen = EventGenerator::with_config(
+            thread_rng(),
+            Config {
+                campaign: CampaignModel {
+                    breakdown_conversion_rates: vec![1.0, 0.0],
+                    breakdown_value_means: vec![],
+                },
+                ..Config::new(10, 2, 10, ConversionDistribution::Default)
+            },
+        );
+        let mut impressions = HashMap::new();
+        let mut conversions = HashSet::new();
+        for event in gen.take(10_000) {
+            match event {
+                TestHybridRecord::TestImpression {
+                    match_key,
+                    breakdown_key,
+                } => {
+                    impressions.insert(match_key, breakdown_key);
+                }
+                TestHybridRecord::TestConversion { match_key, .. } => {
+                    conversions.insert(match_key);
+                }
+            }
+        }
+
+        // the last batch may be cut short
+        let converted = impressions
+            .iter()
+            .filter(|(match_key, _)| conversions.contains(*match_key))
+            .collect::<Vec<_>>();
+        assert!(converted
+            .iter()
+            .all(|(_, &breakdown_key)| breakdown_key == 0));
+        assert!(converted.len() + 1 >= impressions.values().filter(|&&bk| bk == 0).count());
+    }
+
     #[test]
     fn only_impressions_config() {
         const NUM_EVENTS: usize = 100;
@@ -1,8 +1,11 @@
-use std::{collections::HashMap, num::NonZeroU32};
+use std::{collections::HashMap, iter::zip, num::NonZeroU32};
 
 use rand::{thread_rng, Rng};
 
-use crate::protocol::ipa_prf::prf_sharding::GroupingKey;
+use crate::{
+    ff::fixed_point::{AttributionWeight, AttributionWeights},
+    protocol::ipa_prf::{prf_sharding::GroupingKey, ValueBuckets},
+};
 #[cfg(feature = "in-memory-infra")]
 use crate::{
     ff::{PrimeField, Serializable},
@@ -10,7 +13,7 @@ use crate::{
     protocol::{
         dp::NoiseParams,
         ipa_prf::oprf_padding::{insecure::OPRFPaddingDp, PaddingParameters},
-        ipa_prf::OPRFIPAInputRow,
+        ipa_prf::{AggregationOutputMode, OPRFIPAInputRow},
     },
     secret_sharing::{
         replicated::{
@@ -20,8 +23,9 @@ use crate::{
     },
 };
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
 pub enum IpaSecurityModel {
     SemiHonest,
     Malicious,
@@ -68,15 +72,82 @@ fn insert_sorted(user_records: &mut Vec<TestRawDataRecord>, record: TestRawDataR
 /// order those records are considered by the attribution algorithm is undefined, and the output
 /// may be non-deterministic.
 ///
+/// If `attribution_weights` are given, each trigger value is split across the most recent source
+/// events like the MPC implementation does, see [`AttributionWeights`].
+///
 /// ## Panics
 /// Will panic if you run in on Intel 80286 or any other 16 bit hardware.
+#[must_use]
 pub fn ipa_in_the_clear(
     input: &[TestRawDataRecord],
     per_user_cap: u32,
     attribution_window: Option<NonZeroU32>,
+    attribution_weights: Option<AttributionWeights>,
+    max_breakdown: u32,
+    order: CappingOrder,
+) -> Vec<u32> {
+    let mut breakdowns = vec![0u32; usize::try_from(max_breakdown).unwrap()];
+    for_each_contribution(
+        input,
+        per_user_cap,
+        attribution_window,
+        attribution_weights,
+        order,
+        |bk, value| {
+            breakdowns[bk] += value;
+        },
+    );
+
+    breakdowns
+}
+
+/// Like [`ipa_in_the_clear`], but counts attributed conversions into `buckets` instead of summing
+/// them, matching [`AggregationOutputMode::ValueBuckets`]. The output has one entry per
+/// (breakdown, bucket) pair, at [`ValueBuckets::histogram_index`].
+///
+/// [`AggregationOutputMode::ValueBuckets`]: crate::protocol::ipa_prf::AggregationOutputMode::ValueBuckets
+///
+/// ## Panics
+/// Will panic if you run in on Intel 80286 or any other 16 bit hardware.
+#[must_use]
+pub fn ipa_value_buckets_in_the_clear(
+    input: &[TestRawDataRecord],
+    per_user_cap: u32,
+    attribution_window: Option<NonZeroU32>,
+    attribution_weights: Option<AttributionWeights>,
     max_breakdown: u32,
-    order: &CappingOrder,
+    buckets: &ValueBuckets,
+    order: CappingOrder,
 ) -> Vec<u32> {
+    let max_breakdown = usize::try_from(max_breakdown).unwrap();
+    let mut counts = vec![0u32; buckets.histogram_index(max_breakdown, 0)];
+    for_each_contribution(
+        input,
+        per_user_cap,
+        attribution_window,
+        attribution_weights,
+        order,
+        |bk, value| {
+            if value > 0 {
+                let bucket = buckets.lower_bounds().partition_point(|&lb| lb <= value) - 1;
+                counts[buckets.histogram_index(bk, bucket)] += 1;
+            }
+        },
+    );
+
+    counts
+}
+
+/// Calls `contribute` with the breakdown and capped value of every touch of every attributed
+/// trigger event.
+fn for_each_contribution<F: FnMut(usize, u32)>(
+    input: &[TestRawDataRecord],
+    per_user_cap: u32,
+    attribution_window: Option<NonZeroU32>,
+    attribution_weights: Option<AttributionWeights>,
+    order: CappingOrder,
+    mut contribute: F,
+) {
     // build a view that is convenient for attribution. match key -> events sorted by timestamp
     // that is more memory intensive, but should be faster to compute. We can always opt-out and
     // execute IPA in place
@@ -88,36 +159,41 @@ pub fn ipa_in_the_clear(
         );
     }
 
-    let mut breakdowns = vec![0u32; usize::try_from(max_breakdown).unwrap()];
+    // last touch attribution credits the most recent source event with the full value
+    let weights = attribution_weights.unwrap_or_else(|| AttributionWeight::ONE.into());
     for records_per_user in user_events.values() {
-        let rev_records = records_per_user.iter().rev();
         update_expected_output_for_user(
-            rev_records,
-            &mut breakdowns,
+            records_per_user,
+            &mut contribute,
             per_user_cap,
             attribution_window,
+            weights.as_slice(),
             order,
         );
     }
-
-    breakdowns
 }
 
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
 pub enum CappingOrder {
     CapOldestFirst,
     CapMostRecentFirst,
 }
 
-/// Assumes records all belong to the same user, and are in reverse chronological order
+/// Assumes records all belong to the same user, and are in chronological order
 /// Will give incorrect results if this is not true
 #[allow(clippy::missing_panics_doc)]
-fn update_expected_output_for_user<'a, I: IntoIterator<Item = &'a TestRawDataRecord>>(
+fn update_expected_output_for_user<'a, I, F>(
     records_for_user: I,
-    expected_results: &mut [u32],
+    contribute: &mut F,
     per_user_cap: u32,
     attribution_window_seconds: Option<NonZeroU32>,
-    order: &CappingOrder,
-) {
+    weights: &[AttributionWeight],
+    order: CappingOrder,
+) where
+    I: IntoIterator<Item = &'a TestRawDataRecord>,
+    F: FnMut(usize, u32),
+{
     let within_window = |value: u64| -> bool {
         if let Some(window) = attribution_window_seconds {
             value <= u64::from(window.get())
@@ -128,50 +204,57 @@ fn update_expected_output_for_user<'a, I: IntoIterator<Item = &'a TestRawDataRec
         }
     };
 
+    // The contributions of each attributed trigger report, most recent touch first.
     let mut attributed_triggers = Vec::new();
-    let mut pending_trigger_reports = Vec::new();
+    // The most recent source reports, most recent first.
+    let mut touches: Vec<&TestRawDataRecord> = Vec::with_capacity(weights.len());
     for record in records_for_user {
         if record.is_trigger_report {
-            pending_trigger_reports.push(record);
-        } else if !pending_trigger_reports.is_empty() {
-            for trigger_report in pending_trigger_reports {
-                let time_delta_to_source_report = trigger_report.timestamp - record.timestamp;
-
-                // only count trigger reports that are within the attribution window
+            let contributions = zip(&touches, weights)
+                // only count touches that are within the attribution window
                 // only if attribution_window is set. This matches the behaviour in MPC
-                if !within_window(time_delta_to_source_report) {
-                    continue;
-                }
-
-                attributed_triggers.push((trigger_report, record));
+                .filter(|(source_report, _)| {
+                    within_window(record.timestamp - source_report.timestamp)
+                })
+                .map(|(source_report, weight)| {
+                    (
+                        source_report.breakdown_key,
+                        weight.apply(record.trigger_value),
+                    )
+                })
+                .collect::<Vec<_>>();
+            if !contributions.is_empty() {
+                attributed_triggers.push(contributions);
             }
-            pending_trigger_reports = Vec::new();
+        } else {
+            touches.insert(0, record);
+            touches.truncate(weights.len());
         }
     }
 
     match order {
-        CappingOrder::CapOldestFirst => {
-            update_breakdowns(attributed_triggers, expected_results, per_user_cap);
-        }
-        CappingOrder::CapMostRecentFirst => update_breakdowns(
+        CappingOrder::CapOldestFirst => update_breakdowns(
             attributed_triggers.into_iter().rev(),
-            expected_results,
+            contribute,
             per_user_cap,
         ),
+        CappingOrder::CapMostRecentFirst => {
+            update_breakdowns(attributed_triggers, contribute, per_user_cap);
+        }
     }
 }
 
-fn update_breakdowns<'a, I>(attributed_triggers: I, expected_results: &mut [u32], per_user_cap: u32)
+fn update_breakdowns<I, F>(attributed_triggers: I, contribute: &mut F, per_user_cap: u32)
 where
-    I: IntoIterator<Item = (&'a TestRawDataRecord, &'a TestRawDataRecord)>,
+    I: IntoIterator<Item = Vec<(u32, u32)>>,
+    F: FnMut(usize, u32),
 {
     let mut total_contribution = 0;
-    for (trigger_report, source_report) in attributed_triggers {
+    for (breakdown_key, value) in attributed_triggers.into_iter().flatten() {
         let delta_to_per_user_cap = per_user_cap - total_contribution;
-        let capped_contribution =
-            std::cmp::min(delta_to_per_user_cap, trigger_report.trigger_value);
-        let bk: usize = source_report.breakdown_key.try_into().unwrap();
-        expected_results[bk] += capped_contribution;
+        let capped_contribution = std::cmp::min(delta_to_per_user_cap, value);
+        let bk: usize = breakdown_key.try_into().unwrap();
+        contribute(bk, capped_contribution);
         total_contribution += capped_contribution;
     }
 }
@@ -209,13 +292,18 @@ pub async fn test_oprf_ipa<F>(
         },
     };
     let padding_params = PaddingParameters::default();
+    let output_mode = config.value_buckets.map_or(
+        AggregationOutputMode::Sum,
+        AggregationOutputMode::ValueBuckets,
+    );
+    #[allow(clippy::large_futures)]
     let result: Vec<_> = if config.per_user_credit_cap == 256 {
         // Note that many parameters are different in this case, not just the credit cap.
         // This config is needed for collect_steps coverage.
         world.semi_honest(
             records.into_iter(),
             |ctx, input_rows: Vec<OPRFIPAInputRow<BA5, BA8, BA20>>| async move {
-                oprf_ipa::<_, BA5, BA8, BA32, BA20, 8, 32>(ctx, input_rows, aws, dp_params, padding_params)
+                oprf_ipa::<_, BA5, BA8, BA32, BA20, 8, 32>(ctx, input_rows, aws, config.attribution_weights, 256, dp_params, padding_params, &output_mode)
                     .await
                     .unwrap()
             },
@@ -226,26 +314,26 @@ pub async fn test_oprf_ipa<F>(
             records.into_iter(),
             |ctx, input_rows: Vec<OPRFIPAInputRow<BA8, BA3, BA20>>| async move {
 
-                match config.per_user_credit_cap {
-                    8 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 3, 256>(ctx, input_rows, aws, dp_params, padding_params)
+                let cap = config.per_user_credit_cap;
+                match cap {
+                    1..=8 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 3, 256>(ctx, input_rows, aws, config.attribution_weights, cap, dp_params, padding_params, &output_mode)
                     .await
                     .unwrap(),
-                    16 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 4, 256>(ctx, input_rows, aws, dp_params, padding_params)
+                    9..=16 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 4, 256>(ctx, input_rows, aws, config.attribution_weights, cap, dp_params, padding_params, &output_mode)
                     .await
                     .unwrap(),
-                    32 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 5, 256>(ctx, input_rows, aws, dp_params, padding_params)
+                    17..=32 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 5, 256>(ctx, input_rows, aws, config.attribution_weights, cap, dp_params, padding_params, &output_mode)
                     .await
                     .unwrap(),
-                    64 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 6, 256>(ctx, input_rows, aws, dp_params, padding_params)
+                    33..=64 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 6, 256>(ctx, input_rows, aws, config.attribution_weights, cap, dp_params, padding_params, &output_mode)
                     .await
                     .unwrap(),
-                    128 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 7, 256>(ctx, input_rows, aws, dp_params, padding_params)
+                    65..=128 => oprf_ipa::<_, BA8, BA3, BA32, BA20, 7, 256>(ctx, input_rows, aws, config.attribution_weights, cap, dp_params, padding_params, &output_mode)
                     .await
                     .unwrap(),
                     _ =>
                     panic!(
-                        "Invalid value specified for per-user cap: {:?}. Must be one of 8, 16, 32, 64, or 128.",
-                        config.per_user_credit_cap
+                        "Invalid value specified for per-user cap: {cap:?}. Must be between 1 and 128, or 256.",
                     ),
                 }
             },
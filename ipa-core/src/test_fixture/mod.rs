@@ -11,11 +11,13 @@ mod app;
 #[cfg(feature = "in-memory-infra")]
 pub mod circuit;
 mod event_gen;
+pub mod event_model;
 pub mod hybrid;
 pub mod hybrid_event_gen;
 pub mod ipa;
 pub mod logging;
 pub mod metrics;
+pub mod reach_frequency;
 #[cfg(feature = "in-memory-infra")]
 mod test_gate;
 
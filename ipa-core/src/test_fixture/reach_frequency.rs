@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use crate::test_fixture::ipa::TestRawDataRecord;
+
+/// Executes the reach and frequency protocol in the clear, that is without any MPC helpers
+/// involved in the computation. The output has the same layout as the output of
+/// [`oprf_reach_frequency`], without the bins past the frequency histogram: `max_breakdown_key`
+/// reach bins, followed by `max_frequency` frequency bins.
+///
+/// Like in MPC, the frequency of each user is clipped at `max_frequency`, each user reaches at most
+/// the `max_breakdowns_per_user` smallest breakdowns they saw, and trigger events are ignored.
+///
+/// [`oprf_reach_frequency`]: crate::protocol::ipa_prf::reach_frequency::oprf_reach_frequency
+///
+/// ## Panics
+/// Will panic if you run in on 16 bit hardware.
+#[must_use]
+pub fn reach_frequency_in_the_clear(
+    input: &[TestRawDataRecord],
+    max_breakdown_key: u32,
+    max_frequency: u32,
+    max_breakdowns_per_user: u32,
+) -> Vec<u32> {
+    let mut user_impressions = HashMap::new();
+    for row in input.iter().filter(|row| !row.is_trigger_report) {
+        user_impressions
+            .entry(row.user_id)
+            .or_insert_with(Vec::new)
+            .push(row.breakdown_key);
+    }
+
+    let reach_bins = usize::try_from(max_breakdown_key).unwrap();
+    let max_frequency = usize::try_from(max_frequency).unwrap();
+    let mut output = vec![0; reach_bins + max_frequency];
+    for impressions in user_impressions.values_mut() {
+        let frequency = impressions.len().min(max_frequency);
+        output[reach_bins + frequency - 1] += 1;
+
+        impressions.retain(|&breakdown_key| breakdown_key < max_breakdown_key);
+        impressions.sort_unstable();
+        impressions.dedup();
+        impressions.truncate(usize::try_from(max_breakdowns_per_user).unwrap());
+        for &breakdown_key in impressions.iter() {
+            output[usize::try_from(breakdown_key).unwrap()] += 1;
+        }
+    }
+
+    output
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::{seq::SliceRandom, thread_rng};
+
+    use super::reach_frequency_in_the_clear;
+    use crate::test_fixture::ipa::TestRawDataRecord;
+
+    fn record(user_id: u64, is_trigger_report: bool, breakdown_key: u32) -> TestRawDataRecord {
+        TestRawDataRecord {
+            timestamp: 0,
+            user_id,
+            is_trigger_report,
+            breakdown_key,
+            trigger_value: u32::from(is_trigger_report),
+        }
+    }
+
+    /// Same records as the tests of `oprf_reach_frequency`.
+    #[test]
+    fn matches_mpc() {
+        let impression = |user_id, breakdown_key| record(user_id, false, breakdown_key);
+        let mut records = vec![
+            impression(1, 1),
+            record(1, true, 0),
+            impression(1, 2),
+            impression(1, 1),
+            impression(2, 1),
+            record(3, true, 0),
+            impression(4, 0),
+            impression(4, 3),
+            impression(4, 3),
+            impression(4, 2),
+            impression(4, 0),
+            impression(5, 7),
+            impression(5, 2),
+            impression(6, 3),
+            impression(6, 2),
+            impression(6, 1),
+            impression(6, 0),
+        ];
+        records.shuffle(&mut thread_rng());
+
+        assert_eq!(
+            reach_frequency_in_the_clear(&records, 4, 3, 3),
+            vec![2, 3, 4, 1, 1, 1, 3]
+        );
+    }
+}
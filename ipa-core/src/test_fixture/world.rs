@@ -15,7 +15,9 @@ use tracing::{Instrument, Level, Span};
 
 use crate::{
     helpers::{
+        faults::FaultInjector,
         in_memory_config::{passthrough, DynStreamInterceptor},
+        in_memory_shaping::{NetworkReport, NetworkShape, NetworkShaper},
         Gateway, GatewayConfig, HelperIdentity, InMemoryMpcNetwork, InMemoryShardNetwork,
         InMemoryTransport, Role, RoleAssignment, TotalRecords, Transport,
     },
@@ -36,6 +38,7 @@ use crate::{
         IntoShares,
     },
     sharding::{NotSharded, ShardBinding, ShardIndex, Sharded},
+    sync::Arc,
     telemetry::{stats::Metrics, StepStatsCsvExporter},
     test_fixture::{
         logging, make_participants,
@@ -94,6 +97,7 @@ pub struct TestWorld<S: ShardingScheme = NotSharded> {
     shards: Box<[ShardWorld<S::ShardBinding>]>,
     metrics_handle: MetricsHandle,
     gate_vendor: Box<dyn TestGateVendor>,
+    network_shaper: Option<Arc<NetworkShaper>>,
     _shard_network: InMemoryShardNetwork,
     _phantom: PhantomData<S>,
 }
@@ -143,6 +147,18 @@ pub struct TestWorldConfig {
     /// [`MaliciousHelper`]: crate::helpers::in_memory_config::MaliciousHelper
     /// [`passthrough`]: crate::helpers::in_memory_config::passthrough
     pub stream_interceptor: DynStreamInterceptor,
+
+    /// Network conditions to simulate between helpers and shards. If `None`, data is delivered
+    /// instantly. See [`NetworkShape`].
+    ///
+    /// When set, the time each step spent on the network is printed when the test world is
+    /// dropped, and is available from [`TestWorld::network_report`].
+    pub network_shape: Option<NetworkShape>,
+
+    /// Faults to inject into the streams sent between helpers and shards. See
+    /// [`FaultInjector`]. The injector can be inspected after the run to see which faults were
+    /// injected.
+    pub fault_injector: Option<Arc<FaultInjector>>,
 }
 
 impl ShardingScheme for NotSharded {
@@ -254,6 +270,9 @@ impl<S: ShardingScheme> Drop for TestWorld<S> {
             let metrics = self.metrics_handle.snapshot();
             metrics.export(&mut stdout()).unwrap();
         }
+        if let Some(report) = self.network_report() {
+            println!("{report}");
+        }
     }
 }
 
@@ -269,8 +288,13 @@ impl<S: ShardingScheme> TestWorld<S> {
         println!("TestWorld random seed {seed}", seed = config.seed);
 
         let shard_count = ShardIndex::try_from(S::SHARDS).unwrap();
-        let shard_network =
-            InMemoryShardNetwork::with_stream_interceptor(shard_count, &config.stream_interceptor);
+        let network_shaper = config.network_shape.clone().map(NetworkShaper::new);
+        let shard_network = InMemoryShardNetwork::with_faults(
+            shard_count,
+            &config.stream_interceptor,
+            network_shaper.as_ref(),
+            config.fault_injector.as_ref(),
+        );
 
         let shards = shard_count
             .iter()
@@ -280,6 +304,7 @@ impl<S: ShardingScheme> TestWorld<S> {
                     config,
                     u64::from(shard),
                     shard_network.shard_transports(shard),
+                    network_shaper.as_ref(),
                 )
             })
             .collect::<Vec<_>>()
@@ -289,11 +314,19 @@ impl<S: ShardingScheme> TestWorld<S> {
             shards,
             metrics_handle: MetricsHandle::new(config.metrics_level),
             gate_vendor: gate_vendor(config.initial_gate.clone()),
+            network_shaper,
             _shard_network: shard_network,
             _phantom: PhantomData,
         }
     }
 
+    /// Returns the time spent on the network by each step so far, if the test world simulates
+    /// network conditions.
+    #[must_use]
+    pub fn network_report(&self) -> Option<NetworkReport> {
+        self.network_shaper.as_ref().map(|shaper| shaper.report())
+    }
+
     #[must_use]
     pub(crate) fn gate(&self) -> Gate {
         self.gate_vendor.current()
@@ -320,6 +353,8 @@ impl Default for TestWorldConfig {
             seed: thread_rng().next_u64(),
             initial_gate: None,
             stream_interceptor: passthrough(),
+            network_shape: None,
+            fault_injector: None,
         }
     }
 }
@@ -337,6 +372,18 @@ impl TestWorldConfig {
         self
     }
 
+    #[must_use]
+    pub fn with_network_shape(mut self, shape: NetworkShape) -> Self {
+        self.network_shape = Some(shape);
+        self
+    }
+
+    #[must_use]
+    pub fn with_fault_injector(mut self, faults: &Arc<FaultInjector>) -> Self {
+        self.fault_injector = Some(Arc::clone(faults));
+        self
+    }
+
     #[must_use]
     pub fn role_assignment(&self) -> &RoleAssignment {
         const DEFAULT_ASSIGNMENT: RoleAssignment = RoleAssignment::new([
@@ -732,11 +779,14 @@ impl<B: ShardBinding> ShardWorld<B> {
         config: &TestWorldConfig,
         shard_seed: u64,
         transports: [InMemoryTransport<ShardIndex>; 3],
+        network_shaper: Option<&Arc<NetworkShaper>>,
     ) -> Self {
         let participants = make_participants(&mut StdRng::seed_from_u64(config.seed + shard_seed));
-        let network = InMemoryMpcNetwork::with_stream_interceptor(
+        let network = InMemoryMpcNetwork::with_faults(
             InMemoryMpcNetwork::noop_handlers(),
             &config.stream_interceptor,
+            network_shaper,
+            config.fault_injector.as_ref(),
         );
 
         let mut gateways = zip3_ref(&network.transports(), &transports).map(|(mpc, shard)| {
@@ -12,7 +12,7 @@ use std::{
 
 use command_fds::CommandFdExt;
 use ipa_core::{
-    cli::IpaQueryResult, helpers::query::IpaQueryConfig, test_fixture::ipa::IpaSecurityModel,
+    cli::ResultExport, helpers::query::IpaQueryConfig, test_fixture::ipa::IpaSecurityModel,
 };
 use rand::thread_rng;
 use rand_core::RngCore;
@@ -109,7 +109,7 @@ impl CommandExt for Command {
     }
 }
 
-fn test_setup(config_path: &Path) -> [TcpListener; 3] {
+pub fn test_setup(config_path: &Path) -> [TcpListener; 3] {
     let sockets: [_; 3] = array::from_fn(|_| TcpListener::bind("127.0.0.1:0").unwrap());
     let ports: [u16; 3] = sockets
         .each_ref()
@@ -339,7 +339,7 @@ pub fn test_ipa_with_config(
     let test_mpc = command.spawn().unwrap().terminate_on_drop();
     test_mpc.wait().unwrap_status();
     // basic output checks - output should have the exact size as number of breakdowns
-    let output = serde_json::from_str::<IpaQueryResult>(
+    let output = serde_json::from_str::<ResultExport>(
         &std::fs::read_to_string(&output_file).expect("IPA results file exists"),
     )
     .expect("IPA results file is valid JSON");
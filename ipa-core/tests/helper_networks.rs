@@ -1,10 +1,16 @@
 mod common;
 
-use std::{array, net::TcpListener, path::Path, process::Command};
+use std::{
+    array, fs,
+    net::TcpListener,
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, SystemTime},
+};
 
 use common::{
-    spawn_helpers, tempdir::TempDir, test_ipa, test_multiply, test_network, CommandExt,
-    UnwrapStatusExt, HELPER_BIN,
+    spawn_helpers, tempdir::TempDir, test_ipa, test_multiply, test_network, test_setup, CommandExt,
+    UnwrapStatusExt, CRYPTO_UTIL_BIN, HELPER_BIN, TEST_RC_BIN,
 };
 use ipa_core::{cli::CliPaths, helpers::HelperIdentity, test_fixture::ipa::IpaSecurityModel};
 
@@ -111,6 +117,159 @@ fn keygen_confgen() {
     drop(helpers);
 }
 
+/// Runs a query, then inspects one of the helpers with the admin CLI, authenticated as another.
+#[test]
+#[cfg(all(test, web_test))]
+fn https_admin_state() {
+    let dir = TempDir::new_delete_on_drop();
+    let path = dir.path();
+    let sockets = test_setup(path);
+    let _helpers = spawn_helpers(path, &sockets, true);
+    test_multiply(path, true);
+
+    let output = Command::new(HELPER_BIN)
+        .arg("admin")
+        .args(["--network".into(), path.join("network.toml")])
+        .args(["--helper", "2"])
+        .args(["--tls-cert".into(), path.join("h1.pem")])
+        .args(["--tls-key".into(), path.join("h1.key")])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let state: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(state["hpke_key_ids"], serde_json::json!([0]));
+    // The query completed and its results were collected, so the helper forgot about it.
+    assert_eq!(state["queries"], serde_json::json!([]));
+    assert!(state["features"]
+        .as_array()
+        .unwrap()
+        .contains(&"real-world-infra".into()));
+}
+
+/// Runs a batch with a query that succeeds and one that cannot read its inputs, then resumes it
+/// after fixing the failing query, and once more after changing the query that succeeded.
+#[test]
+#[cfg(all(test, web_test))]
+fn https_batch() {
+    const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+    let dir = TempDir::new_delete_on_drop();
+    let path = dir.path();
+    let sockets = test_setup(path);
+    let _helpers = spawn_helpers(path, &sockets, true);
+
+    let inputs_file = path.join("ipa_inputs.txt");
+    Command::new(TEST_RC_BIN)
+        .args(["--output-file".as_ref(), inputs_file.as_os_str()])
+        .arg("gen-ipa-inputs")
+        .args(["--count", "100"])
+        .args(["--max-breakdown-key", "5"])
+        .silent()
+        .stdin(Stdio::piped())
+        .status()
+        .unwrap_status();
+    Command::new(CRYPTO_UTIL_BIN)
+        .arg("encrypt")
+        .args(["--input-file".as_ref(), inputs_file.as_os_str()])
+        .args(["--output-dir".as_ref(), path.as_os_str()])
+        .args(["--network".into(), path.join("network.toml")])
+        .stdin(Stdio::piped())
+        .status()
+        .unwrap_status();
+
+    let manifest = path.join("batch.toml");
+    let write_manifest = |failing_inputs: &str, per_user_credit_cap: u32| {
+        let query = |name: &str, inputs: &str, per_user_credit_cap: u32| {
+            format!(
+                "[[queries]]\n\
+                 name = \"{name}\"\n\
+                 inputs.files = [\"{inputs}1.enc\", \"{inputs}2.enc\", \"{inputs}3.enc\"]\n\
+                 config = {{ per_user_credit_cap = {per_user_credit_cap}, max_breakdown_key = 5, \
+                 with_dp = 0, epsilon = 1.0 }}\n"
+            )
+        };
+        fs::write(
+            &manifest,
+            // The failing query comes first, so that the other one only starts once it failed.
+            query("failing", failing_inputs, 8) + &query("good", "helper", per_user_credit_cap),
+        )
+        .unwrap();
+    };
+    let run_batch = |run: u32, resume: bool| {
+        // The report collector does not overwrite its output file.
+        let summary_file = path.join(format!("summary{run}.json"));
+        let mut command = Command::new(TEST_RC_BIN);
+        command
+            .args(["--network".into(), path.join("network.toml")])
+            .args(["--output-file".as_ref(), summary_file.as_os_str()])
+            .args(["--wait", "2"])
+            .silent()
+            .arg("batch")
+            .args(["--manifest".as_ref(), manifest.as_os_str()])
+            .args(["--concurrency", "1"])
+            .args(["--max-attempts", "3"])
+            .args(["--retry-delay", &RETRY_DELAY.as_secs().to_string()]);
+        if resume {
+            command.arg("--resume");
+        }
+        let status = command.status().unwrap();
+        let summary: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(summary_file).unwrap()).unwrap();
+        (status.success(), summary)
+    };
+    let counts = |summary: &serde_json::Value| {
+        ["succeeded", "failed", "resumed"].map(|count| summary[count].as_u64().unwrap())
+    };
+
+    write_manifest("missing", 8);
+    let start = SystemTime::now();
+    let (success, summary) = run_batch(1, false);
+    assert!(!success);
+    assert_eq!(counts(&summary), [1, 1, 0]);
+    assert_eq!(summary["queries"][0]["name"], "failing");
+    assert_eq!(summary["queries"][0]["status"], "failed");
+    assert_eq!(summary["queries"][0]["attempts"], 3);
+    assert_eq!(summary["queries"][1]["status"], "succeeded");
+    assert_eq!(summary["queries"][1]["attempts"], 1);
+    // With one query at a time, the good query waits for the failing one and its retries, after
+    // one delay and then two.
+    let good_output = path.join("good.json");
+    let written = fs::metadata(&good_output).unwrap().modified().unwrap();
+    assert!(written.duration_since(start).unwrap() >= RETRY_DELAY * 3);
+    let output: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&good_output).unwrap()).unwrap();
+    assert_eq!(output["breakdowns"].as_array().unwrap().len(), 5);
+
+    // The good query completed, so it is not run again.
+    write_manifest("helper", 8);
+    let (success, summary) = run_batch(2, true);
+    assert!(success);
+    assert_eq!(counts(&summary), [1, 0, 1]);
+    assert_eq!(summary["queries"][0]["status"], "succeeded");
+    assert_eq!(summary["queries"][1]["status"], "resumed");
+    assert_eq!(
+        fs::metadata(&good_output).unwrap().modified().unwrap(),
+        written
+    );
+
+    // Changing a query that completed runs it again.
+    write_manifest("helper", 16);
+    let (success, summary) = run_batch(3, true);
+    assert!(success);
+    assert_eq!(counts(&summary), [1, 0, 1]);
+    assert_eq!(summary["queries"][0]["status"], "resumed");
+    assert_eq!(summary["queries"][1]["status"], "succeeded");
+    assert!(fs::metadata(&good_output).unwrap().modified().unwrap() > written);
+    let output: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&good_output).unwrap()).unwrap();
+    assert_eq!(output["breakdowns"].as_array().unwrap().len(), 5);
+}
+
 fn exec_keygen_cmd(helper_identity: HelperIdentity, dest_dir: &Path) {
     let mut command = Command::new(HELPER_BIN);
     command
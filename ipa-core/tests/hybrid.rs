@@ -42,6 +42,9 @@ fn test_hybrid() {
     command
         .args(["--input-file".as_ref(), input_file.as_os_str()])
         .args(["--output-file".as_ref(), output_file.as_os_str()])
+        .arg("hybrid")
+        .args(["--max-breakdown-key", &MAX_BREAKDOWN_KEY.to_string()])
+        .args(["--with-dp", "0"])
         .silent()
         .stdin(Stdio::piped());
     command.status().unwrap_status();
@@ -0,0 +1,38 @@
+// some pub functions in `common` to be compiled, and rust complains about dead code.
+#[allow(dead_code)]
+mod common;
+
+use std::{fs, process::Command};
+
+use common::{tempdir::TempDir, UnwrapStatusExt};
+
+const IN_THE_CLEAR_BIN: &str = env!("CARGO_BIN_EXE_in_the_clear");
+
+#[test]
+fn oprf_ipa_value_buckets() {
+    let dir = TempDir::new_delete_on_drop();
+    let input_file = dir.path().join("ipa_inputs.txt");
+    let output_file = dir.path().join("ipa_output.json");
+
+    // timestamp, match key, is trigger, breakdown key, trigger value
+    fs::write(
+        &input_file,
+        "0,1,0,1,0\n5,1,1,0,12\n0,2,0,0,0\n1,2,1,0,3\n0,3,0,2,0\n10,3,1,0,60\n",
+    )
+    .unwrap();
+
+    Command::new(IN_THE_CLEAR_BIN)
+        .args(["--input-file".as_ref(), input_file.as_os_str()])
+        .args(["--output-file".as_ref(), output_file.as_os_str()])
+        .arg("oprf-ipa")
+        .args(["--max-breakdown-key", "3"])
+        .args(["--per-user-credit-cap", "100"])
+        .args(["--value-buckets", "10,50"])
+        .args(["--with-dp", "0"])
+        .status()
+        .unwrap_status();
+
+    // One bin per breakdown and bucket, the buckets being [1, 10), [10, 50) and [50, ...).
+    let output: Vec<u32> = serde_json::from_slice(&fs::read(&output_file).unwrap()).unwrap();
+    assert_eq!(output, vec![1, 0, 0, 0, 1, 0, 0, 0, 1]);
+}